@@ -30,7 +30,15 @@ pub enum FfmpegError {
     #[error("Ffmmpeg decoded no frames from the video")]
     InvalidResolution,
 
+    /// A video stream was requested by index, but the file does not have that many video streams.
+    #[error("The video has no video stream with index {0}")]
+    NoSuchVideoStream(usize),
+
     /// Failed to obtain video information.
     #[error("Failed to get video properties")]
     Info(#[from] VideoInfoError),
+
+    /// The container of the video does not record its duration, and it could not be measured.
+    #[error("The video has no recorded duration, and it could not be measured")]
+    UnknownDuration,
 }
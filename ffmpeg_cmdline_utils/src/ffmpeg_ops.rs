@@ -1,8 +1,9 @@
 use std::{
     ffi::OsStr,
-    io::prelude::*,
+    io::{prelude::*, BufReader},
     path::{Path, PathBuf},
-    process::{Child, Command, ExitStatus, Stdio},
+    process::{Child, ChildStderr, Command, ExitStatus, Stdio},
+    sync::mpsc,
     time::{Duration, SystemTime},
 };
 
@@ -27,6 +28,21 @@ pub struct FfmpegFrameIter {
     frames_read: u32,
     timeout_time: SystemTime,
     finished: bool,
+    //The presentation timestamp of each frame in turn, when decoding with timestamps. None for
+    //frames which have no timestamp.
+    pts_rx: Option<mpsc::Receiver<Option<f64>>>,
+}
+
+impl FfmpegFrameIter {
+    // The presentation timestamp of the frame that was just read, if decoding with timestamps.
+    fn next_pts(&mut self) -> Option<f64> {
+        let pts_rx = self.pts_rx.as_ref()?;
+        let timeout = self
+            .timeout_time
+            .duration_since(SystemTime::now())
+            .unwrap_or_default();
+        pts_rx.recv_timeout(timeout).ok().flatten()
+    }
 }
 
 impl Iterator for FfmpegFrameIter {
@@ -106,6 +122,18 @@ enum FfmpegFps {
     CalcForNumFrames,
 }
 
+/// Whether frames are deinterlaced (with ffmpeg's yadif filter) before they are returned.
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Default, Hash)]
+pub enum Deinterlace {
+    /// Frames are returned as they are decoded.
+    #[default]
+    Off,
+    /// Only frames which are flagged as interlaced are deinterlaced.
+    Auto,
+    /// Every frame is deinterlaced, whether or not it is flagged as interlaced.
+    Force,
+}
+
 #[derive(Clone, Debug)]
 pub struct FfmpegFrameReaderBuilder {
     src_path: PathBuf,
@@ -114,6 +142,8 @@ pub struct FfmpegFrameReaderBuilder {
     num_frames: Option<u32>,
     skip_forward: Option<u32>,
     timeout_secs: Option<u64>,
+    video_stream: Option<usize>,
+    deinterlace: Deinterlace,
 }
 
 impl FfmpegFrameReaderBuilder {
@@ -125,6 +155,8 @@ impl FfmpegFrameReaderBuilder {
             num_frames: None,
             skip_forward: None,
             timeout_secs: None,
+            video_stream: None,
+            deinterlace: Deinterlace::Off,
         }
     }
 
@@ -171,6 +203,36 @@ impl FfmpegFrameReaderBuilder {
         self
     }
 
+    /// Decode the video stream with the given index (counting video streams only) instead of
+    /// [the default][VideoInfo::default_video_stream].
+    pub fn video_stream_index(&mut self, index: usize) -> &mut Self {
+        self.video_stream = Some(index);
+        self
+    }
+
+    /// Deinterlace frames before they are returned (see [`Deinterlace`]).
+    pub fn deinterlace(&mut self, mode: Deinterlace) -> &mut Self {
+        self.deinterlace = mode;
+        self
+    }
+
+    /// The index of the video stream that will be decoded.
+    pub fn selected_video_stream(&self) -> Result<usize, FfmpegError> {
+        let stats = VideoInfo::new(&self.src_path)?;
+        self.select_video_stream(&stats)
+    }
+
+    fn select_video_stream(&self, stats: &VideoInfo) -> Result<usize, FfmpegError> {
+        match self.video_stream {
+            Some(index) if index < stats.video_streams().len() => Ok(index),
+            Some(index) => Err(FfmpegError::NoSuchVideoStream(index)),
+            //a file without video streams is reported in the same way as an empty video stream.
+            None => stats
+                .default_video_stream()
+                .ok_or(FfmpegError::InvalidResolution),
+        }
+    }
+
     pub fn spawn_gray(&self) -> Result<(FfmpegFrameIterGray, VideoInfo), FfmpegError> {
         self.spawn(true).map(|(base_iter, vid_info)| {
             let gray_iter = FfmpegFrameIterGray { base_iter };
@@ -185,24 +247,120 @@ impl FfmpegFrameReaderBuilder {
         })
     }
 
+    /// Decode the frames of the video along with their presentation timestamps. If the selected
+    /// stream has a variable frame rate every frame is decoded with no framerate conversion (any
+    /// fps setting is ignored), otherwise the frames are those of [`Self::spawn_gray`].
+    /// Timestamps are measured from the point given to [`Self::skip_forward`], so the first frame
+    /// is at (or just after) zero.
+    ///
+    /// Iteration stops early if a frame has no timestamp.
+    pub fn spawn_gray_timestamped(
+        &self,
+    ) -> Result<(FfmpegFrameIterGrayTimestamped, VideoInfo), FfmpegError> {
+        self.spawn_with_timestamps(true, true)
+            .map(|(base_iter, vid_info)| (FfmpegFrameIterGrayTimestamped { base_iter }, vid_info))
+    }
+
+    /// Decode up to `duration` seconds of the first audio stream, starting from the point given to
+    /// [`Self::skip_forward`]. The audio is mixed down to mono and resampled to `sample_rate`
+    /// samples per second. Returns None if the file has no audio streams.
+    pub fn decode_audio(
+        &self,
+        sample_rate: u32,
+        duration: f64,
+    ) -> Result<Option<Vec<i16>>, FfmpegError> {
+        let stats = VideoInfo::new(&self.src_path)?;
+        if stats.audio_streams() == 0 {
+            return Ok(None);
+        }
+
+        let skip_forward = self.skip_forward.unwrap_or(0).to_string();
+        let duration = duration.to_string();
+        let sample_rate = sample_rate.to_string();
+
+        #[rustfmt::skip]
+        let args = [
+            OsStr::new("-hide_banner"),
+            OsStr::new("-loglevel"), OsStr::new("error"),
+            OsStr::new("-nostats"),
+            OsStr::new("-ss"),       OsStr::new(&skip_forward),
+            OsStr::new("-i"),        OsStr::new(&self.src_path),
+            OsStr::new("-map"),      OsStr::new("0:a:0"),
+            OsStr::new("-t"),        OsStr::new(&duration),
+            OsStr::new("-ac"),       OsStr::new("1"),
+            OsStr::new("-ar"),       OsStr::new(&sample_rate),
+            OsStr::new("-f"),        OsStr::new("s16le"),
+            OsStr::new("-"),
+        ];
+
+        let stdout = run_ffmpeg_command(Ffmpeg, &args, false)?.stdout;
+        let samples = stdout
+            .chunks_exact(2)
+            .map(|sample| i16::from_le_bytes([sample[0], sample[1]]))
+            .collect();
+        Ok(Some(samples))
+    }
+
+    /// Measure the duration of the selected video stream from the timestamps of its packets, for
+    /// videos whose container does not record it (see [`VideoInfo::container_duration`]). The
+    /// packets are only read, not decoded, and reading stops `cap` into the video. Returns None if
+    /// the video lasts longer than that, or if none of its packets have timestamps.
+    pub fn measure_duration(&self, cap: Duration) -> Result<Option<Duration>, FfmpegError> {
+        let stats = VideoInfo::new(&self.src_path)?;
+        let stream_idx = self.select_video_stream(&stats)?;
+        let stream_arg = format!("v:{stream_idx}");
+        let interval_arg = format!("%+{}", cap.as_secs_f64());
+
+        #[rustfmt::skip]
+        let args = [
+            OsStr::new("-v"),              OsStr::new("error"),
+            OsStr::new("-select_streams"), OsStr::new(&stream_arg),
+            OsStr::new("-read_intervals"), OsStr::new(&interval_arg),
+            OsStr::new("-show_entries"),   OsStr::new("packet=pts_time"),
+            OsStr::new("-of"),             OsStr::new("csv=p=0"),
+            OsStr::new(&self.src_path),
+        ];
+
+        let stdout = run_ffmpeg_command(Ffprobe, &args, true)?.stdout;
+        let packets = String::from_utf8(stdout).map_err(|_| Utf8Conversion)?;
+        Ok(pts_time_span(&packets)
+            .filter(|&span| span < cap.as_secs_f64())
+            .map(Duration::from_secs_f64))
+    }
+
     fn spawn(&self, grayscale: bool) -> Result<(FfmpegFrameIter, VideoInfo), FfmpegError> {
+        self.spawn_with_timestamps(grayscale, false)
+    }
+
+    fn spawn_with_timestamps(
+        &self,
+        grayscale: bool,
+        timestamps: bool,
+    ) -> Result<(FfmpegFrameIter, VideoInfo), FfmpegError> {
         //we also need to find out the resolution of the video so that stdout can be converted into frames.
         let stats = VideoInfo::new(&self.src_path).map_err(|e| FfmpegError::Io(e.to_string()))?;
 
+        //the dimensions of the decoded frames are those of the selected stream.
+        let stream_idx = self.select_video_stream(&stats)?;
+        let stream = &stats.video_streams()[stream_idx];
+        let (x, y) = stream.resolution();
+
         //bail out if we get invalid dimensions.
-        let (x, y) = stats.resolution();
         if x == 0 || y == 0 {
             return Err(FfmpegError::InvalidResolution);
         }
 
-        let fps_string: String;
-        let fps_arg = match (&self.fps, self.num_frames) {
+        let fps_filter = match (&self.fps, self.num_frames) {
+            //The frames of a variable frame rate stream are written to image2pipe exactly as they
+            //are decoded, with nothing duplicated or dropped. (The framerate conversion of other
+            //streams puts the nth frame at n/fps, so it is kept for them as there are far fewer
+            //frames to pipe.)
+            _ if timestamps && !stream.is_constant_frame_rate() => None,
+
             //if a concrete FPS is specified then use it (we don't care about num_frames as
             // it's only needed for CalcForNumFrames).
-            (&Some(FfmpegFps::Specified(ref fps)), _) => {
-                fps_string = format!("fps={fps}");
-                vec![OsStr::new("-vf"), OsStr::new(&fps_string)]
-            }
+            (&Some(FfmpegFps::Specified(ref fps)), _) => Some(format!("fps={fps}")),
+
             //If CalcForFPS is specified but no num_frames is given this doesn't make sense
             (Some(FfmpegFps::CalcForNumFrames), None) => {
                 panic!("When fps is CalcForNumFrames, a number of frames must be given")
@@ -221,12 +379,27 @@ impl FfmpegFrameReaderBuilder {
 
                 let seconds_per_frame = adjusted_duration / num_frames as f64;
                 let seconds_per_1000_frames = seconds_per_frame * 1000.0;
-                fps_string = format!("fps=1000/{}", seconds_per_1000_frames.floor() as u32);
-                vec![OsStr::new("-vf"), OsStr::new(&fps_string)]
+                Some(format!(
+                    "fps=1000/{}",
+                    seconds_per_1000_frames.floor() as u32
+                ))
             }
 
             //otherwise just return every frame.
-            _ => vec![],
+            _ => None,
+        };
+
+        //showinfo logs the timestamp of every frame that reaches it.
+        let fps_filter = match fps_filter {
+            Some(fps_filter) if timestamps => Some(format!("{fps_filter},showinfo")),
+            None if timestamps => Some(String::from("showinfo")),
+            fps_filter => fps_filter,
+        };
+
+        let filter_string = video_filter(self.deinterlace, fps_filter, grayscale);
+        let filter_arg = match &filter_string {
+            Some(filter) => vec![OsStr::new("-vf"), OsStr::new(filter)],
+            None => vec![],
         };
 
         let num_frames_string: String;
@@ -260,10 +433,13 @@ impl FfmpegFrameReaderBuilder {
             vec![]
         };
 
+        //showinfo only logs at the info level.
+        let loglevel = if timestamps { "info" } else { "warning" };
+
         #[rustfmt::skip]
         let mut args = vec![
             OsStr::new("-hide_banner"),
-            OsStr::new("-loglevel"), OsStr::new("warning"),
+            OsStr::new("-loglevel"), OsStr::new(loglevel),
             OsStr::new("-nostats"),
         ];
 
@@ -271,12 +447,15 @@ impl FfmpegFrameReaderBuilder {
 
         args.extend(skip_forward_arg);
 
+        let map_arg_string = format!("0:v:{stream_idx}");
+
         #[rustfmt::skip]
         args.extend([
             OsStr::new("-i"),        OsStr::new(&self.src_path),
+            OsStr::new("-map"),      OsStr::new(&map_arg_string),
         ]);
 
-        args.extend(fps_arg);
+        args.extend(filter_arg);
         args.extend(num_frames_arg);
         args.extend(pix_fmt_arg);
 
@@ -294,13 +473,15 @@ impl FfmpegFrameReaderBuilder {
         //         .join(" ")
         // });
 
-        let mut child = spawn_ffmpeg_command(Ffmpeg, &args, true)?;
+        let mut child = spawn_ffmpeg_command(Ffmpeg, &args, !timestamps)?;
 
         //Prevent possible lockup if stderr gets full by dropping the
-        //handle from our side
-        std::mem::drop(child.stderr.take());
-
-        let (x, y) = stats.resolution();
+        //handle from our side (unless it is being read for timestamps)
+        let pts_rx = child
+            .stderr
+            .take()
+            .filter(|_| timestamps)
+            .map(spawn_pts_reader);
 
         let frame_iterator = FfmpegFrameIter {
             x,
@@ -312,6 +493,7 @@ impl FfmpegFrameReaderBuilder {
             timeout_time: SystemTime::now()
                 + Duration::from_secs(self.timeout_secs.unwrap_or(u32::MAX as u64)), // (just in case u64::MAX has wraparound issues)
             finished: false,
+            pts_rx,
         };
 
         //Ok((frames, stats))
@@ -319,6 +501,33 @@ impl FfmpegFrameReaderBuilder {
     }
 }
 
+// The filter graph to pass to -vf, if any. Grayscale frames are converted to full range luma, as
+// the gstreamer backend does, so that hashes do not depend on whether the video was stored in
+// limited or full range. (Without this, swscale copies limited range luma into gray frames as it
+// is.) The conversion to gray itself is added by -pix_fmt, which ffmpeg merges into this scale.
+//
+// yadif needs the frames on either side of each frame, so it comes before any frames are dropped.
+// It outputs one frame for each frame (rather than one for each field), so timestamps and frame
+// rates are unchanged.
+fn video_filter(
+    deinterlace: Deinterlace,
+    fps_filter: Option<String>,
+    grayscale: bool,
+) -> Option<String> {
+    let deinterlace_filter = match deinterlace {
+        Deinterlace::Off => None,
+        Deinterlace::Auto => Some(String::from("yadif=deint=interlaced")),
+        Deinterlace::Force => Some(String::from("yadif")),
+    };
+    let range_filter = grayscale.then(|| String::from("scale=in_range=auto:out_range=full"));
+    let filters = deinterlace_filter
+        .into_iter()
+        .chain(fps_filter)
+        .chain(range_filter)
+        .collect::<Vec<_>>();
+    (!filters.is_empty()).then(|| filters.join(","))
+}
+
 pub struct FfmpegFrameIterGray {
     base_iter: FfmpegFrameIter,
 }
@@ -334,6 +543,56 @@ impl Iterator for FfmpegFrameIterGray {
     }
 }
 
+pub struct FfmpegFrameIterGrayTimestamped {
+    base_iter: FfmpegFrameIter,
+}
+
+impl Iterator for FfmpegFrameIterGrayTimestamped {
+    type Item = (Duration, GrayImage);
+
+    fn next(&mut self) -> Option<(Duration, GrayImage)> {
+        let frame = match self.base_iter.next()? {
+            image::DynamicImage::ImageLuma8(img) => img,
+            _ => unreachable!(),
+        };
+        let pts = self.base_iter.next_pts()?;
+
+        Some((Duration::from_secs_f64(pts.max(0.0)), frame))
+    }
+}
+
+// Read the frame timestamps logged by showinfo on ffmpeg's stderr, in a separate thread so that
+// ffmpeg never blocks on a full pipe.
+fn spawn_pts_reader(stderr: ChildStderr) -> mpsc::Receiver<Option<f64>> {
+    let (pts_tx, pts_rx) = mpsc::channel();
+    std::thread::spawn(move || {
+        for line in BufReader::new(stderr).split(b'\n') {
+            let Ok(line) = line else {
+                break;
+            };
+            let line = String::from_utf8_lossy(&line);
+            if let Some(pts) = showinfo_pts_time(&line) {
+                if pts_tx.send(pts).is_err() {
+                    break;
+                }
+            }
+        }
+    });
+    pts_rx
+}
+
+// The presentation timestamp from a line of showinfo output. Returns None for other lines, and
+// Some(None) for frames which have no timestamp.
+fn showinfo_pts_time(line: &str) -> Option<Option<f64>> {
+    if !line.contains("Parsed_showinfo") {
+        return None;
+    }
+    let (_, rest) = line.split_once("pts_time:")?;
+    let pts = rest.split_whitespace().next().unwrap_or_default();
+
+    Some(pts.parse::<f64>().ok().filter(|pts| pts.is_finite()))
+}
+
 pub struct FfmpegFrameIterRgb {
     base_iter: FfmpegFrameIter,
 }
@@ -349,6 +608,21 @@ impl Iterator for FfmpegFrameIterRgb {
     }
 }
 
+// The time from the first to the last of the packet timestamps listed by ffprobe, one per line.
+// Packets are listed in decoding order, which is not always the order they are shown in, and
+// packets without a timestamp are listed as "N/A".
+fn pts_time_span(packets: &str) -> Option<f64> {
+    let (first, last) = packets
+        .lines()
+        .filter_map(|line| line.trim().trim_end_matches(',').parse::<f64>().ok())
+        .filter(|pts_time| pts_time.is_finite())
+        .fold(None, |span, pts_time| match span {
+            None => Some((pts_time, pts_time)),
+            Some((first, last)) => Some((pts_time.min(first), pts_time.max(last))),
+        })?;
+    Some(last - first)
+}
+
 pub fn get_video_stats<P: AsRef<Path>>(src_path: P) -> Result<String, FfmpegError> {
     let args = &[
         OsStr::new("-v"),
@@ -410,32 +684,54 @@ pub fn is_video_file<P: AsRef<Path>>(src_path: P) -> Result<bool, FfmpegError> {
 }
 
 pub fn ffmpeg_and_ffprobe_are_callable() -> bool {
-    //check ffprobe is callable.
-    if run_ffmpeg_command(Ffprobe, &[OsStr::new("-version")], true).is_err() {
-        return false;
-    }
+    missing_commands().is_empty()
+}
+
+/// The names of the commands out of ffmpeg and ffprobe which cannot be run. Unlike
+/// [`verify_ffmpeg`], this checks again every time it is called.
+pub fn missing_commands() -> Vec<&'static str> {
+    [Ffprobe, Ffmpeg]
+        .into_iter()
+        .filter(|&name| query_version_output(name).is_err())
+        .map(|name| name.as_str())
+        .collect()
+}
 
-    //now ffmpeg.
-    if run_ffmpeg_command(Ffmpeg, &[OsStr::new("-version")], true).is_err() {
-        return false;
+// The output of `<name> -version`. This does not wait for verify_ffmpeg, because verify_ffmpeg
+// is built on it.
+pub(crate) fn query_version_output(name: FfmpegCommandName) -> Result<String, FfmpegError> {
+    let output = ffmpeg_command(name, &[OsStr::new("-version")], true)
+        .stdin(Stdio::null())
+        .output()
+        .map_err(spawn_error)?;
+    if !output.status.success() {
+        return Err(FfmpegInternal(format!(
+            "{} -version exited with {}",
+            name.as_str(),
+            output.status
+        )));
     }
 
-    true
+    String::from_utf8(output.stdout).map_err(|_| Utf8Conversion)
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
-enum FfmpegCommandName {
+pub(crate) enum FfmpegCommandName {
     Ffprobe,
     Ffmpeg,
 }
 
 impl FfmpegCommandName {
-    pub fn as_os_str(&self) -> &'static OsStr {
+    pub fn as_str(&self) -> &'static str {
         match self {
-            Self::Ffprobe => OsStr::new("ffprobe"),
-            Self::Ffmpeg => OsStr::new("ffmpeg"),
+            Self::Ffprobe => "ffprobe",
+            Self::Ffmpeg => "ffmpeg",
         }
     }
+
+    pub fn as_os_str(&self) -> &'static OsStr {
+        OsStr::new(self.as_str())
+    }
 }
 
 fn spawn_ffmpeg_command(
@@ -443,8 +739,15 @@ fn spawn_ffmpeg_command(
     args: &[&OsStr],
     stderr_null: bool,
 ) -> Result<Child, FfmpegError> {
-    use FfmpegError::*;
+    //If ffmpeg is missing, fail straight away instead of trying to run it for every file.
+    verify_ffmpeg()?;
+
+    ffmpeg_command(name, args, stderr_null)
+        .spawn()
+        .map_err(spawn_error)
+}
 
+fn ffmpeg_command(name: FfmpegCommandName, args: &[&OsStr], stderr_null: bool) -> Command {
     let stderr_cfg = if stderr_null {
         Stdio::null()
     } else {
@@ -462,12 +765,16 @@ fn spawn_ffmpeg_command(
     #[cfg(target_family = "windows")]
     command.creation_flags(winapi::um::winbase::CREATE_NO_WINDOW);
 
-    command.spawn().map_err(|e| match e.kind() {
+    command
+}
+
+fn spawn_error(e: std::io::Error) -> FfmpegError {
+    match e.kind() {
         //shell failed to execute the command. Separate out FileNotFound from all other errors
         //as by far the most likely cause is ffmpeg is not installed.
         std::io::ErrorKind::NotFound => FfmpegNotFound,
         _ => Io(format!("{:?}", e.kind())),
-    })
+    }
 }
 
 struct FfmpegOutput {
@@ -583,3 +890,73 @@ fn run_ffmpeg_command(
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{pts_time_span, showinfo_pts_time, video_filter, Deinterlace};
+
+    #[test]
+    fn test_gray_frames_are_full_range() {
+        assert_eq!(
+            video_filter(Deinterlace::Off, Some(String::from("fps=2/1")), true).as_deref(),
+            Some("fps=2/1,scale=in_range=auto:out_range=full")
+        );
+        assert_eq!(
+            video_filter(Deinterlace::Off, None, true).as_deref(),
+            Some("scale=in_range=auto:out_range=full")
+        );
+
+        //rgb frames are always full range.
+        assert_eq!(
+            video_filter(Deinterlace::Off, Some(String::from("showinfo")), false).as_deref(),
+            Some("showinfo")
+        );
+        assert_eq!(video_filter(Deinterlace::Off, None, false), None);
+    }
+
+    #[test]
+    fn test_deinterlacing_comes_first() {
+        assert_eq!(
+            video_filter(Deinterlace::Auto, Some(String::from("fps=2/1")), true).as_deref(),
+            Some("yadif=deint=interlaced,fps=2/1,scale=in_range=auto:out_range=full")
+        );
+        assert_eq!(
+            video_filter(Deinterlace::Force, None, false).as_deref(),
+            Some("yadif")
+        );
+    }
+
+    #[test]
+    fn test_showinfo_pts_time() {
+        let frame =
+            "[Parsed_showinfo_0 @ 0x5581] n:  12 pts:  24576 pts_time:1.6     duration:  512 \
+                     duration_time:0.0333333 fmt:yuv420p sar:1/1 s:640x360 i:P iskey:0";
+        assert_eq!(showinfo_pts_time(frame), Some(Some(1.6)));
+
+        //ffmpeg 4 has no duration field.
+        let frame = "[Parsed_showinfo_0 @ 0x1f2] n:   0 pts:      0 pts_time:0       pos:   48";
+        assert_eq!(showinfo_pts_time(frame), Some(Some(0.0)));
+
+        let no_pts = "[Parsed_showinfo_0 @ 0x1f2] n:   3 pts:NOPTS pts_time:NOPTS   pos:   48";
+        assert_eq!(showinfo_pts_time(no_pts), Some(None));
+
+        for other in [
+            "[Parsed_showinfo_0 @ 0x1f2] config in time_base: 1/15360, frame_rate: 30/1",
+            "[Parsed_showinfo_0 @ 0x1f2]   color_range:tv color_space:bt709",
+            "  Stream #0:0: Video: h264, pts_time:1.0",
+        ] {
+            assert_eq!(showinfo_pts_time(other), None, "{other}");
+        }
+    }
+
+    #[test]
+    fn test_pts_time_span() {
+        //b-frames are listed before the frames they are shown after.
+        assert_eq!(pts_time_span("0.000000\n0.080000\n0.040000\n"), Some(0.08));
+        //transport streams rarely start at zero.
+        assert_eq!(pts_time_span("1.500000\nN/A\n11.500000\n"), Some(10.0));
+
+        assert_eq!(pts_time_span("N/A\n"), None);
+        assert_eq!(pts_time_span(""), None);
+    }
+}
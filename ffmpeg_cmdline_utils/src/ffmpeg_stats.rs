@@ -39,8 +39,11 @@ impl From<std::num::ParseFloatError> for VideoInfoError {
 // There is a slighty gotcha in ffmpeg where if the video metadata declares a rotation,
 // raw (x, y) resolution in that metadata refers to the "unrotated" resolution. we must
 // therefore swap the x and y values if the rotation is 90 or 270
-#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Debug, Copy, Serialize, Deserialize, Hash)]
+#[derive(
+    PartialEq, Eq, PartialOrd, Ord, Clone, Debug, Copy, Serialize, Deserialize, Hash, Default,
+)]
 enum FfmpegVideoRotation {
+    #[default]
     Rot0,
     Rot90,
     Rot180,
@@ -48,9 +51,41 @@ enum FfmpegVideoRotation {
 }
 use FfmpegVideoRotation::*;
 
-impl Default for FfmpegVideoRotation {
-    fn default() -> Self {
-        Self::Rot0
+/// The range of values that the luma of a video is stored in.
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Serialize, Deserialize, Hash)]
+pub enum ColorRange {
+    /// Luma is stored from 16 to 235, as most video is ("tv" or "mpeg" range).
+    Limited,
+    /// Luma is stored from 0 to 255, as JPEGs and some screen recordings are ("pc" or "jpeg"
+    /// range).
+    Full,
+}
+
+/// The metadata of a single video stream within a file.
+#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize, Default)]
+pub struct VideoStreamInfo {
+    resolution: (u32, u32),
+    duration: Option<std::time::Duration>,
+    #[serde(default)]
+    constant_frame_rate: bool,
+}
+
+impl VideoStreamInfo {
+    /// The resolution of the stream in pixels, in the orientation it is intended to be viewed.
+    pub fn resolution(&self) -> (u32, u32) {
+        self.resolution
+    }
+
+    /// The duration of the stream, if the container records it.
+    pub fn duration(&self) -> Option<std::time::Duration> {
+        self.duration
+    }
+
+    /// Whether every frame of the stream lasts as long as every other. This is only true if ffprobe
+    /// reported the same base and average frame rates, so streams that do not say are assumed to
+    /// have a variable frame rate.
+    pub fn is_constant_frame_rate(&self) -> bool {
+        self.constant_frame_rate
     }
 }
 
@@ -60,11 +95,24 @@ pub struct VideoInfo {
     duration: std::time::Duration,
     file_size: u64,
     resolution: (u32, u32),
+    fps: Option<(u32, u32)>,
+    video_codec: Option<String>,
+    bit_rate: Option<u64>,
+    container: Option<String>,
+    video_streams: Vec<VideoStreamInfo>,
+    #[serde(default)]
+    audio_streams: usize,
+    #[serde(default)]
+    sample_aspect_ratio: Option<(u32, u32)>,
+    #[serde(default)]
+    color_range: Option<ColorRange>,
+    #[serde(default)]
+    interlaced: Option<bool>,
 }
 
 impl VideoInfo {
-    /// Use ffprobe to get the duration and resolution of a video. If the video contains multiple streams then only information
-    /// about the first stream will be returned.
+    /// Use ffprobe to get the metadata of a video. If the video contains multiple streams then only information
+    /// about the first stream will be returned, except by [`Self::video_streams`].
     ///
     /// # errors
     /// * The file cannot be read or is not recognized as a video by ffprobe
@@ -75,9 +123,14 @@ impl VideoInfo {
         P: AsRef<Path>,
     {
         let stats_string = get_video_stats(&src_path)?;
+        Self::from_ffprobe_json(&stats_string, src_path.as_ref())
+    }
 
+    // Parse the output of `ffprobe -show_format -show_streams -print_format json`.
+    // src_path is only used for error reporting.
+    fn from_ffprobe_json(stats_string: &str, src_path: &Path) -> Result<Self, FfmpegError> {
         let stats_parsed: Value =
-            serde_json::from_str(&stats_string).map_err(VideoInfoError::from)?;
+            serde_json::from_str(stats_string).map_err(VideoInfoError::from)?;
 
         let duration = if let Value::String(d) = &stats_parsed["format"]["duration"] {
             std::time::Duration::from_secs_f64(d.parse().map_err(VideoInfoError::from)?)
@@ -91,66 +144,187 @@ impl VideoInfo {
             0
         };
 
-        // If the video metadata declares that a video is rotated, then FFMPEG will conveniently autorotate
-        // each frame for us, however we will have to remember to swap around x and y axis if the rotation is
-        // 90 or 270
-        let rotation = {
-            //extract the rotation from the JSON
-            let rotation = Self::first_video(&stats_parsed).and_then(|video_stream| {
-                video_stream
-                    .get("side_data_list")
-                    .and_then(|y| y.get(0).and_then(|x| x.get("rotation").cloned()))
-            });
-
-            //if the rotation is found, it may either be a JSON String or JSON number, so unify
-            //them here.
-            let rotation = rotation.map(|rotation| match rotation {
-                Value::Number(val) => val.as_i64().unwrap(),
-                Value::String(val) => val.parse::<i64>().unwrap(),
-                _ => panic!(
-                    "got invalid json value type for video rotation. Expected: String or Number"
-                ),
-            });
-
-            //now make sure that the value is one of the four cardinal directions and return it
-            //(or if no rotation is specified, return 0/360)
-            match rotation {
-                None => FfmpegVideoRotation::Rot0,
-                Some(0) => FfmpegVideoRotation::Rot0,
-                Some(90) | Some(-270) => FfmpegVideoRotation::Rot90,
-                Some(180) | Some(-180) => FfmpegVideoRotation::Rot180,
-                Some(-90) | Some(270) => FfmpegVideoRotation::Rot270,
-                Some(_) => panic!(
-                    "ffprobe failure. Got unexpected rotation. src_path: {}, rotation: {:?}",
-                    src_path.as_ref().display(),
-                    rotation
-                ),
-            }
-        };
-
         let resolution = {
             let first_width = Self::first_vid_u32(&stats_parsed, "width").unwrap_or(0);
             let first_height = Self::first_vid_u32(&stats_parsed, "height").unwrap_or(0);
+            let rotation = Self::first_video(&stats_parsed)
+                .map(|video_stream| Self::rotation(video_stream, src_path))
+                .unwrap_or_default();
+
+            Self::rotate_resolution((first_width, first_height), rotation)
+        };
+
+        let video_streams = Self::streams_of_type(&stats_parsed, "video")
+            .unwrap_or_default()
+            .into_iter()
+            .map(|video_stream| {
+                let u32_field = |field_name: &str| {
+                    video_stream[field_name]
+                        .as_u64()
+                        .and_then(|v| u32::try_from(v).ok())
+                        .unwrap_or(0)
+                };
+
+                VideoStreamInfo {
+                    resolution: Self::rotate_resolution(
+                        (u32_field("width"), u32_field("height")),
+                        Self::rotation(video_stream, src_path),
+                    ),
+                    duration: Self::stream_duration(video_stream),
+                    constant_frame_rate: Self::constant_frame_rate(video_stream),
+                }
+            })
+            .collect();
+
+        let audio_streams = Self::streams_of_type(&stats_parsed, "audio").map_or(0, |s| s.len());
+
+        let first_video = Self::first_video(&stats_parsed);
+
+        let fps = first_video.and_then(|video_stream| {
+            //avg_frame_rate is "0/0" for some containers, in which case fall back to r_frame_rate.
+            ["avg_frame_rate", "r_frame_rate"]
+                .iter()
+                .find_map(|field_name| match &video_stream[field_name] {
+                    Value::String(rate) => Self::parse_frame_rate(rate),
+                    _ => None,
+                })
+        });
+
+        let video_codec = first_video.and_then(|video_stream| match &video_stream["codec_name"] {
+            Value::String(codec_name) => Some(codec_name.clone()),
+            _ => None,
+        });
+
+        //Not all containers record a per-stream bitrate (e.g. matroska), so fall back to the
+        //average bitrate of the whole file.
+        let bit_rate = first_video
+            .and_then(|video_stream| Self::parse_u64_string(&video_stream["bit_rate"]))
+            .or_else(|| Self::parse_u64_string(&stats_parsed["format"]["bit_rate"]));
 
-            if matches!(rotation, Rot0 | Rot180) {
-                (first_width, first_height)
-            } else {
-                (first_height, first_width)
+        //In the orientation the video is viewed, like the resolution.
+        let sample_aspect_ratio = first_video.and_then(|video_stream| {
+            let Value::String(ratio) = &video_stream["sample_aspect_ratio"] else {
+                return None;
+            };
+            let (num, den) = Self::parse_ratio(ratio, ':')?;
+            match Self::rotation(video_stream, src_path) {
+                Rot0 | Rot180 => Some((num, den)),
+                Rot90 | Rot270 => Some((den, num)),
             }
+        });
+
+        //ffprobe reports "unknown" (or nothing) when the stream does not say.
+        let color_range = first_video.and_then(|video_stream| match &video_stream["color_range"] {
+            Value::String(range) if range == "tv" || range == "mpeg" => Some(ColorRange::Limited),
+            Value::String(range) if range == "pc" || range == "jpeg" => Some(ColorRange::Full),
+            _ => None,
+        });
+
+        //The field order is "progressive", or says which field comes first ("tt", "bb", "tb" or
+        //"bt"). As with the color range, it is "unknown" (or missing) when the stream does not say.
+        let interlaced = first_video.and_then(|video_stream| match &video_stream["field_order"] {
+            Value::String(order) if order == "progressive" => Some(false),
+            Value::String(order) if ["tt", "bb", "tb", "bt"].contains(&order.as_str()) => {
+                Some(true)
+            }
+            _ => None,
+        });
+
+        let container = match &stats_parsed["format"]["format_name"] {
+            Value::String(format_name) => Some(format_name.clone()),
+            _ => None,
         };
 
         Ok(VideoInfo {
             duration,
             file_size,
             resolution,
+            fps,
+            video_codec,
+            bit_rate,
+            container,
+            video_streams,
+            audio_streams,
+            sample_aspect_ratio,
+            color_range,
+            interlaced,
         })
     }
 
+    // If the video metadata declares that a video is rotated, then FFMPEG will conveniently autorotate
+    // each frame for us, however we will have to remember to swap around x and y axis if the rotation is
+    // 90 or 270
+    fn rotation(video_stream: &Value, src_path: &Path) -> FfmpegVideoRotation {
+        //extract the rotation from the JSON
+        let rotation = video_stream
+            .get("side_data_list")
+            .and_then(|y| y.get(0).and_then(|x| x.get("rotation").cloned()));
+
+        //if the rotation is found, it may either be a JSON String or JSON number, so unify
+        //them here.
+        let rotation = rotation.map(|rotation| match rotation {
+            Value::Number(val) => val.as_i64().unwrap(),
+            Value::String(val) => val.parse::<i64>().unwrap(),
+            _ => {
+                panic!("got invalid json value type for video rotation. Expected: String or Number")
+            }
+        });
+
+        //now make sure that the value is one of the four cardinal directions and return it
+        //(or if no rotation is specified, return 0/360)
+        match rotation {
+            None => FfmpegVideoRotation::Rot0,
+            Some(0) => FfmpegVideoRotation::Rot0,
+            Some(90) | Some(-270) => FfmpegVideoRotation::Rot90,
+            Some(180) | Some(-180) => FfmpegVideoRotation::Rot180,
+            Some(-90) | Some(270) => FfmpegVideoRotation::Rot270,
+            Some(_) => panic!(
+                "ffprobe failure. Got unexpected rotation. src_path: {}, rotation: {:?}",
+                src_path.display(),
+                rotation
+            ),
+        }
+    }
+
+    fn rotate_resolution((x, y): (u32, u32), rotation: FfmpegVideoRotation) -> (u32, u32) {
+        if matches!(rotation, Rot0 | Rot180) {
+            (x, y)
+        } else {
+            (y, x)
+        }
+    }
+
+    // Most containers record the duration of each stream in seconds, but matroska only has a
+    // "HH:MM:SS.fraction" tag.
+    fn stream_duration(video_stream: &Value) -> Option<std::time::Duration> {
+        if let Value::String(secs) = &video_stream["duration"] {
+            return secs
+                .parse()
+                .ok()
+                .and_then(|secs| std::time::Duration::try_from_secs_f64(secs).ok());
+        }
+
+        let Value::String(tag) = &video_stream["tags"]["DURATION"] else {
+            return None;
+        };
+        let mut parts = tag.splitn(3, ':');
+        let hours: f64 = parts.next()?.parse().ok()?;
+        let minutes: f64 = parts.next()?.parse().ok()?;
+        let seconds: f64 = parts.next()?.parse().ok()?;
+        std::time::Duration::try_from_secs_f64(hours * 3600.0 + minutes * 60.0 + seconds).ok()
+    }
+
     /// The duration of the video in seconds
     pub fn duration(&self) -> std::time::Duration {
         self.duration
     }
 
+    /// The duration of the video, or None if its container does not record one (as some transport
+    /// streams and fragmented MP4s do not), in which case [`Self::duration`] is zero.
+    pub fn container_duration(&self) -> Option<std::time::Duration> {
+        Some(self.duration).filter(|duration| !duration.is_zero())
+    }
+
     /// The size of the video in bytes
     pub fn file_size(&self) -> u64 {
         self.file_size
@@ -163,6 +337,123 @@ impl VideoInfo {
         self.resolution
     }
 
+    /// The average frame rate of the first video stream as a fraction (numerator, denominator),
+    /// or None if ffprobe did not report a usable frame rate.
+    pub fn fps(&self) -> Option<(u32, u32)> {
+        self.fps
+    }
+
+    /// The ffprobe name of the codec of the first video stream, e.g. "h264"
+    pub fn video_codec(&self) -> Option<&str> {
+        self.video_codec.as_deref()
+    }
+
+    /// The bitrate of the first video stream in bits per second. If the container
+    /// does not record a per-stream bitrate, the average bitrate of the whole file is returned instead.
+    pub fn bit_rate(&self) -> Option<u64> {
+        self.bit_rate
+    }
+
+    /// The ffprobe name of the container format, e.g. "mov,mp4,m4a,3gp,3g2,mj2"
+    pub fn container(&self) -> Option<&str> {
+        self.container.as_deref()
+    }
+
+    /// Every video stream in the file, in the order they are stored. Indexes into this slice are
+    /// the indexes accepted by [`FfmpegFrameReaderBuilder::video_stream_index`].
+    pub fn video_streams(&self) -> &[VideoStreamInfo] {
+        &self.video_streams
+    }
+
+    /// The shape of the pixels of the first video stream as (width, height), such as (64, 45) for
+    /// a widescreen PAL DVD, or None if ffprobe did not report one. Like the resolution, this is
+    /// for the orientation that the video is intended to be viewed in. Frames are decoded at
+    /// their stored resolution, so they must be stretched by this ratio to look as intended.
+    pub fn sample_aspect_ratio(&self) -> Option<(u32, u32)> {
+        self.sample_aspect_ratio
+    }
+
+    /// The range that the luma of the first video stream is stored in, or None if ffprobe did not
+    /// report one. Grayscale frames are always decoded to full range, whatever this is.
+    pub fn color_range(&self) -> Option<ColorRange> {
+        self.color_range
+    }
+
+    /// Whether the first video stream is interlaced, or None if ffprobe did not report its field
+    /// order.
+    pub fn is_interlaced(&self) -> Option<bool> {
+        self.interlaced
+    }
+
+    /// The number of audio streams in the file.
+    pub fn audio_streams(&self) -> usize {
+        self.audio_streams
+    }
+
+    /// The index of the video stream that is decoded unless another is requested: the stream with
+    /// the largest resolution × duration, or the earliest such stream if there is a tie. Streams
+    /// without a recorded duration are assumed to last as long as the file.
+    ///
+    /// Returns None if the file has no video streams.
+    pub fn default_video_stream(&self) -> Option<usize> {
+        let score = |stream: &VideoStreamInfo| {
+            let (x, y) = stream.resolution;
+            let area = u64::from(x) * u64::from(y);
+            let duration = stream.duration.unwrap_or(self.duration).as_secs_f64();
+            (area as f64 * duration, area)
+        };
+
+        self.video_streams
+            .iter()
+            .enumerate()
+            .rev()
+            .max_by(|(_, s1), (_, s2)| {
+                let (score_1, area_1) = score(s1);
+                let (score_2, area_2) = score(s2);
+                score_1.total_cmp(&score_2).then(area_1.cmp(&area_2))
+            })
+            .map(|(idx, _stream)| idx)
+    }
+
+    fn parse_frame_rate(rate: &str) -> Option<(u32, u32)> {
+        Self::parse_ratio(rate, '/')
+    }
+
+    // r_frame_rate is the lowest rate that every timestamp falls on, so it is only the average
+    // rate if no frames are skipped or held.
+    fn constant_frame_rate(video_stream: &Value) -> bool {
+        let rate = |field_name: &str| match &video_stream[field_name] {
+            Value::String(rate) => Self::parse_frame_rate(rate),
+            _ => None,
+        };
+        match (rate("r_frame_rate"), rate("avg_frame_rate")) {
+            (Some((r_num, r_den)), Some((avg_num, avg_den))) => {
+                u64::from(r_num) * u64::from(avg_den) == u64::from(avg_num) * u64::from(r_den)
+            }
+            _ => false,
+        }
+    }
+
+    // Ratios with a zero in them (such as ffprobe's "0:1" for an unknown aspect ratio) are None.
+    fn parse_ratio(ratio: &str, separator: char) -> Option<(u32, u32)> {
+        let (num, den) = ratio.split_once(separator)?;
+        let (num, den) = (num.trim().parse().ok()?, den.trim().parse().ok()?);
+
+        if num == 0 || den == 0 {
+            None
+        } else {
+            Some((num, den))
+        }
+    }
+
+    fn parse_u64_string(val: &Value) -> Option<u64> {
+        match val {
+            Value::String(s) => s.parse().ok(),
+            Value::Number(n) => n.as_u64(),
+            _ => None,
+        }
+    }
+
     fn first_video(stats_parsed: &Value) -> Option<&Value> {
         Self::streams_of_type(stats_parsed, "video").and_then(|mut videos| videos.drain(..).next())
     }
@@ -200,3 +491,185 @@ impl VideoInfo {
         all_matched_values.iter().cloned().next()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const MP4_STATS: &str = r#"{
+        "streams": [
+            {
+                "index": 0,
+                "codec_name": "h264",
+                "codec_type": "video",
+                "width": 1920,
+                "height": 1080,
+                "r_frame_rate": "30000/1001",
+                "avg_frame_rate": "30000/1001",
+                "bit_rate": "4500000",
+                "sample_aspect_ratio": "4:3",
+                "color_range": "tv",
+                "field_order": "tt",
+                "side_data_list": [{ "rotation": -90 }]
+            },
+            {
+                "index": 1,
+                "codec_name": "aac",
+                "codec_type": "audio",
+                "bit_rate": "128000"
+            }
+        ],
+        "format": {
+            "format_name": "mov,mp4,m4a,3gp,3g2,mj2",
+            "duration": "12.500000",
+            "size": "7231000",
+            "bit_rate": "4627840"
+        }
+    }"#;
+
+    const MKV_STATS: &str = r#"{
+        "streams": [
+            {
+                "index": 0,
+                "codec_name": "vp9",
+                "codec_type": "video",
+                "width": 640,
+                "height": 360,
+                "r_frame_rate": "25/1",
+                "avg_frame_rate": "0/0",
+                "sample_aspect_ratio": "0:1",
+                "color_range": "unknown",
+                "field_order": "unknown"
+            }
+        ],
+        "format": {
+            "format_name": "matroska,webm",
+            "duration": "3.000000",
+            "size": "300000",
+            "bit_rate": "800000"
+        }
+    }"#;
+
+    // A feature with a small cover animation stored before it.
+    const MULTI_STREAM_STATS: &str = r#"{
+        "streams": [
+            {
+                "index": 0,
+                "codec_name": "mjpeg",
+                "codec_type": "video",
+                "width": 320,
+                "height": 240,
+                "tags": { "DURATION": "00:00:05.000000000" }
+            },
+            {
+                "index": 1,
+                "codec_name": "aac",
+                "codec_type": "audio"
+            },
+            {
+                "index": 2,
+                "codec_name": "h264",
+                "codec_type": "video",
+                "width": 1280,
+                "height": 720,
+                "r_frame_rate": "60/1",
+                "avg_frame_rate": "2997/100",
+                "tags": { "DURATION": "01:02:03.500000000" }
+            },
+            {
+                "index": 3,
+                "codec_name": "h264",
+                "codec_type": "video",
+                "width": 720,
+                "height": 1280,
+                "side_data_list": [{ "rotation": 90 }]
+            }
+        ],
+        "format": {
+            "format_name": "matroska,webm",
+            "duration": "3723.500000"
+        }
+    }"#;
+
+    fn parse(stats: &str) -> VideoInfo {
+        VideoInfo::from_ffprobe_json(stats, Path::new("test.vid")).expect("valid ffprobe output")
+    }
+
+    #[test]
+    fn test_parses_stream_metadata() {
+        let info = parse(MP4_STATS);
+
+        assert_eq!(info.duration(), std::time::Duration::from_secs_f64(12.5));
+        assert_eq!(
+            info.container_duration(),
+            Some(std::time::Duration::from_secs_f64(12.5))
+        );
+        assert_eq!(info.file_size(), 7_231_000);
+        assert_eq!(info.resolution(), (1080, 1920));
+        assert_eq!(info.fps(), Some((30000, 1001)));
+        assert_eq!(info.video_codec(), Some("h264"));
+        assert_eq!(info.bit_rate(), Some(4_500_000));
+        assert_eq!(info.container(), Some("mov,mp4,m4a,3gp,3g2,mj2"));
+        assert_eq!(info.audio_streams(), 1);
+        //the video is rotated, so its pixels are too.
+        assert_eq!(info.sample_aspect_ratio(), Some((3, 4)));
+        assert_eq!(info.color_range(), Some(ColorRange::Limited));
+        assert_eq!(info.is_interlaced(), Some(true));
+        assert!(info.video_streams()[0].is_constant_frame_rate());
+    }
+
+    #[test]
+    fn test_falls_back_when_stream_fields_missing() {
+        let info = parse(MKV_STATS);
+
+        assert_eq!(info.resolution(), (640, 360));
+        assert_eq!(info.fps(), Some((25, 1)));
+        assert_eq!(info.video_codec(), Some("vp9"));
+        assert_eq!(info.bit_rate(), Some(800_000));
+        assert_eq!(info.container(), Some("matroska,webm"));
+        assert_eq!(info.audio_streams(), 0);
+        assert_eq!(info.sample_aspect_ratio(), None);
+        assert_eq!(info.color_range(), None);
+        assert_eq!(info.is_interlaced(), None);
+        //without an average frame rate, the stream may skip frames.
+        assert!(!info.video_streams()[0].is_constant_frame_rate());
+    }
+
+    #[test]
+    fn test_default_video_stream() {
+        let info = parse(MULTI_STREAM_STATS);
+
+        let streams = info.video_streams();
+        assert_eq!(streams.len(), 3);
+        assert_eq!(streams[0].resolution(), (320, 240));
+        assert_eq!(
+            streams[0].duration(),
+            Some(std::time::Duration::from_secs(5))
+        );
+        assert_eq!(
+            streams[1].duration(),
+            Some(std::time::Duration::from_secs_f64(3723.5))
+        );
+        assert_eq!(streams[2].resolution(), (1280, 720));
+        assert_eq!(streams[2].duration(), None);
+        //a screen recording, which only has frames when something moves.
+        assert!(!streams[1].is_constant_frame_rate());
+
+        //streams 1 and 2 tie (stream 2 lasts as long as the file), so the earliest wins.
+        assert_eq!(info.default_video_stream(), Some(1));
+
+        //the first stream is still used for the summary fields.
+        assert_eq!(info.resolution(), (320, 240));
+        assert_eq!(info.video_codec(), Some("mjpeg"));
+
+        assert_eq!(parse(MKV_STATS).default_video_stream(), Some(0));
+    }
+
+    #[test]
+    fn test_missing_metadata_is_none() {
+        let info = parse(r#"{ "streams": [], "format": {} }"#);
+
+        assert_eq!(info, VideoInfo::default());
+        assert_eq!(info.container_duration(), None);
+    }
+}
@@ -0,0 +1,112 @@
+use std::sync::OnceLock;
+
+use serde::{Deserialize, Serialize};
+
+use crate::ffmpeg_ops::{query_version_output, FfmpegCommandName};
+use crate::*;
+
+// The result of the first call to verify_ffmpeg, which every later call returns.
+static VERIFIED: OnceLock<Result<FfmpegVersion, FfmpegError>> = OnceLock::new();
+
+/// The versions of ffmpeg and ffprobe that were found by [`verify_ffmpeg`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FfmpegVersion {
+    /// The version printed by `ffmpeg -version`, such as "6.1.1-3ubuntu5". Builds from git print
+    /// something like "N-113404-g0ae5f7b" instead.
+    pub ffmpeg: String,
+
+    /// The version printed by `ffprobe -version`.
+    pub ffprobe: String,
+}
+
+impl FfmpegVersion {
+    /// The major version of ffmpeg, or of ffprobe if it is older. `None` if either version has no
+    /// version number, as for builds from git.
+    pub fn major(&self) -> Option<u32> {
+        Some(major_version(&self.ffmpeg)?.min(major_version(&self.ffprobe)?))
+    }
+
+    /// True if the major version is known, and is older than `min_major`.
+    pub fn is_older_than(&self, min_major: u32) -> bool {
+        self.major().is_some_and(|major| major < min_major)
+    }
+}
+
+/// Check that ffmpeg and ffprobe can be run, and find their versions.
+///
+/// The check is only done the first time this is called, and every later call returns the same
+/// result. Everything in this crate that runs ffmpeg or ffprobe calls this first, so if either is
+/// missing they all fail immediately with [`FfmpegError::FfmpegNotFound`], without trying to run
+/// anything.
+pub fn verify_ffmpeg() -> Result<FfmpegVersion, FfmpegError> {
+    VERIFIED
+        .get_or_init(|| {
+            let version_of = |name| query_version_output(name).map(|out| parse_version(&out));
+            Ok(FfmpegVersion {
+                ffmpeg: version_of(FfmpegCommandName::Ffmpeg)?,
+                ffprobe: version_of(FfmpegCommandName::Ffprobe)?,
+            })
+        })
+        .clone()
+}
+
+// The first line of `ffmpeg -version` looks like "ffmpeg version 6.1.1-3ubuntu5 Copyright (c)...".
+// If it doesn't, there is no version to speak of.
+fn parse_version(output: &str) -> String {
+    output
+        .lines()
+        .next()
+        .and_then(|line| line.split_once(" version "))
+        .and_then(|(_name, rest)| rest.split_whitespace().next())
+        .unwrap_or_default()
+        .to_string()
+}
+
+// Release builds are numbered like "6.1.1" (or "n6.1.1" when built from a release tag).
+fn major_version(version: &str) -> Option<u32> {
+    let version = version.strip_prefix('n').unwrap_or(version);
+    let end = version
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(version.len());
+    version[..end].parse().ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_version() {
+        let version = |ffmpeg: &str, ffprobe: &str| FfmpegVersion {
+            ffmpeg: parse_version(ffmpeg),
+            ffprobe: parse_version(ffprobe),
+        };
+
+        let distro = version(
+            "ffmpeg version 6.1.1-3ubuntu5 Copyright (c) 2000-2023 the FFmpeg developers\nbuilt with gcc 13",
+            "ffprobe version 6.1.1-3ubuntu5 Copyright (c) 2007-2023 the FFmpeg developers",
+        );
+        assert_eq!(distro.ffmpeg, "6.1.1-3ubuntu5");
+        assert_eq!(distro.major(), Some(6));
+        assert!(distro.is_older_than(7));
+        assert!(!distro.is_older_than(6));
+
+        //the older of the two counts.
+        let mixed = version(
+            "ffmpeg version n7.0 Copyright",
+            "ffprobe version 4.4.2 Copyright",
+        );
+        assert_eq!(mixed.major(), Some(4));
+
+        //nothing is known about builds from git, so they are never too old.
+        let git = version(
+            "ffmpeg version N-113404-g0ae5f7b Copyright",
+            "ffprobe version N-113404-g0ae5f7b Copyright",
+        );
+        assert_eq!(git.major(), None);
+        assert!(!git.is_older_than(100));
+
+        assert_eq!(parse_version(""), "");
+        assert_eq!(major_version(""), None);
+    }
+}
@@ -1,7 +1,9 @@
 mod ffmpeg_error_kind;
 mod ffmpeg_ops;
 mod ffmpeg_stats;
+mod ffmpeg_version;
 
 pub use ffmpeg_error_kind::*;
 pub use ffmpeg_ops::*;
 pub use ffmpeg_stats::*;
+pub use ffmpeg_version::*;
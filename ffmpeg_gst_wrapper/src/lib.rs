@@ -1,35 +1,252 @@
 use std::{fmt::Debug, path::Path, time::Duration};
 
 use image::{GrayImage, RgbImage};
+use serde::{Deserialize, Serialize};
 
 //#[cfg(all(feature = "ffmpeg_backend", feature = "gstreamer_backend"))]
 //compile_error!("feature \"ffmpeg_backend\" and feature \"gstreamer_backend\" cannot be enabled at the same time");
 
+/// Metadata about a video, as reported by the active backend.
+///
+/// Codec and container names are backend specific: ffmpeg reports short names
+/// such as "h264", whereas gstreamer reports descriptions such as "H.264 (High Profile)".
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct MediaInfo {
+    pub resolution: (u32, u32),
+
+    /// The duration that the container records, or zero if it records none. Unlike
+    /// [`FrameReadCfgTrait::get_duration`], the duration is never measured.
+    pub duration: Duration,
+    pub fps: Option<(u32, u32)>,
+    pub video_codec: Option<String>,
+    pub bit_rate: Option<u64>,
+    pub container: Option<String>,
+
+    /// The shape of the pixels of the video as (width, height), if the file says what it is.
+    /// Frames are decoded at [`Self::resolution`], so anamorphic video (such as a widescreen DVD,
+    /// stored at 720x576 with a pixel aspect ratio of 64:45) must be stretched by this ratio to
+    /// look as intended.
+    pub pixel_aspect_ratio: Option<(u32, u32)>,
+
+    /// The range that the luma of the video is stored in, if the file says. Both backends
+    /// convert gray frames to full range, so this does not change the frames that are decoded.
+    pub color_range: Option<ColorRange>,
+
+    /// Whether the video is interlaced, if the file says. This is what
+    /// [`Deinterlace::Auto`] uses to decide whether to deinterlace.
+    #[serde(default)]
+    pub interlaced: Option<bool>,
+}
+
+/// The range of values that the luma of a video is stored in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ColorRange {
+    /// Luma is stored from 16 to 235, as most video is ("tv" range).
+    Limited,
+    /// Luma is stored from 0 to 255 ("pc" range).
+    Full,
+}
+
+/// Where the duration of a video came from, as returned by
+/// [`FrameReadCfgTrait::get_duration_with_source`].
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default, Serialize, Deserialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum DurationSource {
+    /// The container of the video records its duration.
+    #[default]
+    Container,
+    /// The container does not record a duration (as some transport streams and fragmented MP4s
+    /// do not), so it was measured by reading the video (see
+    /// [`FrameReadCfgTrait::measure_duration`]).
+    Measured,
+}
+
+/// The longest video whose duration [`FrameReadCfgTrait::get_duration`] measures when its
+/// container does not record one. Reading stops after this much of a video, so that a broken
+/// file cannot be read forever.
+pub const DEFAULT_MAX_MEASURED_DURATION: Duration = Duration::from_secs(4 * 60 * 60);
+
+/// Whether to deinterlace frames before they are returned. gstreamer uses its `deinterlace`
+/// element and ffmpeg uses its yadif filter, which both output one frame for each interlaced
+/// frame, so timestamps and frame rates are unchanged.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default, Serialize, Deserialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum Deinterlace {
+    /// Frames are returned as they are decoded.
+    #[default]
+    Off,
+    /// Interlaced video is deinterlaced, as the backend detects it (from the caps of the stream
+    /// for gstreamer, and the flags of each frame for ffmpeg).
+    Auto,
+    /// Every video is deinterlaced, whether or not it is interlaced.
+    Force,
+}
+
+/// What a backend used to decode a video and how long it took, as returned by
+/// [`FrameReadCfgTrait::diagnose`].
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct DecodeDiagnostics {
+    /// What decoded the video. For gstreamer this lists the elements of the pipeline (see
+    /// `vid_frame_iter::VideoFrameIter::pipeline_description`). ffmpeg chooses its decoder
+    /// internally, so for ffmpeg this only names the stream that was decoded.
+    pub description: String,
+
+    /// The name of the video decoder, if the backend reports it. Only gstreamer does, giving the
+    /// factory name of the decoder element, such as "avdec_h264".
+    pub decoder: Option<String>,
+
+    /// How long the decoded frames took to arrive, or None if no frames were decoded.
+    pub frame_latency: Option<FrameLatency>,
+}
+
+/// What a backend decodes videos with, as returned by [`FrameReadCfgTrait::components`].
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct BackendComponents {
+    /// The name of the backend, "gstreamer" or "ffmpeg". Empty for backends which do not report
+    /// their components.
+    pub backend: &'static str,
+
+    /// The version of each component that frames pass through, as `(name, version)` pairs. For
+    /// gstreamer these are gstreamer itself and each plugin that decodes or converts frames, and
+    /// for ffmpeg they are ffmpeg and ffprobe.
+    pub versions: Vec<(String, String)>,
+}
+
+/// Decoding pipelines shared between the readers given it with
+/// [`FrameReadCfgTrait::reuse_pipelines`], so that reading many videos does not set up a new
+/// pipeline for each one. Only gstreamer keeps pipelines (see `vid_frame_iter::PipelinePool`).
+/// ffmpeg runs a new process for every video, so for ffmpeg the pool does nothing.
+///
+/// Clones share the same pipelines.
+#[derive(Debug, Clone, Default)]
+pub struct PipelinePool {
+    #[cfg(feature = "gstreamer_backend")]
+    gst: vid_frame_iter::PipelinePool,
+}
+
+impl PipelinePool {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+/// The time taken for a backend to produce each frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameLatency {
+    pub frames: u64,
+    pub min: Duration,
+    pub mean: Duration,
+    pub max: Duration,
+}
+
 pub trait FrameReadCfgTrait {
     type E: Debug + std::error::Error;
 
     fn from_path(src_path: &Path) -> Self;
+
+    /// The duration of the video, measured (up to [`DEFAULT_MAX_MEASURED_DURATION`]) if its
+    /// container does not record one.
     fn get_duration(&self) -> Result<Duration, Self::E>;
+
+    /// The duration of the video as its container records it, or None if it records none.
+    fn get_container_duration(&self) -> Result<Option<Duration>, Self::E>;
+
+    /// Measure the duration of the video by reading it, which is much slower than asking its
+    /// container. Reading stops `cap` into the video, returning None if it lasts any longer (or
+    /// if nothing could be read).
+    fn measure_duration(&self, cap: Duration) -> Result<Option<Duration>, Self::E>;
+
+    /// The duration of the video and where it came from: its container, or if the container
+    /// records none, [`Self::measure_duration`] with `cap`. None if neither knows it.
+    fn get_duration_with_source(
+        &self,
+        cap: Duration,
+    ) -> Result<Option<(Duration, DurationSource)>, Self::E> {
+        if let Some(duration) = self.get_container_duration()? {
+            return Ok(Some((duration, DurationSource::Container)));
+        }
+        Ok(self
+            .measure_duration(cap)?
+            .map(|duration| (duration, DurationSource::Measured)))
+    }
+
     fn get_resolution(&self) -> Result<(u32, u32), Self::E>;
+
+    /// Get all available metadata at once. Prefer this over calling [`Self::get_duration`]
+    /// and [`Self::get_resolution`] separately, as the file is only probed once.
+    fn get_media_info(&self) -> Result<MediaInfo, Self::E>;
     fn fps(&mut self, fps: (u64, u64));
     fn start_offset(&mut self, offset: f64);
+
+    /// Decode the video stream with the given index (counting video streams only) instead of the
+    /// default, which is the stream with the largest resolution × duration.
+    fn video_stream_index(&mut self, index: usize);
+
+    /// The index of the video stream that will be decoded.
+    fn selected_video_stream(&self) -> Result<usize, Self::E>;
+
+    /// Deinterlace frames before they are returned. Off by default.
+    fn deinterlace(&mut self, mode: Deinterlace);
+
+    /// Decode with pipelines from `pool` where the backend can, returning them to it afterwards.
+    /// The frames are the same either way.
+    fn reuse_pipelines(&mut self, _pool: &PipelinePool) {}
     fn spawn_gray(self) -> impl Iterator<Item = Result<GrayImage, Self::E>>;
+
+    /// Decode frames paired with their presentation timestamps measured from
+    /// [`Self::start_offset`]. Variable frame rate videos are decoded with no framerate conversion
+    /// (ignoring [`Self::fps`]), but backends may convert others as [`Self::spawn_gray`] does.
+    /// Returns None if the backend cannot report timestamps.
+    fn spawn_gray_timestamped(
+        self,
+    ) -> Option<impl Iterator<Item = Result<(Duration, GrayImage), Self::E>>>;
     fn spawn_rgb(self) -> impl Iterator<Item = Result<RgbImage, Self::E>>;
+
+    /// Decode up to `duration` seconds of the first audio stream from [`Self::start_offset`],
+    /// mixed down to mono and resampled to `sample_rate`. Returns None if there is no audio.
+    fn decode_audio(&self, sample_rate: u32, duration: f64) -> Result<Option<Vec<i16>>, Self::E>;
+
+    /// Start decoding the video in grayscale as [`Self::spawn_gray`] does, decode up to `frames`
+    /// frames, and report what decoded them and how long they took.
+    fn diagnose(&self, frames: usize) -> Result<DecodeDiagnostics, Self::E>;
+
+    /// What this backend decodes videos with. The backend must have been initialized first.
+    /// Backends which do not report their components return the default.
+    fn components() -> BackendComponents {
+        BackendComponents::default()
+    }
 }
 
 #[cfg(feature = "gstreamer_backend")]
 pub mod gst_impl {
-    use std::{path::Path, time::Duration};
+    use std::{path::Path, sync::OnceLock, time::Duration};
 
     use image::{GrayImage, RgbImage};
     use serde::{Deserialize, Serialize};
     use thiserror::Error;
     use vid_frame_iter::{ImageFns, VideoFrameIterBuilder};
 
-    use crate::FrameReadCfgTrait;
+    use crate::{
+        BackendComponents, ColorRange, DecodeDiagnostics, Deinterlace, FrameLatency,
+        FrameReadCfgTrait, MediaInfo, PipelinePool, DEFAULT_MAX_MEASURED_DURATION,
+    };
+
+    // The plugins cannot change while the process is running, so they are only looked up once.
+    static COMPONENT_VERSIONS: OnceLock<Vec<(String, String)>> = OnceLock::new();
+
+    // The frame rate that durations are measured at, which they are accurate to within a frame of.
+    const MEASURE_DURATION_FPS: u64 = 2;
 
+    // Holds an error instead of a builder if the path could not be converted to a URI, which is
+    // then returned by every method that reads the video.
     #[derive(Debug, Clone)]
-    pub struct FrameReaderCfgGst(VideoFrameIterBuilder);
+    pub struct FrameReaderCfgGst(Result<VideoFrameIterBuilder, GstError>);
 
     #[derive(Error, Debug, Clone, Serialize, Deserialize)]
     pub enum GstError {
@@ -38,6 +255,49 @@ pub mod gst_impl {
 
         #[error("gstreamer error: {0}")]
         GlibError(String),
+
+        /// The path cannot be expressed as a file URI, so gstreamer cannot open it. Holds the
+        /// debug representation of the path, which escapes any bytes which are not UTF-8.
+        #[error("Path cannot be converted to a URI: {0}")]
+        UnrepresentablePath(String),
+    }
+
+    /// Initialize gstreamer and check that the elements needed to read frames are installed.
+    /// Returns the names of anything that is missing.
+    pub fn init() -> Result<(), Vec<String>> {
+        if let Err(e) = vid_frame_iter::try_init_gstreamer() {
+            return Err(vec![format!("gstreamer ({})", e.message())]);
+        }
+
+        let missing = vid_frame_iter::missing_elements();
+        if missing.is_empty() {
+            Ok(())
+        } else {
+            Err(missing
+                .into_iter()
+                .map(|name| format!("gstreamer element {name}"))
+                .collect())
+        }
+    }
+
+    // On unix any absolute path can be converted, as bytes which are not UTF-8 are
+    // percent-encoded. Relative paths are made absolute first.
+    fn uri_from_path(src_path: &Path) -> Result<String, GstError> {
+        let unrepresentable = || GstError::UnrepresentablePath(format!("{src_path:?}"));
+        let abs_path = std::path::absolute(src_path).map_err(|_e| unrepresentable())?;
+        url::Url::from_file_path(abs_path)
+            .map(String::from)
+            .map_err(|()| unrepresentable())
+    }
+
+    impl FrameReaderCfgGst {
+        fn builder(&self) -> Result<&VideoFrameIterBuilder, GstError> {
+            self.0.as_ref().map_err(Clone::clone)
+        }
+
+        fn uri(&self) -> Result<&str, GstError> {
+            self.builder().map(VideoFrameIterBuilder::uri)
+        }
     }
 
     impl From<glib::Error> for GstError {
@@ -54,22 +314,91 @@ pub mod gst_impl {
     impl FrameReadCfgTrait for FrameReaderCfgGst {
         type E = GstError;
 
+        fn components() -> BackendComponents {
+            BackendComponents {
+                backend: "gstreamer",
+                versions: COMPONENT_VERSIONS
+                    .get_or_init(vid_frame_iter::component_versions)
+                    .clone(),
+            }
+        }
+
         fn from_path(src_path: &Path) -> Self {
             vid_frame_iter::init_gstreamer();
-            let uri_string = url::Url::from_file_path(src_path).unwrap().to_string();
-            Self(VideoFrameIterBuilder::from_uri(uri_string))
+            Self(uri_from_path(src_path).map(VideoFrameIterBuilder::from_uri))
         }
 
         fn fps(&mut self, fps: (u64, u64)) {
-            self.0.frame_rate(fps)
+            if let Ok(builder) = &mut self.0 {
+                builder.frame_rate(fps)
+            }
         }
 
         fn start_offset(&mut self, duration: f64) {
-            self.0.start_offset(duration)
+            if let Ok(builder) = &mut self.0 {
+                builder.start_offset(duration)
+            }
+        }
+
+        fn decode_audio(
+            &self,
+            sample_rate: u32,
+            duration: f64,
+        ) -> Result<Option<Vec<i16>>, Self::E> {
+            Ok(self.builder()?.decode_audio(sample_rate, duration)?)
+        }
+
+        fn diagnose(&self, frames: usize) -> Result<DecodeDiagnostics, Self::E> {
+            let mut it = self.builder()?.spawn_gray()?;
+            for frame in it.by_ref().take(frames) {
+                frame?;
+            }
+            Ok(DecodeDiagnostics {
+                description: it.pipeline_description(),
+                decoder: it.decoder().map(str::to_string),
+                frame_latency: it.decode_stats().map(|stats| FrameLatency {
+                    frames: stats.frames,
+                    min: stats.min,
+                    mean: stats.mean,
+                    max: stats.max,
+                }),
+            })
+        }
+
+        fn video_stream_index(&mut self, index: usize) {
+            if let Ok(builder) = &mut self.0 {
+                builder.video_stream_index(index)
+            }
+        }
+
+        fn selected_video_stream(&self) -> Result<usize, Self::E> {
+            match self.builder()?.selected_video_stream() {
+                Ok(Some(index)) => Ok(index),
+                Ok(None) => Err(GstError::NotVideo),
+                Err(e) => Err(e.into()),
+            }
+        }
+
+        fn deinterlace(&mut self, mode: Deinterlace) {
+            if let Ok(builder) = &mut self.0 {
+                builder.deinterlace(match mode {
+                    Deinterlace::Off => vid_frame_iter::Deinterlace::Off,
+                    Deinterlace::Auto => vid_frame_iter::Deinterlace::Auto,
+                    Deinterlace::Force => vid_frame_iter::Deinterlace::Force,
+                })
+            }
+        }
+
+        fn reuse_pipelines(&mut self, pool: &PipelinePool) {
+            if let Ok(builder) = &mut self.0 {
+                builder.reuse_pipelines(&pool.gst);
+            }
         }
 
         fn spawn_gray(self) -> impl Iterator<Item = Result<GrayImage, Self::E>> {
-            let mut maybe_it = self.0.spawn_gray();
+            let mut maybe_it = self
+                .0
+                .and_then(|builder| builder.spawn_gray().map_err(GstError::from));
             let mut done = false;
             std::iter::from_fn(move || {
                 if done {
@@ -78,7 +407,7 @@ pub mod gst_impl {
                     match &mut maybe_it {
                         Err(e) => {
                             done = true;
-                            Some(Err(e.clone().into()))
+                            Some(Err(e.clone()))
                         }
                         &mut Ok(ref mut it) => match it.next() {
                             Some(next_frame) => match next_frame {
@@ -92,8 +421,17 @@ pub mod gst_impl {
             })
         }
 
+        // vid_frame_iter does not expose the timestamps of frames.
+        fn spawn_gray_timestamped(
+            self,
+        ) -> Option<impl Iterator<Item = Result<(Duration, GrayImage), Self::E>>> {
+            None::<std::iter::Empty<_>>
+        }
+
         fn spawn_rgb(self) -> impl Iterator<Item = Result<RgbImage, Self::E>> {
-            let mut maybe_it = self.0.spawn_rgb();
+            let mut maybe_it = self
+                .0
+                .and_then(|builder| builder.spawn_rgb().map_err(GstError::from));
             let mut done = false;
             std::iter::from_fn(move || {
                 if done {
@@ -102,7 +440,7 @@ pub mod gst_impl {
                     match &mut maybe_it {
                         Err(e) => {
                             done = true;
-                            Some(Err(e.clone().into()))
+                            Some(Err(e.clone()))
                         }
                         &mut Ok(ref mut it) => match it.next() {
                             Some(next_frame) => match next_frame {
@@ -117,44 +455,136 @@ pub mod gst_impl {
         }
 
         fn get_duration(&self) -> Result<Duration, Self::E> {
-            match vid_frame_iter::mediainfo_utils::duration(self.0.uri()) {
-                Ok(Some(duration)) => Ok(duration),
-                Ok(None) => Err(GstError::NotVideo),
+            self.get_duration_with_source(DEFAULT_MAX_MEASURED_DURATION)?
+                .map(|(duration, _source)| duration)
+                .ok_or(GstError::NotVideo)
+        }
+
+        fn get_container_duration(&self) -> Result<Option<Duration>, Self::E> {
+            match vid_frame_iter::mediainfo_utils::duration(self.uri()?) {
+                Ok(Some(duration)) => Ok(Some(duration)),
+                //also returned for files without video streams, which are not videos.
+                Ok(None) => self.selected_video_stream().map(|_index| None),
                 Err(e) => Err(e.into()),
             }
         }
 
+        // vid_frame_iter does not expose the timestamps of frames, so the video is decoded at a
+        // low frame rate, and the frames are counted.
+        fn measure_duration(&self, cap: Duration) -> Result<Option<Duration>, Self::E> {
+            let mut builder = self.builder()?.clone();
+            builder.frame_rate((MEASURE_DURATION_FPS, 1));
+
+            let max_frames = (cap.as_secs_f64() * MEASURE_DURATION_FPS as f64) as u64;
+            let mut frames = 0;
+            for frame in builder.spawn_gray()? {
+                frame?;
+                frames += 1;
+                if frames > max_frames {
+                    return Ok(None);
+                }
+            }
+            Ok((frames > 0)
+                .then(|| Duration::from_secs_f64(frames as f64 / MEASURE_DURATION_FPS as f64)))
+        }
+
         fn get_resolution(&self) -> Result<(u32, u32), Self::E> {
-            match vid_frame_iter::mediainfo_utils::dimensions(self.0.uri()) {
+            match vid_frame_iter::mediainfo_utils::dimensions(self.uri()?) {
                 Ok(Some(dims)) => Ok(dims),
                 Ok(None) => Err(GstError::NotVideo),
                 Err(e) => Err(e.into()),
             }
         }
+
+        fn get_media_info(&self) -> Result<MediaInfo, Self::E> {
+            match vid_frame_iter::mediainfo_utils::summary(self.uri()?) {
+                Ok(Some(summary)) => Ok(MediaInfo {
+                    resolution: summary.dimensions,
+                    duration: summary.duration.unwrap_or_default(),
+                    fps: summary.frame_rate,
+                    video_codec: summary.video_codec,
+                    bit_rate: summary.bit_rate,
+                    container: summary.container,
+                    pixel_aspect_ratio: summary.pixel_aspect_ratio,
+                    color_range: summary.color_range.map(|range| match range {
+                        vid_frame_iter::ColorRange::Limited => ColorRange::Limited,
+                        vid_frame_iter::ColorRange::Full => ColorRange::Full,
+                    }),
+                    interlaced: summary.interlaced,
+                }),
+                Ok(None) => Err(GstError::NotVideo),
+                Err(e) => Err(e.into()),
+            }
+        }
     }
 }
 
 #[cfg(feature = "ffmpeg_backend")]
 pub mod ffmpeg_impl {
 
+    use std::time::{Duration, Instant};
+
     use ffmpeg_cmdline_utils::{FfmpegError, FfmpegFrameReaderBuilder, VideoInfo};
     use image::{GrayImage, RgbImage};
 
-    use crate::FrameReadCfgTrait;
+    use crate::{
+        BackendComponents, ColorRange, DecodeDiagnostics, Deinterlace, FrameLatency,
+        FrameReadCfgTrait, MediaInfo, DEFAULT_MAX_MEASURED_DURATION,
+    };
+
+    pub use ffmpeg_cmdline_utils::FfmpegVersion;
 
     #[derive(Debug, Clone)]
     pub struct FrameReaderCfgFfmpeg(FfmpegFrameReaderBuilder);
 
+    /// Check that ffmpeg and ffprobe can be run, and return their versions. Otherwise returns
+    /// the names of those that cannot be run.
+    pub fn init() -> Result<FfmpegVersion, Vec<String>> {
+        ffmpeg_cmdline_utils::verify_ffmpeg().map_err(|e| {
+            let missing = ffmpeg_cmdline_utils::missing_commands();
+            if missing.is_empty() {
+                vec![format!("ffmpeg ({e})")]
+            } else {
+                missing.into_iter().map(String::from).collect()
+            }
+        })
+    }
+
     impl FrameReadCfgTrait for FrameReaderCfgFfmpeg {
         type E = FfmpegError;
 
+        //The versions are only found once, when ffmpeg is first verified.
+        fn components() -> BackendComponents {
+            BackendComponents {
+                backend: "ffmpeg",
+                versions: ffmpeg_cmdline_utils::verify_ffmpeg()
+                    .map(|version| {
+                        vec![
+                            ("ffmpeg".to_string(), version.ffmpeg),
+                            ("ffprobe".to_string(), version.ffprobe),
+                        ]
+                    })
+                    .unwrap_or_default(),
+            }
+        }
+
         fn from_path(src_path: &std::path::Path) -> Self {
             Self(FfmpegFrameReaderBuilder::new(src_path))
         }
 
         fn get_duration(&self) -> Result<std::time::Duration, Self::E> {
+            self.get_duration_with_source(DEFAULT_MAX_MEASURED_DURATION)?
+                .map(|(duration, _source)| duration)
+                .ok_or(FfmpegError::UnknownDuration)
+        }
+
+        fn get_container_duration(&self) -> Result<Option<Duration>, Self::E> {
             let info = VideoInfo::new(self.0.src_path())?;
-            Ok(info.duration())
+            Ok(info.container_duration())
+        }
+
+        fn measure_duration(&self, cap: Duration) -> Result<Option<Duration>, Self::E> {
+            self.0.measure_duration(cap)
         }
 
         fn get_resolution(&self) -> Result<(u32, u32), Self::E> {
@@ -162,6 +592,24 @@ pub mod ffmpeg_impl {
             Ok(info.resolution())
         }
 
+        fn get_media_info(&self) -> Result<MediaInfo, Self::E> {
+            let info = VideoInfo::new(self.0.src_path())?;
+            Ok(MediaInfo {
+                resolution: info.resolution(),
+                duration: info.duration(),
+                fps: info.fps(),
+                video_codec: info.video_codec().map(str::to_string),
+                bit_rate: info.bit_rate(),
+                container: info.container().map(str::to_string),
+                pixel_aspect_ratio: info.sample_aspect_ratio(),
+                color_range: info.color_range().map(|range| match range {
+                    ffmpeg_cmdline_utils::ColorRange::Limited => ColorRange::Limited,
+                    ffmpeg_cmdline_utils::ColorRange::Full => ColorRange::Full,
+                }),
+                interlaced: info.is_interlaced(),
+            })
+        }
+
         fn fps(&mut self, (fps_num, fps_den): (u64, u64)) {
             self.0.fps(format!("{fps_num}/{fps_den}"));
         }
@@ -170,6 +618,64 @@ pub mod ffmpeg_impl {
             self.0.skip_forward(offset as u32);
         }
 
+        fn decode_audio(
+            &self,
+            sample_rate: u32,
+            duration: f64,
+        ) -> Result<Option<Vec<i16>>, Self::E> {
+            self.0.decode_audio(sample_rate, duration)
+        }
+
+        fn diagnose(&self, frames: usize) -> Result<DecodeDiagnostics, Self::E> {
+            let info = VideoInfo::new(self.0.src_path())?;
+            let stream = self.0.selected_video_stream()?;
+            let description = format!(
+                "ffmpeg decoding video stream {stream} ({}) of {}",
+                info.video_codec().unwrap_or("unknown codec"),
+                self.0.src_path().display()
+            );
+
+            //frames are written by ffmpeg as they are decoded, so time how long each takes to
+            //arrive.
+            let mut latencies = vec![];
+            if frames > 0 {
+                let (mut it, _info) = self.0.spawn_gray()?;
+                let mut pull_start = Instant::now();
+                while latencies.len() < frames && it.next().is_some() {
+                    latencies.push(pull_start.elapsed());
+                    pull_start = Instant::now();
+                }
+            }
+            let frame_latency = (!latencies.is_empty()).then(|| FrameLatency {
+                frames: latencies.len() as u64,
+                min: latencies.iter().copied().min().unwrap_or_default(),
+                mean: latencies.iter().sum::<Duration>() / latencies.len() as u32,
+                max: latencies.iter().copied().max().unwrap_or_default(),
+            });
+
+            Ok(DecodeDiagnostics {
+                description,
+                decoder: None,
+                frame_latency,
+            })
+        }
+
+        fn video_stream_index(&mut self, index: usize) {
+            self.0.video_stream_index(index);
+        }
+
+        fn selected_video_stream(&self) -> Result<usize, Self::E> {
+            self.0.selected_video_stream()
+        }
+
+        fn deinterlace(&mut self, mode: Deinterlace) {
+            self.0.deinterlace(match mode {
+                Deinterlace::Off => ffmpeg_cmdline_utils::Deinterlace::Off,
+                Deinterlace::Auto => ffmpeg_cmdline_utils::Deinterlace::Auto,
+                Deinterlace::Force => ffmpeg_cmdline_utils::Deinterlace::Force,
+            });
+        }
+
         fn spawn_gray(self) -> impl Iterator<Item = Result<GrayImage, Self::E>> {
             let mut maybe_it = self.0.spawn_gray();
             let mut done = false;
@@ -188,6 +694,26 @@ pub mod ffmpeg_impl {
             })
         }
 
+        fn spawn_gray_timestamped(
+            self,
+        ) -> Option<impl Iterator<Item = Result<(Duration, GrayImage), Self::E>>> {
+            let mut maybe_it = self.0.spawn_gray_timestamped();
+            let mut done = false;
+            Some(std::iter::from_fn(move || {
+                if done {
+                    None
+                } else {
+                    match &mut maybe_it {
+                        Err(e) => {
+                            done = true;
+                            Some(Err(e.clone()))
+                        }
+                        &mut Ok((ref mut it, ref mut _info)) => it.next().map(Ok),
+                    }
+                }
+            }))
+        }
+
         fn spawn_rgb(self) -> impl Iterator<Item = Result<RgbImage, Self::E>> {
             let mut maybe_it = self.0.spawn_rgb();
             let mut done = false;
@@ -0,0 +1,54 @@
+use std::path::PathBuf;
+
+use ffmpeg_gst_wrapper::FrameReadCfgTrait;
+
+fn example_vid() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("..")
+        .join("vid_dup_finder_lib")
+        .join("examples")
+        .join("vids")
+        .join("dog.1.mp4")
+}
+
+fn assert_frames_timed<T: FrameReadCfgTrait>() {
+    let cfg = T::from_path(&example_vid());
+    let diagnostics = cfg.diagnose(10).expect("example video is readable");
+    assert!(!diagnostics.description.is_empty());
+
+    let latency = diagnostics.frame_latency.expect("frames were decoded");
+    assert_eq!(latency.frames, 10);
+    assert!(latency.min <= latency.mean && latency.mean <= latency.max);
+
+    //nothing is timed when no frames are decoded.
+    let diagnostics = cfg.diagnose(0).expect("example video is readable");
+    assert_eq!(diagnostics.frame_latency, None);
+}
+
+#[cfg(feature = "ffmpeg_backend")]
+#[test]
+fn test_diagnostics_ffmpeg() {
+    use ffmpeg_gst_wrapper::ffmpeg_impl::FrameReaderCfgFfmpeg;
+
+    if !ffmpeg_cmdline_utils::ffmpeg_and_ffprobe_are_callable() {
+        eprintln!("ffmpeg/ffprobe not found. Skipping test.");
+        return;
+    }
+
+    assert_frames_timed::<FrameReaderCfgFfmpeg>();
+}
+
+#[cfg(feature = "gstreamer_backend")]
+#[test]
+fn test_diagnostics_gstreamer() {
+    use ffmpeg_gst_wrapper::gst_impl::FrameReaderCfgGst;
+
+    assert_frames_timed::<FrameReaderCfgGst>();
+
+    let diagnostics = FrameReaderCfgGst::from_path(&example_vid())
+        .diagnose(0)
+        .expect("example video is readable");
+    assert!(diagnostics.description.contains("(appsink)"));
+    let decoder = diagnostics.decoder.expect("gstreamer reports its decoder");
+    assert!(diagnostics.description.contains(&decoder));
+}
@@ -0,0 +1,106 @@
+use std::path::PathBuf;
+
+use ffmpeg_gst_wrapper::{FrameReadCfgTrait, MediaInfo};
+
+fn example_vids() -> Vec<PathBuf> {
+    let vids_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("..")
+        .join("vid_dup_finder_lib")
+        .join("examples")
+        .join("vids");
+
+    let mut ret = std::fs::read_dir(vids_dir)
+        .expect("example videos should be present")
+        .map(|entry| entry.expect("readable dir entry").path())
+        .collect::<Vec<_>>();
+    ret.sort();
+    ret
+}
+
+fn assert_sensible(src_path: &std::path::Path, info: &MediaInfo) {
+    let (x, y) = info.resolution;
+    assert!(
+        x > 0 && y > 0,
+        "{}: bad resolution {info:?}",
+        src_path.display()
+    );
+
+    let secs = info.duration.as_secs_f64();
+    assert!(
+        (1.0..600.0).contains(&secs),
+        "{}: bad duration {info:?}",
+        src_path.display()
+    );
+
+    let (num, den) = info.fps.expect("example videos have a known frame rate");
+    let fps = f64::from(num) / f64::from(den);
+    assert!(
+        (1.0..=120.0).contains(&fps),
+        "{}: bad fps {info:?}",
+        src_path.display()
+    );
+
+    assert!(
+        info.video_codec.is_some(),
+        "{}: {info:?}",
+        src_path.display()
+    );
+    assert!(
+        info.bit_rate.is_some_and(|b| b > 0),
+        "{}: {info:?}",
+        src_path.display()
+    );
+
+    let container = info
+        .container
+        .as_deref()
+        .expect("example videos have a known container")
+        .to_lowercase();
+    if src_path.extension().is_some_and(|ext| ext == "webm") {
+        assert!(
+            container.contains("webm") || container.contains("matroska"),
+            "{}: {info:?}",
+            src_path.display()
+        );
+    } else {
+        assert!(
+            container.contains("mp4") || container.contains("quicktime"),
+            "{}: {info:?}",
+            src_path.display()
+        );
+    }
+}
+
+fn assert_consistent<T: FrameReadCfgTrait>(src_path: &std::path::Path) {
+    let cfg = T::from_path(src_path);
+    let info = cfg.get_media_info().expect("example videos are readable");
+
+    assert_sensible(src_path, &info);
+    assert_eq!(info.duration, cfg.get_duration().expect("readable"));
+    assert_eq!(info.resolution, cfg.get_resolution().expect("readable"));
+}
+
+#[cfg(feature = "ffmpeg_backend")]
+#[test]
+fn test_media_info_ffmpeg() {
+    use ffmpeg_gst_wrapper::ffmpeg_impl::FrameReaderCfgFfmpeg;
+
+    if !ffmpeg_cmdline_utils::ffmpeg_and_ffprobe_are_callable() {
+        eprintln!("ffmpeg/ffprobe not found. Skipping test.");
+        return;
+    }
+
+    for src_path in example_vids() {
+        assert_consistent::<FrameReaderCfgFfmpeg>(&src_path);
+    }
+}
+
+#[cfg(feature = "gstreamer_backend")]
+#[test]
+fn test_media_info_gstreamer() {
+    use ffmpeg_gst_wrapper::gst_impl::FrameReaderCfgGst;
+
+    for src_path in example_vids() {
+        assert_consistent::<FrameReaderCfgGst>(&src_path);
+    }
+}
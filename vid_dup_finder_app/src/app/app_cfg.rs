@@ -1,7 +1,17 @@
+use std::collections::BTreeMap;
 use std::ffi::OsString;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-use vid_dup_finder_lib::Cropdetect;
+use serde::Deserialize;
+use uuid::Uuid;
+use vid_dup_finder_lib::{
+    Coverage, CreationOptions, Cropdetect, Deinterlace, HashSize, Normalization, SearchOptions,
+    SpeedFactors, Tolerance, DEFAULT_MIN_MOTION_CROP_CONFIDENCE,
+    DEFAULT_VID_HASH_MAX_MEASURED_DURATION, DEFAULT_VID_HASH_MIN_DURATION,
+};
+
+use super::ConfigError;
+use crate::video_hash_filesystem_cache::filename_pattern::SymlinkPolicy;
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum ReportVerbosity {
@@ -16,9 +26,18 @@ pub enum Sorting {
     NumMatches,
     Distance,
     Duration,
+    // The total size of the files in each group, biggest first.
+    Size,
+    // How much space removing the duplicates of each group would free, most first.
+    Savings,
+    // The most recent modification time of the files in each group, newest first.
+    Modified,
     RevNumMatches,
     RevDistance,
     RevDuration,
+    RevSize,
+    RevSavings,
+    RevModified,
 }
 
 #[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
@@ -27,17 +46,61 @@ pub enum OutputFormat {
     Json,
 }
 
-#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+// Where the gui moves trashed files to, inside the trash directory.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum TrashLayout {
+    // At their original path, so /videos/a.mp4 goes to <trash>/videos/a.mp4.
+    #[default]
+    Mirrored,
+    // At their original path inside a folder for the (UTC) day they were trashed on, such as
+    // <trash>/2024-05-01/videos/a.mp4, so that files only collide with others trashed that day.
+    Dated,
+}
+
+#[derive(
+    clap::ValueEnum, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord,
+)]
+#[serde(rename_all = "lowercase")]
 pub(super) enum CropdetectTypeArg {
     None,
     Letterbox,
     Motion,
 }
 
+impl From<CropdetectTypeArg> for Cropdetect {
+    fn from(arg: CropdetectTypeArg) -> Self {
+        match arg {
+            CropdetectTypeArg::None => Self::None,
+            CropdetectTypeArg::Letterbox => Self::Letterbox,
+            CropdetectTypeArg::Motion => Self::Motion,
+        }
+    }
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub(super) enum SymlinkPolicyArg {
+    Files,
+    Follow,
+    Ignore,
+    Dedup,
+}
+
+impl From<SymlinkPolicyArg> for SymlinkPolicy {
+    fn from(arg: SymlinkPolicyArg) -> Self {
+        match arg {
+            SymlinkPolicyArg::Files => Self::FilesOnly,
+            SymlinkPolicyArg::Follow => Self::Follow,
+            SymlinkPolicyArg::Ignore => Self::Ignore,
+            SymlinkPolicyArg::Dedup => Self::ResolveAndDeduplicate,
+        }
+    }
+}
+
 #[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub(super) enum OutputKindRaw {
     NoOutput,
     Unique,
+    Truncated,
     Dups,
 }
 
@@ -45,6 +108,8 @@ pub(super) enum OutputKindRaw {
 pub enum TextOutputCfg {
     NoOutput,
     Unique(OutputFormat),
+    //Videos whose hashes were built from a decode that failed part way through.
+    Truncated(OutputFormat),
     Dups {
         format: OutputFormat,
         sorting: Sorting,
@@ -58,7 +123,13 @@ pub enum GuiOutputCfg {
     GuiSlint {
         sorting: Sorting,
         trash_path: Option<PathBuf>,
+        trash_layout: TrashLayout,
         max_thumbs: Option<u64>,
+        //The most memory that rendered thumbnails may take up, in bytes.
+        cache_budget: u64,
+        //The commands used to play videos and to show them in a file manager, if given.
+        player_cmd: Option<String>,
+        browser_cmd: Option<String>,
     },
 }
 
@@ -71,23 +142,48 @@ pub enum ThumbOutputCfg {
     },
 }
 
+//A machine readable report of every member of every group, written to a file.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum ReportCfg {
+    Csv(PathBuf),
+    Sqlite(PathBuf),
+    //A web page, with at most thumbnail_budget bytes of thumbnails.
+    Html {
+        path: PathBuf,
+        thumbnail_budget: u64,
+    },
+}
+
 #[derive(Debug, Clone)]
 pub struct OutputCfg {
     pub text: TextOutputCfg,
     pub thumbs: ThumbOutputCfg,
+    pub reports: Vec<ReportCfg>,
+    //Where the members of each group are linked for review, if anywhere.
+    pub quarantine_dir: Option<PathBuf>,
     #[allow(dead_code)]
     pub gui: GuiOutputCfg,
 
     pub verbosity: ReportVerbosity,
     pub cartesian_product: bool,
+    //Whether to find which videos of each group were cut from which before output.
+    pub containment: bool,
+
+    //Whether to print a summary when the run ends, in the same format as the text output.
+    pub summary: bool,
+    pub summary_format: OutputFormat,
 }
 
 #[derive(Debug, Clone)]
 pub struct DirCfg {
     pub cand_dirs: Vec<PathBuf>,
+    //Paths given by --files-from which do not exist. They are left out of cand_dirs, and reported
+    //at the end of the run.
+    pub missing_listed_paths: Vec<PathBuf>,
     pub ref_dirs: Vec<PathBuf>,
     pub excl_dirs: Vec<PathBuf>,
     pub excl_exts: Vec<OsString>,
+    pub symlinks: SymlinkPolicy,
 }
 
 #[derive(Debug, Clone)]
@@ -95,6 +191,8 @@ pub struct MatchDbCfg {
     pub db_path: Option<PathBuf>,
 
     pub fix_moved_files: bool,
+    //The directory of another match database to merge into this one.
+    pub merge_from: Option<PathBuf>,
 
     pub remove_known_matches: bool,
     pub remove_falsepos: bool,
@@ -104,6 +202,36 @@ pub struct MatchDbCfg {
 pub struct CacheCfg {
     pub cache_path: Option<PathBuf>,
     pub no_update_cache: bool,
+    pub export_hashes: Option<PathBuf>,
+    pub import_hashes: Option<PathBuf>,
+    //Directories whose entries are removed from the cache before it is updated.
+    pub forget_dirs: Vec<PathBuf>,
+    //(old, new) pairs of directories which have been moved since the cache was updated.
+    pub moved_dirs: Vec<(PathBuf, PathBuf)>,
+    //Whether each video is given a stable ID.
+    pub video_ids: bool,
+}
+
+// A maintenance command given as `vid_dup_finder cache <command>`, which is run instead of a search.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CacheCmd {
+    Stats,
+    PruneMissing,
+    PruneErrors,
+    Forget(PathBuf),
+    Move(PathBuf, PathBuf),
+    Verify,
+    Export(PathBuf),
+    Import(PathBuf),
+    MigrateToSqlite(PathBuf),
+    FindId(Uuid),
+}
+
+#[derive(Debug, Clone)]
+pub struct CacheCmdCfg {
+    pub cmd: CacheCmd,
+    //Only print what would change, and exit with EXIT_CHANGES_NEEDED if anything would.
+    pub dry_run: bool,
 }
 
 #[derive(Debug, Clone)]
@@ -111,6 +239,48 @@ pub struct HashCfg {
     pub cropdetect: Cropdetect,
     pub skip_forward: f64,
     pub duration: f64,
+    pub ignore_pixel_aspect_ratio: bool,
+    pub speed_variants: SpeedFactors,
+}
+
+impl HashCfg {
+    pub fn creation_options(&self) -> CreationOptions {
+        CreationOptions {
+            skip_forward_amount: self.skip_forward,
+            duration: self.duration,
+            cropdetect: self.cropdetect,
+            hash_bits: HashSize::default(),
+            video_stream: None,
+            frame_hashes: false,
+            skip_leading_black: false,
+            reliability_mask: false,
+            min_duration: DEFAULT_VID_HASH_MIN_DURATION,
+            normalization: Normalization::None,
+            audio_fingerprint: false,
+            coverage: Coverage::Start,
+            min_motion_crop_confidence: DEFAULT_MIN_MOTION_CROP_CONFIDENCE,
+            ignore_pixel_aspect_ratio: self.ignore_pixel_aspect_ratio,
+            deinterlace: Deinterlace::Off,
+            speed_variants: self.speed_variants,
+            max_measured_duration: DEFAULT_VID_HASH_MAX_MEASURED_DURATION,
+        }
+    }
+}
+
+// A named profile from the --config file. Videos under any of its paths are hashed with its
+// hash_cfg, and are only searched against each other using its tolerance.
+#[derive(Debug, Clone)]
+pub struct SearchProfile {
+    pub name: String,
+    pub paths: Vec<PathBuf>,
+    pub tolerance: Tolerance,
+    pub hash_cfg: HashCfg,
+}
+
+impl SearchProfile {
+    pub fn covers(&self, path: &Path) -> bool {
+        self.paths.iter().any(|p| path.starts_with(p))
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -127,10 +297,347 @@ pub struct AppCfg {
     pub show_missed_matches: bool,
 
     pub update_cache_only: bool,
+    pub cache_stats: bool,
+    pub debug_pipeline: Option<PathBuf>,
+    pub cache_cmd: Option<CacheCmdCfg>,
     pub reload_err_vids: bool,
     pub reload_all_vids: bool,
 
+    pub strict_exit_codes: bool,
+
     pub matchdb_cfg: MatchDbCfg,
 
-    pub tolerance: f64,
+    pub search_opts: SearchOptions,
+    //Refine the groups found by hashing their videos again with bigger hashes of a longer window.
+    pub refine: bool,
+
+    pub profiles: Vec<SearchProfile>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawConfigFile {
+    #[serde(default)]
+    profiles: BTreeMap<String, RawProfile>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawProfile {
+    paths: Vec<PathBuf>,
+    tolerance: Option<f64>,
+    cropdetect: Option<CropdetectTypeArg>,
+    skip_forward: Option<f64>,
+    duration: Option<f64>,
+}
+
+// Parse the contents of a --config file. Any setting missing from a profile is taken from the
+// command line (or its default), and relative paths are relative to base_dir.
+pub fn parse_config_file(
+    text: &str,
+    base_dir: &Path,
+    default_hash_cfg: &HashCfg,
+    default_tolerance: Tolerance,
+) -> Result<Vec<SearchProfile>, ConfigError> {
+    let raw: RawConfigFile = toml::from_str(text)?;
+
+    let profiles = raw
+        .profiles
+        .into_iter()
+        .map(|(name, raw)| {
+            if raw.paths.is_empty() {
+                return Err(ConfigError::NoPaths(name));
+            }
+
+            let tolerance = match raw.tolerance {
+                None => default_tolerance,
+                Some(tolerance) => match Tolerance::new(tolerance) {
+                    Ok(tolerance) => tolerance,
+                    Err(_) => return Err(ConfigError::InvalidTolerance { name, tolerance }),
+                },
+            };
+
+            let hash_cfg = HashCfg {
+                cropdetect: raw
+                    .cropdetect
+                    .map_or(default_hash_cfg.cropdetect, Cropdetect::from),
+                skip_forward: raw.skip_forward.unwrap_or(default_hash_cfg.skip_forward),
+                duration: raw.duration.unwrap_or(default_hash_cfg.duration),
+                ignore_pixel_aspect_ratio: default_hash_cfg.ignore_pixel_aspect_ratio,
+                speed_variants: default_hash_cfg.speed_variants,
+            };
+
+            let paths = raw
+                .paths
+                .iter()
+                .map(|path| {
+                    let path = base_dir.join(path);
+                    path.canonicalize().unwrap_or(path)
+                })
+                .collect();
+
+            Ok(SearchProfile {
+                name,
+                paths,
+                tolerance,
+                hash_cfg,
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    check_profiles_are_disjoint(&profiles)?;
+    Ok(profiles)
+}
+
+// Read the paths in a list given by --files-from, one per entry, where entries are separated by
+// `delimiter`. Empty entries are skipped, a newline delimiter also removes any trailing carriage
+// return, and relative paths are relative to base_dir.
+pub fn parse_file_list(list: &[u8], delimiter: u8, base_dir: &Path) -> Vec<PathBuf> {
+    list.split(|&b| b == delimiter)
+        .map(|entry| match delimiter {
+            b'\n' => entry.strip_suffix(b"\r").unwrap_or(entry),
+            _ => entry,
+        })
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let path = base_dir.join(path_from_bytes(entry));
+            path.canonicalize().unwrap_or(path)
+        })
+        .collect()
+}
+
+// Split listed paths into those that exist and those that do not. Paths under any of excl_dirs
+// are left out of both, just as they would be if their parent directory had been searched.
+pub fn partition_listed_paths(
+    listed: Vec<PathBuf>,
+    excl_dirs: &[PathBuf],
+) -> (Vec<PathBuf>, Vec<PathBuf>) {
+    listed
+        .into_iter()
+        .filter(|path| !excl_dirs.iter().any(|excl| path.starts_with(excl)))
+        .partition(|path| path.exists())
+}
+
+#[cfg(target_family = "unix")]
+fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    use std::os::unix::ffi::OsStrExt;
+    PathBuf::from(std::ffi::OsStr::from_bytes(bytes))
+}
+
+#[cfg(not(target_family = "unix"))]
+fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(bytes).into_owned())
+}
+
+// Every video must be hashed and searched in exactly one way, so no path may be covered by two
+// profiles.
+fn check_profiles_are_disjoint(profiles: &[SearchProfile]) -> Result<(), ConfigError> {
+    for (i, first) in profiles.iter().enumerate() {
+        for second in &profiles[i + 1..] {
+            for p1 in &first.paths {
+                for p2 in &second.paths {
+                    let inner = if p1.starts_with(p2) {
+                        p1
+                    } else if p2.starts_with(p1) {
+                        p2
+                    } else {
+                        continue;
+                    };
+                    return Err(ConfigError::OverlappingProfiles {
+                        first: first.name.clone(),
+                        second: second.name.clone(),
+                        path: inner.clone(),
+                    });
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn defaults() -> HashCfg {
+        HashCfg {
+            cropdetect: Cropdetect::Letterbox,
+            skip_forward: 0.0,
+            duration: 10.0,
+            ignore_pixel_aspect_ratio: false,
+            speed_variants: SpeedFactors::NONE,
+        }
+    }
+
+    fn parse(text: &str) -> Result<Vec<SearchProfile>, ConfigError> {
+        let tolerance = Tolerance::new(0.3).expect("valid tolerance");
+        parse_config_file(text, Path::new("/base"), &defaults(), tolerance)
+    }
+
+    #[test]
+    fn test_parse_profiles() {
+        let text = r#"
+            [profiles.movies]
+            paths = ["/videos/movies", "more_movies"]
+            tolerance = 0.1
+            cropdetect = "motion"
+            skip_forward = 60.0
+            duration = 20.0
+
+            [profiles.clips]
+            paths = ["/videos/clips"]
+        "#;
+
+        let profiles = parse(text).expect("valid config");
+        assert_eq!(profiles.len(), 2);
+
+        //profiles are sorted by name.
+        let clips = &profiles[0];
+        assert_eq!(clips.name, "clips");
+        assert_eq!(clips.paths, [PathBuf::from("/videos/clips")]);
+        assert_eq!(clips.tolerance.value(), 0.3);
+        assert_eq!(
+            clips.hash_cfg.creation_options(),
+            defaults().creation_options()
+        );
+
+        let movies = &profiles[1];
+        assert_eq!(movies.name, "movies");
+        assert_eq!(
+            movies.paths,
+            [
+                PathBuf::from("/videos/movies"),
+                PathBuf::from("/base/more_movies")
+            ]
+        );
+        assert_eq!(movies.tolerance.value(), 0.1);
+        assert_eq!(movies.hash_cfg.cropdetect, Cropdetect::Motion);
+        assert_eq!(movies.hash_cfg.skip_forward, 60.0);
+        assert_eq!(movies.hash_cfg.duration, 20.0);
+
+        assert!(movies.covers(Path::new("/videos/movies/a.mp4")));
+        assert!(!movies.covers(Path::new("/videos/movies_2/a.mp4")));
+
+        assert!(parse("").expect("empty config is valid").is_empty());
+    }
+
+    #[test]
+    fn test_invalid_profiles() {
+        let unknown_field = r#"
+            [profiles.a]
+            paths = ["/a"]
+            tolerence = 0.1
+        "#;
+        assert!(matches!(parse(unknown_field), Err(ConfigError::Parse(_))));
+
+        let bad_cropdetect = r#"
+            [profiles.a]
+            paths = ["/a"]
+            cropdetect = "sideways"
+        "#;
+        assert!(matches!(parse(bad_cropdetect), Err(ConfigError::Parse(_))));
+
+        let no_paths = r#"
+            [profiles.a]
+            paths = []
+        "#;
+        assert!(matches!(parse(no_paths), Err(ConfigError::NoPaths(_))));
+
+        let bad_tolerance = r#"
+            [profiles.a]
+            paths = ["/a"]
+            tolerance = 1.5
+        "#;
+        assert!(matches!(
+            parse(bad_tolerance),
+            Err(ConfigError::InvalidTolerance { .. })
+        ));
+    }
+
+    #[test]
+    fn test_overlapping_profiles_are_rejected() {
+        let nested = r#"
+            [profiles.all]
+            paths = ["/videos"]
+
+            [profiles.some]
+            paths = ["/other", "/videos/some"]
+        "#;
+        match parse(nested) {
+            Err(ConfigError::OverlappingProfiles {
+                first,
+                second,
+                path,
+            }) => {
+                assert_eq!((first.as_str(), second.as_str()), ("all", "some"));
+                assert_eq!(path, PathBuf::from("/videos/some"));
+            }
+            other => panic!("expected an overlap error, got {other:?}"),
+        }
+
+        let same = r#"
+            [profiles.a]
+            paths = ["/videos"]
+
+            [profiles.b]
+            paths = ["/videos"]
+        "#;
+        assert!(matches!(
+            parse(same),
+            Err(ConfigError::OverlappingProfiles { .. })
+        ));
+
+        //sharing a prefix that is not a whole directory is fine.
+        let siblings = r#"
+            [profiles.a]
+            paths = ["/videos/a"]
+
+            [profiles.b]
+            paths = ["/videos/ab"]
+        "#;
+        assert_eq!(parse(siblings).expect("no overlap").len(), 2);
+    }
+
+    #[test]
+    fn test_parse_file_list() {
+        use crate::video_hash_filesystem_cache::filename_pattern::{
+            FilenamePattern, FilterFilenames,
+        };
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(temp_dir.path().join("excluded")).unwrap();
+        let dir = temp_dir.path().canonicalize().unwrap();
+        let (kept, excluded) = (dir.join("kept.mp4"), dir.join("excluded").join("other.mp4"));
+        for path in [&kept, &excluded] {
+            std::fs::write(path, "").unwrap();
+        }
+        let missing = dir.join("missing.mp4");
+
+        let list_path = dir.join("list.txt");
+        let list = format!(
+            "{}\r\n\nexcluded/other.mp4\n{}\n",
+            kept.display(),
+            missing.display()
+        );
+        std::fs::write(&list_path, list).unwrap();
+
+        let listed = parse_file_list(&std::fs::read(&list_path).unwrap(), b'\n', &dir);
+        assert_eq!(listed, [kept.clone(), excluded.clone(), missing.clone()]);
+
+        let nul_list = format!("{}\0{}\0", kept.display(), missing.display());
+        assert_eq!(
+            parse_file_list(nul_list.as_bytes(), b'\0', &dir),
+            [kept.clone(), missing.clone()]
+        );
+
+        //Listed files are excluded just as they would be when found by walking a directory.
+        let excl_dirs = [dir.join("excluded")];
+        let (found, not_found) = partition_listed_paths(listed, &excl_dirs);
+        assert_eq!(found, std::slice::from_ref(&kept));
+        assert_eq!(not_found, [missing]);
+
+        let pattern = FilenamePattern::new(found, excl_dirs.to_vec(), vec![]).unwrap();
+        assert!(pattern.includes(&kept));
+        assert!(!pattern.includes(&excluded));
+    }
 }
@@ -1,7 +1,11 @@
-use crate::{app::app_fns::filename_pattern::FilenamePattern, video_hash_filesystem_cache::*};
+use crate::{
+    app::app_fns::filename_pattern::{FilenamePattern, SymlinkAliases},
+    video_hash_filesystem_cache::*,
+};
+use ffmpeg_gst_wrapper::FrameReadCfgTrait;
 use filename_pattern::FilterFilenames;
 use itertools::Itertools;
-use match_group_ext::MatchGroupExt;
+use match_group_ext::{thumbnail_strip, MatchGroupExt};
 #[cfg(feature = "parallel_loading")]
 use rayon::prelude::*;
 use serde::Serialize;
@@ -9,15 +13,23 @@ use serde_json::json;
 #[cfg(feature = "print_timings")]
 use std::time::Instant;
 use std::{
-    collections::{hash_map::RandomState, HashSet},
+    collections::{hash_map::RandomState, BTreeMap, HashSet},
     error::Error,
     io::BufWriter,
     path::{Path, PathBuf},
 };
 use vid_dup_finder_lib::*;
 
+use crate::app::interrupt::Interrupt;
+use crate::app::search_output::{
+    CsvReport, HtmlReport, ReportSink, ReportSources, RunSummary, SortKeyData, SqliteReport,
+    EXIT_FATAL, EXIT_INTERRUPTED, EXIT_OK, THUMBNAIL_HEIGHT,
+};
 use crate::app::*;
 
+// How many new hashes are cached before the cache is saved part way through an update.
+const CACHE_SAVE_THRESHOLD: u32 = 2000;
+
 // * read cfg
 // * load paths
 // * update video hash cache
@@ -39,14 +51,46 @@ pub fn run_app() -> i32 {
     // dbg!(&cfg);
     configure_logs(cfg.output_cfg.verbosity);
 
+    if let Some(path) = &cfg.debug_pipeline {
+        return debug_pipeline(path);
+    }
+
+    if let Some(cache_cmd) = &cfg.cache_cmd {
+        return match cache_cmd::run_cache_cmd(&cfg, cache_cmd) {
+            Ok(exit_code) => exit_code,
+            Err(fatal_error) => {
+                print_fatal_err(fatal_error, cfg.output_cfg.verbosity);
+                EXIT_FATAL
+            }
+        };
+    }
+
     let ret = match run_app_inner(&cfg) {
-        Ok(()) => 0,
+        Ok(summary) => {
+            print_summary(&summary, &cfg.output_cfg);
+            if summary.interrupted {
+                EXIT_INTERRUPTED
+            } else if cfg.strict_exit_codes {
+                summary.exit_code()
+            } else {
+                EXIT_OK
+            }
+        }
         Err(fatal_error) => {
             print_fatal_err(fatal_error, cfg.output_cfg.verbosity);
-            1
+            EXIT_FATAL
         }
     };
 
+    let missing = &cfg.dir_cfg.missing_listed_paths;
+    if !missing.is_empty() {
+        warn!(
+            "{} listed paths do not exist and were skipped: {}",
+            missing.len(),
+            missing.iter().map(|p| p.to_string_lossy()).join(", ")
+        );
+    }
+
     ret
 }
 
@@ -79,8 +123,10 @@ fn make_sure_lots_of_file_handles_are_available() {
     };
 }
 
-fn run_app_inner(cfg: &AppCfg) -> eyre::Result<()> {
+fn run_app_inner(cfg: &AppCfg) -> eyre::Result<RunSummary> {
     make_sure_lots_of_file_handles_are_available();
+    vid_dup_finder_lib::init()?;
+    let interrupt = Interrupt::install();
 
     //shorten some long variable names
     let cand_dirs = &cfg.dir_cfg.cand_dirs;
@@ -136,14 +182,34 @@ fn run_app_inner(cfg: &AppCfg) -> eyre::Result<()> {
     let cache_load_start = Instant::now();
 
     //load up existing hashes from disk.
-    let cache_save_threshold = 2000;
-    let cache = VideoHashFilesystemCache::new(
-        cache_save_threshold,
+    let profile_opts = cfg
+        .profiles
+        .iter()
+        .flat_map(|profile| {
+            let opts = profile.hash_cfg.creation_options();
+            profile.paths.iter().map(move |path| (path.clone(), opts))
+        })
+        .collect();
+    let cache = VideoHashFilesystemCache::with_path_options(
+        CACHE_SAVE_THRESHOLD,
         cfg.cache_cfg.cache_path.as_ref().unwrap().clone(),
-        cfg.hash_cfg.cropdetect,
-        cfg.hash_cfg.skip_forward,
-        cfg.hash_cfg.duration,
+        cfg.hash_cfg.creation_options(),
+        profile_opts,
     )?;
+    let cache = if cfg.cache_cfg.video_ids {
+        cache.with_video_ids()?
+    } else {
+        cache
+    };
+
+    if let Some(change) = cache.environment_changed() {
+        if !cfg.reload_all_vids {
+            warn!(
+                "The video decoders have changed since {} of the {} cached videos were hashed (environment {} is now {}). Their hashes may differ slightly from new hashes of the same videos. Use --reload-all to hash them again",
+                change.affected, change.total, change.previous, change.current
+            );
+        }
+    }
 
     // let content_cache = if let Some(matchdb_path) = &cfg.matchdb_cfg.db_path {
     //     let content_cache_path = MatchDb::content_cache_path(matchdb_path);
@@ -167,9 +233,80 @@ fn run_app_inner(cfg: &AppCfg) -> eyre::Result<()> {
     // let cand_excls = excl_dirs.iter().chain(ref_dirs);
     // let ref_excls = excl_dirs.iter().chain(cand_dirs);
 
+    //Directories are moved before the update, so that the videos in them are not hashed again.
+    for dir in &cfg.cache_cfg.forget_dirs {
+        let count = cache.remove_under(dir)?;
+        info!("Forgot {count} cached videos under {}", dir.display());
+    }
+    for (old, new) in &cfg.cache_cfg.moved_dirs {
+        let count = cache.rename_prefix(old, new)?;
+        info!(
+            "Moved {count} cached videos from {} to {}",
+            old.display(),
+            new.display()
+        );
+    }
+    if !cfg.cache_cfg.forget_dirs.is_empty() || !cfg.cache_cfg.moved_dirs.is_empty() {
+        cache.save()?;
+    }
+
+    //Videos hashed with other options are only hashed again when the cache is updated, so without
+    //an update their hashes would be searched against hashes that are not comparable.
+    if cfg.cache_cfg.no_update_cache {
+        let file_filter = create_filename_filter(cfg);
+        let num_mismatched = cache
+            .options_mismatch_paths(&cfg.hash_cfg.creation_options())
+            .into_iter()
+            .filter(|path| file_filter.includes(path))
+            //videos in a profile are hashed with the options of the profile.
+            .filter(|path| !cfg.profiles.iter().any(|profile| profile.covers(path)))
+            .count();
+        if num_mismatched > 0 {
+            warn!(
+                "{num_mismatched} cached videos were hashed with options other than the ones given, and will not be hashed again because of --no-update-cache. Their matches may be unreliable"
+            );
+        }
+    }
+
     // Update the cache file with all videos specified by --files and --with-refs
-    if !cfg.cache_cfg.no_update_cache {
-        update_hash_cache(cfg, &cache)?;
+    let (symlink_aliases, update_report) = if cfg.cache_cfg.no_update_cache {
+        (SymlinkAliases::default(), UpdateReport::default())
+    } else {
+        update_hash_cache(cfg, &cache, &interrupt)?
+    };
+    let interrupted_summary = || RunSummary {
+        interrupted: true,
+        ..RunSummary::from_update_report(&update_report)
+    };
+    if interrupt.is_interrupted() {
+        return Ok(interrupted_summary());
+    }
+
+    //Exchange hashes with other programs. The export comes first, so that it only holds the hashes
+    //of this cache.
+    if let Some(export_path) = &cfg.cache_cfg.export_hashes {
+        let export = || -> eyre::Result<usize> {
+            let file = std::fs::File::create(export_path)?;
+            Ok(cache.write_portable_hashes(file)?)
+        };
+        let num_hashes = export().map_err(|e| {
+            e.wrap_err(format!(
+                "Failed to export hashes to {}",
+                export_path.display()
+            ))
+        })?;
+        info!("Exported {num_hashes} hashes to {}", export_path.display());
+    }
+    if let Some(import_path) = &cfg.cache_cfg.import_hashes {
+        //the hashes are labelled with where they came from.
+        let label = import_path.to_string_lossy();
+        let import = || -> eyre::Result<usize> {
+            let file = std::io::BufReader::new(std::fs::File::open(import_path)?);
+            Ok(cache.read_portable_hashes(file, &label)?)
+        };
+        let num_hashes =
+            import().map_err(|e| e.wrap_err(format!("Failed to import hashes from {label}")))?;
+        info!("Imported {num_hashes} hashes from {label}");
     }
 
     //if the match db is requested then create it.
@@ -179,22 +316,22 @@ fn run_app_inner(cfg: &AppCfg) -> eyre::Result<()> {
         let match_db_load_start = Instant::now();
 
         let db_path = cfg.matchdb_cfg.db_path.as_ref().unwrap();
+        let _graceful = interrupt.graceful();
 
         //check if there is an existing DB and load it.
         //Otherwise create a new DB.
-        let mut db = if MatchDb::exists_on_disk(db_path) {
-            let db = match MatchDb::from_disk(db_path) {
-                Ok(db) => db,
-                Err(e) => {
-                    error!("{e}");
-                    std::process::exit(1);
-                }
-            };
-
-            db
+        let db = if MatchDb::exists_on_disk(db_path) {
+            MatchDb::from_disk(db_path)
         } else {
             MatchDb::new(db_path)
         };
+        let mut db = match db {
+            Ok(db) => db,
+            Err(e) => {
+                error!("{e}");
+                std::process::exit(1);
+            }
+        };
 
         #[cfg(feature = "print_timings")]
         println!(
@@ -209,20 +346,56 @@ fn run_app_inner(cfg: &AppCfg) -> eyre::Result<()> {
             .filter(|p| filename_filter.includes(p))
             .collect::<Vec<_>>();
 
-        db.update_file_content_cache(paths_to_update_matchdb.iter().cloned())
-            .unwrap();
+        db.update_file_content_cache(
+            paths_to_update_matchdb.iter().cloned(),
+            Some(interrupt.token()),
+        )
+        .unwrap();
 
         //if requested, load the raw db entries into the match database
 
-        if let Err(e) = db.load_new_inputs() {
-            error!("{e}");
-            std::process::exit(1);
+        if !interrupt.is_interrupted() {
+            if let Err(e) = db.load_new_inputs() {
+                error!("{e}");
+                std::process::exit(1);
+            }
+        }
+
+        if let Some(other_path) = &cfg.matchdb_cfg.merge_from {
+            if !interrupt.is_interrupted() {
+                let other = MatchDb::from_disk(other_path).unwrap_or_else(|e| {
+                    error!("{e}");
+                    std::process::exit(1);
+                });
+                let report = db.merge(&other);
+                info!(
+                    "Merged {}: {} new or extended groups, {} new false positives",
+                    other_path.display(),
+                    report.added_groups.len(),
+                    report.added_falsepos.len()
+                );
+                for conflict in &report.conflicts {
+                    warn!("Match database merge conflict: {conflict}");
+                }
+            }
         }
 
-        if cfg.matchdb_cfg.fix_moved_files {
-            if let Err(e) = db.fix_moved_files() {
+        if cfg.matchdb_cfg.fix_moved_files && !interrupt.is_interrupted() {
+            let moves = db.fix_moved_files().unwrap_or_else(|e| {
                 error!("{e}");
                 std::process::exit(1);
+            });
+
+            //moved videos keep their IDs.
+            let num_transferred = moves
+                .iter()
+                .filter(|(old, new)| cache.transfer_id(old, new))
+                .count();
+            if num_transferred > 0 {
+                if let Err(e) = cache.save() {
+                    error!("{e}");
+                    std::process::exit(1);
+                }
             }
         }
 
@@ -231,10 +404,19 @@ fn run_app_inner(cfg: &AppCfg) -> eyre::Result<()> {
 
         db
     });
+    if interrupt.is_interrupted() {
+        return Ok(interrupted_summary());
+    }
+
+    if cfg.cache_stats {
+        #[allow(clippy::print_stdout)]
+        let () = print!("{}", cache.stats_report());
+        return Ok(RunSummary::from_update_report(&update_report));
+    }
 
     //if the app was only invoked to update the cache, then we're done at this point.
     if cfg.update_cache_only {
-        return Ok(());
+        return Ok(RunSummary::from_update_report(&update_report));
     }
 
     // Perform the search
@@ -245,12 +427,113 @@ fn run_app_inner(cfg: &AppCfg) -> eyre::Result<()> {
     let search_output = if non_search_output_requested {
         display_match_db_output(cfg, match_db.as_ref().unwrap())
     } else {
-        search_disk(cfg, &cache, match_db.as_ref())
+        search_disk(cfg, &cache, match_db.as_ref(), &interrupt)?
+    }
+    .with_aliases(symlink_aliases);
+    if interrupt.is_interrupted() {
+        return Ok(interrupted_summary());
+    }
+
+    //The savings are only shown in the printed summary, and finding the best file of each group
+    //means probing every duplicate, so don't bother when the summary won't be printed. The exit
+    //code only needs the counts.
+    let summary = if !cfg.output_cfg.summary || cfg.output_cfg.verbosity == ReportVerbosity::Quiet {
+        RunSummary {
+            groups: search_output.len(),
+            ..RunSummary::from_update_report(&update_report)
+        }
+    } else {
+        search_output.summary(&update_report, keep_best, |path| {
+            std::fs::metadata(path).ok().map(|m| m.len())
+        })
     };
 
     do_app_outputs(cfg, search_output, cache)?;
 
-    Ok(())
+    Ok(summary)
+}
+
+// The file that would be kept out of a group of duplicates: its reference, or otherwise the copy
+// with the highest resolution (and then the biggest file). None if the group holds videos whose
+// hashes are identical but whose frames differ, as they may not be duplicates at all.
+fn keep_best(group: &MatchGroup) -> Option<PathBuf> {
+    if group
+        .contained_paths()
+        .any(|path| group.frames_differ(path))
+    {
+        return None;
+    }
+    match group.reference() {
+        Some(reference) => Some(reference.to_path_buf()),
+        None => pick_best(group, &[Criterion::Resolution, Criterion::FileSize])
+            .map(|pick| pick.winner().to_path_buf())
+            .ok(),
+    }
+}
+
+// What sorting needs to know about a file: its cached hash, and its size and modification time.
+fn sort_key_data(cache: &VideoHashFilesystemCache) -> impl Fn(&Path) -> Option<SortKeyData> + '_ {
+    |path| {
+        let metadata = std::fs::metadata(path).ok();
+        Some(SortKeyData {
+            hash: cache.fetch(path).ok(),
+            size: metadata.as_ref().map(std::fs::Metadata::len),
+            modified: metadata.and_then(|metadata| metadata.modified().ok()),
+        })
+    }
+}
+
+#[cfg(feature = "ffmpeg_backend")]
+type Backend = ffmpeg_gst_wrapper::ffmpeg_impl::FrameReaderCfgFfmpeg;
+#[cfg(all(feature = "gstreamer_backend", not(feature = "ffmpeg_backend")))]
+type Backend = ffmpeg_gst_wrapper::gst_impl::FrameReaderCfgGst;
+
+fn probe_resolution(path: &Path) -> Option<(u32, u32)> {
+    Backend::from_path(path).get_resolution().ok()
+}
+
+// The number of frames decoded by --debug-pipeline.
+const DEBUG_PIPELINE_FRAMES: usize = 100;
+
+#[allow(clippy::print_stdout)]
+fn debug_pipeline(path: &Path) -> i32 {
+    let diagnostics = match Backend::from_path(path).diagnose(DEBUG_PIPELINE_FRAMES) {
+        Ok(diagnostics) => diagnostics,
+        Err(e) => {
+            error!("failed to decode {}: {e}", path.display());
+            return EXIT_FATAL;
+        }
+    };
+
+    println!("Pipeline: {}", diagnostics.description);
+    println!(
+        "Decoder: {}",
+        diagnostics.decoder.as_deref().unwrap_or("unknown")
+    );
+    match diagnostics.frame_latency {
+        Some(latency) => println!(
+            "Frame latency over {} frames: min {:.1}ms, mean {:.1}ms, max {:.1}ms",
+            latency.frames,
+            latency.min.as_secs_f64() * 1000.0,
+            latency.mean.as_secs_f64() * 1000.0,
+            latency.max.as_secs_f64() * 1000.0,
+        ),
+        None => println!("No frames were decoded"),
+    }
+    EXIT_OK
+}
+
+// The summary goes to stderr so that it doesn't get mixed up with the results on stdout.
+#[allow(clippy::print_stderr)]
+fn print_summary(summary: &RunSummary, output_cfg: &OutputCfg) {
+    if !output_cfg.summary || output_cfg.verbosity == ReportVerbosity::Quiet {
+        return;
+    }
+
+    match output_cfg.summary_format {
+        OutputFormat::Normal => eprintln!("{}", summary.summary_line()),
+        OutputFormat::Json => eprintln!("{}", json!(summary)),
+    }
 }
 
 #[allow(clippy::print_stdout)]
@@ -297,13 +580,37 @@ fn do_app_outputs(
             }
         }
 
+        Truncated(format) => {
+            let cands_filter = create_cands_filename_filter(cfg);
+            let truncated_paths = cache
+                .all_cached_paths()
+                .into_iter()
+                .filter(|p| cands_filter.includes(p))
+                .filter(|p| cache.fetch(p).is_ok_and(|hash| hash.truncated_decode()))
+                .collect::<Vec<_>>();
+
+            match format {
+                Normal => {
+                    for truncated_file in &truncated_paths {
+                        println!("{}", truncated_file.display());
+                    }
+                }
+                Json => {
+                    let stdout = BufWriter::new(std::io::stdout());
+                    serde_json::to_writer_pretty(stdout, &json!(truncated_paths))
+                        .unwrap_or_default();
+                    println!();
+                }
+            }
+        }
+
         //////////////////////////////
         // Unstructure text output.
         Dups {
             format: Normal,
             sorting,
         } => {
-            search_output.sort(sorting, &cache);
+            search_output.sort(sorting, sort_key_data(&cache));
             for group in search_output.dup_groups() {
                 if let Some(video) = group.reference() {
                     println!("{}", video.display());
@@ -321,13 +628,30 @@ fn do_app_outputs(
             format: Json,
             sorting,
         } => {
-            search_output.sort(sorting, &cache);
+            search_output.sort(sorting, sort_key_data(&cache));
 
             //Sturct only exists to be serialized.
             #[derive(Serialize)]
             struct JsonStruct<'a> {
                 reference: Option<&'a Path>,
                 duplicates: Vec<&'a Path>,
+                //Only present when symlinks are deduplicated.
+                #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+                aliases: BTreeMap<&'a Path, Vec<PathBuf>>,
+                //The shortest and longest durations, only present when --duration-gate is loosened.
+                #[serde(skip_serializing_if = "Option::is_none")]
+                duration_spread: Option<(u32, u32)>,
+                //How many times as fast each video plays as the reference (or the first duplicate),
+                //only present with --speed-variants for videos which matched it at another speed.
+                #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+                speed_factors: BTreeMap<&'a Path, f64>,
+                //The longer videos of the group that each video was cut from, only present with
+                //--containment for videos which were found inside another.
+                #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+                contained_in: BTreeMap<&'a Path, Vec<&'a Path>>,
+                //The stable ID of each video, only present with --video-ids.
+                #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+                video_ids: BTreeMap<&'a Path, uuid::Uuid>,
             }
 
             let output_vec: Vec<JsonStruct> = search_output
@@ -335,6 +659,28 @@ fn do_app_outputs(
                 .map(|group| JsonStruct {
                     reference: group.reference(),
                     duplicates: group.duplicates().collect(),
+                    aliases: group
+                        .contained_paths()
+                        .map(|path| (path, search_output.aliases_of(path)))
+                        .filter(|(_path, aliases)| !aliases.is_empty())
+                        .collect(),
+                    duration_spread: group.duration_spread(),
+                    speed_factors: group
+                        .contained_paths()
+                        .filter_map(|path| {
+                            let base = group.reference().or_else(|| group.duplicates().next())?;
+                            Some((path, group.speed_factor(base, path)?))
+                        })
+                        .collect(),
+                    contained_in: group
+                        .contained_paths()
+                        .map(|path| (path, group.containment().contained_in(path).collect_vec()))
+                        .filter(|(_path, longer)| !longer.is_empty())
+                        .collect(),
+                    video_ids: group
+                        .contained_paths()
+                        .filter_map(|path| Some((path, cache.id_for_path(path)?)))
+                        .collect(),
                 })
                 .collect();
 
@@ -372,15 +718,58 @@ fn do_app_outputs(
                     .filter_map(|p| MatchGroup::new([p.to_path_buf(), p.to_path_buf()]).ok());
 
                 let mut new_search_output = SearchOutput::new(new_groups.collect());
-                new_search_output.sort(*sorting, &cache);
-                new_search_output.save_debug_imgs(thumbs_dir);
+                new_search_output.sort(*sorting, sort_key_data(&cache));
+                new_search_output.save_debug_imgs(thumbs_dir, &cache);
             } else {
-                search_output.sort(*sorting, &cache);
-                search_output.save_debug_imgs(thumbs_dir);
+                search_output.sort(*sorting, sort_key_data(&cache));
+                search_output.save_debug_imgs(thumbs_dir, &cache);
             }
         }
     }
 
+    ////////////////////////////////////////////////////////////////////////////
+    // Report file output
+    ////////////////////////////////////////////////////////////////////////////
+    let sources = ReportSources {
+        keep: keep_best,
+        hash: |path: &Path| cache.fetch(path).ok(),
+        resolution: probe_resolution,
+        id: |path: &Path| cache.id_for_path(path),
+    };
+    if !cfg.output_cfg.reports.is_empty() {
+        let rows = search_output.report_rows(&sources);
+
+        for report_cfg in &cfg.output_cfg.reports {
+            match report_cfg {
+                ReportCfg::Csv(path) => CsvReport::new(path)
+                    .with_video_ids(cfg.cache_cfg.video_ids)
+                    .write_rows(&rows)?,
+                ReportCfg::Sqlite(path) => SqliteReport::new(path).write_rows(&rows)?,
+                ReportCfg::Html {
+                    path,
+                    thumbnail_budget,
+                } => HtmlReport::new(path, *thumbnail_budget, |path: &Path| {
+                    thumbnail_strip(&cache, path, THUMBNAIL_HEIGHT)
+                })
+                .write_rows(&rows)?,
+            }
+        }
+    }
+
+    ////////////////////////////////////////////////////////////////////////////
+    // Quarantine directory output
+    ////////////////////////////////////////////////////////////////////////////
+    if let Some(dir) = &cfg.output_cfg.quarantine_dir {
+        let summary = search_output.quarantine(dir, &sources)?;
+        info!(
+            "Quarantined {} groups in {} ({} new links, {} old groups removed)",
+            summary.groups,
+            dir.display(),
+            summary.linked,
+            summary.removed_groups
+        );
+    }
+
     ////////////////////////////////////////////////////////////////////////////
     // Gui output
     ////////////////////////////////////////////////////////////////////////////
@@ -390,7 +779,11 @@ fn do_app_outputs(
         super::app_cfg::GuiOutputCfg::GuiSlint {
             sorting,
             trash_path,
+            trash_layout,
             max_thumbs: _max_thumbs,
+            cache_budget,
+            player_cmd,
+            browser_cmd,
         } => {
             if matches!(cfg.output_cfg.text, Unique(_)) {
                 let dup_paths = search_output
@@ -413,42 +806,118 @@ fn do_app_outputs(
                 search_output = SearchOutput::new(new_groups.collect());
             }
 
-            search_output.sort(*sorting, &cache);
-            let thunks = search_output.resolution_thunks(&cache, trash_path.as_deref());
+            search_output.sort(*sorting, sort_key_data(&cache));
+            let thunks =
+                search_output.resolution_thunks(&cache, trash_path.as_deref(), *trash_layout);
 
             #[cfg(feature = "gui_slint")]
-            run_gui_slint(thunks).unwrap();
+            let session_path = cfg.cache_cfg.cache_path.as_deref().map(session_state_path);
+            run_gui_slint(
+                thunks,
+                trash_path.as_deref(),
+                session_path.as_deref(),
+                *cache_budget,
+                GuiCommands {
+                    player: player_cmd.clone(),
+                    browser: browser_cmd.clone(),
+                },
+                cfg.output_cfg.quarantine_dir.as_deref(),
+                cache,
+            )
+            .unwrap();
         }
     }
     Ok(())
 }
 
+// One bucket for each profile in cfg, in the same order, plus a final bucket of the hashes that
+// are not in any profile.
+fn split_by_profile(cfg: &AppCfg, hashes: Vec<VideoHash>) -> Vec<Vec<VideoHash>> {
+    let mut ret = vec![vec![]; cfg.profiles.len() + 1];
+    for hash in hashes {
+        let idx = cfg
+            .profiles
+            .iter()
+            .position(|profile| profile.covers(hash.src_path()))
+            .unwrap_or(cfg.profiles.len());
+        ret[idx].push(hash);
+    }
+    ret
+}
+
+// Search the candidates in two passes (see --refine): with their cached hashes first, and then
+// with bigger hashes of a longer window of only the videos that were grouped, which are kept in a
+// cache of their own beside the main one. Returns no groups if the search was interrupted.
+fn refine_search(
+    cfg: &AppCfg,
+    cache: &VideoHashFilesystemCache,
+    cand_hashes: &[VideoHash],
+    interrupt: &Interrupt,
+) -> Result<Vec<MatchGroup>, AppError> {
+    let coarse_opts = cfg.hash_cfg.creation_options();
+    let fine_opts = CreationOptions {
+        duration: coarse_opts
+            .duration
+            .max(Preset::Thorough.options().duration),
+        hash_bits: HashSize::Ten,
+        ..coarse_opts
+    };
+    let tolerances = TwoPassTolerances {
+        coarse: cfg.search_opts.tolerance,
+        fine: cfg.search_opts.tolerance,
+    };
+    let paths = cand_hashes
+        .iter()
+        .map(|hash| hash.src_path().to_path_buf())
+        .collect();
+
+    let _graceful = interrupt.graceful();
+    let update_opts = UpdateOptions {
+        cancellation: Some(interrupt.token()),
+        ..UpdateOptions::default()
+    };
+    match cache.two_pass_search(
+        CACHE_SAVE_THRESHOLD,
+        paths,
+        coarse_opts,
+        fine_opts,
+        tolerances,
+        update_opts,
+    ) {
+        Err(VdfCacheError::CreateHashError(vid_dup_finder_lib::Error::Cancelled)) => Ok(vec![]),
+        result => Ok(result?),
+    }
+}
+
 fn search_disk(
     cfg: &AppCfg,
     cache: &VideoHashFilesystemCache,
 
     match_db: Option<&MatchDb>,
-) -> SearchOutput {
+    interrupt: &Interrupt,
+) -> Result<SearchOutput, AppError> {
     #[cfg(feature = "print_timings")]
     let hash_fetch_start = Instant::now();
 
     // Now that we have updated the caches, we can fetch hashes from the cache in preparation for a search.
-    // the unwraps here are infallible, as the keys we are fetching are sourced from the cache itself.
+    // The keys we are fetching are sourced from the cache itself, so fetching should not fail.
     let all_hash_paths = cache.all_cached_paths();
 
+    //imported hashes are not on this filesystem, so they are always candidates.
     let cands_filter = create_cands_filename_filter(cfg);
-    let cand_hashes = all_hash_paths
+    let mut cand_hashes = all_hash_paths
         .iter()
         .filter(|&p| cands_filter.includes(p))
-        .map(|p| cache.fetch(p).unwrap())
-        .collect::<Vec<_>>();
+        .map(|p| cache.fetch(p))
+        .collect::<Result<Vec<_>, _>>()?;
+    cand_hashes.extend(cache.imported_hashes());
 
     let refs_filter = create_refs_filename_filter(cfg);
     let ref_hashes = all_hash_paths
         .iter()
         .filter(|&p| refs_filter.includes(p))
-        .map(|p| cache.fetch(p).unwrap())
-        .collect::<Vec<_>>();
+        .map(|p| cache.fetch(p))
+        .collect::<Result<Vec<_>, _>>()?;
 
     #[cfg(feature = "print_timings")]
     println!(
@@ -472,19 +941,78 @@ fn search_disk(
     }
 
     //If there are just cands, then perform a find-all search. Otherwise perform a with-refs search.
-    let mut matchset = if ref_hashes.is_empty() {
-        search(cand_hashes, cfg.tolerance)
+    //Each profile from the config file is searched separately with its own tolerance, followed by
+    //all the videos that are not in any profile. With --refine, the candidates are searched in two
+    //passes instead.
+    let with_refs = !ref_hashes.is_empty();
+    let tolerances = cfg
+        .profiles
+        .iter()
+        .map(|profile| profile.tolerance)
+        .chain([cfg.search_opts.tolerance]);
+    let mut matchset = if cfg.refine {
+        refine_search(cfg, cache, &cand_hashes, interrupt)?
     } else {
-        search_with_references(ref_hashes, cand_hashes, cfg.tolerance)
+        tolerances
+            .zip(split_by_profile(cfg, cand_hashes))
+            .zip(split_by_profile(cfg, ref_hashes))
+            .flat_map(|((tolerance, cand_hashes), ref_hashes)| {
+                let opts = SearchOptions {
+                    tolerance,
+                    ..cfg.search_opts.clone()
+                };
+                if with_refs {
+                    search_with_references_with_opts(ref_hashes, cand_hashes, &opts)
+                } else {
+                    search_with_opts(cand_hashes, &opts)
+                }
+            })
+            .collect::<Vec<_>>()
     };
 
+    //drop the videos whose frames did not look alike, so that they are never deleted as duplicates.
+    if cfg.search_opts.verify_matches.is_some() {
+        let num_groups = matchset.len();
+        matchset = matchset
+            .iter()
+            .filter_map(MatchGroup::without_failed_verifications)
+            .collect();
+        info!(
+            "Verification by SSIM left {} of {num_groups} groups",
+            matchset.len()
+        );
+    }
+
     //unfortunately currently need to convert each matchgroup into
     //its cartesian product to apply filters.
     if cfg.output_cfg.cartesian_product {
-        matchset = matchset
+        matchset = match_filtering::cartesian_product(&matchset);
+    }
+
+    //label the videos which were cut from a longer video in the same group, so that the longer
+    //one can be kept.
+    if cfg.output_cfg.containment {
+        let frame_source = WindowHasher::from_options(CreationOptions {
+            skip_forward_amount: 0.0,
+            ..cfg.hash_cfg.creation_options()
+        });
+        let analyze = |group: MatchGroup| {
+            let report = group.analyze_containment(&frame_source);
+            group.with_containment(report)
+        };
+
+        #[cfg(feature = "parallel_loading")]
+        let it = matchset.into_par_iter();
+
+        #[cfg(not(feature = "parallel_loading"))]
+        let it = matchset.into_iter();
+
+        matchset = it.map(analyze).collect();
+        let num_contained = matchset
             .iter()
-            .flat_map(|group| group.dup_combinations())
-            .collect();
+            .map(|group| group.containment().edges().len())
+            .sum::<usize>();
+        info!("Found {num_contained} videos cut from a longer video in the same group");
     }
 
     #[cfg(feature = "print_timings")]
@@ -513,50 +1041,20 @@ fn search_disk(
             .flat_map(|group| group.dup_combinations())
             .count();
 
-        let group_would_be_falsepos = |group: &[PathBuf], cand_path: &Path| {
-            group
-                .iter()
-                .any(|group_path| match_db.is_falsepos(group_path, cand_path))
-        };
-
-        // let first_group_that_is_confirmed = |groups: &[MatchGroup], cand_path: &Path| {
-        //     groups
-        //         .iter()
-        //         .position(|group| match_db.all_confirmed(group.contained_paths(), cand_path))
-        // };
-
-        let first_group_that_is_unconfirmed = |groups: &Vec<Vec<PathBuf>>, cand_path: &Path| {
-            groups
-                .iter()
-                .position(|group| !match_db.all_confirmed(group, cand_path))
-        };
-
         #[cfg(feature = "print_timings")]
         let remove_known_start = Instant::now();
 
         if cfg.matchdb_cfg.remove_known_matches {
+            let is_confirmed = |p1: &Path, p2: &Path| match_db.is_confirmed(p1, p2);
+
             #[cfg(feature = "parallel_loading")]
-            let it = matchset.into_par_iter();
+            let it = matchset.par_iter();
 
             #[cfg(not(feature = "parallel_loading"))]
-            let it = matchset.into_iter();
+            let it = matchset.iter();
 
-            // #[cfg(not(feature = "parallel_loading"))]
-            // let matchset = matchset.into_iter();
             matchset = it
-                .flat_map(|group| {
-                    let mut ret: Vec<Vec<PathBuf>> = vec![];
-                    for src_path in group.contained_paths() {
-                        if let Some(idx) = first_group_that_is_unconfirmed(&ret, src_path) {
-                            ret[idx].push(src_path.to_path_buf())
-                        } else {
-                            ret.push(vec![src_path.to_path_buf()])
-                        }
-                    }
-                    ret.into_iter()
-                        .filter_map(|paths| MatchGroup::new(paths).ok())
-                        .collect::<Vec<_>>()
-                })
+                .flat_map(|group| match_filtering::remove_known_matches(group, is_confirmed))
                 .collect::<Vec<_>>()
         }
         #[cfg(feature = "print_timings")]
@@ -566,24 +1064,14 @@ fn search_disk(
         );
 
         if cfg.matchdb_cfg.remove_falsepos {
+            let is_falsepos = |p1: &Path, p2: &Path| match_db.is_falsepos(p1, p2);
+
             matchset = matchset
-                .into_iter()
-                .flat_map(|group| {
-                    let mut ret: Vec<PathBuf> = vec![];
-
-                    for src_path in group.contained_paths() {
-                        if ret.is_empty() {
-                            ret.push(src_path.to_path_buf());
-                        } else if !group_would_be_falsepos(&ret, src_path) {
-                            ret.push(src_path.to_path_buf())
-                        } else {
-                            num_falsepos_removed += 1;
-                        }
-                    }
-                    match MatchGroup::new(ret) {
-                        Ok(group) => vec![group],
-                        Err(_) => vec![],
-                    }
+                .iter()
+                .filter_map(|group| {
+                    let (group, num_removed) = match_filtering::remove_falsepos(group, is_falsepos);
+                    num_falsepos_removed += num_removed;
+                    group
                 })
                 .collect::<Vec<_>>()
         }
@@ -638,14 +1126,98 @@ fn search_disk(
     );
 
     if cfg.show_missed_matches {
-        search_output = show_missed_matches(match_db.as_ref().unwrap(), search_output);
+        search_output = show_missed_matches(cfg, cache, match_db.as_ref().unwrap(), search_output);
+    }
+
+    //counting the memory visits every path, which is slow for huge results.
+    if log::log_enabled!(log::Level::Debug) {
+        debug!(
+            "The {} groups found use about {} of memory",
+            search_output.len(),
+            bytesize::ByteSize::b(search_output.approx_memory_usage() as u64)
+        );
     }
 
-    search_output
+    Ok(search_output)
 }
 
-/// Find the items in the given search output that should have been returned, but were not.
-fn show_missed_matches(match_db: &MatchDb, curr_output: SearchOutput) -> SearchOutput {
+/// Why a confirmed match was not found by the search.
+enum MissedMatchReason {
+    NonMatch(NonMatchReason),
+    HashMissingFromCache { path: PathBuf },
+}
+
+impl MissedMatchReason {
+    // The reason without its details, for counting how often each reason occurs.
+    const fn kind(&self) -> &'static str {
+        match self {
+            Self::NonMatch(reason) => match reason {
+                NonMatchReason::IncompatibleHashes { .. } => "IncompatibleHashes",
+                NonMatchReason::DurationGate { .. } => "DurationGate",
+                NonMatchReason::Suppressed => "Suppressed",
+                NonMatchReason::ShortVideosExcluded => "ShortVideosExcluded",
+                NonMatchReason::AudioDisagrees => "AudioDisagrees",
+                NonMatchReason::ActiveAreaMismatch { .. } => "ActiveAreaMismatch",
+                NonMatchReason::TooFewReliableBits { .. } => "TooFewReliableBits",
+                NonMatchReason::DistanceAboveTolerance { .. } => "DistanceAboveTolerance",
+                NonMatchReason::Matches => "Matches",
+            },
+            Self::HashMissingFromCache { .. } => "HashMissingFromCache",
+        }
+    }
+}
+
+impl std::fmt::Display for MissedMatchReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NonMatch(reason) => write!(f, "{reason}"),
+            Self::HashMissingFromCache { path } => {
+                write!(f, "no hash in the cache for {}", path.display())
+            }
+        }
+    }
+}
+
+// Why the search would not match the two videos, with the options of the profile that the first
+// is in (as search_disk searches each profile with its own tolerance).
+fn explain_missed_match(
+    cfg: &AppCfg,
+    cache: &VideoHashFilesystemCache,
+    p1: &Path,
+    p2: &Path,
+) -> MissedMatchReason {
+    let fetch = |path: &Path| {
+        cache
+            .fetch(path)
+            .map_err(|_| MissedMatchReason::HashMissingFromCache {
+                path: path.to_path_buf(),
+            })
+    };
+    let (h1, h2) = match (fetch(p1), fetch(p2)) {
+        (Ok(h1), Ok(h2)) => (h1, h2),
+        (Err(e), _) | (_, Err(e)) => return e,
+    };
+
+    let tolerance = cfg
+        .profiles
+        .iter()
+        .find(|profile| profile.covers(p1))
+        .map_or(cfg.search_opts.tolerance, |profile| profile.tolerance);
+    let opts = SearchOptions {
+        tolerance,
+        ..cfg.search_opts.clone()
+    };
+    MissedMatchReason::NonMatch(explain_non_match(&h1, &h2, &opts))
+}
+
+/// Find the items in the given search output that should have been returned, but were not, and
+/// print why the search did not match each of them.
+fn show_missed_matches(
+    cfg: &AppCfg,
+    cache: &VideoHashFilesystemCache,
+    match_db: &MatchDb,
+    curr_output: SearchOutput,
+) -> SearchOutput {
     let all_found_in_search = curr_output
         .dup_groups()
         .flat_map(|group| group.dup_combinations())
@@ -682,10 +1254,29 @@ fn show_missed_matches(match_db: &MatchDb, curr_output: SearchOutput) -> SearchO
 
     let remaining_len = filtered.len();
 
+    let mut kind_counts = BTreeMap::<&str, usize>::new();
     #[allow(clippy::print_stdout)]
-    let () = println!(
-        "all confirmed groups: {all_confirmed_len}, failed to match groups: {remaining_len}"
-    );
+    for group in &filtered {
+        if let Some((p1, p2)) = group.contained_paths().collect_tuple() {
+            let reason = explain_missed_match(cfg, cache, p1, p2);
+            println!("{} <-> {}: {reason}", p1.display(), p2.display());
+            *kind_counts.entry(reason.kind()).or_default() += 1;
+        }
+    }
+
+    #[allow(clippy::print_stdout)]
+    {
+        println!(
+            "all confirmed groups: {all_confirmed_len}, failed to match groups: {remaining_len}"
+        );
+        for (kind, count) in kind_counts
+            .into_iter()
+            .sorted_by_key(|&(kind, count)| (std::cmp::Reverse(count), kind))
+        {
+            let pct = 100.0 * count as f64 / remaining_len as f64;
+            println!("    {kind}: {count} ({pct:.0}%)");
+        }
+    }
 
     SearchOutput::new(filtered)
 }
@@ -766,6 +1357,7 @@ fn create_filename_filter(cfg: &AppCfg) -> FilenamePattern {
 
     FilenamePattern::new(incl_paths, excl_paths, excl_exts)
         .unwrap_or_else(|e| print_error_and_quit(e))
+        .with_symlink_policy(cfg.dir_cfg.symlinks)
 }
 
 fn create_cands_filename_filter(cfg: &AppCfg) -> FilenamePattern {
@@ -780,6 +1372,7 @@ fn create_cands_filename_filter(cfg: &AppCfg) -> FilenamePattern {
     let excl_exts = cfg.dir_cfg.excl_exts.clone();
     FilenamePattern::new(incl_paths, excl_paths, excl_exts)
         .unwrap_or_else(|e| print_error_and_quit(e))
+        .with_symlink_policy(cfg.dir_cfg.symlinks)
 }
 
 fn create_refs_filename_filter(cfg: &AppCfg) -> FilenamePattern {
@@ -796,9 +1389,16 @@ fn create_refs_filename_filter(cfg: &AppCfg) -> FilenamePattern {
 
     FilenamePattern::new(incl_paths, excl_paths, excl_exts)
         .unwrap_or_else(|e| print_error_and_quit(e))
+        .with_symlink_policy(cfg.dir_cfg.symlinks)
 }
 
-fn update_hash_cache(cfg: &AppCfg, cache: &VideoHashFilesystemCache) -> eyre::Result<()> {
+// Returns the other paths to each file that was found, if symlinks are being deduplicated, along
+// with what happened to each file.
+fn update_hash_cache(
+    cfg: &AppCfg,
+    cache: &VideoHashFilesystemCache,
+    interrupt: &Interrupt,
+) -> eyre::Result<(SymlinkAliases, UpdateReport)> {
     #[cfg(feature = "print_timings")]
     let cache_update_start = Instant::now();
 
@@ -823,12 +1423,30 @@ fn update_hash_cache(cfg: &AppCfg, cache: &VideoHashFilesystemCache) -> eyre::Re
     // cache.remove_deleted_items(all_files.iter().cloned());
     // cache.update_using_fs(all_files.iter().cloned());
 
-    let it = file_filter.iterate_from_fs()?.into_iter();
+    let (it, symlink_aliases) = file_filter.iterate_from_fs()?;
+    let it = it.into_iter();
     let t = iter_tee::Tee::new(it);
 
-    cache.update_using_fs(t.clone());
-    cache.remove_deleted_items(t.clone());
-    cache.save().unwrap();
+    //an interrupted update still removes deleted files and saves whatever was hashed.
+    let report = {
+        let _graceful = interrupt.graceful();
+        let opts = UpdateOptions {
+            cancellation: Some(interrupt.token()),
+            ..UpdateOptions::default()
+        };
+        let report = cache.update_using_fs(t.clone(), opts);
+        cache.remove_deleted_items(t.clone());
+        cache.save().unwrap();
+        report
+    };
+
+    for (path, e) in &report.failed {
+        warn!(target: "cache_update", "Failed to hash {}: {e}", path.display());
+    }
+    info!(target: "cache_update", "Cache update: {}", report.summary());
+    if let Some(e) = report.aborted {
+        return Err(eyre::Report::new(e).wrap_err("Cache update failed"));
+    }
 
     #[cfg(feature = "print_timings")]
     println!(
@@ -836,7 +1454,7 @@ fn update_hash_cache(cfg: &AppCfg, cache: &VideoHashFilesystemCache) -> eyre::Re
         cache_update_start.elapsed().as_secs_f64()
     );
 
-    Ok(())
+    Ok((symlink_aliases, report))
 }
 
 fn print_fatal_err(fatal_err: eyre::Report, verbosity: ReportVerbosity) {
@@ -1,18 +1,26 @@
 use std::{
     ffi::OsString,
+    io::Read,
     path::{Path, PathBuf},
 };
 
 use clap::{value_parser, ArgAction::*};
+use itertools::Itertools;
+use uuid::Uuid;
 use vid_dup_finder_lib::*;
 
 use crate::app::*;
+use crate::video_hash_filesystem_cache::filename_pattern::SymlinkPolicy;
 
 // file specification
 const FILE_PATHS: &str = "Directories/files to search";
+const FILES_FROM: &str = "Read files to search from a list";
+const FILES_FROM0: &str = "Read files to search from a NUL-separated list";
+const FILES_FROM_STRICT: &str = "Fail if listed files do not exist";
 const REF_PATHS: &str = "Reference file paths";
 const EXCL_FILE_PATHS: &str = "Exclude file paths";
 const EXCL_EXTS: &str = "Exclude file extensions";
+const SYMLINKS: &str = "Symlink policy";
 
 // Type of search
 const DISPLAY_MATCH_DB_MATCHES: &str =
@@ -26,16 +34,40 @@ const UPDATE_CACHE_ONLY: &str = "Update cache only. Do not perform any search";
 const NO_UPDATE_CACHE: &str = "Do not update the cache. Search using alreaady-cached data";
 const RELOAD_ERR_VIDS: &str = "Reload error videos";
 const RELOAD_ALL_VIDS: &str = "Reload all videos";
+const EXPORT_HASHES: &str = "Export hashes";
+const IMPORT_HASHES: &str = "Import hashes";
+const CACHE_STATS: &str = "Cache stats";
+const DEBUG_PIPELINE: &str = "Debug pipeline";
+const CACHE_FORGET: &str = "Forget cached directory";
+const CACHE_MOVE: &str = "Move cached directory";
+const VIDEO_IDS: &str = "Video ids";
+
+//cache maintenance commands, run as `vid_dup_finder cache <command>`
+const CACHE_CMD: &str = "cache";
+const CACHE_CMD_STATS: &str = "stats";
+const CACHE_CMD_PRUNE_MISSING: &str = "prune-missing";
+const CACHE_CMD_PRUNE_ERRORS: &str = "prune-errors";
+const CACHE_CMD_FORGET: &str = "forget";
+const CACHE_CMD_MOVE: &str = "move";
+const CACHE_CMD_VERIFY: &str = "verify";
+const CACHE_CMD_EXPORT: &str = "export";
+const CACHE_CMD_IMPORT: &str = "import";
+const CACHE_CMD_MIGRATE_TO_SQLITE: &str = "migrate-to-sqlite";
+const CACHE_CMD_FIND_ID: &str = "find-id";
+const CACHE_DRY_RUN: &str = "Dry run";
 
 //hashing configuration
 const CROPDETECT: &str = "Cropdetect algorithm";
 
 const SKIP_FORWARD: &str = "Amount";
 const DURATION: &str = "Hash Duration";
+const IGNORE_PIXEL_ASPECT_RATIO: &str = "Ignore pixel aspect ratio";
+const SPEED_VARIANTS: &str = "Speed variants";
 
 //match confirmation/filtering
 const MATCH_DB_PATH: &str = "Match database path";
 const MATCH_DB_FIX_MOVED_FILES: &str = "Check all matchdb entries still exist";
+const MATCH_DB_MERGE: &str = "Merge another match database into this one";
 const MATCH_DB_REMOVE_KNOWN_MATCHES: &str = "Return matches already in the database";
 const MATCH_DB_REMOVE_FALSEPOS: &str =
     "Filter out matches that the database knows are false positive";
@@ -44,33 +76,62 @@ const MATCH_DB_SHOW_MISSED_MATCHES: &str =
 
 //output settings
 const CARTESIAN_PRODUCT: &str = "Cartesian Product";
+const CONTAINMENT: &str = "Analyze containment";
 const SORTED: &str = "Sort";
 const OUTPUT_FORMAT: &str = "Format";
 const OUTPUT_THUMBS_DIR: &str = "Directory";
+const REPORT_CSV: &str = "CSV report path";
+const REPORT_SQLITE: &str = "SQLite report path";
+const REPORT_HTML: &str = "HTML report path";
+const REPORT_HTML_THUMBNAIL_BUDGET: &str = "HTML report thumbnail budget";
+const QUARANTINE_DIR: &str = "Quarantine directory";
 
 //gui settings
 const GUI_SLINT: &str = "Run other gui";
 const GUI_TRASH_PATH: &str = "Gui trash path";
+const GUI_TRASH_LAYOUT: &str = "Gui trash layout";
 const GUI_MAX_THUMBS: &str = "maximum number of thumbnails in gui";
+const GUI_CACHE_SIZE: &str = "Gui thumbnail cache size";
+const GUI_PLAYER_CMD: &str = "Gui video player command";
+const GUI_BROWSER_CMD: &str = "Gui file manager command";
 
 //search configuration
 const TOLERANCE: &str = "Comparison tolerance";
+const SHORT_VIDEO_TOLERANCE: &str = "Short video comparison tolerance";
+const GROUPING: &str = "Grouping mode";
+const DURATION_GATE: &str = "Duration gate";
+const MIN_ACTIVE_AREA: &str = "Minimum active area";
+const NO_GROUP_WITHIN: &str = "Never group within paths";
+const MUST_CONTAIN: &str = "Only report groups containing paths";
+const PROTECT: &str = "Protected paths";
+const VERIFY_ZERO_DISTANCE: &str = "Verify identical hashes";
+const VERIFY_MATCHES: &str = "Verify matches";
+const REFINE: &str = "Refine matches";
 const OUTPUT_KIND: &str = "What to output (default is to print duplicate items)";
 
 // Arg specification
 const ARGS_FILE: &str = "Args file";
+const CONFIG_FILE: &str = "Config file";
 
 //Verbosity
 const VERBOSITY_QUIET: &str = "Quiet";
 const VERBOSITY_VERBOSE: &str = "Verbose";
 
-const DISPLAY_ORDERING: [&str; 32] = [
+//Exit status
+const SUMMARY: &str = "Summary";
+const STRICT_EXIT_CODES: &str = "Strict exit codes";
+
+const DISPLAY_ORDERING: [&str; 69] = [
     //
     // file specification
     FILE_PATHS,
+    FILES_FROM,
+    FILES_FROM0,
+    FILES_FROM_STRICT,
     REF_PATHS,
     EXCL_FILE_PATHS,
     EXCL_EXTS,
+    SYMLINKS,
     //type of search
     DISPLAY_MATCH_DB_MATCHES,
     DISPLAY_MATCH_DB_FALSEPOS,
@@ -78,11 +139,23 @@ const DISPLAY_ORDERING: [&str; 32] = [
     //
     //search modifiers
     TOLERANCE,
+    SHORT_VIDEO_TOLERANCE,
+    GROUPING,
+    DURATION_GATE,
+    MIN_ACTIVE_AREA,
+    NO_GROUP_WITHIN,
+    MUST_CONTAIN,
+    PROTECT,
+    VERIFY_ZERO_DISTANCE,
+    VERIFY_MATCHES,
+    REFINE,
     //
     //HASHING
     CROPDETECT,
     SKIP_FORWARD,
     DURATION,
+    IGNORE_PIXEL_ASPECT_RATIO,
+    SPEED_VARIANTS,
     //
     //caching
     CACHE_FILE,
@@ -90,17 +163,31 @@ const DISPLAY_ORDERING: [&str; 32] = [
     NO_UPDATE_CACHE,
     RELOAD_ERR_VIDS,
     RELOAD_ALL_VIDS,
+    EXPORT_HASHES,
+    IMPORT_HASHES,
+    CACHE_STATS,
+    DEBUG_PIPELINE,
+    CACHE_FORGET,
+    CACHE_MOVE,
+    VIDEO_IDS,
     //
     //outputs
     CARTESIAN_PRODUCT,
+    CONTAINMENT,
     SORTED,
     OUTPUT_KIND,
     OUTPUT_FORMAT,
     OUTPUT_THUMBS_DIR,
+    REPORT_CSV,
+    REPORT_SQLITE,
+    REPORT_HTML,
+    REPORT_HTML_THUMBNAIL_BUDGET,
+    QUARANTINE_DIR,
     //
     //match database
     MATCH_DB_PATH,
     MATCH_DB_FIX_MOVED_FILES,
+    MATCH_DB_MERGE,
     MATCH_DB_REMOVE_KNOWN_MATCHES,
     MATCH_DB_REMOVE_FALSEPOS,
     MATCH_DB_SHOW_MISSED_MATCHES,
@@ -108,13 +195,20 @@ const DISPLAY_ORDERING: [&str; 32] = [
     //verbosity
     VERBOSITY_QUIET,
     VERBOSITY_VERBOSE,
+    SUMMARY,
+    STRICT_EXIT_CODES,
     //
     //gui
     GUI_SLINT,
     GUI_TRASH_PATH,
+    GUI_TRASH_LAYOUT,
     GUI_MAX_THUMBS,
+    GUI_CACHE_SIZE,
+    GUI_PLAYER_CMD,
+    GUI_BROWSER_CMD,
     //argument replacement
     ARGS_FILE,
+    CONFIG_FILE,
 ];
 
 fn build_app() -> clap::Command {
@@ -144,7 +238,7 @@ fn build_app() -> clap::Command {
     clap_app = clap_app.arg(
         clap::Arg::new(FILE_PATHS)
             .long("files")
-            .required_unless_present(ARGS_FILE)
+            .required_unless_present_any([ARGS_FILE, FILES_FROM, FILES_FROM0, DEBUG_PIPELINE])
             .num_args(0..)
             .value_parser(value_parser!(PathBuf))
             .action(Append)
@@ -152,6 +246,34 @@ fn build_app() -> clap::Command {
             .display_order(get_ordering(FILE_PATHS)),
     );
 
+    clap_app = clap_app.arg(
+        clap::Arg::new(FILES_FROM)
+            .long("files-from")
+            .value_parser(value_parser!(PathBuf))
+            .num_args(1)
+            .action(Append)
+            .help("Read more paths for --files from a file with one path per line, or from stdin if the file is \"-\". Relative paths are relative to the current directory. Listed paths which do not exist are reported at the end of the run")
+            .display_order(get_ordering(FILES_FROM)),
+    );
+
+    clap_app = clap_app.arg(
+        clap::Arg::new(FILES_FROM0)
+            .long("files-from0")
+            .value_parser(value_parser!(PathBuf))
+            .num_args(1)
+            .action(Append)
+            .help("As --files-from, but paths are separated by NUL characters, as written by find -print0")
+            .display_order(get_ordering(FILES_FROM0)),
+    );
+
+    clap_app = clap_app.arg(
+        clap::Arg::new(FILES_FROM_STRICT)
+            .long("files-from-strict")
+            .help("Stop with an error if any path given by --files-from or --files-from0 does not exist")
+            .action(SetTrue)
+            .display_order(get_ordering(FILES_FROM_STRICT)),
+    );
+
     clap_app = clap_app.arg(
         clap::Arg::new(REF_PATHS)
             .long("with-refs")
@@ -184,6 +306,16 @@ fn build_app() -> clap::Command {
             .display_order(get_ordering(EXCL_EXTS)),
     );
 
+    clap_app = clap_app.arg(
+        clap::Arg::new(SYMLINKS)
+            .long("symlinks")
+            .num_args(1)
+            .value_parser(value_parser!(SymlinkPolicyArg))
+            .default_value("files")
+            .help("What to do with symlinks. 'files' finds files through symlinks to them but does not walk into symlinked directories, 'follow' also walks into symlinked directories and finds a file once for every path that leads to it, 'ignore' skips symlinks, and 'dedup' follows symlinks but finds each file once under its real path, so that a file is never reported as a duplicate of itself")
+            .display_order(get_ordering(SYMLINKS)),
+    );
+
     clap_app = clap_app.arg(
         clap::Arg::new(DISPLAY_MATCH_DB_MATCHES)
             .long("display-match-db-matches")
@@ -246,7 +378,8 @@ fn build_app() -> clap::Command {
             .value_parser(value_parser!(PathBuf))
             .num_args(1)
             .default_value(default_cache_file())
-            .help("An optional custom location for the cache file (used to speed up repeated runs)")
+            .global(true)
+            .help("An optional custom location for the cache file (used to speed up repeated runs). Caches ending in .sqlite, .sqlite3 or .db are stored in a sqlite database")
             .display_order(get_ordering(CACHE_FILE)),
     );
 
@@ -260,10 +393,57 @@ fn build_app() -> clap::Command {
             .display_order(get_ordering(UPDATE_CACHE_ONLY)),
     );
 
+    clap_app = clap_app.arg(
+        clap::Arg::new(CACHE_STATS)
+            .long("cache-stats")
+            .help("Do not run a search. Update the cache (unless --no-update-cache is given), then print how long the cached videos took to hash: the median and 95th percentile times, the slowest files, how many videos were hashed by each backend and of each codec, and how long videos took to hash with each decoder.")
+            .action(SetTrue)
+            .display_order(get_ordering(CACHE_STATS)),
+    );
+
+    clap_app = clap_app.arg(
+        clap::Arg::new(DEBUG_PIPELINE)
+            .long("debug-pipeline")
+            .value_name("FILE")
+            .value_parser(value_parser!(PathBuf))
+            .num_args(1)
+            .help("Do not run a search. Decode the start of the given video, then print what decoded it (for gstreamer, every element in the pipeline) and how long each frame took to decode. Use this to find out why a video is slow to hash.")
+            .display_order(get_ordering(DEBUG_PIPELINE)),
+    );
+
+    clap_app = clap_app.arg(
+        clap::Arg::new(CACHE_FORGET)
+            .long("cache-forget")
+            .value_parser(value_parser!(PathBuf))
+            .num_args(1)
+            .action(Append)
+            .help("Before updating the cache, remove every cached video in the given directory (and its subdirectories) from the cache. May be given more than once")
+            .display_order(get_ordering(CACHE_FORGET)),
+    );
+
+    clap_app = clap_app.arg(
+        clap::Arg::new(CACHE_MOVE)
+            .long("cache-move")
+            .value_parser(value_parser!(PathBuf))
+            .num_args(2)
+            .value_names(["OLD", "NEW"])
+            .action(Append)
+            .help("Before updating the cache, record that the directory OLD has been moved to NEW, so that the videos in it are not hashed again. May be given more than once")
+            .display_order(get_ordering(CACHE_MOVE)),
+    );
+
+    clap_app = clap_app.arg(
+        clap::Arg::new(VIDEO_IDS)
+            .long("video-ids")
+            .action(SetTrue)
+            .help("Give each video a stable ID, kept in a file beside the cache, which follows it when it is moved with --cache-move or found by --matchdb-fix-moved-files. The IDs are included in JSON output and CSV reports")
+            .display_order(get_ordering(VIDEO_IDS)),
+    );
+
     clap_app = clap_app.arg(
         clap::Arg::new(RELOAD_ERR_VIDS)
             .long("reload-errs")
-            .help("Attempt to re-process videos which previously failed to loead.")
+            .help("Attempt to re-process videos which previously failed to load. Videos which were too short to hash are not retried.")
             .conflicts_with(NO_UPDATE_CACHE)
             .action(SetTrue)
             .display_order(get_ordering(RELOAD_ERR_VIDS)),
@@ -278,6 +458,24 @@ fn build_app() -> clap::Command {
             .display_order(get_ordering(RELOAD_ALL_VIDS)),
     );
 
+    clap_app = clap_app.arg(
+        clap::Arg::new(EXPORT_HASHES)
+            .long("export-hashes")
+            .value_parser(value_parser!(PathBuf))
+            .num_args(1)
+            .help("After updating the cache, write every hash in it to the given file as portable JSON (one hash per line), for use by other programs")
+            .display_order(get_ordering(EXPORT_HASHES)),
+    );
+
+    clap_app = clap_app.arg(
+        clap::Arg::new(IMPORT_HASHES)
+            .long("import-hashes")
+            .value_parser(value_parser!(PathBuf))
+            .num_args(1)
+            .help("Read hashes from a file written by --export-hashes (or by another program), and search them along with the files given by --files. Imported hashes are not saved in the cache")
+            .display_order(get_ordering(IMPORT_HASHES)),
+    );
+
     #[cfg(all(target_family = "unix", feature = "gui_slint"))]
     #[allow(unused_mut)]
     let mut clap_app = clap_app.arg(
@@ -304,7 +502,22 @@ fn build_app() -> clap::Command {
     );
 
     #[cfg(all(target_family = "unix", feature = "gui_slint"))]
+    #[allow(unused_mut)]
     let mut clap_app = clap_app.arg(
+        clap::Arg::new(GUI_TRASH_LAYOUT)
+            .long("gui-trash-layout")
+            .hide(true)
+            .value_parser(value_parser!(TrashLayout))
+            .default_value("mirrored")
+            .num_args(1)
+            .help(
+                "For use in the gui: Whether trashed files are moved to their original path inside the trash directory, or to that path inside a folder for the day they were trashed on",
+            )
+            .display_order(get_ordering(GUI_TRASH_LAYOUT)),
+    );
+
+    #[cfg(all(target_family = "unix", feature = "gui_slint"))]
+    let clap_app = clap_app.arg(
         clap::Arg::new(GUI_MAX_THUMBS)
             .long("gui-max-thumbs")
             .hide(true)
@@ -313,10 +526,44 @@ fn build_app() -> clap::Command {
             .display_order(get_ordering(GUI_MAX_THUMBS)),
     );
 
+    #[cfg(all(target_family = "unix", feature = "gui_slint"))]
+    let clap_app = clap_app.arg(
+        clap::Arg::new(GUI_CACHE_SIZE)
+            .long("gui-cache-size")
+            .hide(true)
+            .value_parser(value_parser!(bytesize::ByteSize))
+            .default_value("512 MiB")
+            .num_args(1)
+            .help("For use in the gui: The most memory that rendered thumbnails are allowed to take up, such as \"2 GiB\"")
+            .display_order(get_ordering(GUI_CACHE_SIZE)),
+    );
+
+    #[cfg(all(target_family = "unix", feature = "gui_slint"))]
+    let clap_app = clap_app.arg(
+        clap::Arg::new(GUI_PLAYER_CMD)
+            .long("gui-player-cmd")
+            .hide(true)
+            .value_parser(parse_external_cmd)
+            .num_args(1)
+            .help("For use in the gui: The command that plays videos, such as \"mpv --loop {path}\". {path} is replaced by the path of the video, {dir} by its directory and {paths} by the paths of every video in the group, which the gui opens in one player if the command contains {paths} or no placeholder at all. Remembered for later sessions. Defaults to the desktop's video player")
+            .display_order(get_ordering(GUI_PLAYER_CMD)),
+    );
+
+    #[cfg(all(target_family = "unix", feature = "gui_slint"))]
+    let mut clap_app = clap_app.arg(
+        clap::Arg::new(GUI_BROWSER_CMD)
+            .long("gui-browser-cmd")
+            .hide(true)
+            .value_parser(parse_external_cmd)
+            .num_args(1)
+            .help("For use in the gui: The command that shows a video in a file manager, such as \"dolphin --select {path}\". Placeholders are as for --gui-player-cmd. Remembered for later sessions. Defaults to opening the video's directory with the desktop's file manager")
+            .display_order(get_ordering(GUI_BROWSER_CMD)),
+    );
+
     clap_app = clap_app.arg(
         clap::Arg::new(OUTPUT_KIND)
             .long("output")
-            .help("Whether to output groups of duplicates, a list of unique videos, or nothing")
+            .help("Whether to output groups of duplicates, a list of unique videos, a list of videos that could only be partly decoded (which are probably truncated or corrupt), or nothing")
             .value_parser(value_parser!(OutputKindRaw))
             .num_args(1)
             .display_order(get_ordering(OUTPUT_KIND)),
@@ -330,10 +577,18 @@ fn build_app() -> clap::Command {
             .display_order(get_ordering(CARTESIAN_PRODUCT)),
     );
 
+    clap_app = clap_app.arg(
+        clap::Arg::new(CONTAINMENT)
+            .long("containment")
+            .help("Before output, find which videos of each group were cut from a longer video in the same group (such as a clip which matches the video it was cut from because their starts are the same), by decoding both and looking for windows of the shorter video in the longer one. The longer video is labelled as containing the clip in json output, html reports and the gui, so that it can be kept. Much slower than the search itself")
+            .action(SetTrue)
+            .display_order(get_ordering(CONTAINMENT)),
+    );
+
     clap_app = clap_app.arg(
         clap::Arg::new(SORTED)
             .long("sort")
-            .help("Whether to sort results by the number of matching videos, or by how similar the videos are")
+            .help("Whether to sort results by the number of matching videos, how similar the videos are, their duration, their total size, how much space removing the duplicates would free, or when they were last modified")
             .value_parser(value_parser!(Sorting))
             .default_value("num-matches")
             .num_args(1)
@@ -359,6 +614,73 @@ fn build_app() -> clap::Command {
             .display_order(get_ordering(OUTPUT_THUMBS_DIR)),
     );
 
+    clap_app = clap_app.arg(
+        clap::Arg::new(REPORT_CSV)
+            .long("report-csv")
+            .value_parser(value_parser!(PathBuf))
+            .num_args(1)
+            .help(
+                "Write a CSV file with one row for each file in each group of duplicates. The \
+            file is replaced if it already exists",
+            )
+            .display_order(get_ordering(REPORT_CSV)),
+    );
+
+    clap_app = clap_app.arg(
+        clap::Arg::new(REPORT_SQLITE)
+            .long("report-sqlite")
+            .value_parser(value_parser!(PathBuf))
+            .num_args(1)
+            .help(
+                "Add the groups of duplicates to a SQLite database, creating it if it does not \
+            exist. Every run is added with its own run_id, so earlier runs are kept",
+            )
+            .display_order(get_ordering(REPORT_SQLITE)),
+    );
+
+    clap_app = clap_app.arg(
+        clap::Arg::new(REPORT_HTML)
+            .long("report-html")
+            .value_parser(value_parser!(PathBuf))
+            .num_args(1)
+            .help(
+                "Write a web page with thumbnails, sizes and durations of the files in each group \
+            of duplicates, which can be sorted and filtered. Everything is inside the one file, so \
+            it can be copied elsewhere and opened offline. The file is replaced if it already \
+            exists",
+            )
+            .display_order(get_ordering(REPORT_HTML)),
+    );
+
+    clap_app = clap_app.arg(
+        clap::Arg::new(REPORT_HTML_THUMBNAIL_BUDGET)
+            .long("report-html-thumbnail-budget")
+            .value_parser(value_parser!(bytesize::ByteSize))
+            .default_value("64 MiB")
+            .num_args(1)
+            .requires(REPORT_HTML)
+            .help(
+                "The most space that thumbnails can take up in the --report-html page, such as \
+            \"200 MiB\". Files after that are shown without a thumbnail",
+            )
+            .display_order(get_ordering(REPORT_HTML_THUMBNAIL_BUDGET)),
+    );
+
+    clap_app = clap_app.arg(
+        clap::Arg::new(QUARANTINE_DIR)
+            .long("quarantine-dir")
+            .value_parser(value_parser!(PathBuf))
+            .num_args(1)
+            .help(
+                "Hard link the files in each group of duplicates into a subdirectory of this \
+            directory, named by the group's id, for review. Symlinks or copies are made where hard \
+            links cannot be. The files themselves are not touched. Rerunning updates the \
+            directory, removing groups which are no longer found. It should not be inside a \
+            searched directory",
+            )
+            .display_order(get_ordering(QUARANTINE_DIR)),
+    );
+
     clap_app = clap_app.arg(
         clap::Arg::new(MATCH_DB_PATH)
             .long("matchdb")
@@ -377,6 +699,16 @@ fn build_app() -> clap::Command {
             .display_order(get_ordering(MATCH_DB_FIX_MOVED_FILES)),
     );
 
+    clap_app = clap_app.arg(
+        clap::Arg::new(MATCH_DB_MERGE)
+            .long("matchdb-merge")
+            .requires(MATCH_DB_PATH)
+            .value_parser(value_parser!(PathBuf))
+            .num_args(1)
+            .help("Add the confirmed matches and false positives of the match database in this directory to the one given by --matchdb. Conflicts between the two are logged and left for you to resolve. The database is backed up before it is saved")
+            .display_order(get_ordering(MATCH_DB_MERGE)),
+    );
+
     clap_app = clap_app.arg(
         clap::Arg::new(MATCH_DB_REMOVE_KNOWN_MATCHES)
             .long("matchdb-remove-known-matches")
@@ -427,7 +759,107 @@ fn build_app() -> clap::Command {
             .default_value(default_tol_string)
             .display_order(get_ordering(TOLERANCE))
             .num_args(1)
-            .value_parser(value_parser!(f64)));
+            .value_parser(parse_tolerance));
+
+    clap_app = clap_app.arg(
+        clap::Arg::new(SHORT_VIDEO_TOLERANCE)
+            .long("short-video-tolerance")
+            .help("Search tolerance used when comparing two videos that are both shorter than --hash-duration. Very short clips match each other easily, so this defaults to a stricter value than --tolerance")
+            .num_args(1)
+            .value_parser(parse_tolerance)
+            .display_order(get_ordering(SHORT_VIDEO_TOLERANCE)),
+    );
+
+    clap_app = clap_app.arg(
+        clap::Arg::new(GROUPING)
+            .long("grouping")
+            .help("How matching videos are grouped. 'centered' groups each video with every other video that matches it. 'components' also groups videos that are only linked through a chain of matches. 'cliques' only groups videos which all match each other, and a video may then be in more than one group. 'cliques:<fraction>' also merges overlapping cliques while every video in the group matches at least that fraction of the others")
+            .num_args(1)
+            .value_parser(parse_grouping)
+            .default_value("centered")
+            .display_order(get_ordering(GROUPING)),
+    );
+
+    clap_app = clap_app.arg(
+        clap::Arg::new(DURATION_GATE)
+            .long("duration-gate")
+            .help("Which videos are compared, by duration. 'strict' only compares videos whose durations are close. 'loose:<ratio>' also compares videos when the longer is at most <ratio> times the length of the shorter, such as a copy with a long intro or black padding. 'ignore' compares every pair of videos, which is much slower. Groups found with a loosened gate report the spread of their durations")
+            .num_args(1)
+            .value_parser(parse_duration_gate)
+            .default_value("strict")
+            .display_order(get_ordering(DURATION_GATE)),
+    );
+
+    clap_app = clap_app.arg(
+        clap::Arg::new(MIN_ACTIVE_AREA)
+            .long("min-active-area")
+            .help("Letterboxed videos hashed without --cropdetect can match unrelated videos with similar black borders. Never match two videos whose frames have amounts of non-black area more than 0.1 apart, or where either has less than FRACTION of its frame that is not black. Hashes created before this was recorded are never affected")
+            .value_name("FRACTION")
+            .num_args(1)
+            .value_parser(parse_tolerance)
+            .display_order(get_ordering(MIN_ACTIVE_AREA)),
+    );
+
+    clap_app = clap_app.arg(
+        clap::Arg::new(VERIFY_ZERO_DISTANCE)
+            .long("verify-zero-distance")
+            .help("Different videos which are very short or nearly blank (such as two clips of black frames) can have identical hashes. Decode a few frames of each pair of grouped videos whose distance is at most EPSILON (0.0 if not given) and compare them. Groups whose frames differ are never resolved automatically, by the kept file in reports or by the gui")
+            .value_name("EPSILON")
+            .num_args(0..=1)
+            .default_missing_value("0.0")
+            .value_parser(parse_tolerance)
+            .display_order(get_ordering(VERIFY_ZERO_DISTANCE)),
+    );
+
+    clap_app = clap_app.arg(
+        clap::Arg::new(VERIFY_MATCHES)
+            .long("verify-matches")
+            .help("Before output, decode a few frames from the same positions in each pair of grouped videos and compare them by their structural similarity (SSIM). Videos whose mean SSIM against the rest of their group is below THRESHOLD (0.6 if not given) are dropped from the group. Much slower than the search itself, but worth it before deleting files unattended")
+            .value_name("THRESHOLD")
+            .num_args(0..=1)
+            .default_missing_value("0.6")
+            .value_parser(parse_ssim_threshold)
+            .display_order(get_ordering(VERIFY_MATCHES)),
+    );
+
+    clap_app = clap_app.arg(
+        clap::Arg::new(REFINE)
+            .long("refine")
+            .help("Search in two passes. After the usual search, hash only the grouped videos again with bigger hashes of a longer window (at least 30 seconds), and search each group again with them, so that videos which only share their opening are split apart. The second hashes are kept in a cache of their own beside the cache file. Profiles from --config are searched at the tolerance given by --tolerance")
+            .action(SetTrue)
+            .conflicts_with_all([REF_PATHS, NO_UPDATE_CACHE])
+            .display_order(get_ordering(REFINE)),
+    );
+
+    clap_app = clap_app.arg(
+        clap::Arg::new(NO_GROUP_WITHIN)
+            .long("no-group-within")
+            .num_args(1)
+            .value_parser(value_parser!(PathBuf))
+            .action(Append)
+            .help("Never match two videos which are both inside this directory with each other, such as an archive whose duplicates are already known. They are still grouped if a video outside the directory matches both of them. May be given more than once")
+            .display_order(get_ordering(NO_GROUP_WITHIN)),
+    );
+
+    clap_app = clap_app.arg(
+        clap::Arg::new(MUST_CONTAIN)
+            .long("must-contain")
+            .num_args(1)
+            .value_parser(value_parser!(PathBuf))
+            .action(Append)
+            .help("Only report groups containing a video inside this directory. If given more than once, groups need a video inside any one of the directories")
+            .display_order(get_ordering(MUST_CONTAIN)),
+    );
+
+    clap_app = clap_app.arg(
+        clap::Arg::new(PROTECT)
+            .long("protect")
+            .num_args(1)
+            .value_parser(value_parser!(PathBuf))
+            .action(Append)
+            .help("Never remove videos inside this directory, such as the originals from a camera. They are still grouped, but the gui refuses to trash or move them, and groups where one would not be the file kept are left out of the potential savings and counted separately. May be given more than once")
+            .display_order(get_ordering(PROTECT)),
+    );
 
     clap_app = clap_app.arg(
         clap::Arg::new(CROPDETECT)
@@ -456,6 +888,26 @@ fn build_app() -> clap::Command {
             .display_order(get_ordering(DURATION)),
     );
 
+    clap_app = clap_app.arg(
+        clap::Arg::new(IGNORE_PIXEL_ASPECT_RATIO)
+            .long("ignore-pixel-aspect-ratio")
+            .help("Do not stretch videos with non-square pixels (such as anamorphic DVDs) to the shape they are shown at before detecting crops. Videos hashed with and without this option never match, so changing it rehashes every video")
+            .action(SetTrue)
+            .num_args(0)
+            .display_order(get_ordering(IGNORE_PIXEL_ASPECT_RATIO)),
+    );
+
+    clap_app = clap_app.arg(
+        clap::Arg::new(SPEED_VARIANTS)
+            .long("speed-variants")
+            .help("Also match copies which were sped up or slowed down by a common factor. Each video is hashed as if it were played at each of FACTORS (a comma separated list of 0.5, 1.25, 1.5 and 2, or all of them if not given) as well as at its own speed, and groups report the speed of each such copy. Changing the factors rehashes every video, and hashing takes longer for each factor")
+            .value_name("FACTORS")
+            .num_args(0..=1)
+            .default_missing_value("0.5,1.25,1.5,2")
+            .value_parser(parse_speed_factors)
+            .display_order(get_ordering(SPEED_VARIANTS)),
+    );
+
     clap_app = clap_app.arg(
         clap::Arg::new(NO_UPDATE_CACHE)
             .long("no-update-cache")
@@ -474,6 +926,15 @@ fn build_app() -> clap::Command {
             .display_order(get_ordering(ARGS_FILE)),
     );
 
+    clap_app = clap_app.arg(
+        clap::Arg::new(CONFIG_FILE)
+            .long("config")
+            .value_parser(value_parser!(PathBuf))
+            .num_args(1)
+            .help("Read search profiles from a TOML file. Each profile sets the tolerance and hashing options for the videos under its paths, and its videos are only matched against each other")
+            .display_order(get_ordering(CONFIG_FILE)),
+    );
+
     clap_app = clap_app.arg(
         clap::Arg::new(VERBOSITY_QUIET)
             .long("quiet")
@@ -492,9 +953,113 @@ fn build_app() -> clap::Command {
             .display_order(get_ordering(VERBOSITY_VERBOSE)),
     );
 
+    clap_app = clap_app.arg(
+        clap::Arg::new(SUMMARY)
+            .long("summary")
+            .help("When the run ends, print a summary of it to stderr (a JSON object with --output-format json): how many files were scanned, hashed and found in the cache, how many could not be hashed, how many groups were found, and how much space deleting the duplicates would save. Working out the savings probes the resolution of every duplicate")
+            .action(SetTrue)
+            .display_order(get_ordering(SUMMARY)),
+    );
+
+    clap_app = clap_app.arg(
+        clap::Arg::new(STRICT_EXIT_CODES)
+            .long("strict-exit-codes")
+            .help("Exit with 0 if no duplicates were found, 2 if duplicates were found, 3 if some files could not be hashed, and 1 on a fatal error. Without this flag the exit code is 0 unless there is a fatal error. Either way, a run stopped by Ctrl+C (or SIGTERM) exits with 130 once the cache has been saved")
+            .action(SetTrue)
+            .display_order(get_ordering(STRICT_EXIT_CODES)),
+    );
+
+    clap_app = clap_app
+        .subcommand(build_cache_cmd())
+        .subcommand_negates_reqs(true);
+
     clap_app
 }
 
+fn build_cache_cmd() -> clap::Command {
+    let path_arg = |name: &'static str, help: &'static str| {
+        clap::Arg::new(name)
+            .required(true)
+            .value_parser(value_parser!(PathBuf))
+            .help(help)
+    };
+
+    let mut cache_cmd = clap::Command::new(CACHE_CMD)
+        .about("Maintain the cache without searching or hashing anything. Give --cache-file to work on a cache other than the default one")
+        .subcommand_required(true);
+
+    cache_cmd = cache_cmd.arg(
+        clap::Arg::new(CACHE_DRY_RUN)
+            .long("dry-run")
+            .global(true)
+            .action(SetTrue)
+            .help("Print what would change, without changing anything. Exits with 4 if anything would change, so that scripts can check whether the cache needs maintenance"),
+    );
+
+    cache_cmd = cache_cmd.subcommand(clap::Command::new(CACHE_CMD_STATS).about(
+        "Print how many entries the cache has, how many are errors or stale, and its size on disk",
+    ));
+
+    cache_cmd = cache_cmd.subcommand(
+        clap::Command::new(CACHE_CMD_PRUNE_MISSING)
+            .about("Remove the entries of videos which no longer exist"),
+    );
+
+    cache_cmd = cache_cmd.subcommand(
+        clap::Command::new(CACHE_CMD_PRUNE_ERRORS)
+            .about("Remove the entries of videos which could not be hashed, so that they are tried again. Videos which were too short to hash are kept"),
+    );
+
+    cache_cmd = cache_cmd.subcommand(
+        clap::Command::new(CACHE_CMD_FORGET)
+            .about("Remove the entries of every video in a directory, as --cache-forget does")
+            .arg(path_arg("PREFIX", "The directory (or video) to forget")),
+    );
+
+    cache_cmd = cache_cmd.subcommand(
+        clap::Command::new(CACHE_CMD_MOVE)
+            .about("Record that a directory has been moved, so that the videos in it are not hashed again, as --cache-move does")
+            .arg(path_arg("OLD", "Where the directory was"))
+            .arg(path_arg("NEW", "Where the directory is now")),
+    );
+
+    cache_cmd = cache_cmd.subcommand(
+        clap::Command::new(CACHE_CMD_VERIFY)
+            .about("Print the entries whose videos have been deleted, or whose modification times or sizes have changed since they were hashed, without hashing them again"),
+    );
+
+    cache_cmd = cache_cmd.subcommand(
+        clap::Command::new(CACHE_CMD_EXPORT)
+            .about("Write every cached hash to a file as portable JSON, one hash per line, as --export-hashes does")
+            .arg(path_arg("FILE", "The file to write")),
+    );
+
+    cache_cmd = cache_cmd.subcommand(
+        clap::Command::new(CACHE_CMD_IMPORT)
+            .about("Save the hashes in a file of portable JSON hashes into the cache, as if their videos had just been hashed. Unlike --import-hashes the hashes are kept, but hashes of videos which are not on this filesystem are skipped")
+            .arg(path_arg("FILE", "The file to read")),
+    );
+
+    cache_cmd = cache_cmd.subcommand(
+        clap::Command::new(CACHE_CMD_MIGRATE_TO_SQLITE)
+            .about("Copy every entry of a bincode cache into a new sqlite cache, which is updated one entry at a time instead of being rewritten on every save. The bincode cache is left as it is; give the sqlite cache to --cache-file to use it")
+            .arg(path_arg("FILE", "The sqlite cache to write, ending in .sqlite, .sqlite3 or .db")),
+    );
+
+    cache_cmd = cache_cmd.subcommand(
+        clap::Command::new(CACHE_CMD_FIND_ID)
+            .about("Print the path of the video with an ID given by --video-ids. This is where the video was when the cache last saw it")
+            .arg(
+                clap::Arg::new("ID")
+                    .required(true)
+                    .value_parser(value_parser!(Uuid))
+                    .help("The ID of the video"),
+            ),
+    );
+
+    cache_cmd
+}
+
 pub fn parse_args() -> AppCfg {
     //capture the cwd once, to minimize the risk of working with two values if it is changed by the OS at runtime.
     let cwd = std::env::current_dir().expect("failed to extract cwd");
@@ -504,11 +1069,11 @@ pub fn parse_args() -> AppCfg {
     //take the arguments from the file instead.
     let args = get_args_from_cmdline_or_file();
 
-    let file_paths = match args.get_many::<PathBuf>(FILE_PATHS) {
+    let mut file_paths = match args.get_many::<PathBuf>(FILE_PATHS) {
         Some(paths) => paths
             .into_iter()
             .map(|p| absolutify_path(&cwd, p))
-            .collect(),
+            .collect::<Vec<_>>(),
         None => vec![],
     };
 
@@ -524,6 +1089,24 @@ pub fn parse_args() -> AppCfg {
         None => vec![],
     };
 
+    let (listed_paths, missing_listed_paths) =
+        partition_listed_paths(get_listed_paths(&args, &cwd), &exclude_file_paths);
+    if args.get_flag(FILES_FROM_STRICT) && !missing_listed_paths.is_empty() {
+        print_error_and_quit(eyre::Report::msg(format!(
+            "{} listed paths do not exist: {}",
+            missing_listed_paths.len(),
+            missing_listed_paths
+                .iter()
+                .map(|p| p.to_string_lossy())
+                .join(", ")
+        )));
+    }
+    for path in listed_paths {
+        if !file_paths.contains(&path) {
+            file_paths.push(path);
+        }
+    }
+
     let excl_exts = args
         .get_many::<OsString>(EXCL_EXTS)
         .unwrap()
@@ -531,20 +1114,82 @@ pub fn parse_args() -> AppCfg {
         .collect();
 
     let tolerance = *args
-        .get_one::<f64>(TOLERANCE)
-        .unwrap_or(&DEFAULT_SEARCH_TOLERANCE);
+        .get_one::<Tolerance>(TOLERANCE)
+        .unwrap_or(&Tolerance::DEFAULT);
+
+    let short_video_policy = match args.get_one::<Tolerance>(SHORT_VIDEO_TOLERANCE) {
+        Some(short_tolerance) => ShortVideoPolicy::Tolerance(short_tolerance.value()),
+        None => ShortVideoPolicy::default(),
+    };
+
+    let constraint_dirs = |arg| {
+        args.get_many::<PathBuf>(arg)
+            .into_iter()
+            .flatten()
+            .map(|p| absolutify_path(&cwd, p))
+    };
+    let constraints = constraint_dirs(NO_GROUP_WITHIN)
+        .map(ConstraintRule::NeverGroupWithin)
+        .chain(constraint_dirs(MUST_CONTAIN).map(ConstraintRule::OnlyReportGroupsContaining))
+        .collect();
+    let protected = constraint_dirs(PROTECT).collect();
+
+    let search_opts = SearchOptions {
+        short_video_policy,
+        constraints,
+        protected,
+        grouping: *args
+            .get_one::<GroupingMode>(GROUPING)
+            .expect("This argument has a default value"),
+        duration_gate: *args
+            .get_one::<DurationGate>(DURATION_GATE)
+            .expect("This argument has a default value"),
+        active_area: args
+            .get_one::<Tolerance>(MIN_ACTIVE_AREA)
+            .map(|fraction| ActiveAreaGuard::new(fraction.value())),
+        speed_variants: args.contains_id(SPEED_VARIANTS),
+        zero_distance_check: match args.get_one::<Tolerance>(VERIFY_ZERO_DISTANCE) {
+            Some(epsilon) => ZeroDistanceCheck::Verify {
+                epsilon: epsilon.value(),
+            },
+            None => ZeroDistanceCheck::Off,
+        },
+        verify_matches: args
+            .get_one::<f64>(VERIFY_MATCHES)
+            .map(|&threshold| VerifyOpts {
+                threshold,
+                ..VerifyOpts::default()
+            }),
+        ..SearchOptions::new(tolerance)
+    };
 
     let cache_cfg = CacheCfg {
         cache_path: args.get_one::<PathBuf>(CACHE_FILE).map(PathBuf::from),
         no_update_cache: args.get_flag(NO_UPDATE_CACHE),
+        export_hashes: args
+            .get_one::<PathBuf>(EXPORT_HASHES)
+            .map(|p| absolutify_path(&cwd, p)),
+        import_hashes: args
+            .get_one::<PathBuf>(IMPORT_HASHES)
+            .map(|p| absolutify_path(&cwd, p)),
+        forget_dirs: args
+            .get_many::<PathBuf>(CACHE_FORGET)
+            .unwrap_or_default()
+            .map(|p| absolutify_path(&cwd, p))
+            .collect(),
+        moved_dirs: args
+            .get_many::<PathBuf>(CACHE_MOVE)
+            .unwrap_or_default()
+            .map(|p| absolutify_path(&cwd, p))
+            .tuples()
+            .collect(),
+        video_ids: args.get_flag(VIDEO_IDS),
     };
 
     let hash_cfg = HashCfg {
-        cropdetect: match args.get_one::<CropdetectTypeArg>(CROPDETECT) {
-            None | Some(CropdetectTypeArg::None) => Cropdetect::None,
-            Some(CropdetectTypeArg::Letterbox) => Cropdetect::Letterbox,
-            Some(CropdetectTypeArg::Motion) => Cropdetect::Motion,
-        },
+        cropdetect: args
+            .get_one::<CropdetectTypeArg>(CROPDETECT)
+            .map_or(Cropdetect::None, |&arg| Cropdetect::from(arg)),
         skip_forward: *args
             .get_one::<f64>(SKIP_FORWARD)
             .unwrap_or(&CreationOptions::default().skip_forward_amount),
@@ -552,13 +1197,31 @@ pub fn parse_args() -> AppCfg {
         duration: *args
             .get_one::<f64>(DURATION)
             .unwrap_or(&CreationOptions::default().duration),
+        ignore_pixel_aspect_ratio: args.get_flag(IGNORE_PIXEL_ASPECT_RATIO),
+        speed_variants: args
+            .get_one::<SpeedFactors>(SPEED_VARIANTS)
+            .copied()
+            .unwrap_or_default(),
+    };
+
+    let profiles = match args.get_one::<PathBuf>(CONFIG_FILE) {
+        Some(config_path) => {
+            get_config_file_profiles(&absolutify_path(&cwd, config_path), &hash_cfg, tolerance)
+        }
+        None => vec![],
     };
 
     let dir_cfg = DirCfg {
         cand_dirs: file_paths,
+        missing_listed_paths,
         ref_dirs: ref_file_paths,
         excl_dirs: exclude_file_paths,
         excl_exts,
+        symlinks: SymlinkPolicy::from(
+            *args
+                .get_one::<SymlinkPolicyArg>(SYMLINKS)
+                .expect("This argument has a default value"),
+        ),
     };
 
     let verbosity = if args.get_flag(VERBOSITY_QUIET) {
@@ -582,26 +1245,59 @@ pub fn parse_args() -> AppCfg {
             None => ThumbOutputCfg::NoThumbs,
         };
 
+        let reports_cfg = [
+            args.get_one::<PathBuf>(REPORT_CSV)
+                .map(|p| ReportCfg::Csv(absolutify_path(&cwd, p))),
+            args.get_one::<PathBuf>(REPORT_SQLITE)
+                .map(|p| ReportCfg::Sqlite(absolutify_path(&cwd, p))),
+            args.get_one::<PathBuf>(REPORT_HTML)
+                .map(|p| ReportCfg::Html {
+                    path: absolutify_path(&cwd, p),
+                    thumbnail_budget: args
+                        .get_one::<bytesize::ByteSize>(REPORT_HTML_THUMBNAIL_BUDGET)
+                        .expect("This argument has a default value")
+                        .as_u64(),
+                }),
+        ]
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>();
+
+        let quarantine_dir = args
+            .get_one::<PathBuf>(QUARANTINE_DIR)
+            .map(|p| absolutify_path(&cwd, p));
+
         //Gui is an optional component.
         let gui_available = cfg!(all(target_family = "unix", feature = "gui_slint"));
         let gui_cfg = if gui_available && cfg!(feature = "gui_slint") && args.get_flag(GUI_SLINT) {
             GuiOutputCfg::GuiSlint {
                 sorting,
                 trash_path: args.get_one::<PathBuf>(GUI_TRASH_PATH).map(PathBuf::from),
+                trash_layout: args
+                    .get_one::<TrashLayout>(GUI_TRASH_LAYOUT)
+                    .copied()
+                    .unwrap_or_default(),
                 max_thumbs: args.get_one::<u64>(GUI_MAX_THUMBS).cloned(),
+                cache_budget: args
+                    .get_one::<bytesize::ByteSize>(GUI_CACHE_SIZE)
+                    .expect("This argument has a default value")
+                    .as_u64(),
+                player_cmd: args.get_one::<String>(GUI_PLAYER_CMD).cloned(),
+                browser_cmd: args.get_one::<String>(GUI_BROWSER_CMD).cloned(),
             }
         } else {
             GuiOutputCfg::NoGui
         };
 
-        let text_cfg = {
-            let format = *args
-                .get_one::<OutputFormat>(OUTPUT_FORMAT)
-                .expect("This argument has a default value");
+        let format = *args
+            .get_one::<OutputFormat>(OUTPUT_FORMAT)
+            .expect("This argument has a default value");
 
+        let text_cfg = {
             match args.get_one::<OutputKindRaw>(OUTPUT_KIND) {
                 Some(OutputKindRaw::NoOutput) => TextOutputCfg::NoOutput,
                 Some(OutputKindRaw::Unique) => TextOutputCfg::Unique(format),
+                Some(OutputKindRaw::Truncated) => TextOutputCfg::Truncated(format),
                 Some(OutputKindRaw::Dups) => TextOutputCfg::Dups { format, sorting },
 
                 //handle the default: If the user wrote no explicit argument for any type of output, then they probably
@@ -609,8 +1305,14 @@ pub fn parse_args() -> AppCfg {
                 None => {
                     let gui_not_requested = matches!(gui_cfg, GuiOutputCfg::NoGui);
                     let thumbs_not_requested = matches!(thumbs_cfg, ThumbOutputCfg::NoThumbs);
-
-                    if gui_not_requested && thumbs_not_requested {
+                    let reports_not_requested = reports_cfg.is_empty();
+                    let quarantine_not_requested = quarantine_dir.is_none();
+
+                    if gui_not_requested
+                        && thumbs_not_requested
+                        && reports_not_requested
+                        && quarantine_not_requested
+                    {
                         TextOutputCfg::Dups { format, sorting }
                     } else {
                         TextOutputCfg::NoOutput
@@ -621,17 +1323,23 @@ pub fn parse_args() -> AppCfg {
 
         OutputCfg {
             cartesian_product: args.get_flag(CARTESIAN_PRODUCT),
+            containment: args.get_flag(CONTAINMENT),
             text: text_cfg,
             thumbs: thumbs_cfg,
+            reports: reports_cfg,
+            quarantine_dir,
             gui: gui_cfg,
 
             verbosity,
+            summary: args.get_flag(SUMMARY),
+            summary_format: format,
         }
     };
 
     let matchdb_cfg = MatchDbCfg {
         db_path: args.get_one::<PathBuf>(MATCH_DB_PATH).map(PathBuf::from),
         fix_moved_files: args.get_flag(MATCH_DB_FIX_MOVED_FILES),
+        merge_from: args.get_one::<PathBuf>(MATCH_DB_MERGE).cloned(),
         remove_known_matches: args.get_flag(MATCH_DB_REMOVE_KNOWN_MATCHES),
         remove_falsepos: args.get_flag(MATCH_DB_REMOVE_FALSEPOS),
     };
@@ -648,16 +1356,60 @@ pub fn parse_args() -> AppCfg {
         show_missed_matches: args.get_flag(MATCH_DB_SHOW_MISSED_MATCHES),
 
         update_cache_only: args.get_flag(UPDATE_CACHE_ONLY),
+        cache_stats: args.get_flag(CACHE_STATS),
+        debug_pipeline: args.get_one::<PathBuf>(DEBUG_PIPELINE).cloned(),
+        cache_cmd: args
+            .subcommand_matches(CACHE_CMD)
+            .map(|cache_args| parse_cache_cmd(cache_args, &cwd)),
         reload_err_vids: args.get_flag(RELOAD_ERR_VIDS),
         reload_all_vids: args.get_flag(RELOAD_ALL_VIDS),
 
+        strict_exit_codes: args.get_flag(STRICT_EXIT_CODES),
+
         matchdb_cfg,
-        tolerance,
+        search_opts,
+        refine: args.get_flag(REFINE),
+        profiles,
     };
 
     ret
 }
 
+fn parse_cache_cmd(args: &clap::ArgMatches, cwd: &Path) -> CacheCmdCfg {
+    let (name, cmd_args) = args
+        .subcommand()
+        .expect("A cache command is required by the parser");
+    let path = |id: &str| {
+        let path = cmd_args
+            .get_one::<PathBuf>(id)
+            .expect("This argument is required");
+        absolutify_path(cwd, path)
+    };
+
+    let cmd = match name {
+        CACHE_CMD_STATS => CacheCmd::Stats,
+        CACHE_CMD_PRUNE_MISSING => CacheCmd::PruneMissing,
+        CACHE_CMD_PRUNE_ERRORS => CacheCmd::PruneErrors,
+        CACHE_CMD_FORGET => CacheCmd::Forget(path("PREFIX")),
+        CACHE_CMD_MOVE => CacheCmd::Move(path("OLD"), path("NEW")),
+        CACHE_CMD_VERIFY => CacheCmd::Verify,
+        CACHE_CMD_EXPORT => CacheCmd::Export(path("FILE")),
+        CACHE_CMD_IMPORT => CacheCmd::Import(path("FILE")),
+        CACHE_CMD_MIGRATE_TO_SQLITE => CacheCmd::MigrateToSqlite(path("FILE")),
+        CACHE_CMD_FIND_ID => CacheCmd::FindId(
+            *cmd_args
+                .get_one::<Uuid>("ID")
+                .expect("This argument is required"),
+        ),
+        _ => unreachable!("unknown cache command {name}"),
+    };
+
+    CacheCmdCfg {
+        cmd,
+        dry_run: cmd_args.get_flag(CACHE_DRY_RUN),
+    }
+}
+
 // Arguments are always first read from the command line, but if --args-file
 // is present, then arguments are actually located in a file on disk.
 // This fn obtains the args from the correct location.
@@ -697,6 +1449,130 @@ fn get_argsfile_args(argsfile_path: &Path) -> clap::ArgMatches {
     matches
 }
 
+// The paths in every list given by --files-from and --files-from0.
+fn get_listed_paths(args: &clap::ArgMatches, cwd: &Path) -> Vec<PathBuf> {
+    let lists = [(FILES_FROM, b'\n'), (FILES_FROM0, b'\0')]
+        .into_iter()
+        .flat_map(|(arg, delimiter)| {
+            args.get_many::<PathBuf>(arg)
+                .into_iter()
+                .flatten()
+                .map(move |list_path| (list_path, delimiter))
+        });
+
+    let mut ret = vec![];
+    for (list_path, delimiter) in lists {
+        let list = if list_path == Path::new("-") {
+            let mut list = vec![];
+            std::io::stdin().read_to_end(&mut list).map(|_| list)
+        } else {
+            std::fs::read(absolutify_path(cwd, list_path))
+        };
+
+        let list = list.unwrap_or_else(|e| {
+            print_error_and_quit(eyre::Report::msg(e).wrap_err(format!(
+                "Failed to read file list at location {}",
+                list_path.to_string_lossy()
+            )))
+        });
+        ret.extend(parse_file_list(&list, delimiter, cwd));
+    }
+    ret
+}
+
+// Paths in the config file are relative to the directory containing it.
+fn get_config_file_profiles(
+    config_path: &Path,
+    hash_cfg: &HashCfg,
+    tolerance: Tolerance,
+) -> Vec<SearchProfile> {
+    let base_dir = config_path.parent().unwrap_or(config_path);
+
+    std::fs::read_to_string(config_path)
+        .map_err(eyre::Report::msg)
+        .and_then(|text| {
+            parse_config_file(&text, base_dir, hash_cfg, tolerance).map_err(eyre::Report::msg)
+        })
+        .map_err(|e| {
+            e.wrap_err(format!(
+                "Failed to load config file at location {}",
+                config_path.to_string_lossy()
+            ))
+        })
+        .unwrap_or_else(|e| print_error_and_quit(e))
+}
+
+// Out of range tolerances are reported by clap, like any other invalid argument.
+fn parse_tolerance(arg: &str) -> Result<Tolerance, String> {
+    let tolerance = arg.parse::<f64>().map_err(|e| e.to_string())?;
+    Tolerance::new(tolerance).map_err(|e| e.to_string())
+}
+
+fn parse_ssim_threshold(arg: &str) -> Result<f64, String> {
+    let threshold = arg.parse::<f64>().map_err(|e| e.to_string())?;
+    if (-1.0..=1.0).contains(&threshold) {
+        Ok(threshold)
+    } else {
+        Err(format!(
+            "SSIM threshold must be between -1.0 and 1.0. Got {threshold}"
+        ))
+    }
+}
+
+fn parse_grouping(arg: &str) -> Result<GroupingMode, String> {
+    match arg.split_once(':') {
+        None if arg == "centered" => Ok(GroupingMode::Centered),
+        None if arg == "components" => Ok(GroupingMode::ConnectedComponents),
+        None if arg == "cliques" => Ok(GroupingMode::Cliques { min_mutual: 1.0 }),
+        Some(("cliques", fraction)) => {
+            let min_mutual = fraction.parse::<f64>().map_err(|e| e.to_string())?;
+            if (0.0..=1.0).contains(&min_mutual) {
+                Ok(GroupingMode::Cliques { min_mutual })
+            } else {
+                Err(format!(
+                    "The fraction must be between 0.0 and 1.0, but got {min_mutual}"
+                ))
+            }
+        }
+        _ => Err("Expected centered, components, cliques or cliques:<fraction>".to_string()),
+    }
+}
+
+fn parse_duration_gate(arg: &str) -> Result<DurationGate, String> {
+    match arg.split_once(':') {
+        None if arg == "strict" => Ok(DurationGate::Strict),
+        None if arg == "ignore" => Ok(DurationGate::Ignore),
+        Some(("loose", ratio)) => {
+            let max_ratio = ratio.parse::<f64>().map_err(|e| e.to_string())?;
+            if max_ratio.is_finite() && max_ratio >= 1.0 {
+                Ok(DurationGate::Loose { max_ratio })
+            } else {
+                Err(format!(
+                    "The ratio must be at least 1.0, but got {max_ratio}"
+                ))
+            }
+        }
+        _ => Err("Expected strict, ignore or loose:<ratio>".to_string()),
+    }
+}
+
+fn parse_speed_factors(arg: &str) -> Result<SpeedFactors, String> {
+    arg.split(',')
+        .map(|factor| factor.parse::<SpeedFactor>().map_err(|e| e.to_string()))
+        .collect()
+}
+
+// Commands are split into arguments again when the gui runs them, but are checked here so that
+// mistakes are reported straight away.
+#[cfg(all(target_family = "unix", feature = "gui_slint"))]
+fn parse_external_cmd(arg: &str) -> Result<String, String> {
+    match shell_words::split(arg) {
+        Ok(args) if args.is_empty() => Err("The command is empty".to_string()),
+        Ok(_) => Ok(arg.to_string()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
 fn absolutify_path(cwd: &Path, path: &Path) -> PathBuf {
     //get the absolute path if it is not absolute, by prepending the cwd.
     let path = if path.is_relative() {
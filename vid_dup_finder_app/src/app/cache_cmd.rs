@@ -0,0 +1,246 @@
+//! `vid_dup_finder cache <command>`: maintenance of the cache, without searching or hashing.
+//!
+//! Each command prints what it changes to stdout, one entry per line, and logs a count of the
+//! changes when it is done. With `--dry-run` the same lines are printed but nothing is changed,
+//! and the exit code is [`EXIT_CHANGES_NEEDED`] if anything would have been.
+
+use std::{
+    io::BufReader,
+    path::{Path, PathBuf},
+};
+
+use crate::app::search_output::{EXIT_CHANGES_NEEDED, EXIT_OK};
+use crate::app::*;
+use crate::video_hash_filesystem_cache::*;
+use vid_dup_finder_lib::CreationOptions;
+
+// Maintenance only loads and saves the cache, so it is saved once at the end.
+const CACHE_SAVE_THRESHOLD: u32 = u32::MAX;
+
+pub fn run_cache_cmd(cfg: &AppCfg, cache_cmd: &CacheCmdCfg) -> eyre::Result<i32> {
+    let cache_path = cfg
+        .cache_cfg
+        .cache_path
+        .clone()
+        .expect("This argument has a default value");
+    let dry_run = cache_cmd.dry_run;
+    let opts = cfg.hash_cfg.creation_options();
+
+    //migrating copies the cache file as it is, without opening it as the cache in use.
+    if let CacheCmd::MigrateToSqlite(sqlite_path) = &cache_cmd.cmd {
+        let num_changes = migrate_to_sqlite(&cache_path, sqlite_path, opts, dry_run)?;
+        return Ok(finish(dry_run, num_changes));
+    }
+
+    //hashes can be imported into a new cache, such as when restoring an export. A dry run does not
+    //create the cache, and would add every hash of a video on this filesystem.
+    let cache = match &cache_cmd.cmd {
+        CacheCmd::Import(path) if dry_run && !cache_path.exists() => {
+            let num_changes = import(None, path, dry_run)?;
+            return Ok(finish(dry_run, num_changes));
+        }
+        CacheCmd::Import(_) if !cache_path.exists() => VideoHashFilesystemCache::with_path_options(
+            CACHE_SAVE_THRESHOLD,
+            cache_path,
+            opts,
+            vec![],
+        )?,
+        _ => VideoHashFilesystemCache::open_existing(CACHE_SAVE_THRESHOLD, cache_path, opts)?,
+    };
+
+    let num_changes = match &cache_cmd.cmd {
+        CacheCmd::Stats => {
+            print_line(&cache.summary().to_string());
+            0
+        }
+        CacheCmd::PruneMissing => {
+            let paths = cache.missing_paths();
+            remove_entries(&cache, &paths, dry_run)?
+        }
+        CacheCmd::PruneErrors => {
+            let paths = cache.error_paths();
+            remove_entries(&cache, &paths, dry_run)?
+        }
+        CacheCmd::Forget(prefix) => {
+            let paths = cache.paths_under(prefix);
+            for path in &paths {
+                print_line(&format!("remove {}", path.display()));
+            }
+            if !dry_run {
+                cache.remove_under(prefix)?;
+            }
+            paths.len()
+        }
+        CacheCmd::Move(old, new) => {
+            let paths = cache.paths_under(old);
+            for path in &paths {
+                let moved = new.join(path.strip_prefix(old).unwrap_or(path));
+                print_line(&format!("move {} -> {}", path.display(), moved.display()));
+            }
+            if !dry_run {
+                cache.rename_prefix(old, new)?;
+            }
+            paths.len()
+        }
+        CacheCmd::Verify => {
+            let mismatches = cache.stat_mismatches()?;
+            for (path, mismatch) in &mismatches {
+                let description = match mismatch {
+                    StatMismatch::Missing => "missing",
+                    StatMismatch::Changed {
+                        mtime: true,
+                        size: true,
+                    } => "modification time and size changed",
+                    StatMismatch::Changed { mtime: true, .. } => "modification time changed",
+                    StatMismatch::Changed { .. } => "size changed",
+                };
+                print_line(&format!("{description}: {}", path.display()));
+            }
+            mismatches.len()
+        }
+        CacheCmd::Export(path) => export(&cache, path, dry_run)?,
+        CacheCmd::Import(path) => import(Some(&cache), path, dry_run)?,
+        CacheCmd::FindId(id) => {
+            let cache = cache.with_video_ids()?;
+            match cache.path_for_id(*id) {
+                Some(path) => print_line(&path.display().to_string()),
+                None => warn!("No video has the ID {id}"),
+            }
+            return Ok(EXIT_OK);
+        }
+        CacheCmd::MigrateToSqlite(_) => unreachable!("migrations do not open the cache"),
+    };
+
+    if !dry_run && num_changes > 0 {
+        cache.save()?;
+    }
+
+    Ok(finish(dry_run, num_changes))
+}
+
+// Logs the number of changes, returning the exit code.
+fn finish(dry_run: bool, num_changes: usize) -> i32 {
+    if dry_run {
+        info!("Dry run: {num_changes} changes would be made");
+        if num_changes > 0 {
+            return EXIT_CHANGES_NEEDED;
+        }
+    } else if num_changes > 0 {
+        info!("Made {num_changes} changes");
+    }
+    EXIT_OK
+}
+
+#[allow(clippy::print_stdout)]
+fn print_line(line: &str) {
+    println!("{}", line.trim_end());
+}
+
+fn remove_entries(
+    cache: &VideoHashFilesystemCache,
+    paths: &[PathBuf],
+    dry_run: bool,
+) -> Result<usize, VdfCacheError> {
+    for path in paths {
+        print_line(&format!("remove {}", path.display()));
+        if !dry_run {
+            cache.remove(path)?;
+        }
+    }
+    Ok(paths.len())
+}
+
+// Counts as one change if the file would be created or would differ.
+fn export(cache: &VideoHashFilesystemCache, path: &Path, dry_run: bool) -> eyre::Result<usize> {
+    let mut exported = vec![];
+    let num_hashes = cache.write_portable_hashes(&mut exported)?;
+    if std::fs::read(path).is_ok_and(|existing| existing == exported) {
+        info!("{} is up to date", path.display());
+        return Ok(0);
+    }
+
+    print_line(&format!("export {num_hashes} hashes to {}", path.display()));
+    if !dry_run {
+        std::fs::write(path, exported).map_err(|e| {
+            eyre::Report::new(e).wrap_err(format!("Failed to write {}", path.display()))
+        })?;
+    }
+    Ok(1)
+}
+
+// With no cache, finds what importing into a new cache would do.
+fn import(
+    cache: Option<&VideoHashFilesystemCache>,
+    path: &Path,
+    dry_run: bool,
+) -> eyre::Result<usize> {
+    let read = || -> eyre::Result<_> {
+        let file = BufReader::new(std::fs::File::open(path)?);
+        Ok(VideoHashFilesystemCache::parse_portable_hashes(file)?)
+    };
+    let hashes = read()
+        .map_err(|e| e.wrap_err(format!("Failed to import hashes from {}", path.display())))?;
+
+    let mut num_changes = 0;
+    for hash in hashes {
+        let src_path = hash.src_path().to_path_buf();
+        let action = match cache {
+            None if src_path.exists() => ImportAction::Add,
+            None => ImportAction::Missing,
+            Some(cache) if dry_run => cache.import_action(&hash),
+            Some(cache) => cache.import_entry(hash)?,
+        };
+        match action {
+            ImportAction::Add => print_line(&format!("add {}", src_path.display())),
+            ImportAction::Replace => print_line(&format!("replace {}", src_path.display())),
+            ImportAction::Unchanged => (),
+            ImportAction::Missing => warn!(
+                "Skipped {}, which is not on this filesystem",
+                src_path.display()
+            ),
+        }
+        if action.changes_cache() {
+            num_changes += 1;
+        }
+    }
+
+    Ok(num_changes)
+}
+
+// Each entry copied into the sqlite cache counts as one change.
+fn migrate_to_sqlite(
+    cache_path: &Path,
+    sqlite_path: &Path,
+    opts: CreationOptions,
+    dry_run: bool,
+) -> eyre::Result<usize> {
+    if CacheFormat::from_path(cache_path) == CacheFormat::Sqlite {
+        eyre::bail!("{} is already a sqlite cache", cache_path.display());
+    }
+    if CacheFormat::from_path(sqlite_path) != CacheFormat::Sqlite {
+        eyre::bail!(
+            "{} is not a sqlite cache. Sqlite caches end in .sqlite, .sqlite3 or .db",
+            sqlite_path.display()
+        );
+    }
+    if sqlite_path.exists() {
+        eyre::bail!("{} already exists", sqlite_path.display());
+    }
+
+    let num_entries = if dry_run {
+        let cache = VideoHashFilesystemCache::open_existing(
+            CACHE_SAVE_THRESHOLD,
+            cache_path.to_path_buf(),
+            opts,
+        )?;
+        cache.summary().entries
+    } else {
+        VideoHashFilesystemCache::migrate_to_sqlite(cache_path, sqlite_path)?
+    };
+
+    print_line(&format!(
+        "migrate {num_entries} entries to {}",
+        sqlite_path.display()
+    ));
+    Ok(num_entries)
+}
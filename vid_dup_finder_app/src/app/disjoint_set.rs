@@ -3,6 +3,7 @@ use std::{
     collections::{BTreeMap, BTreeSet, HashSet},
 };
 
+use itertools::Itertools;
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
@@ -13,6 +14,11 @@ where
     //Maps a path into the index of "entries" containing all duplicates
     map: BTreeMap<T, usize>,
     entries: Vec<BTreeSet<T>>,
+
+    //The pairs that were inserted, in both directions. The sets are the connected components of
+    //these pairs, which is what allows a set to be split again.
+    #[serde(default)]
+    pairs: BTreeMap<T, BTreeSet<T>>,
 }
 
 impl<T> DisjointSet<T>
@@ -20,6 +26,8 @@ where
     T: Ord + Clone + std::hash::Hash,
 {
     pub fn insert(&mut self, p1: T, p2: T) {
+        self.insert_pair(&p1, &p2);
+
         let (p1_idx, p2_idx) = (self.map.get(&p1).copied(), self.map.get(&p2).copied());
 
         //If we're lucky, both entries might already be in the same group. If so then
@@ -43,6 +51,66 @@ where
         }
     }
 
+    fn insert_pair(&mut self, p1: &T, p2: &T) {
+        if p1 != p2 {
+            self.pairs.entry(p1.clone()).or_default().insert(p2.clone());
+            self.pairs.entry(p2.clone()).or_default().insert(p1.clone());
+        }
+    }
+
+    //returns false if the pair was never inserted.
+    fn remove_pair<T1>(&mut self, i1: &T1, i2: &T1) -> bool
+    where
+        T: Borrow<T1>,
+        T1: Ord,
+        T1: ?Sized,
+    {
+        let mut remove_one_way = |from: &T1, to: &T1| {
+            let Some(neighbours) = self.pairs.get_mut(from) else {
+                return false;
+            };
+            let removed = neighbours.remove(to);
+            if neighbours.is_empty() {
+                self.pairs.remove(from);
+            }
+            removed
+        };
+
+        remove_one_way(i1, i2) & remove_one_way(i2, i1)
+    }
+
+    /// Undo the insertion of a pair. The set containing them is split into the connected
+    /// components of the pairs that remain, which may still put the two items together. Items
+    /// that are left without any pairs are removed. No other set is affected.
+    ///
+    /// Returns false if the pair was never inserted.
+    pub fn split<T1>(&mut self, i1: &T1, i2: &T1) -> bool
+    where
+        T: Borrow<T1>,
+        T1: Ord,
+        T1: ?Sized,
+    {
+        if !self.remove_pair(i1, i2) {
+            return false;
+        }
+
+        let idx = *self
+            .map
+            .get(i1)
+            .expect("an item with a pair is always in a set");
+        let (members, _) = self.remove_entry(idx);
+
+        //Every pair of a member is with another member, so this only rebuilds the one set.
+        for member in &members {
+            let neighbours = self.pairs.get::<T>(member).cloned().unwrap_or_default();
+            for neighbour in neighbours.into_iter().filter(|n| member < n) {
+                self.insert(member.clone(), neighbour);
+            }
+        }
+
+        true
+    }
+
     fn append_to_entry(&mut self, idx: usize, items: impl IntoIterator<Item = T>) {
         let entry = self.entries.get_mut(idx).unwrap();
         for item in items {
@@ -131,6 +199,16 @@ where
         self.entries.iter().map(|x| x.iter())
     }
 
+    /// Every pair that has been inserted and not split, once each, with the lesser item first.
+    pub fn all_pairs(&self) -> impl Iterator<Item = (&T, &T)> {
+        self.pairs.iter().flat_map(|(p1, neighbours)| {
+            neighbours
+                .iter()
+                .filter(move |p2| p1 < *p2)
+                .map(move |p2| (p1, p2))
+        })
+    }
+
     pub fn remove_item<T1>(&mut self, item: &T1)
     where
         T: Borrow<T1>,
@@ -140,6 +218,16 @@ where
         // let x = self.map.remove(&item).is_some();
         // debug_assert!(x);
 
+        //the rest of the set stays together, so chain the neighbours of the item together.
+        if let Some(neighbours) = self.pairs.remove(item) {
+            for neighbour in &neighbours {
+                self.remove_pair(Borrow::<T1>::borrow(neighbour), item);
+            }
+            for (n1, n2) in neighbours.iter().tuple_windows() {
+                self.insert_pair(n1, n2);
+            }
+        }
+
         let idxs_to_remove_path_from = self
             .entries
             .iter()
@@ -156,13 +244,26 @@ where
             if entry.len() <= 1 {
                 unreachable!("MatchMap should never have an entry with lengths less than 2.")
             } else if entry.len() == 2 {
-                self.remove_entry(idx);
+                let (removed, _) = self.remove_entry(idx);
+                for removed in &removed {
+                    self.pairs.remove::<T>(removed);
+                }
             } else {
                 assert!(entry.remove(item));
+                self.map.remove(item);
             }
         }
     }
 
+    pub fn contains<T1>(&self, item: &T1) -> bool
+    where
+        T: Borrow<T1>,
+        T1: Ord,
+        T1: ?Sized,
+    {
+        self.map.contains_key(item)
+    }
+
     pub fn contains_pair<T1>(&self, i1: &T1, i2: &T1) -> bool
     where
         T: Borrow<T1>,
@@ -315,6 +416,91 @@ mod test {
         assert!(!set.contains_pair(&1, &11));
     }
 
+    #[test]
+    pub fn test_split_chain() {
+        let mut set = DisjointSet::<&str>::default();
+        set.insert("a", "b");
+        set.insert("b", "c");
+        set.insert("x", "y");
+        assert!(set.contains_pair(&"a", &"c"));
+
+        assert!(set.split(&"c", &"b"));
+        check_entries_equal(&set, &["a", "b", "x", "y"]);
+        assert!(set.contains_pair(&"a", &"b"));
+        assert!(!set.contains_pair(&"b", &"c"));
+        assert!(!set.contains(&"c"));
+        assert!(set.contains_pair(&"x", &"y"));
+        assert!(set.all_sets().count() == 2);
+
+        //the pair has already gone.
+        assert!(!set.split(&"b", &"c"));
+        assert!(!set.split(&"a", &"x"));
+        check_entries_equal(&set, &["a", "b", "x", "y"]);
+    }
+
+    #[test]
+    pub fn test_split_into_two_sets() {
+        let mut set = DisjointSet::<usize>::default();
+        set.insert(1, 2);
+        set.insert(2, 3);
+        set.insert(3, 4);
+        set.insert(4, 5);
+
+        assert!(set.split(&3, &4));
+        check_entries_equal(&set, &[1, 2, 3, 4, 5]);
+        assert!(set.all_sets().count() == 2);
+        assert!(set.contains_pair(&1, &3));
+        assert!(set.contains_pair(&4, &5));
+        assert!(!set.contains_pair(&3, &4));
+    }
+
+    #[test]
+    pub fn test_split_keeps_items_joined_through_other_pairs() {
+        let mut set = DisjointSet::<usize>::default();
+        set.insert(1, 2);
+        set.insert(1, 3);
+        set.insert(2, 3);
+
+        assert!(set.split(&2, &3));
+        assert!(set.contains_pair(&2, &3));
+
+        assert!(set.split(&1, &3));
+        check_entries_equal(&set, &[1, 2]);
+    }
+
+    #[test]
+    pub fn test_split_after_removing_item() {
+        //removing the middle of a chain keeps the ends together, and they can still be split.
+        let mut set = DisjointSet::<usize>::default();
+        set.insert(1, 2);
+        set.insert(2, 3);
+        set.remove_item(&2);
+        assert!(set.contains_pair(&1, &3));
+
+        assert!(set.split(&1, &3));
+        check_entries_equal(&set, &[]);
+        set.dbg_check_integrity();
+    }
+
+    #[test]
+    pub fn test_all_pairs() {
+        let mut set = DisjointSet::<usize>::default();
+        set.insert(2, 1);
+        set.insert(2, 3);
+        set.insert(1, 2);
+        set.insert(11, 12);
+        assert_eq!(
+            set.all_pairs().collect::<Vec<_>>(),
+            vec![(&1, &2), (&2, &3), (&11, &12)]
+        );
+
+        set.split(&2, &3);
+        assert_eq!(
+            set.all_pairs().collect::<Vec<_>>(),
+            vec![(&1, &2), (&11, &12)]
+        );
+    }
+
     fn check_entries_equal<T>(set: &DisjointSet<T>, exp: &[T])
     where
         T: Ord + Clone + std::hash::Hash,
@@ -326,7 +512,7 @@ mod test {
         exp.sort();
 
         assert!(act.len() == exp.len());
-        for (a, e) in act.into_iter().zip(exp.into_iter()) {
+        for (a, e) in act.into_iter().zip(exp) {
             if a != e {
                 panic!()
             }
@@ -1,8 +1,12 @@
+use std::path::PathBuf;
+
 use thiserror::Error;
 use vid_dup_finder_lib::*;
 
 use crate::video_hash_filesystem_cache::*;
 
+use super::search_output::{QuarantineError, ReportError};
+
 use self::file_hash_filesystem_cache::FileContentCacheErrorKind;
 
 #[derive(Error, Debug)]
@@ -20,6 +24,14 @@ pub enum AppError {
     #[error("File content cache error: {0}")]
     ContentCacheError(#[from] FileContentCacheErrorKind),
 
+    /////////////////////////////////
+    //search output reports
+    #[error(transparent)]
+    ReportError(#[from] ReportError),
+
+    #[error(transparent)]
+    QuarantineError(#[from] QuarantineError),
+
     /////////////////////////////////
     //gui
     #[error("Failed to start the GUI")]
@@ -27,6 +39,25 @@ pub enum AppError {
     GuiStartError,
 }
 
+#[derive(Error, Debug)]
+pub enum ConfigError {
+    #[error("Failed to parse config file: {0}")]
+    Parse(#[from] toml::de::Error),
+
+    #[error("Profile {0:?} has no paths")]
+    NoPaths(String),
+
+    #[error("Profile {name:?} has tolerance {tolerance}, but it must be between 0.0 and 1.0")]
+    InvalidTolerance { name: String, tolerance: f64 },
+
+    #[error("{} is covered by both profile {first:?} and profile {second:?}", path.display())]
+    OverlappingProfiles {
+        first: String,
+        second: String,
+        path: PathBuf,
+    },
+}
+
 pub fn print_error_and_quit(e: eyre::Report) -> ! {
     #[allow(clippy::print_stderr)]
     let () = eprintln!("{:?}", e);
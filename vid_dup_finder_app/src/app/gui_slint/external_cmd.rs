@@ -0,0 +1,221 @@
+use std::{
+    ffi::OsString,
+    path::{Path, PathBuf},
+    process::{Child, Command},
+};
+
+use thiserror::Error;
+
+// Replaced by the path of the video.
+const PATH: &str = "{path}";
+// Replaced by the directory containing the video.
+const DIR: &str = "{dir}";
+// Replaced by the paths of several videos, one argument each.
+const PATHS: &str = "{paths}";
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum ExternalCmdError {
+    #[error("The command is empty")]
+    Empty,
+
+    #[error("Failed to split the command into arguments: {0}")]
+    Split(String),
+}
+
+/// A command that the GUI runs to play or browse to videos, such as `mpv --loop {path}`.
+///
+/// The command is split into arguments in the same way as a shell would split it. Within the
+/// arguments, `{path}` is replaced by the path of the video and `{dir}` by the directory that
+/// contains it, and an argument that is exactly `{paths}` is replaced by the path of every
+/// video to open at once. If the command has none of these, the paths are added to the end.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExternalCmd {
+    // The program is the first argument.
+    args: Vec<String>,
+}
+
+impl ExternalCmd {
+    pub fn parse(cmd: &str) -> Result<Self, ExternalCmdError> {
+        let args = shell_words::split(cmd).map_err(|e| ExternalCmdError::Split(e.to_string()))?;
+        if args.is_empty() {
+            return Err(ExternalCmdError::Empty);
+        }
+        Ok(Self { args })
+    }
+
+    /// The command that opens videos with whatever the desktop usually opens them with.
+    pub fn default_player() -> Self {
+        let args: &[&str] = if cfg!(target_os = "macos") {
+            &["open", PATH]
+        } else if cfg!(target_os = "windows") {
+            &["explorer", PATH]
+        } else {
+            &["xdg-open", PATH]
+        };
+        Self::from_args(args)
+    }
+
+    /// The command that shows a video in the desktop's file manager.
+    pub fn default_browser() -> Self {
+        let args: &[&str] = if cfg!(target_os = "macos") {
+            &["open", "-R", PATH]
+        } else if cfg!(target_os = "windows") {
+            &["explorer", "/select,{path}"]
+        } else {
+            //xdg-open would play the video itself, so open the directory instead.
+            &["xdg-open", DIR]
+        };
+        Self::from_args(args)
+    }
+
+    fn from_args(args: &[&str]) -> Self {
+        Self {
+            args: args.iter().map(ToString::to_string).collect(),
+        }
+    }
+
+    /// The command given by the user if there is one, otherwise the given default. A command
+    /// which cannot be parsed is logged and the default used instead.
+    pub fn or_default(cmd: Option<&str>, default: fn() -> Self) -> Self {
+        match cmd.map(Self::parse) {
+            Some(Ok(cmd)) => cmd,
+            Some(Err(e)) => {
+                warn!("Ignoring gui command {:?}: {e}", cmd.unwrap_or_default());
+                default()
+            }
+            None => default(),
+        }
+    }
+
+    pub fn program(&self) -> &str {
+        &self.args[0]
+    }
+
+    /// True if a single run of the command can open several videos.
+    pub fn opens_many(&self) -> bool {
+        let takes_one = |arg: &String| arg.contains(PATH) || arg.contains(DIR);
+        self.args.iter().any(|arg| arg == PATHS) || !self.args.iter().any(takes_one)
+    }
+
+    /// The arguments (not including the program) for one run of the command on the given
+    /// videos. If the command can only open one video at a time then only the first is used.
+    pub fn args(&self, paths: &[PathBuf]) -> Vec<OsString> {
+        let Some(first) = paths.first() else {
+            return vec![];
+        };
+        let paths = if self.opens_many() {
+            paths
+        } else {
+            std::slice::from_ref(first)
+        };
+        let dir = first.parent().unwrap_or(Path::new("."));
+
+        let mut substituted = false;
+        let mut ret = vec![];
+        for arg in &self.args[1..] {
+            if arg == PATHS {
+                ret.extend(paths.iter().map(|p| p.clone().into_os_string()));
+                substituted = true;
+            } else if arg.contains(PATH) || arg.contains(DIR) {
+                ret.push(match arg.as_str() {
+                    PATH => first.clone().into_os_string(),
+                    DIR => dir.as_os_str().to_owned(),
+                    //paths which are not utf8 are only passed exactly as whole arguments.
+                    _ => arg
+                        .replace(PATH, &first.to_string_lossy())
+                        .replace(DIR, &dir.to_string_lossy())
+                        .into(),
+                });
+                substituted = true;
+            } else {
+                ret.push(arg.into());
+            }
+        }
+        if !substituted {
+            ret.extend(paths.iter().map(|p| p.clone().into_os_string()));
+        }
+        ret
+    }
+
+    /// Start the command on the given videos.
+    pub fn spawn(&self, paths: &[PathBuf]) -> Result<Child, String> {
+        Command::new(self.program())
+            .args(self.args(paths))
+            .spawn()
+            .map_err(|e| format!("Failed to run {}: {e}", self.program()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn args(cmd: &str, paths: &[&str]) -> Vec<String> {
+        let paths = paths.iter().map(PathBuf::from).collect::<Vec<_>>();
+        ExternalCmd::parse(cmd)
+            .unwrap()
+            .args(&paths)
+            .into_iter()
+            .map(|arg| arg.into_string().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_placeholders_are_substituted() {
+        assert_eq!(
+            args("mpv --loop {path}", &["/vids/a b.mp4"]),
+            ["--loop", "/vids/a b.mp4"]
+        );
+        assert_eq!(
+            args("dolphin --select '{dir}/x' {path}", &["/vids/a.mp4"]),
+            ["--select", "/vids/x", "/vids/a.mp4"]
+        );
+        assert_eq!(
+            args("\"my player\" --file={path}", &["/vids/a.mp4"]),
+            ["--file=/vids/a.mp4"]
+        );
+        assert_eq!(
+            ExternalCmd::parse("\"my player\" {path}")
+                .unwrap()
+                .program(),
+            "my player"
+        );
+
+        //without placeholders the paths go at the end.
+        assert_eq!(args("mpv", &["/vids/a.mp4"]), ["/vids/a.mp4"]);
+    }
+
+    #[test]
+    fn test_several_videos() {
+        let paths = ["/vids/a.mp4", "/vids/b.mp4"];
+        assert_eq!(args("mpv", &paths), paths);
+        assert_eq!(
+            args("vlc {paths} --no-loop", &paths),
+            ["/vids/a.mp4", "/vids/b.mp4", "--no-loop"]
+        );
+
+        //only the first video is opened by commands that take one path.
+        let single = ExternalCmd::parse("xdg-open {path}").unwrap();
+        assert!(!single.opens_many());
+        assert_eq!(args("xdg-open {path}", &paths), ["/vids/a.mp4"]);
+        assert!(ExternalCmd::parse("mpv").unwrap().opens_many());
+        assert!(!ExternalCmd::default_player().opens_many());
+    }
+
+    #[test]
+    fn test_invalid_commands() {
+        assert_eq!(ExternalCmd::parse("  "), Err(ExternalCmdError::Empty));
+        assert!(matches!(
+            ExternalCmd::parse("mpv 'unclosed"),
+            Err(ExternalCmdError::Split(_))
+        ));
+        assert_eq!(
+            ExternalCmd::or_default(Some("mpv 'unclosed"), ExternalCmd::default_browser),
+            ExternalCmd::default_browser()
+        );
+        assert_eq!(
+            ExternalCmd::or_default(None, ExternalCmd::default_player),
+            ExternalCmd::default_player()
+        );
+    }
+}
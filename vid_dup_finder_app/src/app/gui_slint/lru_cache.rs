@@ -1,13 +1,13 @@
 use std::{
-    collections::{HashMap, VecDeque},
-    num::{NonZero, NonZeroU32},
+    collections::HashMap,
+    num::NonZeroU32,
     os::unix::fs::MetadataExt,
     sync::Arc,
     thread::{self, JoinHandle},
     time::Duration,
 };
 
-use crossbeam_channel::{Receiver, Select, Sender};
+use crossbeam_channel::{Receiver, Select, SendError, Sender, TryRecvError, TrySendError};
 use ffmpeg_gst_wrapper::FrameReadCfgTrait;
 use image::{
     buffer::ConvertBuffer,
@@ -19,44 +19,95 @@ use parking_lot::Mutex;
 use rayon::prelude::*;
 use slint::SharedPixelBuffer;
 
-use crate::app::ResolutionThunk;
+use crate::{
+    app::{ResolutionThunk, WinningStats},
+    video_hash_filesystem_cache::VideoHashFilesystemCache,
+};
 
-use super::{prerender, CacheEntry, GuiCmd, GuiRsp, SlintImage};
+use super::{
+    prerender::{self, PrerenderRsp},
+    prerender_queue::PrerenderQueue,
+    CacheEntry, GuiCmd, GuiRsp, SlintImage,
+};
+
+// The rendered thumbnails of recently viewed groups. Thumbnails of big groups at high zoom can be
+// tens of megabytes each, so the cache is limited by the total size of the images it holds rather
+// than by the number of entries. The least recently used entries are evicted first.
+struct Cache {
+    imgs: LruCache<CacheEntry, Vec<SlintImage>>,
+    budget: u64,
+    used: u64,
+}
+
+fn imgs_bytes(imgs: &[SlintImage]) -> u64 {
+    imgs.iter()
+        .map(|img| u64::from(img.width()) * u64::from(img.height()) * 3)
+        .sum()
+}
 
-struct Cache(lru::LruCache<CacheEntry, Vec<SharedPixelBuffer<slint::Rgb8Pixel>>>);
 impl Cache {
-    pub fn new() -> Self {
-        let size = NonZero::new(50).unwrap();
-        Self(LruCache::new(size))
+    pub fn new(budget: u64) -> Self {
+        Self {
+            imgs: LruCache::unbounded(),
+            budget,
+            used: 0,
+        }
     }
 
     pub fn clear_thumbs(&mut self, thunk: &ResolutionThunk) {
         let matching_entries = self
-            .0
+            .imgs
             .iter()
             .filter(|entry| entry.0.thunk == *thunk)
             .map(|(entry, _imgs)| entry.clone())
             .collect::<Vec<_>>();
 
         for e in matching_entries {
-            let _ = self.0.pop(&e);
+            if let Some(imgs) = self.imgs.pop(&e) {
+                self.used -= imgs_bytes(&imgs);
+            }
         }
     }
 
     pub fn promote(&mut self, entry: &CacheEntry) {
-        self.0.promote(entry)
+        self.imgs.promote(entry)
     }
 
     pub fn contains(&self, entry: &CacheEntry) -> bool {
-        self.0.contains(entry)
+        self.imgs.contains(entry)
     }
 
     pub fn get(&mut self, entry: &CacheEntry) -> Option<&Vec<SlintImage>> {
-        self.0.get(entry)
+        self.imgs.get(entry)
     }
 
+    // The entry that was put last is never evicted, even if it is bigger than the whole budget,
+    // as it is about to be shown.
     pub fn put(&mut self, entry: CacheEntry, imgs: Vec<SlintImage>) {
-        let _ = self.0.put(entry, imgs);
+        self.used += imgs_bytes(&imgs);
+        if let Some(old_imgs) = self.imgs.put(entry, imgs) {
+            self.used -= imgs_bytes(&old_imgs);
+        }
+
+        while self.used > self.budget && self.imgs.len() > 1 {
+            let (_entry, imgs) = self.imgs.pop_lru().expect("the cache is not empty");
+            self.used -= imgs_bytes(&imgs);
+        }
+    }
+
+    pub fn used_bytes(&self) -> u64 {
+        self.used
+    }
+
+    pub fn budget_bytes(&self) -> u64 {
+        self.budget
+    }
+
+    fn usage_rsp(&self) -> GuiRsp {
+        GuiRsp::CacheUsage {
+            used: self.used_bytes(),
+            budget: self.budget_bytes(),
+        }
     }
 }
 
@@ -67,6 +118,7 @@ type CannySizeCache = HashMap<CacheEntry, Vec<u64>>;
 type FileSizeCache = HashMap<CacheEntry, Vec<u64>>;
 type ResolutionCache = HashMap<CacheEntry, Vec<(u32, u32)>>;
 type DurationCache = HashMap<CacheEntry, Vec<Duration>>;
+type WinningStatsCache = HashMap<CacheEntry, Vec<WinningStats>>;
 // type LenCache = HashMap<CacheEntry, Vec<u64>>;
 
 #[allow(clippy::type_complexity)]
@@ -74,17 +126,21 @@ type DurationCache = HashMap<CacheEntry, Vec<Duration>>;
 pub fn start_cache_thread(
     gui_cmd_rx: Receiver<GuiCmd>,
     gui_rsp_tx: Sender<GuiRsp>,
+    hash_cache: Arc<VideoHashFilesystemCache>,
+    cache_budget: u64,
 ) -> JoinHandle<()> {
     let thread_main = move || {
-        let (_gen_thread, gen_cmd_tx, gen_rsp_rx) = prerender::start_prerender_thread();
+        let (_gen_thread, gen_cmd_tx, gen_rsp_rx) =
+            prerender::start_prerender_thread(hash_cache.clone());
 
         ///////////////////////////////////////////////////////////////
         // need to merge commands from the gui and responses from the worker
         enum MergedMsg {
             FromGui(GuiCmd),
-            FromGen((CacheEntry, Vec<RgbImage>)),
+            FromGen(PrerenderRsp),
         }
 
+        //A channel is also ready once its other end has gone, which stops the thread.
         let mut get_next_msg = {
             let mut inputs_merge_rx = Select::new();
             let gen_rx_idx = inputs_merge_rx.recv(&gen_rsp_rx);
@@ -92,16 +148,24 @@ pub fn start_cache_thread(
 
             let gen_rsp_rx = gen_rsp_rx.clone();
             let gui_cmd_rx = gui_cmd_rx.clone();
-            move || match inputs_merge_rx.ready() {
-                i if i == gen_rx_idx => MergedMsg::FromGen(gen_rsp_rx.try_recv().unwrap()),
-                i if i == cache_rx_idx => MergedMsg::FromGui(gui_cmd_rx.try_recv().unwrap()),
-                _ => unreachable!(),
+            move || loop {
+                let msg = match inputs_merge_rx.ready() {
+                    i if i == gen_rx_idx => gen_rsp_rx.try_recv().map(MergedMsg::FromGen),
+                    i if i == cache_rx_idx => gui_cmd_rx.try_recv().map(MergedMsg::FromGui),
+                    _ => unreachable!(),
+                };
+                match msg {
+                    Ok(msg) => return Ok(msg),
+                    //ready() can return spuriously.
+                    Err(TryRecvError::Empty) => continue,
+                    Err(TryRecvError::Disconnected) => return Err(Disconnected),
+                }
             }
         };
 
-        let gen_q = Arc::new(Mutex::new(VecDeque::new()));
+        let mut gen_q = PrerenderQueue::new(gui_rsp_tx.clone());
         let mut fetch_req = None;
-        let mut cache = Cache::new();
+        let mut cache = Cache::new(cache_budget);
         let png_size_cache = Arc::new(Mutex::new(PngSizeCache::new()));
         let avif_size_cache = Arc::new(Mutex::new(AvifSizeCache::new()));
         let jpg_size_cache = Arc::new(Mutex::new(JpgSizeCache::new()));
@@ -109,364 +173,422 @@ pub fn start_cache_thread(
         let file_size_cache = Arc::new(Mutex::new(FileSizeCache::new()));
         let duration_cache = Arc::new(Mutex::new(DurationCache::new()));
         let resolution_cache = Arc::new(Mutex::new(ResolutionCache::new()));
+        let winning_stats_cache = Arc::new(Mutex::new(WinningStatsCache::new()));
         let mut stats_en = true;
         // let vid_len_cache = Arc::new(Mutex::new(LenCache::new()));
 
-        loop {
-            use GuiCmd::*;
-            use MergedMsg::*;
-
-            let next_msg = get_next_msg();
-            let mut gen_q = gen_q.lock();
-
-            match next_msg {
-                FromGui(Clear(thunk)) => cache.clear_thumbs(&thunk),
-                FromGui(Generate(entry)) => {
-                    //bump to the top of list if exists in cache
-                    if cache.contains(&entry) {
-                        cache.promote(&entry)
-                    } else {
-                        gen_q.push_back(entry);
-                        gui_rsp_tx.send(GuiRsp::IncQLen).unwrap()
+        let mut run = || -> Result<(), Disconnected> {
+            loop {
+                use GuiCmd::*;
+                use MergedMsg::*;
+
+                let next_msg = get_next_msg()?;
+
+                match next_msg {
+                    FromGui(Clear(thunk)) => {
+                        cache.clear_thumbs(&thunk);
+                        gui_rsp_tx.send(cache.usage_rsp())?;
                     }
-                }
-                FromGui(Fetch(entry)) => {
-                    for _ in 0..gen_q.len() {
-                        gui_rsp_tx.send(GuiRsp::DecQLen).unwrap();
+                    FromGui(Generate(entry, generation)) => {
+                        //bump to the top of list if exists in cache
+                        if cache.contains(&entry) {
+                            cache.promote(&entry)
+                        } else {
+                            gen_q.generate(entry, generation);
+                        }
                     }
-                    gen_q.clear();
-                    if let Some(imgs) = cache.get(&entry) {
-                        fetch_req = None;
-                        gui_rsp_tx
-                            .send(GuiRsp::Fetched((entry.clone(), imgs.clone())))
-                            .unwrap();
-                    } else {
-                        fetch_req = Some(entry.clone());
-                        gui_rsp_tx.send(GuiRsp::Wait).unwrap();
-                        gen_q.push_back(entry);
-                        gui_rsp_tx.send(GuiRsp::IncQLen).unwrap()
+                    FromGui(Fetch(entry, generation)) => {
+                        gen_q.fetch(generation);
+                        if let Some(imgs) = cache.get(&entry) {
+                            fetch_req = None;
+                            gui_rsp_tx.send(GuiRsp::Fetched((entry.clone(), imgs.clone())))?;
+                        } else {
+                            fetch_req = Some(entry.clone());
+                            gui_rsp_tx.send(GuiRsp::Wait)?;
+                            gen_q.generate(entry, generation);
+                        }
                     }
-                }
 
-                FromGui(FetchPngSize(entry)) => {
-                    if let Some(sizes) = png_size_cache.lock().get(&entry) {
-                        gui_rsp_tx
-                            .send(GuiRsp::PngSize(entry.clone(), sizes.clone()))
-                            .unwrap();
+                    FromGui(FetchPngSize(entry)) => {
+                        if let Some(sizes) = png_size_cache.lock().get(&entry) {
+                            gui_rsp_tx.send(GuiRsp::PngSize(entry.clone(), sizes.clone()))?;
+                        }
                     }
-                }
 
-                FromGui(FetchAvifSize(entry)) => {
-                    if let Some(sizes) = avif_size_cache.lock().get(&entry) {
-                        gui_rsp_tx
-                            .send(GuiRsp::AvifSize(entry.clone(), sizes.clone()))
-                            .unwrap();
+                    FromGui(FetchAvifSize(entry)) => {
+                        if let Some(sizes) = avif_size_cache.lock().get(&entry) {
+                            gui_rsp_tx.send(GuiRsp::AvifSize(entry.clone(), sizes.clone()))?;
+                        }
                     }
-                }
 
-                FromGui(FetchJpgSize(entry)) => {
-                    if let Some(sizes) = jpg_size_cache.lock().get(&entry) {
-                        gui_rsp_tx
-                            .send(GuiRsp::JpgSize(entry.clone(), sizes.clone()))
-                            .unwrap();
+                    FromGui(FetchJpgSize(entry)) => {
+                        if let Some(sizes) = jpg_size_cache.lock().get(&entry) {
+                            gui_rsp_tx.send(GuiRsp::JpgSize(entry.clone(), sizes.clone()))?;
+                        }
                     }
-                }
 
-                FromGui(FetchCannySize(entry)) => {
-                    if let Some(sizes) = canny_size_cache.lock().get(&entry) {
-                        gui_rsp_tx
-                            .send(GuiRsp::CannySize(entry.clone(), sizes.clone()))
-                            .unwrap();
+                    FromGui(FetchCannySize(entry)) => {
+                        if let Some(sizes) = canny_size_cache.lock().get(&entry) {
+                            gui_rsp_tx.send(GuiRsp::CannySize(entry.clone(), sizes.clone()))?;
+                        }
                     }
-                }
 
-                FromGui(FetchFileSize(entry)) => {
-                    if let Some(sizes) = file_size_cache.lock().get(&entry) {
-                        gui_rsp_tx
-                            .send(GuiRsp::FileSize(entry.clone(), sizes.clone()))
-                            .unwrap();
+                    FromGui(FetchFileSize(entry)) => {
+                        if let Some(sizes) = file_size_cache.lock().get(&entry) {
+                            gui_rsp_tx.send(GuiRsp::FileSize(entry.clone(), sizes.clone()))?;
+                        }
                     }
-                }
 
-                FromGui(FetchVidDuration(entry)) => {
-                    if let Some(sizes) = duration_cache.lock().get(&entry) {
-                        gui_rsp_tx
-                            .send(GuiRsp::VidDuration(entry.clone(), sizes.clone()))
-                            .unwrap();
+                    FromGui(FetchVidDuration(entry)) => {
+                        if let Some(sizes) = duration_cache.lock().get(&entry) {
+                            gui_rsp_tx.send(GuiRsp::VidDuration(entry.clone(), sizes.clone()))?;
+                        }
                     }
-                }
 
-                FromGui(FetchVidResolution(entry)) => {
-                    if let Some(resolutions) = resolution_cache.lock().get(&entry) {
-                        gui_rsp_tx
-                            .send(GuiRsp::VidResolution(entry.clone(), resolutions.clone()))
-                            .unwrap();
+                    FromGui(FetchVidResolution(entry)) => {
+                        if let Some(resolutions) = resolution_cache.lock().get(&entry) {
+                            gui_rsp_tx
+                                .send(GuiRsp::VidResolution(entry.clone(), resolutions.clone()))?;
+                        }
                     }
-                }
-
-                FromGui(StatsEn(val)) => {
-                    stats_en = val;
-                }
-
-                FromGen((entry, imgs)) => {
-                    gui_rsp_tx.send(GuiRsp::DecQLen).unwrap();
 
-                    let imgbufs_slint = imgs
-                        .iter()
-                        .map(|img| {
-                            slint::SharedPixelBuffer::<slint::Rgb8Pixel>::clone_from_slice(
-                                img.as_raw(),
-                                img.width(),
-                                img.height(),
-                            )
-                        })
-                        .collect::<Vec<_>>();
-
-                    cache.put(entry.clone(), imgbufs_slint.clone());
-
-                    if let Some(ref fetch_req_val) = fetch_req {
-                        if *fetch_req_val == entry {
-                            fetch_req = None;
-                            gui_rsp_tx
-                                .send(GuiRsp::Fetched((entry.clone(), imgbufs_slint.clone())))
-                                .unwrap();
+                    FromGui(FetchWinningStats(entry)) => {
+                        if let Some(stats) = winning_stats_cache.lock().get(&entry) {
+                            gui_rsp_tx.send(GuiRsp::WinningStats(entry.clone(), stats.clone()))?;
                         }
                     }
 
-                    if stats_en {
-                        thread::spawn({
-                            let entry = entry.clone();
-                            let png_size_cache = png_size_cache.clone();
+                    FromGui(FetchHashDiff(entry, idx, ref_idx)) => {
+                        spawn_sender({
+                            let hash_cache = hash_cache.clone();
                             let gui_rsp_tx = gui_rsp_tx.clone();
-                            let imgs = imgs.clone();
                             move || {
-                                gui_rsp_tx.send(GuiRsp::IncQQueue).unwrap();
-                                thread_priority::set_current_thread_priority(
-                                    thread_priority::ThreadPriority::Min,
-                                )
-                                .unwrap();
-                                // loop {
-                                //     thread::sleep(Duration::from_millis(100));
-                                //     let gen_q = gen_q.lock();
-                                //     if gen_q.len() < 1 {
-                                //         break;
-                                //     }
-                                // }
-                                gui_rsp_tx.send(GuiRsp::IncPngQueue).unwrap();
-
-                                let png_sizes =
-                                    imgs.par_iter().map(calc_png_size).collect::<Vec<_>>();
-
-                                let _ = png_size_cache
-                                    .lock()
-                                    .insert(entry.clone(), png_sizes.clone());
-
-                                gui_rsp_tx.send(GuiRsp::PngSize(entry, png_sizes)).unwrap();
-                                gui_rsp_tx.send(GuiRsp::DecPngQueue).unwrap();
+                                if let Some(img) = calc_hash_diff(&hash_cache, &entry, idx, ref_idx)
+                                {
+                                    gui_rsp_tx.send(GuiRsp::HashDiff(entry, idx, img))?;
+                                }
+                                Ok(())
                             }
                         });
+                    }
 
-                        thread::spawn({
-                            let entry = entry.clone();
-                            let jpg_size_cache = jpg_size_cache.clone();
-                            let gui_rsp_tx = gui_rsp_tx.clone();
-                            let imgs = imgs.clone();
-                            move || {
-                                gui_rsp_tx.send(GuiRsp::IncQQueue).unwrap();
-                                thread_priority::set_current_thread_priority(
-                                    thread_priority::ThreadPriority::Min,
-                                )
-                                .unwrap();
-                                // loop {
-                                //     thread::sleep(Duration::from_millis(100));
-                                //     let gen_q = gen_q.lock();
-                                //     if gen_q.len() < 1 {
-                                //         break;
-                                //     }
-                                // }
-                                gui_rsp_tx.send(GuiRsp::IncJpgQueue).unwrap();
-
-                                let jpg_sizes =
-                                    imgs.par_iter().map(calc_jpg_size).collect::<Vec<_>>();
-
-                                let _ = jpg_size_cache
-                                    .lock()
-                                    .insert(entry.clone(), jpg_sizes.clone());
+                    FromGui(StatsEn(val)) => {
+                        stats_en = val;
+                    }
 
-                                gui_rsp_tx.send(GuiRsp::JpgSize(entry, jpg_sizes)).unwrap();
-                                gui_rsp_tx.send(GuiRsp::DecJpgQueue).unwrap();
-                            }
-                        });
+                    FromGen((entry, None)) => gen_q.finished(&entry, false),
 
-                        thread::spawn({
-                            let entry = entry.clone();
-                            let avif_size_cache = avif_size_cache.clone();
-                            let gui_rsp_tx = gui_rsp_tx.clone();
-                            let imgs = imgs.clone();
-                            move || {
-                                thread_priority::set_current_thread_priority(
-                                    thread_priority::ThreadPriority::Min,
+                    FromGen((entry, Some(imgs))) => {
+                        gen_q.finished(&entry, true);
+
+                        let imgbufs_slint = imgs
+                            .iter()
+                            .map(|img| {
+                                slint::SharedPixelBuffer::<slint::Rgb8Pixel>::clone_from_slice(
+                                    img.as_raw(),
+                                    img.width(),
+                                    img.height(),
                                 )
-                                .unwrap();
-                                gui_rsp_tx.send(GuiRsp::IncQQueue).unwrap();
-                                // loop {
-                                //     thread::sleep(Duration::from_millis(100));
-                                //     let gen_q = gen_q.lock();
-                                //     if gen_q.len() < 1 {
-                                //         break;
-                                //     }
-                                // }
-                                gui_rsp_tx.send(GuiRsp::IncAvifQueue).unwrap();
-
-                                let avif_sizes =
-                                    imgs.par_iter().map(calc_avif_size).collect::<Vec<_>>();
-
-                                let _ = avif_size_cache
-                                    .lock()
-                                    .insert(entry.clone(), avif_sizes.clone());
+                            })
+                            .collect::<Vec<_>>();
+
+                        cache.put(entry.clone(), imgbufs_slint.clone());
+                        gui_rsp_tx.send(cache.usage_rsp())?;
+
+                        if let Some(ref fetch_req_val) = fetch_req {
+                            if *fetch_req_val == entry {
+                                fetch_req = None;
+                                gui_rsp_tx.send(GuiRsp::Fetched((
+                                    entry.clone(),
+                                    imgbufs_slint.clone(),
+                                )))?;
+                            }
+                        }
 
-                                gui_rsp_tx
-                                    .send(GuiRsp::AvifSize(entry, avif_sizes))
+                        if stats_en {
+                            //decodes a few frames of every entry, so is only done once per group.
+                            spawn_sender({
+                                let entry = entry.clone();
+                                let winning_stats_cache = winning_stats_cache.clone();
+                                let gui_rsp_tx = gui_rsp_tx.clone();
+                                move || {
+                                    let best_pick = entry.thunk.best_pick().ok();
+                                    let stats = entry
+                                        .thunk
+                                        .entries()
+                                        .iter()
+                                        .map(|p| {
+                                            entry.thunk.calc_winning_stats(p, best_pick.as_ref())
+                                        })
+                                        .collect::<Vec<_>>();
+
+                                    let _ = winning_stats_cache
+                                        .lock()
+                                        .insert(entry.clone(), stats.clone());
+
+                                    gui_rsp_tx.send(GuiRsp::WinningStats(entry, stats))
+                                }
+                            });
+
+                            spawn_sender({
+                                let entry = entry.clone();
+                                let png_size_cache = png_size_cache.clone();
+                                let gui_rsp_tx = gui_rsp_tx.clone();
+                                let imgs = imgs.clone();
+                                move || {
+                                    gui_rsp_tx.send(GuiRsp::IncQQueue)?;
+                                    thread_priority::set_current_thread_priority(
+                                        thread_priority::ThreadPriority::Min,
+                                    )
                                     .unwrap();
-                                gui_rsp_tx.send(GuiRsp::DecAvifQueue).unwrap();
-                            }
-                        });
+                                    // loop {
+                                    //     thread::sleep(Duration::from_millis(100));
+                                    //     let gen_q = gen_q.lock();
+                                    //     if gen_q.len() < 1 {
+                                    //         break;
+                                    //     }
+                                    // }
+                                    gui_rsp_tx.send(GuiRsp::IncPngQueue)?;
+
+                                    let png_sizes =
+                                        imgs.par_iter().map(calc_png_size).collect::<Vec<_>>();
+
+                                    let _ = png_size_cache
+                                        .lock()
+                                        .insert(entry.clone(), png_sizes.clone());
+
+                                    gui_rsp_tx.send(GuiRsp::PngSize(entry, png_sizes))?;
+                                    gui_rsp_tx.send(GuiRsp::DecPngQueue)?;
+                                    Ok(())
+                                }
+                            });
+
+                            spawn_sender({
+                                let entry = entry.clone();
+                                let jpg_size_cache = jpg_size_cache.clone();
+                                let gui_rsp_tx = gui_rsp_tx.clone();
+                                let imgs = imgs.clone();
+                                move || {
+                                    gui_rsp_tx.send(GuiRsp::IncQQueue)?;
+                                    thread_priority::set_current_thread_priority(
+                                        thread_priority::ThreadPriority::Min,
+                                    )
+                                    .unwrap();
+                                    // loop {
+                                    //     thread::sleep(Duration::from_millis(100));
+                                    //     let gen_q = gen_q.lock();
+                                    //     if gen_q.len() < 1 {
+                                    //         break;
+                                    //     }
+                                    // }
+                                    gui_rsp_tx.send(GuiRsp::IncJpgQueue)?;
+
+                                    let jpg_sizes =
+                                        imgs.par_iter().map(calc_jpg_size).collect::<Vec<_>>();
+
+                                    let _ = jpg_size_cache
+                                        .lock()
+                                        .insert(entry.clone(), jpg_sizes.clone());
+
+                                    gui_rsp_tx.send(GuiRsp::JpgSize(entry, jpg_sizes))?;
+                                    gui_rsp_tx.send(GuiRsp::DecJpgQueue)?;
+                                    Ok(())
+                                }
+                            });
+
+                            spawn_sender({
+                                let entry = entry.clone();
+                                let avif_size_cache = avif_size_cache.clone();
+                                let gui_rsp_tx = gui_rsp_tx.clone();
+                                let imgs = imgs.clone();
+                                move || {
+                                    thread_priority::set_current_thread_priority(
+                                        thread_priority::ThreadPriority::Min,
+                                    )
+                                    .unwrap();
+                                    gui_rsp_tx.send(GuiRsp::IncQQueue)?;
+                                    // loop {
+                                    //     thread::sleep(Duration::from_millis(100));
+                                    //     let gen_q = gen_q.lock();
+                                    //     if gen_q.len() < 1 {
+                                    //         break;
+                                    //     }
+                                    // }
+                                    gui_rsp_tx.send(GuiRsp::IncAvifQueue)?;
+
+                                    let avif_sizes =
+                                        imgs.par_iter().map(calc_avif_size).collect::<Vec<_>>();
+
+                                    let _ = avif_size_cache
+                                        .lock()
+                                        .insert(entry.clone(), avif_sizes.clone());
+
+                                    gui_rsp_tx.send(GuiRsp::AvifSize(entry, avif_sizes))?;
+                                    gui_rsp_tx.send(GuiRsp::DecAvifQueue)?;
+                                    Ok(())
+                                }
+                            });
+
+                            spawn_sender({
+                                let entry = entry.clone();
+                                let canny_size_cache = canny_size_cache.clone();
+                                let gui_rsp_tx = gui_rsp_tx.clone();
+                                move || {
+                                    gui_rsp_tx.send(GuiRsp::IncQQueue)?;
+                                    thread_priority::set_current_thread_priority(
+                                        thread_priority::ThreadPriority::Min,
+                                    )
+                                    .unwrap();
+                                    // loop {
+                                    //     std::thread::sleep(Duration::from_millis(100));
+                                    //     let gen_q = gen_q.lock();
+                                    //     if gen_q.len() < 1 {
+                                    //         break;
+                                    //     }
+                                    // }
+                                    gui_rsp_tx.send(GuiRsp::IncCannyQueue)?;
+                                    let canny_sizes =
+                                        imgs.par_iter().map(calc_canny_size).collect::<Vec<_>>();
+
+                                    let _ = canny_size_cache
+                                        .lock()
+                                        .insert(entry.clone(), canny_sizes.clone());
+
+                                    gui_rsp_tx.send(GuiRsp::CannySize(entry, canny_sizes))?;
+                                    gui_rsp_tx.send(GuiRsp::DecCannyQueue)?;
+                                    Ok(())
+                                }
+                            });
+                        }
 
-                        thread::spawn({
+                        spawn_sender({
                             let entry = entry.clone();
-                            let canny_size_cache = canny_size_cache.clone();
+                            let file_size_cache = file_size_cache.clone();
+                            let duration_cache = duration_cache.clone();
+                            let resolution_cache = resolution_cache.clone();
                             let gui_rsp_tx = gui_rsp_tx.clone();
                             move || {
-                                gui_rsp_tx.send(GuiRsp::IncQQueue).unwrap();
-                                thread_priority::set_current_thread_priority(
-                                    thread_priority::ThreadPriority::Min,
-                                )
-                                .unwrap();
-                                // loop {
-                                //     std::thread::sleep(Duration::from_millis(100));
-                                //     let gen_q = gen_q.lock();
-                                //     if gen_q.len() < 1 {
-                                //         break;
-                                //     }
-                                // }
-                                gui_rsp_tx.send(GuiRsp::IncCannyQueue).unwrap();
-                                let canny_sizes =
-                                    imgs.par_iter().map(calc_canny_size).collect::<Vec<_>>();
-
-                                let _ = canny_size_cache
+                                let file_sizes = entry
+                                    .thunk
+                                    .entries()
+                                    .iter()
+                                    .map(|p| {
+                                        std::fs::metadata(p)
+                                            .map(|metadata| metadata.size())
+                                            .unwrap_or_default()
+                                    })
+                                    .collect::<Vec<_>>();
+
+                                let _ = file_size_cache
                                     .lock()
-                                    .insert(entry.clone(), canny_sizes.clone());
+                                    .insert(entry.clone(), file_sizes.clone());
 
-                                gui_rsp_tx
-                                    .send(GuiRsp::CannySize(entry, canny_sizes))
-                                    .unwrap();
-                                gui_rsp_tx.send(GuiRsp::DecCannyQueue).unwrap();
-                            }
-                        });
-                    }
+                                gui_rsp_tx.send(GuiRsp::FileSize(entry.clone(), file_sizes))?;
 
-                    thread::spawn({
-                        let entry = entry.clone();
-                        let file_size_cache = file_size_cache.clone();
-                        let duration_cache = duration_cache.clone();
-                        let resolution_cache = resolution_cache.clone();
-                        let gui_rsp_tx = gui_rsp_tx.clone();
-                        move || {
-                            let file_sizes = entry
+                                //probe each file once, and split the result into durations and resolutions.
+                                let media_infos = entry
                                 .thunk
                                 .entries()
                                 .iter()
                                 .map(|p| {
-                                    std::fs::metadata(p)
-                                        .map(|metadata| metadata.size())
-                                        .unwrap_or_default()
+                                    #[cfg(feature = "gstreamer_backend")]
+                                    let val =
+                                        ffmpeg_gst_wrapper::gst_impl::FrameReaderCfgGst::from_path(p)
+                                            .get_media_info()
+                                            .unwrap_or_default();
+
+                                    #[cfg(feature = "ffmpeg_backend")]
+                                    let val =
+                                        ffmpeg_gst_wrapper::ffmpeg_impl::FrameReaderCfgFfmpeg::from_path(p)
+                                            .get_media_info()
+                                            .unwrap_or_default();
+
+                                    val
                                 })
                                 .collect::<Vec<_>>();
 
-                            let _ = file_size_cache
-                                .lock()
-                                .insert(entry.clone(), file_sizes.clone());
-
-                            gui_rsp_tx
-                                .send(GuiRsp::FileSize(entry.clone(), file_sizes))
-                                .unwrap();
-
-                            let durations = entry
-                                    .thunk
-                                    .entries()
+                                let durations = media_infos
                                     .iter()
-                                    .map(|p| {
-                                        #[cfg(feature="gstreamer_backend")]
-                                        let val = ffmpeg_gst_wrapper::gst_impl::FrameReaderCfgGst::from_path(
-                                            p,
-                                        )
-                                        .get_duration()
-                                        .unwrap_or_default();
-
-                                        #[cfg(feature="ffmpeg_backend")]
-                                        let val = ffmpeg_gst_wrapper::ffmpeg_impl::FrameReaderCfgFfmpeg::from_path(
-                                            p,
-                                        )
-                                        .get_duration()
-                                        .unwrap_or_default();
-
-                                    val
-                                    })
+                                    .map(|info| info.duration)
                                     .collect::<Vec<_>>();
 
-                            let _ = duration_cache
-                                .lock()
-                                .insert(entry.clone(), durations.clone());
-
-                            gui_rsp_tx
-                                .send(GuiRsp::VidDuration(entry.clone(), durations))
-                                .unwrap();
-
-                            let resolutions = entry.thunk.entries().iter().map(|p| {
-                                #[cfg(feature="gstreamer_backend")]
-                                let val = ffmpeg_gst_wrapper::gst_impl::FrameReaderCfgGst::from_path(
-                                    p,
-                                )
-                                .get_resolution()
-                                .unwrap_or_default();
-
-                                #[cfg(feature="ffmpeg_backend")]
-                                let val = ffmpeg_gst_wrapper::ffmpeg_impl::FrameReaderCfgFfmpeg::from_path(
-                                    p,
-                                )
-                                .get_resolution()
-                                .unwrap_or_default();
+                                let _ = duration_cache
+                                    .lock()
+                                    .insert(entry.clone(), durations.clone());
 
-                            val
+                                gui_rsp_tx.send(GuiRsp::VidDuration(entry.clone(), durations))?;
 
+                                let resolutions = media_infos
+                                    .iter()
+                                    .map(|info| info.resolution)
+                                    .collect::<Vec<_>>();
 
-                            }).collect::<Vec<_>>();
+                                let _ = resolution_cache
+                                    .lock()
+                                    .insert(entry.clone(), resolutions.clone());
 
-                            let _ = resolution_cache
-                                .lock()
-                                .insert(entry.clone(), resolutions.clone());
+                                gui_rsp_tx.send(GuiRsp::VidResolution(entry, resolutions))?;
+                                Ok(())
+                            }
+                        });
+                    }
+                };
 
-                            gui_rsp_tx
-                                .send(GuiRsp::VidResolution(entry, resolutions))
-                                .unwrap();
+                if !gen_cmd_tx.is_full() {
+                    if let Some(job) = gen_q.next_job() {
+                        if let Err(TrySendError::Disconnected(_)) = gen_cmd_tx.try_send(job) {
+                            return Err(Disconnected);
                         }
-                    });
+                    }
                 }
-            };
 
-            if !gen_cmd_tx.is_full() {
-                if let Some(cmd) = gen_q.pop_front() {
-                    gen_cmd_tx.try_send(cmd).unwrap();
-                }
+                // gui_rsp_tx.send(GuiRsp::QLen(gen_q.len() as i32)).unwrap()
             }
+        };
 
-            // gui_rsp_tx.send(GuiRsp::QLen(gen_q.len() as i32)).unwrap()
+        if run().is_err() {
+            debug!("A channel to the thumbnail cache thread has closed, so it is stopping");
         }
     };
 
     std::thread::spawn(thread_main)
 }
 
+// The gui or the cache thread has gone, so there is nobody left to send to.
+struct Disconnected;
+
+impl<T> From<SendError<T>> for Disconnected {
+    fn from(_: SendError<T>) -> Self {
+        Self
+    }
+}
+
+// Run f on a new thread. f stops at the first failed send, as by then the gui has closed and its
+// results are no longer wanted.
+fn spawn_sender(f: impl FnOnce() -> Result<(), SendError<GuiRsp>> + Send + 'static) {
+    thread::spawn(move || {
+        let _ = f();
+    });
+}
+
+// The bits where the hashes of two videos in a group disagree, or None if either was not hashed.
+fn calc_hash_diff(
+    hash_cache: &VideoHashFilesystemCache,
+    entry: &CacheEntry,
+    idx: usize,
+    ref_idx: usize,
+) -> Option<SlintImage> {
+    let fetch = |i: usize| hash_cache.fetch(entry.thunk.entries().get(i)?).ok();
+
+    let img = fetch(idx)?.difference_image(&fetch(ref_idx)?).ok()?;
+
+    Some(SharedPixelBuffer::clone_from_slice(
+        img.as_raw(),
+        img.width(),
+        img.height(),
+    ))
+}
+
 fn calc_png_size(img: &RgbImage) -> u64 {
     let size = NonZeroU32::try_from(500).expect("literal");
 
@@ -535,3 +657,79 @@ fn calc_canny_size(img: &RgbImage) -> u64 {
     let new_img = imageproc::edges::canny(&norm_frame, CANNY_MIN, CANNY_MAX);
     new_img.pixels().filter(|pix| pix.0[0] > 0).count() as u64
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::app::gui_slint::RenderDetails;
+
+    fn entry(path: &str) -> CacheEntry {
+        CacheEntry {
+            render_details: RenderDetails {
+                cropdetect: false,
+                is_current: false,
+            },
+            thunk: ResolutionThunk::from_paths(None, &[path]),
+        }
+    }
+
+    // Images taking up the given number of bytes each (at 3 bytes per pixel).
+    fn imgs(sizes: &[u32]) -> Vec<SlintImage> {
+        sizes
+            .iter()
+            .map(|&size| SharedPixelBuffer::new(size / 3, 1))
+            .collect()
+    }
+
+    fn cached(cache: &Cache) -> Vec<CacheEntry> {
+        let mut entries = cache
+            .imgs
+            .iter()
+            .map(|(entry, _)| entry.clone())
+            .collect::<Vec<_>>();
+        entries.reverse();
+        entries
+    }
+
+    #[test]
+    fn test_evicts_least_recently_used_by_bytes() {
+        let mut cache = Cache::new(1000);
+        cache.put(entry("a"), imgs(&[300, 150]));
+        cache.put(entry("b"), imgs(&[300]));
+        cache.put(entry("c"), imgs(&[99]));
+        assert_eq!(cache.used_bytes(), 849);
+
+        //a is the least recently used until it is looked at.
+        assert!(cache.get(&entry("a")).is_some());
+        cache.put(entry("d"), imgs(&[300]));
+        assert_eq!(cached(&cache), [entry("c"), entry("a"), entry("d")]);
+        assert_eq!(cache.used_bytes(), 849);
+
+        //several entries can be evicted to make room for one.
+        cache.put(entry("e"), imgs(&[600]));
+        assert_eq!(cached(&cache), [entry("d"), entry("e")]);
+        assert_eq!(cache.used_bytes(), 900);
+        assert_eq!(cache.budget_bytes(), 1000);
+    }
+
+    #[test]
+    fn test_replacing_and_clearing_entries_updates_usage() {
+        let mut cache = Cache::new(1000);
+        cache.put(entry("a"), imgs(&[300]));
+        cache.put(entry("a"), imgs(&[600]));
+        assert_eq!(cache.used_bytes(), 600);
+
+        cache.put(entry("b"), imgs(&[300]));
+        cache.clear_thumbs(&entry("a").thunk);
+        assert_eq!(cached(&cache), [entry("b")]);
+        assert_eq!(cache.used_bytes(), 300);
+
+        //an entry bigger than the budget is kept on its own.
+        cache.put(entry("c"), imgs(&[3000]));
+        assert_eq!(cached(&cache), [entry("c")]);
+        assert_eq!(cache.used_bytes(), 3000);
+        cache.put(entry("d"), imgs(&[3]));
+        assert_eq!(cached(&cache), [entry("d")]);
+        assert_eq!(cache.used_bytes(), 3);
+    }
+}
@@ -1,25 +1,47 @@
+mod external_cmd;
 mod lru_cache;
 mod prerender;
-
-use std::{path::PathBuf, time::Duration};
+mod prerender_queue;
+mod session_state;
+
+use std::{
+    collections::BTreeSet,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 
 use bytesize::ByteSize;
 mod modulo;
-mod vlc_thread;
+mod player_thread;
+use crossbeam_channel::Sender;
+use external_cmd::ExternalCmd;
 use itertools::Itertools;
 use lru_cache::start_cache_thread;
 use modulo::Modulo;
-use slint::{Model, ModelRc, SharedString, VecModel, Weak};
-use vlc_thread::start_vlc_thread;
-
-use super::{ResolutionError, ResolutionThunk};
+use parking_lot::Mutex;
+use player_thread::start_player_thread;
+pub use session_state::session_state_path;
+use session_state::{LoadedSession, SessionState};
+use slint::{Model, ModelRc, SharedString, TimerMode, VecModel, Weak};
+use vid_dup_finder_lib::fmt::{format_duration, format_resolution};
+
+use super::{
+    search_output::quarantine_manifest_path, ResolutionError, ResolutionHistory, ResolutionThunk,
+    UndoError, WinningStats,
+};
+use crate::video_hash_filesystem_cache::{ThumbCrop, VideoHashFilesystemCache};
 
 slint::include_modules!();
 
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 enum GuiCmd {
-    Generate(CacheEntry),
-    Fetch(CacheEntry),
+    // (entry, generation of the batch of prerender commands it was sent in)
+    Generate(CacheEntry, u64),
+    Fetch(CacheEntry, u64),
     Clear(ResolutionThunk),
     FetchPngSize(CacheEntry),
     FetchAvifSize(CacheEntry),
@@ -28,6 +50,9 @@ enum GuiCmd {
     FetchFileSize(CacheEntry),
     FetchVidDuration(CacheEntry),
     FetchVidResolution(CacheEntry),
+    FetchWinningStats(CacheEntry),
+    // (entry, index of the selected video, index of the video to compare it with)
+    FetchHashDiff(CacheEntry, usize, usize),
     StatsEn(bool),
 }
 
@@ -38,8 +63,12 @@ enum GuiRsp {
     IncQLen,
     DecQLen,
     ResolvedResult(Result<(), ResolutionError>),
-    VlcOpened,
-    VlcClosed,
+    UndoResult(Result<(), UndoError>),
+    History(Vec<SharedString>),
+    PlayerOpened,
+    PlayerClosed,
+    // An external player or file manager could not be started.
+    SpawnFailed(String),
     PngSize(CacheEntry, Vec<u64>),
     AvifSize(CacheEntry, Vec<u64>),
     JpgSize(CacheEntry, Vec<u64>),
@@ -47,6 +76,10 @@ enum GuiRsp {
     FileSize(CacheEntry, Vec<u64>),
     VidDuration(CacheEntry, Vec<Duration>),
     VidResolution(CacheEntry, Vec<(u32, u32)>),
+    WinningStats(CacheEntry, Vec<WinningStats>),
+    HashDiff(CacheEntry, usize, SlintImage),
+    // Bytes of rendered thumbnails held by the cache thread, and the most it may hold.
+    CacheUsage { used: u64, budget: u64 },
     IncQQueue,
     IncPngQueue,
     DecPngQueue,
@@ -72,24 +105,78 @@ struct CacheEntry {
     thunk: ResolutionThunk,
 }
 
-pub fn run_gui_slint(thunks: Vec<ResolutionThunk>) -> Result<(), slint::PlatformError> {
+// Number of past resolutions shown in the history pane.
+const HISTORY_PANE_LEN: usize = 20;
+
+// How often the session is saved while the gui is open (it is also saved on exit).
+const SESSION_SAVE_INTERVAL: Duration = Duration::from_secs(30);
+
+// How long error messages are shown for.
+const TOAST_DURATION: Duration = Duration::from_secs(8);
+
+/// The commands given on the command line for playing videos and for showing them in a file
+/// manager. Commands which are not given are taken from the last session, or failing that the
+/// desktop's defaults are used.
+#[derive(Debug, Clone, Default)]
+pub struct GuiCommands {
+    pub player: Option<String>,
+    pub browser: Option<String>,
+}
+
+pub fn run_gui_slint(
+    thunks: Vec<ResolutionThunk>,
+    gui_trash_path: Option<&Path>,
+    session_path: Option<&Path>,
+    cache_budget: u64,
+    commands: GuiCommands,
+    quarantine_dir: Option<&Path>,
+    cache: VideoHashFilesystemCache,
+) -> Result<(), slint::PlatformError> {
     let (gui_cmd_tx, gui_cmd_rx) = crossbeam_channel::unbounded::<GuiCmd>();
     let (gui_rsp_tx, gui_rsp_rx) = crossbeam_channel::unbounded::<GuiRsp>();
 
-    let _cache_thread = start_cache_thread(gui_cmd_rx.clone(), gui_rsp_tx.clone());
+    let history = ResolutionHistory::load(gui_trash_path).unwrap_or_else(|e| {
+        warn!("Failed to load resolution history. Undo will only be available for resolutions made in this session: {e}");
+        ResolutionHistory::default()
+    });
 
-    let (vlc_tx, vlc_rx) = crossbeam_channel::unbounded::<PathBuf>();
-    start_vlc_thread(vlc_rx, gui_rsp_tx.clone());
+    let _cache_thread = start_cache_thread(
+        gui_cmd_rx.clone(),
+        gui_rsp_tx.clone(),
+        Arc::new(cache),
+        cache_budget,
+    );
 
     let ui = MainWindow::new()?;
 
-    gui_cmd_tx
-        .send(GuiCmd::StatsEn(!ui.get_disable_stats()))
-        .unwrap();
+    let session = Arc::new(Mutex::new(restore_session(&ui, &thunks, session_path)));
+
+    let (player, browser) = {
+        let mut session = session.lock();
+        //commands given on the command line are remembered for later sessions.
+        if let Some(player) = commands.player {
+            session.player_cmd = Some(player);
+        }
+        if let Some(browser) = commands.browser {
+            session.browser_cmd = Some(browser);
+        }
+        (
+            ExternalCmd::or_default(session.player_cmd.as_deref(), ExternalCmd::default_player),
+            ExternalCmd::or_default(session.browser_cmd.as_deref(), ExternalCmd::default_browser),
+        )
+    };
+
+    let (player_tx, player_rx) = crossbeam_channel::unbounded::<Vec<PathBuf>>();
+    start_player_thread(player, player_rx, gui_rsp_tx.clone());
+
+    ui.set_history_entries(ModelRc::new(VecModel::from(history_pane_entries(&history))));
+    let history = Arc::new(Mutex::new(history));
+
+    send_cmd(&gui_cmd_tx, GuiCmd::StatsEn(!ui.get_disable_stats()));
     ui.on_set_stats_en({
         let gui_cmd_tx = gui_cmd_tx.clone();
         move |val| {
-            gui_cmd_tx.send(GuiCmd::StatsEn(val)).unwrap();
+            send_cmd(&gui_cmd_tx, GuiCmd::StatsEn(val));
         }
     });
 
@@ -160,24 +247,51 @@ pub fn run_gui_slint(thunks: Vec<ResolutionThunk>) -> Result<(), slint::Platform
         }
     });
 
+    ui.on_undo_last_resolution({
+        let ui_handle = ui.as_weak();
+        let gui_rsp_tx = gui_rsp_tx.clone();
+        let history = history.clone();
+        move || {
+            ui_handle
+                .unwrap()
+                .invoke_set_resolved_ok_colour("blue".into());
+            let gui_rsp_tx = gui_rsp_tx.clone();
+            let history = history.clone();
+            std::thread::spawn(move || {
+                let mut history = history.lock();
+                let result = history.undo_last().map(|_transaction| ());
+                let _ = gui_rsp_tx.send(GuiRsp::History(history_pane_entries(&history)));
+                let _ = gui_rsp_tx.send(GuiRsp::UndoResult(result));
+            });
+        }
+    });
+
     ui.on_accept_idx_keep({
         let ui_handle = ui.as_weak();
         let thunks = thunks.clone();
         let gui_cmd_tx = gui_cmd_tx.clone();
+        let gui_rsp_tx = gui_rsp_tx.clone();
         move |s| {
             let ui = ui_handle.unwrap();
             let thunk_idx = ui.get_thunk_idx() as usize;
 
             let thunk = thunks.get(thunk_idx).unwrap().clone();
             let s = s.to_string();
-            gui_cmd_tx.send(GuiCmd::Clear(thunk.clone())).unwrap();
+            send_cmd(&gui_cmd_tx, GuiCmd::Clear(thunk.clone()));
             let gui_rsp_tx = gui_rsp_tx.clone();
+            let history = history.clone();
             ui.invoke_set_resolved_ok_colour("blue".into());
             std::thread::spawn(move || {
                 // std::thread::sleep(std::time::Duration::from_secs(1));
-                gui_rsp_tx
-                    .send(GuiRsp::ResolvedResult(thunk.resolve_2(s)))
-                    .unwrap()
+                let result = thunk.resolve_2(s).map(|transaction| {
+                    let mut history = history.lock();
+                    if let Err(e) = history.push(transaction) {
+                        warn!("Failed to save resolution history: {e}");
+                    }
+                    let _ = gui_rsp_tx.send(GuiRsp::History(history_pane_entries(&history)));
+                });
+
+                let _ = gui_rsp_tx.send(GuiRsp::ResolvedResult(result));
             });
 
             ui.invoke_request_next_thunk();
@@ -188,17 +302,45 @@ pub fn run_gui_slint(thunks: Vec<ResolutionThunk>) -> Result<(), slint::Platform
     ui.on_exclude_curr_vid({
         let ui_handle = ui.as_weak();
         let thunks = thunks.clone();
+        let session = session.clone();
         move || {
             let ui = ui_handle.unwrap();
-
-            //make sure we can't exlude the last vid
-            if ui.get_thunk_entries().iter().filter(|e| !e.enabled).count() < 1 {
+            let Some(thunk) = thunks.get(ui.get_thunk_idx() as usize) else {
                 return;
-            }
+            };
+            let group_id = thunk.group_id();
+            let entries = thunk.entries();
+
+            {
+                let mut session = session.lock();
+
+                //make sure we can't exlude the last vid
+                let num_enabled = entries
+                    .iter()
+                    .filter(|path| !session.is_excluded(&group_id, path))
+                    .count();
+                if num_enabled <= 1 {
+                    return;
+                }
 
-            let vid_idx = ui.get_curr_vid() as usize;
+                let Some(path) = entries.get(ui.get_curr_vid() as usize) else {
+                    return;
+                };
+                session.exclude(&group_id, path);
+            }
 
-            ui.get_thunk_entries().row_data(vid_idx).unwrap().enabled = false;
+            let gui_entries = ui.get_thunk_entries();
+            for row in 0..gui_entries.row_count() {
+                if let Some(mut entry) = gui_entries.row_data(row) {
+                    if session
+                        .lock()
+                        .is_excluded(&group_id, Path::new(entry.path.as_str()))
+                    {
+                        entry.enabled = false;
+                        gui_entries.set_row_data(row, entry);
+                    }
+                }
+            }
             ui.set_curr_vid(incr_curr_vid(&ui.as_weak(), &thunks));
         }
     });
@@ -223,31 +365,24 @@ pub fn run_gui_slint(thunks: Vec<ResolutionThunk>) -> Result<(), slint::Platform
     // });
 
     ui.on_view_curr_vid({
-        let vlc_tx = vlc_tx.clone();
+        let player_tx = player_tx.clone();
         let ui_handle = ui.as_weak();
         move |p| {
-            ui_handle.unwrap().invoke_set_vlc_colour("red".into());
-            vlc_tx.send(PathBuf::from(p.to_string())).unwrap();
+            ui_handle.unwrap().invoke_set_player_colour("red".into());
+            let _ = player_tx.send(vec![PathBuf::from(p.to_string())]);
         }
-        // move |p| {
-        //     let binary = "autocrop-vid.sh";
-        //     let p = p.to_string();
-        //     let _ = std::process::Command::new(binary).arg(p).spawn();
-        // }
     });
 
     ui.on_browse_curr_vid({
-        move |p| {
-            let binary = "nautilus";
-            let p = p.to_string();
-            let _ = std::process::Command::new(binary).arg(p).spawn();
-        }
+        let browser = browser.clone();
+        let gui_rsp_tx = gui_rsp_tx.clone();
+        move |p| browse_to(&browser, PathBuf::from(p.to_string()), &gui_rsp_tx)
     });
 
     ui.on_view_top_vid({
         let ui_handle = ui.as_weak();
         let thunks = thunks.clone();
-        let vlc_tx = vlc_tx.clone();
+        let player_tx = player_tx.clone();
         move || {
             let ui = ui_handle.unwrap();
             if let Some(curr_vid) = thunks.get(ui.get_thunk_idx() as usize).and_then(|thunk| {
@@ -256,12 +391,61 @@ pub fn run_gui_slint(thunks: Vec<ResolutionThunk>) -> Result<(), slint::Platform
                     .get(ui.get_curr_vid() as usize)
                     .map(|x| x.to_path_buf())
             }) {
-                ui_handle.unwrap().invoke_set_vlc_colour("red".into());
-                vlc_tx.send(curr_vid).unwrap();
-                // let binary = "autocrop-vid.sh";
-                // let _ = std::process::Command::new(binary)
-                //     .arg(curr_vid.to_string_lossy().to_string())
-                //     .spawn();
+                ui.invoke_set_player_colour("red".into());
+                let _ = player_tx.send(vec![curr_vid]);
+            }
+        }
+    });
+
+    ui.on_view_all_vids({
+        let ui_handle = ui.as_weak();
+        let thunks = thunks.clone();
+        let session = session.clone();
+        let player_tx = player_tx.clone();
+        move || {
+            let ui = ui_handle.unwrap();
+            let Some(thunk) = thunks.get(ui.get_thunk_idx() as usize) else {
+                return;
+            };
+            let group_id = thunk.group_id();
+            let enabled = {
+                let session = session.lock();
+                thunk
+                    .entries()
+                    .iter()
+                    .filter(|path| !session.is_excluded(&group_id, path))
+                    .map(|path| path.to_path_buf())
+                    .collect::<Vec<_>>()
+            };
+            ui.invoke_set_player_colour("red".into());
+            let _ = player_tx.send(enabled);
+        }
+    });
+
+    ui.set_has_quarantine(quarantine_dir.is_some());
+    ui.on_open_quarantine_dir({
+        let ui_handle = ui.as_weak();
+        let thunks = thunks.clone();
+        let browser = browser.clone();
+        let gui_rsp_tx = gui_rsp_tx.clone();
+        let quarantine_dir = quarantine_dir.map(Path::to_path_buf);
+        move || {
+            let ui = ui_handle.unwrap();
+            let (Some(quarantine_dir), Some(thunk)) = (
+                quarantine_dir.as_deref(),
+                thunks.get(ui.get_thunk_idx() as usize),
+            ) else {
+                return;
+            };
+            //browsing to the manifest shows the directory that it is in.
+            let manifest = quarantine_manifest_path(quarantine_dir, &thunk.group_id());
+            if manifest.is_file() {
+                browse_to(&browser, manifest, &gui_rsp_tx);
+            } else {
+                show_toast(
+                    &ui_handle,
+                    "This group has not been quarantined".to_string(),
+                );
             }
         }
     });
@@ -269,6 +453,7 @@ pub fn run_gui_slint(thunks: Vec<ResolutionThunk>) -> Result<(), slint::Platform
     ui.on_browse_top_vid({
         let ui_handle = ui.as_weak();
         let thunks = thunks.clone();
+        let gui_rsp_tx = gui_rsp_tx.clone();
         move || {
             let ui = ui_handle.unwrap();
             if let Some(curr_vid) = thunks.get(ui.get_thunk_idx() as usize).and_then(|thunk| {
@@ -277,18 +462,7 @@ pub fn run_gui_slint(thunks: Vec<ResolutionThunk>) -> Result<(), slint::Platform
                     .get(ui.get_curr_vid() as usize)
                     .map(|x| x.to_path_buf())
             }) {
-                let binary = "nautilus";
-
-                let _ = std::thread::spawn({
-                    let curr_vid = curr_vid.clone();
-                    move || {
-                        std::process::Command::new(binary)
-                            .arg(curr_vid.to_string_lossy().to_string())
-                            .spawn()
-                            .unwrap()
-                            .wait()
-                    }
-                });
+                browse_to(&browser, curr_vid, &gui_rsp_tx);
             }
         }
     });
@@ -321,6 +495,7 @@ pub fn run_gui_slint(thunks: Vec<ResolutionThunk>) -> Result<(), slint::Platform
                     let resolution_command = format!("u{}", ui.get_curr_vid());
                     ui.invoke_accept_idx_keep(resolution_command.into())
                 }
+                'u' if event.modifiers.control => ui.invoke_undo_last_resolution(),
                 '\u{f703}' => ui.invoke_request_next_thunk(),
                 '\'' if event.modifiers.control => ui.invoke_accept_idx_keep("1".into()),
                 '\u{f702}' => ui.invoke_request_prev_thunk(),
@@ -361,6 +536,9 @@ pub fn run_gui_slint(thunks: Vec<ResolutionThunk>) -> Result<(), slint::Platform
                 'b' if event.modifiers.control => {
                     ui.invoke_browse_top_vid();
                 }
+                'a' if event.modifiers.control => {
+                    ui.invoke_view_all_vids();
+                }
                 _ => (),
             }
         }
@@ -374,13 +552,15 @@ pub fn run_gui_slint(thunks: Vec<ResolutionThunk>) -> Result<(), slint::Platform
     #[allow(clippy::useless_conversion)] //false positive
     let _fetch_thread = std::thread::spawn({
         let gui_cmd_tx = gui_cmd_tx.clone();
-        move || loop {
+        let session = session.clone();
+        move || {
             for resp in gui_rsp_rx.iter() {
                 let ui = ui_weak.clone();
                 use GuiRsp::*;
 
-                slint::invoke_from_event_loop({
+                let sent = slint::invoke_from_event_loop({
                     let gui_cmd_tx = gui_cmd_tx.clone();
+                    let session = session.clone();
 
                     if !matches!(resp, Fetched(_)) {
                         // dbg!(&resp);
@@ -388,34 +568,27 @@ pub fn run_gui_slint(thunks: Vec<ResolutionThunk>) -> Result<(), slint::Platform
 
                     move || match resp {
                         Fetched((thunk, imgs)) => {
-                            let x = gen_gui_data(imgs, thunk.clone(), &ui);
+                            let excluded = session.lock().excluded_from(&thunk.thunk.group_id());
+                            let x = gen_gui_data(imgs, thunk.clone(), &excluded, &ui);
 
                             let ui = ui.unwrap();
 
                             ui.set_thunk_entries(x);
 
-                            gui_cmd_tx
-                                .send(GuiCmd::FetchPngSize(thunk.clone()))
-                                .unwrap();
-                            gui_cmd_tx
-                                .send(GuiCmd::FetchAvifSize(thunk.clone()))
-                                .unwrap();
-                            gui_cmd_tx
-                                .send(GuiCmd::FetchJpgSize(thunk.clone()))
-                                .unwrap();
-                            gui_cmd_tx
-                                .send(GuiCmd::FetchCannySize(thunk.clone()))
-                                .unwrap();
-                            gui_cmd_tx
-                                .send(GuiCmd::FetchFileSize(thunk.clone()))
-                                .unwrap();
-                            gui_cmd_tx
-                                .send(GuiCmd::FetchVidDuration(thunk.clone()))
-                                .unwrap();
-
-                            gui_cmd_tx
-                                .send(GuiCmd::FetchVidResolution(thunk.clone()))
-                                .unwrap();
+                            send_cmd(&gui_cmd_tx, GuiCmd::FetchPngSize(thunk.clone()));
+                            send_cmd(&gui_cmd_tx, GuiCmd::FetchAvifSize(thunk.clone()));
+                            send_cmd(&gui_cmd_tx, GuiCmd::FetchJpgSize(thunk.clone()));
+                            send_cmd(&gui_cmd_tx, GuiCmd::FetchCannySize(thunk.clone()));
+                            send_cmd(&gui_cmd_tx, GuiCmd::FetchFileSize(thunk.clone()));
+                            send_cmd(&gui_cmd_tx, GuiCmd::FetchVidDuration(thunk.clone()));
+
+                            send_cmd(&gui_cmd_tx, GuiCmd::FetchVidResolution(thunk.clone()));
+                            send_cmd(&gui_cmd_tx, GuiCmd::FetchWinningStats(thunk.clone()));
+
+                            //the reference video is always first.
+                            for i in 1..thunk.thunk.entries().len() {
+                                send_cmd(&gui_cmd_tx, GuiCmd::FetchHashDiff(thunk.clone(), i, 0));
+                            }
                         }
                         Wait => {
                             ui.unwrap()
@@ -435,11 +608,36 @@ pub fn run_gui_slint(thunks: Vec<ResolutionThunk>) -> Result<(), slint::Platform
                         ResolvedResult(Ok(())) => {
                             ui.unwrap().invoke_set_resolved_ok_colour("black".into())
                         }
+                        ResolvedResult(Err(
+                            e @ (ResolutionError::FramesDiffer(_)
+                            | ResolutionError::ProtectedEntry(_)),
+                        )) => {
+                            ui.unwrap().invoke_set_resolved_ok_colour("red".into());
+                            show_toast(&ui, e.to_string());
+                        }
                         ResolvedResult(Err(_)) => {
                             ui.unwrap().invoke_set_resolved_ok_colour("red".into())
                         }
-                        VlcOpened => ui.unwrap().invoke_set_vlc_colour("blue".into()),
-                        VlcClosed => ui.unwrap().invoke_set_vlc_colour("black".into()),
+                        UndoResult(Ok(())) => {
+                            let ui = ui.unwrap();
+                            ui.set_history_status("".into());
+                            ui.invoke_set_resolved_ok_colour("black".into())
+                        }
+                        UndoResult(Err(e)) => {
+                            let ui = ui.unwrap();
+                            ui.set_history_status(e.to_string().into());
+                            ui.invoke_set_resolved_ok_colour("red".into())
+                        }
+                        History(entries) => ui
+                            .unwrap()
+                            .set_history_entries(ModelRc::new(VecModel::from(entries))),
+                        PlayerOpened => ui.unwrap().invoke_set_player_colour("blue".into()),
+                        PlayerClosed => ui.unwrap().invoke_set_player_colour("black".into()),
+                        SpawnFailed(msg) => show_toast(&ui, msg),
+
+                        CacheUsage { used, budget } => ui.unwrap().set_cache_usage(
+                            format!("{} / {}", ByteSize(used), ByteSize(budget)).into(),
+                        ),
 
                         IncQQueue => {
                             let ui = ui.unwrap();
@@ -773,22 +971,11 @@ pub fn run_gui_slint(thunks: Vec<ResolutionThunk>) -> Result<(), slint::Platform
                                         if let Some((cache_entry_idx, _cache_entry)) =
                                             matching_entry
                                         {
-                                            let mut dur = durations
+                                            let dur = durations
                                                 .get(cache_entry_idx)
-                                                .cloned()
-                                                .unwrap_or_else(|| Duration::from_secs(0))
-                                                .as_secs();
-
-                                            let hours = dur / 3600;
-                                            dur -= hours * 3600;
-
-                                            let mins = dur / 60;
-                                            dur -= mins * 60;
-
-                                            let secs = dur;
-
-                                            gui_entry.vid_duration =
-                                                format!("{hours:>02}:{mins:>02}:{secs:>02}").into();
+                                                .copied()
+                                                .unwrap_or_default();
+                                            gui_entry.vid_duration = format_duration(dur).into();
                                         }
                                     }
                                     ui.set_thunk_entries(ModelRc::new(VecModel::from(gui_entries)));
@@ -824,26 +1011,215 @@ pub fn run_gui_slint(thunks: Vec<ResolutionThunk>) -> Result<(), slint::Platform
                                         if let Some((cache_entry_idx, _cache_entry)) =
                                             matching_entry
                                         {
-                                            let (x, y) =
-                                                resolutions.get(cache_entry_idx).unwrap_or(&(0, 0));
-                                            gui_entry.vid_resolution = format!("{x}x{y}",).into();
+                                            let res = resolutions
+                                                .get(cache_entry_idx)
+                                                .copied()
+                                                .unwrap_or_default();
+                                            gui_entry.vid_resolution =
+                                                format_resolution(res).into();
                                         }
                                     }
                                     ui.set_thunk_entries(ModelRc::new(VecModel::from(gui_entries)));
                                 }
                             }
                         }
+
+                        WinningStats(cache_entries, stats) => {
+                            let ui = ui.unwrap();
+
+                            if ui.get_cropdetect() == cache_entries.render_details.cropdetect {
+                                let mut gui_entries =
+                                    ui.get_thunk_entries().iter().collect::<Vec<_>>();
+
+                                let mut found = false;
+                                for gui_entry in gui_entries.iter_mut() {
+                                    let matching_entry =
+                                        cache_entries.thunk.entries().iter().position(
+                                            |cache_entry| {
+                                                cache_entry.to_string_lossy()
+                                                    == gui_entry.path.to_string()
+                                            },
+                                        );
+                                    if let Some(stats) =
+                                        matching_entry.and_then(|idx| stats.get(idx))
+                                    {
+                                        gui_entry.winning_stats = winning_stats(stats);
+                                        found = true;
+                                    }
+                                }
+                                if found {
+                                    ui.set_thunk_entries(ModelRc::new(VecModel::from(gui_entries)));
+                                }
+                            }
+                        }
+
+                        HashDiff(cache_entries, idx, diff_img) => {
+                            let ui = ui.unwrap();
+
+                            if ui.get_cropdetect() == cache_entries.render_details.cropdetect {
+                                if let Some(path) = cache_entries.thunk.entries().get(idx) {
+                                    let path = path.to_string_lossy();
+                                    let mut gui_entries =
+                                        ui.get_thunk_entries().iter().collect::<Vec<_>>();
+
+                                    let mut found = false;
+                                    for gui_entry in
+                                        gui_entries.iter_mut().filter(|e| *path == *e.path)
+                                    {
+                                        gui_entry.hash_diff =
+                                            slint::Image::from_rgb8(diff_img.clone());
+                                        gui_entry.has_hash_diff = true;
+                                        found = true;
+                                    }
+                                    if found {
+                                        ui.set_thunk_entries(ModelRc::new(VecModel::from(
+                                            gui_entries,
+                                        )));
+                                    }
+                                }
+                            }
+                        }
                     }
-                })
-                .unwrap();
+                });
+                //the event loop has quit, so the gui has closed.
+                if sent.is_err() {
+                    break;
+                }
+            }
+        }
+    });
+
+    let save_session = {
+        let ui_handle = ui.as_weak();
+        let session_path = session_path.map(Path::to_path_buf);
+        let mut last_saved = None;
+        move || {
+            let (Some(ui), Some(session_path)) = (ui_handle.upgrade(), &session_path) else {
+                return;
+            };
+            let mut session = session.lock();
+            session.thunk_idx = ui.get_thunk_idx().max(0) as usize;
+            session.cropdetect = ui.get_cropdetect();
+            session.zoom = ui.get_zoom_val();
+            session.disable_stats = ui.get_disable_stats();
+
+            if last_saved.as_ref() == Some(&*session) {
+                return;
+            }
+            match session.save(session_path) {
+                Ok(()) => last_saved = Some(session.clone()),
+                Err(e) => warn!("Failed to save gui session: {e}"),
             }
         }
+    };
+    let save_session = Arc::new(Mutex::new(save_session));
+    let session_timer = slint::Timer::default();
+    session_timer.start(TimerMode::Repeated, SESSION_SAVE_INTERVAL, {
+        let save_session = save_session.clone();
+        move || save_session.lock()()
     });
 
     issue_prerender_commands(&ui.as_weak(), &thunks, &gui_cmd_tx.clone());
     ui.invoke_focus_default();
-    ui.set_max_idx((thunks.len() - 1).max(0) as i32);
-    ui.run()
+    ui.set_max_idx(thunks.len().saturating_sub(1) as i32);
+    let ret = ui.run();
+
+    session_timer.stop();
+    save_session.lock()();
+    ret
+}
+
+// Put the gui back where it was when it was last closed, if it was showing the same groups.
+// Otherwise a new session starts from the first group, with the gui's default settings.
+fn restore_session(
+    ui: &MainWindow,
+    thunks: &[ResolutionThunk],
+    session_path: Option<&Path>,
+) -> SessionState {
+    let fingerprint = session_state::fingerprint(thunks.iter().map(ResolutionThunk::group_id));
+
+    let loaded = match session_path.map(|path| SessionState::load(path, &fingerprint)) {
+        None => LoadedSession::Missing,
+        Some(Ok(loaded)) => loaded,
+        Some(Err(e)) => {
+            warn!("Failed to load gui session, starting a new one: {e}");
+            LoadedSession::Missing
+        }
+    };
+
+    match loaded {
+        LoadedSession::Restored(session) => {
+            let thunk_idx = session.thunk_idx.min(thunks.len().saturating_sub(1));
+            info!("Restoring gui session at group {thunk_idx}");
+            ui.set_thunk_idx(thunk_idx as i32);
+            ui.set_cropdetect(session.cropdetect);
+            ui.set_zoom_val(session.zoom);
+            ui.set_disable_stats(session.disable_stats);
+            session
+        }
+        LoadedSession::Stale(_) | LoadedSession::Missing => {
+            let (player_cmd, browser_cmd) = match loaded {
+                LoadedSession::Stale(stale) => {
+                    warn!("The duplicate groups have changed since the last gui session, so it will not be restored");
+                    (stale.player_cmd, stale.browser_cmd)
+                }
+                _ => (None, None),
+            };
+            SessionState {
+                fingerprint,
+                thunk_idx: 0,
+                excluded: Default::default(),
+                cropdetect: ui.get_cropdetect(),
+                zoom: ui.get_zoom_val(),
+                disable_stats: ui.get_disable_stats(),
+                player_cmd,
+                browser_cmd,
+            }
+        }
+    }
+}
+
+// Show the video in the file manager. The file manager is waited for on another thread so that
+// it is cleaned up once it is closed.
+fn browse_to(browser: &ExternalCmd, path: PathBuf, gui_rsp_tx: &Sender<GuiRsp>) {
+    match browser.spawn(&[path]) {
+        Ok(mut child) => {
+            std::thread::spawn(move || child.wait());
+        }
+        Err(e) => {
+            warn!("{e}");
+            let _ = gui_rsp_tx.send(GuiRsp::SpawnFailed(e));
+        }
+    }
+}
+
+// Show an error message at the top of the window for a while.
+fn show_toast(ui: &Weak<MainWindow>, msg: String) {
+    let Some(window) = ui.upgrade() else {
+        return;
+    };
+    window.set_toast(msg.as_str().into());
+    let ui = ui.clone();
+    slint::Timer::single_shot(TOAST_DURATION, move || {
+        //a newer message is cleared by its own timer.
+        if let Some(window) = ui
+            .upgrade()
+            .filter(|window| window.get_toast() == msg.as_str())
+        {
+            window.set_toast("".into());
+        }
+    });
+}
+
+// The most recent resolutions, newest first.
+fn history_pane_entries(history: &ResolutionHistory) -> Vec<SharedString> {
+    history
+        .transactions()
+        .iter()
+        .rev()
+        .take(HISTORY_PANE_LEN)
+        .map(|transaction| transaction.summary().into())
+        .collect()
 }
 
 fn incr_curr_vid(ui: &Weak<MainWindow>, thunks: &[ResolutionThunk]) -> i32 {
@@ -913,6 +1289,7 @@ fn decr_curr_vid(ui: &Weak<MainWindow>, thunks: &[ResolutionThunk]) -> i32 {
 fn gen_gui_data(
     imgs: Vec<SlintImage>,
     thunk: CacheEntry,
+    excluded: &BTreeSet<PathBuf>,
     ui: &Weak<MainWindow>,
 ) -> ModelRc<ThunkGuiData> {
     let ui = ui.unwrap();
@@ -932,7 +1309,7 @@ fn gen_gui_data(
             ThunkGuiData {
                 path: SharedString::from(entry.to_string_lossy().to_string()),
                 idx: i as i32,
-                enabled: true,
+                enabled: !excluded.contains(entry),
                 thumb: image,
                 aspect_ratio,
                 vid_duration: "00:00:00".into(),
@@ -950,6 +1327,16 @@ fn gen_gui_data(
                 jpg_size: "0".into(),
                 jpg_size_int: 0,
                 jpg_rank_proportion: 0.0,
+                hash_diff: slint::Image::default(),
+                has_hash_diff: false,
+                protected: thunk.thunk.is_protected(entry),
+                crop_source: crop_source(prerender::thumb_crop(
+                    &thunk.thunk,
+                    entry,
+                    thunk.render_details.cropdetect,
+                )),
+                containment: containment(&thunk.thunk, entry),
+                winning_stats: "".into(),
             }
         })
         .collect::<Vec<ThunkGuiData>>();
@@ -965,11 +1352,69 @@ fn gen_gui_data(
     x
 }
 
+// Where the crop of an entry's thumbnails came from, shown under its path.
+fn crop_source(crop: ThumbCrop) -> SharedString {
+    match crop {
+        ThumbCrop::Uncropped => "".into(),
+        ThumbCrop::FromHash(_) => "crop: from hash".into(),
+        ThumbCrop::Recomputed => "crop: recomputed".into(),
+    }
+}
+
+// Which other entries an entry was cut from, or were cut from it, shown under its path. Empty
+// unless the search was run with --containment.
+fn containment(thunk: &ResolutionThunk, entry: &Path) -> SharedString {
+    let names = |paths: Vec<&Path>| {
+        paths
+            .iter()
+            .map(|path| path.file_name().unwrap_or_default().to_string_lossy())
+            .join(", ")
+    };
+    let longer = thunk.contained_in(entry).collect::<Vec<_>>();
+    let contents = thunk.contents_of(entry).collect::<Vec<_>>();
+
+    let mut lines = vec![];
+    if !longer.is_empty() {
+        lines.push(format!("cut from: {}", names(longer)));
+    }
+    if !contents.is_empty() {
+        lines.push(format!("contains: {}", names(contents)));
+    }
+    lines.join("\n").into()
+}
+
+// What an entry is the best of its group at, shown under its path. Empty until the entries have
+// been compared, or if it is not the best at anything.
+fn winning_stats(stats: &WinningStats) -> SharedString {
+    let mut labels = vec![];
+    if stats.is_reference {
+        labels.push("reference");
+    }
+    if stats.res {
+        labels.push("best resolution");
+    }
+    if stats.pngsize {
+        labels.push("most detail");
+    }
+    labels.join(", ").into()
+}
+
 enum Direction {
     Forwards,
     Backwards,
 }
 
+static NEXT_GENERATION: AtomicU64 = AtomicU64::new(1);
+
+// Ask the cache thread for something. It only stops if its own channels have closed, after which
+// the gui would wait for thumbnails forever, so close the gui instead.
+fn send_cmd(cmd_tx: &crossbeam_channel::Sender<GuiCmd>, cmd: GuiCmd) {
+    if cmd_tx.send(cmd).is_err() {
+        error!("The thumbnail cache thread has stopped, so the GUI is closing");
+        let _ = slint::quit_event_loop();
+    }
+}
+
 fn issue_prerender_commands(
     ui: &Weak<MainWindow>,
     thunks: &[ResolutionThunk],
@@ -998,7 +1443,10 @@ fn issue_prerender_commands(
             thunk,
         };
 
-        cmd_tx.send(GuiCmd::Fetch(entry)).unwrap();
+        //everything sent from here on supersedes the previous batch of commands.
+        let generation = NEXT_GENERATION.fetch_add(1, Ordering::Relaxed);
+
+        send_cmd(cmd_tx, GuiCmd::Fetch(entry, generation));
 
         //get the next and the previous
         //with opposite cropdetect
@@ -1011,7 +1459,7 @@ fn issue_prerender_commands(
                     },
                     thunk: next_thunk.clone(),
                 };
-                cmd_tx.send(GuiCmd::Generate(entry)).unwrap();
+                send_cmd(cmd_tx, GuiCmd::Generate(entry, generation));
             }
             if let Some(next_thunk) = idx.checked_add(1).and_then(|idx| thunks.get(idx)) {
                 let entry = CacheEntry {
@@ -1021,7 +1469,7 @@ fn issue_prerender_commands(
                     },
                     thunk: next_thunk.clone(),
                 };
-                cmd_tx.send(GuiCmd::Generate(entry)).unwrap();
+                send_cmd(cmd_tx, GuiCmd::Generate(entry, generation));
             }
             if let Some(next_thunk) = idx.checked_sub(1).and_then(|idx| thunks.get(idx)) {
                 let entry = CacheEntry {
@@ -1031,7 +1479,7 @@ fn issue_prerender_commands(
                     },
                     thunk: next_thunk.clone(),
                 };
-                cmd_tx.send(GuiCmd::Generate(entry)).unwrap();
+                send_cmd(cmd_tx, GuiCmd::Generate(entry, generation));
             }
             // if let Some(next_thunk) = idx.checked_add(1).and_then(|idx| thunks.get(idx)) {
             //     let entry = CacheEntry {
@@ -1074,7 +1522,7 @@ fn issue_prerender_commands(
                 render_details: details,
                 thunk: thunk.clone(),
             };
-            cmd_tx.send(GuiCmd::Generate(entry)).unwrap();
+            send_cmd(cmd_tx, GuiCmd::Generate(entry, generation));
         }
 
         Some(())
@@ -0,0 +1,68 @@
+use std::{path::PathBuf, process::Child, time::Duration};
+
+use crossbeam_channel::{Receiver, RecvTimeoutError, Sender};
+
+use super::{external_cmd::ExternalCmd, GuiRsp};
+
+// How often the thread checks whether the player has been closed.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Play each list of videos received on rx with the given command, first closing the players
+/// opened for the previous list. If the command can only open one video at a time, each video is
+/// opened in a player of its own.
+///
+/// Only the players started by this thread are closed, so commands such as xdg-open, which hand
+/// the video to another program and exit, leave the video playing and are reported as closed
+/// straight away.
+pub fn start_player_thread(cmd: ExternalCmd, rx: Receiver<Vec<PathBuf>>, tx: Sender<GuiRsp>) {
+    std::thread::spawn(move || {
+        let mut players: Vec<Child> = vec![];
+        loop {
+            match rx.recv_timeout(POLL_INTERVAL) {
+                Ok(paths) => {
+                    close_players(&mut players);
+                    let runs = if cmd.opens_many() {
+                        vec![paths]
+                    } else {
+                        paths.into_iter().map(|path| vec![path]).collect()
+                    };
+                    for run in runs {
+                        match cmd.spawn(&run) {
+                            Ok(child) => players.push(child),
+                            Err(e) => {
+                                warn!("{e}");
+                                //the gui has closed.
+                                if tx.send(GuiRsp::SpawnFailed(e)).is_err() {
+                                    return;
+                                }
+                            }
+                        }
+                    }
+                    let rsp = if players.is_empty() {
+                        GuiRsp::PlayerClosed
+                    } else {
+                        GuiRsp::PlayerOpened
+                    };
+                    if tx.send(rsp).is_err() {
+                        return;
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => (),
+                Err(RecvTimeoutError::Disconnected) => return,
+            }
+
+            let was_open = !players.is_empty();
+            players.retain_mut(|child| matches!(child.try_wait(), Ok(None)));
+            if was_open && players.is_empty() && tx.send(GuiRsp::PlayerClosed).is_err() {
+                return;
+            }
+        }
+    });
+}
+
+fn close_players(players: &mut Vec<Child>) {
+    for mut child in players.drain(..) {
+        let _ = child.kill();
+        let _ = child.wait();
+    }
+}
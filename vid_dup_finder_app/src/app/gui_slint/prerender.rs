@@ -9,35 +9,36 @@ use std::{
 };
 
 use crossbeam_channel::{self, Receiver, Sender};
-
-#[cfg(feature = "gstreamer_backend")]
-use ffmpeg_gst_wrapper::{gst_impl::FrameReaderCfgGst, FrameReadCfgTrait};
-
-#[cfg(feature = "ffmpeg_backend")]
-use ffmpeg_gst_wrapper::{ffmpeg_impl::FrameReaderCfgFfmpeg, FrameReadCfgTrait};
-use image::{buffer::ConvertBuffer, RgbImage};
+use image::RgbImage;
 
 use rayon::prelude::*;
-use vid_dup_finder_common::{
-    row_images, video_frames_gray::VdfFrameSeqExt, FrameSeqRgb, VideoFramesGray,
-};
-use vid_dup_finder_lib::{debug_util::build_frame_reader, CreationOptions};
+use vid_dup_finder_common::row_images;
+use vid_dup_finder_lib::ThumbSpec;
+
+use super::{prerender_queue::PrerenderJob, CacheEntry, RenderDetails};
+use crate::app::match_group_ext::{video_thumbnails_with_hash, THUMB_SPEC};
+use crate::app::ResolutionThunk;
+use crate::video_hash_filesystem_cache::{ThumbCrop, VideoHashFilesystemCache};
 
-use super::{CacheEntry, RenderDetails};
+// The thumbnails for an entry, or None if the render was cancelled part way through.
+pub type PrerenderRsp = (CacheEntry, Option<Vec<RgbImage>>);
 
 #[allow(clippy::type_complexity)]
-pub fn start_prerender_thread() -> (
+pub fn start_prerender_thread(
+    hash_cache: Arc<VideoHashFilesystemCache>,
+) -> (
     Vec<JoinHandle<()>>,
-    Sender<CacheEntry>,
-    Receiver<(CacheEntry, Vec<RgbImage>)>,
+    Sender<PrerenderJob<CacheEntry>>,
+    Receiver<PrerenderRsp>,
 ) {
-    let (cmd_tx, cmd_rx) = crossbeam_channel::bounded::<CacheEntry>(1);
+    let (cmd_tx, cmd_rx) = crossbeam_channel::bounded::<PrerenderJob<CacheEntry>>(1);
     let (rsp_tx, rsp_rx) = crossbeam_channel::bounded(1);
     let dbg_count = Arc::<AtomicUsize>::new(0.into());
     let rendering_current_vid = Arc::<AtomicBool>::new(false.into());
     let handles = (0..=3)
         .map(|_| {
             worker_thread(
+                hash_cache.clone(),
                 cmd_rx.clone(),
                 rsp_tx.clone(),
                 dbg_count.clone(),
@@ -50,14 +51,15 @@ pub fn start_prerender_thread() -> (
 }
 
 fn worker_thread(
-    cmd_rx: Receiver<CacheEntry>,
-    rsp_tx: Sender<(CacheEntry, Vec<RgbImage>)>,
+    hash_cache: Arc<VideoHashFilesystemCache>,
+    cmd_rx: Receiver<PrerenderJob<CacheEntry>>,
+    rsp_tx: Sender<PrerenderRsp>,
     dbg_count: Arc<AtomicUsize>,
     rendering_current_vid: Arc<AtomicBool>,
 ) -> JoinHandle<()> {
     std::thread::spawn({
         move || {
-            for entry in cmd_rx.iter() {
+            for PrerenderJob { entry, cancelled } in cmd_rx.iter() {
                 let _active_threads = dbg_count.fetch_add(1, Ordering::SeqCst) + 1;
                 // dbg!(active_threads);
 
@@ -66,7 +68,9 @@ fn worker_thread(
                 }
 
                 if !entry.render_details.is_current {
-                    while rendering_current_vid.load(Ordering::SeqCst) {
+                    while rendering_current_vid.load(Ordering::SeqCst)
+                        && !cancelled.load(Ordering::SeqCst)
+                    {
                         thread::sleep(Duration::from_millis(100));
                     }
                 }
@@ -75,14 +79,25 @@ fn worker_thread(
                     .thunk
                     .entries()
                     .into_par_iter()
-                    .map(|p| render_thumbs(p, entry.render_details))
-                    .collect();
+                    .map(|p| {
+                        render_thumbs(
+                            &hash_cache,
+                            &entry.thunk,
+                            p,
+                            entry.render_details,
+                            &cancelled,
+                        )
+                    })
+                    .collect::<Option<Vec<_>>>();
 
                 if entry.render_details.is_current {
                     rendering_current_vid.store(false, Ordering::SeqCst);
                 }
 
-                rsp_tx.send((entry, thumbs)).unwrap();
+                //the cache thread has stopped, so nothing more will be asked for.
+                if rsp_tx.send((entry, thumbs)).is_err() {
+                    break;
+                }
                 // let active_threads = dbg_count.fetch_sub(1, Ordering::SeqCst) - 1;
 
                 // if cmd_rx.is_empty() {
@@ -97,42 +112,131 @@ fn fallback_images() -> RgbImage {
     RgbImage::new(100, 100)
 }
 
-fn render_thumbs(src_path: &Path, render_details: RenderDetails) -> RgbImage {
-    let max_thumbs = 3;
-    let opts = CreationOptions::default();
-
-    #[cfg(feature = "gstreamer_backend")]
-    let cfg = build_frame_reader::<FrameReaderCfgGst>(src_path, opts);
+// How render_thumbs crops the thumbnails of src_path, one of the entries of thunk. The hash that
+// the thunk fetched from the cache records the crop that was used to make it, which is only found
+// again (from frames which might not be the same) if the hash has no record of it.
+pub fn thumb_crop(thunk: &ResolutionThunk, src_path: &Path, cropdetect: bool) -> ThumbCrop {
+    ThumbCrop::new(thunk.hash(src_path).as_ref(), cropdetect)
+}
 
-    #[cfg(feature = "ffmpeg_backend")]
-    let cfg = build_frame_reader::<FrameReaderCfgFfmpeg>(src_path, opts);
+// The thumbnails of a video side by side, made as they are for every other output (see
+// THUMB_SPEC), and cropped as thumb_crop says if asked for.
+fn render_thumbs(
+    hash_cache: &VideoHashFilesystemCache,
+    thunk: &ResolutionThunk,
+    src_path: &Path,
+    render_details: RenderDetails,
+    cancelled: &AtomicBool,
+) -> Option<RgbImage> {
+    if cancelled.load(Ordering::SeqCst) {
+        return None;
+    }
 
-    let frame_iter_cfg = match cfg {
-        Ok(obj) => obj,
-        Err(_e) => return fallback_images(),
+    let spec = ThumbSpec {
+        cropdetect: render_details.cropdetect,
+        ..THUMB_SPEC
     };
+    let hash = thunk.hash(src_path);
+    let img = video_thumbnails_with_hash(hash_cache, src_path, hash.as_ref(), &spec)
+        .and_then(|thumbs| row_images(thumbs.iter()))
+        .unwrap_or_else(fallback_images);
 
-    let mut frame_iter = frame_iter_cfg.spawn_rgb().peekable();
-
-    if matches!(frame_iter.peek(), None | Some(Err(_))) {
-        return fallback_images();
+    if cancelled.load(Ordering::SeqCst) {
+        return None;
     }
 
-    let thumbs = frame_iter
-        .step_by(8)
-        .filter_map(Result::ok)
-        .take(max_thumbs as usize)
-        .collect::<Vec<_>>();
+    Some(img)
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use vid_dup_finder_lib::Cropdetect;
+
+    use super::*;
+    use crate::app::match_group_ext::video_thumbnails;
+
+    #[test]
+    fn test_gui_thumbnails_match_thumbnail_files() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let dir = temp_dir.path();
+
+        let video = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("../vid_dup_finder_lib/examples/vids/cat.1.mp4");
+        let cache = VideoHashFilesystemCache::new(
+            100,
+            dir.join("cache.bin"),
+            Cropdetect::Letterbox,
+            0.0,
+            10.0,
+        )
+        .unwrap();
+        let thunk = ResolutionThunk::from_paths(None, &[video.to_str().unwrap()]);
+
+        for cropdetect in [false, true] {
+            let details = RenderDetails {
+                cropdetect,
+                is_current: false,
+            };
+            let rendered = render_thumbs(&cache, &thunk, &video, details, &AtomicBool::new(false));
+
+            //the same frames, in the same row, as thumbnail files and reports are made from.
+            let spec = ThumbSpec {
+                cropdetect,
+                ..THUMB_SPEC
+            };
+            let expected = video_thumbnails(&cache, &video, &spec)
+                .and_then(|thumbs| row_images(thumbs.iter()))
+                .unwrap_or_else(fallback_images);
+            assert!(
+                rendered.as_ref() == Some(&expected),
+                "cropdetect: {cropdetect}"
+            );
+        }
+
+        //nothing is rendered once the view has moved on.
+        let details = RenderDetails {
+            cropdetect: false,
+            is_current: false,
+        };
+        assert!(render_thumbs(&cache, &thunk, &video, details, &AtomicBool::new(true)).is_none());
+    }
 
-    if render_details.cropdetect {
-        let uncropped_frames =
-            VideoFramesGray::from_images(thumbs.iter().map(|t| t.convert())).unwrap();
-        let crop = uncropped_frames.motiondetect_crop();
-        //dbg!(crop);
-        let frames = FrameSeqRgb::from_images(thumbs).unwrap().crop(crop);
+    #[test]
+    fn test_thumbnails_are_cropped_as_the_cached_hash_was() {
+        use vid_dup_finder_lib::{Crop, MatchGroup, VideoHash};
+
+        use crate::app::TrashLayout;
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let dir = temp_dir.path();
+
+        let cache = VideoHashFilesystemCache::new(
+            100,
+            dir.join("cache.bin"),
+            Cropdetect::Letterbox,
+            0.0,
+            10.0,
+        )
+        .unwrap();
+
+        //one hash records the letterbox it was cropped to, and the other is from before crops
+        //were recorded.
+        let crop = Crop::from_edge_offsets((640, 480), 0, 0, 60, 60);
+        let (boxed, old) = (dir.join("boxed.mp4"), dir.join("old.mp4"));
+        for (path, hash) in [
+            (&boxed, VideoHash::full_hash(&boxed).with_raw_crop(crop)),
+            (&old, VideoHash::full_hash(&old)),
+        ] {
+            std::fs::write(path, "").unwrap();
+            cache.import_entry(hash.with_duration(30)).unwrap();
+        }
 
-        row_images(frames.into_inner().iter()).unwrap()
-    } else {
-        row_images(thumbs.iter()).unwrap()
+        let group = MatchGroup::new([boxed.clone(), old.clone()]).unwrap();
+        let thunk = ResolutionThunk::from_matchgroup(&group, &cache, None, TrashLayout::default());
+        assert_eq!(thumb_crop(&thunk, &boxed, true), ThumbCrop::FromHash(crop));
+        assert_eq!(thumb_crop(&thunk, &old, true), ThumbCrop::Recomputed);
+        assert_eq!(thumb_crop(&thunk, &boxed, false), ThumbCrop::Uncropped);
     }
 }
@@ -0,0 +1,236 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    hash::Hash,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use crossbeam_channel::Sender;
+
+use super::GuiRsp;
+
+/// A thumbnail render handed to a prerender worker. The worker should give up
+/// (between frames) as soon as `cancelled` is set.
+#[derive(Debug)]
+pub struct PrerenderJob<K> {
+    pub entry: K,
+    pub cancelled: Arc<AtomicBool>,
+}
+
+#[derive(Debug)]
+struct InFlight {
+    generation: u64,
+    cancelled: Arc<AtomicBool>,
+}
+
+/// Bookkeeping for thumbnails which are waiting to be rendered, or are being
+/// rendered right now.
+///
+/// Every batch of prerender commands from the gui carries a generation number.
+/// When a Fetch for a newer generation arrives, anything queued for an older
+/// generation is dropped and older renders which are already running are
+/// cancelled, so that skipping far ahead doesn't leave the workers busy with
+/// thumbnails nobody is going to look at.
+///
+/// The gui's queue length is kept in step: one IncQLen is sent when an entry is
+/// first wanted, and exactly one DecQLen when it is dropped or finished.
+pub struct PrerenderQueue<K> {
+    queued: VecDeque<(K, u64)>,
+    in_flight: HashMap<K, InFlight>,
+    latest_fetch: u64,
+    rsp_tx: Sender<GuiRsp>,
+}
+
+impl<K: Clone + Eq + Hash> PrerenderQueue<K> {
+    pub fn new(rsp_tx: Sender<GuiRsp>) -> Self {
+        Self {
+            queued: VecDeque::new(),
+            in_flight: HashMap::new(),
+            latest_fetch: 0,
+            rsp_tx,
+        }
+    }
+
+    /// Number of entries which are queued or being rendered.
+    #[cfg(test)]
+    pub fn len(&self) -> usize {
+        self.queued.len() + self.in_flight.len()
+    }
+
+    /// Ask for an entry to be rendered, unless its generation has already been
+    /// superseded by a Fetch.
+    pub fn generate(&mut self, entry: K, generation: u64) {
+        if generation < self.latest_fetch {
+            return;
+        }
+
+        //already being rendered: make sure it isn't thrown away.
+        if let Some(in_flight) = self.in_flight.get_mut(&entry) {
+            in_flight.generation = in_flight.generation.max(generation);
+            in_flight.cancelled.store(false, Ordering::SeqCst);
+            return;
+        }
+
+        if let Some((_, queued_gen)) = self.queued.iter_mut().find(|(e, _)| *e == entry) {
+            *queued_gen = (*queued_gen).max(generation);
+            return;
+        }
+
+        self.queued.push_back((entry, generation));
+        self.rsp_tx.send(GuiRsp::IncQLen).unwrap();
+    }
+
+    /// Start a new generation. Everything wanted only by older generations is
+    /// dropped from the queue, and older in-flight renders are cancelled.
+    pub fn fetch(&mut self, generation: u64) {
+        self.latest_fetch = self.latest_fetch.max(generation);
+
+        let latest_fetch = self.latest_fetch;
+        let before = self.queued.len();
+        self.queued.retain(|(_, gen)| *gen >= latest_fetch);
+        for _ in self.queued.len()..before {
+            self.rsp_tx.send(GuiRsp::DecQLen).unwrap();
+        }
+
+        for in_flight in self.in_flight.values() {
+            if in_flight.generation < latest_fetch {
+                in_flight.cancelled.store(true, Ordering::SeqCst);
+            }
+        }
+    }
+
+    /// Take the next entry to be rendered.
+    pub fn next_job(&mut self) -> Option<PrerenderJob<K>> {
+        let (entry, generation) = self.queued.pop_front()?;
+        let cancelled = Arc::new(AtomicBool::new(false));
+        self.in_flight.insert(
+            entry.clone(),
+            InFlight {
+                generation,
+                cancelled: cancelled.clone(),
+            },
+        );
+
+        Some(PrerenderJob { entry, cancelled })
+    }
+
+    /// A worker has finished with an entry, either by rendering it or by giving up.
+    ///
+    /// A render can be abandoned just before the entry is wanted again, in which
+    /// case it goes back to the front of the queue.
+    pub fn finished(&mut self, entry: &K, rendered: bool) {
+        let Some(in_flight) = self.in_flight.remove(entry) else {
+            return;
+        };
+
+        let wanted = !in_flight.cancelled.load(Ordering::SeqCst);
+        if !rendered && wanted {
+            self.queued
+                .push_front((entry.clone(), in_flight.generation));
+        } else {
+            self.rsp_tx.send(GuiRsp::DecQLen).unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crossbeam_channel::Receiver;
+
+    use super::*;
+
+    // The queue length the gui would display, from the messages sent so far.
+    fn drain_qlen(rsp_rx: &Receiver<GuiRsp>, qlen: &mut i64) {
+        for rsp in rsp_rx.try_iter() {
+            match rsp {
+                GuiRsp::IncQLen => *qlen += 1,
+                GuiRsp::DecQLen => *qlen -= 1,
+                other => panic!("unexpected response: {other:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_skipping_ahead_drops_stale_work() {
+        let (rsp_tx, rsp_rx) = crossbeam_channel::unbounded();
+        let mut q = PrerenderQueue::new(rsp_tx);
+        let mut qlen = 0;
+
+        q.fetch(1);
+        for entry in 0..5 {
+            q.generate(entry, 1);
+        }
+        let first = q.next_job().unwrap();
+        let second = q.next_job().unwrap();
+        drain_qlen(&rsp_rx, &mut qlen);
+        assert_eq!(qlen, 5);
+
+        //skip far ahead. Entry 1 happens to still be wanted.
+        q.fetch(2);
+        q.generate(100, 2);
+        q.generate(1, 2);
+        drain_qlen(&rsp_rx, &mut qlen);
+        assert_eq!(qlen, q.len() as i64);
+
+        assert!(first.cancelled.load(Ordering::SeqCst));
+        assert!(!second.cancelled.load(Ordering::SeqCst));
+
+        //the stale render gives up, the wanted one completes.
+        q.finished(&first.entry, false);
+        q.finished(&second.entry, true);
+        drain_qlen(&rsp_rx, &mut qlen);
+        assert_eq!(qlen, 1);
+        assert_eq!(q.next_job().map(|job| job.entry), Some(100));
+        assert!(q.next_job().is_none());
+
+        q.finished(&100, true);
+        drain_qlen(&rsp_rx, &mut qlen);
+        assert_eq!(qlen, 0);
+        assert_eq!(q.len(), 0);
+    }
+
+    #[test]
+    fn test_commands_older_than_latest_fetch_are_ignored() {
+        let (rsp_tx, rsp_rx) = crossbeam_channel::unbounded();
+        let mut q = PrerenderQueue::new(rsp_tx);
+        let mut qlen = 0;
+
+        q.fetch(3);
+        q.generate(7, 2);
+        q.generate(8, 3);
+        q.generate(8, 3);
+        drain_qlen(&rsp_rx, &mut qlen);
+
+        assert_eq!(qlen, 1);
+        assert_eq!(q.next_job().map(|job| job.entry), Some(8));
+    }
+
+    #[test]
+    fn test_abandoned_render_which_is_wanted_again_is_requeued() {
+        let (rsp_tx, rsp_rx) = crossbeam_channel::unbounded();
+        let mut q = PrerenderQueue::new(rsp_tx);
+        let mut qlen = 0;
+
+        q.fetch(1);
+        q.generate(5, 1);
+        let job = q.next_job().unwrap();
+        q.fetch(2);
+        assert!(job.cancelled.load(Ordering::SeqCst));
+
+        //the gui comes back to the entry before the worker has reported that it gave up.
+        q.fetch(3);
+        q.generate(5, 3);
+        assert!(!job.cancelled.load(Ordering::SeqCst));
+        q.finished(&5, false);
+        drain_qlen(&rsp_rx, &mut qlen);
+        assert_eq!(qlen, 1);
+
+        let job = q.next_job().unwrap();
+        assert_eq!(job.entry, 5);
+        q.finished(&job.entry, true);
+        drain_qlen(&rsp_rx, &mut qlen);
+        assert_eq!(qlen, 0);
+    }
+}
@@ -0,0 +1,205 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io::BufReader,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum SessionStateError {
+    #[error("Failed to read or write gui session at {0}: {1}")]
+    Io(PathBuf, #[source] std::io::Error),
+
+    #[error("Failed to parse gui session at {0}: {1}")]
+    Parse(PathBuf, #[source] serde_json::Error),
+}
+
+/// Where the GUI had got to when it was last closed, so that reviewing a long list of groups
+/// can be spread over several runs.
+///
+/// The state only makes sense for the exact list of groups it was saved with, so it is stored
+/// alongside a fingerprint of their [group ids][super::ResolutionThunk::group_id], in order.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SessionState {
+    pub fingerprint: String,
+    pub thunk_idx: usize,
+    // Keyed by group id.
+    pub excluded: BTreeMap<String, BTreeSet<PathBuf>>,
+    pub cropdetect: bool,
+    pub zoom: f32,
+    pub disable_stats: bool,
+    // The commands used to play videos and to show them in a file manager. These are kept even
+    // when the rest of the session is stale, as they do not depend on the groups.
+    #[serde(default)]
+    pub player_cmd: Option<String>,
+    #[serde(default)]
+    pub browser_cmd: Option<String>,
+}
+
+/// The result of [`SessionState::load`].
+#[derive(Debug, PartialEq)]
+pub enum LoadedSession {
+    /// No session has been saved.
+    Missing,
+    /// A session was saved, but for a different list of groups.
+    Stale(SessionState),
+    Restored(SessionState),
+}
+
+/// The session file for the hash cache at the given path.
+pub fn session_state_path(cache_path: &Path) -> PathBuf {
+    cache_path.with_extension("gui_session.json")
+}
+
+/// Fingerprint a list of group ids. Reordering the list changes the fingerprint, as thunk
+/// indexes would then refer to different groups.
+pub fn fingerprint(group_ids: impl IntoIterator<Item = impl AsRef<str>>) -> String {
+    let mut hasher = blake3::Hasher::new();
+    for group_id in group_ids {
+        hasher.update(group_id.as_ref().as_bytes());
+        hasher.update(b"\n");
+    }
+    hasher.finalize().to_hex().to_string()
+}
+
+impl SessionState {
+    pub fn is_excluded(&self, group_id: &str, path: &Path) -> bool {
+        self.excluded
+            .get(group_id)
+            .is_some_and(|excluded| excluded.contains(path))
+    }
+
+    pub fn excluded_from(&self, group_id: &str) -> BTreeSet<PathBuf> {
+        self.excluded.get(group_id).cloned().unwrap_or_default()
+    }
+
+    pub fn exclude(&mut self, group_id: &str, path: &Path) {
+        self.excluded
+            .entry(group_id.to_string())
+            .or_default()
+            .insert(path.to_path_buf());
+    }
+
+    /// Load the session saved at the given path, if it was saved for the groups with the given
+    /// fingerprint.
+    pub fn load(path: &Path, fingerprint: &str) -> Result<LoadedSession, SessionStateError> {
+        if !path.exists() {
+            return Ok(LoadedSession::Missing);
+        }
+
+        let f = std::fs::File::open(path).map_err(|e| SessionStateError::Io(path.to_owned(), e))?;
+        let state: Self = serde_json::from_reader(BufReader::new(f))
+            .map_err(|e| SessionStateError::Parse(path.to_owned(), e))?;
+
+        if state.fingerprint == fingerprint {
+            Ok(LoadedSession::Restored(state))
+        } else {
+            Ok(LoadedSession::Stale(state))
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), SessionStateError> {
+        if let Some(parent_dir) = path.parent() {
+            std::fs::create_dir_all(parent_dir)
+                .map_err(|e| SessionStateError::Io(path.to_owned(), e))?;
+        }
+
+        //write to a temporary file first so that a session is never half written.
+        let tmp_file = path.with_extension("json.tmp");
+        let contents = serde_json::to_vec_pretty(self)
+            .map_err(|e| SessionStateError::Parse(path.to_owned(), e))?;
+        std::fs::write(&tmp_file, contents)
+            .and_then(|()| std::fs::rename(&tmp_file, path))
+            .map_err(|e| SessionStateError::Io(path.to_owned(), e))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn state(fingerprint: String) -> SessionState {
+        let mut state = SessionState {
+            fingerprint,
+            thunk_idx: 17,
+            excluded: BTreeMap::new(),
+            cropdetect: false,
+            zoom: 350.0,
+            disable_stats: false,
+            player_cmd: Some("mpv {path}".to_string()),
+            browser_cmd: None,
+        };
+        state.exclude("group_a", Path::new("/vids/a.mp4"));
+        state.exclude("group_a", Path::new("/vids/b.mp4"));
+        state
+    }
+
+    #[test]
+    fn test_session_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = session_state_path(&dir.path().join("cache.bin"));
+        let fingerprint = fingerprint(["group_a", "group_b"]);
+
+        assert_eq!(
+            SessionState::load(&path, &fingerprint).unwrap(),
+            LoadedSession::Missing
+        );
+
+        let state = state(fingerprint.clone());
+        state.save(&path).unwrap();
+        assert_eq!(
+            SessionState::load(&path, &fingerprint).unwrap(),
+            LoadedSession::Restored(state.clone())
+        );
+
+        let LoadedSession::Restored(restored) = SessionState::load(&path, &fingerprint).unwrap()
+        else {
+            panic!("session was not restored");
+        };
+        assert!(restored.is_excluded("group_a", Path::new("/vids/a.mp4")));
+        assert!(!restored.is_excluded("group_a", Path::new("/vids/c.mp4")));
+        assert!(!restored.is_excluded("group_b", Path::new("/vids/a.mp4")));
+    }
+
+    #[test]
+    fn test_session_for_different_groups_is_stale() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = session_state_path(&dir.path().join("cache.bin"));
+
+        state(fingerprint(["group_a", "group_b"]))
+            .save(&path)
+            .unwrap();
+
+        //a group was resolved, or the groups came out in a different order.
+        for changed in [
+            fingerprint(["group_b"]),
+            fingerprint(["group_b", "group_a"]),
+        ] {
+            let LoadedSession::Stale(stale) = SessionState::load(&path, &changed).unwrap() else {
+                panic!("session was not stale");
+            };
+            assert_eq!(stale.player_cmd.as_deref(), Some("mpv {path}"));
+        }
+
+        std::fs::write(&path, "not json").unwrap();
+        assert!(matches!(
+            SessionState::load(&path, &fingerprint(["group_a", "group_b"])),
+            Err(SessionStateError::Parse(..))
+        ));
+    }
+
+    #[test]
+    fn test_sessions_saved_before_commands_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = session_state_path(&dir.path().join("cache.bin"));
+
+        let old = r#"{"fingerprint":"","thunk_idx":1,"excluded":{},"cropdetect":true,"zoom":100.0,"disable_stats":true}"#;
+        std::fs::write(&path, old).unwrap();
+        let LoadedSession::Restored(old) = SessionState::load(&path, "").unwrap() else {
+            panic!("session was not restored");
+        };
+        assert_eq!((old.player_cmd, old.browser_cmd), (None, None));
+    }
+}
@@ -0,0 +1,139 @@
+//! Stopping cleanly on SIGINT or SIGTERM (Ctrl+C on windows).
+//!
+//! While a cache is being updated, the first signal cancels the update: videos which are already
+//! being hashed are allowed to finish, and then the caches are saved and the app exits with
+//! [`EXIT_INTERRUPTED`]. A second signal exits immediately. At any other time there is nothing
+//! to save, so the first signal exits immediately.
+
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+use vid_dup_finder_lib::CancellationToken;
+
+use super::search_output::EXIT_INTERRUPTED;
+
+#[derive(Debug, Clone, Default)]
+pub struct Interrupt {
+    token: CancellationToken,
+    //true while a signal should cancel work rather than exit.
+    graceful: Arc<AtomicBool>,
+}
+
+impl Interrupt {
+    /// Handle SIGINT and SIGTERM for the rest of the run. If the handler cannot be installed, a
+    /// warning is logged and signals kill the app as before.
+    pub fn install() -> Self {
+        let ret = Self::default();
+        let handler = ret.clone();
+        if let Err(e) = ctrlc::set_handler(move || handler.on_signal()) {
+            warn!("Failed to handle interrupts, so interrupting will lose unsaved hashes: {e}");
+        }
+        ret
+    }
+
+    fn on_signal(&self) {
+        if !self.graceful.load(Ordering::SeqCst) || self.token.is_cancelled() {
+            std::process::exit(EXIT_INTERRUPTED);
+        }
+        self.token.cancel();
+        warn!("Interrupted. Waiting for the videos being hashed to finish so that the cache can be saved. Interrupt again to exit immediately");
+    }
+
+    /// Cancelled by the first signal received during [`Interrupt::graceful`].
+    pub fn token(&self) -> &CancellationToken {
+        &self.token
+    }
+
+    pub fn is_interrupted(&self) -> bool {
+        self.token.is_cancelled()
+    }
+
+    /// Cancel [`Interrupt::token`] on the next signal, rather than exiting, until the returned
+    /// guard is dropped.
+    pub fn graceful(&self) -> GracefulGuard<'_> {
+        self.graceful.store(true, Ordering::SeqCst);
+        GracefulGuard(&self.graceful)
+    }
+}
+
+pub struct GracefulGuard<'a>(&'a AtomicBool);
+
+impl Drop for GracefulGuard<'_> {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        path::{Path, PathBuf},
+        sync::atomic::AtomicUsize,
+    };
+
+    use vid_dup_finder_lib::Cropdetect;
+
+    use super::*;
+    use crate::video_hash_filesystem_cache::{UpdateOptions, VideoHashFilesystemCache};
+
+    #[test]
+    fn test_interrupted_updates_keep_finished_hashes() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let dir = temp_dir.path();
+
+        //files which are not videos, so that each is cached as an error.
+        let files = (0..100)
+            .map(|i| {
+                let path = dir.join(format!("{i:03}.txt"));
+                std::fs::write(&path, "not a video").unwrap();
+                path
+            })
+            .collect::<Vec<PathBuf>>();
+
+        let cache_path = dir.join("cache.bin");
+        let open = || {
+            VideoHashFilesystemCache::new(10_000, cache_path.clone(), Cropdetect::None, 0.0, 10.0)
+                .unwrap()
+        };
+
+        //as if a signal arrived after the third file.
+        let interrupt = Interrupt::default();
+        let done = AtomicUsize::new(0);
+        let on_file_done = |_: &Path, _: &_| {
+            if done.fetch_add(1, Ordering::SeqCst) == 2 {
+                interrupt.on_signal();
+            }
+        };
+        let cache = open();
+        let report = {
+            let _graceful = interrupt.graceful();
+            cache.update_using_fs(
+                files.iter().cloned(),
+                UpdateOptions {
+                    cancellation: Some(interrupt.token()),
+                    on_file_done: Some(&on_file_done),
+                    ..UpdateOptions::default()
+                },
+            )
+        };
+        assert!(interrupt.is_interrupted());
+        assert!(report.cancelled);
+        assert!(report.num_attempted() < files.len());
+        assert!(!interrupt.graceful.load(Ordering::SeqCst));
+
+        //every file that finished is kept once the cache is saved.
+        cache.save().unwrap();
+        drop(cache);
+        let mut cached = open().error_paths();
+        cached.sort();
+        let mut attempted = report
+            .failed
+            .into_iter()
+            .map(|(path, _)| path)
+            .collect::<Vec<_>>();
+        attempted.sort();
+        assert_eq!(cached, attempted);
+    }
+}
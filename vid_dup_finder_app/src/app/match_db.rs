@@ -8,7 +8,7 @@ use std::{
 use itertools::Itertools;
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
-use vid_dup_finder_lib::MatchGroup;
+use vid_dup_finder_lib::{CancellationToken, MatchGroup};
 
 use crate::video_hash_filesystem_cache::file_hash_filesystem_cache::{
     FileContentCache, FileContentCacheErrorKind,
@@ -61,6 +61,84 @@ pub struct MatchMapEntry {
     pub content_hash: ContentHash,
 }
 
+/// Which of the two databases given to [`MatchDb::merge`] something was found in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MergeSide {
+    /// The database that was merged into.
+    Ours,
+    /// The database that was merged from.
+    Theirs,
+    Both,
+    /// Neither on its own: the paths were only grouped once the groups of both were combined.
+    Combined,
+}
+
+impl MergeSide {
+    fn from_membership(ours: bool, theirs: bool) -> Self {
+        match (ours, theirs) {
+            (true, true) => Self::Both,
+            (true, false) => Self::Ours,
+            (false, true) => Self::Theirs,
+            (false, false) => Self::Combined,
+        }
+    }
+}
+
+/// Something [`MatchDb::merge`] could not reconcile, which is left for the user to resolve.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MergeConflict {
+    /// The two paths are in the same confirmed group of the merged database, but are also a false
+    /// positive. Both entries are kept.
+    ConfirmedAndFalsepos {
+        paths: [PathBuf; 2],
+        confirmed_in: MergeSide,
+        falsepos_in: MergeSide,
+    },
+
+    /// The databases recorded different contents for the same path, so they are not talking about
+    /// the same file. The confirmed pairs of the path are not taken from the other database.
+    ContentHash {
+        path: PathBuf,
+        ours: ContentHash,
+        theirs: ContentHash,
+    },
+}
+
+impl std::fmt::Display for MergeConflict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let hex = |hash: &ContentHash| hash.iter().map(|b| format!("{b:02x}")).collect::<String>();
+        match self {
+            Self::ConfirmedAndFalsepos {
+                paths: [p1, p2],
+                confirmed_in,
+                falsepos_in,
+            } => write!(
+                f,
+                "{} and {} are confirmed ({confirmed_in:?}) and a false positive ({falsepos_in:?})",
+                p1.display(),
+                p2.display()
+            ),
+            Self::ContentHash { path, ours, theirs } => write!(
+                f,
+                "{} has different contents: ours {}, theirs {}",
+                path.display(),
+                hex(ours),
+                hex(theirs)
+            ),
+        }
+    }
+}
+
+/// The changes made by [`MatchDb::merge`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MergeReport {
+    /// Confirmed groups which are new or gained members, with all of their members.
+    pub added_groups: Vec<Vec<PathBuf>>,
+    /// False positive pairs which were only in the other database.
+    pub added_falsepos: Vec<[PathBuf; 2]>,
+    pub conflicts: Vec<MergeConflict>,
+}
+
 //Maps filesystem paths into other paths (those which contain duplicates)
 //
 #[derive(Debug, Default, Clone, Serialize, Deserialize)]
@@ -100,6 +178,24 @@ impl MatchMap {
         self.map.contains_pair(p1, p2)
     }
 
+    //Removes a confirmed pair. Only the group containing it is affected: it is split up according to
+    //the confirmed pairs that remain, so the two paths may still be grouped through a third path
+    //that was confirmed against both of them.
+    pub fn unmatch_pair(&mut self, p1: impl AsRef<Path>, p2: impl AsRef<Path>) {
+        let p1 = p1.as_ref();
+        let p2 = p2.as_ref();
+        if !self.map.split(p1, p2) {
+            return;
+        }
+
+        //only the two paths themselves can have been left without a group.
+        for p in [p1, p2] {
+            if !self.map.contains(p) {
+                self.file_hashes.remove(p);
+            }
+        }
+    }
+
     #[allow(dead_code)]
     pub fn remove_path(&mut self, p: impl AsRef<Path>) {
         let p = p.as_ref();
@@ -111,6 +207,55 @@ impl MatchMap {
         let _ = self.file_hashes.remove(p);
     }
 
+    //The entries whose files no longer exist, each with a member of the same group whose file
+    //still exists, if there is one.
+    pub fn deleted_items(&self) -> Vec<(MatchMapEntry, Option<PathBuf>)> {
+        self.map
+            .all_sets()
+            .flat_map(|set| {
+                let (deleted, existing): (Vec<_>, Vec<_>) = set.partition(|p| !p.exists());
+                let partner = existing.first().map(|p| p.to_path_buf());
+                deleted.into_iter().filter_map(move |p| {
+                    let entry = MatchMapEntry {
+                        path: p.clone(),
+                        content_hash: *self.file_hashes.get(p)?,
+                    };
+                    Some((entry, partner.clone()))
+                })
+            })
+            .collect()
+    }
+
+    //Adds the confirmed pairs of `other`. Pairs with a path whose content differs between the two
+    //maps are left out, and the paths are returned as conflicts.
+    pub fn merge(&mut self, other: &MatchMap) -> Vec<MergeConflict> {
+        let mismatched = other
+            .file_hashes
+            .iter()
+            .filter_map(|(path, theirs)| {
+                let ours = self.file_hashes.get(path)?;
+                (ours != theirs).then(|| (path.clone(), (*ours, *theirs)))
+            })
+            .collect::<BTreeMap<_, _>>();
+
+        for (p1, p2) in other.map.all_pairs() {
+            if mismatched.contains_key(p1) || mismatched.contains_key(p2) {
+                continue;
+            }
+            self.map.insert(p1.clone(), p2.clone());
+            for p in [p1, p2] {
+                if let Some(hash) = other.file_hashes.get(p) {
+                    self.file_hashes.entry(p.clone()).or_insert(*hash);
+                }
+            }
+        }
+
+        mismatched
+            .into_iter()
+            .map(|(path, (ours, theirs))| MergeConflict::ContentHash { path, ours, theirs })
+            .collect()
+    }
+
     //iterates through every entry, and checks that each file inside actually
     //exists on disk. If not, then removes the entry
     pub fn remove_deleted_items(&mut self) {
@@ -186,6 +331,20 @@ impl FalseposMap {
             .map(|dups| dups.iter().map(PathBuf::as_path))
     }
 
+    pub fn contains(&self, p1: impl AsRef<Path>, p2: impl AsRef<Path>) -> bool {
+        let p1_falsepos = match self.get_entries(&p1) {
+            Some(mut entries) => entries.contains(&p2.as_ref()),
+            None => false,
+        };
+
+        let p2_falsepos = match self.get_entries(&p2) {
+            Some(mut entries) => entries.contains(&p1.as_ref()),
+            None => false,
+        };
+
+        p1_falsepos || p2_falsepos
+    }
+
     fn remove_path(&mut self, path_to_remove: impl AsRef<Path>) {
         let path_to_remove = path_to_remove.as_ref();
         let mut entries_to_remove = vec![];
@@ -251,21 +410,54 @@ impl MatchDb {
     }
 
     pub fn content_cache_path(db_path: impl AsRef<Path>) -> PathBuf {
+        db_path.as_ref().join("content_ids.bin")
+    }
+
+    //held the full hash of every file, before files were fingerprinted.
+    fn legacy_content_cache_path(db_path: impl AsRef<Path>) -> PathBuf {
         db_path.as_ref().join("content_cache.bin")
     }
 
+    fn open_content_cache(db_path: impl AsRef<Path>) -> MatchDbResult<FileContentCache> {
+        let cache_path = Self::content_cache_path(&db_path);
+        let legacy_path = Self::legacy_content_cache_path(&db_path);
+        let needs_migration = !cache_path.exists() && legacy_path.exists();
+
+        let content_cache = FileContentCache::new(200, cache_path.clone())
+            .map_err(|e| MatchDbError::FileContentCacheError(cache_path.clone(), e))?;
+        if needs_migration {
+            let count = content_cache
+                .migrate_full_hashes(legacy_path.clone())
+                .map_err(|e| MatchDbError::FileContentCacheError(legacy_path.clone(), e))?;
+            content_cache
+                .save()
+                .map_err(|e| MatchDbError::FileContentCacheError(cache_path, e))?;
+            info!("Migrated {count} entries to the fingerprinted file content cache");
+
+            //Everything is in the new cache now. If the old one cannot be removed it is harmless,
+            //because it is only migrated while there is no new cache.
+            if let Err(e) = std::fs::remove_file(&legacy_path) {
+                warn!(
+                    "Failed to remove the old file content cache at {}: {e}",
+                    legacy_path.display()
+                );
+            }
+        }
+        Ok(content_cache)
+    }
+
     pub fn raw_data_path(db_path: impl AsRef<Path>) -> PathBuf {
         db_path.as_ref().join("../manual_inputs")
     }
 
-    pub fn new(db_path: impl AsRef<Path>) -> Self {
-        Self {
-            content_cache: FileContentCache::new(200, Self::content_cache_path(&db_path)).unwrap(),
+    pub fn new(db_path: impl AsRef<Path>) -> MatchDbResult<Self> {
+        Ok(Self {
+            content_cache: Self::open_content_cache(&db_path)?,
             db_path: db_path.as_ref().to_owned(),
 
             confirmed: MatchMap::default(),
             falsepos: FalseposMap::default(),
-        }
+        })
     }
 
     pub fn confirmed_and_falsepos_entries(&self) -> impl Iterator<Item = (&PathBuf, &PathBuf)> {
@@ -287,28 +479,8 @@ impl MatchDb {
         self.confirmed.is_confirmed(p1, p2)
     }
 
-    pub fn all_confirmed(
-        &self,
-        paths: impl IntoIterator<Item = impl AsRef<Path>>,
-        cand_path: impl AsRef<Path>,
-    ) -> bool {
-        paths
-            .into_iter()
-            .all(|group_path| self.is_confirmed(group_path, &cand_path))
-    }
-
     pub fn is_falsepos(&self, p1: impl AsRef<Path>, p2: impl AsRef<Path>) -> bool {
-        let p1_falsepos = match self.falsepos.get_entries(&p1) {
-            Some(mut entries) => entries.contains(&p2.as_ref()),
-            None => false,
-        };
-
-        let p2_falsepos = match self.falsepos.get_entries(&p2) {
-            Some(mut entries) => entries.contains(&p1.as_ref()),
-            None => false,
-        };
-
-        p1_falsepos || p2_falsepos
+        self.falsepos.contains(p1, p2)
     }
 
     pub fn confirmed_groups(&self) -> impl Iterator<Item = MatchGroup> {
@@ -347,6 +519,67 @@ impl MatchDb {
         self.confirmed.remove_path(p);
     }
 
+    /// Add the confirmed groups and false positives of `other`. Groups which share a path are
+    /// joined. Conflicts are listed in the report rather than resolved, and pairs which were
+    /// already both confirmed and false positives here are not reported again. The result is
+    /// only written to disk by [`MatchDb::to_disk`].
+    pub fn merge(&mut self, other: &MatchDb) -> MergeReport {
+        let group_paths = |group: MatchGroup| {
+            group
+                .contained_paths()
+                .map(Path::to_path_buf)
+                .collect::<BTreeSet<_>>()
+        };
+        let groups_before = self
+            .confirmed_groups()
+            .map(group_paths)
+            .collect::<BTreeSet<_>>();
+        let confirmed_before = self.confirmed.clone();
+        let falsepos_before = self.falsepos.clone();
+
+        let mut conflicts = self.confirmed.merge(&other.confirmed);
+
+        let mut added_falsepos = vec![];
+        for [p1, p2] in other.falsepos.all_entries() {
+            if !self.falsepos.contains(p1, p2) {
+                self.falsepos.insert([p1, p2].into_iter());
+                added_falsepos.push([p1.to_path_buf(), p2.to_path_buf()]);
+            }
+        }
+
+        for [p1, p2] in self.falsepos.all_entries() {
+            let already_conflicting =
+                confirmed_before.is_confirmed(p1, p2) && falsepos_before.contains(p1, p2);
+            if !self.is_confirmed(p1, p2) || already_conflicting {
+                continue;
+            }
+            conflicts.push(MergeConflict::ConfirmedAndFalsepos {
+                paths: [p1.to_path_buf(), p2.to_path_buf()],
+                confirmed_in: MergeSide::from_membership(
+                    confirmed_before.is_confirmed(p1, p2),
+                    other.is_confirmed(p1, p2),
+                ),
+                falsepos_in: MergeSide::from_membership(
+                    falsepos_before.contains(p1, p2),
+                    other.is_falsepos(p1, p2),
+                ),
+            });
+        }
+
+        let added_groups = self
+            .confirmed_groups()
+            .map(group_paths)
+            .filter(|group| !groups_before.contains(group))
+            .map(|group| group.into_iter().collect())
+            .collect();
+
+        MergeReport {
+            added_groups,
+            added_falsepos,
+            conflicts,
+        }
+    }
+
     ////////////////////////////////////////////////////////////////////////////////
     // Serialization/Deserialization
     ////////////////////////////////////////////////////////////////////////////////
@@ -463,7 +696,7 @@ impl MatchDb {
     pub fn from_disk(db_path: impl AsRef<Path>) -> MatchDbResult<Self> {
         let db_path = db_path.as_ref();
 
-        let content_cache = FileContentCache::new(200, Self::content_cache_path(db_path)).unwrap();
+        let content_cache = Self::open_content_cache(db_path)?;
 
         //read confirmed entries from disk
         let confirmed = {
@@ -520,7 +753,7 @@ impl MatchDb {
     ////////////////////////////////////////////////////////////////////////////////
 
     fn create_match_map_entry(&self, p: PathBuf) -> Result<MatchMapEntry, MatchDbError> {
-        match self.content_cache.fetch(&p) {
+        match self.content_cache.full_hash(&p) {
             Err(e) => Err(MatchDbError::FileContentCacheError(p, e)),
             Ok(hash) => Ok(MatchMapEntry {
                 path: p,
@@ -559,35 +792,9 @@ impl MatchDb {
         }
 
         for group in unmatch_path_entries {
-            let all_pairs_to_unmatch = group
-                .iter()
-                .tuple_combinations::<(_, _)>()
-                .collect::<Vec<_>>();
-
-            let mut new_match_map = MatchMap::default();
-            for group in self.confirmed.all_groups() {
-                if !all_pairs_to_unmatch
-                    .iter()
-                    .any(|(unmatch_path_1, unmatch_path_2)| {
-                        let unmatch_path_1_found = group
-                            .contained_paths()
-                            .any(|p| p == unmatch_path_1.as_path());
-                        let unmatch_path_2_found = group
-                            .contained_paths()
-                            .any(|p| p == unmatch_path_2.as_path());
-
-                        unmatch_path_1_found && unmatch_path_2_found
-                    })
-                {
-                    let paths = group.contained_paths().collect::<Vec<_>>();
-                    for (e1, e2) in paths.iter().tuple_combinations::<(_, _)>() {
-                        let e1 = self.create_match_map_entry(e1.to_path_buf())?;
-                        let e2 = self.create_match_map_entry(e2.to_path_buf())?;
-                        new_match_map.insert(e1, e2);
-                    }
-                }
+            for (p1, p2) in group.iter().tuple_combinations() {
+                self.confirmed.unmatch_pair(p1, p2);
             }
-            self.confirmed = new_match_map;
         }
 
         Ok(())
@@ -680,9 +887,12 @@ impl MatchDb {
         process_path(num_files_path)
     }
 
+    /// Update the content cache from `paths`, and save it. If `cancellation` is cancelled, the
+    /// files which have already been read are still saved.
     pub fn update_file_content_cache<T>(
         &mut self,
         paths: T,
+        cancellation: Option<&CancellationToken>,
     ) -> Result<Vec<MatchDbError>, MatchDbError>
     where
         T: IntoIterator<Item = PathBuf>,
@@ -690,7 +900,7 @@ impl MatchDb {
     {
         let loading_errs = self
             .content_cache
-            .update_using_fs(paths, false)
+            .update_using_fs(paths, false, cancellation)
             .map_err(MatchDbError::FileContentCacheErrorNoPath)?
             .into_iter()
             .map(MatchDbError::from)
@@ -699,8 +909,24 @@ impl MatchDb {
         Ok(loading_errs)
     }
 
-    pub fn fix_moved_files(&mut self) -> Result<(), MatchDbError> {
+    /// Add files which are identical to a file in a confirmed group to its group. Files which are
+    /// identical to a file that has since been deleted are taken to have been moved, and take its
+    /// place in its group.
+    ///
+    /// Returns the (old, new) paths of the files that were moved.
+    pub fn fix_moved_files(&mut self) -> Result<Vec<(PathBuf, PathBuf)>, MatchDbError> {
+        //the contents of deleted files are only known until their entries are removed.
+        let deleted = self
+            .confirmed
+            .deleted_items()
+            .into_iter()
+            .filter_map(|(entry, partner)| {
+                let content_id = self.content_cache.fetch(&entry.path).ok()?;
+                Some((entry, content_id, partner))
+            })
+            .collect::<Vec<_>>();
         self.remove_deleted_items();
+        let mut moves = vec![];
 
         let mut all_db_entries = BTreeSet::new();
         for group in self.confirmed_groups() {
@@ -719,20 +945,44 @@ impl MatchDb {
 
         for unmatched_entry in unmatched_entries {
             //we need to ignore entries which aren't in the content cache
-            let Ok(unmatched_hash) = self.content_cache.fetch(unmatched_entry) else {
+            if self.content_cache.fetch(unmatched_entry).is_err() {
                 warn!("item missing from content cache: {unmatched_entry:?}");
                 continue;
-            };
+            }
+
+            if let Some((old_entry, _, partner)) = deleted.iter().find(|(old_entry, old_id, _)| {
+                self.content_cache
+                    .fetch(unmatched_entry)
+                    .is_ok_and(|id| id.may_match(old_id))
+                    && self
+                        .content_cache
+                        .full_hash(unmatched_entry)
+                        .is_ok_and(|hash| *hash.as_bytes() == old_entry.content_hash)
+            }) {
+                info!(
+                    "Moved file in matchdb: {:?} -> {unmatched_entry:?}",
+                    &old_entry.path
+                );
+                if let Some(partner) = partner {
+                    let new_entry = self.create_match_map_entry(unmatched_entry.clone())?;
+                    let partner_entry = self.create_match_map_entry(partner.clone())?;
+                    self.insert_confirmed_pair(new_entry, partner_entry);
+                }
+                moves.push((old_entry.path.clone(), unmatched_entry.clone()));
+                continue;
+            }
 
             let confirmed_groups = self.confirmed_groups().collect::<Vec<_>>();
             for group in confirmed_groups {
                 if let Some(matching_entry) = group.contained_paths().find(|p| {
-                    let Ok(cand_hash) = self.content_cache.fetch(p) else {
-                        warn!("item missing from content cache: {p:?}");
-                        return false;
-                    };
-
-                    cand_hash == unmatched_hash
+                    //only files with matching fingerprints are read in full.
+                    match self.content_cache.confirm_identical(unmatched_entry, p) {
+                        Ok(identical) => identical,
+                        Err(e) => {
+                            warn!("could not compare {unmatched_entry:?} with {p:?}: {e}");
+                            false
+                        }
+                    }
                 }) {
                     let new_entry = self.create_match_map_entry(unmatched_entry.clone())?;
                     let matching_entry =
@@ -752,7 +1002,199 @@ impl MatchDb {
             "unmatched fix time: {}",
             unmatched_fix_start.elapsed().as_secs_f64()
         );
-        Ok(())
+        Ok(moves)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    fn entry(path: String) -> MatchMapEntry {
+        MatchMapEntry {
+            path: PathBuf::from(path),
+            content_hash: [0; 32],
+        }
+    }
+
+    #[test]
+    fn test_unmatch_only_touches_one_group() {
+        //a thousand groups, each a chain of three paths.
+        const NUM_GROUPS: usize = 1000;
+        let mut map = MatchMap::default();
+        for i in 0..NUM_GROUPS {
+            map.insert(entry(format!("{i}/a")), entry(format!("{i}/b")));
+            map.insert(entry(format!("{i}/b")), entry(format!("{i}/c")));
+        }
+        let sets = |map: &MatchMap| {
+            map.map
+                .all_sets()
+                .map(|set| set.cloned().collect::<Vec<_>>())
+                .collect::<Vec<_>>()
+        };
+        let sets_before = sets(&map);
+        let file_hashes_before = map.file_hashes.clone();
+
+        map.unmatch_pair("500/b", "500/c");
+
+        //Only the group's own set is replaced, with what is left of it going at the end. The last
+        //set is moved into its place, and every other set is where it was. Rebuilding the map
+        //would have put the sets back in order.
+        let mut expected = sets_before;
+        expected.swap_remove(500);
+        expected.push(vec![PathBuf::from("500/a"), "500/b".into()]);
+        assert_eq!(sets(&map), expected);
+
+        //and only the path that was left without a group loses its content hash.
+        let mut expected = file_hashes_before;
+        expected.remove(Path::new("500/c"));
+        assert_eq!(map.file_hashes, expected);
+
+        assert!(map.is_confirmed("500/a", "500/b"));
+        assert!(!map.is_confirmed("500/b", "500/c"));
+        assert!(map.is_confirmed("501/b", "501/c"));
+    }
+
+    fn db(dir: &TempDir, name: &str, confirmed: &[&[&str]], falsepos: &[[&str; 2]]) -> MatchDb {
+        let mut db = MatchDb::new(dir.path().join(name)).unwrap();
+        for group in confirmed {
+            for (p1, p2) in group.iter().tuple_combinations() {
+                db.insert_confirmed_pair(entry(p1.to_string()), entry(p2.to_string()));
+            }
+        }
+        for pair in falsepos {
+            db.falsepos.insert(pair.iter());
+        }
+        db
+    }
+
+    #[test]
+    fn test_legacy_content_caches_are_migrated_once() {
+        //the layout of entries in caches written before files were fingerprinted.
+        #[derive(Serialize)]
+        struct LegacyEntry {
+            cache_mtime: std::time::SystemTime,
+            size: u64,
+            value: Result<blake3::Hash, FileContentCacheErrorKind>,
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let db_path = dir.path().join("db");
+        std::fs::create_dir(&db_path).unwrap();
+        let legacy_path = MatchDb::legacy_content_cache_path(&db_path);
+
+        //a corrupt cache is an error, and is left for the user to look at.
+        std::fs::write(&legacy_path, "not a cache").unwrap();
+        assert!(matches!(
+            MatchDb::new(&db_path),
+            Err(MatchDbError::FileContentCacheError(path, _)) if path == legacy_path
+        ));
+        assert!(legacy_path.exists());
+
+        let video = dir.path().join("a.mp4");
+        std::fs::write(&video, "a video").unwrap();
+        let metadata = std::fs::metadata(&video).unwrap();
+        let entry = LegacyEntry {
+            cache_mtime: metadata.modified().unwrap(),
+            size: metadata.len(),
+            value: Ok(blake3::hash(b"a video")),
+        };
+        let legacy = HashMap::from([(video.clone(), entry)]);
+        std::fs::write(&legacy_path, bincode::serialize(&legacy).unwrap()).unwrap();
+
+        let db = MatchDb::new(&db_path).unwrap();
+        assert!(db.content_cache.fetch(&video).is_ok());
+        assert!(MatchDb::content_cache_path(&db_path).exists());
+        assert!(!legacy_path.exists());
+    }
+
+    fn paths(paths: &[&str]) -> Vec<PathBuf> {
+        paths.iter().map(PathBuf::from).collect()
+    }
+
+    fn pair(p1: &str, p2: &str) -> [PathBuf; 2] {
+        [PathBuf::from(p1), PathBuf::from(p2)]
+    }
+
+    #[test]
+    fn test_merge_coalesces_groups_transitively() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut ours = db(&dir, "ours", &[&["a", "b"], &["c", "d"]], &[]);
+        let theirs = db(&dir, "theirs", &[&["b", "c"], &["x", "y"]], &[]);
+
+        let mut report = ours.merge(&theirs);
+        report.added_groups.sort();
+
+        assert_eq!(
+            report.added_groups,
+            vec![paths(&["a", "b", "c", "d"]), paths(&["x", "y"])]
+        );
+        assert!(report.added_falsepos.is_empty());
+        assert!(report.conflicts.is_empty());
+        assert!(ours.is_confirmed("a", "d"));
+        assert!(!ours.is_confirmed("a", "x"));
+        assert_eq!(ours.confirmed_groups().count(), 2);
+
+        //merging again changes nothing.
+        assert_eq!(ours.merge(&theirs), MergeReport::default());
+    }
+
+    #[test]
+    fn test_merge_reports_confirmed_and_falsepos_conflicts() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut ours = db(&dir, "ours", &[&["a", "b"]], &[["a", "c"], ["x", "y"]]);
+        let theirs = db(&dir, "theirs", &[&["b", "c"], &["x", "y"]], &[["d", "e"]]);
+
+        let report = ours.merge(&theirs);
+
+        assert_eq!(report.added_falsepos, vec![pair("e", "d")]);
+        assert_eq!(
+            report.conflicts,
+            vec![
+                MergeConflict::ConfirmedAndFalsepos {
+                    paths: pair("c", "a"),
+                    confirmed_in: MergeSide::Combined,
+                    falsepos_in: MergeSide::Ours,
+                },
+                MergeConflict::ConfirmedAndFalsepos {
+                    paths: pair("y", "x"),
+                    confirmed_in: MergeSide::Theirs,
+                    falsepos_in: MergeSide::Ours,
+                },
+            ]
+        );
+
+        //neither side of a conflict is dropped.
+        assert!(ours.is_confirmed("a", "c") && ours.is_falsepos("a", "c"));
+        assert!(ours.is_confirmed("x", "y") && ours.is_falsepos("x", "y"));
+    }
+
+    #[test]
+    fn test_merge_skips_paths_with_different_contents() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut ours = db(&dir, "ours", &[&["a", "b"]], &[]);
+        let mut theirs = db(&dir, "theirs", &[], &[]);
+        let changed = MatchMapEntry {
+            path: PathBuf::from("a"),
+            content_hash: [1; 32],
+        };
+        theirs.insert_confirmed_pair(changed, entry("c".to_string()));
+        theirs.insert_confirmed_pair(entry("d".to_string()), entry("e".to_string()));
+
+        let report = ours.merge(&theirs);
+
+        assert_eq!(
+            report.conflicts,
+            vec![MergeConflict::ContentHash {
+                path: PathBuf::from("a"),
+                ours: [0; 32],
+                theirs: [1; 32],
+            }]
+        );
+        assert_eq!(report.added_groups, vec![paths(&["d", "e"])]);
+        assert!(!ours.is_confirmed("a", "c"));
     }
 }
 
@@ -0,0 +1,258 @@
+//! Post-search filtering of match groups against the MatchDb.
+//!
+//! Every function here preserves the reference video of a group (if it has one).
+//! Groups that end up with too few entries are dropped.
+
+use std::path::{Path, PathBuf};
+
+use vid_dup_finder_lib::MatchGroup;
+
+/// Expand each group into every pair of videos it contains. For groups with a reference,
+/// this is the reference paired with each duplicate.
+pub fn cartesian_product(matchset: &[MatchGroup]) -> Vec<MatchGroup> {
+    matchset
+        .iter()
+        .flat_map(MatchGroup::dup_combinations)
+        .collect()
+}
+
+/// Remove matches that are already known (i.e. confirmed in the MatchDb).
+///
+/// For a group with a reference, duplicates already confirmed as matching the reference
+/// are removed. Otherwise the group is split up such that each resulting group contains
+/// at least one unconfirmed pair.
+pub fn remove_known_matches(
+    group: &MatchGroup,
+    is_confirmed: impl Fn(&Path, &Path) -> bool,
+) -> Vec<MatchGroup> {
+    match group.reference() {
+        Some(reference) => {
+            let dups = group
+                .duplicates()
+                .filter(|dup| !is_confirmed(reference, dup))
+                .map(Path::to_path_buf);
+
+            MatchGroup::new_with_reference(reference.to_path_buf(), dups)
+                .into_iter()
+                .collect()
+        }
+        None => {
+            let mut ret: Vec<Vec<PathBuf>> = vec![];
+            for src_path in group.duplicates() {
+                let first_unconfirmed = ret.iter_mut().find(|paths| {
+                    !paths
+                        .iter()
+                        .all(|group_path| is_confirmed(group_path, src_path))
+                });
+
+                match first_unconfirmed {
+                    Some(paths) => paths.push(src_path.to_path_buf()),
+                    None => ret.push(vec![src_path.to_path_buf()]),
+                }
+            }
+
+            ret.into_iter()
+                .filter_map(|paths| MatchGroup::new(paths).ok())
+                .collect()
+        }
+    }
+}
+
+/// Remove known false positives from a group, returning the filtered group (if any
+/// of it remains) and the number of videos removed.
+///
+/// For a group with a reference, duplicates that are false positives of the reference are removed.
+/// Otherwise, each video is removed if it is a false positive of any video kept before it.
+pub fn remove_falsepos(
+    group: &MatchGroup,
+    is_falsepos: impl Fn(&Path, &Path) -> bool,
+) -> (Option<MatchGroup>, usize) {
+    let mut num_removed = 0;
+
+    let ret = match group.reference() {
+        Some(reference) => {
+            let mut dups = vec![];
+            for dup in group.duplicates() {
+                if is_falsepos(reference, dup) {
+                    num_removed += 1;
+                } else {
+                    dups.push(dup.to_path_buf());
+                }
+            }
+
+            MatchGroup::new_with_reference(reference.to_path_buf(), dups).ok()
+        }
+        None => {
+            let mut kept: Vec<PathBuf> = vec![];
+            for src_path in group.duplicates() {
+                if kept
+                    .iter()
+                    .any(|kept_path| is_falsepos(kept_path, src_path))
+                {
+                    num_removed += 1;
+                } else {
+                    kept.push(src_path.to_path_buf());
+                }
+            }
+
+            MatchGroup::new(kept).ok()
+        }
+    };
+
+    (ret, num_removed)
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+
+    use vid_dup_finder_lib::{
+        search_with_opts, search_with_references_with_opts, SearchOptions, VideoHash,
+    };
+
+    use super::*;
+
+    fn hash(name: &str) -> VideoHash {
+        VideoHash::empty_hash(name).with_duration(10)
+    }
+
+    fn pairs(pairs: &[(&str, &str)]) -> impl Fn(&Path, &Path) -> bool {
+        let pairs = pairs
+            .iter()
+            .flat_map(|&(a, b)| [(PathBuf::from(a), PathBuf::from(b)), (b.into(), a.into())])
+            .collect::<HashSet<_>>();
+        move |a, b| pairs.contains(&(a.to_path_buf(), b.to_path_buf()))
+    }
+
+    // run the same filtering pipeline as search_disk.
+    fn filter(
+        matchset: Vec<MatchGroup>,
+        cartesian: bool,
+        confirmed: &[(&str, &str)],
+        falsepos: &[(&str, &str)],
+    ) -> Vec<MatchGroup> {
+        let matchset = if cartesian {
+            cartesian_product(&matchset)
+        } else {
+            matchset
+        };
+
+        let is_confirmed = pairs(confirmed);
+        let is_falsepos = pairs(falsepos);
+
+        let mut ret = matchset
+            .iter()
+            .flat_map(|group| remove_known_matches(group, &is_confirmed))
+            .filter_map(|group| remove_falsepos(&group, &is_falsepos).0)
+            .collect::<Vec<_>>();
+        ret.sort();
+        ret
+    }
+
+    fn with_ref(reference: &str, dups: &[&str]) -> MatchGroup {
+        MatchGroup::new_with_reference(PathBuf::from(reference), dups.iter().map(PathBuf::from))
+            .unwrap()
+    }
+
+    fn refs_search() -> Vec<MatchGroup> {
+        let mut groups = search_with_references_with_opts(
+            [hash("ref")],
+            [hash("a"), hash("b"), hash("c")],
+            &SearchOptions::default(),
+        );
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].reference(), Some(Path::new("ref")));
+
+        //make the order of duplicates deterministic for comparisons.
+        let mut dups = groups[0].duplicates().collect::<Vec<_>>();
+        dups.sort();
+        groups[0] = with_ref(
+            "ref",
+            &dups.iter().map(|p| p.to_str().unwrap()).collect::<Vec<_>>(),
+        );
+        groups
+    }
+
+    #[test]
+    fn test_cartesian_keeps_reference() {
+        let groups = filter(refs_search(), true, &[], &[]);
+
+        assert_eq!(
+            groups,
+            [
+                with_ref("ref", &["a"]),
+                with_ref("ref", &["b"]),
+                with_ref("ref", &["c"])
+            ]
+        );
+    }
+
+    #[test]
+    fn test_known_matches_keep_reference() {
+        for cartesian in [false, true] {
+            let groups = filter(refs_search(), cartesian, &[("ref", "a")], &[]);
+            let expected = if cartesian {
+                vec![with_ref("ref", &["b"]), with_ref("ref", &["c"])]
+            } else {
+                vec![with_ref("ref", &["b", "c"])]
+            };
+
+            assert_eq!(groups, expected, "cartesian: {cartesian}");
+        }
+    }
+
+    #[test]
+    fn test_falsepos_keep_reference() {
+        for cartesian in [false, true] {
+            let groups = filter(
+                refs_search(),
+                cartesian,
+                &[("ref", "a")],
+                &[("ref", "b"), ("b", "c")],
+            );
+
+            //b-c is not a falsepos of the reference, so does not affect reference groups.
+            assert_eq!(groups, [with_ref("ref", &["c"])], "cartesian: {cartesian}");
+        }
+    }
+
+    #[test]
+    fn test_fully_filtered_groups_are_dropped() {
+        for cartesian in [false, true] {
+            let groups = filter(
+                refs_search(),
+                cartesian,
+                &[("ref", "a"), ("ref", "b")],
+                &[("ref", "c")],
+            );
+            assert!(groups.is_empty(), "cartesian: {cartesian}");
+
+            let groups = filter(
+                search_with_opts([hash("a"), hash("b")], &SearchOptions::default()),
+                cartesian,
+                &[],
+                &[("a", "b")],
+            );
+            assert!(groups.is_empty(), "cartesian: {cartesian}");
+        }
+    }
+
+    #[test]
+    fn test_known_matches_split_unreferenced_groups() {
+        let groups = search_with_opts([hash("a"), hash("b"), hash("c")], &SearchOptions::default());
+
+        //a-b and a-c are known, but b-c is not.
+        let groups = filter(groups, false, &[("a", "b"), ("a", "c")], &[]);
+        assert_eq!(groups.len(), 1);
+        assert!(groups[0].reference().is_none());
+
+        let mut paths = groups[0].duplicates().collect::<Vec<_>>();
+        paths.sort();
+        assert!(paths.contains(&Path::new("b")) && paths.contains(&Path::new("c")));
+
+        //if everything is known, nothing remains.
+        let groups = search_with_opts([hash("a"), hash("b"), hash("c")], &SearchOptions::default());
+        let groups = filter(groups, true, &[("a", "b"), ("a", "c"), ("b", "c")], &[]);
+        assert!(groups.is_empty());
+    }
+}
@@ -1,15 +1,43 @@
-use std::path::{Path, PathBuf};
+use std::{
+    num::NonZeroU32,
+    path::{Path, PathBuf},
+};
 
-use ffmpeg_gst_wrapper::FrameReadCfgTrait;
 use image::{GenericImage, ImageBuffer, RgbImage};
 use itertools::{Either, Itertools};
-use vid_dup_finder_common::FrameSeqRgb;
-use vid_dup_finder_lib::MatchGroup;
+use vid_dup_finder_common::{row_images, FrameSeqRgb};
+use vid_dup_finder_lib::{MatchGroup, ThumbSpec, VideoHash};
 
 use crate::video_hash_filesystem_cache::filename_pattern::{FilenamePattern, FilterFilenames};
+use crate::video_hash_filesystem_cache::VideoHashFilesystemCache;
+
+/// The thumbnails shown by every output (the gui, thumbnail files and html reports), so that a video
+/// looks the same in all of them. The gui crops them when asked to.
+pub const THUMB_SPEC: ThumbSpec = ThumbSpec {
+    count: 4,
+    max_dimension: NonZeroU32::new(320),
+    cropdetect: false,
+    timestamps: false,
+};
+
+/// An id for a group of files. It only depends on which files are in the group and which of them
+/// (if any) is the reference, so it stays the same for as long as searches keep producing the same
+/// group.
+pub fn group_id<'a>(members: impl IntoIterator<Item = (bool, &'a Path)>) -> String {
+    let mut hasher = blake3::Hasher::new();
+    for (is_reference, path) in members.into_iter().sorted_by(|a, b| a.1.cmp(b.1)) {
+        hasher.update(&[u8::from(is_reference)]);
+        hasher.update(path.as_os_str().as_encoded_bytes());
+        hasher.update(&[0]);
+    }
+    hasher.finalize().to_hex()[..16].to_string()
+}
 
 pub trait MatchGroupExt {
-    fn to_image(&self) -> Result<RgbImage, String>;
+    fn to_image(&self, cache: &VideoHashFilesystemCache) -> Result<RgbImage, String>;
+
+    /// See [`group_id`].
+    fn group_id(&self) -> String;
 
     fn filter<F>(&self, filter: F) -> Option<MatchGroup>
     where
@@ -25,23 +53,24 @@ pub trait MatchGroupExt {
 
 impl MatchGroupExt for MatchGroup {
     #[cfg(target_family = "unix")]
-    fn to_image(&self) -> Result<RgbImage, String> {
-        #[cfg(feature = "ffmpeg_backend")]
-        return to_image_temp::<ffmpeg_gst_wrapper::ffmpeg_impl::FrameReaderCfgFfmpeg>(
-            self.contained_paths(),
-        );
-
-        #[cfg(feature = "gstreamer_backend")]
-        return to_image_temp::<ffmpeg_gst_wrapper::ffmpeg_impl::FrameReaderCfgFfmpeg>(
-            self.contained_paths(),
-        );
+    fn to_image(&self, cache: &VideoHashFilesystemCache) -> Result<RgbImage, String> {
+        to_image_temp(cache, self.contained_paths())
     }
 
     #[cfg(target_family = "windows")]
-    fn to_image(&self) -> Result<RgbImage, String> {
+    fn to_image(&self, _cache: &VideoHashFilesystemCache) -> Result<RgbImage, String> {
         Err("".to_string())
     }
 
+    fn group_id(&self) -> String {
+        let reference = self.reference().map(|path| (true, path));
+        group_id(
+            reference
+                .into_iter()
+                .chain(self.duplicates().map(|path| (false, path))),
+        )
+    }
+
     fn filter<F>(&self, filter: F) -> Option<MatchGroup>
     where
         F: FilterFilenames,
@@ -149,10 +178,16 @@ fn grid_images_with_text(images: &[(String, Vec<RgbImage>)]) -> Result<RgbImage,
     let (first_src_path, first_row) = images
         .first()
         .ok_or_else(|| "grid_images failed: No images were supplied".to_string())?;
-    let first_img = first_row.first().ok_or_else(|| {
-        format!("grid_images failed: No images were supplied for {first_src_path}",)
-    })?;
-    let (img_x, img_y) = first_img.dimensions();
+    if first_row.is_empty() {
+        return Err(format!(
+            "grid_images failed: No images were supplied for {first_src_path}",
+        ));
+    }
+
+    //thumbnails keep the aspect ratio of their video, so each cell fits the largest of them.
+    let all_imgs = || images.iter().flat_map(|(_src_path, imgs)| imgs);
+    let img_x = all_imgs().map(|img| img.width()).max().unwrap_or(0);
+    let img_y = all_imgs().map(|img| img.height()).max().unwrap_or(0);
     let grid_num_x = images
         .iter()
         .map(|(_src_path, imgs)| imgs.len())
@@ -196,84 +231,99 @@ fn grid_images_with_text(images: &[(String, Vec<RgbImage>)]) -> Result<RgbImage,
     Ok(grid_buf)
 }
 
+/// The thumbnails of a video that are shown by every output (see [`THUMB_SPEC`]), with `spec`
+/// instead. None if no frames could be extracted.
+pub fn video_thumbnails(
+    cache: &VideoHashFilesystemCache,
+    src_path: &Path,
+    spec: &ThumbSpec,
+) -> Option<Vec<RgbImage>> {
+    thumbnails_or_warn(src_path, cache.thumbnails(src_path, spec))
+}
+
+/// As [`video_thumbnails`], with the hash of the video given (see
+/// [`VideoHashFilesystemCache::thumbnails_with_hash`]).
+pub fn video_thumbnails_with_hash(
+    cache: &VideoHashFilesystemCache,
+    src_path: &Path,
+    hash: Option<&VideoHash>,
+    spec: &ThumbSpec,
+) -> Option<Vec<RgbImage>> {
+    thumbnails_or_warn(src_path, cache.thumbnails_with_hash(src_path, hash, spec))
+}
+
+fn thumbnails_or_warn(
+    src_path: &Path,
+    thumbs: Result<Vec<RgbImage>, vid_dup_finder_lib::Error>,
+) -> Option<Vec<RgbImage>> {
+    match thumbs {
+        Ok(thumbs) if !thumbs.is_empty() => Some(thumbs),
+        Ok(_) => {
+            let src_path = src_path.display();
+            warn!("Failed to extract any frames from video: {src_path}");
+            None
+        }
+        Err(e) => {
+            let src_path = src_path.display();
+            warn!("failed to generate output image for {src_path}. Got error {e}");
+            None
+        }
+    }
+}
+
+/// The frames of a video that [`MatchGroupExt::to_image`] shows, side by side, each shrunk to
+/// `height` pixels high. None if no frames could be extracted.
+#[cfg(target_family = "unix")]
+pub fn thumbnail_strip(
+    cache: &VideoHashFilesystemCache,
+    src_path: &Path,
+    height: NonZeroU32,
+) -> Option<RgbImage> {
+    let frames = video_thumbnails(cache, src_path, &THUMB_SPEC)?;
+    let frames = FrameSeqRgb::from_images(frames)?;
+    Some(frames_strip(frames, height))
+}
+
+#[cfg(target_family = "windows")]
+pub fn thumbnail_strip(
+    _cache: &VideoHashFilesystemCache,
+    _src_path: &Path,
+    _height: NonZeroU32,
+) -> Option<RgbImage> {
+    None
+}
+
+#[cfg(target_family = "unix")]
+// The frames of a video side by side, resized to the given height keeping their aspect ratio.
+fn frames_strip(frames: FrameSeqRgb, height: NonZeroU32) -> RgbImage {
+    let frames = frames.into_inner();
+    let (w, h) = frames[0].dimensions();
+    let width = u64::from(w) * u64::from(height.get()) / u64::from(h.max(1));
+    let width = NonZeroU32::new(width as u32).unwrap_or(NonZeroU32::MIN);
+
+    let resized = FrameSeqRgb::from_images(frames)
+        .expect("there is at least one frame")
+        .resize(width, height)
+        .into_inner();
+    row_images(resized.iter()).expect("there is at least one frame")
+}
+
 #[cfg(target_family = "unix")]
-fn to_image_temp<T: FrameReadCfgTrait>(
+fn to_image_temp(
+    cache: &VideoHashFilesystemCache,
     img_paths: impl IntoIterator<Item = impl AsRef<Path>>,
 ) -> Result<RgbImage, String> {
-    use std::num::NonZeroU32;
-
     let all_thumbs: Vec<(String, Vec<RgbImage>)> = img_paths
         .into_iter()
         .map(|src_path| {
             let src_path = src_path.as_ref();
 
-            let get_frames = |fps| {
-                let mut builder = T::from_path(src_path);
-                builder.fps(fps);
-                let mut frame_iterator = builder.spawn_rgb().peekable();
-
-                match frame_iterator.peek() {
-                    None => Err(()),
-                    Some(Err(_)) => Err(()),
-                    Some(Ok(_frame)) => Ok(frame_iterator),
-                }
-            };
-
-            //first try and get 7 frames at 1/5 fps. If that doesn't work then try a faster framerate
-            //Finally try and get 7 frames at native framerate.
-            let b1 = || get_frames((1, 5));
-            let b2 = || get_frames((2, 1));
-            let b3 = || get_frames((5, 1));
-            let b4 = || get_frames((30, 1));
-
-            let frame_builder = b1().or_else(|_| b2()).or_else(|_| b3()).or_else(|_| b4());
-
-            let frames = match frame_builder {
-                Ok(frame_iter) => Some(frame_iter),
-                Err(e) => {
-                    let src_path = src_path.display();
-                    warn!("failed to generate output image for {src_path}. Got error {e:?}",);
-                    None
-                }
-            };
-
-            //process each frame.
-            let frames = frames.and_then(|frames| {
-                let frame_vec = frames
-                    .into_iter()
-                    .filter_map(Result::ok)
-                    .take(4)
-                    .collect::<Vec<_>>();
-                let seq = FrameSeqRgb::from_images(frame_vec);
-
-                if seq.is_none() {
-                    warn!(
-                        "Failed to extract any frames from video: {}",
-                        src_path.display()
-                    );
-                }
-
-                seq
-            });
-
             //if any step failed, then use the fallback images instead
-            let frames = frames.unwrap_or_else(|| {
-                FrameSeqRgb::from_images((0..5).map(|_i| fallback_image())).unwrap()
-            });
+            let frames = video_thumbnails(cache, src_path, &THUMB_SPEC)
+                .unwrap_or_else(|| (0..THUMB_SPEC.count).map(|_i| fallback_image()).collect());
 
             (src_path.to_string_lossy().to_string(), frames)
         })
-        .flat_map(|(path, frames)| {
-            if false {
-                vec![(path, frames.into_inner())].into_iter()
-            } else {
-                let size = NonZeroU32::new(150).expect("literal value");
-                let new_frames = frames.resize(size, size);
-                let new_frames = new_frames.into_inner();
-                //println!("{:?}, {}", new_frames[0].dimensions(), new_frames.len());
-                vec![(path, new_frames)].into_iter()
-            }
-        })
         .collect::<Vec<_>>();
 
     if all_thumbs.is_empty() {
@@ -1,9 +1,12 @@
 mod app_cfg;
 mod app_fns;
 mod arg_parse;
+mod cache_cmd;
 mod disjoint_set;
 mod errors;
+mod interrupt;
 mod match_db;
+mod match_filtering;
 mod match_group_ext;
 
 mod search_output;
@@ -21,7 +24,12 @@ mod resolution_thunk;
 #[cfg(all(target_family = "unix", feature = "gui_slint"))]
 pub(crate) use resolution_thunk::*;
 
+#[cfg(all(target_family = "unix", feature = "gui_slint"))]
+mod resolution_history;
+#[cfg(all(target_family = "unix", feature = "gui_slint"))]
+pub(crate) use resolution_history::*;
+
 #[cfg(all(target_family = "unix", feature = "gui_slint"))]
 mod gui_slint;
 #[cfg(all(target_family = "unix", feature = "gui_slint"))]
-use gui_slint::run_gui_slint;
+use gui_slint::{run_gui_slint, session_state_path, GuiCommands};
@@ -0,0 +1,169 @@
+use std::{
+    io::BufReader,
+    path::{Path, PathBuf},
+};
+
+use super::{ResolutionTransaction, UndoError};
+
+const HISTORY_FILENAME: &str = "vid_dup_finder_resolution_history.json";
+
+/// A stack of the resolutions made in the GUI, newest last.
+///
+/// When a trash directory is configured, the stack is saved there as JSON after every change,
+/// so resolutions can still be undone after restarting the GUI.
+#[derive(Debug, Default)]
+pub struct ResolutionHistory {
+    history_file: Option<PathBuf>,
+    transactions: Vec<ResolutionTransaction>,
+}
+
+impl ResolutionHistory {
+    /// Load the history stored in the given trash directory, if any. If no trash
+    /// directory is given, the history is only kept in memory.
+    pub fn load(gui_trash_path: Option<&Path>) -> Result<Self, UndoError> {
+        let Some(history_file) = gui_trash_path.map(|p| p.join(HISTORY_FILENAME)) else {
+            return Ok(Self::default());
+        };
+
+        let transactions = if history_file.exists() {
+            let f = std::fs::File::open(&history_file)
+                .map_err(|e| UndoError::HistoryIo(history_file.clone(), e))?;
+            serde_json::from_reader(BufReader::new(f))
+                .map_err(|e| UndoError::HistoryParse(history_file.clone(), e))?
+        } else {
+            vec![]
+        };
+
+        Ok(Self {
+            history_file: Some(history_file),
+            transactions,
+        })
+    }
+
+    pub fn transactions(&self) -> &[ResolutionTransaction] {
+        &self.transactions
+    }
+
+    /// Record a new transaction. Transactions which made no changes are not recorded.
+    pub fn push(&mut self, transaction: ResolutionTransaction) -> Result<(), UndoError> {
+        if transaction.is_empty() {
+            return Ok(());
+        }
+
+        self.transactions.push(transaction);
+        self.save()
+    }
+
+    /// Undo the most recent transaction and remove it from the history. If the
+    /// transaction cannot be undone then it is left in the history.
+    pub fn undo_last(&mut self) -> Result<ResolutionTransaction, UndoError> {
+        let transaction = self.transactions.last().ok_or(UndoError::NothingToUndo)?;
+        transaction.undo()?;
+
+        let transaction = self.transactions.pop().expect("checked above");
+        self.save()?;
+
+        Ok(transaction)
+    }
+
+    fn save(&self) -> Result<(), UndoError> {
+        let Some(history_file) = &self.history_file else {
+            return Ok(());
+        };
+
+        if let Some(parent_dir) = history_file.parent() {
+            std::fs::create_dir_all(parent_dir)
+                .map_err(|e| UndoError::HistoryIo(history_file.clone(), e))?;
+        }
+
+        //write to a temporary file first so that the history is not lost if writing is interrupted.
+        let tmp_file = history_file.with_extension("json.tmp");
+        let contents = serde_json::to_vec_pretty(&self.transactions)
+            .map_err(|e| UndoError::HistoryParse(history_file.clone(), e))?;
+        std::fs::write(&tmp_file, contents)
+            .and_then(|()| std::fs::rename(&tmp_file, history_file))
+            .map_err(|e| UndoError::HistoryIo(history_file.clone(), e))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn write_file(dir: &Path, name: &str, contents: &str) -> PathBuf {
+        let p = dir.join(name);
+        std::fs::create_dir_all(p.parent().unwrap()).unwrap();
+        std::fs::write(&p, contents).unwrap();
+        p
+    }
+
+    #[test]
+    fn test_undo_restores_files_and_persists() {
+        let dir = tempfile::tempdir().unwrap();
+        let trash = dir.path().join("trash");
+
+        // a resolution which trashed b.mp4, then renamed a.mp4 into b.mp4's place.
+        let orig_a = dir.path().join("a.mp4");
+        let orig_b = dir.path().join("b.mp4");
+        let trashed_b = write_file(dir.path(), "trash/b.mp4", "b");
+        let renamed_a = write_file(dir.path(), "b.mp4", "a");
+        let transaction = ResolutionTransaction {
+            moves: vec![(orig_b.clone(), trashed_b.clone())],
+            renames: vec![(orig_a.clone(), renamed_a)],
+        };
+
+        let mut history = ResolutionHistory::load(Some(&trash)).unwrap();
+        history.push(transaction.clone()).unwrap();
+
+        //the history should survive being reloaded.
+        let mut history = ResolutionHistory::load(Some(&trash)).unwrap();
+        assert_eq!(history.transactions(), std::slice::from_ref(&transaction));
+
+        assert_eq!(history.undo_last().unwrap(), transaction);
+        assert_eq!(std::fs::read_to_string(&orig_a).unwrap(), "a");
+        assert_eq!(std::fs::read_to_string(&orig_b).unwrap(), "b");
+        assert!(!trashed_b.exists());
+
+        let history = ResolutionHistory::load(Some(&trash)).unwrap();
+        assert!(history.transactions().is_empty());
+    }
+
+    #[test]
+    fn test_undo_refuses_conflicts() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let orig = dir.path().join("a.mp4");
+        let trashed = write_file(dir.path(), "trash/a.mp4", "a");
+        let transaction = ResolutionTransaction {
+            moves: vec![(orig.clone(), trashed.clone())],
+            renames: vec![],
+        };
+
+        let mut history = ResolutionHistory::load(None).unwrap();
+        history.push(transaction).unwrap();
+
+        //something new now lives at the original location.
+        write_file(dir.path(), "a.mp4", "new");
+        assert!(matches!(history.undo_last(), Err(UndoError::DestExists(_))));
+        std::fs::remove_file(&orig).unwrap();
+
+        //the trashed file has gone missing.
+        std::fs::remove_file(&trashed).unwrap();
+        assert!(matches!(
+            history.undo_last(),
+            Err(UndoError::SourceMissing(_))
+        ));
+
+        //failed undos stay in the history.
+        assert_eq!(history.transactions().len(), 1);
+        assert!(!orig.exists());
+    }
+
+    #[test]
+    fn test_nothing_to_undo() {
+        let mut history = ResolutionHistory::load(None).unwrap();
+        history.push(ResolutionTransaction::default()).unwrap();
+
+        assert!(matches!(history.undo_last(), Err(UndoError::NothingToUndo)));
+    }
+}
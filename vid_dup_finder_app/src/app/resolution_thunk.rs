@@ -1,14 +1,19 @@
 #![allow(dead_code, unused_variables, unused_imports)]
 
 use std::{
-    ffi::OsString,
+    collections::HashSet,
+    ffi::{OsStr, OsString},
     fmt::Write,
+    os::unix::ffi::OsStrExt,
     path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
 };
 
-use crate::video_hash_filesystem_cache::*;
+use super::TrashLayout;
+use crate::video_hash_filesystem_cache::{file_hash_filesystem_cache::ContentId, *};
 use itertools::Itertools;
 use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 use vid_dup_finder_lib::*;
 use ResolutionError::*;
@@ -96,6 +101,100 @@ pub enum ResolutionError {
 
     #[error("Duplicate file detected in resolution thunk: {0}")]
     DuplicatedEntry(String),
+
+    #[error("Refusing to remove {0}: its hash is identical to the file to keep, but its frames are different. Check the files and remove them by hand")]
+    FramesDiffer(String),
+
+    #[error("Refusing to remove or move {0}: it is in a protected directory")]
+    ProtectedEntry(String),
+}
+
+#[derive(Error, Debug)]
+pub enum UndoError {
+    #[error("Nothing to undo")]
+    NothingToUndo,
+
+    #[error("Cannot undo: file to restore is missing: {0}")]
+    SourceMissing(String),
+
+    #[error("Cannot undo: destination now exists: {0}")]
+    DestExists(String),
+
+    #[error("Failed to perform undo operation: {0}")]
+    TrashFailed(#[from] TrashError),
+
+    #[error("Failed to read or write resolution history at {0}: {1}")]
+    HistoryIo(PathBuf, #[source] std::io::Error),
+
+    #[error("Failed to parse resolution history at {0}: {1}")]
+    HistoryParse(PathBuf, #[source] serde_json::Error),
+}
+
+/// The filesystem changes made by a single call to [`ResolutionThunk::resolve`],
+/// in the order they were made. Replaying the inverse of each change undoes the resolution.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResolutionTransaction {
+    /// Files moved into (or back out of) the trash directory, as (from, to).
+    pub moves: Vec<(PathBuf, PathBuf)>,
+
+    /// Renames of the kept file, as (from, to). These always happen after `moves`.
+    pub renames: Vec<(PathBuf, PathBuf)>,
+}
+
+impl ResolutionTransaction {
+    pub fn is_empty(&self) -> bool {
+        self.moves.is_empty() && self.renames.is_empty()
+    }
+
+    //every change, in the order it was made.
+    fn changes(&self) -> impl DoubleEndedIterator<Item = &(PathBuf, PathBuf)> {
+        self.moves.iter().chain(self.renames.iter())
+    }
+
+    /// A one-line description of the transaction, for display in the GUI.
+    pub fn summary(&self) -> String {
+        let mut ret = format!("moved {}", self.moves.len());
+        if let Some((from, _to)) = self.moves.first() {
+            write!(ret, " (incl. {})", from.display()).unwrap();
+        }
+        for (from, to) in &self.renames {
+            write!(ret, ", renamed {} -> {}", from.display(), to.display()).unwrap();
+        }
+        ret
+    }
+
+    /// Undo every change in the transaction, newest first.
+    ///
+    /// Nothing is touched unless every change can be reversed: if a file that is to be moved back
+    /// has since gone missing, or something now occupies the place it is to be moved back to,
+    /// an error is returned instead.
+    pub fn undo(&self) -> Result<(), UndoError> {
+        //Earlier inverse operations may free up or occupy paths needed by later ones,
+        //so track those changes while checking for conflicts.
+        let mut vacated = HashSet::new();
+        let mut occupied = HashSet::new();
+        for (from, to) in self.changes().rev() {
+            let exists = |p: &Path| occupied.contains(p) || (!vacated.contains(p) && p.exists());
+
+            if !exists(to) {
+                return Err(UndoError::SourceMissing(to.to_string_lossy().to_string()));
+            }
+            if exists(from) {
+                return Err(UndoError::DestExists(from.to_string_lossy().to_string()));
+            }
+
+            occupied.remove(to.as_path());
+            vacated.insert(to.as_path());
+            vacated.remove(from.as_path());
+            occupied.insert(from.as_path());
+        }
+
+        for (from, to) in self.changes().rev() {
+            move_path(to, from)?;
+        }
+
+        Ok(())
+    }
 }
 
 #[derive(Debug, PartialEq, Default, Clone, Eq, Hash, Ord, PartialOrd)]
@@ -103,6 +202,8 @@ struct ResolutionThunkEntry {
     filename: PathBuf,
     hash: Option<VideoHash>,
     is_reference: bool,
+    //in one of the search's protected directories, so it must stay where it is.
+    is_protected: bool,
 }
 
 #[derive(Debug)]
@@ -113,17 +214,33 @@ struct ResolutionInstruction {
     contents_idx: usize,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct WinningStats {
     pub is_reference: bool,
     pub pngsize: bool,
     pub res: bool,
 }
 
+//The directory that the gui trashes files to, and how they are laid out inside it.
+#[derive(Debug, Clone, PartialEq, PartialOrd, Hash)]
+struct GuiTrash {
+    path: PathBuf,
+    layout: TrashLayout,
+}
+
 #[derive(Debug, Default, Clone, PartialEq, PartialOrd)]
 pub struct ResolutionThunk {
     entries: Vec<ResolutionThunkEntry>,
     distance: Option<f64>,
-    gui_trash_path: Option<PathBuf>,
+    //Boxed, like frames_differ below.
+    gui_trash: Option<Box<GuiTrash>>,
+    //Pairs of entries whose hashes are identical but whose frames were found to differ, so that
+    //neither may be removed in favour of the other. Boxed so that thunks stay small enough to
+    //send between the gui's threads.
+    frames_differ: Box<[(PathBuf, PathBuf)]>,
+    //Pairs of entries (longer first) where the shorter was found to be cut from the longer, so
+    //that the gui can say which to keep. Boxed like frames_differ.
+    containment: Box<[(PathBuf, PathBuf)]>,
 }
 
 //dodgy... may be unsound? am I breaking a soundness assumbption?
@@ -133,7 +250,9 @@ impl std::hash::Hash for ResolutionThunk {
         self.distance
             .map(|distance| distance.to_le_bytes())
             .hash(state);
-        self.gui_trash_path.hash(state);
+        self.gui_trash.hash(state);
+        self.frames_differ.hash(state);
+        self.containment.hash(state);
     }
 }
 
@@ -145,11 +264,34 @@ impl ResolutionThunk {
         match_group: &MatchGroup,
         cache: &VideoHashFilesystemCache,
         gui_trash_path: Option<&Path>,
+        trash_layout: TrashLayout,
     ) -> Self {
         let mut thunk = Self {
             entries: Vec::default(),
             distance: Option::default(),
-            gui_trash_path: gui_trash_path.map(Path::to_path_buf),
+            gui_trash: gui_trash_path.map(|path| {
+                Box::new(GuiTrash {
+                    path: path.to_path_buf(),
+                    layout: trash_layout,
+                })
+            }),
+            frames_differ: match_group
+                .contained_paths()
+                .collect::<Vec<_>>()
+                .into_iter()
+                .tuple_combinations()
+                .filter(|(a, b)| {
+                    match_group.zero_distance_verification(a, b)
+                        == Some(ZeroDistanceVerification::FramesDiffer)
+                })
+                .map(|(a, b)| (a.to_path_buf(), b.to_path_buf()))
+                .collect(),
+            containment: match_group
+                .containment()
+                .edges()
+                .iter()
+                .map(|edge| (edge.longer().to_path_buf(), edge.shorter().to_path_buf()))
+                .collect(),
         };
 
         //first add the reference, if it exists...
@@ -161,20 +303,48 @@ impl ResolutionThunk {
             thunk.insert_entry(entry.to_path_buf());
         }
 
+        for entry in &mut thunk.entries {
+            entry.is_protected = match_group.is_protected(&entry.filename);
+        }
+
         thunk.populate_distance(cache);
         thunk.populate_entries(cache);
 
         thunk
     }
 
+    //A thunk of files which have not been hashed.
+    #[cfg(test)]
+    pub fn from_paths(reference: Option<&str>, entries: &[&str]) -> Self {
+        let mut thunk = Self::default();
+        if let Some(reference) = reference {
+            thunk.insert_reference(PathBuf::from(reference));
+        }
+        for entry in entries {
+            thunk.insert_entry(PathBuf::from(entry));
+        }
+        thunk
+    }
+
     pub fn len(&self) -> usize {
         self.entries.len()
     }
 
+    /// An id for the group of files in this thunk, the same as the
+    /// [id of the group][super::match_group_ext::group_id] that it was made from.
+    pub fn group_id(&self) -> String {
+        super::match_group_ext::group_id(
+            self.entries
+                .iter()
+                .map(|entry| (entry.is_reference, entry.filename.as_path())),
+        )
+    }
+
     fn insert_entry(&mut self, filename: PathBuf) {
         self.entries.push(ResolutionThunkEntry {
             filename,
             is_reference: false,
+            is_protected: false,
             hash: None,
         });
         self.entries
@@ -185,6 +355,7 @@ impl ResolutionThunk {
         self.entries.push(ResolutionThunkEntry {
             filename,
             is_reference: true,
+            is_protected: false,
             hash: None,
         });
         self.entries
@@ -201,8 +372,10 @@ impl ResolutionThunk {
                 let hash_a = &pair[0];
                 let hash_b = &pair[1];
 
-                let current_distance = hash_a.normalized_hamming_distance(hash_b);
-                current_distance.max(max_distance)
+                match hash_a.normalized_hamming_distance(hash_b) {
+                    Ok(current_distance) => current_distance.max(max_distance),
+                    Err(_) => max_distance,
+                }
             });
 
         self.distance = Some(max_distance);
@@ -225,6 +398,28 @@ impl ResolutionThunk {
             .collect::<Vec<_>>()
     }
 
+    pub fn is_protected(&self, src_path: &Path) -> bool {
+        self.entries
+            .iter()
+            .any(|x| x.filename == src_path && x.is_protected)
+    }
+
+    /// The entries that the given entry was found to be cut from.
+    pub fn contained_in<'a>(&'a self, src_path: &'a Path) -> impl Iterator<Item = &'a Path> {
+        self.containment
+            .iter()
+            .filter(move |(_longer, shorter)| shorter == src_path)
+            .map(|(longer, _shorter)| longer.as_path())
+    }
+
+    /// The entries that were found to be cut from the given entry.
+    pub fn contents_of<'a>(&'a self, src_path: &'a Path) -> impl Iterator<Item = &'a Path> {
+        self.containment
+            .iter()
+            .filter(move |(longer, _shorter)| longer == src_path)
+            .map(|(_longer, shorter)| shorter.as_path())
+    }
+
     pub fn hash(&self, src_path: &Path) -> Option<VideoHash> {
         self.entries
             .iter()
@@ -232,17 +427,45 @@ impl ResolutionThunk {
             .and_then(|x| x.hash.clone())
     }
 
-    pub fn calc_winning_stats(&self, filename: &Path) -> WinningStats {
+    /// Compare every entry on the statistics shown in the GUI. This decodes a few frames of every
+    /// entry, so should be done once per thunk rather than once per entry.
+    pub fn best_pick(&self) -> Result<BestPick, vid_dup_finder_lib::Error> {
+        let paths = self.entries.iter().map(|e| e.filename.clone());
+        let group = match self.entries.iter().find(|e| e.is_reference) {
+            Some(reference) => MatchGroup::new_with_reference(
+                reference.filename.clone(),
+                paths.filter(|path| *path != reference.filename),
+            ),
+            None => MatchGroup::new(paths),
+        }
+        .map_err(|_| {
+            vid_dup_finder_lib::Error::VidProc("too few entries to compare".to_string())
+        })?;
+
+        pick_best(
+            &group,
+            &[Criterion::Resolution, Criterion::EncodedFrameSize],
+        )
+    }
+
+    pub fn calc_winning_stats(
+        &self,
+        filename: &Path,
+        best_pick: Option<&BestPick>,
+    ) -> WinningStats {
         let current_entry = self
             .entries
             .iter()
             .find(|e| e.filename == filename)
             .unwrap();
 
+        let is_best_for =
+            |criterion| best_pick.is_some_and(|pick| pick.is_best_for(filename, criterion));
+
         WinningStats {
             is_reference: current_entry.is_reference,
-            pngsize: false,
-            res: false,
+            pngsize: is_best_for(Criterion::EncodedFrameSize),
+            res: is_best_for(Criterion::Resolution),
         }
     }
 
@@ -374,13 +597,15 @@ impl ResolutionThunk {
         }
     }
 
-    pub fn resolve_2(&self, choice: String) -> Result<(), ResolutionError> {
+    pub fn resolve_2(&self, choice: String) -> Result<ResolutionTransaction, ResolutionError> {
         let ret = self.resolve(&choice);
         dbg!(&ret);
         ret
     }
 
-    pub fn resolve(&self, choice: &str) -> Result<(), ResolutionError> {
+    /// Carry out the resolution described by `choice`, returning a record of
+    /// the filesystem changes made so that they can be undone later.
+    pub fn resolve(&self, choice: &str) -> Result<ResolutionTransaction, ResolutionError> {
         let choice = Self::parse_choice(choice)?;
         self.validate_choice(&choice)?;
 
@@ -395,9 +620,14 @@ impl ResolutionThunk {
         let contents_entry = &self.entries[contents_idx];
         let basename_entry = &self.entries[basename_idx];
 
+        let mut transaction = ResolutionTransaction::default();
+
         if untrash {
-            self.untrash_file(&basename_entry.filename)?;
-            return Ok(());
+            let untrashed_path = self.untrash_file(&basename_entry.filename)?;
+            transaction
+                .moves
+                .push((basename_entry.filename.clone(), untrashed_path));
+            return Ok(transaction);
         }
 
         //make sure that no entries are identical to each other
@@ -415,6 +645,24 @@ impl ResolutionThunk {
 
         let entries_to_trash = self.entries.iter().filter(|&entry| entry != contents_entry);
 
+        //different videos can have identical hashes, so only remove files which were not found to
+        //differ from the one being kept.
+        if let Some(entry) = entries_to_trash
+            .clone()
+            .find(|entry| self.frames_differ(&contents_entry.filename, &entry.filename))
+        {
+            return Err(ResolutionError::FramesDiffer(
+                entry.filename.to_string_lossy().to_string(),
+            ));
+        }
+
+        //protected files may be kept, but never trashed or moved.
+        if let Some(entry) = entries_to_trash.clone().find(|entry| entry.is_protected) {
+            return Err(ResolutionError::ProtectedEntry(
+                entry.filename.to_string_lossy().to_string(),
+            ));
+        }
+
         //If the contents_entry is to be renamed, get the new name.
         let new_name;
         let need_to_move_contents;
@@ -423,6 +671,12 @@ impl ResolutionThunk {
             new_name = contents_entry.filename.clone();
         } else {
             need_to_move_contents = true;
+            if contents_entry.is_protected {
+                return Err(ResolutionError::ProtectedEntry(
+                    contents_entry.filename.to_string_lossy().to_string(),
+                ));
+            }
+
             let new_name_with_wrong_ext =
                 with_basename(&dirname_entry.filename, &basename_entry.filename);
             new_name = with_extension(&new_name_with_wrong_ext, &contents_entry.filename);
@@ -444,29 +698,47 @@ impl ResolutionThunk {
         //now trash all other entries (ignoring contents_entry)
         debug!("Trashing all files except contents_entry");
         for entry in entries_to_trash {
-            self.trash_file(&entry.filename)?;
+            let trashed_path = self.trash_file(&entry.filename)?;
+            transaction
+                .moves
+                .push((entry.filename.clone(), trashed_path));
         }
 
         if need_to_move_contents {
             debug!("Moving contents_entry to dir of dirname_entry with name of basename_entry");
-            move_path(&contents_entry.filename, &new_name)?;
+            let renamed_path = move_path(&contents_entry.filename, &new_name)?;
+            transaction
+                .renames
+                .push((contents_entry.filename.clone(), renamed_path));
         }
 
-        Ok(())
+        Ok(transaction)
+    }
+
+    fn frames_differ(&self, a: &Path, b: &Path) -> bool {
+        self.frames_differ
+            .iter()
+            .any(|(p1, p2)| (p1 == a && p2 == b) || (p1 == b && p2 == a))
     }
 
     fn get_trash_path(&self, p: &Path) -> Result<PathBuf, TrashError> {
         let relative_filename = p.strip_prefix("/")?;
-        self.gui_trash_path
-            .as_ref()
-            .map(|p| p.join(relative_filename))
-            .ok_or(NoTrashPathError)
+        let gui_trash = self.gui_trash.as_ref().ok_or(NoTrashPathError)?;
+        Ok(match gui_trash.layout {
+            TrashLayout::Mirrored => gui_trash.path.join(relative_filename),
+            TrashLayout::Dated => gui_trash
+                .path
+                .join(date_folder_name(SystemTime::now()))
+                .join(relative_filename),
+        })
     }
 
-    fn untrash_file(&self, trashed_path: &Path) -> Result<(), TrashError> {
-        let Some(gui_trash_path) = &self.gui_trash_path else {
+    //returns the path the file was restored to.
+    fn untrash_file(&self, trashed_path: &Path) -> Result<PathBuf, TrashError> {
+        let Some(gui_trash) = &self.gui_trash else {
             return Err(TrashError::NoTrashPathError);
         };
+        let gui_trash_path = &gui_trash.path;
         if !trashed_path.starts_with(gui_trash_path) {
             return Err(TrashError::TrashPathNotPrefix(
                 gui_trash_path.to_string_lossy().to_string(),
@@ -479,6 +751,9 @@ impl ResolutionThunk {
         for _ in gui_trash_path.components() {
             let _ = trashed_path_components.next();
         }
+        if gui_trash.layout == TrashLayout::Dated {
+            let _ = trashed_path_components.next();
+        }
         let untrash_path = Path::new("/")
             .components()
             .chain(trashed_path_components)
@@ -492,29 +767,20 @@ impl ResolutionThunk {
 
         dbg!(trashed_path, &untrash_path);
 
-        move_path(trashed_path, &untrash_path)?;
-
-        Ok(())
+        move_path(trashed_path, &untrash_path)
     }
 
-    fn trash_file(&self, old_path: &Path) -> Result<(), TrashError> {
+    //returns the path the file now occupies in the trash.
+    fn trash_file(&self, old_path: &Path) -> Result<PathBuf, TrashError> {
         fn is_already_trashed(old_path: &Path, trash_path: &Path) -> Result<bool, TrashError> {
-            fn sha2_file(path: &Path) -> Result<[u8; 32], TrashError> {
-                use sha2::Digest;
-
-                let mut file = match std::fs::File::open(path) {
-                    Ok(file) => Ok(file),
-                    Err(e) => Err(TrashError::FileOpenError(
-                        path.to_string_lossy().to_string(),
-                        e,
-                    )),
-                }?;
-                let mut hasher = sha2::Sha256::new();
-
-                match std::io::copy(&mut file, &mut hasher) {
-                    Ok(_) => Ok(hasher.finalize().into()),
-                    Err(e) => Err(TrashError::IoError(path.to_string_lossy().to_string(), e)),
-                }
+            fn fingerprint(path: &Path) -> Result<ContentId, TrashError> {
+                ContentId::of_file(path)
+                    .map_err(|e| TrashError::FileOpenError(path.to_string_lossy().to_string(), e))
+            }
+
+            fn full_hash(id: ContentId, path: &Path) -> Result<blake3::Hash, TrashError> {
+                id.full_hash(path)
+                    .map_err(|e| TrashError::IoError(path.to_string_lossy().to_string(), e))
             }
 
             //If there is no file in the trash path, then it is not already trashed.
@@ -522,11 +788,17 @@ impl ResolutionThunk {
                 return Ok(false);
             }
 
-            Ok(sha2_file(old_path)? == sha2_file(trash_path)?)
+            //Most files in the trash differ from the file being trashed in size or at their ends,
+            //so they are only read in full if their fingerprints match.
+            let (old_id, trash_id) = (fingerprint(old_path)?, fingerprint(trash_path)?);
+            if !old_id.may_match(&trash_id) {
+                return Ok(false);
+            }
+            Ok(full_hash(old_id, old_path)? == full_hash(trash_id, trash_path)?)
         }
 
-        if let Some(gui_trash_path) = &self.gui_trash_path {
-            if old_path.starts_with(gui_trash_path) {
+        if let Some(gui_trash) = &self.gui_trash {
+            if old_path.starts_with(&gui_trash.path) {
                 return Err(TrashError::AlreadyTrashed(
                     old_path.to_string_lossy().to_string(),
                 ));
@@ -537,13 +809,14 @@ impl ResolutionThunk {
 
         debug!("  trashing {}", old_path.display());
 
+        //If an identical copy is already in the trash then that copy stands in for
+        //the deleted file when undoing.
         if is_already_trashed(old_path, &new_path)? {
             delete_path(old_path)?;
+            Ok(new_path)
         } else {
-            move_path(old_path, &new_path)?;
+            move_path(old_path, &new_path)
         }
-
-        Ok(())
     }
 }
 
@@ -558,7 +831,9 @@ fn delete_path(path: &Path) -> Result<(), TrashError> {
     Ok(())
 }
 
-fn move_path(source: &Path, dest: &Path) -> Result<(), TrashError> {
+//returns the path that source was actually moved to, which differs from dest
+//if dest already existed.
+fn move_path(source: &Path, dest: &Path) -> Result<PathBuf, TrashError> {
     debug!(
         "      Moving {} ------> {}",
         source.display(),
@@ -616,28 +891,311 @@ fn move_path(source: &Path, dest: &Path) -> Result<(), TrashError> {
         }
     }
 
-    Ok(())
+    Ok(dest)
+}
+
+//The name of the folder for the (UTC) day of the given time in the dated trash layout, in the
+//form YYYY-MM-DD.
+fn date_folder_name(time: SystemTime) -> String {
+    let days = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+        / 86400;
+
+    //days since 1970-01-01 to a civil date, from Howard Hinnant's date algorithms.
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let day_of_era = z % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + u64::from(month <= 2);
+    format!("{year:04}-{month:02}-{day:02}")
 }
 
 //with a given path, check if it already exists on the filesystem.
 //If it does, append a suffix that does not exist (in the form "(1)" or "(2)" etc..)
-//until a filename is found that does exist. Then return the new name.
+//and return the new name.
 fn get_new_name_if_path_already_exists(p: &Path) -> PathBuf {
-    let original_stem = p.file_stem().unwrap();
+    let max_name_bytes = p.parent().map_or(DEFAULT_MAX_NAME_BYTES, max_name_bytes);
+    first_free_name(p, max_name_bytes, Path::exists)
+}
+
+//Hot names can have hundreds of numbered copies, so rather than trying every counter in turn,
+//the counter is doubled until a free name is found and then the gap between it and the last
+//taken name is bisected, which looks at O(log n) names. Copies are numbered from 1 upwards so
+//this finds the next number, but if some have since been removed it may find one of the gaps,
+//which is just as good.
+fn first_free_name(p: &Path, max_name_bytes: usize, is_taken: impl Fn(&Path) -> bool) -> PathBuf {
+    if !is_taken(p) {
+        return p.to_path_buf();
+    }
+
+    let (mut taken, mut free) = (0, 1);
+    while is_taken(&numbered_name(p, free, max_name_bytes)) {
+        taken = free;
+        free *= 2;
+    }
+    while free - taken > 1 {
+        let mid = taken + (free - taken) / 2;
+        if is_taken(&numbered_name(p, mid, max_name_bytes)) {
+            taken = mid;
+        } else {
+            free = mid;
+        }
+    }
+
+    numbered_name(p, free, max_name_bytes)
+}
+
+//The path with " (counter)" appended to its stem, keeping the extension. If that would make the
+//name longer than max_name_bytes then the stem is shortened instead of the suffix or extension.
+fn numbered_name(p: &Path, counter: u64, max_name_bytes: usize) -> PathBuf {
+    let suffix = format!(" ({counter})");
     let extension = p.extension();
+    let extension_len = extension.map_or(0, |ext| ext.len() + 1);
+    let max_stem_len = max_name_bytes.saturating_sub(suffix.len() + extension_len);
+
+    let mut name = truncated(p.file_stem().unwrap_or_default(), max_stem_len);
+    name.push(suffix);
+    if let Some(extension) = extension {
+        name.push(".");
+        name.push(extension);
+    }
+    p.with_file_name(name)
+}
 
-    let mut ret = p.to_path_buf();
-    let mut counter = 1u64;
-    while ret.exists() {
-        let mut new_file_stem = original_stem.to_os_string();
-        new_file_stem.push(OsString::from(format!(" ({counter})")));
-        ret.set_file_name(new_file_stem);
-        if let Some(ref extension) = extension {
-            ret.set_extension(extension);
+//Shorten a name to at most max_len bytes. Names which are UTF-8 (nearly all of them) are cut on a
+//character boundary so that they stay UTF-8.
+fn truncated(name: &OsStr, max_len: usize) -> OsString {
+    if name.len() <= max_len {
+        return name.to_os_string();
+    }
+    match name.to_str() {
+        Some(name) => {
+            let end = (0..=max_len)
+                .rev()
+                .find(|&i| name.is_char_boundary(i))
+                .unwrap_or_default();
+            OsString::from(&name[..end])
         }
+        None => OsStr::from_bytes(&name.as_bytes()[..max_len]).to_os_string(),
+    }
+}
+
+//The limit of most filesystems, for when the real one cannot be found.
+const DEFAULT_MAX_NAME_BYTES: usize = 255;
+
+//The longest file name allowed by the filesystem that the directory is on, in bytes.
+fn max_name_bytes(dir: &Path) -> usize {
+    let Ok(dir) = std::ffi::CString::new(dir.as_os_str().as_bytes()) else {
+        return DEFAULT_MAX_NAME_BYTES;
+    };
+    //Safety: dir is a valid nul-terminated string for the duration of the call.
+    let max = unsafe { libc::pathconf(dir.as_ptr(), libc::_PC_NAME_MAX) };
+    usize::try_from(max)
+        .ok()
+        .filter(|&max| max > 0)
+        .unwrap_or(DEFAULT_MAX_NAME_BYTES)
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::Cell;
 
-        counter += 1;
+    use super::*;
+    use crate::app::match_group_ext::MatchGroupExt;
+
+    fn touch(dir: &Path, name: impl AsRef<Path>) -> PathBuf {
+        let p = dir.join(name);
+        std::fs::create_dir_all(p.parent().unwrap()).unwrap();
+        std::fs::write(&p, "contents").unwrap();
+        p
+    }
+
+    fn thunk(reference: Option<&str>, entries: &[&str]) -> ResolutionThunk {
+        ResolutionThunk::from_paths(reference, entries)
+    }
+
+    #[test]
+    fn test_group_id_depends_only_on_the_group() {
+        let id = thunk(None, &["/a.mp4", "/bb.mp4"]).group_id();
+        assert_eq!(thunk(None, &["/bb.mp4", "/a.mp4"]).group_id(), id);
+
+        let mut with_distance = thunk(None, &["/a.mp4", "/bb.mp4"]);
+        with_distance.distance = Some(0.1);
+        assert_eq!(with_distance.group_id(), id);
+
+        assert_ne!(thunk(None, &["/a.mp4", "/c.mp4"]).group_id(), id);
+        assert_ne!(thunk(Some("/a.mp4"), &["/bb.mp4"]).group_id(), id);
+
+        //the same as the id of the group that the thunk was made from.
+        let group = MatchGroup::new(vec![PathBuf::from("/bb.mp4"), PathBuf::from("/a.mp4")]);
+        assert_eq!(group.unwrap().group_id(), id);
+        let group =
+            MatchGroup::new_with_reference(PathBuf::from("/a.mp4"), vec![PathBuf::from("/bb.mp4")]);
+        assert_eq!(
+            group.unwrap().group_id(),
+            thunk(Some("/a.mp4"), &["/bb.mp4"]).group_id()
+        );
+    }
+
+    #[test]
+    fn test_containment() {
+        let mut thunk = thunk(None, &["/a.mp4", "/bb.mp4", "/ccc.mp4"]);
+        thunk.containment = Box::new([
+            (PathBuf::from("/ccc.mp4"), PathBuf::from("/a.mp4")),
+            (PathBuf::from("/ccc.mp4"), PathBuf::from("/bb.mp4")),
+        ]);
+
+        let contained_in = |path| thunk.contained_in(Path::new(path)).collect::<Vec<_>>();
+        let contents_of = |path| thunk.contents_of(Path::new(path)).collect::<Vec<_>>();
+        assert_eq!(contained_in("/a.mp4"), [Path::new("/ccc.mp4")]);
+        assert!(contained_in("/ccc.mp4").is_empty());
+        assert_eq!(
+            contents_of("/ccc.mp4"),
+            [Path::new("/a.mp4"), Path::new("/bb.mp4")]
+        );
+        assert!(contents_of("/a.mp4").is_empty());
+    }
+
+    #[test]
+    fn test_files_whose_frames_differ_are_not_removed() {
+        let mut thunk = thunk(None, &["/a.mp4", "/bb.mp4", "/ccc.mp4"]);
+        thunk.frames_differ = Box::new([(PathBuf::from("/bb.mp4"), PathBuf::from("/a.mp4"))]);
+
+        //keeping either video of the pair would remove the other.
+        for choice in ["0", "1", "0 as 2"] {
+            assert!(
+                matches!(thunk.resolve(choice), Err(FramesDiffer(_))),
+                "{choice}"
+            );
+        }
+
+        //but keeping the third is allowed (and only fails here because it doesn't exist).
+        assert!(matches!(thunk.resolve("2"), Err(MissingContentsFile(_))));
+    }
+
+    #[test]
+    fn test_protected_files_are_not_removed() {
+        let protected = |idx: usize| {
+            let mut thunk = thunk(None, &["/a.mp4", "/bb.mp4", "/ccc.mp4"]);
+            thunk.entries[idx].is_protected = true;
+            thunk
+        };
+
+        //keeping another file would remove the protected one, wherever it is in the group.
+        for idx in 0..3 {
+            let thunk = protected(idx);
+            assert!(thunk.is_protected(&thunk.entries[idx].filename));
+            for choice in ["0", "1", "2"]
+                .into_iter()
+                .filter(|choice| *choice != idx.to_string())
+            {
+                assert!(
+                    matches!(thunk.resolve(choice), Err(ProtectedEntry(_))),
+                    "{idx}: {choice}"
+                );
+            }
+
+            //keeping it is allowed (and only fails here because it doesn't exist).
+            assert!(matches!(
+                thunk.resolve(&idx.to_string()),
+                Err(MissingContentsFile(_))
+            ));
+        }
+
+        //a protected file can't donate its name, as it would be removed...
+        assert!(matches!(
+            protected(2).resolve("0 as 2"),
+            Err(ProtectedEntry(_))
+        ));
+        //...or be moved to take the name of another.
+        assert!(matches!(
+            protected(0).resolve("0 as 2"),
+            Err(ProtectedEntry(_))
+        ));
+    }
+
+    #[test]
+    fn test_many_collisions_take_few_probes() {
+        let dir = tempfile::tempdir().unwrap();
+        let original = touch(dir.path(), "clip.mp4");
+        for counter in 1..=300 {
+            touch(dir.path(), format!("clip ({counter}).mp4"));
+        }
+
+        let probes = Cell::new(0);
+        let new_name = first_free_name(&original, DEFAULT_MAX_NAME_BYTES, |p| {
+            probes.set(probes.get() + 1);
+            p.exists()
+        });
+        assert_eq!(new_name, dir.path().join("clip (301).mp4"));
+        assert!(probes.get() <= 20, "{} probes", probes.get());
+        assert_eq!(get_new_name_if_path_already_exists(&original), new_name);
+
+        //names which are free are returned as they are.
+        let free = dir.path().join("other.mp4");
+        assert_eq!(get_new_name_if_path_already_exists(&free), free);
     }
 
-    ret
+    #[test]
+    fn test_long_names_are_truncated() {
+        let dir = tempfile::tempdir().unwrap();
+
+        //a 250 byte name, whose stem is two-byte characters.
+        let long_name = format!("{}.mp4", "é".repeat(123));
+        assert_eq!(long_name.len(), 250);
+        let original = touch(dir.path(), &long_name);
+
+        let new_name = get_new_name_if_path_already_exists(&original);
+        let file_name = new_name.file_name().unwrap();
+        assert!(file_name.len() <= 255);
+        let file_name = file_name.to_str().expect("still UTF-8");
+        assert!(file_name.starts_with("éé"));
+        assert!(file_name.ends_with("é (1).mp4"));
+        std::fs::write(&new_name, "contents").expect("the new name is short enough to create");
+
+        //shortened names are still numbered on from the copies which exist.
+        let next_name = get_new_name_if_path_already_exists(&original);
+        assert!(next_name.to_str().unwrap().ends_with(" (2).mp4"));
+    }
+
+    #[test]
+    fn test_date_folder_name() {
+        let at = |secs| date_folder_name(UNIX_EPOCH + std::time::Duration::from_secs(secs));
+        assert_eq!(at(0), "1970-01-01");
+        assert_eq!(at(951_868_740), "2000-02-29");
+        assert_eq!(at(1_735_646_400), "2024-12-31");
+    }
+
+    #[test]
+    fn test_dated_trash_layout() {
+        let dir = tempfile::tempdir().unwrap();
+        let trash = dir.path().join("trash");
+        let original = touch(dir.path(), "videos/a.mp4");
+        let thunk = ResolutionThunk {
+            gui_trash: Some(Box::new(GuiTrash {
+                path: trash.clone(),
+                layout: TrashLayout::Dated,
+            })),
+            ..ResolutionThunk::default()
+        };
+
+        //(in case the day changes while trashing)
+        let before = SystemTime::now();
+        let trashed = thunk.trash_file(&original).unwrap();
+        let days = [before, SystemTime::now()].map(date_folder_name);
+        assert!(!original.exists());
+        assert!(days
+            .iter()
+            .any(|day| trashed == trash.join(day).join(original.strip_prefix("/").unwrap())));
+
+        assert_eq!(thunk.untrash_file(&trashed).unwrap(), original);
+        assert!(original.exists());
+    }
 }
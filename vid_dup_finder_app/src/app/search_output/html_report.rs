@@ -0,0 +1,437 @@
+//! A report for reviewing duplicates in a web browser, with no gui and no technical knowledge
+//! needed.
+//!
+//! The report is a single HTML file with everything inlined: its style, the script which sorts
+//! and filters the groups, and a strip of thumbnails of every file as a JPEG data uri. It can be
+//! copied anywhere and opened offline.
+
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    num::NonZeroU32,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use bytesize::ByteSize;
+use image::{codecs::jpeg::JpegEncoder, RgbImage};
+#[cfg(feature = "parallel_loading")]
+use rayon::prelude::*;
+use vid_dup_finder_lib::fmt::format_duration;
+
+use super::report::{ReportError, ReportRow, ReportSink};
+
+/// The height of the thumbnails in a report, in pixels.
+pub const THUMBNAIL_HEIGHT: NonZeroU32 = NonZeroU32::new(72).expect("literal value");
+
+const JPEG_QUALITY: u8 = 70;
+
+const HEADER: &str = r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>Duplicate videos</title>
+<style>
+body { font-family: sans-serif; margin: 0; background: #f4f4f4; color: #222; }
+header { position: sticky; top: 0; background: #333; color: #fff; padding: 0.5em 1em; }
+header h1 { font-size: 1.3em; margin: 0 0 0.3em 0; }
+header input, header select { font-size: 1em; margin-right: 1em; }
+main { padding: 1em; }
+section.group { background: #fff; border-radius: 4px; margin-bottom: 1em; padding: 0.5em 1em; box-shadow: 0 1px 3px #aaa; }
+section.group h2 { font-size: 1.1em; margin: 0.3em 0; }
+section.group h2 .group-id { color: #888; font-weight: normal; font-size: 0.8em; }
+table { border-collapse: collapse; width: 100%; }
+th, td { text-align: left; padding: 0.3em 0.6em; border-bottom: 1px solid #eee; vertical-align: middle; }
+td.path { word-break: break-all; font-family: monospace; }
+tr.kept td.path::after { content: " (kept)"; color: #080; font-family: sans-serif; }
+.no-thumb { color: #999; font-style: italic; }
+.containment { display: block; color: #a60; font-family: sans-serif; }
+</style>
+</head>
+<body>
+<header>
+<h1>Duplicate videos</h1>
+<label>Filter <input id="filter" type="search" placeholder="Part of a path"></label>
+<label>Sort by <select id="sort">
+<option value="index">Order found</option>
+<option value="size">Total size</option>
+<option value="members">Number of files</option>
+<option value="similarity">Similarity</option>
+</select></label>
+<span id="shown"></span>
+</header>
+<main id="groups">
+"#;
+
+const FOOTER: &str = r#"</main>
+<script>
+const groups = Array.from(document.querySelectorAll("section.group"));
+const main = document.getElementById("groups");
+const filter = document.getElementById("filter");
+const sort = document.getElementById("sort");
+
+function update() {
+  const needle = filter.value.toLowerCase();
+  const key = sort.value;
+  const sorted = groups.slice().sort((a, b) => key === "index"
+    ? a.dataset.index - b.dataset.index
+    : b.dataset[key] - a.dataset[key] || a.dataset.index - b.dataset.index);
+  let shown = 0;
+  for (const group of sorted) {
+    group.hidden = !group.dataset.paths.includes(needle);
+    shown += group.hidden ? 0 : 1;
+    main.appendChild(group);
+  }
+  document.getElementById("shown").textContent = shown + " of " + groups.length + " groups";
+}
+
+filter.addEventListener("input", update);
+sort.addEventListener("change", update);
+update();
+</script>
+</body>
+</html>
+"#;
+
+/// Writes a self-contained HTML report, replacing whatever was there. Thumbnails are made from
+/// each member with `thumbnail`, until `thumbnail_budget` bytes of them have been embedded. The
+/// members after that are shown without one.
+///
+/// Each group is written as soon as its thumbnails are made, so only one group's thumbnails are
+/// in memory at once.
+pub struct HtmlReport<T> {
+    path: PathBuf,
+    thumbnail_budget: u64,
+    thumbnail: T,
+}
+
+impl<T> HtmlReport<T>
+where
+    T: Fn(&Path) -> Option<RgbImage> + Sync,
+{
+    pub fn new(path: impl Into<PathBuf>, thumbnail_budget: u64, thumbnail: T) -> Self {
+        Self {
+            path: path.into(),
+            thumbnail_budget,
+            thumbnail,
+        }
+    }
+
+    // The thumbnails of a group's members as data uris, in the same order. None for members with
+    // no thumbnail, or with one that did not fit in what is left of the budget.
+    fn thumbnails(&self, rows: &[ReportRow], budget_left: &mut u64) -> Vec<Option<String>> {
+        if *budget_left == 0 {
+            return vec![None; rows.len()];
+        }
+
+        #[cfg(feature = "parallel_loading")]
+        let it = rows.par_iter();
+
+        #[cfg(not(feature = "parallel_loading"))]
+        let it = rows.iter();
+
+        let thumbnails = it
+            .map(|row| (self.thumbnail)(&row.member_path).and_then(|img| data_uri(&img)))
+            .collect::<Vec<_>>();
+
+        thumbnails
+            .into_iter()
+            .map(|uri| {
+                let uri = uri?;
+                let len = uri.len() as u64;
+                if len <= *budget_left {
+                    *budget_left -= len;
+                    Some(uri)
+                } else {
+                    *budget_left = 0;
+                    None
+                }
+            })
+            .collect()
+    }
+}
+
+impl<T> ReportSink for HtmlReport<T>
+where
+    T: Fn(&Path) -> Option<RgbImage> + Sync,
+{
+    fn write_rows(&mut self, rows: &[ReportRow]) -> Result<(), ReportError> {
+        let io_err = |e| ReportError::Io(self.path.clone(), e);
+
+        let mut f = BufWriter::new(File::create(&self.path).map_err(io_err)?);
+        f.write_all(HEADER.as_bytes()).map_err(io_err)?;
+
+        let mut budget_left = self.thumbnail_budget;
+        let mut omitted = 0;
+        for (idx, rows) in rows.chunk_by(|a, b| a.group_id == b.group_id).enumerate() {
+            let thumbnails = self.thumbnails(rows, &mut budget_left);
+            omitted += thumbnails.iter().filter(|uri| uri.is_none()).count();
+            write_group(&mut f, idx, rows, &thumbnails).map_err(io_err)?;
+        }
+
+        f.write_all(FOOTER.as_bytes()).map_err(io_err)?;
+        if omitted > 0 {
+            info!(
+                "{omitted} files in {} have no thumbnail",
+                self.path.display()
+            );
+        }
+        f.flush().map_err(io_err)
+    }
+}
+
+fn data_uri(img: &RgbImage) -> Option<String> {
+    let mut jpeg = vec![];
+    JpegEncoder::new_with_quality(&mut jpeg, JPEG_QUALITY)
+        .encode_image(img)
+        .ok()?;
+    Some(format!("data:image/jpeg;base64,{}", STANDARD.encode(jpeg)))
+}
+
+// How similar a member's hash is to that of the kept file, from 0 to 1.
+fn similarity(row: &ReportRow) -> Option<f64> {
+    let (distance, bits) = row.distance_to_best.zip(row.hash_bits)?;
+    Some(1.0 - f64::from(distance) / f64::from(bits.max(1)))
+}
+
+fn write_group(
+    f: &mut impl Write,
+    idx: usize,
+    rows: &[ReportRow],
+    thumbnails: &[Option<String>],
+) -> std::io::Result<()> {
+    let total_size = rows.iter().filter_map(|row| row.file_size).sum::<u64>();
+    //the least similar member, so that sorting by similarity puts the most doubtful groups last.
+    let group_similarity = rows
+        .iter()
+        .filter_map(similarity)
+        .min_by(f64::total_cmp)
+        .unwrap_or(0.0);
+    let paths = rows
+        .iter()
+        .map(|row| row.member_path.to_string_lossy().to_lowercase())
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    writeln!(
+        f,
+        r#"<section class="group" data-index="{idx}" data-size="{total_size}" data-members="{}" data-similarity="{group_similarity:.4}" data-paths="{}">"#,
+        rows.len(),
+        escape(&paths),
+    )?;
+    writeln!(
+        f,
+        r#"<h2>Group {} <span class="group-id">{}</span></h2>"#,
+        idx + 1,
+        escape(&rows[0].group_id)
+    )?;
+    writeln!(f, "<table>")?;
+    writeln!(f, "<tr><th>Thumbnails</th><th>Path</th><th>Duration</th><th>Resolution</th><th>Size</th><th>Similarity</th></tr>")?;
+
+    for (row, thumbnail) in rows.iter().zip(thumbnails) {
+        let class = if row.distance_to_best == Some(0) {
+            r#" class="kept""#
+        } else {
+            ""
+        };
+        let thumbnail = match thumbnail {
+            Some(uri) => format!(r#"<img src="{uri}" alt="">"#),
+            None => r#"<span class="no-thumb">No thumbnail</span>"#.to_string(),
+        };
+        let path = row.member_path.to_string_lossy();
+        let path = if row.is_reference {
+            format!("{} (reference)", escape(&path))
+        } else {
+            escape(&path)
+        };
+        let path = format!("{path}{}", containment(row, rows));
+        let duration = row
+            .duration
+            .map(|secs| format_duration(Duration::from_secs(u64::from(secs))))
+            .unwrap_or_default();
+        let resolution = row.resolution_text().unwrap_or_default();
+        let size = row
+            .file_size
+            .map(|size| ByteSize::b(size).to_string())
+            .unwrap_or_default();
+        let similarity = similarity(row)
+            .map(|similarity| format!("{:.1}%", similarity * 100.0))
+            .unwrap_or_default();
+
+        writeln!(
+            f,
+            r#"<tr{class}><td>{thumbnail}</td><td class="path">{path}</td><td>{duration}</td><td>{resolution}</td><td>{size}</td><td>{similarity}</td></tr>"#
+        )?;
+    }
+
+    writeln!(f, "</table>\n</section>")
+}
+
+// Which other members of the group the member was cut from, or were cut from it, found with
+// --containment. Empty if neither.
+fn containment(row: &ReportRow, rows: &[ReportRow]) -> String {
+    let names = |paths: Vec<&Path>| {
+        paths
+            .iter()
+            .map(|path| escape(&path.file_name().unwrap_or_default().to_string_lossy()))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    let contents = rows
+        .iter()
+        .filter(|other| other.contained_in.contains(&row.member_path))
+        .map(|other| other.member_path.as_path())
+        .collect::<Vec<_>>();
+
+    let mut ret = String::new();
+    if !row.contained_in.is_empty() {
+        let longer = row.contained_in.iter().map(PathBuf::as_path).collect();
+        ret += &format!(
+            r#"<span class="containment">cut from {}</span>"#,
+            names(longer)
+        );
+    }
+    if !contents.is_empty() {
+        ret += &format!(
+            r#"<span class="containment">contains {}</span>"#,
+            names(contents)
+        );
+    }
+    ret
+}
+
+fn escape(text: &str) -> String {
+    let mut ret = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => ret.push_str("&amp;"),
+            '<' => ret.push_str("&lt;"),
+            '>' => ret.push_str("&gt;"),
+            '"' => ret.push_str("&quot;"),
+            '\'' => ret.push_str("&#39;"),
+            '\n' => ret.push_str("&#10;"),
+            c => ret.push(c),
+        }
+    }
+    ret
+}
+
+#[cfg(test)]
+mod test {
+    use image::Rgb;
+
+    use super::*;
+    use crate::app::search_output::example_vid;
+
+    // The cats and the dogs of the example videos, as two groups.
+    fn example_rows() -> Vec<ReportRow> {
+        [
+            ("cats", "cat.1.mp4", 0),
+            ("cats", "cat.2.mp4", 3),
+            ("dogs", "dog.1.mp4", 0),
+            ("dogs", "dog.2.mp4", 5),
+            ("dogs", "dog.3.webm", 8),
+        ]
+        .into_iter()
+        .map(|(group_id, name, distance)| {
+            let member_path = example_vid(name);
+            ReportRow {
+                group_id: group_id.to_string(),
+                file_size: std::fs::metadata(&member_path).ok().map(|m| m.len()),
+                member_path,
+                is_reference: false,
+                distance_to_best: Some(distance),
+                hash_bits: Some(512),
+                duration: Some(30),
+                resolution: Some((640, 480)),
+                video_id: None,
+                contained_in: vec![],
+            }
+        })
+        .collect()
+    }
+
+    // Stands in for the frames of the example videos, which cannot be decoded without ffmpeg.
+    fn thumbnail(path: &Path) -> Option<RgbImage> {
+        let shade = path.to_string_lossy().len() as u8;
+        Some(RgbImage::from_fn(96, 54, |x, y| {
+            Rgb([shade, x as u8 * 2, y as u8 * 4])
+        }))
+    }
+
+    fn render(budget: u64) -> String {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("report.html");
+        HtmlReport::new(&path, budget, thumbnail)
+            .write_rows(&example_rows())
+            .unwrap();
+        std::fs::read_to_string(&path).unwrap()
+    }
+
+    #[test]
+    fn test_report_landmarks() {
+        let html = render(u64::MAX);
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert!(html.trim_end().ends_with("</html>"));
+        assert_eq!(html.matches(r#"<section class="group""#).count(), 2);
+        assert_eq!(html.matches("</section>").count(), 2);
+        assert_eq!(
+            html.matches(r#"<img src="data:image/jpeg;base64,/9j/"#)
+                .count(),
+            5
+        );
+        assert_eq!(html.matches(r#"<tr class="kept">"#).count(), 2);
+        assert!(html.contains(r#"data-members="3""#));
+        assert!(html.contains("99.4%"));
+        assert!(html.contains(&example_vid("dog.3.webm").to_string_lossy().to_string()));
+
+        //nothing is fetched from anywhere else.
+        assert!(!html.contains(r#"src="http"#) && !html.contains("href="));
+        assert!(html.contains("<style>") && html.contains("<script>"));
+    }
+
+    #[test]
+    fn test_thumbnails_stop_at_the_budget() {
+        let uri_len = |name| {
+            data_uri(&thumbnail(&example_vid(name)).unwrap())
+                .unwrap()
+                .len() as u64
+        };
+
+        let html = render(0);
+        assert_eq!(html.matches("<img").count(), 0);
+        assert_eq!(html.matches("No thumbnail").count(), 5);
+
+        //the budget is spent in order, and the first thumbnail which does not fit ends it.
+        let html = render(uri_len("cat.1.mp4") + uri_len("cat.2.mp4") + 10);
+        assert_eq!(html.matches("<img").count(), 2);
+        assert_eq!(html.matches("No thumbnail").count(), 3);
+    }
+
+    #[test]
+    fn test_containment_is_labelled() {
+        let mut rows = example_rows();
+        rows[1].contained_in = vec![rows[0].member_path.clone()];
+
+        assert_eq!(
+            containment(&rows[0], &rows),
+            r#"<span class="containment">contains cat.2.mp4</span>"#
+        );
+        assert_eq!(
+            containment(&rows[1], &rows),
+            r#"<span class="containment">cut from cat.1.mp4</span>"#
+        );
+        assert_eq!(containment(&rows[2], &rows), "");
+    }
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(
+            escape(r#"<a href="x">'&'</a>"#),
+            "&lt;a href=&quot;x&quot;&gt;&#39;&amp;&#39;&lt;/a&gt;"
+        );
+        assert_eq!(escape("plain/path.mp4"), "plain/path.mp4");
+    }
+}
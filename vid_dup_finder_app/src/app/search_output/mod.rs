@@ -0,0 +1,660 @@
+use std::{
+    io::{prelude::*, BufWriter},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use bytesize::ByteSize;
+use itertools::Itertools;
+use serde::Serialize;
+use serde_json::json;
+use vid_dup_finder_lib::{MatchGroup, VideoHash};
+
+use crate::app::match_group_ext::MatchGroupExt;
+use crate::video_hash_filesystem_cache::filename_pattern::SymlinkAliases;
+use crate::video_hash_filesystem_cache::UpdateReport;
+use crate::video_hash_filesystem_cache::VideoHashFilesystemCache;
+
+#[cfg(all(target_family = "unix", feature = "gui_slint",))]
+use crate::app::{ResolutionThunk, TrashLayout};
+
+use super::Sorting;
+
+mod report;
+pub use report::{CsvReport, ReportError, ReportSink, ReportSources, SqliteReport};
+
+mod html_report;
+pub use html_report::{HtmlReport, THUMBNAIL_HEIGHT};
+
+mod quarantine;
+#[cfg(all(target_family = "unix", feature = "gui_slint"))]
+pub use quarantine::manifest_path as quarantine_manifest_path;
+pub use quarantine::QuarantineError;
+
+// Exit codes used with --strict-exit-codes. Without it, every run which completes exits with
+// EXIT_OK.
+pub const EXIT_OK: i32 = 0;
+pub const EXIT_FATAL: i32 = 1;
+pub const EXIT_DUPS_FOUND: i32 = 2;
+pub const EXIT_FILE_ERRORS: i32 = 3;
+
+// Used by `vid_dup_finder cache <command> --dry-run`, when the command would have changed the
+// cache (or the file it exports to).
+pub const EXIT_CHANGES_NEEDED: i32 = 4;
+
+// Used whether or not --strict-exit-codes is given, when the run was cut short by a signal. The
+// shell convention for SIGINT.
+pub const EXIT_INTERRUPTED: i32 = 130;
+
+/// Counts describing a whole run, printed when it ends.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct RunSummary {
+    /// Files which were found on disk. Zero when the cache was not updated.
+    pub files_scanned: usize,
+    /// Files which were new or modified, and were hashed during this run.
+    pub hashed: usize,
+    /// Files which were unchanged, so their hash was taken from the cache.
+    pub cache_hits: usize,
+    /// Files which could not be hashed.
+    pub errors: usize,
+    /// Files which were too short to hash. They are not counted as errors.
+    pub too_short: usize,
+    /// Groups of duplicates found by the search.
+    pub groups: usize,
+    /// Bytes which would be freed by keeping only the best file of every group.
+    pub potential_savings: u64,
+    /// Groups left out of the potential savings, as keeping their best file would remove a
+    /// protected file.
+    pub protected_groups: usize,
+    /// The run was stopped early by a signal, after saving the caches.
+    pub interrupted: bool,
+}
+
+impl RunSummary {
+    pub fn from_update_report(report: &UpdateReport) -> Self {
+        Self {
+            files_scanned: report.hashed.len()
+                + report.skipped_cached
+                + report.failed.len()
+                + report.too_short.len(),
+            hashed: report.hashed.len(),
+            cache_hits: report.skipped_cached,
+            errors: report.failed.len(),
+            too_short: report.too_short.len(),
+            ..Self::default()
+        }
+    }
+
+    // File errors take precedence over duplicates, so that a cron job notices them.
+    pub fn exit_code(&self) -> i32 {
+        if self.errors > 0 {
+            EXIT_FILE_ERRORS
+        } else if self.groups > 0 {
+            EXIT_DUPS_FOUND
+        } else {
+            EXIT_OK
+        }
+    }
+
+    pub fn summary_line(&self) -> String {
+        format!(
+            "Summary: {} files scanned, {} hashed, {} cached, {} errors, {} too short, {} duplicate groups, {} potential savings{}{}",
+            self.files_scanned,
+            self.hashed,
+            self.cache_hits,
+            self.errors,
+            self.too_short,
+            self.groups,
+            ByteSize::b(self.potential_savings),
+            if self.protected_groups > 0 {
+                format!(
+                    ", {} groups skipped as they contain protected files",
+                    self.protected_groups
+                )
+            } else {
+                String::new()
+            },
+            if self.interrupted { " (interrupted)" } else { "" }
+        )
+    }
+}
+
+/// What [`SearchOutput::sort`] needs to know about a file. Anything which is not known is None.
+#[derive(Debug, Clone, Default)]
+pub struct SortKeyData {
+    /// The hash of the file, for sorting by distance or duration.
+    pub hash: Option<VideoHash>,
+    /// The size of the file, in bytes. Files of unknown size count as empty.
+    pub size: Option<u64>,
+    /// When the file was last modified.
+    pub modified: Option<SystemTime>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SearchOutput {
+    dup_groups: Vec<MatchGroup>,
+    aliases: SymlinkAliases,
+}
+
+impl SearchOutput {
+    pub fn new(dup_groups: Vec<MatchGroup>) -> Self {
+        Self {
+            dup_groups,
+            aliases: SymlinkAliases::default(),
+        }
+    }
+
+    pub fn with_aliases(mut self, aliases: SymlinkAliases) -> Self {
+        self.aliases = aliases;
+        self
+    }
+
+    /// The paths other than the given path which lead to the same file, when symlinks are deduplicated.
+    pub fn aliases_of(&self, path: impl AsRef<Path>) -> Vec<PathBuf> {
+        self.aliases.aliases_of(path)
+    }
+
+    pub fn len(&self) -> usize {
+        self.dup_groups.len()
+    }
+
+    pub fn dup_groups(&self) -> impl Iterator<Item = &MatchGroup> {
+        self.dup_groups.iter()
+    }
+
+    /// Roughly how many bytes of memory the groups use, for diagnosing huge searches. Paths are
+    /// shared between groups, so each is only counted once.
+    pub fn approx_memory_usage(&self) -> usize {
+        size_of::<Self>() + MatchGroup::approx_memory_usage(&self.dup_groups)
+    }
+
+    pub fn dup_paths(&self) -> impl Iterator<Item = &Path> {
+        self.dup_groups.iter().flat_map(MatchGroup::duplicates)
+    }
+
+    /// The total size of every file in every group except the one which would be kept, and the
+    /// number of groups left out because a protected file would not be kept. `keep` chooses the
+    /// file to keep from a group (groups it returns None for are not counted at all), and
+    /// `file_size` returns the size of a file (files it returns None for count as empty).
+    pub fn potential_savings(
+        &self,
+        keep: impl Fn(&MatchGroup) -> Option<PathBuf>,
+        file_size: impl Fn(&Path) -> Option<u64>,
+    ) -> (u64, usize) {
+        let mut savings = 0;
+        let mut protected_groups = 0;
+        for group in &self.dup_groups {
+            let Some(kept) = keep(group) else {
+                continue;
+            };
+            if group.protected_members().any(|path| path != kept) {
+                protected_groups += 1;
+                continue;
+            }
+            savings += group
+                .contained_paths()
+                .filter(|path| *path != kept)
+                .filter_map(&file_size)
+                .sum::<u64>();
+        }
+        (savings, protected_groups)
+    }
+
+    pub fn summary(
+        &self,
+        report: &UpdateReport,
+        keep: impl Fn(&MatchGroup) -> Option<PathBuf>,
+        file_size: impl Fn(&Path) -> Option<u64>,
+    ) -> RunSummary {
+        let (potential_savings, protected_groups) = self.potential_savings(keep, file_size);
+        RunSummary {
+            groups: self.len(),
+            potential_savings,
+            protected_groups,
+            ..RunSummary::from_update_report(report)
+        }
+    }
+
+    /// Sort the groups. `lookup` returns what is known about each file, and is only called when
+    /// the sorting needs it. Groups which tie keep their order.
+    pub fn sort(&mut self, sorting: Sorting, lookup: impl Fn(&Path) -> Option<SortKeyData>) {
+        let data = |g: &MatchGroup| {
+            g.contained_paths()
+                .map(|path| lookup(path).unwrap_or_default())
+                .collect::<Vec<_>>()
+        };
+        let sizes = |g: &MatchGroup| {
+            data(g)
+                .iter()
+                .map(|data| data.size.unwrap_or(0))
+                .collect::<Vec<_>>()
+        };
+
+        let sort_num_matches = |g: &MatchGroup| u64::MAX - g.len() as u64;
+        let sort_distance = |g: &MatchGroup| {
+            data(g)
+                .iter()
+                .tuple_combinations::<(_, _)>()
+                .map(|comb| {
+                    if let (Some(h1), Some(h2)) = (&comb.0.hash, &comb.1.hash) {
+                        h1.hamming_distance(h2).map_or(u64::MAX, u64::from)
+                    } else {
+                        u64::MAX
+                    }
+                })
+                .max()
+                .unwrap_or(u64::MAX)
+        };
+        let sort_duration = |g: &MatchGroup| match data(g).first() {
+            Some(first_vid) => match &first_vid.hash {
+                Some(hash) => u64::MAX - u64::from(hash.duration()),
+                None => u64::MAX / 2,
+            },
+            None => u64::MIN,
+        };
+        let sort_size = |g: &MatchGroup| u64::MAX - sizes(g).iter().sum::<u64>();
+        //as if the reference (or otherwise the biggest file) were kept.
+        let sort_savings = |g: &MatchGroup| {
+            let sizes = g.contained_paths().zip(sizes(g)).collect::<Vec<_>>();
+            let kept = g.reference().or_else(|| {
+                sizes
+                    .iter()
+                    .max_by_key(|(_, size)| *size)
+                    .map(|(path, _)| *path)
+            });
+            let savings = sizes
+                .iter()
+                .filter(|(path, _)| Some(*path) != kept)
+                .map(|(_, size)| size)
+                .sum::<u64>();
+            u64::MAX - savings
+        };
+        let sort_modified = |g: &MatchGroup| {
+            let newest = data(g)
+                .iter()
+                .filter_map(|data| data.modified?.duration_since(UNIX_EPOCH).ok())
+                .max()
+                .unwrap_or_default();
+            u64::MAX - newest.as_secs()
+        };
+
+        let key_fn = |g: &MatchGroup| match sorting {
+            Sorting::NumMatches => sort_num_matches(g),
+            Sorting::RevNumMatches => u64::MAX - sort_num_matches(g),
+            Sorting::Distance => sort_distance(g),
+            Sorting::RevDistance => u64::MAX - sort_distance(g),
+            Sorting::Duration => sort_duration(g),
+            Sorting::RevDuration => u64::MAX - sort_duration(g),
+            Sorting::Size => sort_size(g),
+            Sorting::RevSize => u64::MAX - sort_size(g),
+            Sorting::Savings => sort_savings(g),
+            Sorting::RevSavings => u64::MAX - sort_savings(g),
+            Sorting::Modified => sort_modified(g),
+            Sorting::RevModified => u64::MAX - sort_modified(g),
+        };
+
+        self.dup_groups.sort_by_cached_key(key_fn)
+    }
+
+    pub fn save_debug_imgs(
+        &self,
+        output_thumbs_dir: impl AsRef<Path>,
+        cache: &VideoHashFilesystemCache,
+    ) {
+        #[cfg(feature = "parallel_loading")]
+        use rayon::prelude::*;
+
+        let output_thumbs_dir = output_thumbs_dir.as_ref();
+
+        //first save a json file which maps image names to the duplicate videos within.
+        {
+            //dump indexed log of matches
+            #[derive(Serialize)]
+            struct IdxAndMatchSet {
+                pub idx: u64,
+                pub matchset: Vec<PathBuf>,
+            }
+            let json_vec: Vec<_> = self
+                .dup_groups
+                .iter()
+                .enumerate()
+                .map(|(i, matchset)| IdxAndMatchSet {
+                    idx: i as u64,
+                    matchset: matchset.contained_paths().map(PathBuf::from).collect(),
+                })
+                .collect();
+
+            let s = serde_json::to_string_pretty(&json!(json_vec)).unwrap();
+            let dest_file_name = output_thumbs_dir.join("idx.json");
+            std::fs::create_dir_all(output_thumbs_dir).unwrap();
+            let f = std::fs::File::create(dest_file_name).unwrap();
+            let mut f = BufWriter::new(f);
+            f.write_all(s.as_bytes()).unwrap();
+        }
+
+        #[cfg(feature = "parallel_loading")]
+        let it = self
+            .dup_groups
+            .iter()
+            .enumerate()
+            .par_bridge()
+            .into_par_iter();
+
+        #[cfg(not(feature = "parallel_loading"))]
+        let it = self.dup_groups.iter().enumerate();
+
+        it.for_each(|(i, match_group)| {
+            let output_path = output_thumbs_dir.join(format!("{i}.jpg"));
+
+            info!(
+                target: "write_image",
+                    "Writing match image to {}", output_path.display()
+            );
+
+            match match_group.to_image(cache) {
+                Ok(img) => {
+                    std::fs::create_dir_all(output_path.parent().unwrap()).unwrap();
+                    img.save(output_path).unwrap();
+                }
+                Err(msg) => {
+                    let dup_group_paths = match_group
+                    .contained_paths()
+                    .map(|p| p.to_string_lossy().to_string())
+                    .collect::<Vec<_>>();
+                    warn!(
+                        "failed to save output images: {msg}, dup_group_paths: {dup_group_paths:?}, img_path: {output_path:?}"
+                    );
+                    //panic!();
+                }
+            };
+
+            });
+    }
+
+    #[cfg(all(target_family = "unix", feature = "gui_slint"))]
+    pub fn resolution_thunks(
+        &self,
+        cache: &VideoHashFilesystemCache,
+        gui_trash_path: Option<&Path>,
+        trash_layout: TrashLayout,
+    ) -> Vec<ResolutionThunk> {
+        self.dup_groups
+            .iter()
+            .map(|group| {
+                ResolutionThunk::from_matchgroup(group, cache, gui_trash_path, trash_layout)
+            })
+            .collect::<Vec<_>>()
+    }
+}
+
+//The example videos of vid_dup_finder_lib, for the tests of the reports.
+#[cfg(test)]
+fn example_vid(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("../vid_dup_finder_lib/examples/vids")
+        .join(name)
+}
+
+#[cfg(test)]
+mod test {
+    use vid_dup_finder_lib::Error;
+
+    use super::*;
+    use crate::app::match_filtering;
+    use crate::video_hash_filesystem_cache::VdfCacheError;
+
+    fn paths(names: &[&str]) -> Vec<PathBuf> {
+        names.iter().map(PathBuf::from).collect()
+    }
+
+    // Every file is as many bytes long as the number in its name.
+    fn file_size(path: &Path) -> Option<u64> {
+        path.to_str()?.parse().ok()
+    }
+
+    // Keep the reference if there is one, otherwise the biggest file.
+    fn keep(group: &MatchGroup) -> Option<PathBuf> {
+        group
+            .reference()
+            .or_else(|| group.contained_paths().max_by_key(|path| file_size(path)))
+            .map(PathBuf::from)
+    }
+
+    fn search_output() -> SearchOutput {
+        SearchOutput::new(vec![
+            MatchGroup::new(paths(&["100", "30", "20"])).unwrap(),
+            MatchGroup::new_with_reference(PathBuf::from("5"), paths(&["1000"])).unwrap(),
+        ])
+    }
+
+    #[test]
+    fn test_potential_savings_excludes_kept_files() {
+        let search_output = search_output();
+        assert_eq!(
+            search_output.potential_savings(keep, file_size),
+            (30 + 20 + 1000, 0)
+        );
+
+        //groups with no file to keep are not counted.
+        let keep_in_first_group = |group: &MatchGroup| match group.reference() {
+            Some(_) => None,
+            None => keep(group),
+        };
+        assert_eq!(
+            search_output.potential_savings(keep_in_first_group, file_size),
+            (30 + 20, 0)
+        );
+    }
+
+    #[test]
+    fn test_groups_which_would_remove_protected_files_are_skipped() {
+        let protect = |protected: &'static [&'static str]| {
+            let groups = search_output()
+                .dup_groups()
+                .map(|group| {
+                    group
+                        .clone()
+                        .with_protected(|path| protected.iter().any(|p| path == Path::new(p)))
+                })
+                .collect();
+            SearchOutput::new(groups)
+        };
+        let report = UpdateReport::default();
+
+        //a protected file that would be kept anyway changes nothing.
+        let summary = protect(&["100", "5"]).summary(&report, keep, file_size);
+        assert_eq!(summary.potential_savings, 30 + 20 + 1000);
+        assert_eq!(summary.protected_groups, 0);
+
+        //but a group where one would be removed is left out, and counted.
+        let summary = protect(&["20"]).summary(&report, keep, file_size);
+        assert_eq!(summary.potential_savings, 1000);
+        assert_eq!(summary.protected_groups, 1);
+        assert!(summary
+            .summary_line()
+            .contains(", 1 groups skipped as they contain protected files"));
+
+        let summary = protect(&["1000", "30"]).summary(&report, keep, file_size);
+        assert_eq!(summary.potential_savings, 0);
+        assert_eq!(summary.protected_groups, 2);
+        assert_eq!(
+            protect(&["1000", "30"]).potential_savings(keep, file_size),
+            (0, 2)
+        );
+    }
+
+    #[test]
+    fn test_summary_counts() {
+        let report = UpdateReport {
+            hashed: paths(&["a", "b"]),
+            skipped_cached: 10,
+            failed: vec![],
+            too_short: paths(&["d"]),
+            removed: paths(&["c"]),
+            ..UpdateReport::default()
+        };
+
+        let summary = search_output().summary(&report, keep, file_size);
+        assert_eq!(
+            summary,
+            RunSummary {
+                files_scanned: 13,
+                hashed: 2,
+                cache_hits: 10,
+                errors: 0,
+                too_short: 1,
+                groups: 2,
+                potential_savings: 1050,
+                protected_groups: 0,
+                interrupted: false,
+            }
+        );
+        assert_eq!(summary.exit_code(), EXIT_DUPS_FOUND);
+        assert_eq!(
+            serde_json::to_value(&summary).unwrap(),
+            json!({
+                "files_scanned": 13,
+                "hashed": 2,
+                "cache_hits": 10,
+                "errors": 0,
+                "too_short": 1,
+                "groups": 2,
+                "potential_savings": 1050,
+                "protected_groups": 0,
+                "interrupted": false,
+            })
+        );
+    }
+
+    #[test]
+    fn test_approx_memory_usage_counts_shared_paths_once() {
+        let long_paths = (0..100)
+            .map(|idx| PathBuf::from(format!("{}/{idx}.mp4", "subdir/".repeat(100))))
+            .collect::<Vec<_>>();
+        let group = MatchGroup::new(long_paths.clone()).unwrap();
+        let pairs = SearchOutput::new(match_filtering::cartesian_product(&[group]));
+        assert_eq!(pairs.len(), 100 * 99 / 2);
+
+        //the same pairs, each with its own copy of the paths.
+        let copied_pairs = long_paths
+            .into_iter()
+            .tuple_combinations()
+            .map(|(p1, p2)| MatchGroup::new([p1, p2]).unwrap())
+            .collect::<Vec<_>>();
+        let copied_pairs = SearchOutput::new(copied_pairs);
+        assert!(pairs.approx_memory_usage() * 3 < copied_pairs.approx_memory_usage());
+        assert!(SearchOutput::new(vec![]).approx_memory_usage() < 100);
+    }
+
+    #[test]
+    fn test_exit_codes() {
+        let no_dups = SearchOutput::new(vec![]).summary(&UpdateReport::default(), keep, file_size);
+        assert_eq!(no_dups.exit_code(), EXIT_OK);
+
+        //file errors take precedence over duplicates.
+        let report = UpdateReport {
+            failed: vec![(
+                PathBuf::from("bad"),
+                VdfCacheError::CreateHashError(Error::NotVideo),
+            )],
+            ..UpdateReport::default()
+        };
+        let with_errors = search_output().summary(&report, keep, file_size);
+        assert_eq!(with_errors.errors, 1);
+        assert_eq!(with_errors.exit_code(), EXIT_FILE_ERRORS);
+    }
+
+    // Groups of different sizes, distances and durations, whose files are as big as the number in
+    // their names and were modified that many seconds after the epoch, except for "5".
+    fn sortable_groups() -> (SearchOutput, impl Fn(&Path) -> Option<SortKeyData>) {
+        let search_output = SearchOutput::new(vec![
+            MatchGroup::new(paths(&["100", "30", "20"])).unwrap(),
+            MatchGroup::new_with_reference(PathBuf::from("5"), paths(&["1000"])).unwrap(),
+            MatchGroup::new(paths(&["200", "300"])).unwrap(),
+        ]);
+
+        let hash = |name: &str, distance, duration| {
+            VideoHash::empty_hash(name)
+                .with_flipped_bits(0..distance)
+                .with_duration(duration)
+        };
+        let hashes = [
+            hash("100", 0, 60),
+            hash("30", 8, 60),
+            hash("20", 2, 60),
+            hash("5", 0, 10),
+            hash("1000", 2, 10),
+            hash("200", 0, 30),
+            hash("300", 4, 30),
+        ];
+        let lookup = move |path: &Path| {
+            Some(SortKeyData {
+                hash: hashes.iter().find(|hash| hash.src_path() == path).cloned(),
+                size: file_size(path),
+                modified: file_size(path)
+                    .filter(|_| path != Path::new("5"))
+                    .map(|secs| UNIX_EPOCH + std::time::Duration::from_secs(secs)),
+            })
+        };
+        (search_output, lookup)
+    }
+
+    #[test]
+    fn test_sort_modes() {
+        let sorted = |sorting| {
+            let (mut search_output, lookup) = sortable_groups();
+            search_output.sort(sorting, lookup);
+            search_output
+                .dup_groups()
+                .map(|group| group.contained_paths().next().unwrap().to_str().unwrap())
+                .map(str::to_string)
+                .collect::<Vec<_>>()
+        };
+
+        //each group is named by its first duplicate.
+        for (sorting, expected, reversed) in [
+            (
+                Sorting::NumMatches,
+                ["100", "200", "1000"],
+                Sorting::RevNumMatches,
+            ),
+            (
+                Sorting::Distance,
+                ["1000", "200", "100"],
+                Sorting::RevDistance,
+            ),
+            (
+                Sorting::Duration,
+                ["100", "200", "1000"],
+                Sorting::RevDuration,
+            ),
+            //1005, 500 and 150 bytes
+            (Sorting::Size, ["1000", "200", "100"], Sorting::RevSize),
+            //keeping the reference frees 1000 bytes, and keeping the biggest file frees 200 and 50.
+            (
+                Sorting::Savings,
+                ["1000", "200", "100"],
+                Sorting::RevSavings,
+            ),
+            (
+                Sorting::Modified,
+                ["1000", "200", "100"],
+                Sorting::RevModified,
+            ),
+        ] {
+            assert_eq!(sorted(sorting), expected, "{sorting:?}");
+            let mut reversed_expected = expected;
+            reversed_expected.reverse();
+            assert_eq!(sorted(reversed), reversed_expected, "{reversed:?}");
+        }
+    }
+
+    #[test]
+    fn test_sorting_by_num_matches_looks_nothing_up() {
+        let (mut search_output, _lookup) = sortable_groups();
+        search_output.sort(Sorting::NumMatches, |path| {
+            unreachable!("looked up {}", path.display())
+        });
+        assert_eq!(search_output.len(), 3);
+    }
+}
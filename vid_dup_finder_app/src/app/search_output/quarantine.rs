@@ -0,0 +1,430 @@
+//! A directory for reviewing duplicates without touching them. Each group of duplicates gets a
+//! subdirectory, named by the id of the group, holding links to every member of the group and a
+//! `group.json` describing them.
+//!
+//! Quarantining again updates the directory in place: members which are already linked are left
+//! alone, and the directories of groups which are no longer found are removed.
+
+use std::{
+    collections::{BTreeMap, HashSet},
+    ffi::OsString,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use vid_dup_finder_lib::{MatchGroup, VideoHash};
+
+use super::{report::ReportRow, ReportSources, SearchOutput};
+
+// Written into every group's directory. Directories without one were not made by quarantining, so
+// they are never removed.
+const MANIFEST: &str = "group.json";
+
+#[derive(Error, Debug)]
+pub enum QuarantineError {
+    #[error("Failed to quarantine {0}: {1}")]
+    Io(PathBuf, #[source] io::Error),
+
+    #[error("Failed to write {0}: {1}")]
+    Manifest(PathBuf, #[source] serde_json::Error),
+}
+
+/// How a member of a group was placed in the group's directory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LinkKind {
+    HardLink,
+    Symlink,
+    Copy,
+}
+
+/// Places the file at the first path at the second path.
+pub type Linker = fn(&Path, &Path) -> io::Result<()>;
+
+/// Hard links cannot cross filesystems (and some filesystems do not have them at all), so when
+/// one cannot be made a symlink is made instead, and failing that the file is copied.
+pub const LINKERS: [(LinkKind, Linker); 3] = [
+    (LinkKind::HardLink, |src, dst| fs::hard_link(src, dst)),
+    (LinkKind::Symlink, symlink),
+    (LinkKind::Copy, |src, dst| fs::copy(src, dst).map(drop)),
+];
+
+#[cfg(unix)]
+fn symlink(src: &Path, dst: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(src, dst)
+}
+
+#[cfg(windows)]
+fn symlink(src: &Path, dst: &Path) -> io::Result<()> {
+    std::os::windows::fs::symlink_file(src, dst)
+}
+
+#[cfg(not(any(unix, windows)))]
+fn symlink(_src: &Path, _dst: &Path) -> io::Result<()> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+/// The contents of `group.json`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GroupManifest {
+    pub group_id: String,
+    pub members: Vec<QuarantinedMember>,
+}
+
+/// A member of a group, and the link to it in the group's directory.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QuarantinedMember {
+    /// The name of the link within the group's directory.
+    pub name: PathBuf,
+    pub source: PathBuf,
+    pub link: LinkKind,
+    pub is_reference: bool,
+    /// The hamming distance from the hash of the file that would be kept out of the group.
+    pub distance_to_best: Option<u32>,
+    pub file_size: Option<u64>,
+    /// In seconds.
+    pub duration: Option<u32>,
+    pub resolution: Option<(u32, u32)>,
+}
+
+/// What changed in the quarantine directory.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct QuarantineSummary {
+    pub groups: usize,
+    /// Members which were not already linked from an earlier run.
+    pub linked: usize,
+    /// Directories of groups which were not found this time.
+    pub removed_groups: usize,
+}
+
+/// The directory which the members of a group are linked into.
+pub fn group_dir(quarantine_dir: &Path, group_id: &str) -> PathBuf {
+    quarantine_dir.join(group_id)
+}
+
+/// The `group.json` of a group, which only exists once the group has been quarantined.
+#[cfg(all(target_family = "unix", feature = "gui_slint"))]
+pub fn manifest_path(quarantine_dir: &Path, group_id: &str) -> PathBuf {
+    group_dir(quarantine_dir, group_id).join(MANIFEST)
+}
+
+impl SearchOutput {
+    /// Link the members of every group into a directory per group within `dir`. The metadata in
+    /// each `group.json` is the same as in a [report][Self::report_rows].
+    pub fn quarantine<K, H, R, I>(
+        &self,
+        dir: &Path,
+        sources: &ReportSources<K, H, R, I>,
+    ) -> Result<QuarantineSummary, QuarantineError>
+    where
+        K: Fn(&MatchGroup) -> Option<PathBuf> + Sync,
+        H: Fn(&Path) -> Option<VideoHash> + Sync,
+        R: Fn(&Path) -> Option<(u32, u32)> + Sync,
+        I: Fn(&Path) -> Option<uuid::Uuid> + Sync,
+    {
+        quarantine_rows(dir, &self.report_rows(sources), &LINKERS)
+    }
+}
+
+fn quarantine_rows(
+    dir: &Path,
+    rows: &[ReportRow],
+    linkers: &[(LinkKind, Linker)],
+) -> Result<QuarantineSummary, QuarantineError> {
+    let io_err = |path: &Path| {
+        let path = path.to_path_buf();
+        move |e| QuarantineError::Io(path, e)
+    };
+
+    fs::create_dir_all(dir).map_err(io_err(dir))?;
+
+    let mut summary = QuarantineSummary::default();
+    let mut group_ids = HashSet::new();
+    for rows in rows.chunk_by(|a, b| a.group_id == b.group_id) {
+        let group_id = &rows[0].group_id;
+        summary.linked += quarantine_group(&group_dir(dir, group_id), rows, linkers)?;
+        summary.groups += 1;
+        group_ids.insert(OsString::from(group_id));
+    }
+
+    for entry in fs::read_dir(dir).map_err(io_err(dir))? {
+        let path = entry.map_err(io_err(dir))?.path();
+        let stale = path
+            .file_name()
+            .is_some_and(|name| !group_ids.contains(name));
+        if stale && path.join(MANIFEST).is_file() {
+            fs::remove_dir_all(&path).map_err(io_err(&path))?;
+            summary.removed_groups += 1;
+        }
+    }
+
+    Ok(summary)
+}
+
+// Returns the number of members which were newly linked.
+fn quarantine_group(
+    group_dir: &Path,
+    rows: &[ReportRow],
+    linkers: &[(LinkKind, Linker)],
+) -> Result<usize, QuarantineError> {
+    let io_err = |path: &Path| {
+        let path = path.to_path_buf();
+        move |e| QuarantineError::Io(path, e)
+    };
+
+    fs::create_dir_all(group_dir).map_err(io_err(group_dir))?;
+    let manifest_path = group_dir.join(MANIFEST);
+
+    //a manifest which cannot be read is treated as though nothing was linked.
+    let mut previous = fs::read(&manifest_path)
+        .ok()
+        .and_then(|bytes| serde_json::from_slice::<GroupManifest>(&bytes).ok())
+        .map(|manifest| {
+            manifest
+                .members
+                .into_iter()
+                .map(|member| (member.name.clone(), member))
+                .collect::<BTreeMap<_, _>>()
+        })
+        .unwrap_or_default();
+
+    let mut taken = HashSet::from([PathBuf::from(MANIFEST)]);
+    let mut linked = 0;
+    let mut members = vec![];
+    for row in rows {
+        let name = unique_name(&row.member_path, &mut taken);
+        let dst = group_dir.join(&name);
+
+        let up_to_date = previous.remove(&name).filter(|prev| {
+            //copies (and the targets of links) must still be the same size as the original.
+            prev.source == row.member_path
+                && fs::metadata(&dst).is_ok_and(|metadata| Some(metadata.len()) == row.file_size)
+        });
+        let link = match up_to_date {
+            Some(prev) => prev.link,
+            None => {
+                remove_if_exists(&dst).map_err(io_err(&dst))?;
+                linked += 1;
+                link_with(linkers, &row.member_path, &dst)?
+            }
+        };
+
+        members.push(QuarantinedMember {
+            name,
+            source: row.member_path.clone(),
+            link,
+            is_reference: row.is_reference,
+            distance_to_best: row.distance_to_best,
+            file_size: row.file_size,
+            duration: row.duration,
+            resolution: row.resolution,
+        });
+    }
+
+    //members which have left the group since the last run.
+    for name in previous.keys() {
+        let path = group_dir.join(name);
+        remove_if_exists(&path).map_err(io_err(&path))?;
+    }
+
+    let manifest = GroupManifest {
+        group_id: rows[0].group_id.clone(),
+        members,
+    };
+    let json = serde_json::to_vec_pretty(&manifest)
+        .map_err(|e| QuarantineError::Manifest(manifest_path.clone(), e))?;
+    fs::write(&manifest_path, json).map_err(io_err(&manifest_path))?;
+
+    Ok(linked)
+}
+
+// The file name of the member, with a number in front if another member has the same file name.
+fn unique_name(member: &Path, taken: &mut HashSet<PathBuf>) -> PathBuf {
+    let file_name = member.file_name().unwrap_or(member.as_os_str());
+    (1..)
+        .map(|n| match n {
+            1 => PathBuf::from(file_name),
+            n => {
+                let mut name = OsString::from(format!("{n}_"));
+                name.push(file_name);
+                PathBuf::from(name)
+            }
+        })
+        .find(|name| taken.insert(name.clone()))
+        .expect("some number is not taken")
+}
+
+fn link_with(
+    linkers: &[(LinkKind, Linker)],
+    src: &Path,
+    dst: &Path,
+) -> Result<LinkKind, QuarantineError> {
+    let mut last_err = io::Error::from(io::ErrorKind::Unsupported);
+    for (kind, linker) in linkers {
+        match linker(src, dst) {
+            Ok(()) => return Ok(*kind),
+            Err(e) => {
+                debug!("Failed to quarantine {} as a {kind:?}: {e}", src.display());
+                last_err = e;
+            }
+        }
+    }
+    Err(QuarantineError::Io(src.to_path_buf(), last_err))
+}
+
+fn remove_if_exists(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        ret => ret,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // A video (as far as quarantining cares) in the test directory.
+    fn video(dir: &Path, name: &str) -> PathBuf {
+        let path = dir.join("vids").join(name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, name).unwrap();
+        path
+    }
+
+    fn row(group_id: &str, path: &Path, is_reference: bool) -> ReportRow {
+        ReportRow {
+            group_id: group_id.to_string(),
+            member_path: path.to_path_buf(),
+            is_reference,
+            distance_to_best: Some(3),
+            hash_bits: Some(512),
+            file_size: fs::metadata(path).ok().map(|metadata| metadata.len()),
+            duration: Some(60),
+            resolution: Some((640, 480)),
+            video_id: None,
+            contained_in: vec![],
+        }
+    }
+
+    fn manifest(quarantine_dir: &Path, group_id: &str) -> GroupManifest {
+        let bytes = fs::read(group_dir(quarantine_dir, group_id).join(MANIFEST)).unwrap();
+        serde_json::from_slice(&bytes).unwrap()
+    }
+
+    fn cross_device(_src: &Path, _dst: &Path) -> io::Result<()> {
+        Err(io::ErrorKind::CrossesDevices.into())
+    }
+
+    #[test]
+    fn test_quarantine_is_idempotent() {
+        let test_dir = tempfile::tempdir().unwrap();
+        let quarantine_dir = test_dir.path().join("quarantine");
+        let a = video(test_dir.path(), "a/x.mp4");
+        let b = video(test_dir.path(), "b/x.mp4");
+        let c = video(test_dir.path(), "c.mp4");
+
+        let rows = [
+            row("first", &a, true),
+            row("first", &b, false),
+            row("first", &c, false),
+            row("second", &c, false),
+            row("second", &a, false),
+        ];
+        let summary = quarantine_rows(&quarantine_dir, &rows, &LINKERS).unwrap();
+        assert_eq!(
+            summary,
+            QuarantineSummary {
+                groups: 2,
+                linked: 5,
+                removed_groups: 0
+            }
+        );
+
+        //members with the same file name are both linked.
+        let first = manifest(&quarantine_dir, "first");
+        let names = first.members.iter().map(|m| &m.name).collect::<Vec<_>>();
+        assert_eq!(names, ["x.mp4", "2_x.mp4", "c.mp4"]);
+        assert!(first.members[0].is_reference);
+        assert_eq!(first.members[1].source, b);
+        assert_eq!(first.members[2].resolution, Some((640, 480)));
+        assert!(first.members.iter().all(|m| m.link == LinkKind::HardLink));
+        let first_dir = group_dir(&quarantine_dir, "first");
+        assert_eq!(fs::read(first_dir.join("2_x.mp4")).unwrap(), b"b/x.mp4");
+
+        //running again with the same groups changes nothing.
+        let summary = quarantine_rows(&quarantine_dir, &rows, &LINKERS).unwrap();
+        assert_eq!(summary.linked, 0);
+        assert_eq!(manifest(&quarantine_dir, "first"), first);
+
+        //groups which have gone are removed, as are members which have left a group. Other
+        //directories are left alone.
+        fs::create_dir_all(quarantine_dir.join("notes")).unwrap();
+        let summary = quarantine_rows(&quarantine_dir, &rows[1..3], &LINKERS).unwrap();
+        assert_eq!(
+            summary,
+            QuarantineSummary {
+                groups: 1,
+                linked: 1,
+                removed_groups: 1
+            }
+        );
+        let names = manifest(&quarantine_dir, "first")
+            .members
+            .into_iter()
+            .map(|m| m.name)
+            .collect::<Vec<_>>();
+        assert_eq!(names, [PathBuf::from("x.mp4"), PathBuf::from("c.mp4")]);
+        assert_eq!(fs::read(first_dir.join("x.mp4")).unwrap(), b"b/x.mp4");
+        assert!(!first_dir.join("2_x.mp4").exists());
+        assert!(!group_dir(&quarantine_dir, "second").exists());
+        assert!(quarantine_dir.join("notes").is_dir());
+
+        //the originals are untouched.
+        for (path, contents) in [(&a, "a/x.mp4"), (&b, "b/x.mp4"), (&c, "c.mp4")] {
+            assert_eq!(fs::read_to_string(path).unwrap(), contents);
+        }
+    }
+
+    #[test]
+    fn test_links_fall_back_across_devices() {
+        let test_dir = tempfile::tempdir().unwrap();
+        let quarantine_dir = test_dir.path().join("quarantine");
+        let a = video(test_dir.path(), "a.mp4");
+        let rows = [row("group", &a, false)];
+
+        let link_kind = |linkers: &[(LinkKind, Linker)]| {
+            quarantine_rows(&quarantine_dir, &rows, linkers).unwrap();
+            let member = &manifest(&quarantine_dir, "group").members[0];
+            let dst = group_dir(&quarantine_dir, "group").join(&member.name);
+            assert_eq!(fs::read_to_string(dst).unwrap(), "a.mp4");
+            member.link
+        };
+
+        let no_hard_links = [
+            (LinkKind::HardLink, cross_device as Linker),
+            LINKERS[1],
+            LINKERS[2],
+        ];
+        assert_eq!(link_kind(&no_hard_links), LinkKind::Symlink);
+
+        //a link of a different kind from an earlier run is kept.
+        assert_eq!(link_kind(&LINKERS), LinkKind::Symlink);
+
+        fs::remove_dir_all(&quarantine_dir).unwrap();
+        let only_copies = [
+            (LinkKind::HardLink, cross_device as Linker),
+            (LinkKind::Symlink, cross_device),
+            LINKERS[2],
+        ];
+        assert_eq!(link_kind(&only_copies), LinkKind::Copy);
+
+        let nothing_works = [(LinkKind::HardLink, cross_device as Linker)];
+        fs::remove_dir_all(&quarantine_dir).unwrap();
+        assert!(matches!(
+            quarantine_rows(&quarantine_dir, &rows, &nothing_works),
+            Err(QuarantineError::Io(path, e)) if path == a && e.kind() == io::ErrorKind::CrossesDevices
+        ));
+    }
+}
@@ -0,0 +1,517 @@
+//! Machine readable reports of the groups found by a search, with one row per member of each group.
+
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use itertools::Itertools;
+#[cfg(feature = "parallel_loading")]
+use rayon::prelude::*;
+#[cfg(feature = "sqlite")]
+use rusqlite::{params, Connection};
+use thiserror::Error;
+use uuid::Uuid;
+use vid_dup_finder_lib::{fmt::format_resolution, MatchGroup, VideoHash};
+
+use super::SearchOutput;
+use crate::app::match_group_ext::MatchGroupExt;
+
+#[derive(Error, Debug)]
+pub enum ReportError {
+    #[error("Failed to write report to {0}: {1}")]
+    Io(PathBuf, #[source] std::io::Error),
+
+    #[cfg(feature = "sqlite")]
+    #[error("Failed to write report to {0}: {1}")]
+    Sqlite(PathBuf, #[source] rusqlite::Error),
+
+    #[cfg(not(feature = "sqlite"))]
+    #[error("Failed to write report to {0}: this build has no sqlite support")]
+    SqliteUnsupported(PathBuf),
+}
+
+/// A member of a group of duplicates.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReportRow {
+    pub group_id: String,
+    pub member_path: PathBuf,
+    pub is_reference: bool,
+    /// The hamming distance from the hash of the file that would be kept out of the group.
+    pub distance_to_best: Option<u32>,
+    /// The size of the member's hash, which `distance_to_best` is out of.
+    pub hash_bits: Option<u32>,
+    pub file_size: Option<u64>,
+    /// In seconds.
+    pub duration: Option<u32>,
+    pub resolution: Option<(u32, u32)>,
+    /// The stable ID of the member, if the cache keeps them.
+    pub video_id: Option<Uuid>,
+    /// The longer members of the group that this member was cut from, with `--containment`.
+    pub contained_in: Vec<PathBuf>,
+}
+
+impl ReportRow {
+    pub(super) fn resolution_text(&self) -> Option<String> {
+        self.resolution.map(format_resolution)
+    }
+}
+
+/// Somewhere that rows of a report can be written to.
+pub trait ReportSink {
+    fn write_rows(&mut self, rows: &[ReportRow]) -> Result<(), ReportError>;
+}
+
+/// Where the metadata columns of a report come from.
+pub struct ReportSources<K, H, R, I> {
+    /// The file that would be kept out of a group, which distances are measured from.
+    pub keep: K,
+    /// The cached hash of a file.
+    pub hash: H,
+    pub resolution: R,
+    /// The stable ID of a file.
+    pub id: I,
+}
+
+impl SearchOutput {
+    /// The rows of a report of every group. Each group is measured on its own thread (with the
+    /// `parallel_loading` feature), because finding resolutions means probing every file.
+    pub fn report_rows<K, H, R, I>(&self, sources: &ReportSources<K, H, R, I>) -> Vec<ReportRow>
+    where
+        K: Fn(&MatchGroup) -> Option<PathBuf> + Sync,
+        H: Fn(&Path) -> Option<VideoHash> + Sync,
+        R: Fn(&Path) -> Option<(u32, u32)> + Sync,
+        I: Fn(&Path) -> Option<Uuid> + Sync,
+    {
+        #[cfg(feature = "parallel_loading")]
+        let it = self.dup_groups.par_iter();
+
+        #[cfg(not(feature = "parallel_loading"))]
+        let it = self.dup_groups.iter();
+
+        it.map(|group| group_rows(group, sources))
+            .collect::<Vec<_>>()
+            .into_iter()
+            .flatten()
+            .collect()
+    }
+}
+
+fn group_rows<K, H, R, I>(group: &MatchGroup, sources: &ReportSources<K, H, R, I>) -> Vec<ReportRow>
+where
+    K: Fn(&MatchGroup) -> Option<PathBuf>,
+    H: Fn(&Path) -> Option<VideoHash>,
+    R: Fn(&Path) -> Option<(u32, u32)>,
+    I: Fn(&Path) -> Option<Uuid>,
+{
+    let group_id = group.group_id();
+    let best_hash = (sources.keep)(group).and_then(|best| (sources.hash)(&best));
+
+    let reference = group.reference().map(|path| (true, path));
+    let members = reference
+        .into_iter()
+        .chain(group.duplicates().map(|path| (false, path)));
+
+    members
+        .map(|(is_reference, path)| {
+            let hash = (sources.hash)(path);
+            let distance_to_best = hash
+                .as_ref()
+                .zip(best_hash.as_ref())
+                .and_then(|(hash, best)| hash.hamming_distance(best).ok());
+
+            ReportRow {
+                group_id: group_id.clone(),
+                member_path: path.to_path_buf(),
+                is_reference,
+                distance_to_best,
+                hash_bits: hash.as_ref().map(|hash| hash.hash_size().bits()),
+                file_size: std::fs::metadata(path).ok().map(|metadata| metadata.len()),
+                duration: hash.as_ref().map(VideoHash::duration),
+                resolution: (sources.resolution)(path),
+                video_id: (sources.id)(path),
+                contained_in: group
+                    .containment()
+                    .contained_in(path)
+                    .map(Path::to_path_buf)
+                    .collect(),
+            }
+        })
+        .collect()
+}
+
+const CSV_HEADER: [&str; 7] = [
+    "group_id",
+    "member_path",
+    "is_reference",
+    "distance_to_best",
+    "file_size",
+    "duration",
+    "resolution",
+];
+
+/// Writes a report to a CSV file, replacing whatever was there. Missing values are left empty.
+pub struct CsvReport {
+    path: PathBuf,
+    //Whether there is a column for the ID of each member.
+    video_ids: bool,
+}
+
+impl CsvReport {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            video_ids: false,
+        }
+    }
+
+    /// Add a `video_id` column after the others, if `video_ids` is true.
+    pub fn with_video_ids(mut self, video_ids: bool) -> Self {
+        self.video_ids = video_ids;
+        self
+    }
+}
+
+// Quote a field if it would otherwise be misread.
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+impl ReportSink for CsvReport {
+    fn write_rows(&mut self, rows: &[ReportRow]) -> Result<(), ReportError> {
+        let io_err = |e| ReportError::Io(self.path.clone(), e);
+
+        let mut f = BufWriter::new(File::create(&self.path).map_err(io_err)?);
+        let mut header = CSV_HEADER.to_vec();
+        if self.video_ids {
+            header.push("video_id");
+        }
+        writeln!(f, "{}", header.join(",")).map_err(io_err)?;
+
+        for row in rows {
+            let mut fields = vec![
+                row.group_id.clone(),
+                row.member_path.to_string_lossy().to_string(),
+                row.is_reference.to_string(),
+                row.distance_to_best
+                    .map(|d| d.to_string())
+                    .unwrap_or_default(),
+                row.file_size.map(|s| s.to_string()).unwrap_or_default(),
+                row.duration.map(|d| d.to_string()).unwrap_or_default(),
+                row.resolution_text().unwrap_or_default(),
+            ];
+            if self.video_ids {
+                fields.push(row.video_id.map(|id| id.to_string()).unwrap_or_default());
+            }
+            writeln!(f, "{}", fields.iter().map(|s| csv_field(s)).join(",")).map_err(io_err)?;
+        }
+
+        f.flush().map_err(io_err)
+    }
+}
+
+/// Appends a report to a sqlite database, creating its tables if they are not there yet.
+///
+/// Each report is a new run in the `runs` table, and its rows in the `groups` and `members` tables
+/// are tagged with the run's id, so that the results of different runs can be compared.
+pub struct SqliteReport {
+    path: PathBuf,
+}
+
+impl SqliteReport {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+}
+
+#[cfg(feature = "sqlite")]
+const SQLITE_SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS runs (
+        run_id INTEGER PRIMARY KEY,
+        -- seconds since the unix epoch.
+        timestamp INTEGER NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS groups (
+        run_id INTEGER NOT NULL REFERENCES runs(run_id),
+        group_id TEXT NOT NULL,
+        num_members INTEGER NOT NULL,
+        PRIMARY KEY (run_id, group_id)
+    );
+    CREATE TABLE IF NOT EXISTS members (
+        run_id INTEGER NOT NULL REFERENCES runs(run_id),
+        group_id TEXT NOT NULL,
+        member_path TEXT NOT NULL,
+        is_reference INTEGER NOT NULL,
+        distance_to_best INTEGER,
+        file_size INTEGER,
+        duration INTEGER,
+        resolution TEXT,
+        PRIMARY KEY (run_id, group_id, member_path)
+    );";
+
+#[cfg(not(feature = "sqlite"))]
+impl ReportSink for SqliteReport {
+    fn write_rows(&mut self, _rows: &[ReportRow]) -> Result<(), ReportError> {
+        Err(ReportError::SqliteUnsupported(self.path.clone()))
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl ReportSink for SqliteReport {
+    fn write_rows(&mut self, rows: &[ReportRow]) -> Result<(), ReportError> {
+        use std::time::{SystemTime, UNIX_EPOCH};
+
+        let db_err = |e| ReportError::Sqlite(self.path.clone(), e);
+
+        let mut conn = Connection::open(&self.path).map_err(db_err)?;
+        conn.execute_batch(SQLITE_SCHEMA).map_err(db_err)?;
+
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+
+        //all or nothing, so that a failed write never leaves a partial run behind.
+        let tx = conn.transaction().map_err(db_err)?;
+        tx.execute(
+            "INSERT INTO runs (timestamp) VALUES (?1)",
+            params![timestamp as i64],
+        )
+        .map_err(db_err)?;
+        let run_id = tx.last_insert_rowid();
+
+        {
+            let mut insert_group = tx
+                .prepare(
+                    "INSERT INTO groups (run_id, group_id, num_members) VALUES (?1, ?2, ?3)
+                     ON CONFLICT DO UPDATE SET num_members = num_members + 1",
+                )
+                .map_err(db_err)?;
+            let mut insert_member = tx
+                .prepare(
+                    "INSERT INTO members (run_id, group_id, member_path, is_reference,
+                         distance_to_best, file_size, duration, resolution)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                )
+                .map_err(db_err)?;
+
+            for row in rows {
+                insert_group
+                    .execute(params![run_id, row.group_id, 1])
+                    .map_err(db_err)?;
+                insert_member
+                    .execute(params![
+                        run_id,
+                        row.group_id,
+                        row.member_path.to_string_lossy(),
+                        row.is_reference,
+                        row.distance_to_best,
+                        row.file_size.map(|s| s as i64),
+                        row.duration,
+                        row.resolution_text(),
+                    ])
+                    .map_err(db_err)?;
+            }
+        }
+
+        tx.commit().map_err(db_err)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use vid_dup_finder_lib::{search_with_opts, SearchOptions};
+
+    use super::*;
+    use crate::app::search_output::example_vid;
+
+    // Hashes standing in for the example videos (which cannot be hashed without ffmpeg): The cats
+    // are duplicates of each other, and so are the dogs.
+    fn example_hashes() -> Vec<VideoHash> {
+        let cat = VideoHash::empty_hash("cat").with_duration(30);
+        let dog = VideoHash::full_hash("dog").with_duration(30);
+        vec![
+            cat.with_src_path(example_vid("cat.1.mp4")),
+            cat.with_flipped_bits(0..3)
+                .with_src_path(example_vid("cat.2.mp4")),
+            dog.with_src_path(example_vid("dog.1.mp4")),
+            dog.with_flipped_bits(0..5)
+                .with_src_path(example_vid("dog.2.mp4")),
+        ]
+    }
+
+    fn example_rows() -> Vec<ReportRow> {
+        let hashes = example_hashes();
+        let groups = search_with_opts(hashes.clone(), &SearchOptions::default());
+        assert_eq!(groups.len(), 2);
+
+        //keep the ".1" copy, and pretend that every video is 640x480.
+        let sources = ReportSources {
+            keep: |group: &MatchGroup| {
+                group
+                    .contained_paths()
+                    .find(|path| path.to_string_lossy().contains(".1."))
+                    .map(Path::to_path_buf)
+            },
+            hash: |path: &Path| hashes.iter().find(|hash| hash.src_path() == path).cloned(),
+            resolution: |_path: &Path| Some((640, 480)),
+            id: |path: &Path| {
+                let idx = hashes.iter().position(|hash| hash.src_path() == path)?;
+                Some(Uuid::from_u128(idx as u128 + 1))
+            },
+        };
+
+        let mut rows = SearchOutput::new(groups).report_rows(&sources);
+        rows.sort_by(|r1, r2| r1.member_path.cmp(&r2.member_path));
+        rows
+    }
+
+    #[test]
+    fn test_report_rows() {
+        let rows = example_rows();
+        let distances = rows
+            .iter()
+            .map(|row| row.distance_to_best)
+            .collect::<Vec<_>>();
+        assert_eq!(distances, [Some(0), Some(3), Some(0), Some(5)]);
+
+        assert_eq!(rows[0].group_id, rows[1].group_id);
+        assert_ne!(rows[0].group_id, rows[2].group_id);
+        for row in &rows {
+            let file_size = std::fs::metadata(&row.member_path).unwrap().len();
+            assert_eq!(row.file_size, Some(file_size));
+            assert_eq!(row.duration, Some(30));
+            assert!(!row.is_reference);
+        }
+    }
+
+    #[test]
+    fn test_report_rows_record_containment() {
+        use std::time::Duration;
+
+        use rand::{rngs::StdRng, SeedableRng};
+
+        //cat.2 is a cut of 10 seconds from the middle of cat.1.
+        let (full, cut) = (example_vid("cat.1.mp4"), example_vid("cat.2.mp4"));
+        let mut rng = StdRng::seed_from_u64(1);
+        let full_windows = (0..30)
+            .map(|i| {
+                VideoHash::random_hash(&mut rng)
+                    .with_src_path(&full)
+                    .with_duration(60)
+                    .with_raw_window_start(Duration::from_secs(i * 2))
+            })
+            .collect::<Vec<_>>();
+        let cut_windows = full_windows[10..15]
+            .iter()
+            .zip(0..)
+            .map(|(window, i)| {
+                window
+                    .with_src_path(&cut)
+                    .with_duration(10)
+                    .with_raw_window_start(Duration::from_secs(i * 2))
+            })
+            .collect::<Vec<_>>();
+        let frame_source = |path: &Path| {
+            Ok(if path == full {
+                full_windows.clone()
+            } else {
+                cut_windows.clone()
+            })
+        };
+
+        let group = MatchGroup::new([full.clone(), cut.clone()]).unwrap();
+        let report = group.analyze_containment(&frame_source);
+        let group = group.with_containment(report);
+        let sources = ReportSources {
+            keep: |_group: &MatchGroup| Some(full.clone()),
+            hash: |_path: &Path| None,
+            resolution: |_path: &Path| None,
+            id: |_path: &Path| None,
+        };
+
+        let rows = SearchOutput::new(vec![group]).report_rows(&sources);
+        assert_eq!(rows[0].member_path, full);
+        assert!(rows[0].contained_in.is_empty());
+        assert_eq!(rows[1].contained_in, [full]);
+    }
+
+    #[test]
+    fn test_csv_report() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("report.csv");
+        let rows = example_rows();
+        CsvReport::new(&path).write_rows(&rows).unwrap();
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        let lines = contents.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 5);
+        assert_eq!(
+            lines[0],
+            "group_id,member_path,is_reference,distance_to_best,file_size,duration,resolution"
+        );
+        assert_eq!(
+            lines[2],
+            format!(
+                "{},{},false,3,{},30,640x480",
+                rows[1].group_id,
+                rows[1].member_path.display(),
+                rows[1].file_size.unwrap()
+            )
+        );
+
+        //the IDs are only written when asked for.
+        CsvReport::new(&path)
+            .with_video_ids(true)
+            .write_rows(&rows)
+            .unwrap();
+        let contents = std::fs::read_to_string(&path).unwrap();
+        let lines = contents.lines().collect::<Vec<_>>();
+        assert!(lines[0].ends_with(",resolution,video_id"));
+        for (line, row) in lines[1..].iter().zip(&rows) {
+            let id = line.rsplit(',').next().unwrap().parse::<Uuid>().unwrap();
+            assert_eq!(Some(id), row.video_id);
+        }
+
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_field("plain"), "plain");
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn test_sqlite_report_appends_runs() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("report.sqlite");
+        let rows = example_rows();
+
+        let mut report = SqliteReport::new(&path);
+        report.write_rows(&rows).unwrap();
+        report.write_rows(&rows[..2]).unwrap();
+
+        let conn = Connection::open(&path).unwrap();
+        let count = |sql: &str| -> i64 { conn.query_row(sql, [], |row| row.get(0)).unwrap() };
+        assert_eq!(count("SELECT COUNT(*) FROM runs"), 2);
+        assert_eq!(count("SELECT COUNT(*) FROM groups WHERE run_id = 1"), 2);
+        assert_eq!(count("SELECT COUNT(*) FROM groups WHERE run_id = 2"), 1);
+        assert_eq!(count("SELECT SUM(num_members) FROM groups"), 6);
+        assert_eq!(count("SELECT COUNT(*) FROM members"), 6);
+
+        let (member_path, distance, resolution): (String, u32, String) = conn
+            .query_row(
+                "SELECT member_path, distance_to_best, resolution FROM members
+                 WHERE run_id = 1 AND is_reference = 0 ORDER BY member_path LIMIT 1 OFFSET 3",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .unwrap();
+        assert_eq!(PathBuf::from(member_path), rows[3].member_path);
+        assert_eq!(distance, 5);
+        assert_eq!(resolution, "640x480");
+    }
+}
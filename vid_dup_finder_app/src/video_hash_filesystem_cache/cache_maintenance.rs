@@ -0,0 +1,78 @@
+use std::fmt;
+
+/// Counts of the entries in a cache, returned by
+/// [summary][`super::VideoHashFilesystemCache::summary`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheSummary {
+    /// The number of entries, whether or not their files could be hashed.
+    pub entries: usize,
+
+    /// The number of entries with a hash.
+    pub hashes: usize,
+
+    /// The number of entries whose files could not be hashed, and might be hashed if they were
+    /// tried again (see [error_paths][`super::VideoHashFilesystemCache::error_paths`]).
+    pub errors: usize,
+
+    /// The number of entries whose files are too short to hash.
+    pub too_short: usize,
+
+    /// The number of entries created with options other than those the cache hashes their files
+    /// with now, which are hashed again when they are next visited.
+    pub stale_options: usize,
+
+    /// The number of entries which were imported rather than hashed, so have no hash stats.
+    pub without_stats: usize,
+
+    /// The number of hashes created by decoders other than the ones in use now. None if the
+    /// decoders in use now are not known.
+    pub other_environment: Option<usize>,
+
+    /// The size in bytes of the cache file.
+    pub size_on_disk: u64,
+}
+
+impl fmt::Display for CacheSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Entries: {}", self.entries)?;
+        writeln!(f, "    hashes: {}", self.hashes)?;
+        writeln!(f, "    errors: {}", self.errors)?;
+        writeln!(f, "    too short to hash: {}", self.too_short)?;
+        writeln!(f, "Stale entries:")?;
+        writeln!(f, "    hashed with other options: {}", self.stale_options)?;
+        writeln!(f, "    imported without stats: {}", self.without_stats)?;
+        match self.other_environment {
+            Some(count) => writeln!(f, "    hashed by other decoders: {count}")?,
+            None => writeln!(f, "    hashed by other decoders: unknown")?,
+        }
+        writeln!(
+            f,
+            "Size on disk: {}",
+            bytesize::ByteSize::b(self.size_on_disk)
+        )
+    }
+}
+
+/// What importing a hash into a cache does to the entry of its video, as found by
+/// [import_action][`super::VideoHashFilesystemCache::import_action`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ImportAction {
+    /// The video has no entry, so one is added.
+    Add,
+
+    /// The video has an entry with another hash (or an error), which is replaced.
+    Replace,
+
+    /// The video already has an entry with the same hash.
+    Unchanged,
+
+    /// The video is not on this filesystem, so nothing is imported.
+    Missing,
+}
+
+impl ImportAction {
+    /// Whether the cache is changed.
+    pub const fn changes_cache(self) -> bool {
+        matches!(self, Self::Add | Self::Replace)
+    }
+}
@@ -1,7 +1,9 @@
 use cfg_if::cfg_if;
 use std::str::FromStr;
 
-use vid_dup_finder_lib::Cropdetect;
+use vid_dup_finder_lib::{Cropdetect, EnvironmentDigest};
+
+pub(super) const CACHE_VERSION: u64 = 29;
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash)]
 enum OperatingSystem {
@@ -48,6 +50,9 @@ pub(crate) struct VdfCacheMetadata {
     crop: Cropdetect,
     skip_forward_amount: f64,
     cache_version: u64,
+    //The decoders in use when the metadata was written. None if they could not be found, or the
+    //metadata was written before they were recorded.
+    environment: Option<EnvironmentDigest>,
 }
 
 impl VdfCacheMetadata {
@@ -66,7 +71,10 @@ impl VdfCacheMetadata {
             }
         };
 
-        let cache_version = 1;
+        let cache_version = CACHE_VERSION;
+        let environment = vid_dup_finder_lib::environment_info()
+            .ok()
+            .map(|info| info.digest());
 
         Self {
             operating_system,
@@ -74,22 +82,37 @@ impl VdfCacheMetadata {
             crop,
             skip_forward_amount,
             cache_version,
+            environment,
         }
     }
 
     pub fn to_disk_fmt(self) -> String {
         format!(
-            "{:?},{:?},{:?},{},{}",
+            "{:?},{:?},{:?},{},{},{}",
             self.operating_system,
             self.decode_backend,
             self.crop,
             self.skip_forward_amount,
-            self.cache_version
+            self.cache_version,
+            self.environment
+                .map(|digest| digest.to_string())
+                .unwrap_or_default()
         )
     }
 
     pub fn try_parse(val: &str) -> Result<Self, String> {
-        let split = val.split([',']).collect::<Vec<_>>();
+        let mut split = val.split([',']).collect::<Vec<_>>();
+
+        //Metadata written before the environment was recorded has no sixth field.
+        let environment = if split.len() == 6 { split.pop() } else { None };
+        let environment = environment
+            .filter(|environment| !environment.is_empty())
+            .map(|environment| {
+                environment
+                    .parse::<EnvironmentDigest>()
+                    .map_err(|_e| format!("Could not parse environment. Got {environment}"))
+            })
+            .transpose()?;
 
         match split[..] {
             [operating_system, decode_backend, crop, skip_forward_amount, cache_version] => {
@@ -118,18 +141,17 @@ impl VdfCacheMetadata {
                     crop,
                     skip_forward_amount,
                     cache_version,
+                    environment,
                 })
             }
             _ => Err(format!("Could not parse cache metadata. Got {val}")),
         }
     }
 
-    pub fn validate(
-        self,
-        exp_crop: Cropdetect,
-        exp_skip_forward_amount: f64,
-    ) -> Result<(), String> {
-        let exp = Self::new(exp_crop, exp_skip_forward_amount);
+    /// Check that the cache was created by a compatible build. The hashing options are not checked
+    /// here, see [`has_options`][Self::has_options].
+    pub fn validate(self) -> Result<(), String> {
+        let exp = Self::new(self.crop, self.skip_forward_amount);
 
         if self.operating_system != exp.operating_system {
             Err(format!(
@@ -141,15 +163,10 @@ impl VdfCacheMetadata {
                 "decode_backend mismatch: Act: {:?}, Exp: {:?}",
                 self.decode_backend, exp.decode_backend
             ))
-        } else if self.crop != exp.crop {
-            Err(format!(
-                "crop mismatch: Act: {:?}, Exp: {:?}",
-                self.crop, exp.crop
-            ))
-        } else if self.skip_forward_amount != exp.skip_forward_amount {
+        } else if self.cache_version < exp.cache_version {
             Err(format!(
-                "skip_forward_amount mismatch: Act: {:?}, Exp: {:?}",
-                self.skip_forward_amount, exp.skip_forward_amount
+                "cache is out of date (version {}, current version {}). Delete it to rebuild it",
+                self.cache_version, exp.cache_version
             ))
         } else if self.cache_version != exp.cache_version {
             Err(format!(
@@ -160,4 +177,15 @@ impl VdfCacheMetadata {
             Ok(())
         }
     }
+
+    pub fn has_options(self, crop: Cropdetect, skip_forward_amount: f64) -> bool {
+        self.crop == crop && self.skip_forward_amount == skip_forward_amount
+    }
+
+    /// The decoders in use when the metadata was written. Unlike the backend, a change of
+    /// decoders does not invalidate the cache, as hashes made by other versions of the same
+    /// decoders still match.
+    pub fn environment(self) -> Option<EnvironmentDigest> {
+        self.environment
+    }
 }
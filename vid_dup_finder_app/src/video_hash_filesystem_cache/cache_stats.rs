@@ -0,0 +1,208 @@
+use std::{
+    collections::BTreeMap,
+    fmt,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use vid_dup_finder_lib::HashStats;
+
+/// The number of files listed in [`CacheStatsReport::slowest`].
+pub const SLOWEST_FILES: usize = 10;
+
+/// A summary of how the hashes in a cache were created, returned by
+/// [stats_report][`super::VideoHashFilesystemCache::stats_report`].
+///
+/// Only entries with [`HashStats`] are summarized. Entries which were imported rather than
+/// hashed have no stats, so are only counted, in [`CacheStatsReport::without_stats`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CacheStatsReport {
+    /// The number of entries with stats.
+    pub with_stats: usize,
+
+    /// The number of entries without stats.
+    pub without_stats: usize,
+
+    /// The median time taken to hash a file, or `None` if no entry has stats.
+    pub p50: Option<Duration>,
+
+    /// The 95th percentile of the time taken to hash a file, or `None` if no entry has stats.
+    pub p95: Option<Duration>,
+
+    /// The [`SLOWEST_FILES`] files that took longest to hash, slowest first.
+    pub slowest: Vec<(PathBuf, Duration)>,
+
+    /// The number of entries hashed by each backend.
+    pub by_backend: BTreeMap<String, usize>,
+
+    /// The number of entries of each video codec. Entries whose codec is not known are counted as
+    /// `"unknown"`.
+    pub by_codec: BTreeMap<String, usize>,
+
+    /// The number of entries decoded by each decoder, and the mean time taken to hash them, so
+    /// that decoders which are slow (such as software decoders used in place of hardware ones)
+    /// stand out. Entries whose decoder is not known (which is all of those hashed by ffmpeg)
+    /// are counted as `"unknown"`.
+    pub by_decoder: BTreeMap<String, (usize, Duration)>,
+}
+
+impl CacheStatsReport {
+    pub fn from_entries<'a>(
+        entries: impl IntoIterator<Item = (&'a Path, Option<&'a HashStats>)>,
+    ) -> Self {
+        let mut ret = Self::default();
+        let mut times = vec![];
+        let mut decoder_times = BTreeMap::<String, (usize, Duration)>::new();
+        for (path, stats) in entries {
+            let Some(stats) = stats else {
+                ret.without_stats += 1;
+                continue;
+            };
+
+            ret.with_stats += 1;
+            times.push((path, stats.elapsed));
+            *ret.by_backend.entry(stats.backend.clone()).or_default() += 1;
+            let codec = stats.video_codec.as_deref().unwrap_or("unknown");
+            *ret.by_codec.entry(codec.to_string()).or_default() += 1;
+            let decoder = stats.decoder.as_deref().unwrap_or("unknown");
+            let (count, total) = decoder_times.entry(decoder.to_string()).or_default();
+            *count += 1;
+            *total += stats.elapsed;
+        }
+        ret.by_decoder = decoder_times
+            .into_iter()
+            .map(|(decoder, (count, total))| (decoder, (count, total / count as u32)))
+            .collect();
+
+        //slowest first, with ties in path order so that the report is stable.
+        times.sort_by(|(p1, t1), (p2, t2)| t2.cmp(t1).then(p1.cmp(p2)));
+        ret.p50 = nearest_rank(&times, 50);
+        ret.p95 = nearest_rank(&times, 95);
+        ret.slowest = times
+            .iter()
+            .take(SLOWEST_FILES)
+            .map(|(path, time)| (path.to_path_buf(), *time))
+            .collect();
+
+        ret
+    }
+}
+
+// The nearest-rank percentile of times, which are sorted slowest first.
+fn nearest_rank(times: &[(&Path, Duration)], percentile: usize) -> Option<Duration> {
+    if times.is_empty() {
+        return None;
+    }
+    let rank = (percentile * times.len()).div_ceil(100).max(1);
+    Some(times[times.len() - rank].1)
+}
+
+impl fmt::Display for CacheStatsReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Hash stats: {} entries with stats, {} imported without stats",
+            self.with_stats, self.without_stats
+        )?;
+
+        let (Some(p50), Some(p95)) = (self.p50, self.p95) else {
+            return Ok(());
+        };
+        writeln!(
+            f,
+            "Hash time: p50 {:.2}s, p95 {:.2}s",
+            p50.as_secs_f64(),
+            p95.as_secs_f64()
+        )?;
+
+        writeln!(f, "Slowest files:")?;
+        for (path, time) in &self.slowest {
+            writeln!(f, "    {:>8.2}s  {}", time.as_secs_f64(), path.display())?;
+        }
+
+        for (title, counts) in [("Backends", &self.by_backend), ("Codecs", &self.by_codec)] {
+            writeln!(f, "{title}:")?;
+            for (name, count) in counts {
+                writeln!(f, "    {name}: {count}")?;
+            }
+        }
+
+        writeln!(f, "Decoders:")?;
+        for (name, (count, mean)) in &self.by_decoder {
+            writeln!(f, "    {name}: {count} (mean {:.2}s)", mean.as_secs_f64())?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn stats(millis: u64, backend: &str, codec: Option<&str>) -> HashStats {
+        HashStats {
+            elapsed: Duration::from_millis(millis),
+            backend: backend.to_string(),
+            video_codec: codec.map(str::to_string),
+            decoder: (backend == "gstreamer").then(|| String::from("avdec_h264")),
+            ..HashStats::default()
+        }
+    }
+
+    #[test]
+    fn test_stats_report() {
+        let paths = (1..=20)
+            .map(|i| PathBuf::from(format!("{i:02}.mp4")))
+            .collect::<Vec<_>>();
+        //the nth file took n seconds. Every third has no codec, and the last two have no stats.
+        let entries = paths
+            .iter()
+            .enumerate()
+            .map(|(i, path)| {
+                let codec = (i % 3 != 0).then_some("h264");
+                let backend = if i < 10 { "ffmpeg" } else { "gstreamer" };
+                (path, stats((i as u64 + 1) * 1000, backend, codec))
+            })
+            .collect::<Vec<_>>();
+        let report = CacheStatsReport::from_entries(
+            entries
+                .iter()
+                .enumerate()
+                .map(|(i, (path, stats))| (path.as_path(), (i < 18).then_some(stats))),
+        );
+
+        assert_eq!(report.with_stats, 18);
+        assert_eq!(report.without_stats, 2);
+        assert_eq!(report.p50, Some(Duration::from_secs(9)));
+        assert_eq!(report.p95, Some(Duration::from_secs(18)));
+        assert_eq!(report.slowest.len(), SLOWEST_FILES);
+        assert_eq!(
+            report.slowest[0],
+            (paths[17].clone(), Duration::from_secs(18))
+        );
+        assert_eq!(report.slowest[9].1, Duration::from_secs(9));
+        assert_eq!(report.by_backend["ffmpeg"], 10);
+        assert_eq!(report.by_backend["gstreamer"], 8);
+        assert_eq!(report.by_codec["h264"], 12);
+        assert_eq!(report.by_codec["unknown"], 6);
+        //files 1-10 were hashed by ffmpeg, and 11-18 by gstreamer.
+        assert_eq!(
+            report.by_decoder["unknown"],
+            (10, Duration::from_millis(5500))
+        );
+        assert_eq!(
+            report.by_decoder["avdec_h264"],
+            (8, Duration::from_millis(14500))
+        );
+        assert!(report.to_string().contains("avdec_h264: 8 (mean 14.50s)"));
+
+        let single = CacheStatsReport::from_entries([(paths[0].as_path(), Some(&entries[0].1))]);
+        assert_eq!(single.p50, Some(Duration::from_secs(1)));
+        assert_eq!(single.p95, Some(Duration::from_secs(1)));
+
+        let empty = CacheStatsReport::from_entries([(paths[0].as_path(), None)]);
+        assert_eq!((empty.p50, empty.p95), (None, None));
+        assert!(empty.slowest.is_empty());
+    }
+}
@@ -9,10 +9,29 @@ pub enum VdfCacheError {
     #[error(transparent)]
     CreateHashError(#[from] Error),
 
+    /// The options videos would be hashed with are not valid.
+    #[error(transparent)]
+    InvalidOptions(#[from] CreationOptionsError),
+
+    /// There is no cache at the given path.
+    #[error("No cache at {}", .0.display())]
+    NoCache(std::path::PathBuf),
+
     #[error("Metadata validation error: {0}")]
     MetadataValidationError(String),
 
     /// An caching error occurred.
     #[error(transparent)]
     CacheErrror(#[from] FsCacheErrorKind),
+
+    /// A line of a file of portable hashes could not be read.
+    #[error("Line {line}: {source}")]
+    PortableHashError {
+        line: usize,
+        source: PortableHashError,
+    },
+
+    /// Portable hashes could not be read or written.
+    #[error(transparent)]
+    IoError(#[from] std::io::Error),
 }
@@ -1,4 +1,8 @@
-use std::path::{Path, PathBuf};
+use std::{
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
 
 use super::generic_filesystem_cache::*;
 
@@ -7,6 +11,7 @@ use itertools::Itertools;
 use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
+use vid_dup_finder_lib::CancellationToken;
 
 #[derive(Clone, Debug, Error, Serialize, Deserialize)]
 pub enum FileContentCacheErrorKind {
@@ -28,6 +33,82 @@ impl From<FsCacheErrorKind> for FileContentCacheErrorKind {
     }
 }
 
+//Files are fingerprinted by their size and the contents of this many bytes at each end.
+const FINGERPRINT_END_LEN: u64 = 1024 * 1024;
+
+/// What is known about the contents of a file.
+///
+/// Every file is fingerprinted by its size and a hash of its first and last MiB, which is cheap
+/// even for large videos. Files with different fingerprints differ, but files with the same
+/// fingerprint are only identical if the hashes of their whole contents are too, which are
+/// computed when they are first needed. Files no larger than two MiB are hashed whole straight
+/// away.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ContentId {
+    size: u64,
+    //None for entries migrated from caches which only held full hashes.
+    fingerprint: Option<blake3::Hash>,
+    full: Option<blake3::Hash>,
+}
+
+impl ContentId {
+    /// Fingerprint the file at `path`.
+    pub fn of_file(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let mut file = File::open(path)?;
+        let size = file.metadata()?.len();
+
+        let mut hasher = blake3::Hasher::new();
+        if size <= 2 * FINGERPRINT_END_LEN {
+            let hash = hasher.update_reader(&mut file)?.finalize();
+            return Ok(Self {
+                size,
+                fingerprint: Some(hash),
+                full: Some(hash),
+            });
+        }
+
+        hasher.update_reader((&mut file).take(FINGERPRINT_END_LEN))?;
+        file.seek(SeekFrom::Start(size - FINGERPRINT_END_LEN))?;
+        hasher.update_reader(file.take(FINGERPRINT_END_LEN))?;
+        Ok(Self {
+            size,
+            fingerprint: Some(hasher.finalize()),
+            full: None,
+        })
+    }
+
+    /// False if the files these were created from cannot be identical. If true, their full hashes
+    /// must still be compared.
+    pub fn may_match(&self, other: &Self) -> bool {
+        if self.size != other.size {
+            return false;
+        }
+        if let (Some(full), Some(other_full)) = (self.full, other.full) {
+            return full == other_full;
+        }
+        match (self.fingerprint, other.fingerprint) {
+            (Some(fingerprint), Some(other_fingerprint)) => fingerprint == other_fingerprint,
+            _ => true,
+        }
+    }
+
+    /// The hash of the whole of the file at `path`, which this was created from. It is read
+    /// unless the hash is already known.
+    pub fn full_hash(&self, path: impl AsRef<Path>) -> std::io::Result<blake3::Hash> {
+        match self.full {
+            Some(full) => Ok(full),
+            None => Ok(blake3::Hasher::new().update_mmap(path)?.finalize()),
+        }
+    }
+}
+
+impl SqliteColumns for Result<ContentId, FileContentCacheErrorKind> {}
+impl PackedColumns for Result<ContentId, FileContentCacheErrorKind> {}
+
+// Caches written before files were fingerprinted held the full hash of every file.
+impl SqliteColumns for Result<blake3::Hash, FileContentCacheErrorKind> {}
+impl PackedColumns for Result<blake3::Hash, FileContentCacheErrorKind> {}
+
 pub struct FileContentCacheIf {}
 
 impl FileContentCacheIf {
@@ -37,21 +118,19 @@ impl FileContentCacheIf {
 }
 
 impl CacheInterface for FileContentCacheIf {
-    type T = Result<blake3::Hash, FileContentCacheErrorKind>;
+    type T = Result<ContentId, FileContentCacheErrorKind>;
 
     fn load(&self, src_path: impl AsRef<Path>) -> Self::T {
-        let mut hasher = blake3::Hasher::new();
-        let new_entry = hasher
-            .update_mmap(src_path.as_ref())
-            .map_err(FileContentCacheErrorKind::from);
+        let new_entry =
+            ContentId::of_file(src_path.as_ref()).map_err(FileContentCacheErrorKind::from);
         match &new_entry {
             Ok(_) => info!(target: "hash_creation",
                 "contents caching : {}",
                 src_path.as_ref().to_string_lossy()
             ),
-            Err(e) => warn!(target: "hash_creation", "Hashing failed: {}", e.to_string()),
+            Err(e) => warn!(target: "hash_creation", "Hashing failed: {}", e),
         }
-        Ok(hasher.finalize())
+        new_entry
     }
 }
 
@@ -74,10 +153,35 @@ impl FileContentCache {
     ) -> Result<Self, FileContentCacheErrorKind> {
         let interface = FileContentCacheIf::new();
 
-        let ret = ProcessingFsCache::new(cache_save_thresold, cache_path, interface)?;
+        let ret = ProcessingFsCache::new(
+            cache_save_thresold,
+            cache_path,
+            CacheFormat::Bincode,
+            interface,
+        )?;
         Ok(Self(ret))
     }
 
+    /// Copy every entry of the cache at `legacy_path`, which was written before files were
+    /// fingerprinted and so holds the full hash of each file, into this cache. Returns the number
+    /// of entries copied.
+    pub fn migrate_full_hashes(
+        &self,
+        legacy_path: PathBuf,
+    ) -> Result<usize, FileContentCacheErrorKind> {
+        let count = self.0.import(
+            legacy_path,
+            |value: Result<blake3::Hash, FileContentCacheErrorKind>, size| {
+                value.map(|full| ContentId {
+                    size,
+                    fingerprint: None,
+                    full: Some(full),
+                })
+            },
+        )?;
+        Ok(count)
+    }
+
     /// Fetch the hash for the video file at the given source path. If the cache does not already contain a hash
     /// will not create one. This method does not read ``src_path`` on the filesystem.
     ///
@@ -86,9 +190,56 @@ impl FileContentCache {
     pub fn fetch(
         &self,
         src_path: impl AsRef<Path>,
-    ) -> Result<blake3::Hash, FileContentCacheErrorKind> {
+    ) -> Result<ContentId, FileContentCacheErrorKind> {
         self.fetch_entry(src_path)
-            .map_err(FileContentCacheErrorKind::from)
+    }
+
+    /// The hash of the whole contents of `src_path`. If it has not been computed yet, then the
+    /// file is read (after updating its cache entry, as [`Self::fetch_update`] does) and the hash
+    /// is cached.
+    ///
+    /// Returns an error if `src_path` is not in the cache, or if it cannot be read.
+    pub fn full_hash(
+        &self,
+        src_path: impl AsRef<Path>,
+    ) -> Result<blake3::Hash, FileContentCacheErrorKind> {
+        let src_path = src_path.as_ref();
+        if let Some(full) = self.fetch(src_path)?.full {
+            return Ok(full);
+        }
+
+        let Some(id) = self.fetch_update(src_path)? else {
+            return Err(FileContentCacheErrorKind::Io(format!(
+                "{} no longer exists",
+                src_path.display()
+            )));
+        };
+        let id = id?;
+        let full = id.full_hash(src_path)?;
+        self.0.replace_value(
+            src_path,
+            Ok(ContentId {
+                full: Some(full),
+                ..id
+            }),
+        )?;
+        Ok(full)
+    }
+
+    /// Whether `src_path_1` and `src_path_2` have identical contents. Their whole contents are
+    /// only hashed if their fingerprints match.
+    ///
+    /// Returns an error if either path is not in the cache, or if it cannot be read.
+    pub fn confirm_identical(
+        &self,
+        src_path_1: impl AsRef<Path>,
+        src_path_2: impl AsRef<Path>,
+    ) -> Result<bool, FileContentCacheErrorKind> {
+        let (src_path_1, src_path_2) = (src_path_1.as_ref(), src_path_2.as_ref());
+        if !self.fetch(src_path_1)?.may_match(&self.fetch(src_path_2)?) {
+            return Ok(false);
+        }
+        Ok(self.full_hash(src_path_1)? == self.full_hash(src_path_2)?)
     }
 
     /// Get the paths of all VideoHashes stored in the cache.
@@ -118,7 +269,7 @@ impl FileContentCache {
     pub fn fetch_update(
         &self,
         src_path: impl AsRef<Path>,
-    ) -> Result<Option<Result<blake3::Hash, FileContentCacheErrorKind>>, FileContentCacheErrorKind>
+    ) -> Result<Option<Result<ContentId, FileContentCacheErrorKind>>, FileContentCacheErrorKind>
     {
         let ret = self
             .0
@@ -131,7 +282,7 @@ impl FileContentCache {
     pub fn force_update(
         &self,
         src_path: impl AsRef<Path>,
-    ) -> Result<Option<Result<blake3::Hash, FileContentCacheErrorKind>>, FileContentCacheErrorKind>
+    ) -> Result<Option<Result<ContentId, FileContentCacheErrorKind>>, FileContentCacheErrorKind>
     {
         let _ = self.0.remove(&src_path);
         self.0
@@ -163,6 +314,10 @@ impl FileContentCache {
     ///    * Failure to remove an item from the cache (This is unlikely and should only occur if
     ///      calling this function more than once at the same time with overlapping paths)
     ///
+    /// # Cancellation
+    /// If `cancellation` is cancelled, no more files are started. Files which are already being
+    /// read are allowed to finish.
+    ///
     /// # Parallelism
     /// To speed up loading there is a cargo feature to allow hashes to be created from videos in parallel.
     /// Parallel loading is much faster than sequential loading but be aware that since Ffmpeg is already multithreaded
@@ -172,6 +327,7 @@ impl FileContentCache {
         &self,
         fs_paths: T,
         force_load: bool,
+        cancellation: Option<&CancellationToken>,
     ) -> Result<Vec<FileContentCacheErrorKind>, FileContentCacheErrorKind>
     where
         T: IntoIterator<Item = PathBuf>,
@@ -186,7 +342,10 @@ impl FileContentCache {
         }
 
         //deduplicate all the items for loading with a HashSet.
-        let all_paths = fs_paths.into_iter().unique();
+        let all_paths = fs_paths
+            .into_iter()
+            .unique()
+            .take_while(|_| !cancellation.is_some_and(CancellationToken::is_cancelled));
         // .collect::<HashSet<_>>();
 
         // all_paths.sort_by_cached_key(|x| {
@@ -229,10 +388,125 @@ impl FileContentCache {
     fn fetch_entry(
         &self,
         src_path: impl AsRef<Path>,
-    ) -> Result<blake3::Hash, FileContentCacheErrorKind> {
+    ) -> Result<ContentId, FileContentCacheErrorKind> {
         match self.0.fetch(src_path) {
-            Ok(x) => x.map_err(FileContentCacheErrorKind::from),
+            Ok(x) => x,
             Err(e) => Err(FileContentCacheErrorKind::from(e)),
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::{collections::HashMap, time::SystemTime};
+
+    use super::*;
+
+    const MIB: usize = 1024 * 1024;
+
+    // Three MiB, which differs from other files only in the byte at `middle_byte` in the middle MiB.
+    fn write_large_file(path: &Path, middle_byte: usize) {
+        let mut contents = vec![1u8; 3 * MIB];
+        contents[MIB + middle_byte] = 2;
+        std::fs::write(path, contents).unwrap();
+    }
+
+    #[test]
+    fn test_fingerprint_collisions_are_settled_by_full_hashes() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let dir = temp_dir.path();
+        let [a, b, a_copy, small, other_small] =
+            ["a", "b", "a_copy", "small", "other_small"].map(|name| dir.join(name));
+        write_large_file(&a, 0);
+        write_large_file(&b, 1);
+        write_large_file(&a_copy, 0);
+        std::fs::write(&small, b"small").unwrap();
+        std::fs::write(&other_small, b"smalL").unwrap();
+
+        let cache = FileContentCache::new(100, dir.join("cache.bin")).unwrap();
+        let errs = cache
+            .update_using_fs(
+                [&a, &b, &a_copy, &small, &other_small].map(|p| p.to_path_buf()),
+                false,
+                None,
+            )
+            .unwrap();
+        assert!(errs.is_empty());
+
+        //the large files share their size, head and tail, so only their fingerprints are known.
+        let (a_id, b_id) = (cache.fetch(&a).unwrap(), cache.fetch(&b).unwrap());
+        assert_eq!(a_id.full, None);
+        assert!(a_id.may_match(&b_id));
+
+        assert!(!cache.confirm_identical(&a, &b).unwrap());
+        assert!(cache.confirm_identical(&a, &a_copy).unwrap());
+        assert_ne!(cache.fetch(&a).unwrap().full, None);
+
+        //small files are hashed whole, so their ids alone tell them apart.
+        let (small_id, other_small_id) = (
+            cache.fetch(&small).unwrap(),
+            cache.fetch(&other_small).unwrap(),
+        );
+        assert_ne!(small_id.full, None);
+        assert!(!small_id.may_match(&other_small_id));
+        assert!(!cache.confirm_identical(&small, &a).unwrap());
+
+        //full hashes survive saving.
+        cache.save().unwrap();
+        let reloaded = FileContentCache::new(100, dir.join("cache.bin")).unwrap();
+        assert_eq!(reloaded.fetch(&a).unwrap(), cache.fetch(&a).unwrap());
+    }
+
+    #[test]
+    fn test_full_hash_caches_are_migrated() {
+        //the layout of entries in caches written before files were fingerprinted.
+        #[derive(Serialize)]
+        struct LegacyEntry {
+            cache_mtime: SystemTime,
+            size: u64,
+            value: Result<blake3::Hash, FileContentCacheErrorKind>,
+        }
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let dir = temp_dir.path();
+        let [a, b, a_copy] = ["a", "b", "a_copy"].map(|name| dir.join(name));
+        write_large_file(&a, 0);
+        write_large_file(&b, 1);
+        write_large_file(&a_copy, 0);
+
+        let legacy_path = dir.join("legacy.bin");
+        let legacy = [&a, &b]
+            .into_iter()
+            .map(|p| {
+                let metadata = std::fs::metadata(p).unwrap();
+                let entry = LegacyEntry {
+                    cache_mtime: metadata.modified().unwrap(),
+                    size: metadata.len(),
+                    value: Ok(blake3::hash(&std::fs::read(p).unwrap())),
+                };
+                (p.clone(), entry)
+            })
+            .collect::<HashMap<_, _>>();
+        std::fs::write(&legacy_path, bincode::serialize(&legacy).unwrap()).unwrap();
+
+        let cache = FileContentCache::new(100, dir.join("cache.bin")).unwrap();
+        assert_eq!(cache.migrate_full_hashes(legacy_path).unwrap(), 2);
+        let a_id = cache.fetch(&a).unwrap();
+        assert_eq!(a_id.fingerprint, None);
+        assert_eq!(a_id.full, Some(blake3::hash(&std::fs::read(&a).unwrap())));
+
+        //migrated entries are unmodified, so they are not loaded again.
+        cache
+            .update_using_fs([a.clone(), a_copy.clone()], false, None)
+            .unwrap();
+        assert_eq!(cache.fetch(&a).unwrap(), a_id);
+
+        //and can be compared with fingerprinted entries.
+        assert!(cache
+            .fetch(&a)
+            .unwrap()
+            .may_match(&cache.fetch(&a_copy).unwrap()));
+        assert!(cache.confirm_identical(&a, &a_copy).unwrap());
+        assert!(!cache.confirm_identical(&b, &a_copy).unwrap());
+    }
+}
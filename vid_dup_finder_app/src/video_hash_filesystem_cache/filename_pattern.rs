@@ -1,19 +1,87 @@
 use eyre::eyre;
 use ignore::WalkState;
+use parking_lot::Mutex;
 use std::{
+    collections::{BTreeMap, BTreeSet, HashSet},
     ffi::OsString,
     path::{Path, PathBuf},
+    sync::Arc,
 };
 
 pub trait FilterFilenames {
     fn includes(&self, src_path: impl AsRef<Path>) -> bool;
 }
 
+/// What to do with symlinks found while walking the filesystem.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum SymlinkPolicy {
+    /// Find files through symlinks to them, but do not walk into symlinked directories. This is
+    /// how directories have always been walked.
+    #[default]
+    FilesOnly,
+
+    /// Follow symlinks to files and directories, so a file linked from several places is found
+    /// (and hashed) once for each path. Links which lead back to one of their own parent
+    /// directories are not followed.
+    Follow,
+
+    /// Skip symlinks entirely.
+    Ignore,
+
+    /// Follow symlinks, but find each file only once under its canonical path (the path with all
+    /// symlinks resolved). The other paths to the file are recorded in [`SymlinkAliases`]. Files
+    /// whose canonical path is outside of the included paths are skipped, and no directory is
+    /// walked more than once.
+    ResolveAndDeduplicate,
+}
+
+/// The other paths to each file found by [`FilenamePattern::iterate_from_fs`] with
+/// [`SymlinkPolicy::ResolveAndDeduplicate`], keyed by canonical path. Filled in as the filesystem
+/// is walked, so it is only complete once every file has been received.
+#[derive(Debug, Clone, Default)]
+pub struct SymlinkAliases(Arc<Mutex<AliasMaps>>);
+
+#[derive(Debug, Default)]
+struct AliasMaps {
+    files: BTreeMap<PathBuf, BTreeSet<PathBuf>>,
+    //directories which were not walked because they had already been walked under their
+    //canonical path. Every file inside them is an alias.
+    dirs: BTreeMap<PathBuf, BTreeSet<PathBuf>>,
+}
+
+impl SymlinkAliases {
+    /// The other paths to the file at the given canonical path, in sorted order.
+    pub fn aliases_of(&self, canonical_path: impl AsRef<Path>) -> Vec<PathBuf> {
+        let canonical_path = canonical_path.as_ref();
+        let maps = self.0.lock();
+
+        let mut ret = maps.files.get(canonical_path).cloned().unwrap_or_default();
+        for (canonical_dir, alias_dirs) in &maps.dirs {
+            if let Ok(rel_path) = canonical_path.strip_prefix(canonical_dir) {
+                ret.extend(alias_dirs.iter().map(|alias_dir| alias_dir.join(rel_path)));
+            }
+        }
+
+        ret.into_iter().collect()
+    }
+
+    fn insert_file(&self, canonical_path: PathBuf, alias: PathBuf) {
+        let mut maps = self.0.lock();
+        maps.files.entry(canonical_path).or_default().insert(alias);
+    }
+
+    fn insert_dir(&self, canonical_path: PathBuf, alias: PathBuf) {
+        let mut maps = self.0.lock();
+        maps.dirs.entry(canonical_path).or_default().insert(alias);
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct FilenamePattern {
     incl_paths: Vec<PathBuf>,
     excl_paths: Vec<PathBuf>,
     excl_exts: Vec<OsString>,
+    symlinks: SymlinkPolicy,
 }
 
 impl FilenamePattern {
@@ -27,6 +95,7 @@ impl FilenamePattern {
             incl_paths,
             excl_paths,
             excl_exts,
+            symlinks: SymlinkPolicy::default(),
         };
 
         //check that the same path does not appear in srcs and excls
@@ -44,6 +113,11 @@ impl FilenamePattern {
         Ok(ret)
     }
 
+    pub fn with_symlink_policy(mut self, symlinks: SymlinkPolicy) -> Self {
+        self.symlinks = symlinks;
+        self
+    }
+
     fn raw_includes(&self, p: impl AsRef<Path>) -> bool {
         self.incl_paths
             .iter()
@@ -69,14 +143,40 @@ impl FilenamePattern {
 
 impl FilterFilenames for &FilenamePattern {
     /// Returns true if the given path is a child of any src_path,
-    /// and is not a child of any excl_path.
+    /// and is not a child of any excl_path. When deduplicating symlinks, paths which lead through
+    /// a symlink are not included (only their canonical paths are).
     fn includes(&self, src_path: impl AsRef<Path>) -> bool {
         self.raw_includes(&src_path)
             && !self.raw_excludes(&src_path)
             && !self.has_ignore_ext(&src_path)
+            && !self.is_excluded_alias(&src_path)
+    }
+}
+
+impl FilenamePattern {
+    fn is_excluded_alias(&self, src_path: impl AsRef<Path>) -> bool {
+        self.symlinks == SymlinkPolicy::ResolveAndDeduplicate && is_alias(src_path)
     }
 }
 
+// True if the path exists, but is not its own canonical path.
+fn is_alias(path: impl AsRef<Path>) -> bool {
+    std::fs::canonicalize(&path).is_ok_and(|canonical| canonical != path.as_ref())
+}
+
+// Identifies a directory regardless of the path used to reach it.
+#[cfg(unix)]
+fn dir_id(path: &Path) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+
+    std::fs::metadata(path).ok().map(|m| (m.dev(), m.ino()))
+}
+
+#[cfg(not(unix))]
+fn dir_id(path: &Path) -> Option<PathBuf> {
+    std::fs::canonicalize(path).ok()
+}
+
 impl FilterFilenames for FilenamePattern {
     fn includes(&self, src_path: impl AsRef<Path>) -> bool {
         (&self).includes(src_path)
@@ -85,8 +185,11 @@ impl FilterFilenames for FilenamePattern {
 
 //walkdir integration
 impl FilenamePattern {
-    //visit all files on the filesystem that are included.
-    pub fn iterate_from_fs(&self) -> eyre::Result<crossbeam_channel::Receiver<PathBuf>> {
+    //visit all files on the filesystem that are included. Symlinks are treated according to the
+    //symlink policy, and any aliases found are recorded in the returned SymlinkAliases.
+    pub fn iterate_from_fs(
+        &self,
+    ) -> eyre::Result<(crossbeam_channel::Receiver<PathBuf>, SymlinkAliases)> {
         //test that all start paths and excl paths actually exist.
         for incl_path in &self.incl_paths {
             if !incl_path.exists() {
@@ -112,9 +215,44 @@ impl FilenamePattern {
             walker.add(p);
         }
 
+        //ignore detects links back to a parent directory by itself, and reports them as errors.
+        walker.follow_links(matches!(
+            self.symlinks,
+            SymlinkPolicy::Follow | SymlinkPolicy::ResolveAndDeduplicate
+        ));
+
+        //The canonical paths found by deduplication are compared against the canonical versions
+        //of the included and excluded paths.
+        let (filt, canonical_filt) = match self.symlinks {
+            SymlinkPolicy::ResolveAndDeduplicate => {
+                let canonicalize = |paths: &[PathBuf]| {
+                    paths
+                        .iter()
+                        .map(|p| std::fs::canonicalize(p).unwrap_or_else(|_| p.clone()))
+                        .collect()
+                };
+                let canonical_filt = Self {
+                    incl_paths: canonicalize(&self.incl_paths),
+                    excl_paths: canonicalize(&self.excl_paths),
+                    symlinks: SymlinkPolicy::Follow,
+                    ..self.clone()
+                };
+                let filt = Self {
+                    symlinks: SymlinkPolicy::Follow,
+                    ..self.clone()
+                };
+                (filt, Some(canonical_filt))
+            }
+            _ => (self.clone(), None),
+        };
+
+        let aliases = SymlinkAliases::default();
+        let visited_dirs = Mutex::new(HashSet::new());
+        let visited_files = Mutex::new(HashSet::new());
+
         let (snd, rcv) = crossbeam_channel::bounded(100);
         std::thread::spawn({
-            let filt = self.clone();
+            let aliases = aliases.clone();
             move || {
                 walker.build_parallel().run(|| {
                     Box::new(|res| match res {
@@ -124,10 +262,49 @@ impl FilenamePattern {
                         }
                         Ok(entry) => {
                             let src_path = entry.path().to_path_buf();
-                            if !filt.includes(entry.path()) {
+                            if !filt.includes(entry.path())
+                                || (filt.symlinks == SymlinkPolicy::Ignore
+                                    && entry.path_is_symlink())
+                            {
+                                return WalkState::Skip;
+                            }
+
+                            let Some(canonical_filt) = &canonical_filt else {
+                                if src_path.is_file() {
+                                    snd.send(src_path).unwrap();
+                                }
+                                return WalkState::Continue;
+                            };
+
+                            if src_path.is_dir() {
+                                let Some(id) = dir_id(&src_path) else {
+                                    return WalkState::Skip;
+                                };
+                                if visited_dirs.lock().insert(id) {
+                                    return WalkState::Continue;
+                                }
+
+                                //Already walked, possibly under another path.
+                                if let Ok(canonical) = std::fs::canonicalize(&src_path) {
+                                    if canonical != src_path {
+                                        aliases.insert_dir(canonical, src_path);
+                                    }
+                                }
                                 WalkState::Skip
                             } else if src_path.is_file() {
-                                snd.send(src_path).unwrap();
+                                let Ok(canonical) = std::fs::canonicalize(&src_path) else {
+                                    return WalkState::Continue;
+                                };
+                                if !canonical_filt.includes(&canonical) {
+                                    return WalkState::Continue;
+                                }
+
+                                if canonical != src_path {
+                                    aliases.insert_file(canonical.clone(), src_path);
+                                }
+                                if visited_files.lock().insert(canonical.clone()) {
+                                    snd.send(canonical).unwrap();
+                                }
                                 WalkState::Continue
                             } else {
                                 WalkState::Continue
@@ -139,6 +316,92 @@ impl FilenamePattern {
             }
         });
 
-        Ok(rcv)
+        Ok((rcv, aliases))
+    }
+}
+
+#[cfg(all(test, unix))]
+mod test {
+    use std::os::unix::fs::symlink;
+
+    use tempfile::TempDir;
+
+    use super::*;
+
+    // dir/real/a.mp4, plus a link to the file, a link to its directory, and a loop back to dir.
+    fn make_linked_tree() -> TempDir {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let dir = temp_dir.path();
+        std::fs::create_dir_all(dir.join("real")).unwrap();
+        std::fs::write(dir.join("real/a.mp4"), "not a video").unwrap();
+
+        symlink(dir.join("real/a.mp4"), dir.join("link.mp4")).unwrap();
+        symlink(dir.join("real"), dir.join("linked_dir")).unwrap();
+        symlink(dir, dir.join("real/loop")).unwrap();
+
+        temp_dir
+    }
+
+    fn found_paths(dir: &Path, symlinks: SymlinkPolicy) -> (Vec<PathBuf>, SymlinkAliases) {
+        let (rcv, aliases) = FilenamePattern::new(vec![dir.to_path_buf()], vec![], vec![])
+            .unwrap()
+            .with_symlink_policy(symlinks)
+            .iterate_from_fs()
+            .unwrap();
+        let mut paths = rcv.into_iter().collect::<Vec<_>>();
+        paths.sort();
+        (paths, aliases)
+    }
+
+    #[test]
+    fn test_symlink_policies() {
+        let temp_dir = make_linked_tree();
+        let dir = temp_dir.path();
+
+        //by default, linked files are found but linked directories are not walked.
+        let (paths, _) = found_paths(dir, SymlinkPolicy::default());
+        assert_eq!(paths, [dir.join("link.mp4"), dir.join("real/a.mp4")]);
+
+        let (paths, _) = found_paths(dir, SymlinkPolicy::Follow);
+        assert_eq!(
+            paths,
+            [
+                dir.join("link.mp4"),
+                dir.join("linked_dir/a.mp4"),
+                dir.join("real/a.mp4")
+            ]
+        );
+
+        let (paths, _) = found_paths(dir, SymlinkPolicy::Ignore);
+        assert_eq!(paths, [dir.join("real/a.mp4")]);
+
+        let (paths, aliases) = found_paths(dir, SymlinkPolicy::ResolveAndDeduplicate);
+        let canonical = std::fs::canonicalize(dir.join("real/a.mp4")).unwrap();
+        assert_eq!(paths, std::slice::from_ref(&canonical));
+        assert_eq!(
+            aliases.aliases_of(&canonical),
+            [dir.join("link.mp4"), dir.join("linked_dir/a.mp4")]
+        );
+    }
+
+    #[test]
+    fn test_deduplicated_symlinks_are_cached_once() {
+        use crate::video_hash_filesystem_cache::{UpdateOptions, VideoHashFilesystemCache};
+        use vid_dup_finder_lib::Cropdetect;
+
+        let temp_dir = make_linked_tree();
+        let dir = temp_dir.path();
+        let (paths, _) = found_paths(dir, SymlinkPolicy::ResolveAndDeduplicate);
+
+        let cache =
+            VideoHashFilesystemCache::new(100, dir.join("cache.bin"), Cropdetect::None, 0.0, 10.0)
+                .unwrap();
+        let report = cache.update_using_fs(paths, UpdateOptions::default());
+
+        //not a video, so the one entry is a failure.
+        let canonical = std::fs::canonicalize(dir.join("real/a.mp4")).unwrap();
+        assert_eq!(report.num_attempted(), 1);
+        assert_eq!(cache.error_paths(), [canonical]);
+        assert!(cache.all_cached_paths().is_empty());
     }
 }
@@ -1,49 +1,191 @@
-use std::path::Path;
+use std::path::{Path, PathBuf};
+
+use bincode::Options;
+use serde::{Deserialize, Serialize};
 
 use super::generic_filesystem_cache::*;
 use vid_dup_finder_lib::*;
 
+/// A cached hash, along with the options that it was created with and how long it took to create.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CachedHash {
+    pub opts: CreationOptions,
+    pub hash: Result<VideoHash, Error>,
+    //None for entries which were imported rather than hashed.
+    pub stats: Option<HashStats>,
+}
+
+// Keeping errors in their own column, as text, means they can be read straight out of the database.
+impl SqliteColumns for CachedHash {
+    fn to_columns(&self) -> Result<ValueColumns, String> {
+        let (hash, error) = match &self.hash {
+            Ok(hash) => (
+                Some(bincode::serialize(hash).map_err(|e| e.to_string())?),
+                None,
+            ),
+            Err(e) => (
+                None,
+                Some(serde_json::to_string(e).map_err(|e| e.to_string())?),
+            ),
+        };
+
+        Ok(ValueColumns {
+            hash,
+            error,
+            created_opts: Some(bincode::serialize(&self.opts).map_err(|e| e.to_string())?),
+            stats: self
+                .stats
+                .as_ref()
+                .map(bincode::serialize)
+                .transpose()
+                .map_err(|e| e.to_string())?,
+        })
+    }
+
+    fn from_columns(columns: ValueColumns) -> Result<Self, String> {
+        let opts = columns
+            .created_opts
+            .ok_or("the created_opts column is empty")?;
+        let opts = bincode::deserialize(&opts).map_err(|e| e.to_string())?;
+
+        let hash = match (columns.hash, columns.error) {
+            (Some(hash), _) => Ok(bincode::deserialize(&hash).map_err(|e| e.to_string())?),
+            (None, Some(e)) => Err(serde_json::from_str(&e).map_err(|e| e.to_string())?),
+            (None, None) => return Err("both the hash and error columns are empty".to_string()),
+        };
+
+        let stats = columns
+            .stats
+            .map(|stats| bincode::deserialize(&stats))
+            .transpose()
+            .map_err(|e| e.to_string())?;
+
+        Ok(Self { opts, hash, stats })
+    }
+}
+
+// The path of the hash is the path of its entry, which the packed file already stores, so it is
+// only kept when it differs.
+#[derive(Serialize, Deserialize)]
+struct PackedCachedHash {
+    opts: CreationOptions,
+    hash: Result<PackedHash, Error>,
+    stats: Option<HashStats>,
+    src_path: Option<PackedPath>,
+}
+
+impl PackedColumns for CachedHash {
+    fn to_packed(&self, key: &Path) -> Result<Vec<u8>, String> {
+        let src_path = self
+            .hash
+            .as_ref()
+            .ok()
+            .filter(|hash| hash.src_path().as_os_str() != key.as_os_str())
+            .map(|hash| PackedPath(hash.src_path().to_path_buf()));
+
+        let packed = PackedCachedHash {
+            opts: self.opts,
+            hash: self
+                .hash
+                .as_ref()
+                .map(VideoHash::to_packed)
+                .map_err(Clone::clone),
+            stats: self.stats.clone(),
+            src_path,
+        };
+        packed_options()
+            .serialize(&packed)
+            .map_err(|e| e.to_string())
+    }
+
+    fn from_packed(bytes: &[u8], key: &Path) -> Result<Self, String> {
+        let PackedCachedHash {
+            opts,
+            hash,
+            stats,
+            src_path,
+        } = packed_options()
+            .deserialize(bytes)
+            .map_err(|e| e.to_string())?;
+
+        let src_path = src_path.map_or_else(|| key.to_path_buf(), |PackedPath(path)| path);
+        Ok(Self {
+            opts,
+            hash: hash.map(|hash| hash.into_hash(src_path)),
+            stats,
+        })
+    }
+}
+
 pub struct GenericCacheIf {
-    skip_forward_amount: f64,
-    duration: f64,
-    cropdetect: Cropdetect,
+    default_opts: CreationOptions,
+
+    //Videos under these paths are hashed with their own options instead of the defaults.
+    //The paths never overlap each other.
+    path_opts: Vec<(PathBuf, CreationOptions)>,
 }
 
 impl GenericCacheIf {
-    pub const fn new(skip_forward_amount: f64, duration: f64, cropdetect: Cropdetect) -> Self {
+    pub const fn new(
+        default_opts: CreationOptions,
+        path_opts: Vec<(PathBuf, CreationOptions)>,
+    ) -> Self {
         Self {
-            skip_forward_amount,
-            duration,
-            cropdetect,
+            default_opts,
+            path_opts,
         }
     }
+
+    /// The options that the video at `src_path` is hashed with.
+    pub fn opts_for(&self, src_path: &Path) -> CreationOptions {
+        self.path_opts
+            .iter()
+            .find(|(path, _)| src_path.starts_with(path))
+            .map_or(self.default_opts, |(_, opts)| *opts)
+    }
 }
 
 impl CacheInterface for GenericCacheIf {
-    type T = Result<VideoHash, Error>;
+    type T = CachedHash;
 
     fn load(&self, src_path: impl AsRef<Path>) -> Self::T {
         let src_path = src_path.as_ref().to_path_buf();
-        let opts = CreationOptions {
-            skip_forward_amount: self.skip_forward_amount,
-            duration: self.duration,
-            cropdetect: self.cropdetect,
-        };
+        let opts = self.opts_for(&src_path);
 
         #[cfg(feature = "gstreamer_backend")]
-        let new_entry = gstreamer_builder::VideoHashBuilder::from_options(opts).hash(src_path);
+        let (new_entry, stats) =
+            gstreamer_builder::VideoHashBuilder::from_options(opts).hash_with_stats(src_path);
 
         #[cfg(feature = "ffmpeg_backend")]
-        let new_entry = ffmpeg_builder::VideoHashBuilder::from_options(opts).hash(src_path);
+        let (new_entry, stats) =
+            ffmpeg_builder::VideoHashBuilder::from_options(opts).hash_with_stats(src_path);
 
         match &new_entry {
             Ok(hash) => info!(target: "hash_creation",
                 "inserting : {}",
                 hash.src_path().display()
             ),
-            Err(e) => warn!(target: "hash_creation", "Hashing failed: {}", e.to_string()),
+            //failures are reported (with their paths) in the UpdateReport.
+            Err(e) => debug!(target: "hash_creation", "Hashing failed: {}", e),
         }
 
-        new_entry
+        CachedHash {
+            opts,
+            hash: new_entry,
+            stats: Some(stats),
+        }
+    }
+
+    // Hashes created with other options would not be comparable with the hashes they are
+    // searched against.
+    fn is_current(&self, src_path: &Path, value: &Self::T) -> bool {
+        value.opts == self.opts_for(src_path)
+    }
+
+    fn renamed(&self, value: Self::T, new_path: &Path) -> Self::T {
+        CachedHash {
+            hash: value.hash.map(|hash| hash.with_src_path(new_path)),
+            ..value
+        }
     }
 }
@@ -1,14 +1,16 @@
 use std::{
     fmt::Debug,
-    io::Write,
+    io::{BufRead, Write},
+    ops::Bound,
     path::{Path, PathBuf},
     sync::atomic::{AtomicU32, Ordering::Relaxed},
 };
 
+use bincode::Options;
 use log::info;
 use log::trace;
 use parking_lot::RwLock;
-use serde::{de::DeserializeOwned, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize, Serializer};
 
 #[allow(dead_code)]
 enum SerializationBackend {
@@ -18,13 +20,70 @@ enum SerializationBackend {
 
 const BACKEND: SerializationBackend = SerializationBackend::Bincode;
 
-use super::errors::{
-    FsCacheErrorKind::{self, *},
-    FsCacheResult,
+use super::{
+    errors::{
+        FsCacheErrorKind::{self, *},
+        FsCacheResult,
+    },
+    packed_file::{self, Compression, PackedEntry},
+    sqlite_store::{SqliteEntry, SqliteStore},
 };
 
-//Types defining the on-disk format of the filesystem cacher.
-type CacheDiskFormat<T> = std::collections::HashMap<PathBuf, T>;
+/// How a cache is stored on disk.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CacheFormat {
+    /// A single bincode file, which is rewritten in full on every save. Each directory is only
+    /// stored once, and the file is compressed with zstd when built with the `zstd` feature.
+    #[default]
+    Bincode,
+
+    /// A sqlite database, which is updated one entry at a time.
+    Sqlite,
+}
+
+impl CacheFormat {
+    /// Caches whose filenames end in `.sqlite`, `.sqlite3` or `.db` are stored in sqlite. Any
+    /// other cache is stored in bincode.
+    pub fn from_path(path: impl AsRef<Path>) -> Self {
+        match path.as_ref().extension().and_then(|ext| ext.to_str()) {
+            Some("sqlite" | "sqlite3" | "db") => Self::Sqlite,
+            _ => Self::Bincode,
+        }
+    }
+}
+
+//Sqlite caches are still held in memory, but are written through to the database as they change
+//instead of being saved all at once.
+#[derive(Default, Debug)]
+enum Storage {
+    #[default]
+    Bincode,
+    Sqlite(SqliteStore),
+}
+
+//Types defining the on-disk format of the filesystem cacher. Entries are ordered by path, so that
+//the entries under a directory can be found without visiting the others.
+type CacheDiskFormat<T> = std::collections::BTreeMap<PathBuf, T>;
+
+//Serde's implementation for PathBuf refuses paths which are not valid UTF-8, so keys are written
+//with path_serde instead. Bincode encodes UTF-8 paths identically either way.
+struct DiskFormatRef<'a, T>(&'a CacheDiskFormat<T>);
+
+#[derive(Serialize)]
+struct DiskKeyRef<'a>(#[serde(with = "vid_dup_finder_common::path_serde")] &'a Path);
+
+#[derive(Deserialize, PartialEq, Eq, Hash)]
+struct DiskKey(#[serde(with = "vid_dup_finder_common::path_serde")] PathBuf);
+
+impl<T: Serialize> Serialize for DiskFormatRef<'_, T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(self.0.iter().map(|(k, v)| (DiskKeyRef(k), v)))
+    }
+}
+
+fn from_disk_keys<T>(cache: std::collections::HashMap<DiskKey, T>) -> CacheDiskFormat<T> {
+    cache.into_iter().map(|(DiskKey(k), v)| (k, v)).collect()
+}
 
 #[derive(Default, Debug)]
 pub struct BaseFsCache<T> {
@@ -32,28 +91,63 @@ pub struct BaseFsCache<T> {
     cache_save_threshold: u32,
     cache_modified_count: AtomicU32,
     cache_path: PathBuf,
+    storage: Storage,
     cache: RwLock<CacheDiskFormat<T>>,
 }
 
 impl<T> BaseFsCache<T>
 where
-    T: DeserializeOwned + Serialize + Send + Sync + Clone,
+    T: DeserializeOwned + Serialize + SqliteEntry + PackedEntry + Send + Sync + Clone,
 {
-    pub fn new(cache_save_threshold: u32, cache_path: PathBuf) -> FsCacheResult<Self> {
+    pub fn new(
+        cache_save_threshold: u32,
+        cache_path: PathBuf,
+        format: CacheFormat,
+    ) -> FsCacheResult<Self> {
         let mut ret = Self {
             loaded_from_disk: false,
             cache_save_threshold,
             cache_modified_count: AtomicU32::default(),
             cache_path,
+            storage: Storage::Bincode,
             cache: RwLock::default(),
         };
 
-        match ret.load_cache_from_disk() {
+        let loaded = match format {
+            CacheFormat::Bincode => ret.load_cache_from_disk(),
+            CacheFormat::Sqlite => ret.load_cache_from_db(),
+        };
+
+        match loaded {
             Ok(()) => Ok(ret),
             Err(e) => Err(e),
         }
     }
 
+    /// Copy every entry of the bincode cache at `bincode_path` into the sqlite cache at
+    /// `sqlite_path`, which is created if it does not exist. Returns the number of entries copied.
+    pub fn migrate_to_sqlite(bincode_path: PathBuf, sqlite_path: &Path) -> FsCacheResult<usize> {
+        if !bincode_path.exists() {
+            return Err(CacheFileIo {
+                src: std::io::ErrorKind::NotFound.into(),
+                path: bincode_path,
+            });
+        }
+
+        //nothing is inserted, so the save threshold is never reached.
+        let src = Self::new(u32::MAX, bincode_path, CacheFormat::Bincode)?;
+        let dest = SqliteStore::open(sqlite_path)?;
+
+        let cache = src.cache.read();
+        let count = dest.upsert_all(cache.iter().map(|(k, v)| (k.as_path(), v)))?;
+
+        info!(target: "generic_cache_startup",
+            "Migrated {} entries from {} to {}",
+            count, src.cache_path.display(), sqlite_path.display()
+        );
+        Ok(count)
+    }
+
     pub fn save(&self) -> FsCacheResult<()> {
         let modified_count = self.cache_modified_count.load(Relaxed);
         if modified_count > 0 {
@@ -66,6 +160,11 @@ where
     fn save_inner(&self) -> FsCacheResult<()> {
         use std::io::BufWriter;
 
+        //every change to a sqlite cache has already been written.
+        if matches!(self.storage, Storage::Sqlite(_)) {
+            return Ok(());
+        }
+
         //The cache file and its directory may not exist yet. So first create the directory
         //first if necessary.
         if !&self.cache_path.exists() {
@@ -106,15 +205,17 @@ where
 
         match BACKEND {
             SerializationBackend::Bincode => {
-                if let Err(e) = bincode::serialize_into(&mut cache_buf, &*readable_cache) {
+                if let Err(e) =
+                    packed_file::write(&mut cache_buf, &readable_cache, Compression::DEFAULT)
+                {
                     return Err(Serialization {
-                        src: format!("{e}"),
+                        src: e,
                         path: self.cache_path.clone(),
                     });
                 }
             }
             SerializationBackend::Json => {
-                let json_string = match serde_json::to_string(&*readable_cache) {
+                let json_string = match serde_json::to_string(&DiskFormatRef(&readable_cache)) {
                     Ok(s) => s,
                     Err(e) => {
                         return Err(Serialization {
@@ -185,10 +286,42 @@ where
         };
 
         //we may fail to read the hash file. This most likely to occur in development if <T> is changed.
-        let reader = std::io::BufReader::new(cache_file);
+        //
+        //Bincode allocates as much as each length in the file asks for before reading anything, so a
+        //corrupt length could otherwise abort the program. Nothing in the file can be longer than
+        //the file, so limiting reads to its length rejects those before they are allocated.
+        let file_len = cache_file.metadata().map_or(u64::MAX, |m| m.len());
+        let mut reader = std::io::BufReader::new(cache_file);
+        let is_packed = match reader.fill_buf() {
+            Ok(buf) => buf.starts_with(packed_file::MAGIC),
+            Err(e) => {
+                return Err(CacheFileIo {
+                    src: e,
+                    path: self.cache_path.clone(),
+                })
+            }
+        };
+
         let cache_file_data: CacheDiskFormat<_> = match BACKEND {
-            SerializationBackend::Bincode => match bincode::deserialize_from(reader) {
-                Ok(data) => data,
+            SerializationBackend::Bincode if is_packed => {
+                reader.consume(packed_file::MAGIC.len());
+                match packed_file::read(reader, file_len) {
+                    Ok(data) => data,
+                    Err(e) => {
+                        return Err(Deserialization {
+                            src: e,
+                            path: self.cache_path.clone(),
+                        })
+                    }
+                }
+            }
+            SerializationBackend::Bincode => match bincode::DefaultOptions::new()
+                .with_fixint_encoding()
+                .allow_trailing_bytes()
+                .with_limit(file_len)
+                .deserialize_from(reader)
+            {
+                Ok(data) => from_disk_keys(data),
                 Err(e) => {
                     return Err(Deserialization {
                         src: format!("{e}"),
@@ -197,7 +330,7 @@ where
                 }
             },
             SerializationBackend::Json => match serde_json::from_reader(reader) {
-                Ok(data) => data,
+                Ok(data) => from_disk_keys(data),
                 Err(e) => {
                     return Err(Deserialization {
                         src: format!("{e}"),
@@ -210,17 +343,41 @@ where
         self.cache = RwLock::new(cache_file_data);
         self.loaded_from_disk = true;
 
+        //Caches from before the packed format are rewritten in it when they are next saved.
+        if !is_packed && matches!(BACKEND, SerializationBackend::Bincode) {
+            info!(target: "generic_cache_startup",
+                "Converting {} to the packed format when it is next saved", self.cache_path.display()
+            );
+            self.cache_modified_count.store(1, Relaxed);
+        }
+
         trace!(target: "generic_cache_startup",
             "Loaded cache. Path: {}, Entries: {}", self.cache_path.display(), self.len()
         );
         Ok(())
     }
 
+    fn load_cache_from_db(&mut self) -> FsCacheResult<()> {
+        let store = SqliteStore::open(&self.cache_path)?;
+        self.cache = RwLock::new(store.load_all()?);
+        self.storage = Storage::Sqlite(store);
+        self.loaded_from_disk = true;
+
+        trace!(target: "generic_cache_startup",
+            "Loaded sqlite cache. Path: {}, Entries: {}", self.cache_path.display(), self.len()
+        );
+        Ok(())
+    }
+
     /////////////////////////////
-    // Wrappers for HashMap.
+    // Wrappers for BTreeMap.
     /////////////////////////////
 
     pub fn insert(&self, key: PathBuf, item: T) -> FsCacheResult<()> {
+        if let Storage::Sqlite(store) = &self.storage {
+            store.upsert(&key, &item)?;
+        }
+
         let cache_modified_count = self.cache_modified_count.fetch_add(1, Relaxed);
 
         info!(target: "generic_cache_insert",
@@ -238,6 +395,9 @@ where
     pub fn remove(&self, key: impl AsRef<Path>) -> FsCacheResult<()> {
         {
             info!(target: "generic_cache_remove", "Removing: {}", key.as_ref().display());
+            if let Storage::Sqlite(store) = &self.storage {
+                store.remove(key.as_ref())?;
+            }
             let mut writeable_cache = self.cache.write();
             writeable_cache.remove(key.as_ref());
         }
@@ -278,10 +438,62 @@ where
         self.cache.read().keys().cloned().collect()
     }
 
+    /// The keys which are `prefix` or are inside it. Paths are compared by component, so
+    /// `/video` is not inside `/videos`.
+    pub fn keys_under(&self, prefix: &Path) -> Vec<PathBuf> {
+        //every path inside prefix sorts directly after it.
+        self.cache
+            .read()
+            .range::<Path, _>((Bound::Included(prefix), Bound::Unbounded))
+            .map(|(key, _)| key)
+            .take_while(|key| key.starts_with(prefix))
+            .cloned()
+            .collect()
+    }
+
+    /// Remove every key under `prefix` (see [`Self::keys_under`]). Returns the number removed.
+    pub fn remove_under(&self, prefix: &Path) -> FsCacheResult<usize> {
+        let keys = self.keys_under(prefix);
+        for key in &keys {
+            self.remove(key)?;
+        }
+        Ok(keys.len())
+    }
+
+    /// Move every key under `old` (see [`Self::keys_under`]) to the same place under `new`,
+    /// passing each value through `rename` along with its new key. Returns the number moved.
+    pub fn rename_prefix(
+        &self,
+        old: &Path,
+        new: &Path,
+        rename: impl Fn(T, &Path) -> T,
+    ) -> FsCacheResult<usize> {
+        let keys = self.keys_under(old);
+        for key in &keys {
+            let Ok(rest) = key.strip_prefix(old) else {
+                continue;
+            };
+            //joining an empty path would add a trailing separator.
+            let new_key = if rest.as_os_str().is_empty() {
+                new.to_path_buf()
+            } else {
+                new.join(rest)
+            };
+            let value = rename(self.fetch(key)?, &new_key);
+            self.remove(key)?;
+            self.insert(new_key, value)?;
+        }
+        Ok(keys.len())
+    }
+
     pub fn len(&self) -> usize {
         self.cache.read().len()
     }
 
+    pub fn cache_path(&self) -> &Path {
+        &self.cache_path
+    }
+
     pub fn contains_key(&self, key: impl AsRef<Path>) -> bool {
         self.cache.read().contains_key(key.as_ref())
     }
@@ -290,3 +502,109 @@ where
     //     self.cache.read().is_empty()
     // }
 }
+
+#[cfg(all(test, unix))]
+mod test {
+    use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+    use vid_dup_finder_lib::{PackedHash, VideoHash};
+
+    use super::*;
+    use crate::video_hash_filesystem_cache::generic_filesystem_cache::{
+        packed_file::packed_options, sqlite_store::*,
+    };
+
+    impl SqliteEntry for VideoHash {
+        fn to_row(&self) -> Result<SqliteRow, String> {
+            Ok(SqliteRow {
+                value: ValueColumns {
+                    hash: Some(bincode::serialize(self).map_err(|e| e.to_string())?),
+                    ..ValueColumns::default()
+                },
+                ..SqliteRow::default()
+            })
+        }
+
+        fn from_row(row: SqliteRow) -> Result<Self, String> {
+            bincode::deserialize(&row.value.hash.ok_or("no hash")?).map_err(|e| e.to_string())
+        }
+    }
+
+    impl PackedEntry for VideoHash {
+        fn to_packed(&self, _key: &Path) -> Result<Vec<u8>, String> {
+            packed_options()
+                .serialize(&self.to_packed())
+                .map_err(|e| e.to_string())
+        }
+
+        fn from_packed(bytes: &[u8], key: &Path) -> Result<Self, String> {
+            let packed: PackedHash = packed_options()
+                .deserialize(bytes)
+                .map_err(|e| e.to_string())?;
+            Ok(packed.into_hash(key))
+        }
+    }
+
+    #[test]
+    fn test_non_utf8_paths_survive_save_and_load() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let dir = temp_dir.path();
+        let cache_path = dir.join("cache.bin");
+
+        let odd_path = dir.join(OsStr::from_bytes(b"caf\xe9.mp4"));
+        let hash = VideoHash::full_hash(&odd_path);
+
+        let cache = BaseFsCache::new(100, cache_path.clone(), CacheFormat::Bincode).unwrap();
+        cache.insert(odd_path.clone(), hash.clone()).unwrap();
+        cache
+            .insert(dir.join("plain.mp4"), VideoHash::empty_hash("plain.mp4"))
+            .unwrap();
+        cache.save().unwrap();
+
+        let reloaded =
+            BaseFsCache::<VideoHash>::new(100, cache_path, CacheFormat::Bincode).unwrap();
+        assert_eq!(reloaded.len(), 2);
+        let reloaded_hash = reloaded.fetch(&odd_path).unwrap();
+        assert_eq!(reloaded_hash, hash);
+        assert_eq!(
+            reloaded_hash.src_path().as_os_str().as_bytes(),
+            odd_path.as_os_str().as_bytes()
+        );
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn test_sqlite_changes_are_written_without_saving() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let dir = temp_dir.path();
+        let cache_path = dir.join("cache.sqlite");
+        assert_eq!(CacheFormat::from_path(&cache_path), CacheFormat::Sqlite);
+
+        let odd_path = dir.join(OsStr::from_bytes(b"caf\xe9.mp4"));
+        let plain_path = dir.join("plain.mp4");
+        let hash = VideoHash::full_hash(&odd_path);
+
+        let open = || BaseFsCache::<VideoHash>::new(100, cache_path.clone(), CacheFormat::Sqlite);
+        let cache = open().unwrap();
+        cache.insert(odd_path.clone(), hash.clone()).unwrap();
+        cache
+            .insert(plain_path.clone(), VideoHash::empty_hash("plain.mp4"))
+            .unwrap();
+
+        //inserting an existing key replaces its row.
+        cache
+            .insert(plain_path.clone(), VideoHash::full_hash("plain.mp4"))
+            .unwrap();
+
+        let reloaded = open().unwrap();
+        assert_eq!(reloaded.len(), 2);
+        assert_eq!(reloaded.fetch(&odd_path).unwrap(), hash);
+        assert_eq!(
+            reloaded.fetch(&plain_path).unwrap(),
+            VideoHash::full_hash("plain.mp4")
+        );
+
+        reloaded.remove(&odd_path).unwrap();
+        assert_eq!(open().unwrap().keys(), [plain_path]);
+    }
+}
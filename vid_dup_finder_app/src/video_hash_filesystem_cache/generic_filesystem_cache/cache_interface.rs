@@ -2,9 +2,23 @@ use std::path::Path;
 
 use serde::{de::DeserializeOwned, Serialize};
 
+use super::{packed_file::PackedColumns, sqlite_store::SqliteColumns};
+
 // Users of the generic filesystem cache should implement this interface.
 pub trait CacheInterface {
-    type T: Serialize + DeserializeOwned + Clone + Send + Sync;
+    type T: Serialize + DeserializeOwned + SqliteColumns + PackedColumns + Clone + Send + Sync;
 
     fn load(&self, src_path: impl AsRef<Path>) -> Self::T;
+
+    // Whether a cached value is still wanted, even though its file is unmodified. Values which are
+    // not current are loaded again.
+    fn is_current(&self, _src_path: &Path, _value: &Self::T) -> bool {
+        true
+    }
+
+    // The value to cache for a file which has been moved to new_path, so that it is not loaded
+    // again.
+    fn renamed(&self, value: Self::T, _new_path: &Path) -> Self::T {
+        value
+    }
 }
@@ -9,6 +9,14 @@ pub enum FsCacheErrorKind {
     #[error("Error accessing cache storage file {path}: {src}")]
     CacheFileIo { src: std::io::Error, path: PathBuf },
 
+    #[cfg(feature = "sqlite")]
+    #[error("Error accessing cache database {path}: {src}")]
+    Database { src: rusqlite::Error, path: PathBuf },
+
+    #[cfg(not(feature = "sqlite"))]
+    #[error("Cannot open the sqlite cache {0}, as this build has no sqlite support")]
+    SqliteUnsupported(PathBuf),
+
     #[error("Key missing from cache: {0}")]
     KeyMissing(PathBuf),
 
@@ -4,10 +4,17 @@
 mod base_fs_cache;
 mod cache_interface;
 pub mod errors;
+mod packed_file;
 mod processing_fs_cache;
+//without the sqlite feature, entries still describe their rows but are never written to a database.
+#[cfg_attr(not(feature = "sqlite"), allow(dead_code, unused_imports))]
+mod sqlite_store;
 //mod file_set;
 //Exports
+pub use base_fs_cache::CacheFormat;
 pub use cache_interface::CacheInterface;
 pub use errors::FsCacheErrorKind;
-pub use processing_fs_cache::ProcessingFsCache;
+pub use packed_file::{packed_options, PackedColumns, PackedPath};
+pub use processing_fs_cache::{FetchUpdate, ProcessingFsCache, StatMismatch};
+pub use sqlite_store::{SqliteColumns, ValueColumns};
 //pub use file_set::FileSet;
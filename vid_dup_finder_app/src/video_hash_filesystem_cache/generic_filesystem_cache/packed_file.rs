@@ -0,0 +1,318 @@
+//! The packed format of bincode cache files.
+//!
+//! # Layout (version 1)
+//! | bytes | contents                                                          |
+//! |-------|-------------------------------------------------------------------|
+//! | 8     | the magic bytes `VDFPACK\0`                                       |
+//! | 1     | the version, [`PACKED_VERSION`]                                   |
+//! | 1     | how the rest of the file is compressed: 0 for not at all, 1 for zstd |
+//! | rest  | the directories and entries, in bincode with varint integers      |
+//!
+//! The directories are a list of paths. Each entry is the index of its directory in that list,
+//! the rest of its path, and its value as written by [`PackedEntry::to_packed`]. Entries are in
+//! order of their paths.
+//!
+//! Files which do not start with the magic bytes were written before the packed format, as a
+//! bincode map from each path to its value. They are still read, and are rewritten in the packed
+//! format when they are next saved.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    io::{Read, Write},
+    path::{Path, PathBuf},
+};
+
+use bincode::Options;
+use serde::{
+    de::{self, DeserializeOwned, Visitor},
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+
+pub const PACKED_VERSION: u8 = 1;
+pub(super) const MAGIC: &[u8; 8] = b"VDFPACK\0";
+
+//A corrupt length in a compressed file cannot be rejected by comparing it with the length of the
+//file, so anything which would decompress to more than this many times the file is rejected instead.
+#[cfg(feature = "zstd")]
+const MAX_COMPRESSION_RATIO: u64 = 256;
+
+#[cfg(feature = "zstd")]
+const ZSTD_LEVEL: i32 = 3;
+
+/// How the body of a packed cache file is compressed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    /// Only available when built with the `zstd` feature.
+    Zstd,
+}
+
+impl Compression {
+    /// Caches are compressed with zstd if this build supports it.
+    pub const DEFAULT: Self = if cfg!(feature = "zstd") {
+        Self::Zstd
+    } else {
+        Self::None
+    };
+
+    const fn to_byte(self) -> u8 {
+        match self {
+            Self::None => 0,
+            Self::Zstd => 1,
+        }
+    }
+
+    fn from_byte(byte: u8) -> Result<Self, String> {
+        match byte {
+            0 => Ok(Self::None),
+            1 => Ok(Self::Zstd),
+            _ => Err(format!("unknown compression {byte}")),
+        }
+    }
+}
+
+/// The bincode options of the packed format, which values can also use to pack themselves.
+pub fn packed_options() -> impl Options + Copy {
+    bincode::DefaultOptions::new()
+}
+
+/// How a cached value is stored in a packed cache file. By default the whole value is serialized
+/// with [`packed_options`].
+pub trait PackedColumns: Serialize + DeserializeOwned {
+    fn to_packed(&self, _key: &Path) -> Result<Vec<u8>, String> {
+        packed_options().serialize(self).map_err(|e| e.to_string())
+    }
+
+    fn from_packed(bytes: &[u8], _key: &Path) -> Result<Self, String> {
+        packed_options()
+            .deserialize(bytes)
+            .map_err(|e| e.to_string())
+    }
+}
+
+/// Entries of a [`BaseFsCache`][super::base_fs_cache::BaseFsCache] which can be stored in a packed
+/// cache file. The path of the entry is stored by the file, so it is given back when the entry is
+/// read.
+pub trait PackedEntry: Sized {
+    fn to_packed(&self, key: &Path) -> Result<Vec<u8>, String>;
+
+    fn from_packed(bytes: &[u8], key: &Path) -> Result<Self, String>;
+}
+
+/// A path, serialized without losing any bytes.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PackedPath(#[serde(with = "vid_dup_finder_common::path_serde")] pub PathBuf);
+
+#[derive(Serialize)]
+struct BodyRef<'a> {
+    dirs: Vec<PathRef<'a>>,
+    entries: Vec<EntryRef<'a>>,
+}
+
+#[derive(Serialize)]
+struct PathRef<'a>(#[serde(with = "vid_dup_finder_common::path_serde")] &'a Path);
+
+#[derive(Serialize)]
+struct EntryRef<'a> {
+    dir: u32,
+    name: PathRef<'a>,
+    #[serde(with = "bytes")]
+    value: Vec<u8>,
+}
+
+#[derive(Deserialize)]
+struct Body {
+    dirs: Vec<PackedPath>,
+    entries: Vec<Entry>,
+}
+
+#[derive(Deserialize)]
+struct Entry {
+    dir: u32,
+    name: PackedPath,
+    #[serde(with = "bytes")]
+    value: Vec<u8>,
+}
+
+//Splits a path into its directory and the rest of it, such that joining them gives back exactly
+//the same path.
+fn split(key: &Path) -> (&Path, &Path) {
+    if let Some(dir) = key.parent() {
+        if let Ok(name) = key.strip_prefix(dir) {
+            if dir.join(name).as_os_str() == key.as_os_str() {
+                return (dir, name);
+            }
+        }
+    }
+    (Path::new(""), key)
+}
+
+fn join(dir: &Path, name: PathBuf) -> PathBuf {
+    if dir.as_os_str().is_empty() {
+        name
+    } else {
+        dir.join(name)
+    }
+}
+
+/// Write `cache` to `w` in the packed format.
+pub fn write<T: PackedEntry>(
+    mut w: impl Write,
+    cache: &BTreeMap<PathBuf, T>,
+    compression: Compression,
+) -> Result<(), String> {
+    let mut dir_idxs = HashMap::<&Path, u32>::new();
+    let mut dirs = vec![];
+    let mut entries = Vec::with_capacity(cache.len());
+    for (key, value) in cache {
+        let (dir, name) = split(key);
+        let dir = *dir_idxs.entry(dir).or_insert_with(|| {
+            dirs.push(PathRef(dir));
+            u32::try_from(dirs.len() - 1).expect("fewer than 2^32 directories")
+        });
+        entries.push(EntryRef {
+            dir,
+            name: PathRef(name),
+            value: value.to_packed(key)?,
+        });
+    }
+    let body = BodyRef { dirs, entries };
+
+    w.write_all(MAGIC).map_err(|e| e.to_string())?;
+    w.write_all(&[PACKED_VERSION, compression.to_byte()])
+        .map_err(|e| e.to_string())?;
+
+    match compression {
+        Compression::None => packed_options()
+            .serialize_into(w, &body)
+            .map_err(|e| e.to_string()),
+
+        #[cfg(feature = "zstd")]
+        Compression::Zstd => {
+            let mut encoder = zstd::Encoder::new(w, ZSTD_LEVEL).map_err(|e| e.to_string())?;
+            packed_options()
+                .serialize_into(&mut encoder, &body)
+                .map_err(|e| e.to_string())?;
+            encoder.finish().map(|_| ()).map_err(|e| e.to_string())
+        }
+
+        #[cfg(not(feature = "zstd"))]
+        Compression::Zstd => Err(zstd_unsupported()),
+    }
+}
+
+/// Read a cache written by [`write`] from `r`, which has already had its magic bytes read.
+/// `file_len` is the length of the whole file.
+pub fn read<T: PackedEntry>(
+    mut r: impl Read,
+    file_len: u64,
+) -> Result<BTreeMap<PathBuf, T>, String> {
+    let mut header = [0; 2];
+    r.read_exact(&mut header).map_err(|e| e.to_string())?;
+    let [version, compression] = header;
+    if version != PACKED_VERSION {
+        return Err(format!(
+            "unsupported packed cache version {version}. The supported version is {PACKED_VERSION}"
+        ));
+    }
+
+    //As with the files from before the packed format, nothing can be longer than the file.
+    let body: Body = match Compression::from_byte(compression)? {
+        Compression::None => packed_options()
+            .with_limit(file_len)
+            .deserialize_from(r)
+            .map_err(|e| e.to_string())?,
+
+        #[cfg(feature = "zstd")]
+        Compression::Zstd => {
+            let decoder = zstd::Decoder::new(r).map_err(|e| e.to_string())?;
+            packed_options()
+                .with_limit(file_len.saturating_mul(MAX_COMPRESSION_RATIO))
+                .deserialize_from(decoder)
+                .map_err(|e| e.to_string())?
+        }
+
+        #[cfg(not(feature = "zstd"))]
+        Compression::Zstd => return Err(zstd_unsupported()),
+    };
+
+    body.entries
+        .into_iter()
+        .map(|Entry { dir, name, value }| {
+            let dir = body
+                .dirs
+                .get(dir as usize)
+                .ok_or_else(|| format!("directory {dir} is out of range"))?;
+            let key = join(&dir.0, name.0);
+            let value = T::from_packed(&value, &key)?;
+            Ok((key, value))
+        })
+        .collect()
+}
+
+#[cfg(not(feature = "zstd"))]
+fn zstd_unsupported() -> String {
+    "the cache is compressed with zstd, but this build does not have the zstd feature".to_string()
+}
+
+//Bincode writes a Vec<u8> one element at a time, but writes bytes all at once.
+mod bytes {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(v: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(v)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        deserializer.deserialize_byte_buf(BytesVisitor)
+    }
+
+    struct BytesVisitor;
+
+    impl Visitor<'_> for BytesVisitor {
+        type Value = Vec<u8>;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("bytes")
+        }
+
+        fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<Self::Value, E> {
+            Ok(v.to_vec())
+        }
+
+        fn visit_byte_buf<E: de::Error>(self, v: Vec<u8>) -> Result<Self::Value, E> {
+            Ok(v)
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod test {
+    use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+    use super::*;
+
+    #[test]
+    fn test_split_rejoins_exactly() {
+        let paths = [
+            "/videos/cat.mp4",
+            "/videos/cats/",
+            "cat.mp4",
+            "/",
+            "",
+            "videos/..",
+            "/videos//cat.mp4",
+            "./cat.mp4",
+        ];
+        for path in paths
+            .map(Path::new)
+            .into_iter()
+            .chain([Path::new(OsStr::from_bytes(b"/v/caf\xe9.mp4"))])
+        {
+            let (dir, name) = split(path);
+            let joined = join(dir, name.to_path_buf());
+            assert_eq!(joined.as_os_str(), path.as_os_str(), "{path:?}");
+        }
+    }
+}
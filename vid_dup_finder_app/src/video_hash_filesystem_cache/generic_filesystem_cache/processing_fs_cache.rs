@@ -1,31 +1,121 @@
 use std::{
     fs,
     path::{Path, PathBuf},
-    time::{SystemTime, UNIX_EPOCH},
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
-use serde::{Deserialize, Serialize};
+use bincode::Options;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use FsCacheErrorKind::*;
 
 use super::cache_interface::CacheInterface;
 use super::{
-    base_fs_cache::BaseFsCache,
+    base_fs_cache::{BaseFsCache, CacheFormat},
     errors::{FsCacheErrorKind, FsCacheResult},
+    packed_file::{packed_options, PackedColumns, PackedEntry},
+    sqlite_store::{SqliteColumns, SqliteEntry, SqliteRow},
 };
 
 /// How a file on disk may have changed since the last time the cache was updated
 enum UpdateAction {
     NoChange,
-    Update(SystemTime),
+    Update(FsStat),
     Remove,
 }
 
+#[derive(Clone, Copy)]
+struct FsStat {
+    mtime: SystemTime,
+    size: u64,
+}
+
+/// The result of [`ProcessingFsCache::fetch_update_detailed`]
+pub enum FetchUpdate<T> {
+    /// The file was unchanged, so the cached value was returned.
+    Cached(T),
+    /// The file was new or modified, so a new value was loaded.
+    Updated(T),
+    /// The file no longer exists, so it was removed from the cache.
+    Removed,
+}
+
+/// How a cached file differs on the filesystem from when it was cached, as found by
+/// [`ProcessingFsCache::stat_mismatch`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatMismatch {
+    /// The file no longer exists.
+    Missing,
+    /// The modification time or the size of the file (or both) differ from those it had when it
+    /// was cached.
+    Changed { mtime: bool, size: bool },
+}
+
 #[derive(Serialize, Deserialize, Clone)]
 struct MtimeCacheEntry<T> {
     cache_mtime: SystemTime,
+    size: u64,
     value: T,
 }
 
+impl<T: SqliteColumns> SqliteEntry for MtimeCacheEntry<T> {
+    fn to_row(&self) -> Result<SqliteRow, String> {
+        let mtime = self
+            .cache_mtime
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+
+        Ok(SqliteRow {
+            mtime: i64::try_from(mtime).map_err(|e| e.to_string())?,
+            size: i64::try_from(self.size).map_err(|e| e.to_string())?,
+            value: self.value.to_columns()?,
+        })
+    }
+
+    fn from_row(row: SqliteRow) -> Result<Self, String> {
+        let mtime = u64::try_from(row.mtime).map_err(|e| e.to_string())?;
+
+        Ok(Self {
+            cache_mtime: UNIX_EPOCH + Duration::from_nanos(mtime),
+            size: u64::try_from(row.size).map_err(|e| e.to_string())?,
+            value: T::from_columns(row.value)?,
+        })
+    }
+}
+
+// The mtime and size are written first, followed by the value.
+impl<T: PackedColumns> PackedEntry for MtimeCacheEntry<T> {
+    fn to_packed(&self, key: &Path) -> Result<Vec<u8>, String> {
+        let mtime = self
+            .cache_mtime
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+
+        let mut bytes = packed_options()
+            .serialize(&(mtime.as_secs(), mtime.subsec_nanos(), self.size))
+            .map_err(|e| e.to_string())?;
+        bytes.extend(self.value.to_packed(key)?);
+        Ok(bytes)
+    }
+
+    fn from_packed(mut bytes: &[u8], key: &Path) -> Result<Self, String> {
+        let (secs, nanos, size): (u64, u32, u64) = packed_options()
+            .deserialize_from(&mut bytes)
+            .map_err(|e| e.to_string())?;
+
+        let cache_mtime = (nanos < 1_000_000_000)
+            .then(|| UNIX_EPOCH.checked_add(Duration::new(secs, nanos)))
+            .flatten()
+            .ok_or("the mtime is out of range")?;
+
+        Ok(Self {
+            cache_mtime,
+            size,
+            value: T::from_packed(bytes, key)?,
+        })
+    }
+}
+
 pub struct ProcessingFsCache<I>
 where
     I: CacheInterface,
@@ -41,9 +131,10 @@ where
     pub fn new(
         cache_save_threshold: u32,
         cache_path: PathBuf,
+        format: CacheFormat,
         interface: I,
     ) -> FsCacheResult<Self> {
-        match BaseFsCache::new(cache_save_threshold, cache_path) {
+        match BaseFsCache::new(cache_save_threshold, cache_path, format) {
             Ok(base_cache) => Ok(Self {
                 base_cache,
                 interface,
@@ -52,6 +143,41 @@ where
         }
     }
 
+    /// Copy every entry of the bincode cache at `bincode_path` into a sqlite cache at `sqlite_path`.
+    pub fn migrate_to_sqlite(bincode_path: PathBuf, sqlite_path: &Path) -> FsCacheResult<usize> {
+        BaseFsCache::<MtimeCacheEntry<I::T>>::migrate_to_sqlite(bincode_path, sqlite_path)
+    }
+
+    /// Copy every entry of the bincode cache at `src_path`, whose values are `T`s, into this cache,
+    /// converting each value (along with the size of its file) with `convert`. Returns the number
+    /// of entries copied.
+    pub fn import<T>(
+        &self,
+        src_path: PathBuf,
+        convert: impl Fn(T, u64) -> I::T,
+    ) -> FsCacheResult<usize>
+    where
+        T: Serialize + DeserializeOwned + SqliteColumns + PackedColumns + Clone + Send + Sync,
+    {
+        //nothing is inserted, so the save threshold is never reached.
+        let src = BaseFsCache::<MtimeCacheEntry<T>>::new(u32::MAX, src_path, CacheFormat::Bincode)?;
+        let keys = src.keys();
+        for key in &keys {
+            let MtimeCacheEntry {
+                cache_mtime,
+                size,
+                value,
+            } = src.fetch(key)?;
+            let cache_entry = MtimeCacheEntry {
+                cache_mtime,
+                size,
+                value: convert(value, size),
+            };
+            self.base_cache.insert(key.clone(), cache_entry)?;
+        }
+        Ok(keys.len())
+    }
+
     pub fn save(&self) -> FsCacheResult<()> {
         self.base_cache.save()
     }
@@ -80,14 +206,22 @@ where
         // * Item is not in cache.
         // * Cached item is out of date.
 
+        match self.fetch_update_detailed(key)? {
+            FetchUpdate::Cached(value) | FetchUpdate::Updated(value) => Ok(Some(value)),
+            FetchUpdate::Removed => Ok(None),
+        }
+    }
+
+    /// As [`Self::fetch_update`], but also reports whether the value came from the cache.
+    pub fn fetch_update_detailed(&self, key: impl AsRef<Path>) -> FsCacheResult<FetchUpdate<I::T>> {
         let key = key.as_ref();
 
         match self.get_update_action(key)? {
-            UpdateAction::NoChange => self.fetch(key).map(Option::from),
-            UpdateAction::Update(fs_mtime) => {
-                self.force_update_inner(key, fs_mtime).map(Option::from)
-            }
-            UpdateAction::Remove => self.remove(key).map(|_| None),
+            UpdateAction::NoChange => self.fetch(key).map(FetchUpdate::Cached),
+            UpdateAction::Update(fs_stat) => self
+                .force_update_inner(key, fs_stat)
+                .map(FetchUpdate::Updated),
+            UpdateAction::Remove => self.remove(key).map(|()| FetchUpdate::Removed),
         }
     }
 
@@ -104,12 +238,13 @@ where
     //     )
     // }
 
-    fn force_update_inner(&self, key: impl AsRef<Path>, mtime: SystemTime) -> FsCacheResult<I::T> {
+    fn force_update_inner(&self, key: impl AsRef<Path>, fs_stat: FsStat) -> FsCacheResult<I::T> {
         let key = key.as_ref();
 
         let value = self.interface.load(key);
         let cache_entry = MtimeCacheEntry {
-            cache_mtime: mtime,
+            cache_mtime: fs_stat.mtime,
+            size: fs_stat.size,
             value,
         };
         self.base_cache.insert(key.to_path_buf(), cache_entry)?;
@@ -122,11 +257,69 @@ where
     //     self.base_cache.contains_key(key)
     // }
 
+    /// Replace the cached value of `key`, which must already be in the cache, without changing the
+    /// modification time recorded for it.
+    pub fn replace_value(&self, key: impl AsRef<Path>, value: I::T) -> FsCacheResult<()> {
+        let key = key.as_ref();
+        let cache_entry = MtimeCacheEntry {
+            value,
+            ..self.base_cache.fetch(key)?
+        };
+        self.base_cache.insert(key.to_path_buf(), cache_entry)
+    }
+
+    /// Cache a value for a file as if the interface had just loaded it, recording the file's
+    /// current modification time and size.
+    pub fn insert_loaded(&self, key: impl AsRef<Path>, value: I::T) -> FsCacheResult<()> {
+        let key = key.as_ref();
+        let fs_stat = Self::fs_stat(key).map_err(|e| CacheFileIo {
+            path: key.to_path_buf(),
+            src: e,
+        })?;
+        let cache_entry = MtimeCacheEntry {
+            cache_mtime: fs_stat.mtime,
+            size: fs_stat.size,
+            value,
+        };
+        self.base_cache.insert(key.to_path_buf(), cache_entry)
+    }
+
+    #[inline]
+    pub fn cache_path(&self) -> &Path {
+        self.base_cache.cache_path()
+    }
+
+    #[inline]
+    pub fn interface(&self) -> &I {
+        &self.interface
+    }
+
     #[inline]
     pub fn keys(&self) -> Vec<PathBuf> {
         self.base_cache.keys()
     }
 
+    #[inline]
+    pub fn keys_under(&self, prefix: &Path) -> Vec<PathBuf> {
+        self.base_cache.keys_under(prefix)
+    }
+
+    #[inline]
+    pub fn remove_under(&self, prefix: &Path) -> FsCacheResult<usize> {
+        self.base_cache.remove_under(prefix)
+    }
+
+    /// Move the entries under `old` to the same places under `new`, as if their files had been
+    /// moved. The modification times recorded for them are kept, so the moved files are not
+    /// loaded again unless they have changed.
+    pub fn rename_prefix(&self, old: &Path, new: &Path) -> FsCacheResult<usize> {
+        self.base_cache
+            .rename_prefix(old, new, |entry, new_key| MtimeCacheEntry {
+                value: self.interface.renamed(entry.value, new_key),
+                ..entry
+            })
+    }
+
     // #[inline]
     // pub fn len(&self) -> usize {
     //     self.base_cache.len()
@@ -137,8 +330,40 @@ where
     //     self.base_cache.is_empty()
     // }
 
-    fn fs_mtime(key: &Path) -> Result<SystemTime, std::io::Error> {
-        fs::metadata(key)?.modified()
+    /// Compare the file at `key` with the modification time and size recorded when it was cached,
+    /// without loading it again. Modification times are compared as loosely as they are when
+    /// deciding whether to load a file again.
+    ///
+    /// Returns `None` if the file is unchanged, or an error if `key` is not in the cache or the
+    /// file could not be read.
+    pub fn stat_mismatch(&self, key: impl AsRef<Path>) -> FsCacheResult<Option<StatMismatch>> {
+        let key = key.as_ref();
+        let entry = self.base_cache.fetch(key)?;
+
+        let fs_stat = match Self::fs_stat(key) {
+            Ok(fs_stat) => fs_stat,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(Some(StatMismatch::Missing))
+            }
+            Err(e) => {
+                return Err(CacheFileIo {
+                    path: key.to_path_buf(),
+                    src: e,
+                })
+            }
+        };
+
+        let mtime = Self::mtimes_differ(entry.cache_mtime, fs_stat.mtime);
+        let size = entry.size != fs_stat.size;
+        Ok((mtime || size).then_some(StatMismatch::Changed { mtime, size }))
+    }
+
+    fn fs_stat(key: &Path) -> Result<FsStat, std::io::Error> {
+        let metadata = fs::metadata(key)?;
+        Ok(FsStat {
+            mtime: metadata.modified()?,
+            size: metadata.len(),
+        })
     }
 
     // helper function to get whether a particular path has been updated in the filesystem.
@@ -147,13 +372,10 @@ where
     // reports less granular mtimes?), where a file will only be considered stale if the mtime
     // is different by more than DURATION_TOLERANCE.
     fn get_update_action(&self, key: &Path) -> FsCacheResult<UpdateAction> {
-        // debug: switch between ignoring nanos and not (current  workaround for nanos-difference might be causing issues?)
-        let include_nanos = false;
-
         //If the path is not present on the filesystem, then remove it from the cache
         //(it may have never existed in the cache but this is OK)
-        let fs_mtime = match Self::fs_mtime(key) {
-            Ok(fs_mtime) => fs_mtime,
+        let fs_stat = match Self::fs_stat(key) {
+            Ok(fs_stat) => fs_stat,
             Err(e) => match e.kind() {
                 std::io::ErrorKind::NotFound => return Ok(UpdateAction::Remove),
                 _ => {
@@ -165,14 +387,29 @@ where
             },
         };
 
-        //if the file exists on the filesystem but not in the cache, we will insert it.
+        //if the file exists on the filesystem but not in the cache, we will insert it. Likewise
+        //if the cached value was created in a way the interface no longer wants.
         let cache_mtime = match self.base_cache.fetch(key) {
+            Ok(entry) if !self.interface.is_current(key, &entry.value) => {
+                return Ok(UpdateAction::Update(fs_stat))
+            }
             Ok(entry) => entry.cache_mtime,
-            Err(_e) => return Ok(UpdateAction::Update(fs_mtime)),
+            Err(_e) => return Ok(UpdateAction::Update(fs_stat)),
         };
 
         //otherwise, see if the file is changed...
-        let is_stale = if include_nanos {
+        if Self::mtimes_differ(cache_mtime, fs_stat.mtime) {
+            Ok(UpdateAction::Update(fs_stat))
+        } else {
+            Ok(UpdateAction::NoChange)
+        }
+    }
+
+    fn mtimes_differ(cache_mtime: SystemTime, fs_mtime: SystemTime) -> bool {
+        // debug: switch between ignoring nanos and not (current  workaround for nanos-difference might be causing issues?)
+        let include_nanos = false;
+
+        if include_nanos {
             //original implementation used the following code, which produced errors as SystemTime::duration_since
             //appears to return an error if only the nanos portion of the fields differ
             fs_mtime != cache_mtime
@@ -189,12 +426,156 @@ where
                 .as_secs() as i64;
 
             (cache_mtime_secs - fs_mtime_secs).abs() > DURATION_TOLERANCE_SECS
-        };
+        }
+    }
+}
 
-        if is_stale {
-            Ok(UpdateAction::Update(fs_mtime))
-        } else {
-            Ok(UpdateAction::NoChange)
+#[cfg(test)]
+mod test {
+    use std::io::Read;
+
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+    use vid_dup_finder_lib::{CreationOptions, HashStats, VideoHash};
+
+    use super::super::packed_file::{MAGIC, PACKED_VERSION};
+    use super::*;
+    use crate::video_hash_filesystem_cache::generic_cache_if::CachedHash;
+
+    type Entry = MtimeCacheEntry<CachedHash>;
+
+    //Something like a large video library: shows with seasons of episodes, with the hashes and
+    //stats of a real update.
+    fn corpus(len: usize) -> std::collections::BTreeMap<PathBuf, Entry> {
+        let mut rng = StdRng::seed_from_u64(1);
+        (0..len)
+            .map(|i| {
+                let path = PathBuf::from(format!(
+                    "/mnt/media/library/videos/Show Number {}/Season {:02}/Show Number {} - S{:02}E{:02} - An Episode Title.mkv",
+                    i / 200,
+                    i / 20 % 10,
+                    i / 200,
+                    i / 20 % 10,
+                    i % 20
+                ));
+                let hash = VideoHash::random_hash(&mut rng)
+                    .with_duration(rng.gen_range(60..3600))
+                    .with_src_path(&path);
+                let stats = HashStats {
+                    elapsed: Duration::from_millis(rng.gen_range(200..5000)),
+                    backend: "ffmpeg".to_string(),
+                    frames_decoded: Some(64),
+                    resolution: Some((1920, 1080)),
+                    video_codec: Some("h264".to_string()),
+                    decoder: None,
+                };
+                let entry = MtimeCacheEntry {
+                    cache_mtime: UNIX_EPOCH + Duration::new(rng.gen_range(1e9 as u64..1.7e9 as u64), rng.gen_range(0..1_000_000_000)),
+                    size: rng.gen_range(1 << 20..1 << 32),
+                    value: CachedHash {
+                        opts: CreationOptions::default(),
+                        hash: Ok(hash),
+                        stats: Some(stats),
+                    },
+                };
+                (path, entry)
+            })
+            .collect()
+    }
+
+    fn assert_same(a: &std::collections::BTreeMap<PathBuf, Entry>, b: &BaseFsCache<Entry>) {
+        assert_eq!(b.len(), a.len());
+        for (path, entry) in a {
+            let loaded = b.fetch(path).unwrap();
+            assert_eq!(loaded.cache_mtime, entry.cache_mtime);
+            assert_eq!(loaded.size, entry.size);
+            assert_eq!(loaded.value.hash.ok(), entry.value.hash.clone().ok());
+            assert_eq!(loaded.value.stats, entry.value.stats);
         }
     }
+
+    #[test]
+    fn test_legacy_caches_are_packed_when_saved() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let dir = temp_dir.path();
+        let cache_path = dir.join("cache.bin");
+
+        //a cache as written before the packed format.
+        let entries = corpus(20_000);
+        let file = std::fs::File::create(&cache_path).unwrap();
+        bincode::serialize_into(std::io::BufWriter::new(file), &entries).unwrap();
+        let legacy_len = fs::metadata(&cache_path).unwrap().len();
+
+        let cache =
+            BaseFsCache::<Entry>::new(u32::MAX, cache_path.clone(), CacheFormat::Bincode).unwrap();
+        assert_same(&entries, &cache);
+        cache.save().unwrap();
+
+        //the magic bytes, the version of the packed format, and whether it is compressed.
+        let mut header = [0; 10];
+        fs::File::open(&cache_path)
+            .unwrap()
+            .read_exact(&mut header)
+            .unwrap();
+        assert_eq!(&header[..8], MAGIC);
+        assert_eq!(header[8], PACKED_VERSION);
+        assert_eq!(header[9], u8::from(cfg!(feature = "zstd")));
+
+        let packed_len = fs::metadata(&cache_path).unwrap().len();
+        let reloaded =
+            BaseFsCache::<Entry>::new(u32::MAX, cache_path, CacheFormat::Bincode).unwrap();
+        assert_same(&entries, &reloaded);
+
+        //packing alone takes most of the size away, and zstd takes most of what is left.
+        let min_ratio = if cfg!(feature = "zstd") { 3 } else { 2 };
+        assert!(
+            packed_len * min_ratio <= legacy_len,
+            "{packed_len} is not 1/{min_ratio} of {legacy_len}"
+        );
+    }
+
+    //Not a check, because timings on a busy machine vary too much to compare them. Run with
+    //`cargo test --release -- --ignored --nocapture bench_packed_cache_load`.
+    #[test]
+    #[ignore = "a benchmark, which only prints timings"]
+    #[allow(clippy::print_stdout)]
+    fn bench_packed_cache_load() {
+        use std::time::Instant;
+
+        //the fastest of a few loads, to leave out anything else the machine was doing.
+        fn load_time(path: &Path) -> Duration {
+            (0..3)
+                .map(|_| {
+                    let start = Instant::now();
+                    let cache = BaseFsCache::<Entry>::new(
+                        u32::MAX,
+                        path.to_path_buf(),
+                        CacheFormat::Bincode,
+                    )
+                    .unwrap();
+                    let elapsed = start.elapsed();
+                    assert!(cache.len() > 0);
+                    elapsed
+                })
+                .min()
+                .unwrap()
+        }
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let cache_path = temp_dir.path().join("cache.bin");
+
+        let entries = corpus(20_000);
+        let file = std::fs::File::create(&cache_path).unwrap();
+        bincode::serialize_into(std::io::BufWriter::new(file), &entries).unwrap();
+        let legacy_load = load_time(&cache_path);
+
+        BaseFsCache::<Entry>::new(u32::MAX, cache_path.clone(), CacheFormat::Bincode)
+            .unwrap()
+            .save()
+            .unwrap();
+        let packed_load = load_time(&cache_path);
+
+        //decompressing costs about what reading the smaller file saves.
+        println!("legacy cache load time: {}", legacy_load.as_secs_f64());
+        println!("packed cache load time: {}", packed_load.as_secs_f64());
+    }
 }
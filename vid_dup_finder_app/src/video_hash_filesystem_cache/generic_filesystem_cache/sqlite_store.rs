@@ -0,0 +1,266 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+#[cfg(feature = "sqlite")]
+use parking_lot::Mutex;
+#[cfg(feature = "sqlite")]
+use rusqlite::{params, Connection};
+use serde::{de::DeserializeOwned, Serialize};
+
+use super::errors::{
+    FsCacheErrorKind::{self, *},
+    FsCacheResult,
+};
+
+/// The columns of a sqlite cache which hold a cached value.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ValueColumns {
+    pub hash: Option<Vec<u8>>,
+    pub error: Option<String>,
+    pub created_opts: Option<Vec<u8>>,
+    pub stats: Option<Vec<u8>>,
+}
+
+/// A row of a sqlite cache, apart from its path.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SqliteRow {
+    // nanoseconds since the unix epoch.
+    pub mtime: i64,
+    pub size: i64,
+    pub value: ValueColumns,
+}
+
+/// How a cached value is split into columns of a sqlite cache. By default the whole value is
+/// stored in the `hash` column.
+pub trait SqliteColumns: Serialize + DeserializeOwned {
+    fn to_columns(&self) -> Result<ValueColumns, String> {
+        let hash = bincode::serialize(self).map_err(|e| e.to_string())?;
+        Ok(ValueColumns {
+            hash: Some(hash),
+            ..ValueColumns::default()
+        })
+    }
+
+    fn from_columns(columns: ValueColumns) -> Result<Self, String> {
+        let hash = columns.hash.ok_or("the hash column is empty")?;
+        bincode::deserialize(&hash).map_err(|e| e.to_string())
+    }
+}
+
+/// Entries of a [`BaseFsCache`][super::base_fs_cache::BaseFsCache] which can be stored in sqlite.
+pub trait SqliteEntry: Sized {
+    fn to_row(&self) -> Result<SqliteRow, String>;
+
+    fn from_row(row: SqliteRow) -> Result<Self, String>;
+}
+
+/// A sqlite database which stores one row per cache entry. Every change is written (and committed)
+/// as soon as it is made, so there is never a large file to rewrite, and a crash loses at most the
+/// change being made at the time.
+#[cfg(feature = "sqlite")]
+#[derive(Debug)]
+pub struct SqliteStore {
+    path: PathBuf,
+    conn: Mutex<Connection>,
+}
+
+#[cfg(feature = "sqlite")]
+impl SqliteStore {
+    pub fn open(path: &Path) -> FsCacheResult<Self> {
+        if let Some(parent_dir) = path.parent() {
+            std::fs::create_dir_all(parent_dir).map_err(|e| CacheFileIo {
+                src: e,
+                path: path.to_path_buf(),
+            })?;
+        }
+
+        let db_err = |e| Database {
+            src: e,
+            path: path.to_path_buf(),
+        };
+
+        let conn = Connection::open(path).map_err(db_err)?;
+
+        //WAL keeps the database intact if the application dies part way through a write, and lets
+        //the cache be read while it is being written to.
+        conn.pragma_update_and_check(None, "journal_mode", "WAL", |row| row.get::<_, String>(0))
+            .map_err(db_err)?;
+        conn.pragma_update(None, "synchronous", "NORMAL")
+            .map_err(db_err)?;
+
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS hashes (
+                path BLOB PRIMARY KEY NOT NULL,
+                mtime INTEGER NOT NULL,
+                size INTEGER NOT NULL,
+                hash BLOB,
+                error TEXT,
+                created_opts BLOB,
+                stats BLOB
+            )",
+        )
+        .map_err(db_err)?;
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            conn: Mutex::new(conn),
+        })
+    }
+
+    pub fn load_all<T: SqliteEntry>(&self) -> FsCacheResult<BTreeMap<PathBuf, T>> {
+        let conn = self.conn.lock();
+        let mut stmt = conn
+            .prepare("SELECT path, mtime, size, hash, error, created_opts, stats FROM hashes")
+            .map_err(|e| self.db_err(e))?;
+
+        let rows = stmt
+            .query_map([], |row| {
+                let path: Vec<u8> = row.get(0)?;
+                let row = SqliteRow {
+                    mtime: row.get(1)?,
+                    size: row.get(2)?,
+                    value: ValueColumns {
+                        hash: row.get(3)?,
+                        error: row.get(4)?,
+                        created_opts: row.get(5)?,
+                        stats: row.get(6)?,
+                    },
+                };
+                Ok((path, row))
+            })
+            .map_err(|e| self.db_err(e))?;
+
+        let mut ret = BTreeMap::new();
+        for row in rows {
+            let (path, row) = row.map_err(|e| self.db_err(e))?;
+            let path = path_from_bytes(path).map_err(|e| self.deserialization_err(e))?;
+            let entry = T::from_row(row).map_err(|e| self.deserialization_err(e))?;
+            ret.insert(path, entry);
+        }
+
+        Ok(ret)
+    }
+
+    pub fn upsert<T: SqliteEntry>(&self, key: &Path, entry: &T) -> FsCacheResult<()> {
+        self.upsert_all([(key, entry)]).map(|_| ())
+    }
+
+    /// Write all the given entries in a single transaction, returning how many were written.
+    pub fn upsert_all<'a, T: SqliteEntry + 'a>(
+        &self,
+        entries: impl IntoIterator<Item = (&'a Path, &'a T)>,
+    ) -> FsCacheResult<usize> {
+        let mut conn = self.conn.lock();
+        let tx = conn.transaction().map_err(|e| self.db_err(e))?;
+
+        let mut count = 0;
+        {
+            let mut stmt = tx
+                .prepare_cached(
+                    "INSERT INTO hashes (path, mtime, size, hash, error, created_opts, stats)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+                     ON CONFLICT(path) DO UPDATE SET
+                        mtime = excluded.mtime,
+                        size = excluded.size,
+                        hash = excluded.hash,
+                        error = excluded.error,
+                        created_opts = excluded.created_opts,
+                        stats = excluded.stats",
+                )
+                .map_err(|e| self.db_err(e))?;
+
+            for (key, entry) in entries {
+                let row = entry.to_row().map_err(|e| Serialization {
+                    src: e,
+                    path: self.path.clone(),
+                })?;
+                stmt.execute(params![
+                    key.as_os_str().as_encoded_bytes(),
+                    row.mtime,
+                    row.size,
+                    row.value.hash,
+                    row.value.error,
+                    row.value.created_opts,
+                    row.value.stats,
+                ])
+                .map_err(|e| self.db_err(e))?;
+                count += 1;
+            }
+        }
+
+        tx.commit().map_err(|e| self.db_err(e))?;
+        Ok(count)
+    }
+
+    pub fn remove(&self, key: &Path) -> FsCacheResult<()> {
+        self.conn
+            .lock()
+            .execute(
+                "DELETE FROM hashes WHERE path = ?1",
+                [key.as_os_str().as_encoded_bytes()],
+            )
+            .map(|_| ())
+            .map_err(|e| self.db_err(e))
+    }
+
+    fn db_err(&self, src: rusqlite::Error) -> FsCacheErrorKind {
+        Database {
+            src,
+            path: self.path.clone(),
+        }
+    }
+
+    fn deserialization_err(&self, src: String) -> FsCacheErrorKind {
+        Deserialization {
+            src,
+            path: self.path.clone(),
+        }
+    }
+}
+
+/// Without the `sqlite` feature no database can be opened, so there is never a store to use.
+#[cfg(not(feature = "sqlite"))]
+#[derive(Debug)]
+pub enum SqliteStore {}
+
+#[cfg(not(feature = "sqlite"))]
+impl SqliteStore {
+    pub fn open(path: &Path) -> FsCacheResult<Self> {
+        Err(SqliteUnsupported(path.to_path_buf()))
+    }
+
+    pub fn load_all<T: SqliteEntry>(&self) -> FsCacheResult<BTreeMap<PathBuf, T>> {
+        match *self {}
+    }
+
+    pub fn upsert<T: SqliteEntry>(&self, _key: &Path, _entry: &T) -> FsCacheResult<()> {
+        match *self {}
+    }
+
+    pub fn upsert_all<'a, T: SqliteEntry + 'a>(
+        &self,
+        _entries: impl IntoIterator<Item = (&'a Path, &'a T)>,
+    ) -> FsCacheResult<usize> {
+        match *self {}
+    }
+
+    pub fn remove(&self, _key: &Path) -> FsCacheResult<()> {
+        match *self {}
+    }
+}
+
+#[cfg(all(feature = "sqlite", unix))]
+fn path_from_bytes(bytes: Vec<u8>) -> Result<PathBuf, String> {
+    use std::{ffi::OsString, os::unix::ffi::OsStringExt};
+
+    Ok(PathBuf::from(OsString::from_vec(bytes)))
+}
+
+#[cfg(all(feature = "sqlite", not(unix)))]
+fn path_from_bytes(bytes: Vec<u8>) -> Result<PathBuf, String> {
+    String::from_utf8(bytes)
+        .map(PathBuf::from)
+        .map_err(|e| format!("path is not valid UTF-8: {e}"))
+}
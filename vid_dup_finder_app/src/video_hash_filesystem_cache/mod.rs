@@ -11,8 +11,8 @@
 //! use video_hash_filesystem_cache::*;
 //! use vid_dup_finder_lib::*;
 //!
-//! //Gstreamer must be initialized first
-//! vid_dup_finder_lib::init_gstreamer();
+//! //The video backend must be initialized first
+//! vid_dup_finder_lib::init().expect("video backend is available");
 //!
 //! // Create a cache on disk which will save itself to disk after every 100 changes
 //! # let cache_file_path = PathBuf::from("vid_dup_finder_example_cache.bin");
@@ -45,8 +45,8 @@
 //! # use std::ffi::OsStr;
 //! use video_hash_filesystem_cache::*;
 //! use vid_dup_finder_lib::*;
-//! //Gstreamer must be initialized first
-//! vid_dup_finder_lib::init_gstreamer();
+//! //The video backend must be initialized first
+//! vid_dup_finder_lib::init().expect("video backend is available");
 //!
 //! // Create a cache on disk which will save itself to disk after every 100 changes
 //! # let cache_file_path = PathBuf::from("vid_dup_finder_example_cache.bin");
@@ -61,8 +61,8 @@
 //! let mut projection = FileProjection::new(&video_dirs, &excl_dirs, &excl_exts).unwrap();
 //! let project_errs = projection.project_using_fs().unwrap();
 //!
-//! // Update the cache using the projection. a list of individual loading errors will be returned.
-//! let cache_update_errs = cache.update_using_fs(projection.projected_files());
+//! // Update the cache using the projection. A report of which files were hashed, and which failed, is returned.
+//! let report = cache.update_using_fs(projection.projected_files(), UpdateOptions::default());
 //!
 //! // Now all videos under videos_dir_1 and videos_dir_2 will be cached.
 //! // They can be retrieved from the cache without touching the filesystem using
@@ -79,15 +79,22 @@
 #![deny(clippy::print_stderr)]
 
 pub(crate) mod cache_entry;
+pub(crate) mod cache_maintenance;
 pub(crate) mod cache_metadata;
+pub(crate) mod cache_stats;
 pub(crate) mod errors;
 pub(crate) mod file_hash_filesystem_cache;
 pub(crate) mod filename_pattern;
 pub(crate) mod generic_cache_if;
 pub(crate) mod generic_filesystem_cache;
+pub(crate) mod update_report;
 #[allow(clippy::module_inception)]
 pub(crate) mod video_hash_filesystem_cache;
+pub(crate) mod video_ids;
 
 //exports
-pub use self::video_hash_filesystem_cache::VideoHashFilesystemCache;
+pub use self::video_hash_filesystem_cache::{ThumbCrop, VideoHashFilesystemCache};
+pub use cache_maintenance::{CacheSummary, ImportAction};
 pub use errors::VdfCacheError;
+pub use generic_filesystem_cache::{CacheFormat, StatMismatch};
+pub use update_report::{UpdateOptions, UpdateReport};
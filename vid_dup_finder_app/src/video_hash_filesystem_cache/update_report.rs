@@ -0,0 +1,513 @@
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use parking_lot::Mutex;
+#[cfg(feature = "parallel_loading")]
+use rayon::prelude::*;
+use vid_dup_finder_common::panic_message;
+use vid_dup_finder_lib::{CancellationToken, Error};
+
+use super::VdfCacheError;
+
+/// What happened to a single file during [`VideoHashFilesystemCache::update_using_fs`][super::VideoHashFilesystemCache::update_using_fs]
+#[derive(Debug)]
+pub enum FileOutcome {
+    /// The file was new or modified, and was successfully hashed.
+    Hashed,
+    /// The file was unchanged since it was last cached, so nothing was done.
+    Cached,
+    /// The file could not be hashed, or the cache could not be updated.
+    Failed(VdfCacheError),
+    /// The file is shorter than [`CreationOptions::min_duration`][vid_dup_finder_lib::CreationOptions::min_duration],
+    /// so it was not hashed. It is cached as such, and is not retried unless it changes.
+    TooShort,
+    /// The file no longer exists, so it was removed from the cache.
+    Removed,
+    /// The video backend cannot be used (for instance because ffmpeg is not installed), so the
+    /// update was stopped. Nothing was cached for the file.
+    Aborted(VdfCacheError),
+}
+
+/// Options for [`VideoHashFilesystemCache::update_using_fs`][super::VideoHashFilesystemCache::update_using_fs]
+#[derive(Default, Clone, Copy)]
+pub struct UpdateOptions<'a> {
+    /// If cancelled, no more files will be started. Files which are already being hashed are
+    /// allowed to finish and are included in the report.
+    pub cancellation: Option<&'a CancellationToken>,
+
+    /// Called once for each file as soon as it is done (e.g. for progress bars). When updating in
+    /// parallel this is called from many threads, in no particular order.
+    pub on_file_done: Option<FileDoneCallback<'a>>,
+
+    /// The most files that will be hashed at the same time. Each video being hashed has its own
+    /// decoder (an ffmpeg process or a gstreamer pipeline), and decoders can each use hundreds of
+    /// megabytes for high resolution videos, so this mostly limits memory use.
+    ///
+    /// Files are hashed on a thread pool of this size created just for the update, so an update
+    /// never competes for rayon's global pool. If `None`, [`default_concurrency`] is used.
+    ///
+    /// Ignored (files are always hashed one at a time) without the `parallel_loading` feature.
+    pub concurrency: Option<usize>,
+}
+
+/// The default for [`UpdateOptions::concurrency`]: the number of CPUs, but no more than 8.
+pub fn default_concurrency() -> usize {
+    const MAX_DEFAULT_CONCURRENCY: usize = 8;
+
+    std::thread::available_parallelism()
+        .map_or(1, std::num::NonZeroUsize::get)
+        .min(MAX_DEFAULT_CONCURRENCY)
+}
+
+/// See [`UpdateOptions::on_file_done`]
+pub type FileDoneCallback<'a> = &'a (dyn Fn(&Path, &FileOutcome) + Sync);
+
+/// A summary of [`VideoHashFilesystemCache::update_using_fs`][super::VideoHashFilesystemCache::update_using_fs].
+///
+/// Only files that were attempted appear in the report, so if the update was cancelled, any
+/// file not mentioned here was never looked at.
+#[derive(Debug, Default)]
+pub struct UpdateReport {
+    /// Files which were new or modified, and were successfully hashed.
+    pub hashed: Vec<PathBuf>,
+    /// The number of files which were unchanged since they were last cached.
+    pub skipped_cached: usize,
+    /// Files which could not be hashed, or for which the cache could not be updated.
+    pub failed: Vec<(PathBuf, VdfCacheError)>,
+    /// Files which are too short to be hashed. They are not counted as failures.
+    pub too_short: Vec<PathBuf>,
+    /// Files which no longer exist, and were removed from the cache.
+    pub removed: Vec<PathBuf>,
+    /// True if the update stopped early because it was cancelled.
+    pub cancelled: bool,
+    /// If the update stopped early because the video backend cannot be used, the reason why. Every
+    /// file would have failed in the same way, so they are not listed in `failed`.
+    pub aborted: Option<VdfCacheError>,
+}
+
+impl UpdateReport {
+    /// The number of files which were attempted.
+    pub fn num_attempted(&self) -> usize {
+        self.hashed.len()
+            + self.skipped_cached
+            + self.failed.len()
+            + self.too_short.len()
+            + self.removed.len()
+    }
+
+    /// A one-line human readable summary.
+    pub fn summary(&self) -> String {
+        let mut ret = format!(
+            "{} files checked: {} hashed, {} unchanged, {} failed, {} too short, {} removed",
+            self.num_attempted(),
+            self.hashed.len(),
+            self.skipped_cached,
+            self.failed.len(),
+            self.too_short.len(),
+            self.removed.len()
+        );
+        if self.cancelled {
+            ret.push_str(" (cancelled before all files were processed)");
+        }
+        if let Some(e) = &self.aborted {
+            ret.push_str(&format!(" (stopped early: {e})"));
+        }
+        ret
+    }
+
+    fn record(&mut self, path: PathBuf, outcome: FileOutcome) {
+        match outcome {
+            FileOutcome::Hashed => self.hashed.push(path),
+            FileOutcome::Cached => self.skipped_cached += 1,
+            FileOutcome::Failed(e) => self.failed.push((path, e)),
+            FileOutcome::TooShort => self.too_short.push(path),
+            FileOutcome::Removed => self.removed.push(path),
+            //files which were already being hashed in parallel fail in the same way.
+            FileOutcome::Aborted(e) => {
+                self.aborted.get_or_insert(e);
+            }
+        }
+    }
+}
+
+/// Run update_one over each path, collecting the outcomes into a report. If update_one panics, the
+/// panic is reported as that file's failure, and the other files carry on.
+pub(crate) fn run_update(
+    paths: impl Iterator<Item = PathBuf> + Send,
+    opts: UpdateOptions,
+    update_one: impl Fn(&Path) -> FileOutcome + Sync,
+) -> UpdateReport {
+    let report = Mutex::new(UpdateReport::default());
+    let aborted = AtomicBool::new(false);
+    let is_cancelled = || {
+        opts.cancellation
+            .is_some_and(CancellationToken::is_cancelled)
+    };
+
+    let process = |path: PathBuf| {
+        //checked immediately before starting each file, so that a file is either fully processed
+        //and recorded, or never touched.
+        if is_cancelled() || aborted.load(Ordering::SeqCst) {
+            return;
+        }
+
+        //nothing is cached for a file until it has been hashed, so a panic leaves the cache as it
+        //was, and the file is tried again by the next update.
+        let outcome = panic::catch_unwind(AssertUnwindSafe(|| update_one(&path)))
+            .unwrap_or_else(|payload| FileOutcome::Failed(panicked(&path, payload.as_ref())));
+        if matches!(outcome, FileOutcome::Aborted(_)) {
+            aborted.store(true, Ordering::SeqCst);
+        }
+        if let Some(on_file_done) = opts.on_file_done {
+            on_file_done(&path, &outcome);
+        }
+        report.lock().record(path, outcome);
+    };
+
+    #[cfg(feature = "parallel_loading")]
+    {
+        let num_threads = opts.concurrency.unwrap_or_else(default_concurrency).max(1);
+        match rayon::ThreadPoolBuilder::new()
+            .num_threads(num_threads)
+            .thread_name(|i| format!("vdf-update-{i}"))
+            .build()
+        {
+            Ok(pool) => pool.install(|| paths.par_bridge().for_each(process)),
+            Err(e) => {
+                warn!("Failed to start a thread pool for the cache update, so files will be hashed one at a time: {e}");
+                paths.for_each(process);
+            }
+        }
+    }
+
+    #[cfg(not(feature = "parallel_loading"))]
+    paths.for_each(process);
+
+    let mut report = report.into_inner();
+    report.cancelled = is_cancelled();
+
+    //files complete in a nondeterministic order when loading in parallel.
+    report.hashed.sort();
+    report.failed.sort_by(|(p1, _), (p2, _)| p1.cmp(p2));
+    report.too_short.sort();
+    report.removed.sort();
+    report
+}
+
+pub(crate) fn panicked(path: &Path, payload: &(dyn std::any::Any + Send)) -> VdfCacheError {
+    let msg = format!(
+        "Panicked while hashing {}: {}",
+        path.display(),
+        panic_message(payload)
+    );
+    VdfCacheError::CreateHashError(Error::VidProc(msg))
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::AtomicUsize;
+
+    use super::*;
+
+    fn paths(n: usize) -> impl Iterator<Item = PathBuf> + Send {
+        (0..n).map(|i| PathBuf::from(format!("{i:03}")))
+    }
+
+    // path 0 is hashed, 1 is cached, 2 fails, 3 is removed, 4 is too short, and so on.
+    fn fake_update(path: &Path) -> FileOutcome {
+        let i: usize = path
+            .to_string_lossy()
+            .parse()
+            .expect("test paths are numbers");
+        match i % 5 {
+            0 => FileOutcome::Hashed,
+            1 => FileOutcome::Cached,
+            2 => FileOutcome::Failed(VdfCacheError::MetadataValidationError("bad".to_string())),
+            3 => FileOutcome::Removed,
+            _ => FileOutcome::TooShort,
+        }
+    }
+
+    #[test]
+    fn test_cache_update_report() {
+        use crate::video_hash_filesystem_cache::VideoHashFilesystemCache;
+        use vid_dup_finder_lib::Cropdetect;
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let dir = temp_dir.path();
+
+        //a file which is not a video, so hashing it will always fail.
+        let not_a_video = dir.join("not_a_video.txt");
+        std::fs::write(&not_a_video, "hello").unwrap();
+
+        let cache =
+            VideoHashFilesystemCache::new(100, dir.join("cache.bin"), Cropdetect::None, 0.0, 10.0)
+                .unwrap();
+        let update = || cache.update_using_fs([not_a_video.clone()], UpdateOptions::default());
+
+        let report = update();
+        assert_eq!(report.failed.len(), 1);
+        assert_eq!(report.failed[0].0, not_a_video);
+        assert_eq!(report.num_attempted(), 1);
+
+        //unchanged, so not attempted again.
+        let report = update();
+        assert_eq!(report.skipped_cached, 1);
+        assert_eq!(report.num_attempted(), 1);
+
+        std::fs::remove_file(&not_a_video).unwrap();
+        let report = update();
+        assert_eq!(report.removed, [not_a_video]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_non_utf8_filenames_are_cached() {
+        use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+        use crate::video_hash_filesystem_cache::VideoHashFilesystemCache;
+        use vid_dup_finder_lib::Cropdetect;
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let dir = temp_dir.path();
+
+        //latin-1 encoded, as written by some old NAS boxes.
+        let odd_path = dir.join(OsStr::from_bytes(b"\xe9t\xe9.mp4"));
+        std::fs::write(&odd_path, "not a video").unwrap();
+
+        let new_cache = || {
+            VideoHashFilesystemCache::new(100, dir.join("cache.bin"), Cropdetect::None, 0.0, 10.0)
+                .unwrap()
+        };
+
+        let cache = new_cache();
+        let report = cache.update_using_fs([odd_path.clone()], UpdateOptions::default());
+        assert_eq!(report.failed.len(), 1);
+        assert_eq!(report.failed[0].0, odd_path);
+        cache.save().unwrap();
+
+        //the original bytes of the path are still there after a round trip through the disk.
+        let cache = new_cache();
+        assert_eq!(cache.error_paths(), std::slice::from_ref(&odd_path));
+        let report = cache.update_using_fs([odd_path], UpdateOptions::default());
+        assert_eq!(report.skipped_cached, 1);
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_async_update_streams_every_file() {
+        use std::sync::Arc;
+
+        use futures_util::StreamExt;
+
+        use crate::video_hash_filesystem_cache::VideoHashFilesystemCache;
+        use vid_dup_finder_lib::Cropdetect;
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let dir = temp_dir.path();
+
+        //files which are not videos, so hashing them will always fail.
+        let not_videos = (0..10)
+            .map(|i| {
+                let path = dir.join(format!("not_a_video_{i}.txt"));
+                std::fs::write(&path, "hello").unwrap();
+                path
+            })
+            .collect::<Vec<_>>();
+        let new_cache = |name: &str| {
+            let cache =
+                VideoHashFilesystemCache::new(100, dir.join(name), Cropdetect::None, 0.0, 10.0);
+            Arc::new(cache.unwrap())
+        };
+
+        //driven to completion, every file is reported once.
+        let cache = new_cache("complete.bin");
+        let mut outcomes = cache
+            .update_using_fs_async(not_videos.clone(), Some(4))
+            .collect::<Vec<_>>()
+            .await;
+        outcomes.sort_by(|(p1, _), (p2, _)| p1.cmp(p2));
+        assert_eq!(
+            outcomes.iter().map(|(path, _)| path).collect::<Vec<_>>(),
+            not_videos.iter().collect::<Vec<_>>()
+        );
+        assert!(outcomes
+            .iter()
+            .all(|(_, outcome)| matches!(outcome, FileOutcome::Failed(_))));
+
+        //dropped after the first file, no more files are started.
+        let cache = new_cache("dropped.bin");
+        let mut stream = Box::pin(cache.update_using_fs_async(not_videos.clone(), Some(1)));
+        let (first, _outcome) = stream.next().await.unwrap();
+        drop(stream);
+
+        let report = cache.update_using_fs(not_videos, UpdateOptions::default());
+        assert_eq!(report.skipped_cached, 1);
+        assert_eq!(report.failed.len(), 9);
+        assert!(report.failed.iter().all(|(path, _)| *path != first));
+    }
+
+    #[test]
+    fn test_report_counts_every_file() {
+        let num_callbacks = AtomicUsize::new(0);
+        let on_file_done = |_path: &Path, _outcome: &FileOutcome| {
+            num_callbacks.fetch_add(1, Ordering::SeqCst);
+        };
+        let opts = UpdateOptions {
+            on_file_done: Some(&on_file_done),
+            ..UpdateOptions::default()
+        };
+
+        let report = run_update(paths(10), opts, fake_update);
+
+        assert!(!report.cancelled);
+        assert_eq!(report.num_attempted(), 10);
+        assert_eq!(num_callbacks.load(Ordering::SeqCst), 10);
+        assert_eq!(report.hashed, [PathBuf::from("000"), "005".into()]);
+        assert_eq!(report.skipped_cached, 2);
+        assert_eq!(report.failed.len(), 2);
+        assert_eq!(report.removed, [PathBuf::from("003"), "008".into()]);
+        assert_eq!(report.too_short, [PathBuf::from("004"), "009".into()]);
+    }
+
+    #[test]
+    fn test_cancelled_files_are_not_reported() {
+        let token = CancellationToken::new();
+        let attempted = Mutex::new(vec![]);
+
+        //cancel part way through the update.
+        let update_one = |path: &Path| {
+            let mut attempted = attempted.lock();
+            attempted.push(path.to_path_buf());
+            if attempted.len() == 5 {
+                token.cancel();
+            }
+            fake_update(path)
+        };
+        let opts = UpdateOptions {
+            cancellation: Some(&token),
+            ..UpdateOptions::default()
+        };
+
+        let report = run_update(paths(100), opts, update_one);
+        let attempted = attempted.into_inner();
+
+        assert!(report.cancelled);
+        assert_eq!(report.num_attempted(), attempted.len());
+        assert!(attempted.len() < 100);
+
+        let reported = report
+            .hashed
+            .iter()
+            .chain(report.failed.iter().map(|(path, _)| path))
+            .chain(report.too_short.iter())
+            .chain(report.removed.iter());
+        for path in reported {
+            assert!(
+                attempted.contains(path),
+                "{} was never attempted",
+                path.display()
+            );
+        }
+
+        //Nothing is done if cancelled before starting.
+        let report = run_update(paths(100), opts, fake_update);
+        assert!(report.cancelled);
+        assert_eq!(report.num_attempted(), 0);
+    }
+
+    #[test]
+    fn test_update_stops_when_backend_is_missing() {
+        use vid_dup_finder_lib::Error;
+
+        let num_attempted = AtomicUsize::new(0);
+        let update_one = |path: &Path| {
+            num_attempted.fetch_add(1, Ordering::SeqCst);
+            let missing = Error::FfmpegMissing(vec!["ffmpeg".to_string()]);
+            if path.ends_with("005") {
+                FileOutcome::Aborted(VdfCacheError::from(missing))
+            } else {
+                fake_update(path)
+            }
+        };
+
+        for concurrency in [1, 4] {
+            num_attempted.store(0, Ordering::SeqCst);
+            let opts = UpdateOptions {
+                concurrency: Some(concurrency),
+                ..UpdateOptions::default()
+            };
+            let report = run_update(paths(1000), opts, update_one);
+
+            //only files which were already started when the backend was found missing finish.
+            assert!(report.aborted.is_some());
+            assert!(num_attempted.load(Ordering::SeqCst) < 1000);
+            assert_eq!(
+                report.num_attempted() + 1,
+                num_attempted.load(Ordering::SeqCst)
+            );
+            assert!(report.summary().contains("ffmpeg could not be run"));
+        }
+    }
+
+    #[test]
+    fn test_panics_are_reported_as_file_errors() {
+        let update_one = |path: &Path| {
+            if path.ends_with("003") {
+                panic!("resize failed");
+            }
+            FileOutcome::Hashed
+        };
+
+        for concurrency in [1, 4] {
+            let opts = UpdateOptions {
+                concurrency: Some(concurrency),
+                ..UpdateOptions::default()
+            };
+            let report = run_update(paths(10), opts, update_one);
+
+            assert_eq!(report.num_attempted(), 10);
+            assert_eq!(report.hashed.len(), 9);
+            assert!(!report.hashed.contains(&PathBuf::from("003")));
+            let [(path, VdfCacheError::CreateHashError(Error::VidProc(msg)))] = &report.failed[..]
+            else {
+                panic!("expected one hashing error, got {:?}", report.failed);
+            };
+            assert_eq!(path, Path::new("003"));
+            assert!(msg.contains("003"), "{msg}");
+            assert!(msg.contains("resize failed"), "{msg}");
+        }
+    }
+
+    #[test]
+    fn test_concurrency_is_capped() {
+        let in_flight = AtomicUsize::new(0);
+        let high_water_mark = AtomicUsize::new(0);
+
+        let update_one = |path: &Path| {
+            let now = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            high_water_mark.fetch_max(now, Ordering::SeqCst);
+            std::thread::sleep(std::time::Duration::from_millis(5));
+            fake_update(path)
+        };
+        let on_file_done = |_path: &Path, _outcome: &FileOutcome| {
+            in_flight.fetch_sub(1, Ordering::SeqCst);
+        };
+
+        for concurrency in [1, 2, 3] {
+            high_water_mark.store(0, Ordering::SeqCst);
+            let opts = UpdateOptions {
+                on_file_done: Some(&on_file_done),
+                concurrency: Some(concurrency),
+                ..UpdateOptions::default()
+            };
+
+            let report = run_update(paths(40), opts, update_one);
+            assert_eq!(report.num_attempted(), 40);
+            assert!(high_water_mark.load(Ordering::SeqCst) <= concurrency);
+        }
+
+        assert!((1..=8).contains(&default_concurrency()));
+    }
+}
@@ -1,14 +1,28 @@
-use std::path::{Path, PathBuf};
+use std::{
+    io::{BufRead, Write},
+    path::{Path, PathBuf},
+};
 
 use super::generic_filesystem_cache::*;
 
 use super::{cache_metadata::VdfCacheMetadata, *};
+use image::RgbImage;
 use itertools::Itertools;
-#[cfg(feature = "parallel_loading")]
-use rayon::prelude::*;
-use vid_dup_finder_lib::{Cropdetect, Error, VideoHash};
+use parking_lot::RwLock;
+use uuid::Uuid;
+use vid_dup_finder_lib::{
+    Coverage, CreationOptions, Crop, Cropdetect, Deinterlace, EnvironmentDigest, Error, HashSize,
+    MatchGroup, Normalization, SpeedFactors, ThumbSpec, TwoPassTolerances, VideoHash,
+    DEFAULT_MIN_MOTION_CROP_CONFIDENCE, DEFAULT_VID_HASH_MAX_MEASURED_DURATION,
+    DEFAULT_VID_HASH_MIN_DURATION,
+};
 
-use super::generic_cache_if::GenericCacheIf;
+use super::cache_stats::CacheStatsReport;
+use super::generic_cache_if::{CachedHash, GenericCacheIf};
+#[cfg(feature = "async")]
+use super::update_report::{default_concurrency, panicked};
+use super::update_report::{run_update, FileOutcome, UpdateOptions, UpdateReport};
+use super::video_ids::VideoIds;
 
 /// A disk-backed cache for hashes of videos on the filesystem.
 /// This is a utility struct for long term storage of [VideoHashes][vid_dup_finder_lib::VideoHash].
@@ -25,10 +39,96 @@ use super::generic_cache_if::GenericCacheIf;
 ///
 /// To update all hashes within a given directory (or set of directories) use [update_using_fs][`VideoHashFilesystemCache::update_using_fs`]
 ///
+/// # Storage
+/// The cache is stored either as a single bincode file, which is rewritten on every save, or as a
+/// sqlite database, which is updated as each entry changes (see [`CacheFormat`]). Both behave the
+/// same way. An existing bincode cache can be converted with
+/// [migrate_to_sqlite][`VideoHashFilesystemCache::migrate_to_sqlite`].
+///
+/// Bincode caches store each directory once, and each hash only in as many bytes as its size needs.
+/// With the `zstd` feature (on by default) they are also compressed. Caches written by earlier
+/// versions are still read, and are rewritten in the new format when they are next saved.
+///
+/// # Combining caches
+/// Hashes are exchanged with other caches (for instance, one on another machine) and with other
+/// programs as one JSON hash per line (see [`vid_dup_finder_lib::portable_hash`]), with
+/// [write_portable_hashes][`VideoHashFilesystemCache::write_portable_hashes`] and
+/// [read_portable_hashes][`VideoHashFilesystemCache::read_portable_hashes`]. Hashes which are read
+/// are labelled with a collection, and can be searched together with the hashes of the cache, using
+/// [`vid_dup_finder_lib::CollectionMode::CrossCollectionOnly`] to find only the duplicates between
+/// the caches.
+///
+/// # Video IDs
+/// With [with_video_ids][`VideoHashFilesystemCache::with_video_ids`], each video is also given a
+/// random UUID which stays the same when the video is moved, so that other programs can recognise
+/// it whatever its path (see [id_for_path][`VideoHashFilesystemCache::id_for_path`]). The IDs are
+/// kept in a file beside the cache (`cache.bin` has `cache.ids.jsonl`), and follow the entries moved
+/// by [rename_prefix][`VideoHashFilesystemCache::rename_prefix`].
+///
+/// # Decoders
+/// Each hash records the decoders that created it (see [`VideoHash::environment`]), and the
+/// metadata file records the decoders in use when it was written. Hashes made by other versions of
+/// the decoders still match, but may differ slightly from new hashes of the same videos, so
+/// [environment_changed][`VideoHashFilesystemCache::environment_changed`] reports when the
+/// decoders have changed since most of the cache was hashed, for callers to decide whether to hash
+/// it again.
+///
 /// # A note on interior mutability
 /// All methods on this struct and its [underlying implementation][generic_filesystem_cache::ProcessingFsCache] are use
 /// interior mutability allow for operations to occur in parallel.
-pub struct VideoHashFilesystemCache(ProcessingFsCache<GenericCacheIf>);
+pub struct VideoHashFilesystemCache(
+    ProcessingFsCache<GenericCacheIf>,
+    //Hashes from other caches, which are only held in memory.
+    RwLock<Vec<VideoHash>>,
+    //None unless IDs were asked for.
+    RwLock<Option<VideoIds>>,
+    Environments,
+);
+
+//The decoders recorded in the metadata file when the cache was opened, and those in use now.
+#[derive(Debug, Clone, Copy, Default)]
+struct Environments {
+    recorded: Option<EnvironmentDigest>,
+    current: Option<EnvironmentDigest>,
+}
+
+/// The decoders in use differ from those that created most of the cache, as reported by
+/// [environment_changed][`VideoHashFilesystemCache::environment_changed`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EnvChange {
+    /// The decoders that created the most cached hashes.
+    pub previous: EnvironmentDigest,
+    /// The decoders in use now.
+    pub current: EnvironmentDigest,
+    /// The number of cached hashes which were created by decoders other than the current ones.
+    pub affected: usize,
+    /// The number of cached hashes.
+    pub total: usize,
+}
+
+/// How thumbnails made by [thumbnails_with_hash][`VideoHashFilesystemCache::thumbnails_with_hash`]
+/// are cropped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThumbCrop {
+    /// They were not asked to be cropped.
+    Uncropped,
+    /// With the crop recorded in the hash of the video (see [`VideoHash::crop`]).
+    FromHash(Crop),
+    /// With a crop found again by decoding the frames of the hash, because there is no hash or it
+    /// did not record its crop.
+    Recomputed,
+}
+
+impl ThumbCrop {
+    /// How thumbnails of a video with `hash` are cropped, if `cropdetect` asks for them to be.
+    pub fn new(hash: Option<&VideoHash>, cropdetect: bool) -> Self {
+        match hash.and_then(VideoHash::crop) {
+            _ if !cropdetect => Self::Uncropped,
+            Some(crop) => Self::FromHash(crop),
+            None => Self::Recomputed,
+        }
+    }
+}
 
 impl VideoHashFilesystemCache {
     /// Load a VideoHash cache from disk the specified path. If no cache exists at cache_path
@@ -41,6 +141,7 @@ impl VideoHashFilesystemCache {
     /// call [save][`VideoHashFilesystemCache::save`] after you have made the last modification to the chache contents.
     ///
     /// Returns an error if it was not possible to load the cache or create a new one.
+    #[allow(unused)]
     pub fn new(
         cache_save_thresold: u32,
         cache_path: PathBuf,
@@ -48,55 +149,332 @@ impl VideoHashFilesystemCache {
         skip_forward_amount: f64,
         duration: f64,
     ) -> Result<Self, VdfCacheError> {
-        Self::validate_or_create_metadata_file(&cache_path, cropdetect, skip_forward_amount)?;
+        let default_opts = CreationOptions {
+            skip_forward_amount,
+            duration,
+            cropdetect,
+            hash_bits: HashSize::default(),
+            video_stream: None,
+            frame_hashes: false,
+            skip_leading_black: false,
+            reliability_mask: false,
+            min_duration: DEFAULT_VID_HASH_MIN_DURATION,
+            normalization: Normalization::None,
+            audio_fingerprint: false,
+            coverage: Coverage::Start,
+            min_motion_crop_confidence: DEFAULT_MIN_MOTION_CROP_CONFIDENCE,
+            ignore_pixel_aspect_ratio: false,
+            deinterlace: Deinterlace::Off,
+            speed_variants: SpeedFactors::NONE,
+            max_measured_duration: DEFAULT_VID_HASH_MAX_MEASURED_DURATION,
+        };
+        Self::with_path_options(cache_save_thresold, cache_path, default_opts, vec![])
+    }
+
+    /// As [new][`VideoHashFilesystemCache::new`], but videos are hashed with `default_opts`, except
+    /// for videos under each of the paths in `path_opts`, which are hashed with the accompanying
+    /// options instead. The paths must not overlap.
+    ///
+    /// Each entry remembers the options it was hashed with, so if a video's options change
+    /// between runs then it is hashed again.
+    ///
+    /// The format of the cache is chosen from the extension of `cache_path` with
+    /// [`CacheFormat::from_path`].
+    pub fn with_path_options(
+        cache_save_thresold: u32,
+        cache_path: PathBuf,
+        default_opts: CreationOptions,
+        path_opts: Vec<(PathBuf, CreationOptions)>,
+    ) -> Result<Self, VdfCacheError> {
+        let format = CacheFormat::from_path(&cache_path);
+
+        Self::with_format(
+            cache_save_thresold,
+            cache_path,
+            format,
+            default_opts,
+            path_opts,
+        )
+    }
+
+    /// As [with_path_options][`VideoHashFilesystemCache::with_path_options`], but the cache is
+    /// stored in the given format whatever its extension, and videos outside of `path_opts` are
+    /// hashed with `default_opts`.
+    ///
+    /// When `format` is [`CacheFormat::Sqlite`], `cache_save_threshold` has no effect because every
+    /// change is written as soon as it is made.
+    pub fn with_format(
+        cache_save_thresold: u32,
+        cache_path: PathBuf,
+        format: CacheFormat,
+        default_opts: CreationOptions,
+        path_opts: Vec<(PathBuf, CreationOptions)>,
+    ) -> Result<Self, VdfCacheError> {
+        default_opts.validate()?;
+        for (_path, opts) in &path_opts {
+            opts.validate()?;
+        }
+
+        let metadata = Self::validate_or_create_metadata_file(
+            &cache_path,
+            default_opts.cropdetect,
+            default_opts.skip_forward_amount,
+        )?;
+        let environments = Environments {
+            recorded: metadata.environment(),
+            current: vid_dup_finder_lib::environment_info()
+                .ok()
+                .map(|info| info.digest()),
+        };
+
+        let interface = GenericCacheIf::new(default_opts, path_opts);
+        let ret =
+            ProcessingFsCache::new(cache_save_thresold, cache_path.clone(), format, interface)?;
+
+        Ok(Self(
+            ret,
+            RwLock::default(),
+            RwLock::default(),
+            environments,
+        ))
+    }
+
+    /// As [with_path_options][`VideoHashFilesystemCache::with_path_options`] without any path
+    /// options, but only opens a cache which already exists, for maintaining it without hashing
+    /// anything. If IDs are saved beside the cache, they are loaded as with
+    /// [with_video_ids][`VideoHashFilesystemCache::with_video_ids`], so that they follow any changes.
+    ///
+    /// Returns [`VdfCacheError::NoCache`] if there is no cache at `cache_path`.
+    pub fn open_existing(
+        cache_save_thresold: u32,
+        cache_path: PathBuf,
+        default_opts: CreationOptions,
+    ) -> Result<Self, VdfCacheError> {
+        if !cache_path.exists() {
+            return Err(VdfCacheError::NoCache(cache_path));
+        }
+        let has_ids = VideoIds::sidecar_path(&cache_path).exists();
+        let cache = Self::with_path_options(cache_save_thresold, cache_path, default_opts, vec![])?;
+        if has_ids {
+            cache.with_video_ids()
+        } else {
+            Ok(cache)
+        }
+    }
+
+    /// Keep a stable ID for each video (see [Video IDs](#video-ids)), loading the IDs saved beside
+    /// the cache if there are any.
+    ///
+    /// Entries hashed before IDs were kept are given one when they are next visited, or asked for
+    /// with [id_for_path][`VideoHashFilesystemCache::id_for_path`].
+    ///
+    /// Returns an error if the saved IDs could not be read.
+    pub fn with_video_ids(self) -> Result<Self, VdfCacheError> {
+        let ids = VideoIds::load(self.0.cache_path())?;
+        *self.2.write() = Some(ids);
+        Ok(self)
+    }
+
+    /// The ID of the video at `path`. A cached video which has no ID yet is given one.
+    ///
+    /// Returns None if IDs are not being kept, or if the video has no ID and is not in the cache.
+    pub fn id_for_path(&self, path: impl AsRef<Path>) -> Option<Uuid> {
+        let path = path.as_ref();
+        if let Some(id) = self.2.read().as_ref()?.id_for_path(path) {
+            return Some(id);
+        }
+        self.fetch(path).is_ok().then(|| self.assign_id(path))?
+    }
+
+    /// The path of the video with the given ID. This is where the video was when it was last seen
+    /// by the cache, which may have been since deleted.
+    ///
+    /// Returns None if IDs are not being kept, or if no video has the ID.
+    pub fn path_for_id(&self, id: Uuid) -> Option<PathBuf> {
+        self.2
+            .read()
+            .as_ref()?
+            .path_for_id(id)
+            .map(Path::to_path_buf)
+    }
+
+    /// Give the video at `new` the ID of the video that was at `old`, for instance when a moved
+    /// video has been hashed again at its new path before the move was noticed. Any ID that `new`
+    /// had is forgotten.
+    ///
+    /// Returns false (changing nothing) if IDs are not being kept, or if `old` has no ID.
+    pub fn transfer_id(&self, old: &Path, new: &Path) -> bool {
+        self.2
+            .write()
+            .as_mut()
+            .is_some_and(|ids| ids.transfer(old, new))
+    }
+
+    // Give a video an ID if it has none, when IDs are being kept.
+    fn assign_id(&self, path: &Path) -> Option<Uuid> {
+        self.2.write().as_mut().map(|ids| ids.assign(path))
+    }
+
+    /// Copy every entry of the bincode cache at `bincode_path` into the sqlite cache at
+    /// `sqlite_path`, along with its metadata. The bincode cache is left as it is.
+    ///
+    /// Returns the number of entries copied, or an error if there is no cache at `bincode_path`.
+    pub fn migrate_to_sqlite(
+        bincode_path: impl AsRef<Path>,
+        sqlite_path: impl AsRef<Path>,
+    ) -> Result<usize, VdfCacheError> {
+        let (bincode_path, sqlite_path) = (bincode_path.as_ref(), sqlite_path.as_ref());
+
+        let count = ProcessingFsCache::<GenericCacheIf>::migrate_to_sqlite(
+            bincode_path.to_path_buf(),
+            sqlite_path,
+        )?;
+
+        let src_metadata = Self::metadata_path(bincode_path)?;
+        let dest_metadata = Self::metadata_path(sqlite_path)?;
+        if src_metadata != dest_metadata {
+            std::fs::copy(&src_metadata, &dest_metadata).map_err(|e| {
+                VdfCacheError::CacheErrror(FsCacheErrorKind::CacheFileIo {
+                    src: e,
+                    path: src_metadata.clone(),
+                })
+            })?;
+        }
+
+        Ok(count)
+    }
+
+    /// The path of the cache which holds hashes created with `opts`, beside the cache at
+    /// `cache_path`. The options are written into the file name as a short key
+    /// (`cache.bin` becomes `cache.<key>.bin`), so the hashes of each set of options are kept
+    /// side by side, each with their own metadata.
+    pub fn options_cache_path(cache_path: &Path, opts: &CreationOptions) -> PathBuf {
+        let opts = bincode::serialize(opts).expect("options can always be serialized");
+        let key = blake3::hash(&opts).to_hex();
+        let key = &key[..16];
+        let stem = cache_path.file_stem().unwrap_or_default().to_string_lossy();
+        let file_name = match cache_path.extension() {
+            Some(ext) => format!("{stem}.{key}.{}", ext.to_string_lossy()),
+            None => format!("{stem}.{key}"),
+        };
+        cache_path.with_file_name(file_name)
+    }
+
+    /// Open (or create) the cache at [options_cache_path][`VideoHashFilesystemCache::options_cache_path`],
+    /// in which every video is hashed with `opts`.
+    pub fn for_options(
+        cache_save_thresold: u32,
+        cache_path: &Path,
+        opts: CreationOptions,
+    ) -> Result<Self, VdfCacheError> {
+        let path = Self::options_cache_path(cache_path, &opts);
+        let format = CacheFormat::from_path(&path);
+        Self::with_format(cache_save_thresold, path, format, opts, vec![])
+    }
+
+    /// Search `paths` with [`vid_dup_finder_lib::two_pass_search`]. The hashes of the first pass
+    /// are taken from this cache, whose videos must be hashed with `coarse_opts`, and the hashes
+    /// of the second from the cache [for `fine_opts`][`VideoHashFilesystemCache::for_options`]
+    /// beside it, so the hashes of both sets of options are kept side by side. Only new or
+    /// modified files are hashed, so searching the same files again creates no hashes, and files
+    /// which have already been hashed finely are not hashed again when they next land in a group.
+    ///
+    /// Each pass updates its cache with `update_opts`, so progress is reported for the files of
+    /// both passes, and the search stops (with [`Error::Cancelled`]) at the end of the pass in
+    /// which the token is cancelled. Both caches are saved before returning, even if the search
+    /// was cancelled.
+    pub fn two_pass_search(
+        &self,
+        cache_save_thresold: u32,
+        paths: Vec<PathBuf>,
+        coarse_opts: CreationOptions,
+        fine_opts: CreationOptions,
+        tolerances: TwoPassTolerances,
+        update_opts: UpdateOptions,
+    ) -> Result<Vec<MatchGroup>, VdfCacheError> {
+        //refining only by tolerance needs just the one cache.
+        let fine = (fine_opts != coarse_opts)
+            .then(|| Self::for_options(cache_save_thresold, self.0.cache_path(), fine_opts))
+            .transpose()?;
+        let cache_for = |opts: CreationOptions| match &fine {
+            Some(fine) if opts == fine_opts => fine,
+            _ => self,
+        };
+
+        let mut aborted = None;
+        let groups = vid_dup_finder_lib::two_pass_search(
+            paths,
+            coarse_opts,
+            fine_opts,
+            tolerances,
+            |paths, opts| {
+                let cache = cache_for(opts);
+                let report = cache.update_using_fs(paths.to_vec(), update_opts);
+                aborted = aborted.take().or(report.aborted);
+                paths
+                    .iter()
+                    .filter_map(|path| cache.fetch(path).ok())
+                    .collect()
+            },
+            update_opts.cancellation,
+        );
 
-        let interface = GenericCacheIf::new(skip_forward_amount, duration, cropdetect);
+        self.save()?;
+        if let Some(fine) = &fine {
+            fine.save()?;
+        }
+        match aborted {
+            Some(e) => Err(e),
+            None => Ok(groups?),
+        }
+    }
+
+    fn metadata_path(cache_path: &Path) -> Result<PathBuf, VdfCacheError> {
+        let cache_stem = cache_path
+            .file_stem()
+            .map(|x| x.to_string_lossy().to_string())
+            .ok_or_else(|| {
+                VdfCacheError::CacheErrror(FsCacheErrorKind::CacheFileIo {
+                    src: std::io::Error::from_raw_os_error(22),
+                    path: cache_path.to_path_buf(),
+                })
+            })?;
 
-        let ret = ProcessingFsCache::new(cache_save_thresold, cache_path, interface)?;
-        Ok(Self(ret))
+        Ok(cache_path.with_file_name(format!("{cache_stem}.metadata.txt")))
     }
 
     fn create_metadata_file(
         metadata_path: impl AsRef<Path>,
         cropdetect: Cropdetect,
         skip_forward_amount: f64,
-    ) -> Result<(), VdfCacheError> {
-        let content = VdfCacheMetadata::new(cropdetect, skip_forward_amount).to_disk_fmt();
+    ) -> Result<VdfCacheMetadata, VdfCacheError> {
+        let metadata = VdfCacheMetadata::new(cropdetect, skip_forward_amount);
 
-        std::fs::write(metadata_path.as_ref(), content).map_err(|e| {
+        std::fs::write(metadata_path.as_ref(), metadata.to_disk_fmt()).map_err(|e| {
             VdfCacheError::CacheErrror(FsCacheErrorKind::CacheFileIo {
                 src: e,
                 path: metadata_path.as_ref().to_path_buf(),
             })
         })?;
 
-        Ok(())
+        Ok(metadata)
     }
 
+    // Returns the metadata as it was before the cache was opened (or as it was created, for a new
+    // cache).
     fn validate_or_create_metadata_file(
         cache_path: impl AsRef<Path>,
         cropdetect: Cropdetect,
         skip_forward_amount: f64,
-    ) -> Result<(), VdfCacheError> {
+    ) -> Result<VdfCacheMetadata, VdfCacheError> {
         let cache_path = cache_path.as_ref();
         let cache_exists = cache_path.exists();
 
-        let cache_stem = &cache_path
-            .file_stem()
-            .map(|x| x.to_string_lossy().to_string())
-            .ok_or_else(|| {
-                VdfCacheError::CacheErrror(FsCacheErrorKind::CacheFileIo {
-                    src: std::io::Error::from_raw_os_error(22),
-                    path: cache_path.to_path_buf(),
-                })
-            })?;
-
-        let metadata_path = &cache_path.with_file_name(format!("{cache_stem}.metadata.txt"));
+        let metadata_path = &Self::metadata_path(cache_path)?;
         let metadata_exists = metadata_path.exists();
 
         if !cache_exists {
-            Self::create_metadata_file(metadata_path, cropdetect, skip_forward_amount)?;
-            return Ok(());
+            return Self::create_metadata_file(metadata_path, cropdetect, skip_forward_amount);
         }
 
         if cache_exists && !metadata_exists {
@@ -107,8 +485,7 @@ impl VideoHashFilesystemCache {
         };
 
         if !metadata_exists {
-            Self::create_metadata_file(metadata_path, cropdetect, skip_forward_amount)?;
-            return Ok(());
+            return Self::create_metadata_file(metadata_path, cropdetect, skip_forward_amount);
         }
 
         let content = std::fs::read_to_string(metadata_path).map_err(|e| {
@@ -122,10 +499,20 @@ impl VideoHashFilesystemCache {
             .map_err(VdfCacheError::MetadataValidationError)?;
 
         act_metadata
-            .validate(cropdetect, skip_forward_amount)
+            .validate()
             .map_err(VdfCacheError::MetadataValidationError)?;
 
-        Ok(())
+        //Every entry remembers its own options, so entries hashed with other options are hashed
+        //again as they are visited rather than the whole cache being rejected.
+        if !act_metadata.has_options(cropdetect, skip_forward_amount) {
+            info!(
+                "Hashing options have changed since {} was created. Affected videos will be hashed again",
+                cache_path.display()
+            );
+            Self::create_metadata_file(metadata_path, cropdetect, skip_forward_amount)?;
+        }
+
+        Ok(act_metadata)
     }
 
     /// Fetch the hash for the video file at the given source path. If the cache does not already contain a hash
@@ -134,7 +521,7 @@ impl VideoHashFilesystemCache {
     /// Returns an error if the cache has no entry for `src_path` .
     #[inline]
     pub fn fetch(&self, src_path: impl AsRef<Path>) -> Result<VideoHash, VdfCacheError> {
-        self.fetch_entry(src_path).map_err(VdfCacheError::from)
+        self.fetch_entry(src_path)
     }
 
     /// Get the paths of all [VideoHashes][VideoHash] stored in the cache.
@@ -147,25 +534,325 @@ impl VideoHashFilesystemCache {
             .collect()
     }
 
+    /// Write every hash in the cache, including any added by
+    /// [read_portable_hashes][`VideoHashFilesystemCache::read_portable_hashes`], to `w` as portable
+    /// JSON, one hash per line.
+    ///
+    /// Returns the number of hashes written, or an error if it was not possible to write to `w`.
+    pub fn write_portable_hashes(&self, w: impl Write) -> Result<usize, VdfCacheError> {
+        let mut w = std::io::BufWriter::new(w);
+        let hashes = self
+            .0
+            .keys()
+            .into_iter()
+            .filter_map(|src_path| self.fetch(src_path).ok())
+            .chain(self.imported_hashes())
+            .collect::<Vec<_>>();
+        for hash in &hashes {
+            writeln!(w, "{}", hash.to_json_portable())?;
+        }
+        w.flush()?;
+
+        Ok(hashes.len())
+    }
+
+    /// Read hashes written by [write_portable_hashes][`VideoHashFilesystemCache::write_portable_hashes`]
+    /// (or by another program), labelled with the given collection (see
+    /// [`VideoHash::with_collection`]). Blank lines are skipped.
+    ///
+    /// The hashes are only held in memory (see
+    /// [imported_hashes][`VideoHashFilesystemCache::imported_hashes`]): they are never saved, are not
+    /// returned by [fetch][`VideoHashFilesystemCache::fetch`], and are not affected by
+    /// [update_using_fs][`VideoHashFilesystemCache::update_using_fs`].
+    ///
+    /// Returns the number of hashes read. If any line is not a valid hash then nothing is added,
+    /// and the error gives the number of the first bad line.
+    pub fn read_portable_hashes(
+        &self,
+        r: impl BufRead,
+        collection_label: &str,
+    ) -> Result<usize, VdfCacheError> {
+        let hashes = Self::parse_portable_hashes(r)?;
+        let num_hashes = hashes.len();
+        self.1.write().extend(
+            hashes
+                .into_iter()
+                .map(|hash| hash.with_collection(collection_label)),
+        );
+        Ok(num_hashes)
+    }
+
+    /// Read hashes written by [write_portable_hashes][`VideoHashFilesystemCache::write_portable_hashes`],
+    /// one per line, skipping blank lines.
+    ///
+    /// Returns an error giving the number of the first line which is not a valid hash.
+    pub fn parse_portable_hashes(r: impl BufRead) -> Result<Vec<VideoHash>, VdfCacheError> {
+        let mut hashes = vec![];
+        for (idx, line) in r.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let hash = VideoHash::from_json_portable(&line).map_err(|source| {
+                VdfCacheError::PortableHashError {
+                    line: idx + 1,
+                    source,
+                }
+            })?;
+            hashes.push(hash);
+        }
+
+        Ok(hashes)
+    }
+
+    /// What [import_entry][`VideoHashFilesystemCache::import_entry`] would do with `hash`.
+    pub fn import_action(&self, hash: &VideoHash) -> ImportAction {
+        let src_path = hash.src_path();
+        if !src_path.exists() {
+            return ImportAction::Missing;
+        }
+        match self.0.fetch(src_path) {
+            Ok(entry) if entry.hash.as_ref().ok() == Some(hash) => ImportAction::Unchanged,
+            Ok(_) => ImportAction::Replace,
+            Err(_) => ImportAction::Add,
+        }
+    }
+
+    /// Save a hash created elsewhere (usually read with
+    /// [parse_portable_hashes][`VideoHashFilesystemCache::parse_portable_hashes`]) as the entry of
+    /// its video, as if the video had just been hashed with the options the cache hashes it with.
+    /// Unlike [read_portable_hashes][`VideoHashFilesystemCache::read_portable_hashes`], the entry is saved, and
+    /// is hashed again once the video is modified.
+    ///
+    /// Hashes of videos which are not on this filesystem are skipped. Returns what was done (see
+    /// [import_action][`VideoHashFilesystemCache::import_action`]).
+    pub fn import_entry(&self, hash: VideoHash) -> Result<ImportAction, VdfCacheError> {
+        let action = self.import_action(&hash);
+        if action.changes_cache() {
+            let src_path = hash.src_path().to_path_buf();
+            let entry = CachedHash {
+                opts: self.0.interface().opts_for(&src_path),
+                hash: Ok(hash),
+                stats: None,
+            };
+            self.0.insert_loaded(&src_path, entry)?;
+            self.assign_id(&src_path);
+        }
+        Ok(action)
+    }
+
+    /// The hashes added by [read_portable_hashes][`VideoHashFilesystemCache::read_portable_hashes`].
+    pub fn imported_hashes(&self) -> Vec<VideoHash> {
+        self.1.read().clone()
+    }
+
+    /// Paths which could not be hashed, and might be hashed if they were tried again. Files which
+    /// are too short to hash are left out, because trying again would only fail in the same way.
     pub fn error_paths(&self) -> Vec<PathBuf> {
         self.0
             .keys()
             .into_iter()
-            .filter(|src_path| self.fetch(src_path).is_err())
+            .filter(|src_path| match self.fetch(src_path) {
+                Err(VdfCacheError::CreateHashError(Error::TooShort { .. })) => false,
+                Err(_) => true,
+                Ok(_) => false,
+            })
+            .collect()
+    }
+
+    /// Paths whose cached entries were created with options other than `opts`. These entries are
+    /// hashed again when they are next visited by [fetch_update][`VideoHashFilesystemCache::fetch_update`]
+    /// or [update_using_fs][`VideoHashFilesystemCache::update_using_fs`], so long as `opts` are the
+    /// options the cache hashes them with.
+    pub fn options_mismatch_paths(&self, opts: &CreationOptions) -> Vec<PathBuf> {
+        self.0
+            .keys()
+            .into_iter()
+            .filter(|src_path| {
+                self.0
+                    .fetch(src_path)
+                    .is_ok_and(|entry| entry.opts != *opts)
+            })
+            .collect()
+    }
+
+    /// Thumbnails of the video at `src_path` (see [`vid_dup_finder_lib::thumbnails`]), taken from
+    /// the frames that its cached hash was created from and, if `spec` asks for it, cropped as that
+    /// hash was. Videos without a cached entry are treated as if they were hashed with the options
+    /// the cache would hash them with.
+    pub fn thumbnails(&self, src_path: &Path, spec: &ThumbSpec) -> Result<Vec<RgbImage>, Error> {
+        let hash = self.fetch(src_path).ok();
+        self.thumbnails_with_hash(src_path, hash.as_ref(), spec)
+    }
+
+    /// As [thumbnails][`VideoHashFilesystemCache::thumbnails`], but with the hash of the video
+    /// given, such as one fetched when its match group was made. The thumbnails are cropped as
+    /// [`ThumbCrop::new`] says, so the crop is only found again if the hash did not record it.
+    pub fn thumbnails_with_hash(
+        &self,
+        src_path: &Path,
+        hash: Option<&VideoHash>,
+        spec: &ThumbSpec,
+    ) -> Result<Vec<RgbImage>, Error> {
+        let opts = match self.0.fetch(src_path) {
+            Ok(entry) => entry.opts,
+            Err(_) => self.0.interface().opts_for(src_path),
+        };
+        let crop = match ThumbCrop::new(hash, spec.cropdetect) {
+            ThumbCrop::FromHash(crop) => Some(crop),
+            ThumbCrop::Uncropped | ThumbCrop::Recomputed => None,
+        };
+        vid_dup_finder_lib::thumbnails_with_crop(src_path, spec, opts, crop)
+    }
+
+    /// If the decoders in use differ from those that created the most cached hashes, how many
+    /// hashes were created by other decoders. Hashes which do not record their decoders (because
+    /// they were created before decoders were recorded) are taken to have been created by the
+    /// decoders recorded in the metadata file.
+    ///
+    /// Returns `None` if the decoders are the same, or if either is unknown.
+    pub fn environment_changed(&self) -> Option<EnvChange> {
+        let Environments { recorded, current } = self.3;
+        let current = current?;
+
+        let digests = self
+            .0
+            .keys()
+            .into_iter()
+            .filter_map(|src_path| self.fetch(src_path).ok())
+            .map(|hash| hash.environment().or(recorded))
+            .collect::<Vec<_>>();
+        let (previous, _count) = digests
+            .iter()
+            .flatten()
+            .counts()
+            .into_iter()
+            .max_by_key(|&(digest, count)| (count, *digest))?;
+        if *previous == current {
+            return None;
+        }
+
+        Some(EnvChange {
+            previous: *previous,
+            current,
+            affected: digests
+                .iter()
+                .filter(|digest| digest.is_some_and(|digest| digest != current))
+                .count(),
+            total: digests.len(),
+        })
+    }
+
+    #[cfg(test)]
+    fn with_current_environment(mut self, current: Option<EnvironmentDigest>) -> Self {
+        self.3.current = current;
+        self
+    }
+
+    /// Summarize how long the entries of the cache took to hash, and what was decoded. Entries
+    /// created before this was recorded are only counted.
+    pub fn stats_report(&self) -> CacheStatsReport {
+        let entries = self
+            .0
+            .keys()
+            .into_iter()
+            .filter_map(|src_path| {
+                let stats = self.0.fetch(&src_path).ok()?.stats;
+                Some((src_path, stats))
+            })
+            .collect::<Vec<_>>();
+
+        CacheStatsReport::from_entries(
+            entries
+                .iter()
+                .map(|(src_path, stats)| (src_path.as_path(), stats.as_ref())),
+        )
+    }
+
+    /// Count the entries of the cache, and which of them are stale.
+    pub fn summary(&self) -> CacheSummary {
+        let mut ret = CacheSummary {
+            size_on_disk: std::fs::metadata(self.0.cache_path()).map_or(0, |m| m.len()),
+            other_environment: self.3.current.map(|_current| 0),
+            ..CacheSummary::default()
+        };
+
+        for src_path in self.0.keys() {
+            let Ok(entry) = self.0.fetch(&src_path) else {
+                continue;
+            };
+            ret.entries += 1;
+            if entry.opts != self.0.interface().opts_for(&src_path) {
+                ret.stale_options += 1;
+            }
+            if entry.stats.is_none() {
+                ret.without_stats += 1;
+            }
+            match &entry.hash {
+                Ok(hash) => {
+                    ret.hashes += 1;
+                    let environment = hash.environment().or(self.3.recorded);
+                    if let (Some(count), Some(current)) =
+                        (&mut ret.other_environment, self.3.current)
+                    {
+                        if environment.is_some_and(|environment| environment != current) {
+                            *count += 1;
+                        }
+                    }
+                }
+                Err(Error::TooShort { .. }) => ret.too_short += 1,
+                Err(_) => ret.errors += 1,
+            }
+        }
+
+        ret
+    }
+
+    /// Every cached path whose file differs from when it was cached (see
+    /// [`StatMismatch`]), in order. Nothing is hashed.
+    ///
+    /// Returns an error if any file could not be read.
+    pub fn stat_mismatches(&self) -> Result<Vec<(PathBuf, StatMismatch)>, VdfCacheError> {
+        let mut ret = vec![];
+        for src_path in self.0.keys() {
+            if let Some(mismatch) = self.0.stat_mismatch(&src_path)? {
+                ret.push((src_path, mismatch));
+            }
+        }
+        Ok(ret)
+    }
+
+    /// Every cached path whose file no longer exists, in order.
+    pub fn missing_paths(&self) -> Vec<PathBuf> {
+        self.0
+            .keys()
+            .into_iter()
+            .filter(|src_path| !src_path.exists())
             .collect()
     }
 
     /// If ``src_path`` has not been modified since it was cached, then return the cached hash.
     /// If ``src_path`` has been deleted, then remove it from the cache and return None.
-    /// Otherwise create a new hash, insert it into the cache, and return it.
+    /// Otherwise (including when the cached hash was created with other options) create a new
+    /// hash, insert it into the cache, and return it.
     ///
     /// Returns an error if it was not possible to generate a hash from `src_path`.
     #[inline]
+    #[allow(unused)]
     pub fn fetch_update(
         &self,
         src_path: impl AsRef<Path>,
     ) -> Result<Option<Result<VideoHash, Error>>, VdfCacheError> {
-        self.0.fetch_update(src_path).map_err(VdfCacheError::from)
+        let src_path = src_path.as_ref();
+        let ret = self
+            .0
+            .fetch_update(src_path)
+            .map(|entry| entry.map(|entry| entry.hash))
+            .map_err(VdfCacheError::from);
+        if let Ok(Some(Ok(_hash))) = &ret {
+            self.assign_id(src_path);
+        }
+        ret
     }
 
     #[inline]
@@ -175,7 +862,10 @@ impl VideoHashFilesystemCache {
         src_path: impl AsRef<Path>,
     ) -> Result<Option<Result<VideoHash, Error>>, VdfCacheError> {
         let _ = self.0.remove(&src_path);
-        self.0.fetch_update(&src_path).map_err(VdfCacheError::from)
+        self.0
+            .fetch_update(&src_path)
+            .map(|entry| entry.map(|entry| entry.hash))
+            .map_err(VdfCacheError::from)
     }
 
     /// Save the cache to disk.
@@ -183,7 +873,11 @@ impl VideoHashFilesystemCache {
     ///Returns an error if it was not possible to write the cache to disk.
     #[inline]
     pub fn save(&self) -> Result<(), VdfCacheError> {
-        self.0.save().map_err(VdfCacheError::from)
+        self.0.save().map_err(VdfCacheError::from)?;
+        if let Some(ids) = self.2.write().as_mut() {
+            ids.save()?;
+        }
+        Ok(())
     }
 
     pub fn clear(&self) {
@@ -202,53 +896,130 @@ impl VideoHashFilesystemCache {
         }
     }
 
-    /// For all files on the filesystem matching ``file_projection``, update the cache for all new or modified files.
-    /// Also, remove items from the cache if they no longer exist in the underlying filesystem.
-    ///
-    /// # Return values
-    /// This function will return ``Err`` if any fatal error occurs. Otherwise, it returns a group
-    /// of nonfatal errors, typically a list of paths for which a [`VideoHash`] could not be generated.
+    /// Update the cache for each of the given paths: new or modified files are hashed, and files that
+    /// no longer exist on the filesystem are removed from the cache.
     ///
-    /// ## Fatal errors
-    ///    * Unable to read any of the starting directories in ``file_projection``
-    ///    * Any Io error when reading/writing to the cache file itself.
+    /// Returns an [`UpdateReport`] describing what happened to every file that was attempted. Failures
+    /// for individual files (typically because a [`VideoHash`] could not be generated from them) are
+    /// collected in the report rather than stopping the update. The exception is when the video
+    /// backend cannot be used at all (such as when ffmpeg is not installed), which stops the update
+    /// straight away and is reported in [`UpdateReport::aborted`].
     ///
-    /// ## Nonfatal errors
-    ///    * Failure to create a hash from any individual file.
-    ///    * Failure to remove an item from the cache (This is unlikely and should only occur if
-    ///      calling this function more than once at the same time with overlapping paths)
+    /// The update can be stopped early with [`UpdateOptions::cancellation`], and its progress monitored
+    /// with [`UpdateOptions::on_file_done`].
     ///
     /// # Parallelism
     /// To speed up loading there is a cargo feature to allow hashes to be created from videos in parallel.
     /// Parallel loading is much faster than sequential loading but be aware that since Ffmpeg is already multithreaded
-    /// this can use up a lot of CPU time.
+    /// this can use up a lot of CPU time. The number of files hashed at once is limited by
+    /// [`UpdateOptions::concurrency`].
     #[inline]
-    pub fn update_using_fs<T>(&self, paths: T)
+    pub fn update_using_fs<T>(&self, paths: T, opts: UpdateOptions) -> UpdateReport
     where
         T: IntoIterator<Item = PathBuf>,
         <T as IntoIterator>::IntoIter: Send,
     {
         let loading_paths = paths.into_iter().unique();
 
-        #[cfg(feature = "parallel_loading")]
-        {
-            loading_paths.par_bridge().for_each(|path| {
-                self.fetch_update(path).unwrap();
-            });
-        }
+        run_update(loading_paths, opts, |path| self.update_file(path))
+    }
 
-        #[cfg(not(feature = "parallel_loading"))]
-        {
-            for path in loading_paths {
-                self.fetch_update(&path);
+    /// As [update_using_fs][`VideoHashFilesystemCache::update_using_fs`], for async code running on
+    /// a tokio runtime. Returns a stream of each file and what happened to it, in the order that
+    /// the files finish.
+    ///
+    /// Files are hashed on tokio's blocking thread pool, no more than `concurrency` at a time
+    /// ([`default_concurrency`] if `None`). Dropping the stream stops any more files from being
+    /// started. Files which are already being hashed are allowed to finish, and are cached as usual.
+    ///
+    /// If the video backend cannot be used, the file that found out is reported as
+    /// [`FileOutcome::Aborted`] and no more files are started.
+    #[cfg(feature = "async")]
+    #[allow(unused)]
+    pub fn update_using_fs_async<T>(
+        self: &std::sync::Arc<Self>,
+        paths: T,
+        concurrency: Option<usize>,
+    ) -> impl futures_util::Stream<Item = (PathBuf, FileOutcome)> + Send + 'static
+    where
+        T: IntoIterator<Item = PathBuf>,
+        <T as IntoIterator>::IntoIter: Send + 'static,
+    {
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use std::sync::Arc;
+
+        use futures_util::StreamExt;
+
+        let cache = Arc::clone(self);
+        let aborted = Arc::new(AtomicBool::new(false));
+        let concurrency = concurrency.unwrap_or_else(default_concurrency).max(1);
+
+        futures_util::stream::iter(paths.into_iter().unique())
+            .map(move |path| {
+                let cache = Arc::clone(&cache);
+                let aborted = Arc::clone(&aborted);
+                async move {
+                    if aborted.load(Ordering::SeqCst) {
+                        return None;
+                    }
+                    let task_path = path.clone();
+                    let outcome =
+                        tokio::task::spawn_blocking(move || cache.update_file(&task_path))
+                            .await
+                            .unwrap_or_else(|e| match e.try_into_panic() {
+                                Ok(payload) => {
+                                    FileOutcome::Failed(panicked(&path, payload.as_ref()))
+                                }
+                                Err(e) => FileOutcome::Failed(VdfCacheError::CreateHashError(
+                                    Error::VidProc(format!("Hashing task failed: {e}")),
+                                )),
+                            });
+                    if matches!(outcome, FileOutcome::Aborted(_)) {
+                        aborted.store(true, Ordering::SeqCst);
+                    }
+                    Some((path, outcome))
+                }
+            })
+            .buffer_unordered(concurrency)
+            .filter_map(std::future::ready)
+    }
+
+    // Hash or remove a single file for update_using_fs.
+    fn update_file(&self, path: &Path) -> FileOutcome {
+        match self.0.fetch_update_detailed(path) {
+            Ok(FetchUpdate::Cached(entry)) => {
+                if entry.hash.is_ok() {
+                    self.assign_id(path);
+                }
+                FileOutcome::Cached
             }
+            Ok(FetchUpdate::Updated(entry)) => match entry.hash {
+                Ok(_hash) => {
+                    self.assign_id(path);
+                    FileOutcome::Hashed
+                }
+                //Not cached, or the file would not be hashed again once the backend is fixed.
+                Err(e @ (Error::FfmpegMissing(_) | Error::MissingComponents(_))) => {
+                    if let Err(remove_err) = self.0.remove(path) {
+                        warn!(
+                            "Failed to remove {} from the cache: {remove_err}",
+                            path.display()
+                        );
+                    }
+                    FileOutcome::Aborted(VdfCacheError::from(e))
+                }
+                Err(Error::TooShort { .. }) => FileOutcome::TooShort,
+                Err(e) => FileOutcome::Failed(VdfCacheError::from(e)),
+            },
+            Ok(FetchUpdate::Removed) => FileOutcome::Removed,
+            Err(e) => FileOutcome::Failed(VdfCacheError::from(e)),
         }
     }
 
     #[inline]
     fn fetch_entry(&self, src_path: impl AsRef<Path>) -> Result<VideoHash, VdfCacheError> {
         match self.0.fetch(src_path) {
-            Ok(x) => x.map_err(VdfCacheError::from),
+            Ok(x) => x.hash.map_err(VdfCacheError::from),
             Err(e) => Err(VdfCacheError::from(e)),
         }
     }
@@ -256,4 +1027,882 @@ impl VideoHashFilesystemCache {
     pub fn remove(&self, key: impl AsRef<Path>) -> Result<(), VdfCacheError> {
         self.0.remove(key).map_err(VdfCacheError::from)
     }
+
+    /// Every cached path which is `prefix` or is inside it, in order. Paths are compared by
+    /// component, so `/video` does not contain `/videos/a.mp4`.
+    pub fn paths_under(&self, prefix: &Path) -> Vec<PathBuf> {
+        self.0.keys_under(prefix)
+    }
+
+    /// Forget every cached path under `prefix` (see [paths_under][`VideoHashFilesystemCache::paths_under`]),
+    /// for instance after a tree of videos is deleted, along with their IDs. Returns the number of
+    /// entries removed.
+    pub fn remove_under(&self, prefix: &Path) -> Result<usize, VdfCacheError> {
+        if let Some(ids) = self.2.write().as_mut() {
+            ids.remove_under(prefix);
+        }
+        self.0.remove_under(prefix).map_err(VdfCacheError::from)
+    }
+
+    /// Update the cache after the tree of videos at `old` has been moved to `new`. Each entry under
+    /// `old` (see [paths_under][`VideoHashFilesystemCache::paths_under`]) is moved to the same place
+    /// under `new`, and its hash is given the new path, so the moved videos are not hashed again.
+    /// Returns the number of entries moved. The videos keep their IDs.
+    pub fn rename_prefix(&self, old: &Path, new: &Path) -> Result<usize, VdfCacheError> {
+        if let Some(ids) = self.2.write().as_mut() {
+            ids.rename_prefix(old, new);
+        }
+        self.0.rename_prefix(old, new).map_err(VdfCacheError::from)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_search_between_imported_collections() {
+        use vid_dup_finder_lib::{search_with_opts, CollectionMode, SearchOptions};
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let dir = temp_dir.path();
+
+        //"x" is duplicated within the nas, and "y" is on both machines.
+        let x = VideoHash::empty_hash("x").with_duration(30);
+        let y = VideoHash::full_hash("y").with_duration(30);
+        let nas = [
+            x.with_src_path("/nas/x.mp4"),
+            x.with_flipped_bits(0..4).with_src_path("/nas/x copy.mp4"),
+            y.with_src_path("/nas/y.mp4"),
+        ];
+        let laptop = [y.with_flipped_bits(0..4).with_src_path("/home/y.mp4")];
+
+        let portable = |hashes: &[VideoHash]| {
+            let lines = hashes.iter().map(VideoHash::to_json_portable);
+            lines.collect::<Vec<_>>().join("\n")
+        };
+
+        let cache =
+            VideoHashFilesystemCache::new(100, dir.join("cache.bin"), Cropdetect::None, 0.0, 10.0)
+                .unwrap();
+        assert_eq!(
+            cache
+                .read_portable_hashes(portable(&nas).as_bytes(), "nas")
+                .unwrap(),
+            3
+        );
+        assert_eq!(
+            cache
+                .read_portable_hashes(portable(&laptop).as_bytes(), "laptop")
+                .unwrap(),
+            1
+        );
+
+        let hashes = cache.imported_hashes();
+        assert_eq!(hashes.len(), 4);
+        assert!(cache.all_cached_paths().is_empty());
+
+        let opts = SearchOptions {
+            collections: CollectionMode::CrossCollectionOnly,
+            ..SearchOptions::default()
+        };
+        let groups = search_with_opts(hashes.clone(), &opts);
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].collection_of("/nas/y.mp4"), Some("nas"));
+        assert_eq!(groups[0].collection_of("/home/y.mp4"), Some("laptop"));
+
+        //both groups are found when searching everything.
+        assert_eq!(search_with_opts(hashes, &SearchOptions::default()).len(), 2);
+
+        //nothing imported is saved.
+        cache.save().unwrap();
+        let reopened =
+            VideoHashFilesystemCache::new(100, dir.join("cache.bin"), Cropdetect::None, 0.0, 10.0)
+                .unwrap();
+        assert!(reopened.imported_hashes().is_empty());
+    }
+
+    #[test]
+    fn test_too_short_files_are_not_retried() {
+        use super::super::generic_cache_if::CachedHash;
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let dir = temp_dir.path();
+
+        let cache =
+            VideoHashFilesystemCache::new(100, dir.join("cache.bin"), Cropdetect::None, 0.0, 10.0)
+                .unwrap();
+        let opts = CreationOptions {
+            skip_forward_amount: 0.0,
+            cropdetect: Cropdetect::None,
+            ..CreationOptions::default()
+        };
+
+        //as if a motion photo and a corrupt video had both been hashed.
+        let motion_photo = dir.join("motion_photo.mp4");
+        let corrupt = dir.join("corrupt.mp4");
+        for (path, error) in [
+            (
+                &motion_photo,
+                Error::TooShort {
+                    duration: std::time::Duration::ZERO,
+                },
+            ),
+            (&corrupt, Error::NotEnoughFrames),
+        ] {
+            std::fs::write(path, "").unwrap();
+            let entry = CachedHash {
+                opts,
+                hash: Err(error),
+                stats: None,
+            };
+            cache.0.insert_loaded(path, entry).unwrap();
+        }
+
+        assert_eq!(cache.error_paths(), std::slice::from_ref(&corrupt));
+        let report = cache.update_using_fs([motion_photo, corrupt], UpdateOptions::default());
+        assert_eq!(report.skipped_cached, 2);
+    }
+
+    #[test]
+    fn test_entries_with_other_options_are_rehashed() {
+        use vid_dup_finder_lib::CreationOptionsError;
+
+        use super::super::generic_cache_if::CachedHash;
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let dir = temp_dir.path();
+        let cache_path = dir.join("cache.bin");
+
+        //Invalid options are rejected before the cache is opened.
+        assert!(matches!(
+            VideoHashFilesystemCache::new(100, cache_path.clone(), Cropdetect::None, -1.0, 10.0),
+            Err(VdfCacheError::InvalidOptions(
+                CreationOptionsError::InvalidSkipForward(_)
+            ))
+        ));
+        assert!(matches!(
+            VideoHashFilesystemCache::new(100, cache_path.clone(), Cropdetect::None, 0.0, 0.0),
+            Err(VdfCacheError::InvalidOptions(
+                CreationOptionsError::InvalidDuration(_)
+            ))
+        ));
+
+        let old_opts = CreationOptions {
+            skip_forward_amount: 0.0,
+            cropdetect: Cropdetect::None,
+            ..CreationOptions::default()
+        };
+        let cache =
+            VideoHashFilesystemCache::new(100, cache_path.clone(), Cropdetect::None, 0.0, 10.0)
+                .unwrap();
+        let (stale, current) = (dir.join("stale.mp4"), dir.join("current.mp4"));
+        for path in [&stale, &current] {
+            std::fs::write(path, "").unwrap();
+            let entry = CachedHash {
+                opts: old_opts,
+                hash: Err(Error::NotEnoughFrames),
+                stats: None,
+            };
+            cache.0.insert_loaded(path, entry).unwrap();
+        }
+        cache.save().unwrap();
+        drop(cache);
+
+        //Changing the options no longer rejects the whole cache.
+        let cache =
+            VideoHashFilesystemCache::new(100, cache_path, Cropdetect::None, 5.0, 10.0).unwrap();
+        let new_opts = CreationOptions {
+            skip_forward_amount: 5.0,
+            ..old_opts
+        };
+        let entry = CachedHash {
+            opts: new_opts,
+            hash: Err(Error::NotEnoughFrames),
+            stats: None,
+        };
+        cache.0.insert_loaded(&current, entry).unwrap();
+
+        assert_eq!(
+            cache.options_mismatch_paths(&new_opts),
+            std::slice::from_ref(&stale)
+        );
+        assert_eq!(
+            cache.options_mismatch_paths(&old_opts),
+            std::slice::from_ref(&current)
+        );
+
+        let report = cache.update_using_fs([stale, current], UpdateOptions::default());
+        assert_eq!(report.skipped_cached, 1);
+        assert!(cache.options_mismatch_paths(&new_opts).is_empty());
+    }
+
+    #[test]
+    fn test_prefix_operations() {
+        use rand::{rngs::StdRng, SeedableRng};
+
+        use super::super::generic_cache_if::CachedHash;
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let dir = temp_dir.path();
+        let cache_path = dir.join("cache.bin");
+        let open =
+            || VideoHashFilesystemCache::new(100, cache_path.clone(), Cropdetect::None, 0.0, 10.0);
+
+        //neighbours of dir/video whose names start the same way.
+        let (video, moved) = (dir.join("video"), dir.join("moved"));
+        let names = [
+            "video/a.mp4",
+            "video/sub/b.mp4",
+            "videos/c.mp4",
+            "video.bak/d.mp4",
+        ];
+        let paths = names.map(|name| dir.join(name));
+        let mut rng = StdRng::seed_from_u64(1);
+        let cache = open().unwrap();
+        for path in &paths {
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, "").unwrap();
+            let entry = CachedHash {
+                opts: CreationOptions {
+                    skip_forward_amount: 0.0,
+                    cropdetect: Cropdetect::None,
+                    ..CreationOptions::default()
+                },
+                hash: Ok(VideoHash::random_hash(&mut rng).with_src_path(path)),
+                stats: None,
+            };
+            cache.0.insert_loaded(path, entry).unwrap();
+        }
+
+        assert_eq!(cache.paths_under(&video), paths[..2]);
+        assert_eq!(cache.paths_under(&video.join("sub")), paths[1..2]);
+        assert_eq!(cache.paths_under(&paths[0]), paths[..1]);
+        assert!(cache.paths_under(&dir.join("vid")).is_empty());
+        assert_eq!(cache.paths_under(dir).len(), 4);
+
+        //moving a tree keeps its hashes, so the moved files are not hashed again.
+        let hash_b = cache.fetch(&paths[1]).unwrap();
+        std::fs::rename(&video, &moved).unwrap();
+        assert_eq!(cache.rename_prefix(&video, &moved).unwrap(), 2);
+        assert!(cache.paths_under(&video).is_empty());
+        let moved_paths = cache.paths_under(&moved);
+        assert_eq!(moved_paths, [moved.join("a.mp4"), moved.join("sub/b.mp4")]);
+        let moved_b = cache.fetch(&moved_paths[1]).unwrap();
+        assert_eq!(moved_b.src_path(), moved_paths[1]);
+        assert_eq!(moved_b.with_src_path(&paths[1]), hash_b);
+        let report = cache.update_using_fs(moved_paths.clone(), UpdateOptions::default());
+        assert_eq!(report.skipped_cached, 2);
+
+        //moving a single file.
+        let moved_d = dir.join("d.mp4");
+        assert_eq!(cache.rename_prefix(&paths[3], &moved_d).unwrap(), 1);
+        assert_eq!(cache.paths_under(&moved_d), std::slice::from_ref(&moved_d));
+
+        cache.save().unwrap();
+        drop(cache);
+        let cache = open().unwrap();
+        assert_eq!(cache.remove_under(&moved).unwrap(), 2);
+        assert_eq!(cache.remove_under(&moved).unwrap(), 0);
+        assert_eq!(cache.paths_under(dir), [moved_d, dir.join("videos/c.mp4")]);
+    }
+
+    #[test]
+    fn test_video_ids_follow_moves() {
+        use rand::{rngs::StdRng, SeedableRng};
+
+        use super::super::generic_cache_if::CachedHash;
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let dir = temp_dir.path();
+        let cache_path = dir.join("cache.bin");
+        let open = || {
+            VideoHashFilesystemCache::new(100, cache_path.clone(), Cropdetect::None, 0.0, 10.0)
+                .unwrap()
+        };
+
+        let paths = ["video/a.mp4", "video/b.mp4", "c.mp4"].map(|name| dir.join(name));
+        let mut rng = StdRng::seed_from_u64(1);
+        let cache = open();
+        for path in &paths {
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, "").unwrap();
+            let entry = CachedHash {
+                opts: CreationOptions {
+                    skip_forward_amount: 0.0,
+                    cropdetect: Cropdetect::None,
+                    ..CreationOptions::default()
+                },
+                hash: Ok(VideoHash::random_hash(&mut rng).with_src_path(path)),
+                stats: None,
+            };
+            cache.0.insert_loaded(path, entry).unwrap();
+        }
+
+        //without asking for them, there are no IDs.
+        assert_eq!(cache.id_for_path(&paths[0]), None);
+
+        //existing entries are given IDs when they are first asked for.
+        let cache = cache.with_video_ids().unwrap();
+        let ids = paths.clone().map(|path| cache.id_for_path(path).unwrap());
+        assert_eq!(ids.iter().unique().count(), 3);
+        assert_eq!(cache.id_for_path(&paths[0]), Some(ids[0]));
+        assert_eq!(cache.id_for_path(dir.join("missing.mp4")), None);
+        assert_eq!(cache.path_for_id(ids[2]), Some(paths[2].clone()));
+
+        //moving a tree or a single file keeps the IDs.
+        let (moved_a, moved_b, moved_c) = (
+            dir.join("moved/a.mp4"),
+            dir.join("moved/b.mp4"),
+            dir.join("d.mp4"),
+        );
+        assert_eq!(
+            cache
+                .rename_prefix(&dir.join("video"), &dir.join("moved"))
+                .unwrap(),
+            2
+        );
+        assert_eq!(cache.rename_prefix(&paths[2], &moved_c).unwrap(), 1);
+        assert_eq!(cache.rename_prefix(&paths[2], &moved_c).unwrap(), 0);
+        assert_eq!(cache.id_for_path(&moved_a), Some(ids[0]));
+        assert_eq!(cache.id_for_path(&moved_b), Some(ids[1]));
+        assert_eq!(cache.id_for_path(&moved_c), Some(ids[2]));
+        assert_eq!(cache.id_for_path(&paths[0]), None);
+        assert_eq!(cache.path_for_id(ids[0]), Some(moved_a.clone()));
+
+        //a video hashed again at its new path takes its old ID.
+        assert!(cache.transfer_id(&moved_c, &paths[2]));
+        assert_eq!(cache.path_for_id(ids[2]), Some(paths[2].clone()));
+        assert!(!cache.transfer_id(&moved_c, &paths[2]));
+
+        //the IDs are saved.
+        cache.save().unwrap();
+        drop(cache);
+        let cache = open().with_video_ids().unwrap();
+        assert_eq!(cache.id_for_path(&moved_a), Some(ids[0]));
+        assert_eq!(cache.path_for_id(ids[2]), Some(paths[2].clone()));
+
+        //forgetting a tree forgets its IDs.
+        cache.remove_under(&dir.join("moved")).unwrap();
+        assert_eq!(cache.path_for_id(ids[0]), None);
+    }
+
+    #[test]
+    fn test_corrupt_cache_files_are_rejected() {
+        use rand::{rngs::StdRng, Rng, SeedableRng};
+
+        use super::super::generic_cache_if::CachedHash;
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let dir = temp_dir.path();
+        let cache_path = dir.join("cache.bin");
+        let open =
+            || VideoHashFilesystemCache::new(100, cache_path.clone(), Cropdetect::None, 0.0, 10.0);
+
+        //a hash with every optional part, and an error.
+        let mut rng = StdRng::seed_from_u64(1);
+        let cache = open().unwrap();
+        let hash = VideoHash::random_hash(&mut rng);
+        let hash = hash
+            .with_raw_frame_hashes(vec![1, 2, 3])
+            .with_speed_variant(vid_dup_finder_lib::SpeedFactor::Double, &hash);
+        let other = VideoHash::random_hash(&mut rng).with_src_path(dir.join("c.mp4"));
+        for (name, hash) in [
+            ("a.mp4", Ok(hash)),
+            ("b.mp4", Err(Error::NotEnoughFrames)),
+            ("c.mp4", Ok(other)),
+        ] {
+            let path = dir.join(name);
+            std::fs::write(&path, "").unwrap();
+            let entry = CachedHash {
+                opts: CreationOptions::default(),
+                hash,
+                stats: None,
+            };
+            cache.0.insert_loaded(path, entry).unwrap();
+        }
+        cache.save().unwrap();
+        let valid = std::fs::read(&cache_path).unwrap();
+
+        //anything that loads at all must be usable, including by searches.
+        let load = |bytes: &[u8]| {
+            std::fs::write(&cache_path, bytes).unwrap();
+            match open() {
+                Ok(cache) => {
+                    let hashes = cache
+                        .all_cached_paths()
+                        .into_iter()
+                        .filter_map(|path| cache.fetch(path).ok())
+                        .collect::<Vec<_>>();
+                    let _ = cache.error_paths();
+                    let _ = vid_dup_finder_lib::search_with_opts(
+                        hashes.iter().cloned(),
+                        &vid_dup_finder_lib::SearchOptions::default(),
+                    );
+                    let _ = vid_dup_finder_lib::search_with_opts(
+                        hashes,
+                        &vid_dup_finder_lib::SearchOptions {
+                            speed_variants: true,
+                            ..Default::default()
+                        },
+                    );
+                    true
+                }
+                Err(VdfCacheError::CacheErrror(FsCacheErrorKind::Deserialization { .. })) => false,
+                Err(e) => panic!("unexpected error: {e}"),
+            }
+        };
+        assert!(load(&valid));
+
+        for len in 0..valid.len() {
+            assert!(!load(&valid[..len]), "truncated to {len} bytes");
+        }
+
+        //the length of the first path, far longer than the file.
+        for len in [1 << 40, u64::MAX] {
+            let mut huge = valid.clone();
+            huge[8..16].copy_from_slice(&u64::to_le_bytes(len));
+            assert!(!load(&huge));
+        }
+
+        for _ in 0..500 {
+            let mut bytes = valid.clone();
+            for _ in 0..rng.gen_range(1..4) {
+                let idx = rng.gen_range(0..bytes.len());
+                bytes[idx] = rng.gen();
+            }
+            load(&bytes);
+
+            let len = rng.gen_range(0..64);
+            load(&(0..len).map(|_| rng.gen()).collect::<Vec<u8>>());
+        }
+    }
+
+    #[test]
+    fn test_portable_hashes_round_trip() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let dir = temp_dir.path();
+        let new_cache = |name: &str| {
+            VideoHashFilesystemCache::new(100, dir.join(name), Cropdetect::None, 0.0, 10.0).unwrap()
+        };
+
+        let hashes = vec![
+            VideoHash::empty_hash("/nas/x.mp4").with_duration(30),
+            VideoHash::full_hash("/nas/y.mp4").with_duration(45),
+        ];
+        let nas = new_cache("nas.bin");
+        let lines = hashes.iter().map(VideoHash::to_json_portable);
+        let lines = lines.collect::<Vec<_>>().join("\n");
+        nas.read_portable_hashes(lines.as_bytes(), "nas").unwrap();
+        let mut exported = vec![];
+        assert_eq!(nas.write_portable_hashes(&mut exported).unwrap(), 2);
+        assert_eq!(exported.iter().filter(|&&b| b == b'\n').count(), 2);
+
+        let laptop = new_cache("laptop.bin");
+        let mut with_blank_line = exported.clone();
+        with_blank_line.extend_from_slice(b"\n");
+        assert_eq!(
+            laptop
+                .read_portable_hashes(with_blank_line.as_slice(), "nas")
+                .unwrap(),
+            2
+        );
+        let labelled = hashes
+            .into_iter()
+            .map(|hash| hash.with_collection("nas"))
+            .collect::<Vec<_>>();
+        assert_eq!(laptop.imported_hashes(), labelled);
+
+        //nothing is imported from a file containing a bad line.
+        let broken = new_cache("broken.bin");
+        exported.extend_from_slice(b"{\"version\": 1}\n");
+        let err = broken.read_portable_hashes(exported.as_slice(), "nas");
+        assert!(matches!(
+            err,
+            Err(VdfCacheError::PortableHashError { line: 3, .. })
+        ));
+        assert!(broken.imported_hashes().is_empty());
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn test_migrate_to_sqlite() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let dir = temp_dir.path();
+
+        //one entry that is (given a video backend) a hash, and one that is always an error.
+        let video = dir.join("cat.1.mp4");
+        std::fs::copy(
+            Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("../vid_dup_finder_lib/examples/vids/cat.1.mp4"),
+            &video,
+        )
+        .unwrap();
+        let not_a_video = dir.join("not_a_video.txt");
+        std::fs::write(&not_a_video, "hello").unwrap();
+        let files = vec![video, not_a_video];
+
+        let open = |cache_path: &Path| {
+            VideoHashFilesystemCache::new(
+                100,
+                cache_path.to_path_buf(),
+                Cropdetect::None,
+                0.0,
+                10.0,
+            )
+            .unwrap()
+        };
+
+        let bincode_path = dir.join("cache.bin");
+        let cache = open(&bincode_path);
+        cache.update_using_fs(files.clone(), UpdateOptions::default());
+        cache.save().unwrap();
+
+        let sqlite_path = dir.join("cache.sqlite");
+        let count =
+            VideoHashFilesystemCache::migrate_to_sqlite(&bincode_path, &sqlite_path).unwrap();
+        assert_eq!(count, 2);
+
+        let migrated = open(&sqlite_path);
+        let sorted = |mut paths: Vec<PathBuf>| {
+            paths.sort();
+            paths
+        };
+        assert_eq!(
+            sorted(migrated.all_cached_paths()),
+            sorted(cache.all_cached_paths())
+        );
+        assert_eq!(sorted(migrated.error_paths()), sorted(cache.error_paths()));
+        for file in &files {
+            assert_eq!(
+                format!("{:?}", migrated.fetch(file)),
+                format!("{:?}", cache.fetch(file))
+            );
+        }
+
+        //the migrated entries are up to date, so nothing is hashed again.
+        let report = migrated.update_using_fs(files, UpdateOptions::default());
+        assert_eq!(report.skipped_cached, 2);
+
+        let missing = VideoHashFilesystemCache::migrate_to_sqlite(
+            dir.join("missing.bin"),
+            dir.join("missing.sqlite"),
+        );
+        assert!(missing.is_err());
+    }
+
+    #[test]
+    fn test_out_of_date_caches_are_rejected() {
+        use super::super::cache_metadata::CACHE_VERSION;
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let dir = temp_dir.path();
+        let cache_path = dir.join("cache.bin");
+        std::fs::write(&cache_path, "").unwrap();
+
+        //metadata as if the cache was created by an older version.
+        let metadata_path = VideoHashFilesystemCache::metadata_path(&cache_path).unwrap();
+        let metadata = VdfCacheMetadata::new(Cropdetect::None, 0.0)
+            .to_disk_fmt()
+            .replace(&format!(",{CACHE_VERSION},"), ",1,");
+        std::fs::write(&metadata_path, metadata).unwrap();
+
+        let result = VideoHashFilesystemCache::new(100, cache_path, Cropdetect::None, 0.0, 10.0);
+        match result {
+            Err(VdfCacheError::MetadataValidationError(e)) => assert!(e.contains("out of date")),
+            other => panic!("expected an out of date cache, got {:?}", other.err()),
+        }
+    }
+
+    #[test]
+    fn test_environment_changes_are_reported() {
+        use super::super::generic_cache_if::CachedHash;
+        use vid_dup_finder_lib::EnvironmentInfo;
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let dir = temp_dir.path();
+        let cache_path = dir.join("cache.bin");
+
+        let ffmpeg = |version: &str| {
+            EnvironmentInfo::new("ffmpeg", [("ffmpeg", version), ("ffprobe", version)]).digest()
+        };
+        let (old, new) = (ffmpeg("6.1.1"), ffmpeg("7.0.2"));
+
+        //the decoders can only be recorded in a hash by the backend that created it, or when it
+        //is read from the portable format.
+        let hashed_by = |name: &str, environment: Option<EnvironmentDigest>| {
+            let mut json: serde_json::Value =
+                serde_json::from_str(&VideoHash::full_hash(dir.join(name)).to_json_portable())
+                    .unwrap();
+            if let Some(environment) = environment {
+                json["creation"]["environment"] = environment.to_string().into();
+            }
+            VideoHash::from_json_portable(&json.to_string()).unwrap()
+        };
+
+        let cache =
+            VideoHashFilesystemCache::new(100, cache_path.clone(), Cropdetect::None, 0.0, 10.0)
+                .unwrap();
+        let opts = CreationOptions {
+            skip_forward_amount: 0.0,
+            cropdetect: Cropdetect::None,
+            ..CreationOptions::default()
+        };
+        let insert = |cache: &VideoHashFilesystemCache, name: &str, environment| {
+            let path = dir.join(name);
+            std::fs::write(&path, "").unwrap();
+            let entry = CachedHash {
+                opts,
+                hash: Ok(hashed_by(name, environment)),
+                stats: None,
+            };
+            cache.0.insert_loaded(&path, entry).unwrap();
+        };
+        insert(&cache, "a.mp4", Some(old));
+        insert(&cache, "b.mp4", Some(old));
+        insert(&cache, "c.mp4", Some(new));
+
+        //nothing is reported while the decoders are the same as those that made most of the cache.
+        let cache = cache.with_current_environment(Some(old));
+        assert_eq!(cache.environment_changed(), None);
+        let cache = cache.with_current_environment(None);
+        assert_eq!(cache.environment_changed(), None);
+
+        let cache = cache.with_current_environment(Some(new));
+        assert_eq!(
+            cache.environment_changed(),
+            Some(EnvChange {
+                previous: old,
+                current: new,
+                affected: 2,
+                total: 3,
+            })
+        );
+
+        //hashes which do not record their decoders are taken to be from those of the metadata.
+        insert(&cache, "d.mp4", None);
+        insert(&cache, "e.mp4", None);
+        let mut cache = cache.with_current_environment(Some(new));
+        cache.3.recorded = Some(new);
+        assert_eq!(cache.environment_changed(), None);
+        let cache = cache.with_current_environment(Some(ffmpeg("7.1")));
+        assert_eq!(
+            cache
+                .environment_changed()
+                .map(|change| (change.previous, change.affected)),
+            Some((new, 5))
+        );
+
+        //the metadata of caches created before the decoders were recorded still parses.
+        let metadata = VdfCacheMetadata::new(Cropdetect::None, 0.0).to_disk_fmt();
+        let (without_environment, _environment) = metadata.rsplit_once(',').unwrap();
+        assert_eq!(
+            VdfCacheMetadata::try_parse(without_environment)
+                .unwrap()
+                .environment(),
+            None
+        );
+        let with_environment = format!("{without_environment},{old}");
+        assert_eq!(
+            VdfCacheMetadata::try_parse(&with_environment)
+                .unwrap()
+                .environment(),
+            Some(old)
+        );
+        assert!(VdfCacheMetadata::try_parse(&format!("{without_environment},xyz")).is_err());
+    }
+
+    #[test]
+    fn test_two_pass_search_caches_each_pass() {
+        use super::super::generic_cache_if::CachedHash;
+        use vid_dup_finder_lib::TwoPassTolerances;
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let dir = temp_dir.path();
+        let cache_path = dir.join("cache.bin");
+
+        let coarse_opts = CreationOptions {
+            skip_forward_amount: 0.0,
+            cropdetect: Cropdetect::None,
+            ..CreationOptions::default()
+        };
+        let fine_opts = CreationOptions {
+            duration: 60.0,
+            ..coarse_opts
+        };
+        let fine_path = VideoHashFilesystemCache::options_cache_path(&cache_path, &fine_opts);
+        assert_ne!(fine_path, cache_path);
+        assert_eq!(fine_path.extension(), Some("bin".as_ref()));
+
+        //every video looks the same to the coarse hashes, but "c" differs later on.
+        let coarse_hash = VideoHash::empty_hash("").with_duration(30);
+        let fine_hashes = [
+            ("a.mp4", coarse_hash.clone()),
+            ("b.mp4", coarse_hash.with_flipped_bits(0..2)),
+            ("c.mp4", VideoHash::full_hash("").with_duration(30)),
+        ];
+        let open = || {
+            VideoHashFilesystemCache::with_path_options(
+                100,
+                cache_path.clone(),
+                coarse_opts,
+                vec![],
+            )
+            .unwrap()
+        };
+        let coarse = open();
+        let fine = VideoHashFilesystemCache::for_options(100, &cache_path, fine_opts).unwrap();
+        let mut paths = vec![];
+        for (name, fine_hash) in &fine_hashes {
+            let path = dir.join(name);
+            std::fs::write(&path, "").unwrap();
+            for (cache, opts, hash) in [
+                (&coarse, coarse_opts, &coarse_hash),
+                (&fine, fine_opts, fine_hash),
+            ] {
+                let entry = CachedHash {
+                    opts,
+                    hash: Ok(hash.with_src_path(&path)),
+                    stats: None,
+                };
+                cache.0.insert_loaded(&path, entry).unwrap();
+            }
+            paths.push(path);
+        }
+        coarse.save().unwrap();
+        fine.save().unwrap();
+        drop((coarse, fine));
+
+        let groups = open()
+            .two_pass_search(
+                100,
+                paths.clone(),
+                coarse_opts,
+                fine_opts,
+                TwoPassTolerances::default(),
+                UpdateOptions::default(),
+            )
+            .unwrap();
+        assert_eq!(groups.len(), 1);
+        let mut grouped = groups[0].contained_paths().collect::<Vec<_>>();
+        grouped.sort();
+        assert_eq!(grouped, [&paths[0], &paths[1]]);
+
+        //both sets of hashes are kept, each in their own cache.
+        assert!(cache_path.exists() && fine_path.exists());
+        assert_eq!(
+            open().fetch(&paths[2]).unwrap(),
+            coarse_hash.with_src_path(&paths[2])
+        );
+        let reopened = VideoHashFilesystemCache::for_options(100, &cache_path, fine_opts).unwrap();
+        assert_eq!(
+            reopened.fetch(&paths[2]).unwrap(),
+            fine_hashes[2].1.with_src_path(&paths[2])
+        );
+    }
+
+    #[test]
+    fn test_maintenance() {
+        use super::super::generic_cache_if::CachedHash;
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let dir = temp_dir.path();
+        let cache_path = dir.join("cache.bin");
+
+        assert!(matches!(
+            VideoHashFilesystemCache::open_existing(
+                100,
+                cache_path.clone(),
+                CreationOptions::default()
+            ),
+            Err(VdfCacheError::NoCache(_))
+        ));
+
+        let cache =
+            VideoHashFilesystemCache::new(100, cache_path.clone(), Cropdetect::None, 0.0, 10.0)
+                .unwrap();
+        let opts = CreationOptions {
+            skip_forward_amount: 0.0,
+            cropdetect: Cropdetect::None,
+            ..CreationOptions::default()
+        };
+        let [hashed, short, corrupt, deleted] =
+            ["hashed.mp4", "short.mp4", "corrupt.mp4", "deleted.mp4"].map(|name| dir.join(name));
+        let entries = [
+            (&hashed, Ok(VideoHash::full_hash(&hashed).with_duration(30))),
+            (
+                &short,
+                Err(Error::TooShort {
+                    duration: std::time::Duration::ZERO,
+                }),
+            ),
+            (&corrupt, Err(Error::NotEnoughFrames)),
+            (
+                &deleted,
+                Ok(VideoHash::empty_hash(&deleted).with_duration(30)),
+            ),
+        ];
+        for (path, hash) in entries {
+            std::fs::write(path, "").unwrap();
+            let entry = CachedHash {
+                opts,
+                hash,
+                stats: None,
+            };
+            cache.0.insert_loaded(path, entry).unwrap();
+        }
+        std::fs::remove_file(&deleted).unwrap();
+        std::fs::write(&hashed, "longer than before").unwrap();
+
+        let summary = cache.summary();
+        assert_eq!(
+            (
+                summary.entries,
+                summary.hashes,
+                summary.errors,
+                summary.too_short
+            ),
+            (4, 2, 1, 1)
+        );
+        assert_eq!((summary.stale_options, summary.without_stats), (0, 4));
+
+        assert_eq!(cache.missing_paths(), std::slice::from_ref(&deleted));
+        assert_eq!(
+            cache.stat_mismatches().unwrap(),
+            [
+                (deleted.clone(), StatMismatch::Missing),
+                (
+                    hashed.clone(),
+                    StatMismatch::Changed {
+                        mtime: false,
+                        size: true
+                    }
+                ),
+            ]
+        );
+
+        //importing only changes entries whose hashes differ, and skips videos which are not here.
+        let imported = VideoHash::empty_hash(&hashed).with_duration(30);
+        assert_eq!(
+            cache.import_action(&cache.fetch(&hashed).unwrap()),
+            ImportAction::Unchanged
+        );
+        assert_eq!(cache.import_action(&imported), ImportAction::Replace);
+        assert_eq!(
+            cache.import_action(&imported.with_src_path(&deleted)),
+            ImportAction::Missing
+        );
+        let new = dir.join("new.mp4");
+        std::fs::write(&new, "").unwrap();
+        assert_eq!(
+            cache.import_entry(imported.with_src_path(&new)).unwrap(),
+            ImportAction::Add
+        );
+        assert_eq!(
+            cache.import_entry(imported.clone()).unwrap(),
+            ImportAction::Replace
+        );
+        assert_eq!(cache.fetch(&hashed).unwrap(), imported);
+        assert_eq!(cache.summary().entries, 5);
+    }
 }
@@ -0,0 +1,218 @@
+//! Stable IDs for the videos of a cache, kept in a sidecar file beside it.
+//!
+//! Each video is given a random UUID when it is first seen, which follows it when the cache is
+//! told that it has moved. Other programs (such as a catalog which search results are synced into)
+//! can use the IDs to recognise videos whatever their current paths are.
+//!
+//! The sidecar holds one JSON object per line, each with an `id` and a `path`, preceded by a line
+//! giving the version of the format.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::{BufRead, BufReader, BufWriter, Write},
+    ops::Bound,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::generic_filesystem_cache::FsCacheErrorKind;
+use super::VdfCacheError;
+
+const VIDEO_IDS_VERSION: u64 = 1;
+
+#[derive(Serialize, Deserialize)]
+struct Header {
+    version: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Line {
+    id: Uuid,
+    #[serde(with = "vid_dup_finder_common::path_serde")]
+    path: PathBuf,
+}
+
+pub(crate) struct VideoIds {
+    sidecar_path: PathBuf,
+    by_path: BTreeMap<PathBuf, Uuid>,
+    by_id: HashMap<Uuid, PathBuf>,
+    //Whether anything has changed since the sidecar was loaded or saved.
+    dirty: bool,
+}
+
+impl VideoIds {
+    /// The path of the sidecar of the cache at `cache_path` (`cache.bin` has `cache.ids.jsonl`).
+    pub fn sidecar_path(cache_path: &Path) -> PathBuf {
+        let stem = cache_path.file_stem().unwrap_or_default().to_string_lossy();
+        cache_path.with_file_name(format!("{stem}.ids.jsonl"))
+    }
+
+    /// Load the sidecar of the cache at `cache_path`, or start an empty one if there is none.
+    pub fn load(cache_path: &Path) -> Result<Self, VdfCacheError> {
+        let sidecar_path = Self::sidecar_path(cache_path);
+        let mut ret = Self {
+            sidecar_path,
+            by_path: BTreeMap::new(),
+            by_id: HashMap::new(),
+            dirty: false,
+        };
+
+        let file = match std::fs::File::open(&ret.sidecar_path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(ret),
+            Err(e) => return Err(ret.io_err(e)),
+        };
+
+        let invalid = |line_num: usize, msg: String| {
+            VdfCacheError::MetadataValidationError(format!(
+                "line {line_num} of {}: {msg}",
+                ret.sidecar_path.display()
+            ))
+        };
+        let mut lines = BufReader::new(file).lines().enumerate();
+        if let Some((_, header)) = lines.next() {
+            let header = header?;
+            let Header { version } =
+                serde_json::from_str(&header).map_err(|e| invalid(1, e.to_string()))?;
+            if version != VIDEO_IDS_VERSION {
+                return Err(invalid(
+                    1,
+                    format!("version mismatch: Act: {version}, Exp: {VIDEO_IDS_VERSION}"),
+                ));
+            }
+        }
+        let mut loaded = vec![];
+        for (idx, line) in lines {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let Line { id, path } =
+                serde_json::from_str(&line).map_err(|e| invalid(idx + 1, e.to_string()))?;
+            loaded.push((path, id));
+        }
+
+        for (path, id) in loaded {
+            ret.insert(path, id);
+        }
+        ret.dirty = false;
+        Ok(ret)
+    }
+
+    pub fn id_for_path(&self, path: &Path) -> Option<Uuid> {
+        self.by_path.get(path).copied()
+    }
+
+    pub fn path_for_id(&self, id: Uuid) -> Option<&Path> {
+        self.by_id.get(&id).map(PathBuf::as_path)
+    }
+
+    /// The ID of `path`, which is generated if it does not have one yet.
+    pub fn assign(&mut self, path: &Path) -> Uuid {
+        if let Some(id) = self.id_for_path(path) {
+            return id;
+        }
+        let id = uuid::Builder::from_random_bytes(rand::random()).into_uuid();
+        self.insert(path.to_path_buf(), id);
+        id
+    }
+
+    /// Give `new` the ID of `old`, replacing any ID that `new` had. Returns false (changing
+    /// nothing) if `old` has no ID.
+    pub fn transfer(&mut self, old: &Path, new: &Path) -> bool {
+        let Some(id) = self.by_path.remove(old) else {
+            return false;
+        };
+        self.by_id.remove(&id);
+        self.insert(new.to_path_buf(), id);
+        true
+    }
+
+    /// Move the IDs of every path under `old` to the same place under `new`, as the entries of the
+    /// cache are moved.
+    pub fn rename_prefix(&mut self, old: &Path, new: &Path) {
+        for path in self.paths_under(old) {
+            let Ok(rest) = path.strip_prefix(old) else {
+                continue;
+            };
+            //joining an empty path would add a trailing separator.
+            let new_path = if rest.as_os_str().is_empty() {
+                new.to_path_buf()
+            } else {
+                new.join(rest)
+            };
+            self.transfer(&path, &new_path);
+        }
+    }
+
+    /// Forget the IDs of every path under `prefix`.
+    pub fn remove_under(&mut self, prefix: &Path) {
+        for path in self.paths_under(prefix) {
+            if let Some(id) = self.by_path.remove(&path) {
+                self.by_id.remove(&id);
+                self.dirty = true;
+            }
+        }
+    }
+
+    /// Write the sidecar, if anything has changed since it was loaded.
+    pub fn save(&mut self) -> Result<(), VdfCacheError> {
+        if !self.dirty {
+            return Ok(());
+        }
+
+        let temp_path = self.sidecar_path.with_extension("jsonl.tmp");
+        let write = || -> std::io::Result<()> {
+            let mut f = BufWriter::new(std::fs::File::create(&temp_path)?);
+            let header = Header {
+                version: VIDEO_IDS_VERSION,
+            };
+            writeln!(f, "{}", serde_json::to_string(&header)?)?;
+            for (path, &id) in &self.by_path {
+                let line = Line {
+                    id,
+                    path: path.clone(),
+                };
+                writeln!(f, "{}", serde_json::to_string(&line)?)?;
+            }
+            f.flush()?;
+            std::fs::rename(&temp_path, &self.sidecar_path)
+        };
+        write().map_err(|e| self.io_err(e))?;
+
+        self.dirty = false;
+        Ok(())
+    }
+
+    fn insert(&mut self, path: PathBuf, id: Uuid) {
+        if let Some(old_id) = self.by_path.insert(path.clone(), id) {
+            self.by_id.remove(&old_id);
+        }
+        //an ID belongs to one path at a time.
+        if let Some(old_path) = self.by_id.insert(id, path.clone()) {
+            if old_path != path && self.by_path.get(&old_path) == Some(&id) {
+                self.by_path.remove(&old_path);
+            }
+        }
+        self.dirty = true;
+    }
+
+    // Paths are compared by component, so `/video` does not contain `/videos/a.mp4`.
+    fn paths_under(&self, prefix: &Path) -> Vec<PathBuf> {
+        self.by_path
+            .range::<Path, _>((Bound::Included(prefix), Bound::Unbounded))
+            .map(|(path, _)| path)
+            .take_while(|path| path.starts_with(prefix))
+            .cloned()
+            .collect()
+    }
+
+    fn io_err(&self, e: std::io::Error) -> VdfCacheError {
+        VdfCacheError::CacheErrror(FsCacheErrorKind::CacheFileIo {
+            src: e,
+            path: self.sidecar_path.clone(),
+        })
+    }
+}
@@ -0,0 +1,7 @@
+use std::path::{Path, PathBuf};
+
+pub fn example_vid(filename: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("../vid_dup_finder_lib/examples/vids")
+        .join(filename)
+}
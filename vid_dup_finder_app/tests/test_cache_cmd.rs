@@ -0,0 +1,268 @@
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+use tempfile::TempDir;
+
+use vid_dup_finder_lib::VideoHash;
+
+mod common;
+use common::example_vid;
+
+const EXIT_OK: i32 = 0;
+const EXIT_CHANGES_NEEDED: i32 = 4;
+
+fn test_dir() -> TempDir {
+    let dir = tempfile::tempdir().expect("can create test dir");
+    std::fs::create_dir_all(dir.path().join("vids")).expect("can create test dir");
+    dir
+}
+
+// Runs `vid_dup_finder cache <args>` on the cache in dir, returning the exit code and stdout.
+fn cache_cmd(dir: &Path, args: &[&str]) -> (i32, String) {
+    let Output {
+        status,
+        stdout,
+        stderr,
+    } = Command::new(env!("CARGO_BIN_EXE_vid_dup_finder"))
+        .arg("cache")
+        .args(args)
+        .arg("--cache-file")
+        .arg(dir.join("cache.bin"))
+        .output()
+        .expect("can run vid_dup_finder");
+    let code = status.code().expect("vid_dup_finder was not killed");
+    assert!(
+        code == EXIT_OK || code == EXIT_CHANGES_NEEDED,
+        "{}",
+        String::from_utf8_lossy(&stderr)
+    );
+    (code, String::from_utf8_lossy(&stdout).to_string())
+}
+
+// Fills the cache in dir by importing a hash of each of the given files, which are created.
+fn import_vids(dir: &Path, filenames: &[&str]) -> Vec<PathBuf> {
+    let paths = filenames
+        .iter()
+        .map(|filename| dir.join("vids").join(filename))
+        .collect::<Vec<_>>();
+    let hashes = paths
+        .iter()
+        .map(|path| {
+            std::fs::write(path, "not really a video").expect("can write file");
+            VideoHash::full_hash(path)
+                .with_duration(30)
+                .to_json_portable()
+                + "\n"
+        })
+        .collect::<String>();
+    let hashes_path = dir.join("hashes.jsonl");
+    std::fs::write(&hashes_path, hashes).expect("can write hashes");
+
+    let hashes_arg = hashes_path.to_str().expect("path is utf8");
+    assert_eq!(
+        cache_cmd(dir, &["import", hashes_arg, "--dry-run"]).0,
+        EXIT_CHANGES_NEEDED
+    );
+    let (code, stdout) = cache_cmd(dir, &["import", hashes_arg]);
+    assert_eq!(code, EXIT_OK);
+    assert_eq!(stdout.lines().count(), filenames.len());
+    assert!(stdout.lines().all(|line| line.starts_with("add ")));
+
+    //importing the same hashes again changes nothing.
+    assert_eq!(
+        cache_cmd(dir, &["import", hashes_arg, "--dry-run"]),
+        (EXIT_OK, String::new())
+    );
+
+    paths
+}
+
+#[test]
+fn test_cache_maintenance_commands() {
+    let temp_dir = test_dir();
+    let dir = temp_dir.path();
+    let paths = import_vids(dir, &["a.mp4", "b.mp4", "c.mp4"]);
+
+    let (code, stdout) = cache_cmd(dir, &["stats"]);
+    assert_eq!(code, EXIT_OK);
+    assert!(stdout.contains("Entries: 3"), "{stdout}");
+    assert!(stdout.contains("errors: 0"), "{stdout}");
+    assert!(stdout.contains("imported without stats: 3"), "{stdout}");
+
+    //nothing has changed since the import.
+    assert_eq!(cache_cmd(dir, &["verify", "--dry-run"]).0, EXIT_OK);
+    assert_eq!(cache_cmd(dir, &["prune-missing", "--dry-run"]).0, EXIT_OK);
+    assert_eq!(cache_cmd(dir, &["prune-errors", "--dry-run"]).0, EXIT_OK);
+
+    std::fs::write(&paths[0], "a longer file than before").expect("can write file");
+    std::fs::remove_file(&paths[1]).expect("can remove file");
+    let (code, stdout) = cache_cmd(dir, &["verify", "--dry-run"]);
+    assert_eq!(code, EXIT_CHANGES_NEEDED);
+    assert_eq!(
+        stdout.lines().collect::<Vec<_>>(),
+        [
+            format!("size changed: {}", paths[0].display()),
+            format!("missing: {}", paths[1].display()),
+        ]
+    );
+
+    //a dry run removes nothing.
+    let missing = format!("remove {}\n", paths[1].display());
+    assert_eq!(
+        cache_cmd(dir, &["prune-missing", "--dry-run"]),
+        (EXIT_CHANGES_NEEDED, missing.clone())
+    );
+    assert_eq!(cache_cmd(dir, &["prune-missing"]), (EXIT_OK, missing));
+    assert_eq!(
+        cache_cmd(dir, &["prune-missing", "--dry-run"]),
+        (EXIT_OK, String::new())
+    );
+
+    //exports are only rewritten when they would change.
+    let export_path = dir.join("export.jsonl");
+    let export_arg = export_path.to_str().expect("path is utf8");
+    assert_eq!(
+        cache_cmd(dir, &["export", export_arg, "--dry-run"]).0,
+        EXIT_CHANGES_NEEDED
+    );
+    assert!(!export_path.exists());
+    assert_eq!(cache_cmd(dir, &["export", export_arg]).0, EXIT_OK);
+    let exported = std::fs::read_to_string(&export_path).expect("export was written");
+    assert_eq!(exported.lines().count(), 2);
+    assert_eq!(
+        cache_cmd(dir, &["export", export_arg, "--dry-run"]),
+        (EXIT_OK, String::new())
+    );
+
+    //move the videos, and then forget them.
+    let vids = dir.join("vids");
+    let moved = dir.join("moved");
+    std::fs::rename(&vids, &moved).expect("can move dir");
+    let (vids_arg, moved_arg) = (
+        vids.to_str().expect("path is utf8"),
+        moved.to_str().expect("path is utf8"),
+    );
+    let (code, stdout) = cache_cmd(dir, &["move", vids_arg, moved_arg]);
+    assert_eq!(code, EXIT_OK);
+    assert_eq!(
+        stdout.lines().next(),
+        Some(
+            format!(
+                "move {} -> {}",
+                paths[0].display(),
+                moved.join("a.mp4").display()
+            )
+            .as_str()
+        )
+    );
+    assert_eq!(
+        cache_cmd(dir, &["verify", "--dry-run"]).0,
+        EXIT_CHANGES_NEEDED
+    );
+    assert_eq!(
+        cache_cmd(dir, &["prune-missing", "--dry-run"]),
+        (EXIT_OK, String::new())
+    );
+
+    assert_eq!(
+        cache_cmd(dir, &["forget", moved_arg, "--dry-run"]).0,
+        EXIT_CHANGES_NEEDED
+    );
+    assert_eq!(cache_cmd(dir, &["forget", moved_arg]).0, EXIT_OK);
+    let (_code, stdout) = cache_cmd(dir, &["stats"]);
+    assert!(stdout.contains("Entries: 0"), "{stdout}");
+}
+
+#[cfg(feature = "sqlite")]
+#[test]
+fn test_cache_migrate_to_sqlite() {
+    let temp_dir = test_dir();
+    let dir = temp_dir.path();
+    import_vids(dir, &["a.mp4", "b.mp4"]);
+
+    let sqlite_path = dir.join("cache.sqlite");
+    let sqlite_arg = sqlite_path.to_str().expect("path is utf8");
+    let migrated = format!("migrate 2 entries to {}\n", sqlite_path.display());
+    assert_eq!(
+        cache_cmd(dir, &["migrate-to-sqlite", sqlite_arg, "--dry-run"]),
+        (EXIT_CHANGES_NEEDED, migrated.clone())
+    );
+    assert!(!sqlite_path.exists());
+    assert_eq!(
+        cache_cmd(dir, &["migrate-to-sqlite", sqlite_arg]),
+        (EXIT_OK, migrated)
+    );
+
+    //the sqlite cache has the same entries, and the bincode cache is left as it is.
+    let output = Command::new(env!("CARGO_BIN_EXE_vid_dup_finder"))
+        .args(["cache", "stats", "--cache-file", sqlite_arg])
+        .output()
+        .expect("can run vid_dup_finder");
+    let stdout = String::from_utf8(output.stdout).expect("stdout is utf8");
+    assert!(stdout.contains("Entries: 2"), "{stdout}");
+    let (_code, stdout) = cache_cmd(dir, &["stats"]);
+    assert!(stdout.contains("Entries: 2"), "{stdout}");
+
+    //an existing sqlite cache is not overwritten.
+    let output = Command::new(env!("CARGO_BIN_EXE_vid_dup_finder"))
+        .args(["cache", "migrate-to-sqlite", sqlite_arg, "--cache-file"])
+        .arg(dir.join("cache.bin"))
+        .output()
+        .expect("can run vid_dup_finder");
+    assert!(!output.status.success());
+}
+
+#[test]
+fn test_cache_find_id() {
+    let temp_dir = test_dir();
+    let dir = temp_dir.path();
+    let paths = import_vids(dir, &["a.mp4"]);
+
+    let id = "67e55044-10b1-426f-9247-bb680e5fe0c8";
+    let ids = [
+        serde_json::json!({ "version": 1 }),
+        serde_json::json!({ "id": id, "path": paths[0] }),
+    ];
+    let ids = ids.map(|line| line.to_string()).join("\n");
+    std::fs::write(dir.join("cache.ids.jsonl"), ids).expect("can write ids");
+
+    assert_eq!(
+        cache_cmd(dir, &["find-id", id]),
+        (EXIT_OK, format!("{}\n", paths[0].display()))
+    );
+    assert_eq!(
+        cache_cmd(dir, &["find-id", "00000000-0000-0000-0000-000000000000"]),
+        (EXIT_OK, String::new())
+    );
+}
+
+#[test]
+fn test_cache_prune_errors() {
+    let temp_dir = test_dir();
+    let dir = temp_dir.path();
+    std::fs::copy(example_vid("cat.1.mp4"), dir.join("vids/cat.1.mp4")).expect("can copy vid");
+    std::fs::write(dir.join("vids/broken.mp4"), "not really a video").expect("can write file");
+
+    let status = Command::new(env!("CARGO_BIN_EXE_vid_dup_finder"))
+        .arg("--files")
+        .arg(dir.join("vids"))
+        .arg("--cache-file")
+        .arg(dir.join("cache.bin"))
+        .args(["--update-cache-only", "--quiet"])
+        .status()
+        .expect("can run vid_dup_finder");
+    assert!(status.success());
+
+    let (_code, stdout) = cache_cmd(dir, &["stats"]);
+    assert!(stdout.contains("hashes: 1"), "{stdout}");
+    assert!(stdout.contains("errors: 1"), "{stdout}");
+
+    let broken = format!("remove {}\n", dir.join("vids/broken.mp4").display());
+    assert_eq!(
+        cache_cmd(dir, &["prune-errors", "--dry-run"]),
+        (EXIT_CHANGES_NEEDED, broken.clone())
+    );
+    assert_eq!(cache_cmd(dir, &["prune-errors"]), (EXIT_OK, broken));
+    let (_code, stdout) = cache_cmd(dir, &["stats"]);
+    assert!(stdout.contains("Entries: 1"), "{stdout}");
+}
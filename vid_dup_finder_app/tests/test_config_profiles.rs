@@ -0,0 +1,116 @@
+use std::path::Path;
+use std::process::Command;
+
+mod common;
+use common::example_vid;
+
+fn copy_vids(dir: &Path, filenames: &[&str]) {
+    std::fs::create_dir_all(dir).expect("can create test dir");
+    for filename in filenames {
+        std::fs::copy(example_vid(filename), dir.join(filename)).expect("can copy example vid");
+    }
+}
+
+// Returns the groups of duplicates as sorted lists of filenames.
+fn run_search(dir: &Path, config_path: &Path) -> Vec<Vec<String>> {
+    let output = Command::new(env!("CARGO_BIN_EXE_vid_dup_finder"))
+        .arg("--files")
+        .arg(dir)
+        .arg("--cache-file")
+        .arg(dir.join("cache.bin"))
+        .arg("--config")
+        .arg(config_path)
+        .args(["--output-format", "json", "--quiet"])
+        .output()
+        .expect("can run vid_dup_finder");
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let groups: serde_json::Value = serde_json::from_slice(&output.stdout).expect("output is json");
+    let mut ret = groups
+        .as_array()
+        .expect("output is a list of groups")
+        .iter()
+        .map(|group| {
+            let mut filenames = group["duplicates"]
+                .as_array()
+                .expect("groups have duplicates")
+                .iter()
+                .map(|path| {
+                    let path = Path::new(path.as_str().expect("paths are strings"));
+                    path.file_name()
+                        .expect("paths are files")
+                        .to_string_lossy()
+                        .to_string()
+                })
+                .collect::<Vec<_>>();
+            filenames.sort();
+            filenames
+        })
+        .collect::<Vec<_>>();
+    ret.sort();
+    ret
+}
+
+#[test]
+fn test_two_profiles() {
+    let temp_dir = tempfile::tempdir().expect("can create test dir");
+    let dir = temp_dir.path();
+
+    copy_vids(&dir.join("cats"), &["cat.1.mp4", "cat.2.mp4", "cat.3.webm"]);
+    copy_vids(&dir.join("dogs"), &["dog.1.mp4", "dog.2.mp4"]);
+    //dog.3 would match the other dogs, but only videos in the same profile are compared.
+    copy_vids(&dir.join("others"), &["dog.3.webm"]);
+
+    //A path in two profiles is an error.
+    let config_path = dir.join("config.toml");
+    std::fs::write(
+        &config_path,
+        r#"
+            [profiles.everything]
+            paths = ["."]
+
+            [profiles.dogs]
+            paths = ["dogs"]
+        "#,
+    )
+    .expect("can write config");
+    let output = Command::new(env!("CARGO_BIN_EXE_vid_dup_finder"))
+        .arg("--files")
+        .arg(dir)
+        .arg("--config")
+        .arg(&config_path)
+        .output()
+        .expect("can run vid_dup_finder");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("covered by both profile"));
+
+    std::fs::write(
+        &config_path,
+        r#"
+            [profiles.cats]
+            paths = ["cats"]
+            tolerance = 0.3
+            cropdetect = "letterbox"
+
+            [profiles.dogs]
+            paths = ["dogs"]
+            tolerance = 0.3
+            cropdetect = "none"
+            duration = 5.0
+        "#,
+    )
+    .expect("can write config");
+
+    let expected = vec![
+        vec!["cat.1.mp4", "cat.2.mp4", "cat.3.webm"],
+        vec!["dog.1.mp4", "dog.2.mp4"],
+    ];
+    assert_eq!(run_search(dir, &config_path), expected);
+
+    //The second search uses the cached hashes, which must still be kept apart.
+    assert_eq!(run_search(dir, &config_path), expected);
+}
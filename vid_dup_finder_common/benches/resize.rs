@@ -0,0 +1,87 @@
+//! Compare resizing whole video frames down to the size of a hash (and to the proxies used for crop
+//! detection) with and without the box filter fast path, and cropping 4K frames by copying the crop
+//! out first against resizing straight from a view of it.
+//!
+//! Run with `cargo bench -p vid_dup_finder_common`
+use std::num::NonZeroU32;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use image::{GenericImageView, GrayImage, Luma};
+use vid_dup_finder_common::{
+    crop_flat, crop_resize_flat_with, crop_resize_view, Crop, ResizeMethod,
+};
+
+fn frame(width: u32, height: u32) -> GrayImage {
+    GrayImage::from_fn(width, height, |x, y| {
+        Luma([(x / 7 + y / 5 + (x * y) % 13) as u8])
+    })
+}
+
+fn bench_resize(c: &mut Criterion) {
+    let mut group = c.benchmark_group("crop_resize_flat");
+    for (name, (width, height)) in [("1080p", (1920, 1080)), ("4k", (3840, 2160))] {
+        let frame = frame(width, height);
+        let no_crop = Crop::from_edge_offsets((width, height), 0, 0, 0, 0);
+
+        for (new_width, new_height) in [(16, 16), (256, height * 256 / width)] {
+            let new_width = NonZeroU32::new(new_width).expect("nonzero");
+            let new_height = NonZeroU32::new(new_height).expect("nonzero");
+            let size = format!("{name} to {new_width}x{new_height}");
+
+            for (method_name, method) in [
+                ("auto", ResizeMethod::Auto),
+                ("convolution", ResizeMethod::Convolution),
+            ] {
+                group.bench_with_input(BenchmarkId::new(method_name, &size), &frame, |b, frame| {
+                    b.iter(|| {
+                        crop_resize_flat_with(
+                            frame.as_flat_samples(),
+                            new_width,
+                            new_height,
+                            no_crop,
+                            method,
+                        )
+                    });
+                });
+            }
+        }
+    }
+    group.finish();
+}
+
+fn bench_crop_resize(c: &mut Criterion) {
+    let mut group = c.benchmark_group("crop_resize_4k");
+    let frame = frame(3840, 2160);
+    //a letterboxed 2.39:1 picture.
+    let crop = Crop::from_edge_offsets(frame.dimensions(), 0, 0, 277, 277);
+    let (left, top, width, height) = crop.as_view_args();
+    let no_crop = Crop::from_edge_offsets((width, height), 0, 0, 0, 0);
+    let side = NonZeroU32::new(16).expect("nonzero");
+
+    group.bench_function("copy then resize", |b| {
+        b.iter(|| {
+            let copied = frame.view(left, top, width, height).to_image();
+            crop_resize_flat_with(
+                copied.as_flat_samples(),
+                side,
+                side,
+                no_crop,
+                ResizeMethod::Auto,
+            )
+        });
+    });
+    group.bench_function("flat view", |b| {
+        b.iter(|| {
+            let flat = frame.as_flat_samples();
+            let cropped = crop_flat(&flat, crop).expect("crop fits");
+            crop_resize_flat_with(cropped, side, side, no_crop, ResizeMethod::Auto)
+        });
+    });
+    group.bench_function("generic view", |b| {
+        b.iter(|| crop_resize_view(&frame, side, side, crop, ResizeMethod::Auto));
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_resize, bench_crop_resize);
+criterion_main!(benches);
@@ -1,4 +1,4 @@
-// use image::flat::SampleLayout;
+use image::{flat::SampleLayout, FlatSamples};
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Crop {
@@ -175,16 +175,47 @@ impl Crop {
         Some(ret)
     }
 
+    pub fn contains(&self, x: u32, y: u32) -> bool {
+        let (orig_x, orig_y) = self.orig_res;
+        (self.left..orig_x - self.right).contains(&x)
+            && (self.top..orig_y - self.bottom).contains(&y)
+    }
+
     pub fn is_uncropped(&self) -> bool {
         (self.left == 0) && (self.right == 0) && (self.top == 0) && (self.bottom == 0)
     }
 }
 
-//pub fn as_cropped(layout: SampleLayout, crop: Crop) -> SampleLayout {}
+/// The cropped part of an image, sharing the image's samples rather than copying them. The samples
+/// start at the top left corner of the crop, and the strides stay those of the whole image.
+///
+/// Returns None if the crop is empty, or does not fit inside the image.
+#[must_use]
+pub fn crop_flat<C: AsRef<[u8]>>(flat: &FlatSamples<C>, crop: Crop) -> Option<FlatSamples<&[u8]>> {
+    let layout = flat.layout;
+    let (left, top, width, height) = crop.as_view_args();
+    if width == 0
+        || height == 0
+        || left.checked_add(width)? > layout.width
+        || top.checked_add(height)? > layout.height
+    {
+        return None;
+    }
+
+    let offset = top as usize * layout.height_stride + left as usize * layout.width_stride;
+    Some(FlatSamples {
+        samples: flat.samples.as_ref().get(offset..)?,
+        layout: SampleLayout {
+            width,
+            height,
+            ..layout
+        },
+        color_hint: flat.color_hint,
+    })
+}
 
 impl Default for Crop {
     //an arbitrary 'enormous' crop suitable for initializing a fold/reduce
-    #[must_use]
     fn default() -> Self {
         Self {
             orig_res: (u32::MAX, u32::MAX),
@@ -198,10 +229,30 @@ impl Default for Crop {
 
 #[cfg(test)]
 mod test {
+    use image::GenericImageView;
     use itertools::Itertools;
 
     use super::*;
 
+    #[test]
+    fn test_crop_flat_shares_samples() {
+        let image = image::GrayImage::from_fn(8, 6, |x, y| image::Luma([(y * 8 + x) as u8]));
+        let flat = image.as_flat_samples();
+        let crop = Crop::from_edge_offsets((8, 6), 2, 1, 3, 1);
+
+        let cropped = crop_flat(&flat, crop).unwrap();
+        assert_eq!((cropped.layout.width, cropped.layout.height), (5, 2));
+        assert!(std::ptr::eq(
+            &cropped.samples[0],
+            &image.as_raw()[3 * 8 + 2]
+        ));
+        let view = cropped.as_view::<image::Luma<u8>>().unwrap();
+        assert_eq!(view.get_pixel(4, 1).0[0], 4 * 8 + 6);
+
+        //crops of bigger images do not fit.
+        assert!(crop_flat(&flat, Crop::from_edge_offsets((9, 6), 0, 0, 0, 0)).is_none());
+    }
+
     #[test]
     fn test_as_view_args_nocrop() {
         let crop = Crop::from_edge_offsets((100, 100), 0, 0, 0, 0);
@@ -13,15 +13,23 @@
 pub mod compositing;
 mod crop;
 pub mod motioncrop;
+mod panic_message;
+pub mod path_serde;
 pub mod resize_gray;
 pub mod resize_rgb;
+pub mod ssim;
 pub mod video_frames_gray;
 pub mod video_frames_rgb;
 
 pub use compositing::grid_images_rgb;
 pub use compositing::row_images;
-pub use crop::Crop;
+pub use crop::{crop_flat, Crop};
+pub use panic_message::panic_message;
 pub use resize_gray::crop_resize_buf;
 pub use resize_gray::crop_resize_flat;
+pub use resize_gray::crop_resize_flat_with;
+pub use resize_gray::crop_resize_view;
+pub use resize_gray::ResizeMethod;
+pub use ssim::ssim;
 pub use video_frames_gray::VideoFramesGray;
 pub use video_frames_rgb::FrameSeqRgb;
@@ -8,7 +8,10 @@ use image::{buffer::ConvertBuffer, GenericImageView, GrayImage, Luma, RgbImage};
 use super::{
     darkest_frame::DarkestFrame,
     frame_change::FrameChange,
-    utils::{colourize_regions, maskize_regions, regionize_image, tint_cropped_area, RgbChan},
+    utils::{
+        colourize_regions, maskize_regions, outline_cropped_area, regionize_image,
+        tint_cropped_area, RgbChan,
+    },
 };
 use crate::{
     crop::Crop,
@@ -16,6 +19,99 @@ use crate::{
     video_frames_gray::{LetterboxColour, VdfFrameExt},
 };
 
+//Changes in brightness smaller than this between neighbouring frames are treated as noise (as in
+//FrameChange) when measuring confidence.
+const MOTION_THRESHOLD: u8 = 8;
+
+//A pair of frames agrees with a crop if the contrast between the density of its motion inside and
+//outside the crop is at least this. 0.6 is motion four times denser inside than outside.
+const MIN_AGREEING_CONTRAST: f64 = 0.6;
+
+/// The crop chosen by [`MotiondetectCrop::detect`], with how it was chosen.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MotionCropResult {
+    pub crop: Crop,
+
+    /// How sure the detector is of the crop, between 0.0 and 1.0. This is the proportion of the
+    /// moving frames whose motion is mostly inside the crop, scaled by how much denser the motion
+    /// of all the frames is inside the crop than outside it. The confidence is 0.0 if nothing
+    /// moves, or if the crop is the whole frame (so that the motion says nothing about it).
+    pub confidence: f64,
+
+    /// The indices of the frames whose motion the confidence was measured from: those which differ
+    /// from the frame before or after them.
+    pub contributing_frames: Vec<usize>,
+}
+
+impl MotionCropResult {
+    fn measure(frames: &[GrayImage], crop: Crop) -> Self {
+        let (width, height) = crop.orig_res;
+        let area_in = f64::from(crop.area());
+        let area_out = f64::from(width * height - crop.area());
+
+        //between 0.0 if the motion is no denser inside the crop, and 1.0 if it is all inside.
+        let contrast = |energy_in: u64, energy_out: u64| {
+            if area_out == 0.0 {
+                return 0.0;
+            }
+            if energy_out == 0 {
+                return 1.0;
+            }
+            let density_in = energy_in as f64 / area_in;
+            let density_out = energy_out as f64 / area_out;
+            ((density_in - density_out) / (density_in + density_out)).max(0.0)
+        };
+
+        let mut contributing = vec![false; frames.len()];
+        let (mut moving_pairs, mut agreeing_pairs) = (0, 0);
+        let (mut energy_in, mut energy_out) = (0, 0);
+        for (i, (frame_a, frame_b)) in frames.iter().tuple_windows().enumerate() {
+            let (mut pair_in, mut pair_out) = (0u64, 0u64);
+            for ((x, y, Luma([a_pix])), Luma([b_pix])) in
+                frame_a.enumerate_pixels().zip(frame_b.pixels())
+            {
+                let diff = a_pix.abs_diff(*b_pix);
+                if diff < MOTION_THRESHOLD {
+                    continue;
+                }
+                if crop.contains(x, y) {
+                    pair_in += u64::from(diff);
+                } else {
+                    pair_out += u64::from(diff);
+                }
+            }
+
+            if pair_in + pair_out == 0 {
+                continue;
+            }
+            moving_pairs += 1;
+            contributing[i] = true;
+            contributing[i + 1] = true;
+            if contrast(pair_in, pair_out) >= MIN_AGREEING_CONTRAST {
+                agreeing_pairs += 1;
+            }
+            energy_in += pair_in;
+            energy_out += pair_out;
+        }
+
+        let confidence = if moving_pairs == 0 {
+            0.0
+        } else {
+            f64::from(agreeing_pairs) / f64::from(moving_pairs) * contrast(energy_in, energy_out)
+        };
+
+        Self {
+            crop,
+            confidence,
+            contributing_frames: contributing
+                .into_iter()
+                .enumerate()
+                .filter_map(|(i, contributed)| contributed.then_some(i))
+                .collect(),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct MotiondetectCrop {
     _crop: (),
@@ -36,11 +132,25 @@ impl MotiondetectCrop {
     pub fn from_frames(
         frames: impl IntoIterator<Item = impl Deref<Target = GrayImage>>,
     ) -> Option<Crop> {
+        Self::detect(frames).map(|result| result.crop)
+    }
+
+    /// As [`Self::from_frames`], but also reports how confident the detector is of the crop, and
+    /// which frames it was based on.
+    ///
+    /// If `AUTOCROP_DEBUG_IMG_DIR` is set, the contributing frames are saved there with the crop
+    /// outlined in red.
+    #[must_use]
+    pub fn detect(
+        frames: impl IntoIterator<Item = impl Deref<Target = GrayImage>>,
+    ) -> Option<MotionCropResult> {
         //for now, we need a mutable copy of all of the frames to do this crop :(
         let mut frames = frames
             .into_iter()
             .map(|f| f.deref().clone())
             .collect::<Vec<_>>();
+        //the chosen crop is measured against the frames as they were given.
+        let orig_frames = frames.clone();
 
         if frames.len() < 2 {
             return None;
@@ -141,6 +251,14 @@ impl MotiondetectCrop {
             }
         }
 
+        let measure = |crop| {
+            let ret = MotionCropResult::measure(&orig_frames, crop);
+            if let Some(debug_dir) = debug_img_dir() {
+                save_contributing_frames(&debug_dir, &orig_frames, &ret);
+            }
+            ret
+        };
+
         let crop_1 = Self::from_frames_one(&frames);
 
         let first_frame = frames[0].clone();
@@ -168,7 +286,7 @@ impl MotiondetectCrop {
         let crops = [crop_1, crop_2].into_iter().flatten().collect::<Vec<_>>();
 
         if crops.is_empty() {
-            return Some(letterbox_crop);
+            return Some(measure(letterbox_crop));
         }
 
         let filtered_crops = crops.iter().copied();
@@ -216,7 +334,7 @@ impl MotiondetectCrop {
                 .unwrap();
         }
 
-        Some(ret)
+        Some(measure(ret))
     }
 
     #[allow(clippy::new_without_default)]
@@ -311,6 +429,16 @@ impl MotiondetectCrop {
     }
 }
 
+fn save_contributing_frames(debug_dir: &str, frames: &[GrayImage], result: &MotionCropResult) {
+    std::fs::create_dir_all(debug_dir).ok();
+    for &i in &result.contributing_frames {
+        let frame: RgbImage = frames[i].convert();
+        outline_cropped_area(&frame, result.crop, RgbChan::Red)
+            .save(format!("{debug_dir}/frame_{i}.png"))
+            .unwrap();
+    }
+}
+
 fn debug_img_dir() -> Option<String> {
     std::env::var("AUTOCROP_DEBUG_IMG_DIR").ok()
 }
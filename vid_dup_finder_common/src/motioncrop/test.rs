@@ -225,6 +225,59 @@ fn test_detect_botright() {
     assert_eq!(exp, act);
 }
 
+//A video in a box, on a light static background. The video is of stripes which move one pixel to the
+//right in each frame. Every pixel of every frame, inside the box or not, has noise of up to
+//noise_level added to it.
+fn moving_box_frames(noise_level: u8, seed: u64) -> Vec<GrayImage> {
+    use rand::prelude::*;
+
+    let mut rng = StdRng::seed_from_u64(seed);
+    (0..16)
+        .map(|t| {
+            GrayImage::from_fn(64, 48, |x, y| {
+                let pix = if (16..48).contains(&x) && (8..32).contains(&y) {
+                    40 + ((x + 64 - t) % 8) * 16
+                } else {
+                    235
+                };
+                let noise = rng.gen_range(-i32::from(noise_level)..=i32::from(noise_level));
+                image::Luma([(pix as i32 + noise).clamp(0, 255) as u8])
+            })
+        })
+        .collect()
+}
+
+#[test]
+fn test_confidence_falls_with_noise() {
+    let results = [0, 4, 8, 16, 32, 64]
+        .map(|noise_level| MotiondetectCrop::detect(&moving_box_frames(noise_level, 1)).unwrap());
+
+    //without noise the box is found, from every frame.
+    let clean = &results[0];
+    assert_eq!(
+        clean.crop,
+        Crop::from_edge_offsets((64, 48), 18, 18, 10, 18)
+    );
+    assert!(clean.confidence > 0.8);
+    assert_eq!(clean.contributing_frames, (0..16).collect::<Vec<_>>());
+
+    for pair in results.windows(2) {
+        assert!(pair[0].confidence >= pair[1].confidence);
+    }
+    assert!(results[2].confidence > 0.5);
+    //by this point the noise in the background moves as much as the video does.
+    assert_eq!(results[4].confidence, 0.0);
+}
+
+#[test]
+fn test_static_frames_have_no_confidence() {
+    let frames = moving_box_frames(0, 1);
+    let still = [&frames[0], &frames[0], &frames[0]].map(|frame| (*frame).clone());
+    let result = MotiondetectCrop::detect(&still).unwrap();
+    assert_eq!(result.confidence, 0.0);
+    assert!(result.contributing_frames.is_empty());
+}
+
 //takes a series of vectors describing an image, and turns them into a sequence of images for running
 //the autocrop algorithm over
 fn util_generate_frames(
@@ -149,6 +149,26 @@ pub(super) fn tint_cropped_area(img: &RgbImage, crop: Crop, chan: RgbChan) -> Rg
     ret
 }
 
+//Draw a one pixel border just inside the crop.
+pub(super) fn outline_cropped_area(img: &RgbImage, crop: Crop, chan: RgbChan) -> RgbImage {
+    let mut ret = img.clone();
+    let (x, y, width, height) = crop.as_view_args();
+    let (last_x, last_y) = (x + width - 1, y + height - 1);
+
+    for (x, y) in crop.enumerate_coords() {
+        if x != crop.left && x != last_x && y != crop.top && y != last_y {
+            continue;
+        }
+        let pix = ret.get_pixel_mut(x, y);
+        *pix = match chan {
+            RgbChan::Red => Rgb([255, 0, 0]),
+            RgbChan::Green => Rgb([0, 255, 0]),
+            RgbChan::Blue => Rgb([0, 0, 255]),
+        };
+    }
+    ret
+}
+
 pub(super) fn colourize_regions(img: &Image<Luma<u32>>) -> RgbImage {
     let colours = [
         Rgb::<u8>([0, 0, 255]),
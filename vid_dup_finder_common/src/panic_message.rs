@@ -0,0 +1,28 @@
+/// The message of a panic, from the payload caught by [`std::panic::catch_unwind`].
+#[must_use]
+pub fn panic_message(payload: &(dyn std::any::Any + Send)) -> String {
+    payload
+        .downcast_ref::<&str>()
+        .map(|s| (*s).to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown panic".to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use std::panic;
+
+    use super::*;
+
+    #[test]
+    fn test_panic_message() {
+        let message = |f: fn()| panic_message(panic::catch_unwind(f).unwrap_err().as_ref());
+
+        assert_eq!(message(|| panic!("static")), "static");
+        assert_eq!(message(|| panic!("formatted {}", 1)), "formatted 1");
+        assert_eq!(
+            message(|| panic::panic_any(1_u32)),
+            "unknown panic".to_string()
+        );
+    }
+}
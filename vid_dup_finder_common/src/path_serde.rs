@@ -0,0 +1,82 @@
+//! Serde functions for paths which may not be valid UTF-8, for use with
+//! `#[serde(with = "vid_dup_finder_common::path_serde")]`.
+//!
+//! Serde's own implementation for [`Path`] refuses to serialize paths which are not valid UTF-8,
+//! which on unix is any path containing arbitrary bytes. Here, binary formats always store the
+//! raw bytes of the path. Human readable formats store valid UTF-8 paths as strings (as serde
+//! does) and any other path as an array of bytes.
+//!
+//! For binary formats such as bincode, strings and bytes are encoded the same way, so data
+//! written with serde's implementation can still be read.
+
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use serde::{
+    de::{self, SeqAccess, Visitor},
+    Deserializer, Serializer,
+};
+
+/// Serialize a path without losing any bytes.
+pub fn serialize<S: Serializer>(path: &Path, serializer: S) -> Result<S::Ok, S::Error> {
+    match path.to_str() {
+        Some(s) if serializer.is_human_readable() => serializer.serialize_str(s),
+        _ => serializer.serialize_bytes(path.as_os_str().as_encoded_bytes()),
+    }
+}
+
+/// Deserialize a path written by [`serialize`], or by serde's implementation for [`Path`].
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<PathBuf, D::Error> {
+    if deserializer.is_human_readable() {
+        deserializer.deserialize_any(PathVisitor)
+    } else {
+        deserializer.deserialize_byte_buf(PathVisitor)
+    }
+}
+
+struct PathVisitor;
+
+impl<'de> Visitor<'de> for PathVisitor {
+    type Value = PathBuf;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a path, as a string or as bytes")
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+        Ok(PathBuf::from(v))
+    }
+
+    fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<Self::Value, E> {
+        path_from_bytes(v.to_vec())
+    }
+
+    fn visit_byte_buf<E: de::Error>(self, v: Vec<u8>) -> Result<Self::Value, E> {
+        path_from_bytes(v)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or_default());
+        while let Some(byte) = seq.next_element()? {
+            bytes.push(byte);
+        }
+        path_from_bytes(bytes)
+    }
+}
+
+#[cfg(unix)]
+fn path_from_bytes<E: de::Error>(bytes: Vec<u8>) -> Result<PathBuf, E> {
+    use std::{ffi::OsString, os::unix::ffi::OsStringExt};
+
+    Ok(PathBuf::from(OsString::from_vec(bytes)))
+}
+
+// Elsewhere, only paths which are valid UTF-8 can be safely rebuilt from their bytes.
+#[cfg(not(unix))]
+fn path_from_bytes<E: de::Error>(bytes: Vec<u8>) -> Result<PathBuf, E> {
+    String::from_utf8(bytes)
+        .map(PathBuf::from)
+        .map_err(|e| E::custom(format!("path is not valid UTF-8: {e}")))
+}
@@ -1,6 +1,6 @@
 use std::{borrow::Borrow, num::NonZeroU32, ops::Deref};
 
-use image::{FlatSamples, GrayImage, ImageBuffer, Luma};
+use image::{FlatSamples, GenericImageView, GrayImage, ImageBuffer, Luma};
 
 use crate::Crop;
 
@@ -8,6 +8,9 @@ use fast_image_resize as fr;
 use fr::CropBox;
 use fr::DynamicImageView::U8 as DynView;
 
+/// Images shrunk by at least this factor in both directions are resized with [`box_downscale`].
+pub const BOX_DOWNSCALE_MIN_FACTOR: u32 = 4;
+
 #[must_use]
 pub fn crop_resize_buf<I, C>(
     src_frame: I,
@@ -43,7 +46,7 @@ where
         .unwrap();
 
     let mut dst_frame_buf =
-        vec![0u8; u32::from(new_height) as usize * u32::from(new_height) as usize];
+        vec![0u8; u32::from(new_width) as usize * u32::from(new_height) as usize];
     let mut dst_frame_fr = fr::Image::from_slice_u8(
         new_width,
         new_height,
@@ -64,6 +67,18 @@ where
     dst_frame_img.unwrap()
 }
 
+/// How [`crop_resize_flat_with`] resizes images.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ResizeMethod {
+    /// Use [`box_downscale`] when shrinking by at least [`BOX_DOWNSCALE_MIN_FACTOR`] in both
+    /// directions, and a Lanczos3 convolution otherwise.
+    #[default]
+    Auto,
+
+    /// Always use a Lanczos3 convolution.
+    Convolution,
+}
+
 ///Crop an image, then resize it.
 #[must_use]
 pub fn crop_resize_flat<C>(
@@ -72,6 +87,21 @@ pub fn crop_resize_flat<C>(
     new_height: NonZeroU32,
     crop: Crop,
 ) -> Option<GrayImage>
+where
+    C: AsRef<[u8]>,
+{
+    crop_resize_flat_with(src_frame, new_width, new_height, crop, ResizeMethod::Auto)
+}
+
+///Crop an image, then resize it with the given method.
+#[must_use]
+pub fn crop_resize_flat_with<C>(
+    src_frame: FlatSamples<C>,
+    new_width: NonZeroU32,
+    new_height: NonZeroU32,
+    crop: Crop,
+    method: ResizeMethod,
+) -> Option<GrayImage>
 where
     C: AsRef<[u8]>,
 {
@@ -98,8 +128,28 @@ where
     let src_frame_raw: &[u8] = src_frame.as_slice();
     let old_width = src_frame.layout.width as usize;
     let old_height_stride = src_frame.layout.height_stride;
+
+    //Large reductions (e.g. whole video frames down to the size of a hash) are much quicker with a
+    //box filter, which for these sizes gives almost the same result.
+    let factor = |old: NonZeroU32, new: NonZeroU32| u32::from(old) / u32::from(new);
+    if method == ResizeMethod::Auto
+        && factor(width, new_width) >= BOX_DOWNSCALE_MIN_FACTOR
+        && factor(height, new_height) >= BOX_DOWNSCALE_MIN_FACTOR
+    {
+        let rows = src_frame_raw
+            .chunks(old_height_stride)
+            .skip(top as usize)
+            .take(u32::from(height) as usize)
+            .map(|row| &row[left as usize..left as usize + u32::from(width) as usize]);
+        if let Some(frame) = box_downscale(rows, width, height, new_width, new_height) {
+            return Some(frame);
+        }
+    }
+
+    //the last row may be shorter than the stride, if the samples are a view of a bigger image.
     let rows = src_frame_raw
-        .chunks_exact(old_height_stride)
+        .chunks(old_height_stride)
+        .take(u32::from(src_frame_height) as usize)
         .map(|chunk| {
             let row_slice = &chunk[0..old_width];
             unsafe { std::mem::transmute(row_slice) }
@@ -127,7 +177,7 @@ where
     }
 
     let mut dst_frame_buf =
-        vec![0u8; u32::from(new_height) as usize * u32::from(new_height) as usize];
+        vec![0u8; u32::from(new_width) as usize * u32::from(new_height) as usize];
     let mut dst_frame_fr = fr::Image::from_slice_u8(
         new_width,
         new_height,
@@ -151,6 +201,137 @@ where
     dst_frame_img
 }
 
+///Crop an image, then resize it with the given method, reading its pixels through
+///[`GenericImageView`]. This suits images which are views of some other buffer (such as
+///[`image::SubImage`] or [`image::flat::View`]), as the crop is never copied out of them when the
+///box filter is used. Otherwise only the cropped area is copied, in a single pass.
+///
+///The result is the same as cropping the image into a buffer of its own and then resizing that with
+///[`crop_resize_flat_with`].
+#[must_use]
+pub fn crop_resize_view<V>(
+    src_frame: &V,
+    new_width: NonZeroU32,
+    new_height: NonZeroU32,
+    crop: Crop,
+    method: ResizeMethod,
+) -> Option<GrayImage>
+where
+    V: GenericImageView<Pixel = Luma<u8>>,
+{
+    let (src_width, src_height) = src_frame.dimensions();
+    let (left, top, width, height) = crop.as_view_args();
+    let width = NonZeroU32::new(width.min(src_width.saturating_sub(left)))?;
+    let height = NonZeroU32::new(height.min(src_height.saturating_sub(top)))?;
+
+    let factor = |old: NonZeroU32, new: NonZeroU32| u32::from(old) / u32::from(new);
+    if method == ResizeMethod::Auto
+        && factor(width, new_width) >= BOX_DOWNSCALE_MIN_FACTOR
+        && factor(height, new_height) >= BOX_DOWNSCALE_MIN_FACTOR
+    {
+        let add_row = |y: usize, col_sums: &mut [u32]| {
+            for (x, sum) in col_sums.iter_mut().enumerate() {
+                *sum += u32::from(src_frame.get_pixel(left + x as u32, top + y as u32).0[0]);
+            }
+            Some(())
+        };
+        if let Some(frame) = box_downscale_with(width, height, new_width, new_height, add_row) {
+            return Some(frame);
+        }
+    }
+
+    let cropped = GrayImage::from_fn(width.into(), height.into(), |x, y| {
+        src_frame.get_pixel(left + x, top + y)
+    });
+    let no_crop = Crop::from_edge_offsets(cropped.dimensions(), 0, 0, 0, 0);
+    crop_resize_flat_with(
+        cropped.as_flat_samples(),
+        new_width,
+        new_height,
+        no_crop,
+        ResizeMethod::Convolution,
+    )
+}
+
+/// Shrink an image by averaging the block of source pixels that falls within each output pixel.
+/// The rows of each band of blocks are first added up column by column, and then each block's
+/// columns are added together. Everything is done in integers, so the result is exact and the
+/// same on every platform.
+///
+/// This is much quicker than a convolution. For large reductions of natural images the result is
+/// within a gray level of a Lanczos3 convolution, but for smaller reductions the box filter's
+/// blockiness starts to show, which is why [`crop_resize_flat`] only uses it to shrink by at least
+/// [`BOX_DOWNSCALE_MIN_FACTOR`].
+///
+/// Returns None if there are not exactly `height` rows of `width` pixels, if either dimension would
+/// be enlarged, or if a block is so large that its sum could overflow.
+pub fn box_downscale<'a>(
+    rows: impl IntoIterator<Item = &'a [u8]>,
+    width: NonZeroU32,
+    height: NonZeroU32,
+    new_width: NonZeroU32,
+    new_height: NonZeroU32,
+) -> Option<GrayImage> {
+    let mut rows = rows.into_iter();
+    let add_row = |_, col_sums: &mut [u32]| {
+        let row = rows.next().filter(|row| row.len() == col_sums.len())?;
+        for (sum, &px) in col_sums.iter_mut().zip(row) {
+            *sum += u32::from(px);
+        }
+        Some(())
+    };
+    let ret = box_downscale_with(width, height, new_width, new_height, add_row)?;
+    rows.next().is_none().then_some(ret)
+}
+
+// As box_downscale, but add_row adds each row (counting from 0) to the sums of the columns, or
+// returns None if it cannot.
+fn box_downscale_with(
+    width: NonZeroU32,
+    height: NonZeroU32,
+    new_width: NonZeroU32,
+    new_height: NonZeroU32,
+    mut add_row: impl FnMut(usize, &mut [u32]) -> Option<()>,
+) -> Option<GrayImage> {
+    // Where each block starts, followed by the end of the last block. The blocks along each side
+    // differ in size by at most one pixel.
+    fn block_starts(old: NonZeroU32, new: NonZeroU32) -> Vec<usize> {
+        let (old, new) = (u64::from(u32::from(old)), u64::from(u32::from(new)));
+        (0..=new).map(|i| (i * old / new) as usize).collect()
+    }
+
+    if new_width > width || new_height > height {
+        return None;
+    }
+    let max_block = u32::from(width).div_ceil(u32::from(new_width))
+        * u32::from(height).div_ceil(u32::from(new_height));
+    max_block.checked_mul(u32::from(u8::MAX))?;
+
+    let col_starts = block_starts(width, new_width);
+    let row_starts = block_starts(height, new_height);
+    let width = u32::from(width) as usize;
+
+    let mut col_sums = vec![0u32; width];
+    let mut pixels =
+        Vec::with_capacity(u32::from(new_width) as usize * u32::from(new_height) as usize);
+
+    for row_range in row_starts.windows(2) {
+        col_sums.fill(0);
+        for y in row_range[0]..row_range[1] {
+            add_row(y, &mut col_sums)?;
+        }
+
+        let block_height = row_range[1] - row_range[0];
+        for col_range in col_starts.windows(2) {
+            let sum = col_sums[col_range[0]..col_range[1]].iter().sum::<u32>();
+            let count = (block_height * (col_range[1] - col_range[0])) as u32;
+            pixels.push(((sum + count / 2) / count) as u8);
+        }
+    }
+
+    GrayImage::from_vec(new_width.into(), new_height.into(), pixels)
+}
+
 #[must_use]
 pub fn resize_frame<I, C>(frame: I, new_width: NonZeroU32, new_height: NonZeroU32) -> GrayImage
 where
@@ -166,3 +347,157 @@ where
 
     crop_resize_flat(flat, new_width, new_height, zero_crop).unwrap()
 }
+
+#[cfg(test)]
+mod test {
+    use std::f64::consts::TAU;
+
+    use rand::prelude::*;
+
+    use super::*;
+
+    // A smooth random image, made of a few waves whose amplitude falls with their frequency like
+    // the detail in photos does.
+    fn natural_image(rng: &mut impl Rng, width: u32, height: u32) -> GrayImage {
+        let base = rng.gen_range(60.0..190.0);
+        let waves = (0..4)
+            .map(|_| {
+                let (fx, fy) = (rng.gen_range(0.0..3.0), rng.gen_range(0.0..3.0));
+                let amplitude = rng.gen_range(5.0..30.0) / (1.0 + fx + fy);
+                (fx, fy, rng.gen_range(0.0..TAU), amplitude)
+            })
+            .collect::<Vec<_>>();
+
+        GrayImage::from_fn(width, height, |x, y| {
+            let (x, y) = (
+                f64::from(x) / f64::from(width),
+                f64::from(y) / f64::from(height),
+            );
+            let luma = waves
+                .iter()
+                .map(|(fx, fy, phase, amplitude)| {
+                    amplitude * (TAU * (fx * x + fy * y) + phase).sin()
+                })
+                .sum::<f64>();
+            Luma([(base + luma).clamp(0.0, 255.0) as u8])
+        })
+    }
+
+    fn resize(
+        frame: &GrayImage,
+        new_width: u32,
+        new_height: u32,
+        method: ResizeMethod,
+    ) -> GrayImage {
+        let no_crop = Crop::from_edge_offsets(frame.dimensions(), 0, 0, 0, 0);
+        let new_width = NonZeroU32::new(new_width).unwrap();
+        let new_height = NonZeroU32::new(new_height).unwrap();
+        crop_resize_flat_with(
+            frame.as_flat_samples(),
+            new_width,
+            new_height,
+            no_crop,
+            method,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_fast_path_matches_convolution() {
+        let mut rng = StdRng::seed_from_u64(0);
+        for ((width, height), (new_width, new_height)) in [
+            ((1920, 1080), (16, 16)),
+            ((1920, 1080), (256, 144)),
+            ((640, 480), (16, 16)),
+            ((641, 479), (13, 17)),
+        ] {
+            for _ in 0..4 {
+                let frame = natural_image(&mut rng, width, height);
+                let fast = resize(&frame, new_width, new_height, ResizeMethod::Auto);
+                let slow = resize(&frame, new_width, new_height, ResizeMethod::Convolution);
+                assert_eq!(fast.dimensions(), (new_width, new_height));
+
+                let max_diff = fast
+                    .pixels()
+                    .zip(slow.pixels())
+                    .map(|(fast, slow)| fast.0[0].abs_diff(slow.0[0]))
+                    .max();
+                assert!(
+                    max_diff <= Some(1),
+                    "{width}x{height} -> {new_width}x{new_height}: {max_diff:?}"
+                );
+
+                //no state is carried between resizes.
+                assert_eq!(
+                    fast,
+                    resize(&frame, new_width, new_height, ResizeMethod::Auto)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_crop_is_respected_by_fast_path() {
+        //a bright frame with a black border, which is cropped away.
+        let frame = GrayImage::from_fn(400, 300, |x, y| {
+            let inside = (40..360).contains(&x) && (30..270).contains(&y);
+            Luma([if inside { 200 } else { 0 }])
+        });
+        let crop = Crop::from_edge_offsets((400, 300), 40, 40, 30, 30);
+        let side = NonZeroU32::new(16).unwrap();
+
+        let small = crop_resize_flat(frame.as_flat_samples(), side, side, crop).unwrap();
+        assert!(small.pixels().all(|px| px.0[0] == 200));
+    }
+
+    #[test]
+    fn test_views_match_cropped_copies() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let frame = natural_image(&mut rng, 640, 480);
+        let crop = Crop::from_edge_offsets(frame.dimensions(), 40, 24, 30, 10);
+        let (left, top, width, height) = crop.as_view_args();
+        let copied = frame.view(left, top, width, height).to_image();
+        let no_crop = Crop::from_edge_offsets(copied.dimensions(), 0, 0, 0, 0);
+        let nz = |n| NonZeroU32::new(n).unwrap();
+
+        //the first size is small enough for the box filter, and the second is not.
+        for (new_width, new_height) in [(16, 16), (300, 220)] {
+            for method in [ResizeMethod::Auto, ResizeMethod::Convolution] {
+                let (new_width, new_height) = (nz(new_width), nz(new_height));
+                let expected = crop_resize_flat_with(
+                    copied.as_flat_samples(),
+                    new_width,
+                    new_height,
+                    no_crop,
+                    method,
+                )
+                .unwrap();
+
+                let from_view = crop_resize_view(&frame, new_width, new_height, crop, method);
+                assert_eq!(from_view.as_ref(), Some(&expected), "{method:?}");
+
+                let flat = frame.as_flat_samples();
+                let cropped_flat = crate::crop_flat(&flat, crop).unwrap();
+                let from_flat =
+                    crop_resize_flat_with(cropped_flat, new_width, new_height, no_crop, method);
+                assert_eq!(from_flat.as_ref(), Some(&expected), "{method:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_box_downscale_averages_blocks() {
+        //each 3x2 block of the source holds the values 0..6, so every block averages to 2.5
+        let frame = GrayImage::from_fn(9, 4, |x, y| Luma([(x % 3 + y % 2 * 3) as u8]));
+        let nz = |n| NonZeroU32::new(n).unwrap();
+        let src_rows = frame.as_raw().chunks(9);
+        let small = box_downscale(src_rows, nz(9), nz(4), nz(3), nz(2)).unwrap();
+        assert!(small.pixels().all(|px| px.0[0] == 3));
+
+        //too few rows, or enlarging, is refused.
+        assert!(
+            box_downscale(frame.as_raw().chunks(9).take(3), nz(9), nz(4), nz(3), nz(2)).is_none()
+        );
+        assert!(box_downscale(frame.as_raw().chunks(9), nz(9), nz(4), nz(18), nz(2)).is_none());
+    }
+}
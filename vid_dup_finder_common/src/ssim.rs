@@ -0,0 +1,119 @@
+//! The structural similarity (SSIM) of two grayscale images.
+//!
+//! SSIM compares the local means, contrasts and structure of two images rather than their pixels,
+//! so it scores re-encoded copies of a picture close to 1.0 and different pictures well below it,
+//! even when their overall brightness is similar.
+
+use image::GrayImage;
+
+// The side of the square windows that the statistics are gathered over, and the distance between
+// the corners of neighbouring windows. Uniformly weighted 8x8 windows at half overlap are a common
+// cheaper alternative to the Gaussian windows of the original paper, with very similar results.
+const WINDOW: u32 = 8;
+const STEP: u32 = 4;
+
+// Stabilise the division in windows which are nearly flat, as in the original paper.
+const C1: f64 = (0.01 * 255.0) * (0.01 * 255.0);
+const C2: f64 = (0.03 * 255.0) * (0.03 * 255.0);
+
+/// The mean SSIM of the two images, from -1.0 to 1.0, where 1.0 means that they are identical.
+///
+/// Images smaller than 8 pixels along either side are compared as a single window. Returns None if
+/// the images are of different sizes or are empty.
+#[must_use]
+pub fn ssim(a: &GrayImage, b: &GrayImage) -> Option<f64> {
+    let (width, height) = a.dimensions();
+    if b.dimensions() != (width, height) || width == 0 || height == 0 {
+        return None;
+    }
+
+    //the starts of the windows along one side, the last of which ends at the edge.
+    let starts = |len: u32| {
+        let window = WINDOW.min(len);
+        let last = len - window;
+        let mut starts = (0..=last).step_by(STEP as usize).collect::<Vec<_>>();
+        if starts.last() != Some(&last) {
+            starts.push(last);
+        }
+        (window, starts)
+    };
+    let (window_width, xs) = starts(width);
+    let (window_height, ys) = starts(height);
+
+    let mut total = 0.0;
+    for &y in &ys {
+        for &x in &xs {
+            total += window_ssim(a, b, (x, y), (window_width, window_height));
+        }
+    }
+    Some(total / (xs.len() * ys.len()) as f64)
+}
+
+fn window_ssim(a: &GrayImage, b: &GrayImage, (x0, y0): (u32, u32), (w, h): (u32, u32)) -> f64 {
+    let (mut sum_a, mut sum_b, mut sum_aa, mut sum_bb, mut sum_ab) = (0.0, 0.0, 0.0, 0.0, 0.0);
+    for y in y0..y0 + h {
+        for x in x0..x0 + w {
+            let pa = f64::from(a.get_pixel(x, y).0[0]);
+            let pb = f64::from(b.get_pixel(x, y).0[0]);
+            sum_a += pa;
+            sum_b += pb;
+            sum_aa += pa * pa;
+            sum_bb += pb * pb;
+            sum_ab += pa * pb;
+        }
+    }
+
+    let n = f64::from(w * h);
+    let (mean_a, mean_b) = (sum_a / n, sum_b / n);
+    let var_a = sum_aa / n - mean_a * mean_a;
+    let var_b = sum_bb / n - mean_b * mean_b;
+    let covariance = sum_ab / n - mean_a * mean_b;
+
+    ((2.0 * mean_a * mean_b + C1) * (2.0 * covariance + C2))
+        / ((mean_a * mean_a + mean_b * mean_b + C1) * (var_a + var_b + C2))
+}
+
+#[cfg(test)]
+mod test {
+    use image::Luma;
+    use rand::prelude::*;
+
+    use super::*;
+
+    fn noise(width: u32, height: u32, seed: u64) -> GrayImage {
+        let mut rng = StdRng::seed_from_u64(seed);
+        GrayImage::from_fn(width, height, |_, _| Luma([rng.gen()]))
+    }
+
+    #[test]
+    fn test_identical_images_score_one() {
+        let image = noise(64, 48, 1);
+        let score = ssim(&image, &image).unwrap();
+        assert!((score - 1.0).abs() < 1e-9, "{score}");
+
+        let flat = GrayImage::from_pixel(5, 3, Luma([90]));
+        let score = ssim(&flat, &flat).unwrap();
+        assert!((score - 1.0).abs() < 1e-9, "{score}");
+    }
+
+    #[test]
+    fn test_different_images_score_low() {
+        let (a, b) = (noise(64, 48, 1), noise(64, 48, 2));
+        assert!(ssim(&a, &b).unwrap() < 0.1);
+
+        //a small change in brightness matters much less than a change in structure.
+        let brighter = GrayImage::from_fn(64, 48, |x, y| {
+            Luma([a.get_pixel(x, y).0[0].saturating_add(8)])
+        });
+        assert!(ssim(&a, &brighter).unwrap() > 0.9);
+
+        let inverted = GrayImage::from_fn(64, 48, |x, y| Luma([255 - a.get_pixel(x, y).0[0]]));
+        assert!(ssim(&a, &inverted).unwrap() < 0.0);
+    }
+
+    #[test]
+    fn test_images_of_different_sizes() {
+        assert_eq!(ssim(&noise(64, 48, 1), &noise(48, 64, 1)), None);
+        assert_eq!(ssim(&GrayImage::new(0, 0), &GrayImage::new(0, 0)), None);
+    }
+}
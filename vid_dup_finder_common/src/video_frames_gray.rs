@@ -1,6 +1,9 @@
 use image::{GenericImageView, GrayImage, Luma, SubImage};
 
-use crate::{crop::Crop, motioncrop::autocrop_frames::MotiondetectCrop};
+use crate::{
+    crop::Crop,
+    motioncrop::autocrop_frames::{MotionCropResult, MotiondetectCrop},
+};
 
 #[derive(Copy, Clone)]
 pub enum LetterboxColour {
@@ -213,6 +216,10 @@ pub fn cropdetect_motion(frames: &[GrayImage]) -> Option<Crop> {
     MotiondetectCrop::from_frames(frames)
 }
 
+pub fn cropdetect_motion_detailed(frames: &[GrayImage]) -> Option<MotionCropResult> {
+    MotiondetectCrop::detect(frames)
+}
+
 #[cfg(test)]
 mod test {
     use image::GrayImage;
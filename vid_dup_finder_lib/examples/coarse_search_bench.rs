@@ -0,0 +1,88 @@
+//! Compare search times with and without the coarse signature prefilter.
+//!
+//! Run with `cargo run --release --features test-util --example coarse_search_bench -- [num_hashes]`
+//!
+//! The hashes are synthetic: clusters of near-duplicates around random hashes, spread over a range
+//! of durations. Only the tolerance and coarse signatures vary between runs, and every run must
+//! produce identical results.
+//!
+//! The last row is the default tolerance. The coarse signature is not checked there (it cannot
+//! rule out any pair of hashes of the default size at that tolerance), so its speedup only shows
+//! the noise between runs. On 100k hashes:
+//! ```text
+//! tolerance  full (s)  coarse (s)  speedup  groups
+//!      0.05     4.367       3.328     1.31  4991
+//!       0.1     3.974       3.170     1.25  12557
+//!      0.15     3.073       3.084     1.00  18390
+//!       0.2     2.331       2.209     1.06  21539
+//!      0.25     1.647       1.544     1.07  21016
+//!       0.3     1.342       1.274     1.05  20138
+//!      0.35     1.289       1.210     1.07  19998
+//! ```
+use std::time::Instant;
+
+use rand::prelude::*;
+use vid_dup_finder_lib::{
+    search_with_opts, MatchGroup, SearchOptions, Tolerance, VideoHash, DEFAULT_SEARCH_TOLERANCE,
+    TOLERANCE_SCALING_FACTOR,
+};
+
+const CLUSTER_SIZE: u32 = 5;
+
+fn synthetic_hashes(num_hashes: u32) -> Vec<VideoHash> {
+    let mut rng = StdRng::seed_from_u64(0);
+
+    let mut ret = vec![];
+    for cluster in 0..num_hashes / CLUSTER_SIZE {
+        let base = VideoHash::random_hash(&mut rng).with_duration(60 + cluster % 3600);
+        for i in 0..CLUSTER_SIZE {
+            let distance = rng.gen_range(0..(0.2 * TOLERANCE_SCALING_FACTOR) as u32);
+            ret.push(
+                base.hash_with_spatial_distance(distance, &mut rng)
+                    .with_src_path(format!("{cluster}_{i}")),
+            );
+        }
+    }
+    ret
+}
+
+fn main() {
+    let num_hashes = std::env::args().nth(1).map_or(100_000, |arg| {
+        arg.parse().expect("num_hashes must be an integer")
+    });
+
+    let hashes = synthetic_hashes(num_hashes);
+    let fallback_hashes = hashes
+        .iter()
+        .map(VideoHash::without_coarse_signature)
+        .collect::<Vec<_>>();
+
+    println!("{} hashes", hashes.len());
+    println!("tolerance  full (s)  coarse (s)  speedup  groups");
+    for tolerance in [0.05, 0.1, 0.15, 0.2, 0.25, 0.3, DEFAULT_SEARCH_TOLERANCE] {
+        let (coarse_secs, coarse) = time_search(&hashes, tolerance);
+        let (full_secs, fallback) = time_search(&fallback_hashes, tolerance);
+
+        assert_eq!(coarse, fallback, "results differ at tolerance {tolerance}");
+
+        println!(
+            "{tolerance:>9}  {full_secs:>8.3}  {coarse_secs:>10.3}  {:>7.2}  {}",
+            full_secs / coarse_secs,
+            coarse.len()
+        );
+    }
+}
+
+//the fastest of a few runs, to reduce noise.
+fn time_search(hashes: &[VideoHash], tolerance: f64) -> (f64, Vec<MatchGroup>) {
+    let opts = SearchOptions::new(Tolerance::new(tolerance).expect("tolerances are in range"));
+    let mut best = f64::MAX;
+    let mut ret = vec![];
+    for _ in 0..3 {
+        let input = hashes.to_vec();
+        let start = Instant::now();
+        ret = search_with_opts(input, &opts);
+        best = best.min(start.elapsed().as_secs_f64());
+    }
+    (best, ret)
+}
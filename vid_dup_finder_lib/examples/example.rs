@@ -15,6 +15,7 @@ fn test() {
 }
 
 fn _temp_test() {
+    vid_dup_finder_lib::init().unwrap();
     let _hash = VideoHashBuilder::default()
         .hash(
             std::env::current_dir()
@@ -25,6 +26,8 @@ fn _temp_test() {
 }
 
 fn inner() {
+    vid_dup_finder_lib::init().expect("ffmpeg and ffprobe are installed");
+
     let cat_vids = &[
         "examples/vids/cat.1.mp4",
         "examples/vids/cat.2.mp4",
@@ -63,8 +66,7 @@ fn inner() {
 
     // Get a collection of duplicate groups, using the default search configuration.
     // One should contain all dog vids. One should contain all cat vids.
-    let tol = vid_dup_finder_lib::DEFAULT_SEARCH_TOLERANCE;
-    let dup_groups = search(hashes, tol);
+    let dup_groups = search_with_opts(hashes, &SearchOptions::default());
 
     //Print what was found
     println!("found {} duplicate groups", dup_groups.len());
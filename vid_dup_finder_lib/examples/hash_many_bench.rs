@@ -0,0 +1,60 @@
+//! Compare the time taken to hash videos one at a time with the time taken to hash them with
+//! `VideoHashBuilder::hash_many`, which reuses gstreamer pipelines between videos.
+//!
+//! Run with `cargo run --release --features gstreamer_backend --example hash_many_bench -- [video...]`
+//!
+//! Without arguments the example videos are hashed, several times over so that there are enough
+//! of them to time. The hashes from both runs must be identical.
+use std::{path::PathBuf, time::Instant};
+
+use vid_dup_finder_lib::gstreamer_builder::VideoHashBuilder;
+
+const REPEATS: usize = 5;
+
+fn main() {
+    vid_dup_finder_lib::init().expect("gstreamer can be initialized");
+
+    let mut paths = std::env::args()
+        .skip(1)
+        .map(PathBuf::from)
+        .collect::<Vec<_>>();
+    if paths.is_empty() {
+        let vids = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("examples/vids");
+        let names = [
+            "cat.1.mp4",
+            "cat.2.mp4",
+            "cat.3.webm",
+            "dog.1.mp4",
+            "dog.2.mp4",
+            "dog.3.webm",
+        ];
+        paths = names
+            .repeat(REPEATS)
+            .iter()
+            .map(|name| vids.join(name))
+            .collect();
+    }
+
+    let builder = VideoHashBuilder::default();
+
+    let start = Instant::now();
+    let one_at_a_time = paths
+        .iter()
+        .map(|path| builder.hash(path.clone()).ok())
+        .collect::<Vec<_>>();
+    let one_at_a_time_time = start.elapsed();
+
+    let start = Instant::now();
+    let batched = builder
+        .hash_many(paths.clone())
+        .map(|(_path, hash)| hash.ok())
+        .collect::<Vec<_>>();
+    let batched_time = start.elapsed();
+
+    assert_eq!(one_at_a_time, batched, "hash_many changed the hashes");
+
+    let hashed = batched.iter().flatten().count();
+    println!("{} videos, {hashed} hashed", paths.len());
+    println!("one at a time: {one_at_a_time_time:?}");
+    println!("hash_many:     {batched_time:?}");
+}
@@ -0,0 +1,163 @@
+/*
+ * Loads the vid_dup_finder_lib shared library at runtime and checks that each function of its C
+ * interface can be called.
+ *
+ *     smoke_test <path to library> [<path to a video>]
+ *
+ * If a video is given, it is also hashed (which needs ffmpeg).
+ */
+#define _POSIX_C_SOURCE 200809L
+
+#include <dlfcn.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+#include "../include/vid_dup_finder.h"
+
+/* 27 bytes of ones and of zeros: a full and an empty 6x6x6 hash. */
+#define FULL_BITS "////////////////////////////////////"
+#define EMPTY_BITS "AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA"
+#define PORTABLE_HASH(path, bits)                                                                  \
+    "{\"version\": 1, \"path\": \"" path "\", \"duration_ms\": 60000, \"dimensions\": [6, 6, 6], " \
+    "\"bits\": \"" bits "\", \"creation\": {\"short_video\": false, \"video_stream\": 0, "       \
+    "\"leading_black_ms\": 0}}"
+
+static uint32_t (*abi_version)(void);
+static const char *(*last_error_message)(void);
+static VdfStatus (*options_default)(VdfOptions *);
+static VdfStatus (*hash_file)(const char *, const VdfOptions *, VdfHash **);
+static VdfStatus (*hash_distance)(const VdfHash *, const VdfHash *, double *);
+static VdfStatus (*hash_serialize)(const VdfHash *, char **);
+static VdfStatus (*hash_deserialize)(const char *, VdfHash **);
+static void (*free_hash)(VdfHash *);
+static void (*free_string)(char *);
+
+static int failures = 0;
+
+#define CHECK(cond)                                                                                \
+    do {                                                                                           \
+        if (!(cond)) {                                                                             \
+            fprintf(stderr, "%s:%d: check failed: %s\n", __FILE__, __LINE__, #cond);              \
+            failures++;                                                                            \
+        }                                                                                          \
+    } while (0)
+
+static void *symbol(void *lib, const char *name) {
+    void *sym = dlsym(lib, name);
+    if (sym == NULL) {
+        fprintf(stderr, "missing symbol %s: %s\n", name, dlerror());
+        exit(1);
+    }
+    return sym;
+}
+
+static VdfHash *deserialize(const char *json) {
+    VdfHash *hash = NULL;
+    VdfStatus status = hash_deserialize(json, &hash);
+    if (status != VDF_STATUS_OK) {
+        fprintf(stderr, "could not read hash: %s\n", last_error_message());
+        exit(1);
+    }
+    return hash;
+}
+
+static double distance(const VdfHash *a, const VdfHash *b) {
+    double ret = -1.0;
+    CHECK(hash_distance(a, b, &ret) == VDF_STATUS_OK);
+    return ret;
+}
+
+int main(int argc, char **argv) {
+    if (argc < 2 || argc > 3) {
+        fprintf(stderr, "usage: %s <library> [<video>]\n", argv[0]);
+        return 2;
+    }
+
+    void *lib = dlopen(argv[1], RTLD_NOW | RTLD_LOCAL);
+    if (lib == NULL) {
+        fprintf(stderr, "could not load %s: %s\n", argv[1], dlerror());
+        return 1;
+    }
+
+    /* Casting through a union avoids warnings about converting object pointers to functions. */
+#define LOAD(var, name)                                                                            \
+    do {                                                                                           \
+        union {                                                                                    \
+            void *obj;                                                                             \
+            __typeof__(var) fn;                                                                    \
+        } u;                                                                                       \
+        u.obj = symbol(lib, name);                                                                 \
+        var = u.fn;                                                                                \
+    } while (0)
+    LOAD(abi_version, "vdf_abi_version");
+    LOAD(last_error_message, "vdf_last_error_message");
+    LOAD(options_default, "vdf_options_default");
+    LOAD(hash_file, "vdf_hash_file");
+    LOAD(hash_distance, "vdf_hash_distance");
+    LOAD(hash_serialize, "vdf_hash_serialize");
+    LOAD(hash_deserialize, "vdf_hash_deserialize");
+    LOAD(free_hash, "vdf_free_hash");
+    LOAD(free_string, "vdf_free_string");
+
+    CHECK(abi_version() == VDF_ABI_VERSION);
+    CHECK(last_error_message() == NULL);
+
+    /* Reading, writing and comparing hashes. */
+    VdfHash *full = deserialize(PORTABLE_HASH("full.mp4", FULL_BITS));
+    VdfHash *empty = deserialize(PORTABLE_HASH("empty.mp4", EMPTY_BITS));
+    CHECK(distance(full, full) == 0.0);
+    CHECK(distance(full, empty) == 1.0);
+
+    char *json = NULL;
+    CHECK(hash_serialize(full, &json) == VDF_STATUS_OK);
+    CHECK(json != NULL && strstr(json, "\"full.mp4\"") != NULL);
+    VdfHash *reread = deserialize(json);
+    CHECK(distance(full, reread) == 0.0);
+    free_string(json);
+    free_hash(reread);
+
+    /* Errors. */
+    VdfHash *bad = NULL;
+    CHECK(hash_deserialize("not a hash", &bad) == VDF_STATUS_INVALID_HASH);
+    CHECK(bad == NULL);
+    CHECK(last_error_message() != NULL && strlen(last_error_message()) > 0);
+    CHECK(hash_distance(full, NULL, &(double){0}) == VDF_STATUS_NULL_POINTER);
+
+    VdfOptions opts;
+    CHECK(options_default(&opts) == VDF_STATUS_OK);
+    CHECK(opts.hash_side == 6);
+    opts.hash_side = 7;
+    CHECK(hash_file("video.mp4", &opts, &bad) == VDF_STATUS_INVALID_ARGUMENT);
+    VdfStatus missing = hash_file("/does/not/exist.mp4", NULL, &bad);
+    CHECK(missing == VDF_STATUS_HASHING_FAILED || missing == VDF_STATUS_BACKEND_MISSING);
+    CHECK(bad == NULL);
+
+    /* Hashing a real video. */
+    if (argc == 3) {
+        VdfHash *video = NULL;
+        VdfStatus status = hash_file(argv[2], NULL, &video);
+        if (status != VDF_STATUS_OK) {
+            fprintf(stderr, "could not hash %s: %s\n", argv[2], last_error_message());
+            failures++;
+        } else {
+            CHECK(distance(video, video) == 0.0);
+            double to_full = distance(video, full);
+            CHECK(to_full > 0.0 && to_full < 1.0);
+            free_hash(video);
+        }
+    }
+
+    free_hash(full);
+    free_hash(empty);
+    free_hash(NULL);
+    free_string(NULL);
+    dlclose(lib);
+
+    if (failures > 0) {
+        fprintf(stderr, "%d checks failed\n", failures);
+        return 1;
+    }
+    printf("ffi smoke test passed\n");
+    return 0;
+}
@@ -0,0 +1,26 @@
+#!/bin/sh
+# Builds the C interface of vid_dup_finder_lib as a shared library, then loads it from
+# smoke_test.c and hashes one of the example videos.
+#
+# The cargo profile can be chosen with PROFILE (default: release), and a C compiler with CC.
+
+set -eu
+
+cd "$(dirname "$0")/.."
+
+profile="${PROFILE:-release}"
+target_dir="${CARGO_TARGET_DIR:-../target}"
+
+cargo rustc -p vid_dup_finder_lib --lib --profile "$profile" --features ffi --crate-type cdylib
+
+case "$profile" in
+    dev) out_dir="$target_dir/debug" ;;
+    *) out_dir="$target_dir/$profile" ;;
+esac
+case "$(uname)" in
+    Darwin) lib="$out_dir/libvid_dup_finder_lib.dylib" ;;
+    *) lib="$out_dir/libvid_dup_finder_lib.so" ;;
+esac
+
+"${CC:-cc}" -std=c99 -Wall -Wextra -Werror -o "$out_dir/ffi_smoke_test" ffi/smoke_test.c -ldl
+"$out_dir/ffi_smoke_test" "$lib" ${VIDEO-examples/vids/cat.1.mp4}
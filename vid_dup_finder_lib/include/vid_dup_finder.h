@@ -0,0 +1,199 @@
+#ifndef VID_DUP_FINDER_H
+#define VID_DUP_FINDER_H
+
+/* Generated by cbindgen from src/ffi.rs. Do not edit. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * The version of the interface, returned by [`vdf_abi_version`].
+ */
+#define VDF_ABI_VERSION 1
+
+/**
+ * Do not detect letterboxing. A value for [`VdfOptions::cropdetect`].
+ */
+#define VDF_CROPDETECT_NONE 0
+
+/**
+ * Detect letterboxes around the edges of videos. A value for [`VdfOptions::cropdetect`].
+ */
+#define VDF_CROPDETECT_LETTERBOX 1
+
+/**
+ * Detect the regions of videos that contain motion. A value for [`VdfOptions::cropdetect`].
+ */
+#define VDF_CROPDETECT_MOTION 2
+
+/**
+ * The result of a call.
+ */
+typedef enum VdfStatus {
+  /**
+   * The call succeeded.
+   */
+  VDF_STATUS_OK = 0,
+  /**
+   * A pointer which must not be null was null.
+   */
+  VDF_STATUS_NULL_POINTER = 1,
+  /**
+   * A string was not valid UTF-8, or the options were not valid.
+   */
+  VDF_STATUS_INVALID_ARGUMENT = 2,
+  /**
+   * ffmpeg or ffprobe could not be run, so no videos can be hashed.
+   */
+  VDF_STATUS_BACKEND_MISSING = 3,
+  /**
+   * The video is too short to hash.
+   */
+  VDF_STATUS_TOO_SHORT = 4,
+  /**
+   * The file could not be hashed for any other reason.
+   */
+  VDF_STATUS_HASHING_FAILED = 5,
+  /**
+   * The hashes cannot be compared, because they have different sizes or were created with
+   * different normalizations, coverages, sampling windows or pixel aspect ratio handling (see
+   * [`Incompatibility`][crate::Incompatibility]).
+   */
+  VDF_STATUS_INCOMPATIBLE_HASHES = 6,
+  /**
+   * The string is not a hash in a supported version of the portable format.
+   */
+  VDF_STATUS_INVALID_HASH = 7,
+  /**
+   * The library panicked. This is a bug.
+   */
+  VDF_STATUS_PANIC = 8,
+} VdfStatus;
+
+/**
+ * A hash of a video.
+ */
+typedef struct VdfHash VdfHash;
+
+/**
+ * How videos are hashed by [`vdf_hash_file`]. Fill one in with [`vdf_options_default`] before
+ * changing any of its fields.
+ */
+typedef struct VdfOptions {
+  /**
+   * Seconds of video to skip before the frames which are hashed (see
+   * [`CreationOptions::skip_forward_amount`]).
+   */
+  double skip_forward_secs;
+  /**
+   * Seconds of video to hash (see [`CreationOptions::duration`]).
+   */
+  double duration_secs;
+  /**
+   * Videos shorter than this many seconds are not hashed (see
+   * [`CreationOptions::min_duration`]).
+   */
+  double min_duration_secs;
+  /**
+   * One of the `VDF_CROPDETECT_*` constants.
+   */
+  uint32_t cropdetect;
+  /**
+   * The number of bins along each side of the hash: 5, 6, 8 or 10 (see [`HashSize`]).
+   */
+  uint32_t hash_side;
+} VdfOptions;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * The version of this interface, [`VDF_ABI_VERSION`].
+ */
+uint32_t vdf_abi_version(void);
+
+/**
+ * The message describing the most recent failed call on this thread, or null if no call on this
+ * thread has failed. The string is owned by the library, and is only valid until the next failed
+ * call on this thread.
+ */
+const char *vdf_last_error_message(void);
+
+/**
+ * Fill in `out` with the default options.
+ *
+ * # Safety
+ * `out` must be null or valid for writing a [`VdfOptions`].
+ */
+enum VdfStatus vdf_options_default(struct VdfOptions *out);
+
+/**
+ * Hash the video at `path`, and store a new hash in `*out_hash`. If `opts` is null the default
+ * options are used.
+ *
+ * This decodes part of the video with ffmpeg, so it may take some time. ffmpeg and ffprobe must
+ * be on the PATH.
+ *
+ * # Safety
+ * `path` must be null or a NUL terminated string. `opts` must be null or point to a
+ * [`VdfOptions`]. `out_hash` must be null or valid for writing a pointer.
+ */
+enum VdfStatus vdf_hash_file(const char *path,
+                             const struct VdfOptions *opts,
+                             struct VdfHash **out_hash);
+
+/**
+ * Store the distance between two hashes in `*out`, as the fraction of their bits which differ
+ * (from 0.0 for identical hashes to 1.0). This is the value which searches compare against their
+ * tolerance.
+ *
+ * # Safety
+ * `a` and `b` must be null or hashes returned by this library which have not been freed. `out`
+ * must be null or valid for writing a double.
+ */
+enum VdfStatus vdf_hash_distance(const struct VdfHash *a, const struct VdfHash *b, double *out);
+
+/**
+ * Store a new string holding `hash` in the [portable format][crate::portable_hash] in
+ * `*out_json`. The string must be freed with [`vdf_free_string`].
+ *
+ * # Safety
+ * `hash` must be null or a hash returned by this library which has not been freed. `out_json` must
+ * be null or valid for writing a pointer.
+ */
+enum VdfStatus vdf_hash_serialize(const struct VdfHash *hash, char **out_json);
+
+/**
+ * Read a hash written by [`vdf_hash_serialize`] (or any other writer of the portable format), and
+ * store a new hash in `*out_hash`.
+ *
+ * # Safety
+ * `json` must be null or a NUL terminated string. `out_hash` must be null or valid for writing a
+ * pointer.
+ */
+enum VdfStatus vdf_hash_deserialize(const char *json, struct VdfHash **out_hash);
+
+/**
+ * Free a hash. Does nothing if `hash` is null.
+ *
+ * # Safety
+ * `hash` must be null or a hash returned by this library which has not already been freed.
+ */
+void vdf_free_hash(struct VdfHash *hash);
+
+/**
+ * Free a string returned by this library. Does nothing if `s` is null.
+ *
+ * # Safety
+ * `s` must be null or a string returned by this library which has not already been freed.
+ */
+void vdf_free_string(char *s);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* VID_DUP_FINDER_H */
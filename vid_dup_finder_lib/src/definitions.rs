@@ -1,9 +1,18 @@
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
 /// The default tolerance when performing searches. A value of 0.0 means videos will get paired
 /// only if their hashes are identical. A value of 1.0 means a video hash will match any other.
 /// Reccomend to start with a high value e.g 0.35 and to lower it if there are too many false
 /// positives
 pub const DEFAULT_SEARCH_TOLERANCE: f64 = 0.35;
 
+/// The default tolerance applied to pairs of videos which are both shorter than the hash duration
+/// (see [`ShortVideoPolicy`]). Hashes of very short clips are built from a small amount of
+/// content, so unrelated clips resemble each other more often than unrelated long videos do.
+pub const DEFAULT_SHORT_VIDEO_TOLERANCE: f64 = 0.1;
+
 /// The default time to skip forwards when before extracting video frames. Used to skip past
 /// title credits and/or overlays at the beginning of videos.
 /// Higher numbers extend hasing time (because seeking to this point in videos must be
@@ -28,37 +37,248 @@ pub const DEFAULT_VID_HASH_SKIP_FORWARD: f64 = 15.0;
 /// reccomended range: 2-60.
 pub const DEFAULT_VID_HASH_DURATION: f64 = 10.0;
 
+/// The default shortest video that will be hashed. Shorter files (such as the "motion photos"
+/// taken by phones, which are stored as single frame videos) are rejected with
+/// [`crate::Error::TooShort`] without being decoded.
+///
+/// Unit: Seconds
+pub const DEFAULT_VID_HASH_MIN_DURATION: f64 = 1.0;
+
+/// The default for [`CreationOptions::max_measured_duration`][crate::CreationOptions::max_measured_duration].
+///
+/// Unit: Seconds
+pub const DEFAULT_VID_HASH_MAX_MEASURED_DURATION: f64 =
+    ffmpeg_gst_wrapper::DEFAULT_MAX_MEASURED_DURATION.as_secs_f64();
+
+/// The default factor by which the duration reported by a video's container may exceed the time
+/// of its last decodable frame before the container is assumed to be wrong. Only checked when a
+/// video runs out of frames before a hash can be built from it.
+pub const DEFAULT_DURATION_CHECK_FACTOR: f64 = 1.25;
+
+/// The default for [`CreationOptions::min_motion_crop_confidence`][crate::CreationOptions::min_motion_crop_confidence].
+pub const DEFAULT_MIN_MOTION_CROP_CONFIDENCE: f64 = 0.5;
+
 //tweakable. Number of frames that the 3d DCT is performed on. Higher numbers extend hashing time
 // but (hopefully) makes hashes more robust to small time offsets.
 //This generates a cube of DCT_SIZExDCT_SIZExDCT_SIZE bits, of which the HASH_SIZE cube MSBs will be taken
 pub const DCT_SIZE: u32 = 16;
 
-#[cfg(any(
-    all(feature = "hash_size_6", not(feature = "hash_size_10")),
-    all(not(feature = "hash_size_6"), not(feature = "hash_size_10"))
-))]
-pub const HASH_SIZE: u32 = 6;
+/// The number of DCT bins along each side of the cube of bins that makes up a hash. Larger hashes
+/// capture more detail, so are slightly better at telling similar-looking videos apart, but take
+/// more memory and are slower to search.
+///
+/// Hashes of different sizes cannot be compared with each other, so searches never match them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum HashSize {
+    /// 5x5x5 bins (125 bits)
+    Five,
+    /// 6x6x6 bins (216 bits)
+    Six,
+    /// 8x8x8 bins (512 bits)
+    Eight,
+    /// 10x10x10 bins (1000 bits)
+    Ten,
+}
+
+impl HashSize {
+    /// Every supported size, from smallest to largest.
+    pub const ALL: [Self; 4] = [Self::Five, Self::Six, Self::Eight, Self::Ten];
+
+    /// The size of the hash that is created unless otherwise specified in
+    /// [`crate::CreationOptions`]. The (deprecated) `hash_size_10` cargo feature changes this to
+    /// [`HashSize::Ten`].
+    pub const DEFAULT: Self = if cfg!(all(feature = "hash_size_10", not(feature = "hash_size_6"))) {
+        Self::Ten
+    } else {
+        Self::Six
+    };
+
+    /// The size with the given number of bins along each side, if it is supported.
+    #[must_use]
+    pub fn from_side(side: u32) -> Option<Self> {
+        Self::ALL.into_iter().find(|size| size.side() == side)
+    }
+
+    /// The number of bins along each side of the cube.
+    #[must_use]
+    pub const fn side(self) -> u32 {
+        match self {
+            Self::Five => 5,
+            Self::Six => 6,
+            Self::Eight => 8,
+            Self::Ten => 10,
+        }
+    }
 
-#[cfg(all(not(feature = "hash_size_6"), feature = "hash_size_10"))]
-pub const HASH_SIZE: u32 = 10;
+    /// The number of bits in a hash of this size.
+    #[must_use]
+    pub const fn bits(self) -> u32 {
+        self.side().pow(3)
+    }
 
-#[cfg(all(feature = "hash_size_6", feature = "hash_size_10"))]
-compile_error!("features 'hash_size_6' and 'hash_size_10' cannot be selected at the same time");
+    /// The number of u64s needed to store the bits of a hash of this size.
+    pub(crate) const fn qwords(self) -> usize {
+        self.bits().div_ceil(64) as usize
+    }
+}
+
+impl Default for HashSize {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+impl std::fmt::Display for HashSize {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let side = self.side();
+        write!(f, "{side}x{side}x{side}")
+    }
+}
 
 //At user-level the tolerance parameter is specified as real between 0 and 1.
-//The is the scaling factor to map into the integer-domain being used for calculations.
-pub const TOLERANCE_SCALING_FACTOR: f64 = (HASH_SIZE.pow(3)) as f64;
+//The is the scaling factor to map into the integer-domain being used for calculations, for hashes
+//of the default size.
+#[cfg(any(feature = "test-util", test))]
+pub const TOLERANCE_SCALING_FACTOR: f64 = HashSize::DEFAULT.bits() as f64;
 
-pub const HASH_BITS: u32 = HASH_SIZE.pow(3);
-pub const HASH_QWORDS: u32 = HASH_BITS.div_ceil(64);
+//The bits of hashes of the default size are stored inline. Larger hashes spill to the heap, so
+//that they do not make every hash as large as the largest size.
+pub const INLINE_HASH_QWORDS: usize = HashSize::DEFAULT.qwords();
+
+//The coarse signature is the COARSE_SIZE cube of lowest frequency bins, which is a subset of
+//the HASH_SIZE cube of bins in the full hash. COARSE_BITS must fit in a u64.
+pub const COARSE_SIZE: u32 = 4;
+pub const COARSE_BITS: u32 = COARSE_SIZE.pow(3);
+const _: () = assert!(COARSE_SIZE <= HashSize::ALL[0].side() && COARSE_BITS <= 64);
 
 /// Algorithms to detect [black bars](https://en.wikipedia.org/wiki/Letterboxing_(filming))  around the edges of video frames
-#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash, enum_utils::FromStr)]
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Eq,
+    PartialEq,
+    Ord,
+    PartialOrd,
+    Hash,
+    enum_utils::FromStr,
+    Serialize,
+    Deserialize,
+)]
 pub enum Cropdetect {
     /// Do not detect letterboxing
     None,
     /// Detect letterboxes around the edges of videos (top, bottom, left, right)
     Letterbox,
-    /// Detect regions of videos that contain motion
+    /// Detect regions of videos that contain motion. If the detector is not confident of the
+    /// region it finds, letterboxes are detected instead (see
+    /// [`CreationOptions::min_motion_crop_confidence`][crate::CreationOptions::min_motion_crop_confidence]).
     Motion,
 }
+
+/// Which parts of a video the frames of a hash are sampled from. Chosen with
+/// [`CreationOptions::coverage`][crate::CreationOptions::coverage].
+///
+/// Hashes of only the start of a video match whenever two videos share an opening, such as the
+/// episodes of a TV show with the same title sequence. The other coverages split the frames of
+/// the hash between several parts of the video, which are decoded separately, so the hash is the
+/// same size but such videos no longer match. Each extra part means another seek, so hashing is
+/// slower.
+///
+/// The coverage is recorded in each hash, and hashes with different coverages never match.
+/// Videos shorter than [`CreationOptions::duration`][crate::CreationOptions::duration] are always
+/// sampled evenly across their whole length, whatever the coverage.
+#[derive(
+    Debug, Clone, Copy, Default, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize,
+)]
+pub enum Coverage {
+    /// Sample every frame from one run starting after
+    /// [`CreationOptions::skip_forward_amount`][crate::CreationOptions::skip_forward_amount].
+    #[default]
+    Start,
+    /// Sample a third of the frames from the start (as for [`Coverage::Start`]), a third from the
+    /// middle and a third from just before the end of the video.
+    StartMiddleEnd,
+    /// Sample the frames from this many runs, spaced evenly from the start (as for
+    /// [`Coverage::Start`]) to just before the end of the video. Must be between 1 and
+    /// [`MAX_COVERAGE_SEGMENTS`].
+    EvenlySpaced { segments: u32 },
+}
+
+/// The most parts of a video that [`Coverage::EvenlySpaced`] can sample from. Each part must have
+/// at least two of the frames that a hash is built from, or it would show no motion.
+pub const MAX_COVERAGE_SEGMENTS: u32 = DCT_SIZE / 2;
+
+impl Coverage {
+    // The number of runs of frames that are sampled.
+    pub(crate) const fn segments(self) -> u32 {
+        match self {
+            Self::Start => 1,
+            Self::StartMiddleEnd => 3,
+            Self::EvenlySpaced { segments } => segments,
+        }
+    }
+}
+
+/// The part of a video that the frames of a hash are sampled from, as chosen by
+/// [`CreationOptions::skip_forward_amount`][crate::CreationOptions::skip_forward_amount] and
+/// [`CreationOptions::duration`][crate::CreationOptions::duration], to the nearest millisecond.
+///
+/// The window is recorded in each hash, and hashes sampled from different windows (such as
+/// hashes made with different [`Preset`][crate::Preset]s) never match. Hashes from before the
+/// window was recorded have the default window.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
+pub struct SamplingWindow {
+    skip_forward_ms: u32,
+    duration_ms: u32,
+}
+
+impl SamplingWindow {
+    /// The window starting `skip_forward` seconds into a video and lasting `duration` seconds.
+    /// Negative and NaN times are treated as 0.
+    #[must_use]
+    pub fn new(skip_forward: f64, duration: f64) -> Self {
+        //float to int casts saturate.
+        let millis = |secs: f64| (secs * 1000.0).round() as u32;
+        Self {
+            skip_forward_ms: millis(skip_forward),
+            duration_ms: millis(duration),
+        }
+    }
+
+    #[must_use]
+    pub const fn skip_forward(self) -> Duration {
+        Duration::from_millis(self.skip_forward_ms as u64)
+    }
+
+    #[must_use]
+    pub const fn duration(self) -> Duration {
+        Duration::from_millis(self.duration_ms as u64)
+    }
+}
+
+impl Default for SamplingWindow {
+    fn default() -> Self {
+        Self::new(DEFAULT_VID_HASH_SKIP_FORWARD, DEFAULT_VID_HASH_DURATION)
+    }
+}
+
+/// How searches treat pairs of videos where both videos are shorter than
+/// [`CreationOptions::duration`][crate::CreationOptions::duration].
+///
+/// Pairs where only one of the videos is short are always compared with the normal tolerance.
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub enum ShortVideoPolicy {
+    /// Compare short videos with the same tolerance as any other video.
+    SameTolerance,
+    /// Compare short videos with this tolerance, or the normal search tolerance if that is stricter.
+    Tolerance(f64),
+    /// Never match two short videos with each other.
+    Exclude,
+}
+
+impl Default for ShortVideoPolicy {
+    fn default() -> Self {
+        Self::Tolerance(DEFAULT_SHORT_VIDEO_TOLERANCE)
+    }
+}
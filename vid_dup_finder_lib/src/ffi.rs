@@ -0,0 +1,524 @@
+//! A C interface for creating and comparing hashes, for programs written in other languages.
+//! Enabled with the `ffi` feature.
+//!
+//! The library can be built as a shared library with
+//! ```text
+//! cargo rustc -p vid_dup_finder_lib --lib --release --features ffi --crate-type cdylib
+//! ```
+//! and the matching C declarations are in `include/vid_dup_finder.h`, which is generated from this
+//! module by running `cbindgen --config cbindgen.toml --output include/vid_dup_finder.h` in the
+//! crate directory. `ffi/smoke_test.sh` builds the library and loads it from a small C program.
+//!
+//! # Conventions
+//! * Every function that can fail returns a [`VdfStatus`]. When it is not [`VdfStatus::Ok`],
+//!   [`vdf_last_error_message`] describes what went wrong, and nothing has been written to the
+//!   output arguments.
+//! * Hashes are opaque [`VdfHash`] handles, which must be freed with [`vdf_free_hash`]. Strings
+//!   returned by the library must be freed with [`vdf_free_string`].
+//! * All functions may be called from any thread.
+//! * Panics never unwind into the caller. They are reported as [`VdfStatus::Panic`], unless the
+//!   library was built with `panic = "abort"`, in which case they abort the process. Every profile
+//!   of the workspace unwinds.
+//!
+//! Functions are only ever added to this interface. If the meaning of an existing function changes,
+//! [`VDF_ABI_VERSION`] is increased.
+
+use std::{
+    cell::RefCell,
+    ffi::{c_char, CStr, CString},
+    panic::{self, AssertUnwindSafe},
+    path::PathBuf,
+    ptr,
+};
+
+use vid_dup_finder_common::panic_message;
+
+use crate::{
+    ffmpeg_builder::VideoHashBuilder, video_hashing::search_algorithm::incompatibility,
+    CreationOptions, Cropdetect, Error, HashSize, VideoHash, DEFAULT_VID_HASH_DURATION,
+    DEFAULT_VID_HASH_MIN_DURATION, DEFAULT_VID_HASH_SKIP_FORWARD,
+};
+
+/// The version of the interface, returned by [`vdf_abi_version`].
+pub const VDF_ABI_VERSION: u32 = 1;
+
+/// Do not detect letterboxing. A value for [`VdfOptions::cropdetect`].
+pub const VDF_CROPDETECT_NONE: u32 = 0;
+/// Detect letterboxes around the edges of videos. A value for [`VdfOptions::cropdetect`].
+pub const VDF_CROPDETECT_LETTERBOX: u32 = 1;
+/// Detect the regions of videos that contain motion. A value for [`VdfOptions::cropdetect`].
+pub const VDF_CROPDETECT_MOTION: u32 = 2;
+
+/// The result of a call.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VdfStatus {
+    /// The call succeeded.
+    Ok = 0,
+    /// A pointer which must not be null was null.
+    NullPointer = 1,
+    /// A string was not valid UTF-8, or the options were not valid.
+    InvalidArgument = 2,
+    /// ffmpeg or ffprobe could not be run, so no videos can be hashed.
+    BackendMissing = 3,
+    /// The video is too short to hash.
+    TooShort = 4,
+    /// The file could not be hashed for any other reason.
+    HashingFailed = 5,
+    /// The hashes cannot be compared, because they have different sizes or were created with
+    /// different normalizations, coverages, sampling windows or pixel aspect ratio handling (see
+    /// [`Incompatibility`][crate::Incompatibility]).
+    IncompatibleHashes = 6,
+    /// The string is not a hash in a supported version of the portable format.
+    InvalidHash = 7,
+    /// The library panicked. This is a bug.
+    Panic = 8,
+}
+
+/// How videos are hashed by [`vdf_hash_file`]. Fill one in with [`vdf_options_default`] before
+/// changing any of its fields.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VdfOptions {
+    /// Seconds of video to skip before the frames which are hashed (see
+    /// [`CreationOptions::skip_forward_amount`]).
+    pub skip_forward_secs: f64,
+    /// Seconds of video to hash (see [`CreationOptions::duration`]).
+    pub duration_secs: f64,
+    /// Videos shorter than this many seconds are not hashed (see
+    /// [`CreationOptions::min_duration`]).
+    pub min_duration_secs: f64,
+    /// One of the `VDF_CROPDETECT_*` constants.
+    pub cropdetect: u32,
+    /// The number of bins along each side of the hash: 5, 6, 8 or 10 (see [`HashSize`]).
+    pub hash_side: u32,
+}
+
+/// A hash of a video.
+pub struct VdfHash(VideoHash);
+
+// A failed call, as returned to the caller.
+struct Failure {
+    status: VdfStatus,
+    message: String,
+}
+
+impl Failure {
+    fn new(status: VdfStatus, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            message: message.into(),
+        }
+    }
+}
+
+impl From<Error> for Failure {
+    fn from(e: Error) -> Self {
+        let status = match e {
+            Error::TooShort { .. } => VdfStatus::TooShort,
+            Error::InvalidOptions(_) => VdfStatus::InvalidArgument,
+            Error::FfmpegMissing(_) | Error::MissingComponents(_) | Error::NotInitialized => {
+                VdfStatus::BackendMissing
+            }
+            _ => VdfStatus::HashingFailed,
+        };
+        Self::new(status, e.to_string())
+    }
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(message: String) {
+    let message = CString::new(message.replace('\0', " ")).unwrap_or_default();
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+}
+
+// Run the body of an exported function, turning any error or panic into a status.
+fn guard(f: impl FnOnce() -> Result<(), Failure>) -> VdfStatus {
+    let failure = match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(())) => return VdfStatus::Ok,
+        Ok(Err(failure)) => failure,
+        Err(payload) => Failure::new(
+            VdfStatus::Panic,
+            format!(
+                "vid_dup_finder_lib panicked: {}",
+                panic_message(payload.as_ref())
+            ),
+        ),
+    };
+    set_last_error(failure.message);
+    failure.status
+}
+
+fn non_null<T>(ptr: *const T, name: &str) -> Result<*const T, Failure> {
+    if ptr.is_null() {
+        Err(Failure::new(
+            VdfStatus::NullPointer,
+            format!("{name} must not be null"),
+        ))
+    } else {
+        Ok(ptr)
+    }
+}
+
+// Safety: ptr must be null or point to a NUL terminated string.
+unsafe fn str_arg<'a>(ptr: *const c_char, name: &str) -> Result<&'a str, Failure> {
+    let s = unsafe { CStr::from_ptr(non_null(ptr, name)?) };
+    s.to_str().map_err(|_| {
+        Failure::new(
+            VdfStatus::InvalidArgument,
+            format!("{name} is not valid UTF-8"),
+        )
+    })
+}
+
+// Safety: ptr must be null or point to a NUL terminated string.
+unsafe fn path_arg(ptr: *const c_char) -> Result<PathBuf, Failure> {
+    #[cfg(unix)]
+    {
+        use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+        let s = unsafe { CStr::from_ptr(non_null(ptr, "path")?) };
+        Ok(PathBuf::from(OsStr::from_bytes(s.to_bytes())))
+    }
+
+    #[cfg(not(unix))]
+    unsafe {
+        str_arg(ptr, "path").map(PathBuf::from)
+    }
+}
+
+impl VdfOptions {
+    fn creation_options(&self) -> Result<CreationOptions, Failure> {
+        let invalid = |message: String| Failure::new(VdfStatus::InvalidArgument, message);
+
+        let cropdetect = match self.cropdetect {
+            VDF_CROPDETECT_NONE => Cropdetect::None,
+            VDF_CROPDETECT_LETTERBOX => Cropdetect::Letterbox,
+            VDF_CROPDETECT_MOTION => Cropdetect::Motion,
+            other => return Err(invalid(format!("Unknown cropdetect {other}"))),
+        };
+        let hash_bits = HashSize::from_side(self.hash_side)
+            .ok_or_else(|| invalid(format!("Unsupported hash side {}", self.hash_side)))?;
+        for (name, secs) in [
+            ("skip_forward_secs", self.skip_forward_secs),
+            ("duration_secs", self.duration_secs),
+            ("min_duration_secs", self.min_duration_secs),
+        ] {
+            if !(secs.is_finite() && secs >= 0.0) {
+                return Err(invalid(format!("{name} must not be negative. Got {secs}")));
+            }
+        }
+
+        Ok(CreationOptions {
+            skip_forward_amount: self.skip_forward_secs,
+            duration: self.duration_secs,
+            min_duration: self.min_duration_secs,
+            cropdetect,
+            hash_bits,
+            ..CreationOptions::default()
+        })
+    }
+}
+
+impl Default for VdfOptions {
+    fn default() -> Self {
+        Self {
+            skip_forward_secs: DEFAULT_VID_HASH_SKIP_FORWARD,
+            duration_secs: DEFAULT_VID_HASH_DURATION,
+            min_duration_secs: DEFAULT_VID_HASH_MIN_DURATION,
+            cropdetect: VDF_CROPDETECT_LETTERBOX,
+            hash_side: HashSize::default().side(),
+        }
+    }
+}
+
+/// The version of this interface, [`VDF_ABI_VERSION`].
+#[no_mangle]
+pub extern "C" fn vdf_abi_version() -> u32 {
+    VDF_ABI_VERSION
+}
+
+/// The message describing the most recent failed call on this thread, or null if no call on this
+/// thread has failed. The string is owned by the library, and is only valid until the next failed
+/// call on this thread.
+#[no_mangle]
+pub extern "C" fn vdf_last_error_message() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().as_ref().map_or(ptr::null(), |s| s.as_ptr()))
+}
+
+/// Fill in `out` with the default options.
+///
+/// # Safety
+/// `out` must be null or valid for writing a [`VdfOptions`].
+#[no_mangle]
+pub unsafe extern "C" fn vdf_options_default(out: *mut VdfOptions) -> VdfStatus {
+    guard(|| {
+        let out = non_null(out, "out")?.cast_mut();
+        unsafe { out.write(VdfOptions::default()) };
+        Ok(())
+    })
+}
+
+/// Hash the video at `path`, and store a new hash in `*out_hash`. If `opts` is null the default
+/// options are used.
+///
+/// This decodes part of the video with ffmpeg, so it may take some time. ffmpeg and ffprobe must
+/// be on the PATH.
+///
+/// # Safety
+/// `path` must be null or a NUL terminated string. `opts` must be null or point to a
+/// [`VdfOptions`]. `out_hash` must be null or valid for writing a pointer.
+#[no_mangle]
+pub unsafe extern "C" fn vdf_hash_file(
+    path: *const c_char,
+    opts: *const VdfOptions,
+    out_hash: *mut *mut VdfHash,
+) -> VdfStatus {
+    guard(|| {
+        let out_hash = non_null(out_hash, "out_hash")?.cast_mut();
+        let path = unsafe { path_arg(path)? };
+        let opts = unsafe { opts.as_ref() }.copied().unwrap_or_default();
+        let opts = opts.creation_options()?;
+
+        crate::init()?;
+        let hash = VideoHashBuilder::from_options(opts).hash(path)?;
+        unsafe { out_hash.write(Box::into_raw(Box::new(VdfHash(hash)))) };
+        Ok(())
+    })
+}
+
+/// Store the distance between two hashes in `*out`, as the fraction of their bits which differ
+/// (from 0.0 for identical hashes to 1.0). This is the value which searches compare against their
+/// tolerance.
+///
+/// # Safety
+/// `a` and `b` must be null or hashes returned by this library which have not been freed. `out`
+/// must be null or valid for writing a double.
+#[no_mangle]
+pub unsafe extern "C" fn vdf_hash_distance(
+    a: *const VdfHash,
+    b: *const VdfHash,
+    out: *mut f64,
+) -> VdfStatus {
+    guard(|| {
+        let (a, b) = unsafe { (&*non_null(a, "a")?, &*non_null(b, "b")?) };
+        let out = non_null(out, "out")?.cast_mut();
+
+        if let Some(incompatibility) = incompatibility(&a.0, &b.0) {
+            return Err(Failure::new(
+                VdfStatus::IncompatibleHashes,
+                format!("The hashes have {incompatibility}"),
+            ));
+        }
+        let distance = f64::from(a.0.same_size_distance(&b.0)) / f64::from(a.0.hash_size().bits());
+        unsafe { out.write(distance) };
+        Ok(())
+    })
+}
+
+/// Store a new string holding `hash` in the [portable format][crate::portable_hash] in
+/// `*out_json`. The string must be freed with [`vdf_free_string`].
+///
+/// # Safety
+/// `hash` must be null or a hash returned by this library which has not been freed. `out_json` must
+/// be null or valid for writing a pointer.
+#[no_mangle]
+pub unsafe extern "C" fn vdf_hash_serialize(
+    hash: *const VdfHash,
+    out_json: *mut *mut c_char,
+) -> VdfStatus {
+    guard(|| {
+        let hash = unsafe { &*non_null(hash, "hash")? };
+        let out_json = non_null(out_json, "out_json")?.cast_mut();
+
+        //JSON strings escape any NULs, so there cannot be one in the output.
+        let json = CString::new(hash.0.to_json_portable())
+            .map_err(|e| Failure::new(VdfStatus::Panic, e.to_string()))?;
+        unsafe { out_json.write(json.into_raw()) };
+        Ok(())
+    })
+}
+
+/// Read a hash written by [`vdf_hash_serialize`] (or any other writer of the portable format), and
+/// store a new hash in `*out_hash`.
+///
+/// # Safety
+/// `json` must be null or a NUL terminated string. `out_hash` must be null or valid for writing a
+/// pointer.
+#[no_mangle]
+pub unsafe extern "C" fn vdf_hash_deserialize(
+    json: *const c_char,
+    out_hash: *mut *mut VdfHash,
+) -> VdfStatus {
+    guard(|| {
+        let json = unsafe { str_arg(json, "json")? };
+        let out_hash = non_null(out_hash, "out_hash")?.cast_mut();
+
+        let hash = VideoHash::from_json_portable(json)
+            .map_err(|e| Failure::new(VdfStatus::InvalidHash, e.to_string()))?;
+        unsafe { out_hash.write(Box::into_raw(Box::new(VdfHash(hash)))) };
+        Ok(())
+    })
+}
+
+/// Free a hash. Does nothing if `hash` is null.
+///
+/// # Safety
+/// `hash` must be null or a hash returned by this library which has not already been freed.
+#[no_mangle]
+pub unsafe extern "C" fn vdf_free_hash(hash: *mut VdfHash) {
+    if !hash.is_null() {
+        drop(unsafe { Box::from_raw(hash) });
+    }
+}
+
+/// Free a string returned by this library. Does nothing if `s` is null.
+///
+/// # Safety
+/// `s` must be null or a string returned by this library which has not already been freed.
+#[no_mangle]
+pub unsafe extern "C" fn vdf_free_string(s: *mut c_char) {
+    if !s.is_null() {
+        drop(unsafe { CString::from_raw(s) });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rand::prelude::*;
+
+    use super::*;
+    use crate::SamplingWindow;
+
+    fn last_error() -> String {
+        let message = vdf_last_error_message();
+        assert!(!message.is_null());
+        unsafe { CStr::from_ptr(message) }
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    fn handle(hash: VideoHash) -> *mut VdfHash {
+        Box::into_raw(Box::new(VdfHash(hash)))
+    }
+
+    #[test]
+    fn test_serialize_and_compare() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let hash = VideoHash::random_hash(&mut rng).with_src_path("/videos/a.mp4");
+        let a = handle(hash.clone());
+        let b = handle(hash.with_flipped_bits(0..3));
+
+        unsafe {
+            let mut json = ptr::null_mut();
+            assert_eq!(vdf_hash_serialize(a, &mut json), VdfStatus::Ok);
+            let mut read = ptr::null_mut();
+            assert_eq!(vdf_hash_deserialize(json, &mut read), VdfStatus::Ok);
+            assert_eq!((*read).0, (*a).0);
+
+            let mut distance = -1.0;
+            assert_eq!(vdf_hash_distance(a, read, &mut distance), VdfStatus::Ok);
+            assert_eq!(distance, 0.0);
+            assert_eq!(vdf_hash_distance(a, b, &mut distance), VdfStatus::Ok);
+            assert_eq!(distance, 3.0 / 216.0);
+
+            vdf_free_string(json);
+            vdf_free_hash(read);
+            vdf_free_hash(b);
+            vdf_free_hash(a);
+            vdf_free_hash(ptr::null_mut());
+            vdf_free_string(ptr::null_mut());
+        }
+    }
+
+    #[test]
+    fn test_errors_are_reported() {
+        let small = handle(VideoHash::empty_hash_with_size("small", HashSize::Five));
+        let large = handle(VideoHash::full_hash("large"));
+
+        unsafe {
+            let mut distance = -1.0;
+            assert_eq!(
+                vdf_hash_distance(small, ptr::null(), &mut distance),
+                VdfStatus::NullPointer
+            );
+            assert_eq!(last_error(), "b must not be null");
+
+            assert_eq!(
+                vdf_hash_distance(small, large, &mut distance),
+                VdfStatus::IncompatibleHashes
+            );
+            //nothing is written on failure.
+            assert_eq!(distance, -1.0);
+
+            //hashes of the same size are only compared if a search would compare them.
+            let windowed = handle(
+                VideoHash::full_hash("windowed")
+                    .with_sampling_window(SamplingWindow::new(0.0, 5.0)),
+            );
+            assert_eq!(
+                vdf_hash_distance(large, windowed, &mut distance),
+                VdfStatus::IncompatibleHashes
+            );
+            assert_eq!(last_error(), "The hashes have different sampling windows");
+            assert_eq!(distance, -1.0);
+            vdf_free_hash(windowed);
+
+            let mut hash = ptr::null_mut();
+            assert_eq!(
+                vdf_hash_deserialize(c"{}".as_ptr(), &mut hash),
+                VdfStatus::InvalidHash
+            );
+            assert!(last_error().starts_with("Invalid portable hash"));
+            assert!(hash.is_null());
+
+            //invalid options are rejected before init is called. Only the init tests may call init
+            //in this test binary, so hashing a file is left to ffi/smoke_test.c.
+            let mut opts = VdfOptions {
+                hash_side: 0,
+                ..VdfOptions::default()
+            };
+            assert_eq!(vdf_options_default(&mut opts), VdfStatus::Ok);
+            assert_eq!(opts, VdfOptions::default());
+            for bad in [
+                VdfOptions {
+                    hash_side: 7,
+                    ..opts
+                },
+                VdfOptions {
+                    cropdetect: 3,
+                    ..opts
+                },
+                VdfOptions {
+                    duration_secs: f64::NAN,
+                    ..opts
+                },
+            ] {
+                assert_eq!(
+                    vdf_hash_file(c"a.mp4".as_ptr(), &bad, &mut hash),
+                    VdfStatus::InvalidArgument
+                );
+            }
+            assert!(hash.is_null());
+
+            vdf_free_hash(small);
+            vdf_free_hash(large);
+        }
+    }
+
+    #[test]
+    #[allow(clippy::panic)]
+    fn test_panics_do_not_unwind_into_the_caller() {
+        assert_eq!(guard(|| panic!("oh no")), VdfStatus::Panic);
+        assert_eq!(last_error(), "vid_dup_finder_lib panicked: oh no");
+
+        //errors are kept per thread.
+        let other_thread = std::thread::spawn(|| vdf_last_error_message().is_null())
+            .join()
+            .expect("no panic");
+        assert!(other_thread);
+    }
+}
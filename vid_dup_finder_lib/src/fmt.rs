@@ -0,0 +1,54 @@
+//! Formatting of video properties for display, so that every program built on this library shows
+//! durations and resolutions in the same way.
+
+use std::time::Duration;
+
+/// Format a duration as `mm:ss`, or as `hh:mm:ss` if it is an hour or longer. Fractions of a
+/// second are dropped, and hours are not wrapped into days.
+///
+/// ```
+/// use std::time::Duration;
+/// use vid_dup_finder_lib::fmt::format_duration;
+///
+/// assert_eq!(format_duration(Duration::from_secs(75)), "01:15");
+/// assert_eq!(format_duration(Duration::from_secs(3675)), "01:01:15");
+/// ```
+#[must_use]
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    let (hours, mins, secs) = (secs / 3600, secs / 60 % 60, secs % 60);
+    if hours == 0 {
+        format!("{mins:02}:{secs:02}")
+    } else {
+        format!("{hours:02}:{mins:02}:{secs:02}")
+    }
+}
+
+/// Format a `(width, height)` resolution as `widthxheight`.
+#[must_use]
+pub fn format_resolution((width, height): (u32, u32)) -> String {
+    format!("{width}x{height}")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_format_duration() {
+        let fmt = |secs| format_duration(Duration::from_secs(secs));
+        assert_eq!(fmt(0), "00:00");
+        assert_eq!(fmt(59), "00:59");
+        assert_eq!(fmt(3599), "59:59");
+        assert_eq!(fmt(3600), "01:00:00");
+        assert_eq!(fmt(25 * 3600 + 61), "25:01:01");
+        assert_eq!(fmt(1000 * 3600), "1000:00:00");
+        assert_eq!(format_duration(Duration::from_millis(59_999)), "00:59");
+    }
+
+    #[test]
+    fn test_format_resolution() {
+        assert_eq!(format_resolution((1920, 1080)), "1920x1080");
+        assert_eq!(format_resolution((0, 0)), "0x0");
+    }
+}
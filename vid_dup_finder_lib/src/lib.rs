@@ -16,8 +16,11 @@
 //! A near-duplicate video is a file that closely resembles another but may have differences
 //! such as format, resolution, quality, or framerate.
 //!
-//! However The library will not match video files if they have been rotated/flipped, sped up or slowed down, or embedded in
-//! the corner of another video.
+//! However The library will not match video files if they have been rotated/flipped or embedded in
+//! the corner of another video. (Videos whose containers only record a rotation, as phone videos
+//! do, are turned upright before they are hashed.) Copies which have been sped up or slowed down
+//! by a few common factors, or which have a second or so cut from the start, are only matched when
+//! asked for (see the [Limitations](#limitations)).
 //!
 //! # High Level API
 //! First provide the paths to a set of video files and turn them into hashes
@@ -27,6 +30,7 @@
 //! use vid_dup_finder_lib::VideoHash;
 //! use vid_dup_finder_lib::MatchGroup;
 //! use vid_dup_finder_lib::CreationOptions;
+//! use vid_dup_finder_lib::{SearchOptions, Tolerance};
 //! use vid_dup_finder_lib::ffmpeg_builder::VideoHashBuilder;
 //!
 //!
@@ -38,6 +42,9 @@
 //! // Paths to some vids to search for duplicates.
 //! // Let's assume the first two videos are duplicates and the third is unrelated.
 //! let vids = [&dup_vid_path_1, &dup_vid_path_2, &other_vid_path];
+//!
+//! // The video backend must be initialized before creating any hashes.
+//! vid_dup_finder_lib::init().expect("ffmpeg and ffprobe are installed");
 //! let builder = VideoHashBuilder::default();
 //! let hashes = vids.iter().map(|vid| builder.hash(vid.to_path_buf()).unwrap());
 //!
@@ -45,10 +52,11 @@
 //! // mean searches will match more different videos. The default search tolerance of
 //! // 0.3 is a good starting point for searching, but you can use a lower number if
 //! // there are too many false positives in your results.
-//! let tolerance = vid_dup_finder_lib::DEFAULT_SEARCH_TOLERANCE;
+//! let tolerance = Tolerance::new(vid_dup_finder_lib::DEFAULT_SEARCH_TOLERANCE).unwrap();
 //!
 //! // Perform the search...
-//! let dup_groups: Vec<MatchGroup> = vid_dup_finder_lib::search(hashes, tolerance);
+//! let opts = SearchOptions::new(tolerance);
+//! let dup_groups: Vec<MatchGroup> = vid_dup_finder_lib::search_with_opts(hashes, &opts);
 //!
 //! //dup_groups will contain a single match_group..
 //! assert_eq!(dup_groups.len(), 1);
@@ -86,36 +94,165 @@
 //! in the hash, as this can be used to speed up searching.
 //!
 //! You can then use the library to search with these hashes. Searches will return any group of videos
-//! with a similar length, and whose hashes differ by less than a set threshold.
+//! with a similar length, and whose hashes differ by less than a set threshold. Videos of very
+//! different lengths can also be matched by loosening [`SearchOptions::duration_gate`] (see
+//! [`DurationGate`]).
 //!
 //! ## Search functions
 //! The following search functions are available:
-//! * To find all duplicate videos within a set: [`crate::search`]
-//! * To find all duplicate videos using a set of reference videos: [`crate::search_with_references`]
+//! * To find all duplicate videos within a set: [`crate::search_with_opts`]
+//! * To find all duplicate videos using a set of reference videos: [`crate::search_with_references_with_opts`]
+//!
+//! Both accept a [`SearchOptions`] containing every setting described below. The tolerance is a
+//! [`Tolerance`], which can only be created from a value between 0.0 and 1.0 so that user input can
+//! be checked before searching. The older functions which take the tolerance as a bare `f64` (such
+//! as [`crate::search`]) are deprecated. They clamp out of range tolerances, logging a warning.
+//!
+//! ## Ordering of results
+//! Searches return the same groups in the same order regardless of the order in which hashes are supplied.
+//! By default the paths within each group are sorted, and groups are ordered by the smallest path they contain.
+//! Other orderings can be chosen with [`GroupOrdering`].
+//!
+//! ## Grouping
+//! By default each group is one video and every other video that matches it, so two members of a
+//! group need not match each other. [`SearchOptions::grouping`] can instead group every chain of
+//! matching videos together, or only videos which all match each other (see [`GroupingMode`]).
+//! ## Known false positives
+//! Pairs of videos that are known not to be duplicates can be collected in a [`FalseposFilter`] and
+//! passed to a search as [`SearchOptions::falsepos_filter`]. Those pairs will then never be reported
+//! together again, unless a third video in the same group matches both of them.
+//!
+//! ## Constraining groups by path
+//! [`SearchOptions::constraints`] can stop videos inside a directory from being grouped with each
+//! other, or only report groups with a video inside a directory (see [`ConstraintRule`]). Together
+//! these find the duplicates of newly added videos anywhere, without reporting the duplicates
+//! already known to be within an archive.
+//!
+//! ## Searching between collections
+//! Hashes from different places (such as the caches of two machines) can be labelled with
+//! [`VideoHash::with_collection`]. Setting [`SearchOptions::collections`] to
+//! [`CollectionMode::CrossCollectionOnly`] then only reports the groups that span more than one
+//! collection, and [`MatchGroup::collection_of`] tells which collection each video came from.
+//!
+//! ## Comparing audio
+//! Hashes created with [`CreationOptions::audio_fingerprint`] also carry an [`AudioFingerprint`] of
+//! the audio that plays over the hashed frames. Searching with [`SearchOptions::audio_check`] set
+//! to [`AudioCheck::Annotate`] records in each group whether the audio of its videos agrees (see
+//! [`MatchGroup::audio_agrees`]), and [`AudioCheck::RequireAgreement`] stops videos whose audio
+//! disagrees from matching at all.
+//!
+//! ## Short videos
+//! If a video is shorter than [`CreationOptions::duration`], its frames are sampled evenly across the whole
+//! video instead, and the resulting hash is flagged with [`VideoHash::is_short_video`]. Very short clips
+//! carry little information, so unrelated clips match each other far more often than longer videos do.
+//! For this reason searches compare pairs of short videos with a stricter tolerance
+//! ([`DEFAULT_SHORT_VIDEO_TOLERANCE`]). This can be changed (or short pairs can be excluded entirely) with
+//! [`SearchOptions::short_video_policy`].
+//!
+//! Different short or nearly blank videos (such as two clips of black frames) can even have
+//! identical hashes. Setting [`SearchOptions::zero_distance_check`] decodes a few frames of each
+//! such pair again and compares them, so that [`MatchGroup::frames_differ`] can tell them apart
+//! from real copies before anything is deleted.
+//!
+//! ## Memory usage
+//! Each frame is shrunk as soon as it has been decoded, so only one full resolution frame per video
+//! is held at a time. When [`CreationOptions::cropdetect`] is enabled the crop is detected on copies
+//! of the frames that are no more than 256 pixels on their longest side, which are then shrunk again
+//! to build the hash.
+//!
+//! This means that each thread creating hashes needs about one grayscale frame at the resolution of
+//! the video (8MB for 4K, 33MB for 8K) plus about 10MB for the shrunk frames and the DCT,
+//! regardless of the resolution of the video. The decoder's own buffers are on top of this. When
+//! hashing 8K content in parallel, size the thread pool with this figure in mind.
+//!
+//! ## Inspecting frames
+//! The frames that a hash would be created from can be obtained with `VideoHashBuilder::frames`, for
+//! instance to save them to disk or to check the crop that was detected. They can be hashed again
+//! with `VideoHashBuilder::hash_from_frames`.
+//!
+//! Frames for people to look at are made with `thumbnails` (see [`ThumbSpec`]), which takes them
+//! from the part of the video that its hash is built from and can crop them as the hash did. Using
+//! it everywhere that thumbnails are shown keeps the same video looking the same.
+//!
+//! ## Diagnostics
+//! The library logs through the [`log`](https://docs.rs/log) crate, so nothing is printed unless the
+//! application installs a logger. At debug level, each hash reports how long decoding and the DCT
+//! took, how many frames were used and the crop that was chosen, and each search reports how many
+//! comparisons it made. The search figures are also available as a [`SearchStats`] from
+//! [`crate::search_with_stats`] and [`crate::search_with_references_with_stats`], and the figures for
+//! a single hash as a [`HashStats`] from `VideoHashBuilder::hash_with_stats`.
+//!
+//! Hashes of the same video can differ slightly when they are created with different versions of
+//! the decoders. `environment_info` reports the backend and the versions of its components, and
+//! each hash records the [`EnvironmentDigest`] of the decoders that created it (see
+//! [`VideoHash::environment`]), so that hashes from another machine or an older install can be
+//! recognized.
+//!
+//! # Choosing which duplicate to keep
+//! Once a group of duplicates has been found, [`pick_best`] compares its files by an ordered list of
+//! [`Criterion`] (such as resolution, then file size) and reports the winner along with the values measured
+//! for every file.
+//!
+//! [`MatchGroup::potential_savings`] estimates how much disk space would be reclaimed by keeping one
+//! file of a group (chosen by a [`KeepPolicy`]) and deleting the rest, and [`total_savings`] does the
+//! same for a whole search without counting a file twice when it appears in several groups.
+//!
+//! # Using the library from other languages
+//! With the `ffi` feature the library exports a small C interface for hashing videos and comparing
+//! hashes. See the [`ffi`] module.
+//!
+//! # Using the library from async code
+//! With the `async` feature, `VideoHashBuilder::hash_async` returns a future which hashes the video
+//! on tokio's blocking thread pool, decoding no more than `VideoHashBuilder::with_max_concurrency`
+//! videos at once. Dropping the future stops the decode. The rest of the library does not use
+//! tokio.
 //!
 //! # Caching
-//! To generate the hashes this library must decode the first 20 seconds of each video it processes
-//! if there are a lot of viedos this takes a very long time. There is a companion crate called
+//! To generate the hashes this library must decode the first 25 seconds of each video it processes
+//! (by default, see [`Preset`]), so if there are a lot of viedos this takes a very long time. There is a companion crate called
 //! `video_hash_filesystem_cache` which will store caches on disk in between searches, reducing the amount of time
 //! spent loading videos.
 //!
 //! # Limitations
 //! The library is specifically designed to find near-duplicate videos (i.e ones that have not been significantly edited).
-//! Many transformations are capable of defeating it, such as rotation/flipping, watermarking, or time-offsetting.  
+//! Many transformations are capable of defeating it, such as rotation/flipping, watermarking, or large time offsets.
 //! However if the transformations are minor (a faint watermark, a small crop etc) then this library should still
 //! detect duplicate videos.
 //!
 //! Because the aim of this library is to find near-duplicates, the hashes are generated from the first
-//! 20 seconds of video content to save time.
-//!
-//! This library is will not defeat "classic" methods of hiding duplicates, such as horizontal mirroring, changing
-//! playback speed, or embedding video content in the corner of a static frame.
+//! 25 seconds of video content to save time. By default the first 15 seconds are skipped, and the
+//! frames are sampled from the next 10 seconds (see [`DEFAULT_VID_HASH_SKIP_FORWARD`] and
+//! [`DEFAULT_VID_HASH_DURATION`]).
 //!
+//! This library is will not defeat "classic" methods of hiding duplicates, such as horizontal mirroring
+//! or embedding video content in the corner of a static frame. Some other edits can be matched, but
+//! only with options that make hashes larger or searches slower, so they are off by default:
+//! * Copies played at a few common speeds can be found by creating hashes with
+//!   [`CreationOptions::speed_variants`] and searching with [`SearchOptions::speed_variants`] (see
+//!   [`speed_variants`]).
+//! * Copies with a second or so cut from the start (or added to it) can be found by creating hashes
+//!   with [`CreationOptions::frame_hashes`] and searching with [`SearchOptions::temporal_slack`].
+//!   Copies with black frames added to the start can be found by creating hashes with
+//!   [`CreationOptions::skip_leading_black`].
+//! * Clips cut from anywhere in a longer video can be found with [`search_for_clips`], and
+//!   [`MatchGroup::analyze_containment`] finds which videos of a group were cut from which.
 //!
 //! ## False Positives
 //! Because this library only checks the first few seconds of each video, if two videos are the same
 //! length and share the first few seconds of video content, they will be reported as a false match. This
-//! may occur for TV shows which contain opening credits.
+//! may occur for TV shows which contain opening credits. Creating hashes with a
+//! [`CreationOptions::coverage`] that also samples the middle and end of each video (see
+//! [`Coverage`]) avoids this.
+//!
+//! Videos which open on a plain background (such as a title card) match each other more often than
+//! they should, because most of the bits of their hashes are decided by noise. Creating hashes with
+//! [`CreationOptions::reliability_mask`] and searching with [`SearchOptions::ignore_unreliable_bits`]
+//! compares only the bits that carry information.
+//!
+//! Heavily letterboxed videos hashed without [`CreationOptions::cropdetect`] match each other more
+//! often than they should too, because most of their frames are black. Searching with
+//! [`SearchOptions::active_area`] (see [`ActiveAreaGuard`]) stops videos with differently sized
+//! borders, or with very little picture inside them, from matching.
 //!
 // //! # A note on data structures
 // //! The hashes produced by this library fully satisfy the triangle equality, and it is possible to use a
@@ -126,13 +263,80 @@
 // //! videos with differing durations cannot be duplicates of each other to practically reduce the number of comparisons
 // //! required. However if all your videos are the same length searches will unfortunately still perform n^2 comparisons.
 
+//Only the ffi module is part of the C interface (see cbindgen.toml).
+/// cbindgen:ignore
 mod definitions;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod fmt;
+/// cbindgen:ignore
 mod video_hashing;
 
 pub use video_hashing::{
-    matches::match_group::MatchGroup, video_dup_finder::search,
-    video_dup_finder::search_with_references, video_hash::VideoHash,
-    video_hash_builder::CreationOptions, Error,
+    audio_fingerprint::AudioFingerprint, cancellation::CancellationToken,
+    clip_search::search_for_clips, clip_search::ClipMatch, compact_hash,
+    compact_hash::CompactHashError, compact_hash::COMPACT_HASH_VERSION, containment,
+    containment::ContainmentOpts, containment::ContainmentReport, containment::Contains,
+    containment::FrameSource, distance_matrix::distance_matrix,
+    distance_matrix::distance_matrix_with_opts, distance_matrix::DistanceMatrix,
+    distance_matrix::DistanceMatrixOptions, distance_matrix::MatrixTooLarge,
+    distance_matrix::DEFAULT_MAX_MATRIX_ELEMENTS, duplicate_detector::DuplicateDetector,
+    duplicate_detector::DuplicateHit, environment::EnvironmentDigest, environment::EnvironmentInfo,
+    frame_normalization::FramePreprocessor, frame_normalization::Normalization,
+    hash_stats::HashStats, init::init, match_verification::verify_match,
+    match_verification::VerifyOpts, match_verification::VerifyReport,
+    matches::falsepos_filter::FalseposFilter, matches::match_group::MatchGroup,
+    non_match::explain_non_match, non_match::Incompatibility, non_match::NonMatchReason,
+    packed_hash, packed_hash::PackedHash, pick_best::BestPick, pick_best::Criterion,
+    pick_best::FileMetrics, portable_hash, portable_hash::PortableHashError,
+    portable_hash::PORTABLE_HASH_VERSION, savings::total_savings, savings::KeepPolicy,
+    savings::SavingsReport, savings::TotalSavings, search_options::ActiveAreaGuard,
+    search_options::AudioCheck, search_options::CollectionMode, search_options::ConstraintRule,
+    search_options::DurationGate, search_options::GroupOrdering, search_options::GroupingMode,
+    search_options::SearchOptions, search_options::Tolerance, search_options::ToleranceError,
+    search_options::ZeroDistanceCheck, search_plan::plan_search, search_plan::DurationBucket,
+    search_plan::SearchPlan, search_snapshot, search_snapshot::search_with_references_incremental,
+    search_snapshot::ReferenceSearchResults, search_snapshot::SearchSnapshot,
+    search_stats::DuplicateInput, search_stats::SearchStats, shared_scenes::search_shared_scenes,
+    shared_scenes::SceneMatch, shared_scenes::WindowedVideoHash, speed_variants,
+    speed_variants::SpeedFactor, speed_variants::SpeedFactors,
+    speed_variants::UnsupportedSpeedFactor, thumbnails::ThumbSpec, two_pass::two_pass_search,
+    two_pass::TwoPassTolerances, video_dup_finder::search_with_opts,
+    video_dup_finder::search_with_plan, video_dup_finder::search_with_references_prioritized,
+    video_dup_finder::search_with_references_with_opts,
+    video_dup_finder::search_with_references_with_stats, video_dup_finder::search_with_stats,
+    video_hash::HashSizeMismatch, video_hash::VideoHash, video_hash_builder::CreationOptions,
+    video_hash_builder::CreationOptionsError, video_hash_builder::Preset,
+    zero_distance::ZeroDistanceVerification, zero_distance::VERIFIED_FRAMES, Error,
+};
+
+pub use ffmpeg_gst_wrapper::{ColorRange, Deinterlace, DurationSource};
+pub use vid_dup_finder_common::Crop;
+
+#[allow(deprecated)]
+pub use video_hashing::video_dup_finder::{
+    search, search_filtered, search_with_references, search_with_references_and_short_video_policy,
+    search_with_references_filtered, search_with_short_video_policy,
+};
+
+#[cfg(any(feature = "ffmpeg_backend", feature = "gstreamer_backend"))]
+pub use video_hashing::pick_best::pick_best;
+
+#[cfg(any(feature = "ffmpeg_backend", feature = "gstreamer_backend"))]
+pub use video_hashing::environment::environment_info;
+
+#[cfg(any(feature = "ffmpeg_backend", feature = "gstreamer_backend"))]
+pub use video_hashing::containment::WindowHasher;
+
+#[cfg(any(feature = "ffmpeg_backend", feature = "gstreamer_backend"))]
+pub use video_hashing::thumbnails::{thumbnails, thumbnails_with_crop, thumbnails_with_options};
+
+#[allow(deprecated)]
+pub use video_hashing::init::init_gstreamer;
+
+#[cfg(feature = "ffmpeg_backend")]
+pub use video_hashing::init::{
+    ffmpeg_is_outdated, set_min_ffmpeg_version, DEFAULT_MIN_FFMPEG_VERSION,
 };
 
 #[cfg(feature = "ffmpeg_backend")]
@@ -142,19 +346,22 @@ pub use video_hashing::video_hash_builder::ffmpeg as ffmpeg_builder;
 pub use video_hashing::video_hash_builder::gstreamer as gstreamer_builder;
 
 pub use definitions::{
-    Cropdetect, DEFAULT_SEARCH_TOLERANCE, DEFAULT_VID_HASH_DURATION, DEFAULT_VID_HASH_SKIP_FORWARD,
+    Coverage, Cropdetect, HashSize, SamplingWindow, ShortVideoPolicy,
+    DEFAULT_DURATION_CHECK_FACTOR, DEFAULT_MIN_MOTION_CROP_CONFIDENCE, DEFAULT_SEARCH_TOLERANCE,
+    DEFAULT_SHORT_VIDEO_TOLERANCE, DEFAULT_VID_HASH_DURATION,
+    DEFAULT_VID_HASH_MAX_MEASURED_DURATION, DEFAULT_VID_HASH_MIN_DURATION,
+    DEFAULT_VID_HASH_SKIP_FORWARD, MAX_COVERAGE_SEGMENTS,
 };
 
 #[cfg(any(feature = "test-util", test))]
 pub use definitions::TOLERANCE_SCALING_FACTOR;
 #[cfg(any(feature = "test-util", test))]
+pub use video_hashing::fixtures;
+#[cfg(any(feature = "test-util", test))]
 pub use video_hashing::video_hash::test_util;
 
 #[doc(hidden)]
-/// Utilities for visualizing the frames that vid_dup_finder_lib extracts
-/// These functions are not part of the stable API.
-pub mod debug_util {
-    pub use crate::video_hashing::video_hash_builder::build_frame_reader;
-}
+#[cfg(feature = "app_only_fns")]
+pub use video_hashing::video_hash_builder::build_frame_reader;
 
 type VideoHashResult<T> = Result<T, crate::Error>;
@@ -0,0 +1,98 @@
+//! Hashing from async code, with the `async` feature. See `VideoHashBuilder::hash_async`.
+//!
+//! Decoding a video blocks for seconds at a time, so each hash is created on tokio's blocking
+//! thread pool. The number of videos decoded at once is limited by the builder's slots, which a
+//! hash holds from when it starts decoding until the decoder has stopped, so that videos whose futures
+//! were dropped still count against the limit until they have actually finished with their
+//! decoder.
+use std::future::Future;
+use std::num::NonZeroUsize;
+use std::path::PathBuf;
+use std::sync::{Arc, OnceLock};
+
+use ffmpeg_gst_wrapper::FrameReadCfgTrait;
+use tokio::sync::Semaphore;
+
+use crate::video_hashing::video_hash_builder::gen_hash_cancellable;
+use crate::{CancellationToken, CreationOptions, Error, FramePreprocessor, VideoHash};
+
+// Builders without VideoHashBuilder::with_max_concurrency decode as many videos at once as there
+// are CPUs, but no more than this.
+const MAX_DEFAULT_CONCURRENCY: usize = 8;
+
+// The slots shared by every builder without its own limit.
+static SHARED_SLOTS: OnceLock<Arc<Semaphore>> = OnceLock::new();
+
+// The videos that may be decoded at once by hash_async. Clones share the same slots.
+#[derive(Debug, Clone)]
+pub(crate) struct HashSlots(Arc<Semaphore>);
+
+impl Default for HashSlots {
+    // Shared by the whole process, so that creating more builders does not decode more videos.
+    fn default() -> Self {
+        let slots = SHARED_SLOTS.get_or_init(|| {
+            let num_cpus = std::thread::available_parallelism().map_or(1, NonZeroUsize::get);
+            Arc::new(Semaphore::new(num_cpus.min(MAX_DEFAULT_CONCURRENCY)))
+        });
+        Self(slots.clone())
+    }
+}
+
+impl HashSlots {
+    // Slots which are not shared with any other builder.
+    pub(crate) fn new(max_concurrency: NonZeroUsize) -> Self {
+        Self(Arc::new(Semaphore::new(max_concurrency.get())))
+    }
+}
+
+// Cancels the decode of a hash whose future was dropped before it finished.
+struct CancelOnDrop(CancellationToken);
+
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        self.0.cancel();
+    }
+}
+
+// Hash the video at src_path on tokio's blocking thread pool, once one of the slots is free.
+pub(crate) fn hash_async<T: FrameReadCfgTrait + Clone + 'static>(
+    src_path: PathBuf,
+    opts: CreationOptions,
+    preprocessor: Option<FramePreprocessor>,
+    duration_check: Option<f64>,
+    slots: &HashSlots,
+) -> impl Future<Output = Result<VideoHash, Error>> + Send + 'static
+where
+    T::E: std::error::Error,
+{
+    let slots = slots.0.clone();
+    async move {
+        crate::video_hashing::init::ensure_initialized()?;
+
+        //the semaphore is never closed.
+        let slot = slots
+            .acquire_owned()
+            .await
+            .map_err(|_closed| Error::Cancelled)?;
+
+        let token = CancellationToken::new();
+        let _cancel_on_drop = CancelOnDrop(token.clone());
+        let task = tokio::task::spawn_blocking(move || {
+            let hash = gen_hash_cancellable::<T>(
+                src_path,
+                opts,
+                preprocessor.as_ref(),
+                duration_check,
+                &token,
+            );
+            drop(slot);
+            hash
+        });
+
+        match task.await {
+            Ok(hash) => hash,
+            Err(e) if e.is_panic() => std::panic::resume_unwind(e.into_panic()),
+            Err(e) => Err(Error::VidProc(format!("Hashing task failed: {e}"))),
+        }
+    }
+}
@@ -0,0 +1,217 @@
+//! Fingerprints of the audio at the start of a video, for confirming that videos which look alike
+//! also sound alike.
+//!
+//! The audio is cut into overlapping slices, and the energy of each slice is measured in a number
+//! of frequency bands which are spaced evenly in pitch. Each bit of the fingerprint records whether
+//! the difference in energy between two neighbouring bands grew or shrank since the previous
+//! slice. Lossy encoding changes the energies a little, but rarely the direction in which they
+//! change, so re-encoded copies of some audio have nearly the same bits while other audio differs
+//! in about half of them.
+
+use rustdct::DctPlanner;
+use serde::{Deserialize, Serialize};
+
+/// The rate at which audio is decoded for fingerprinting, in samples per second. Audio is mixed
+/// down to a single channel first.
+pub(crate) const AUDIO_SAMPLE_RATE: u32 = 11025;
+
+//The number of bits recorded for each slice, one per pair of neighbouring bands.
+const BANDS: usize = 32;
+//The number of slices compared with the slice before them. One more slice is measured.
+const SLICES: usize = 64;
+//The number of samples in each slice (about 0.19 seconds).
+const WINDOW: usize = 2048;
+const MIN_FREQ: f64 = 150.0;
+const MAX_FREQ: f64 = 5000.0;
+
+//Less audio than this is not fingerprinted.
+const MIN_SAMPLES: usize = AUDIO_SAMPLE_RATE as usize;
+
+//Band energies below this fraction of the mean are treated as silence, so that the noise in
+//empty bands (which lossy encoding is free to change) does not decide any bits.
+const ENERGY_FLOOR: f64 = 1e-4;
+
+//The largest proportion of differing bits at which two fingerprints agree.
+const AGREEMENT_TOLERANCE: f64 = 0.35;
+
+/// A compact signature of the audio that a hash was created from, kept if
+/// [`CreationOptions::audio_fingerprint`][crate::CreationOptions::audio_fingerprint] is set. See
+/// [`crate::VideoHash::audio_agrees`].
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct AudioFingerprint {
+    //One word per slice, with a bit for each pair of bands.
+    slices: Vec<u32>,
+}
+
+impl AudioFingerprint {
+    /// Fingerprint mono audio sampled at [`AUDIO_SAMPLE_RATE`]. Returns None if there is less than
+    /// a second of audio.
+    pub(crate) fn from_samples(samples: &[i16]) -> Option<Self> {
+        if samples.len() < MIN_SAMPLES {
+            return None;
+        }
+
+        let mut planner = DctPlanner::new();
+        let dct = planner.plan_dct2(WINDOW);
+        let window = (0..WINDOW)
+            .map(|i| 0.5 - 0.5 * (std::f64::consts::TAU * i as f64 / WINDOW as f64).cos())
+            .collect::<Vec<_>>();
+
+        //The DCT coefficient at which each band starts. The last edge is where the last band ends.
+        let edges = (0..=BANDS + 1)
+            .map(|i| {
+                let freq = MIN_FREQ * (MAX_FREQ / MIN_FREQ).powf(i as f64 / (BANDS + 1) as f64);
+                (freq * 2.0 * WINDOW as f64 / f64::from(AUDIO_SAMPLE_RATE)) as usize
+            })
+            .collect::<Vec<_>>();
+
+        let hop = (samples.len() - WINDOW) as f64 / SLICES as f64;
+        let energies = (0..=SLICES)
+            .map(|slice| {
+                let start = (slice as f64 * hop) as usize;
+                let mut buf = samples[start..start + WINDOW]
+                    .iter()
+                    .zip(&window)
+                    .map(|(&sample, &w)| f64::from(sample) * w)
+                    .collect::<Vec<_>>();
+                dct.process_dct2(&mut buf);
+                edges
+                    .windows(2)
+                    .map(|band| buf[band[0]..band[1]].iter().map(|c| c * c).sum::<f64>())
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+
+        let num_energies = energies.len() * (BANDS + 1);
+        let mean = energies.iter().flatten().sum::<f64>() / num_energies as f64;
+        let floor = (mean * ENERGY_FLOOR).max(f64::MIN_POSITIVE);
+        let levels = energies
+            .into_iter()
+            .map(|bands| {
+                bands
+                    .into_iter()
+                    .map(|energy| energy.max(floor).ln())
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+
+        let slices = levels
+            .windows(2)
+            .map(|pair| {
+                let (prev, curr) = (&pair[0], &pair[1]);
+                (0..BANDS).fold(0u32, |acc, band| {
+                    let delta = (curr[band + 1] - curr[band]) - (prev[band + 1] - prev[band]);
+                    acc | (u32::from(delta > 0.0) << band)
+                })
+            })
+            .collect();
+
+        Some(Self { slices })
+    }
+
+    pub(crate) fn from_slices(slices: Vec<u32>) -> Self {
+        Self { slices }
+    }
+
+    pub(crate) fn slices(&self) -> &[u32] {
+        &self.slices
+    }
+
+    /// The proportion of bits that differ between the two fingerprints, between 0.0 and 1.0.
+    /// Fingerprints of the same audio are usually well below 0.2 apart, even after re-encoding,
+    /// and unrelated audio is about 0.5 apart.
+    #[must_use]
+    pub fn distance(&self, other: &Self) -> f64 {
+        let differing = self
+            .slices
+            .iter()
+            .zip(&other.slices)
+            .map(|(a, b)| (a ^ b).count_ones())
+            .sum::<u32>();
+        //bits that only one fingerprint has (which can only happen if one is corrupt) differ.
+        let len = self.slices.len().max(other.slices.len());
+        let missing = (len - self.slices.len().min(other.slices.len())) * BANDS;
+        if len == 0 {
+            0.0
+        } else {
+            (differing as usize + missing) as f64 / (len * BANDS) as f64
+        }
+    }
+
+    /// Whether the two fingerprints are close enough to be of the same audio.
+    #[must_use]
+    pub fn agrees(&self, other: &Self) -> bool {
+        self.distance(other) <= AGREEMENT_TOLERANCE
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use rand::prelude::*;
+
+    use super::*;
+
+    // A few seconds of something like music: a run of notes chosen by seed, each with a couple of
+    // harmonics and a decaying envelope. If reencoded is set the audio is degraded as a low bitrate
+    // encoder might, by making it quieter, smoothing away some of the treble, adding noise and
+    // dropping the lowest bits.
+    pub(crate) fn melody(seed: u64, secs: f64, reencoded: bool) -> Vec<i16> {
+        const NOTE_SECS: f64 = 0.25;
+        let mut rng = StdRng::seed_from_u64(seed);
+        let num_notes = (secs / NOTE_SECS).ceil() as usize;
+        let notes = (0..num_notes)
+            .map(|_| 220.0 * 2f64.powf(f64::from(rng.gen_range(0..36u8)) / 12.0))
+            .collect::<Vec<_>>();
+
+        let num_samples = (secs * f64::from(AUDIO_SAMPLE_RATE)) as usize;
+        let clean = (0..num_samples).map(|i| {
+            let t = i as f64 / f64::from(AUDIO_SAMPLE_RATE);
+            let note = ((t / NOTE_SECS) as usize).min(num_notes - 1);
+            let since_note = t - note as f64 * NOTE_SECS;
+            let envelope = (-6.0 * since_note).exp();
+            let phase = std::f64::consts::TAU * notes[note] * t;
+            envelope * (phase.sin() + 0.5 * (2.0 * phase).sin() + 0.25 * (3.0 * phase).sin())
+        });
+
+        if !reencoded {
+            return clean.map(|x| (x * 12000.0) as i16).collect();
+        }
+
+        let mut noise = StdRng::seed_from_u64(!seed);
+        let mut prev = 0.0;
+        clean
+            .map(|x| {
+                let smoothed = 0.5 * (x + prev);
+                prev = x;
+                let noisy = 0.7 * smoothed + noise.gen_range(-0.01..0.01);
+                (((noisy * 12000.0) as i16) >> 4) << 4
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_reencoded_audio_agrees() {
+        let original =
+            AudioFingerprint::from_samples(&melody(1, 10.0, false)).expect("long enough");
+        let reencoded =
+            AudioFingerprint::from_samples(&melody(1, 10.0, true)).expect("long enough");
+        let other = AudioFingerprint::from_samples(&melody(2, 10.0, false)).expect("long enough");
+
+        assert_eq!(original.slices.len(), SLICES);
+        assert!(original.distance(&reencoded) < 0.2);
+        assert!(original.agrees(&reencoded));
+        assert!(original.distance(&other) > 0.4);
+        assert!(!original.agrees(&other));
+        assert!(!reencoded.agrees(&other));
+    }
+
+    #[test]
+    fn test_short_or_silent_audio() {
+        assert!(AudioFingerprint::from_samples(&melody(1, 0.5, false)).is_none());
+
+        //silence is the same everywhere.
+        let silence = AudioFingerprint::from_samples(&[0; 5 * MIN_SAMPLES]).expect("long enough");
+        assert!(silence.slices.iter().all(|&slice| slice == 0));
+        assert_eq!(silence.distance(&silence), 0.0);
+    }
+}
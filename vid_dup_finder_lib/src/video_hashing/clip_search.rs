@@ -0,0 +1,160 @@
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use crate::VideoHash;
+
+/// A clip which was found inside a longer video by [`search_for_clips`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClipMatch {
+    clip: PathBuf,
+    source: PathBuf,
+    offset: Duration,
+    distance: f64,
+}
+
+impl ClipMatch {
+    /// The path of the clip.
+    #[must_use]
+    pub fn clip(&self) -> &Path {
+        &self.clip
+    }
+
+    /// The path of the longer video that contains the clip.
+    #[must_use]
+    pub fn source(&self) -> &Path {
+        &self.source
+    }
+
+    /// The start of the window of the source which matched the clip.
+    #[must_use]
+    pub const fn offset(&self) -> Duration {
+        self.offset
+    }
+
+    /// The normalized distance between the clip and the matching window, in the range 0..=1.
+    #[must_use]
+    pub const fn distance(&self) -> f64 {
+        self.distance
+    }
+}
+
+/// Search for clips which are contained inside longer videos.
+///
+/// Each clip is an ordinary hash, and each source is the list of hashes returned by
+/// `hash_windows` for a single longer video. A clip matches a source if it is within `tolerance`
+/// of any of the source's windows, and the closest window (or the earliest, if several are
+/// equally close) is reported. Durations are not compared, and a clip is never matched against a
+/// source with the same path.
+///
+/// The clips and sources must be hashed with the same [`CreationOptions::duration`][crate::CreationOptions::duration].
+/// The skip forward amount should usually be 0 when hashing the clips, otherwise the reported offset
+/// is where the skipped-to part of the clip begins. Smaller window strides make matches more likely,
+/// at the price of more hashes per source.
+///
+/// Matches are returned sorted by clip, then by source.
+#[must_use]
+pub fn search_for_clips(
+    clips: Vec<VideoHash>,
+    sources: Vec<Vec<VideoHash>>,
+    tolerance: f64,
+) -> Vec<ClipMatch> {
+    let mut ret = clips
+        .iter()
+        .flat_map(|clip| {
+            sources
+                .iter()
+                .filter_map(move |windows| best_window(clip, windows, tolerance))
+        })
+        .collect::<Vec<_>>();
+
+    ret.sort_by(|m1, m2| (&m1.clip, &m1.source).cmp(&(&m2.clip, &m2.source)));
+    ret
+}
+
+pub(crate) fn best_window(
+    clip: &VideoHash,
+    windows: &[VideoHash],
+    tolerance: f64,
+) -> Option<ClipMatch> {
+    let threshold = (tolerance * f64::from(clip.hash_size().bits())) as u32;
+
+    //hashes of different sizes are never comparable, so they never match.
+    let (distance, window) = windows
+        .iter()
+        .filter(|window| window.src_path() != clip.src_path())
+        .filter_map(|window| Some((clip.hamming_distance(window).ok()?, window)))
+        .filter(|(distance, _)| *distance <= threshold)
+        .min_by_key(|(distance, window)| (*distance, window.window_start()))?;
+
+    Some(ClipMatch {
+        clip: clip.src_path().to_path_buf(),
+        source: window.src_path().to_path_buf(),
+        offset: window.window_start().unwrap_or_default(),
+        distance: f64::from(distance) / f64::from(clip.hash_size().bits()),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+
+    // A source video made of distinct windows, starting every 10 seconds.
+    fn source(name: &str, num_windows: u64, rng: &mut StdRng) -> Vec<VideoHash> {
+        (0..num_windows)
+            .map(|i| {
+                VideoHash::random_hash(rng)
+                    .with_src_path(name)
+                    .with_window_start(Duration::from_secs(i * 10))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_clip_found_at_offset() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let compilation = source("compilation", 20, &mut rng);
+        let other = source("other", 20, &mut rng);
+
+        //A re-encode of the part of the compilation that starts after 70 seconds.
+        let clip = compilation[7].with_src_path("clip").with_flipped_bits(0..5);
+        let unrelated = VideoHash::random_hash(&mut rng).with_src_path("unrelated");
+
+        let matches = search_for_clips(vec![unrelated, clip], vec![other, compilation], 0.1);
+
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].clip(), Path::new("clip"));
+        assert_eq!(matches[0].source(), Path::new("compilation"));
+        assert_eq!(matches[0].offset(), Duration::from_secs(70));
+        assert!(matches[0].distance() > 0.0);
+    }
+
+    #[test]
+    fn test_closest_window_is_reported() {
+        let mut rng = StdRng::seed_from_u64(2);
+        let mut compilation = source("compilation", 5, &mut rng);
+
+        //the clip is shown twice, but the second copy is a closer match.
+        let clip = compilation[1].with_src_path("clip").with_flipped_bits(0..1);
+        compilation[3] = compilation[1]
+            .with_flipped_bits(0..1)
+            .with_window_start(Duration::from_secs(30));
+        compilation[1] = compilation[1].with_flipped_bits(10..13);
+
+        let matches = search_for_clips(vec![clip.clone()], vec![compilation.clone()], 0.1);
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].offset(), Duration::from_secs(30));
+        assert_eq!(matches[0].distance(), 0.0);
+
+        //A clip is not found inside itself.
+        let matches = search_for_clips(
+            vec![clip.with_src_path("compilation")],
+            vec![compilation],
+            0.1,
+        );
+        assert!(matches.is_empty());
+    }
+}
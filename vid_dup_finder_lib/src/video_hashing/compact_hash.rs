@@ -0,0 +1,321 @@
+//! A small binary format for hashes, for programs which keep their own index of hashes and only
+//! need the parts of a hash that a search uses.
+//!
+//! # Layout (version 1)
+//! | bytes    | contents                                                                        |
+//! |----------|---------------------------------------------------------------------------------|
+//! | 1        | the version, [`COMPACT_HASH_VERSION`]                                           |
+//! | 1        | the side of the hash: 5, 6, 8 or 10 (see [`HashSize`])                          |
+//! | 1        | flags: `1` if a path follows the bits, `2` if the hash is of a short video      |
+//! | 1 to 10  | the duration in milliseconds, as an unsigned LEB128 varint                      |
+//! | 16 to 125| the bits, laid out as the `bits` field of the [portable format][crate::portable_hash] |
+//! |          | if there is a path: its length in bytes as a varint, then its bytes             |
+//!
+//! Any other flag, or any byte after the end of the hash, is an error.
+//!
+//! Only the bits, duration, path and the short video flag are stored. Everything else that a hash
+//! can hold (its frame hashes, reliability mask, video stream, window start, leading black,
+//! normalization, coverage, sampling window and pixel aspect ratio) is left out, and is the default when the hash is read back. Use the portable
+//! format to keep them.
+//!
+//! On unix, paths are stored as their raw bytes. Elsewhere they are stored as UTF-8, and paths
+//! which are not valid UTF-8 cannot be read back.
+
+use std::path::{Path, PathBuf};
+
+use thiserror::Error;
+
+use super::{
+    portable_hash::{from_bytes, to_bytes},
+    video_hash::coarse_signature,
+};
+use crate::{HashSize, VideoHash};
+
+/// The version of the compact format written by [`VideoHash::encode_compact`]. It is the only
+/// version that [`VideoHash::decode_compact`] can read.
+pub const COMPACT_HASH_VERSION: u8 = 1;
+
+const FLAG_PATH: u8 = 1;
+const FLAG_SHORT_VIDEO: u8 = 2;
+
+/// The error returned when a hash cannot be read from the compact format.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum CompactHashError {
+    /// The hash was written in a version of the format that cannot be read.
+    #[error(
+        "Unsupported compact hash version {0}. The supported version is {COMPACT_HASH_VERSION}"
+    )]
+    UnsupportedVersion(u8),
+
+    /// The side is not that of any [`HashSize`].
+    #[error("Unsupported hash side {0}")]
+    UnsupportedSide(u8),
+
+    /// The flags byte has bits set which the format does not define.
+    #[error("Unknown flags {0:#04x}")]
+    UnknownFlags(u8),
+
+    /// The input ended part way through the hash.
+    #[error("The compact hash is truncated")]
+    Truncated,
+
+    /// A varint is longer than 64 bits.
+    #[error("Invalid varint")]
+    InvalidVarint,
+
+    /// There were bytes left over after the end of the hash.
+    #[error("{0} unexpected bytes after the end of the compact hash")]
+    TrailingBytes(usize),
+
+    /// The path cannot be represented on this platform.
+    #[error("The path is not valid UTF-8")]
+    InvalidPath,
+}
+
+impl VideoHash {
+    /// This hash in the compact binary format described in the
+    /// [`compact_hash`][crate::compact_hash] module, including its path.
+    #[must_use]
+    pub fn encode_compact(&self) -> Vec<u8> {
+        self.encode_compact_inner(Some(&self.src_path))
+    }
+
+    /// As [`VideoHash::encode_compact`], but without the path, for programs which already key
+    /// hashes by their path. The hash read back has an empty path.
+    #[must_use]
+    pub fn encode_compact_without_path(&self) -> Vec<u8> {
+        self.encode_compact_inner(None)
+    }
+
+    fn encode_compact_inner(&self, path: Option<&Path>) -> Vec<u8> {
+        let mut flags = 0;
+        if path.is_some() {
+            flags |= FLAG_PATH;
+        }
+        if self.short_video {
+            flags |= FLAG_SHORT_VIDEO;
+        }
+
+        let side = u8::try_from(self.hash_size.side()).expect("hash sides fit in a byte");
+        let mut ret = vec![COMPACT_HASH_VERSION, side, flags];
+        write_varint(&mut ret, u64::from(self.duration) * 1000);
+        ret.extend(to_bytes(&self.hash, self.hash_size));
+
+        if let Some(path) = path {
+            let path_bytes = path.as_os_str().as_encoded_bytes();
+            write_varint(&mut ret, path_bytes.len() as u64);
+            ret.extend_from_slice(path_bytes);
+        }
+
+        ret
+    }
+
+    /// Read a hash written by [`VideoHash::encode_compact`] or
+    /// [`VideoHash::encode_compact_without_path`].
+    ///
+    /// # Errors
+    /// Returns `Err` if the input is not exactly one hash in a supported version of the format.
+    pub fn decode_compact(bytes: &[u8]) -> Result<Self, CompactHashError> {
+        let mut reader = Reader(bytes);
+
+        let version = reader.byte()?;
+        if version != COMPACT_HASH_VERSION {
+            return Err(CompactHashError::UnsupportedVersion(version));
+        }
+
+        let side = reader.byte()?;
+        let hash_size =
+            HashSize::from_side(u32::from(side)).ok_or(CompactHashError::UnsupportedSide(side))?;
+
+        let flags = reader.byte()?;
+        if flags & !(FLAG_PATH | FLAG_SHORT_VIDEO) != 0 {
+            return Err(CompactHashError::UnknownFlags(flags));
+        }
+
+        //hashes only record whole seconds.
+        let duration = u32::try_from(reader.varint()? / 1000).unwrap_or(u32::MAX);
+
+        let hash = from_bytes(
+            reader.take(hash_size.bits().div_ceil(8) as usize)?,
+            hash_size,
+        );
+        let coarse = Some(coarse_signature(&hash, hash_size));
+
+        let src_path = if flags & FLAG_PATH == 0 {
+            PathBuf::new()
+        } else {
+            let len = usize::try_from(reader.varint()?).map_err(|_| CompactHashError::Truncated)?;
+            path_from_bytes(reader.take(len)?)?
+        };
+
+        if !reader.0.is_empty() {
+            return Err(CompactHashError::TrailingBytes(reader.0.len()));
+        }
+
+        Ok(Self {
+            hash,
+            hash_size,
+            src_path,
+            duration,
+            short_video: flags & FLAG_SHORT_VIDEO != 0,
+            coarse,
+            ..Self::default()
+        })
+    }
+}
+
+fn write_varint(buf: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            buf.push(byte);
+            return;
+        }
+        buf.push(byte | 0x80);
+    }
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], CompactHashError> {
+        if self.0.len() < len {
+            return Err(CompactHashError::Truncated);
+        }
+        let (taken, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    fn byte(&mut self) -> Result<u8, CompactHashError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn varint(&mut self) -> Result<u64, CompactHashError> {
+        let mut value = 0;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            let bits = u64::from(byte & 0x7f);
+            //the tenth byte may only hold the top bit of a u64.
+            if shift == 63 && bits > 1 {
+                return Err(CompactHashError::InvalidVarint);
+            }
+            value |= bits << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(CompactHashError::InvalidVarint)
+    }
+}
+
+#[cfg(unix)]
+fn path_from_bytes(bytes: &[u8]) -> Result<PathBuf, CompactHashError> {
+    use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+    Ok(PathBuf::from(OsStr::from_bytes(bytes)))
+}
+
+#[cfg(not(unix))]
+fn path_from_bytes(bytes: &[u8]) -> Result<PathBuf, CompactHashError> {
+    std::str::from_utf8(bytes)
+        .map(PathBuf::from)
+        .map_err(|_| CompactHashError::InvalidPath)
+}
+
+#[cfg(test)]
+mod test {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let mut rng = StdRng::seed_from_u64(1);
+        for hash_size in HashSize::ALL {
+            let hash = VideoHash::random_hash_with_size(&mut rng, hash_size)
+                .with_src_path(format!("/videos/{hash_size}.mp4"))
+                .with_duration(61)
+                .with_short_video(true);
+            let encoded = hash.encode_compact();
+            //61000ms takes 3 bytes as a varint, and the length of the path 1.
+            let path_len = hash.src_path().as_os_str().len();
+            assert_eq!(
+                encoded.len(),
+                3 + 3 + hash_size.bits().div_ceil(8) as usize + 1 + path_len
+            );
+            assert_eq!(VideoHash::decode_compact(&encoded), Ok(hash.clone()));
+
+            let without_path = VideoHash::decode_compact(&hash.encode_compact_without_path());
+            assert_eq!(without_path, Ok(hash.with_src_path("")));
+        }
+
+        //long durations take more than one byte.
+        let hash = VideoHash::full_hash("").with_duration(u32::MAX);
+        let encoded = hash.encode_compact();
+        assert_eq!(encoded[..3], [COMPACT_HASH_VERSION, 6, FLAG_PATH]);
+        assert_eq!(VideoHash::decode_compact(&encoded), Ok(hash));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_non_utf8_path_round_trip() {
+        use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+        let path = Path::new(OsStr::from_bytes(b"/videos/\xff\xfe.mp4"));
+        let hash = VideoHash::full_hash(path);
+        let read = VideoHash::decode_compact(&hash.encode_compact()).expect("round trip");
+        assert_eq!(read.src_path(), path);
+    }
+
+    #[test]
+    fn test_malformed_hashes_are_rejected() {
+        use CompactHashError::*;
+
+        let encoded = VideoHash::full_hash("a.mp4")
+            .with_duration(3)
+            .encode_compact();
+        let with = |idx: usize, byte: u8| {
+            let mut bytes = encoded.clone();
+            bytes[idx] = byte;
+            VideoHash::decode_compact(&bytes)
+        };
+
+        assert_eq!(with(0, 2), Err(UnsupportedVersion(2)));
+        assert_eq!(with(1, 7), Err(UnsupportedSide(7)));
+        assert_eq!(with(2, 4), Err(UnknownFlags(4)));
+        assert_eq!(
+            VideoHash::decode_compact(&[encoded.as_slice(), &[0]].concat()),
+            Err(TrailingBytes(1))
+        );
+        for len in 0..encoded.len() {
+            assert_eq!(VideoHash::decode_compact(&encoded[..len]), Err(Truncated));
+        }
+
+        let mut too_long = vec![COMPACT_HASH_VERSION, 6, 0];
+        too_long.extend([0xff; 9]);
+        too_long.push(0x02);
+        assert_eq!(VideoHash::decode_compact(&too_long), Err(InvalidVarint));
+    }
+
+    #[test]
+    fn test_corrupted_input_never_panics() {
+        let mut rng = StdRng::seed_from_u64(2);
+        let encoded = VideoHash::random_hash(&mut rng)
+            .with_src_path("/videos/a.mp4")
+            .encode_compact();
+
+        for _ in 0..2000 {
+            let mut bytes = encoded.clone();
+            for _ in 0..rng.gen_range(1..4) {
+                let idx = rng.gen_range(0..bytes.len());
+                bytes[idx] = rng.gen();
+            }
+            //anything that is still accepted must be a valid hash.
+            if let Ok(read) = VideoHash::decode_compact(&bytes) {
+                assert_eq!(VideoHash::decode_compact(&read.encode_compact()), Ok(read));
+            }
+        }
+    }
+}
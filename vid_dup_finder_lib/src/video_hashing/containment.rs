@@ -0,0 +1,482 @@
+//! Finding which videos of a group were cut from which.
+//!
+//! A clip cut from the start of a longer video can match it, because their first seconds are the
+//! same. [`MatchGroup::analyze_containment`][crate::MatchGroup::analyze_containment] looks at each
+//! pair of videos in a group whose durations are very different, and checks whether a few windows
+//! sampled from all through the shorter video can all be found, in the same order, among the
+//! windows of the longer one. The windows are hashed by a [`FrameSource`], such as a
+//! [`WindowHasher`].
+//!
+//! ```no_run
+//! use vid_dup_finder_lib::*;
+//! # fn search_results() -> Vec<MatchGroup> { vec![] }
+//!
+//! let windows = WindowHasher::default();
+//! for group in search_results() {
+//!     for edge in group.analyze_containment(&windows).edges() {
+//!         println!(
+//!             "{:?} was cut from {:?} ({:.0}% confident)",
+//!             edge.shorter(),
+//!             edge.longer(),
+//!             edge.confidence() * 100.0
+//!         );
+//!     }
+//! }
+//! ```
+use std::{cmp::Ordering, path::Path, sync::Arc};
+
+use itertools::Itertools;
+
+use super::clip_search::best_window;
+use crate::{Error, Tolerance, VideoHash};
+
+/// Where [`MatchGroup::analyze_containment`][crate::MatchGroup::analyze_containment] gets the
+/// hashes of the windows of each video from.
+///
+/// Closures which take a path and return its windows are frame sources too, so windows can be
+/// looked up from somewhere else, such as a cache.
+pub trait FrameSource {
+    /// The hashes of the windows of the video at `path`, as returned by `hash_windows`. Every
+    /// window records the duration of the whole video.
+    ///
+    /// # Errors
+    /// Any error from hashing the windows. Videos whose windows cannot be hashed are left out of
+    /// the analysis.
+    fn window_hashes(&self, path: &Path) -> Result<Vec<VideoHash>, Error>;
+}
+
+impl<F> FrameSource for F
+where
+    F: Fn(&Path) -> Result<Vec<VideoHash>, Error>,
+{
+    fn window_hashes(&self, path: &Path) -> Result<Vec<VideoHash>, Error> {
+        self(path)
+    }
+}
+
+/// A [`FrameSource`] which decodes each video with the default backend, and hashes a window of it
+/// every few seconds.
+#[cfg(any(feature = "ffmpeg_backend", feature = "gstreamer_backend"))]
+#[derive(Debug, Clone, Copy)]
+pub struct WindowHasher {
+    options: crate::CreationOptions,
+    window_stride: f64,
+}
+
+#[cfg(any(feature = "ffmpeg_backend", feature = "gstreamer_backend"))]
+impl WindowHasher {
+    /// Hash windows of [`CreationOptions::duration`][crate::CreationOptions::duration] seconds,
+    /// starting every 2 seconds.
+    #[must_use]
+    pub const fn from_options(options: crate::CreationOptions) -> Self {
+        Self {
+            options,
+            window_stride: 2.0,
+        }
+    }
+
+    /// Start a window every `window_stride` seconds instead. A window of the shorter video can
+    /// start up to half a stride away from the nearest window of the longer one, so the stride
+    /// should be much less than the duration of the windows.
+    #[must_use]
+    pub const fn with_window_stride(mut self, window_stride: f64) -> Self {
+        self.window_stride = window_stride;
+        self
+    }
+}
+
+#[cfg(any(feature = "ffmpeg_backend", feature = "gstreamer_backend"))]
+impl Default for WindowHasher {
+    /// Windows of the default duration, without skipping forward, starting every 2 seconds.
+    fn default() -> Self {
+        Self::from_options(crate::CreationOptions {
+            skip_forward_amount: 0.0,
+            ..crate::CreationOptions::default()
+        })
+    }
+}
+
+#[cfg(any(feature = "ffmpeg_backend", feature = "gstreamer_backend"))]
+impl FrameSource for WindowHasher {
+    fn window_hashes(&self, path: &Path) -> Result<Vec<VideoHash>, Error> {
+        #[cfg(feature = "ffmpeg_backend")]
+        use crate::ffmpeg_builder::VideoHashBuilder;
+        #[cfg(all(feature = "gstreamer_backend", not(feature = "ffmpeg_backend")))]
+        use crate::gstreamer_builder::VideoHashBuilder;
+
+        VideoHashBuilder::from_options(self.options)
+            .hash_windows(path.to_path_buf(), self.window_stride)
+    }
+}
+
+/// Options for [`MatchGroup::analyze_containment`][crate::MatchGroup::analyze_containment].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ContainmentOpts {
+    /// How many times as long as the shorter video of a pair the longer one must be for the pair
+    /// to be analyzed. Pairs of similar durations are copies of each other rather than cuts.
+    pub min_duration_ratio: f64,
+
+    /// The number of windows sampled from the shorter video, spaced evenly from its first window
+    /// to its last. Every one of them must be found in the longer video.
+    pub samples: usize,
+
+    /// How far apart a sampled window and a window of the longer video may be to be the same.
+    pub tolerance: Tolerance,
+}
+
+impl Default for ContainmentOpts {
+    /// Pairs where one video is at least 1.5 times as long as the other, with 4 windows sampled
+    /// and the default tolerance.
+    fn default() -> Self {
+        Self {
+            min_duration_ratio: 1.5,
+            samples: 4,
+            tolerance: Tolerance::DEFAULT,
+        }
+    }
+}
+
+/// A video which was found inside a longer one by
+/// [`MatchGroup::analyze_containment`][crate::MatchGroup::analyze_containment].
+#[derive(Debug, Clone)]
+pub struct Contains {
+    longer: Arc<Path>,
+    shorter: Arc<Path>,
+    confidence: f64,
+}
+
+impl Contains {
+    /// The path of the longer video, which the shorter one was cut from.
+    #[must_use]
+    pub fn longer(&self) -> &Path {
+        &self.longer
+    }
+
+    /// The path of the shorter video.
+    #[must_use]
+    pub fn shorter(&self) -> &Path {
+        &self.shorter
+    }
+
+    /// How closely the sampled windows matched the longer video, from 0.0 (only just within the
+    /// tolerance) to 1.0 (identical).
+    #[must_use]
+    pub const fn confidence(&self) -> f64 {
+        self.confidence
+    }
+
+    fn key(&self) -> (&Path, &Path, u64) {
+        (&self.longer, &self.shorter, self.confidence.to_bits())
+    }
+}
+
+//Edges are compared by the bits of their confidence, so that groups (which record them) can be
+//ordered and hashed.
+impl PartialEq for Contains {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for Contains {}
+
+impl PartialOrd for Contains {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Contains {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.key().cmp(&other.key())
+    }
+}
+
+impl std::hash::Hash for Contains {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.key().hash(state);
+    }
+}
+
+/// Which videos of a group were found inside which, as returned by
+/// [`MatchGroup::analyze_containment`][crate::MatchGroup::analyze_containment].
+#[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ContainmentReport {
+    //sorted by the longer video, then the shorter.
+    edges: Vec<Contains>,
+}
+
+impl ContainmentReport {
+    /// Every video found inside another, sorted by the longer video and then the shorter.
+    #[must_use]
+    pub fn edges(&self) -> &[Contains] {
+        &self.edges
+    }
+
+    /// Whether no video was found inside another.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.edges.is_empty()
+    }
+
+    /// The edge saying that the video at `longer` contains the video at `shorter`, if it does.
+    #[must_use]
+    pub fn contains(
+        &self,
+        longer: impl AsRef<Path>,
+        shorter: impl AsRef<Path>,
+    ) -> Option<&Contains> {
+        let (longer, shorter) = (longer.as_ref(), shorter.as_ref());
+        self.edges
+            .iter()
+            .find(|edge| edge.longer() == longer && edge.shorter() == shorter)
+    }
+
+    /// The longer videos that the video at `path` was found inside. These are the files to keep
+    /// if only one is kept.
+    pub fn contained_in(&self, path: impl AsRef<Path>) -> impl Iterator<Item = &Path> {
+        let path = path.as_ref().to_path_buf();
+        self.edges
+            .iter()
+            .filter(move |edge| edge.shorter() == path)
+            .map(Contains::longer)
+    }
+
+    /// The shorter videos that were found inside the video at `path`.
+    pub fn contents_of(&self, path: impl AsRef<Path>) -> impl Iterator<Item = &Path> {
+        let path = path.as_ref().to_path_buf();
+        self.edges
+            .iter()
+            .filter(move |edge| edge.longer() == path)
+            .map(Contains::shorter)
+    }
+
+    pub(crate) fn retain(&mut self, keep: impl Fn(&Arc<Path>) -> bool) {
+        self.edges
+            .retain(|edge| keep(&edge.longer) && keep(&edge.shorter));
+    }
+
+    // Roughly how many bytes of memory the edges use, counting each path with path_bytes.
+    pub(crate) fn approx_memory_usage(
+        &self,
+        mut path_bytes: impl FnMut(&Arc<Path>) -> usize,
+    ) -> usize {
+        self.edges
+            .iter()
+            .map(|edge| path_bytes(&edge.longer) + path_bytes(&edge.shorter) + size_of::<f64>())
+            .sum()
+    }
+}
+
+// Analyze each pair of the given videos whose durations are different enough. The windows of each
+// video are only hashed once, and videos whose windows cannot be hashed are left out.
+pub(crate) fn analyze_containment<'a>(
+    paths: impl IntoIterator<Item = &'a Arc<Path>>,
+    frame_source: &(impl FrameSource + ?Sized),
+    opts: &ContainmentOpts,
+) -> ContainmentReport {
+    let windowed = paths
+        .into_iter()
+        .filter_map(|path| {
+            let mut windows = frame_source.window_hashes(path).ok()?;
+            windows.retain(|window| window.window_start().is_some());
+            windows.sort_by_key(VideoHash::window_start);
+            let duration = windows.first()?.duration();
+            Some((path, duration, windows))
+        })
+        .collect::<Vec<_>>();
+
+    let mut edges = windowed
+        .iter()
+        .tuple_combinations()
+        .filter_map(|(a, b)| {
+            let ((longer, longer_duration, longer_windows), (shorter, shorter_duration, windows)) =
+                if a.1 >= b.1 { (a, b) } else { (b, a) };
+            if f64::from(*longer_duration)
+                < f64::from(*shorter_duration).max(1.0) * opts.min_duration_ratio
+            {
+                return None;
+            }
+            let confidence = containment_confidence(windows, longer_windows, opts)?;
+            Some(Contains {
+                longer: Arc::clone(longer),
+                shorter: Arc::clone(shorter),
+                confidence,
+            })
+        })
+        .collect::<Vec<_>>();
+    edges.sort();
+    ContainmentReport { edges }
+}
+
+// How confidently the windows of the shorter video were found in the same order among the windows
+// of the longer video, or None if any sampled window was not found, or the matches were out of order.
+fn containment_confidence(
+    shorter: &[VideoHash],
+    longer: &[VideoHash],
+    opts: &ContainmentOpts,
+) -> Option<f64> {
+    let tolerance = opts.tolerance.value();
+    let matches = sample_evenly(shorter, opts.samples)
+        .map(|window| best_window(window, longer, tolerance))
+        .collect::<Option<Vec<_>>>()?;
+    if matches.is_empty() || !matches.iter().is_sorted_by_key(|m| m.offset()) {
+        return None;
+    }
+
+    let mean_distance = matches.iter().map(|m| m.distance()).sum::<f64>() / matches.len() as f64;
+    Some(if tolerance > 0.0 {
+        (1.0 - mean_distance / tolerance).clamp(0.0, 1.0)
+    } else {
+        1.0
+    })
+}
+
+// At most n of the windows, spaced evenly from the first to the last.
+fn sample_evenly(windows: &[VideoHash], n: usize) -> impl Iterator<Item = &VideoHash> {
+    let n = n.min(windows.len());
+    (0..n).map(move |i| {
+        let idx = if n > 1 {
+            i * (windows.len() - 1) / (n - 1)
+        } else {
+            0
+        };
+        &windows[idx]
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+    use crate::MatchGroup;
+
+    // A video made of distinct windows, starting every 2 seconds.
+    fn windows(name: &str, num_windows: u64, rng: &mut StdRng) -> Vec<VideoHash> {
+        (0..num_windows)
+            .map(|i| {
+                VideoHash::random_hash(rng)
+                    .with_src_path(name)
+                    .with_duration(num_windows as u32 * 2 + 8)
+                    .with_raw_window_start(Duration::from_secs(i * 2))
+            })
+            .collect()
+    }
+
+    // The windows of a cut from the given windows, as if it had been hashed on its own. The bits
+    // of each window are changed slightly, as by re-encoding.
+    fn cut(name: &str, windows: &[VideoHash]) -> Vec<VideoHash> {
+        windows
+            .iter()
+            .enumerate()
+            .map(|(i, window)| {
+                window
+                    .with_src_path(name)
+                    .with_flipped_bits(0..3)
+                    .with_duration(windows.len() as u32 * 2 + 8)
+                    .with_raw_window_start(Duration::from_secs(i as u64 * 2))
+            })
+            .collect()
+    }
+
+    // A frame source which looks the windows up by path.
+    fn source(videos: Vec<Vec<VideoHash>>) -> impl Fn(&Path) -> Result<Vec<VideoHash>, Error> {
+        move |path: &Path| {
+            videos
+                .iter()
+                .find(|windows| windows[0].src_path() == path)
+                .cloned()
+                .ok_or(Error::NotVideo)
+        }
+    }
+
+    #[test]
+    fn test_cut_is_found_inside_source() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let full = windows("full.mp4", 300, &mut rng);
+        let clip = cut("clip.mp4", &full[100..130]);
+        let copy = cut("copy.mp4", &full);
+        let frame_source = source(vec![full, clip, copy]);
+
+        let group = MatchGroup::new(["clip.mp4", "copy.mp4", "full.mp4"].map(Path::new))
+            .expect("enough videos");
+        let report = group.analyze_containment(&frame_source);
+
+        //the copy is as long as the full video, so it is not analyzed against it.
+        let edges = report
+            .edges()
+            .iter()
+            .map(|edge| (edge.longer(), edge.shorter()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            edges,
+            [
+                (Path::new("copy.mp4"), Path::new("clip.mp4")),
+                (Path::new("full.mp4"), Path::new("clip.mp4"))
+            ]
+        );
+        let edge = report
+            .contains("full.mp4", "clip.mp4")
+            .expect("the clip is inside the full video");
+        assert!((0.5..1.0).contains(&edge.confidence()), "{edge:?}");
+        assert!(report.contains("clip.mp4", "full.mp4").is_none());
+
+        assert_eq!(
+            report.contained_in("clip.mp4").collect::<Vec<_>>(),
+            [Path::new("copy.mp4"), Path::new("full.mp4")]
+        );
+        assert_eq!(
+            report.contents_of("full.mp4").collect::<Vec<_>>(),
+            [Path::new("clip.mp4")]
+        );
+        assert_eq!(report.contained_in("full.mp4").count(), 0);
+
+        //a group only keeps the edges between its own videos.
+        let pair = MatchGroup::new(["clip.mp4", "full.mp4"].map(Path::new))
+            .expect("enough videos")
+            .with_containment(report.clone());
+        assert_eq!(pair.containment().edges(), &report.edges()[1..]);
+        assert!(group.containment().is_empty());
+    }
+
+    #[test]
+    fn test_unrelated_and_reordered_videos_are_not_contained() {
+        let mut rng = StdRng::seed_from_u64(2);
+        let full = windows("full.mp4", 100, &mut rng);
+        //the same scenes as a part of the full video, but shown backwards.
+        let mut reversed = cut("reversed.mp4", &full[20..40]);
+        reversed.reverse();
+        for (i, window) in reversed.iter_mut().enumerate() {
+            *window = window.with_raw_window_start(Duration::from_secs(i as u64 * 2));
+        }
+        let unrelated = windows("unrelated.mp4", 20, &mut rng);
+        let frame_source = source(vec![full, reversed, unrelated]);
+
+        let group = MatchGroup::new(["full.mp4", "reversed.mp4", "unrelated.mp4"].map(Path::new))
+            .expect("enough videos");
+        assert!(group.analyze_containment(&frame_source).is_empty());
+
+        //videos whose windows cannot be hashed are left out.
+        let group =
+            MatchGroup::new(["full.mp4", "missing.mp4"].map(Path::new)).expect("enough videos");
+        assert!(group.analyze_containment(&frame_source).is_empty());
+    }
+
+    #[test]
+    fn test_sample_evenly() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let full = windows("full.mp4", 10, &mut rng);
+        let starts = |n| {
+            sample_evenly(&full, n)
+                .map(|window| window.window_start().expect("a window").as_secs())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(starts(4), [0, 6, 12, 18]);
+        assert_eq!(starts(1), [0]);
+        assert_eq!(starts(20).len(), 10);
+        assert!(starts(0).is_empty());
+    }
+}
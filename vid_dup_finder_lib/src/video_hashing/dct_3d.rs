@@ -1,5 +1,4 @@
-use crate::definitions::DCT_SIZE;
-use crate::definitions::HASH_SIZE;
+use crate::definitions::{HashSize, DCT_SIZE};
 
 use image::GenericImageView;
 use ndarray::{prelude::*, s};
@@ -14,7 +13,10 @@ use super::raw_dct_ops::dct_3d;
 pub struct Dct3d(Array3<f64>);
 
 const DCT_PATT: [usize; 3] = [DCT_SIZE as usize, DCT_SIZE as usize, DCT_SIZE as usize];
-const HASH_PATT: [usize; 3] = [HASH_SIZE as usize, HASH_SIZE as usize, HASH_SIZE as usize];
+
+//A bin is reliable if its magnitude is at least this fraction of the magnitude of the whole DCT.
+//The bins of a flat or nearly flat video are far below this, so their signs are decided by noise.
+const RELIABLE_BIN_FRACTION: f64 = 1e-3;
 
 impl Dct3d {
     pub fn from_images<I, V>(src_frames: I) -> Option<Self>
@@ -57,17 +59,29 @@ impl Dct3d {
         }
     }
 
-    pub fn hash_bits(&self) -> impl Iterator<Item = bool> + '_ {
+    pub fn hash_bits(&self, hash_size: HashSize) -> impl Iterator<Item = bool> + '_ {
         //keep the lowest frequency bins.
 
-        Self::hash_bins(&self.0)
+        Self::hash_bins(&self.0, hash_size)
             .into_iter()
             .copied()
             .map(|x| x > 0.0)
     }
 
-    fn hash_bins(m: &Array3<f64>) -> ArrayView3<f64> {
-        m.slice(s![..HASH_PATT[0], ..HASH_PATT[1], ..HASH_PATT[2]])
+    /// Whether each bit of [`Dct3d::hash_bits`] comes from a bin that is large enough for its sign
+    /// to survive re-encoding.
+    pub fn reliable_bits(&self, hash_size: HashSize) -> impl Iterator<Item = bool> + '_ {
+        let magnitude = self.0.iter().map(|x| x * x).sum::<f64>().sqrt();
+        let threshold = magnitude * RELIABLE_BIN_FRACTION;
+
+        Self::hash_bins(&self.0, hash_size)
+            .into_iter()
+            .map(move |x| x.abs() > threshold)
+    }
+
+    fn hash_bins(m: &Array3<f64>, hash_size: HashSize) -> ArrayView3<'_, f64> {
+        let side = hash_size.side() as usize;
+        m.slice(s![..side, ..side, ..side])
     }
 }
 
@@ -0,0 +1,332 @@
+use std::{
+    borrow::Cow,
+    io::{BufWriter, Write},
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+};
+
+use thiserror::Error;
+
+use super::search_algorithm::{durations_are_comparable, incompatibility};
+use crate::VideoHash;
+
+/// The default for [`DistanceMatrixOptions::max_elements`], about 200MB of distances.
+pub const DEFAULT_MAX_MATRIX_ELEMENTS: usize = 25_000_000;
+
+/// Options controlling how a [`DistanceMatrix`] is built, for use with [`distance_matrix_with_opts`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DistanceMatrixOptions {
+    /// If true, pairs of videos whose durations are too different for a search to ever compare them
+    /// are not compared here either, and their distance is recorded as NaN.
+    pub duration_gate: bool,
+
+    /// The largest number of distances (one per pair of hashes) that will be calculated.
+    pub max_elements: usize,
+
+    /// If true, [`DistanceMatrixOptions::max_elements`] is ignored.
+    pub force: bool,
+}
+
+impl Default for DistanceMatrixOptions {
+    fn default() -> Self {
+        Self {
+            duration_gate: false,
+            max_elements: DEFAULT_MAX_MATRIX_ELEMENTS,
+            force: false,
+        }
+    }
+}
+
+/// The error returned when a [`DistanceMatrix`] would have more elements than
+/// [`DistanceMatrixOptions::max_elements`].
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+#[error("A distance matrix with {elements} elements is larger than the maximum of {max_elements}")]
+pub struct MatrixTooLarge {
+    pub elements: usize,
+    pub max_elements: usize,
+}
+
+/// The normalized distances between every pair of a set of hashes, as returned by
+/// [`distance_matrix`].
+///
+/// Distances are in the range 0..=1. Pairs of hashes that cannot be compared (because they have
+/// different [`HashSize`][crate::HashSize]s, [`Normalization`][crate::Normalization]s or
+/// [`Coverage`][crate::Coverage]s, or were skipped by [`DistanceMatrixOptions::duration_gate`])
+/// have a distance of NaN.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DistanceMatrix {
+    paths: Vec<PathBuf>,
+    //The distances are symmetric, so only those above the diagonal are stored, row by row.
+    upper: Vec<f64>,
+}
+
+impl DistanceMatrix {
+    /// The number of hashes in the matrix.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.paths.len()
+    }
+
+    /// The paths of the hashes, in the order they were given.
+    #[must_use]
+    pub fn paths(&self) -> &[PathBuf] {
+        &self.paths
+    }
+
+    /// The distance between the `i`th and `j`th hashes.
+    ///
+    /// # Panics
+    /// Panics if either index is out of range.
+    #[must_use]
+    pub fn get(&self, i: usize, j: usize) -> f64 {
+        assert!(
+            i < self.len() && j < self.len(),
+            "index ({i}, {j}) out of range for a distance matrix of {} hashes",
+            self.len()
+        );
+
+        match i.cmp(&j) {
+            std::cmp::Ordering::Equal => 0.0,
+            std::cmp::Ordering::Less => self.upper[self.upper_index(i, j)],
+            std::cmp::Ordering::Greater => self.upper[self.upper_index(j, i)],
+        }
+    }
+
+    /// Every pair `(i, j, distance)` with `i < j`, including those whose distance is NaN.
+    pub fn pairs(&self) -> impl Iterator<Item = (usize, usize, f64)> + '_ {
+        let n = self.len();
+        (0..n)
+            .flat_map(move |i| (i + 1..n).map(move |j| (i, j)))
+            .zip(self.upper.iter())
+            .map(|((i, j), &distance)| (i, j, distance))
+    }
+
+    /// As [`DistanceMatrix::pairs`], but only the pairs whose distance is at most `threshold`.
+    pub fn pairs_below(&self, threshold: f64) -> impl Iterator<Item = (usize, usize, f64)> + '_ {
+        self.pairs()
+            .filter(move |(_, _, distance)| *distance <= threshold)
+    }
+
+    /// As [`DistanceMatrix::pairs`], but only the pairs whose distance is greater than `threshold`.
+    pub fn pairs_above(&self, threshold: f64) -> impl Iterator<Item = (usize, usize, f64)> + '_ {
+        self.pairs()
+            .filter(move |(_, _, distance)| *distance > threshold)
+    }
+
+    /// Write the whole matrix as CSV. The first row and column are the paths of the hashes.
+    ///
+    /// # Errors
+    /// Returns any error from writing to `w`.
+    pub fn write_csv(&self, w: impl Write) -> std::io::Result<()> {
+        let mut w = BufWriter::new(w);
+
+        for path in &self.paths {
+            write!(w, ",{}", csv_field(path))?;
+        }
+        writeln!(w)?;
+
+        for (i, path) in self.paths.iter().enumerate() {
+            write!(w, "{}", csv_field(path))?;
+            for j in 0..self.len() {
+                write!(w, ",{}", self.get(i, j))?;
+            }
+            writeln!(w)?;
+        }
+
+        w.flush()
+    }
+
+    fn upper_index(&self, i: usize, j: usize) -> usize {
+        //the rows before i hold (n - 1) + (n - 2) + ... + (n - i) elements.
+        let n = self.len();
+        i * n - i * (i + 1) / 2 + (j - i - 1)
+    }
+}
+
+fn csv_field(path: &Path) -> Cow<'_, str> {
+    let field = path.to_string_lossy();
+    if field.contains([',', '"', '\n', '\r']) {
+        Cow::Owned(format!("\"{}\"", field.replace('"', "\"\"")))
+    } else {
+        field
+    }
+}
+
+/// Calculate the distance between every pair of the given hashes, with the default
+/// [`DistanceMatrixOptions`].
+///
+/// # Errors
+/// Returns `Err` if there are so many hashes that the matrix would have more than
+/// [`DEFAULT_MAX_MATRIX_ELEMENTS`] elements.
+pub fn distance_matrix(hashes: &[VideoHash]) -> Result<DistanceMatrix, MatrixTooLarge> {
+    distance_matrix_with_opts(hashes, &DistanceMatrixOptions::default())
+}
+
+/// Calculate the distance between every pair of the given hashes. The distances are calculated in
+/// parallel.
+///
+/// # Errors
+/// Returns `Err` if the matrix would have more than [`DistanceMatrixOptions::max_elements`]
+/// elements, unless [`DistanceMatrixOptions::force`] is set.
+pub fn distance_matrix_with_opts(
+    hashes: &[VideoHash],
+    opts: &DistanceMatrixOptions,
+) -> Result<DistanceMatrix, MatrixTooLarge> {
+    let n = hashes.len();
+    let elements = n.saturating_mul(n.saturating_sub(1)) / 2;
+    if elements > opts.max_elements && !opts.force {
+        return Err(MatrixTooLarge {
+            elements,
+            max_elements: opts.max_elements,
+        });
+    }
+
+    let mut upper = vec![f64::NAN; elements];
+
+    //Split the matrix into its rows, and deal them out to threads in turn. Rows get shorter as
+    //they go down, so this gives each thread about the same amount of work.
+    let mut rows = Vec::with_capacity(n);
+    let mut rest = upper.as_mut_slice();
+    for i in 0..n {
+        let (row, tail) = std::mem::take(&mut rest).split_at_mut(n - 1 - i);
+        rows.push((i, row));
+        rest = tail;
+    }
+
+    let num_threads = std::thread::available_parallelism()
+        .map_or(1, NonZeroUsize::get)
+        .min(n)
+        .max(1);
+    let mut work = (0..num_threads).map(|_| vec![]).collect::<Vec<_>>();
+    for (k, row) in rows.into_iter().enumerate() {
+        work[k % num_threads].push(row);
+    }
+
+    std::thread::scope(|s| {
+        for rows in work {
+            s.spawn(move || {
+                for (i, row) in rows {
+                    for (distance, h2) in row.iter_mut().zip(&hashes[i + 1..]) {
+                        *distance = pair_distance(&hashes[i], h2, opts.duration_gate);
+                    }
+                }
+            });
+        }
+    });
+
+    Ok(DistanceMatrix {
+        paths: hashes.iter().map(|h| h.src_path().to_path_buf()).collect(),
+        upper,
+    })
+}
+
+fn pair_distance(h1: &VideoHash, h2: &VideoHash, duration_gate: bool) -> f64 {
+    if incompatibility(h1, h2).is_some()
+        || (duration_gate && !durations_are_comparable(h1.duration(), h2.duration()))
+    {
+        return f64::NAN;
+    }
+
+    f64::from(h1.same_size_distance(h2)) / f64::from(h1.hash_size().bits())
+}
+
+#[cfg(test)]
+mod test {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+    use crate::HashSize;
+
+    #[test]
+    fn test_matrix_matches_pairwise_distances() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let hashes = (0..23)
+            .map(|i| VideoHash::random_hash(&mut rng).with_src_path(format!("{i}")))
+            .collect::<Vec<_>>();
+
+        let matrix = distance_matrix(&hashes).expect("small matrix");
+        assert_eq!(matrix.len(), hashes.len());
+        assert_eq!(matrix.pairs().count(), 23 * 22 / 2);
+
+        for (i, h1) in hashes.iter().enumerate() {
+            assert_eq!(matrix.paths()[i], h1.src_path());
+            for (j, h2) in hashes.iter().enumerate() {
+                let bits = f64::from(h1.hash_size().bits());
+                let exp = f64::from(h1.hamming_distance(h2).expect("same size")) / bits;
+                assert_eq!(matrix.get(i, j), exp);
+            }
+        }
+
+        let threshold = 0.5;
+        let num_below = matrix.pairs_below(threshold).count();
+        let num_above = matrix.pairs_above(threshold).count();
+        assert_eq!(num_below + num_above, matrix.pairs().count());
+        assert!(matrix
+            .pairs_below(threshold)
+            .all(|(i, j, d)| i < j && d <= threshold));
+
+        let too_large = DistanceMatrixOptions {
+            max_elements: 100,
+            ..DistanceMatrixOptions::default()
+        };
+        assert_eq!(
+            distance_matrix_with_opts(&hashes, &too_large),
+            Err(MatrixTooLarge {
+                elements: 253,
+                max_elements: 100
+            })
+        );
+        let forced = DistanceMatrixOptions {
+            force: true,
+            ..too_large
+        };
+        assert_eq!(distance_matrix_with_opts(&hashes, &forced), Ok(matrix));
+    }
+
+    #[test]
+    fn test_incomparable_pairs_are_nan() {
+        let mut rng = StdRng::seed_from_u64(2);
+        let short = VideoHash::random_hash(&mut rng).with_duration(100);
+        let similar = short.with_duration(105).with_flipped_bits(0..3);
+        let long = short.with_duration(200);
+        let other_size = VideoHash::random_hash_with_size(&mut rng, HashSize::Five);
+        let hashes = [short, similar, long, other_size];
+
+        let matrix = distance_matrix(&hashes).expect("small matrix");
+        assert!(matrix.get(0, 1) > 0.0);
+        assert_eq!(matrix.get(0, 2), 0.0);
+        assert!(matrix.get(3, 0).is_nan());
+
+        let gated = DistanceMatrixOptions {
+            duration_gate: true,
+            ..DistanceMatrixOptions::default()
+        };
+        let matrix = distance_matrix_with_opts(&hashes, &gated).expect("small matrix");
+        assert_eq!(matrix.get(1, 0), matrix.get(0, 1));
+        assert!(matrix.get(0, 2).is_nan());
+
+        //NaN is neither above nor below any threshold.
+        let num_compared = matrix.pairs_below(0.5).count() + matrix.pairs_above(0.5).count();
+        assert_eq!(num_compared, 1);
+    }
+
+    #[test]
+    fn test_write_csv() {
+        let hashes = [
+            VideoHash::empty_hash("a.mp4"),
+            VideoHash::full_hash("b, \"the sequel\".mp4"),
+        ];
+        let mut csv = vec![];
+        distance_matrix(&hashes)
+            .expect("small matrix")
+            .write_csv(&mut csv)
+            .expect("can write to a vec");
+
+        assert_eq!(
+            String::from_utf8(csv).expect("paths are UTF-8"),
+            ",a.mp4,\"b, \"\"the sequel\"\".mp4\"\n\
+             a.mp4,0,1\n\
+             \"b, \"\"the sequel\"\".mp4\",1,0\n"
+        );
+    }
+}
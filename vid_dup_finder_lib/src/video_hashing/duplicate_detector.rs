@@ -0,0 +1,262 @@
+//! Checking new videos against a collection of hashes, one at a time.
+//!
+//! A [`DuplicateDetector`] is for when videos arrive one by one (such as uploads, or files
+//! appearing in a watched folder) and each one needs to be checked against everything seen so
+//! far. The existing hashes are sorted by duration once, so each check only compares the new hash
+//! with the videos whose durations are comparable with it, rather than searching the whole
+//! collection again.
+//!
+//! ```
+//! use vid_dup_finder_lib::*;
+//! # fn load_archive() -> Vec<VideoHash> { vec![] }
+//! # fn hash_upload() -> VideoHash { VideoHash::empty_hash("upload.mp4") }
+//!
+//! let mut detector = DuplicateDetector::new(load_archive(), SearchOptions::default());
+//!
+//! let upload = hash_upload();
+//! let hits = detector.check(&upload);
+//! if hits.is_empty() {
+//!     detector.add(upload);
+//! } else {
+//!     println!("rejected, already have {:?}", hits[0].path());
+//! }
+//! ```
+use std::path::{Path, PathBuf};
+
+use super::search_algorithm::{search_order, MatchThreshold};
+use crate::{SearchOptions, VideoHash};
+
+/// An existing video which matched the hash passed to [`DuplicateDetector::check`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct DuplicateHit {
+    path: PathBuf,
+    distance: f64,
+}
+
+impl DuplicateHit {
+    /// The path of the existing video.
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The normalized distance between the two hashes, in the range 0..=1.
+    ///
+    /// Pairs can match on their reliable bits or once their frames are lined up (see
+    /// [`SearchOptions::ignore_unreliable_bits`] and [`SearchOptions::temporal_slack`]), so this
+    /// is not always within the tolerance.
+    #[must_use]
+    pub const fn distance(&self) -> f64 {
+        self.distance
+    }
+}
+
+/// An index of existing hashes which new hashes can be checked against, one at a time.
+///
+/// A new hash matches an existing one exactly when a search of both with the same options would
+/// find them to match: Their durations must be comparable according to the
+/// [`SearchOptions::duration_gate`], they must be within the tolerance, and the pair must not be
+/// suppressed by the falsepos filter or a [`crate::ConstraintRule::NeverGroupWithin`]. Options
+/// that only affect how matches are grouped and reported are ignored.
+///
+/// Checking only needs `&self`, and the detector is `Send` and `Sync`, so many threads can check
+/// hashes against a shared detector at once. Adding a hash needs `&mut self`, so a detector that
+/// is added to while other threads check it must be behind a lock such as an
+/// [`RwLock`][std::sync::RwLock].
+#[derive(Debug, Clone)]
+pub struct DuplicateDetector {
+    //in search order.
+    hashes: Vec<VideoHash>,
+    threshold: MatchThreshold,
+    opts: SearchOptions,
+}
+
+impl DuplicateDetector {
+    /// An index of the given hashes, to be checked against with the given options.
+    #[must_use]
+    pub fn new(mut existing: Vec<VideoHash>, opts: SearchOptions) -> Self {
+        existing.sort_by(search_order);
+        Self {
+            hashes: existing,
+            threshold: MatchThreshold::new(&opts),
+            opts,
+        }
+    }
+
+    /// The number of hashes in the index.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.hashes.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.hashes.is_empty()
+    }
+
+    /// The existing videos which `new_hash` matches, closest first (then by path). Existing
+    /// hashes with the same path as `new_hash` are never reported.
+    ///
+    /// Only the existing hashes with comparable durations are compared, so this takes time in
+    /// proportion to how many of them there are, not to the size of the whole index.
+    #[must_use]
+    pub fn check(&self, new_hash: &VideoHash) -> Vec<DuplicateHit> {
+        let mut hits = self
+            .comparable(new_hash.duration())
+            .iter()
+            .filter(|existing| {
+                existing.src_path() != new_hash.src_path()
+                    && self.threshold.is_match(new_hash, existing)
+                    && !self
+                        .opts
+                        .is_suppressed(new_hash.src_path(), existing.src_path())
+            })
+            .map(|existing| DuplicateHit {
+                path: existing.src_path().to_path_buf(),
+                distance: f64::from(new_hash.same_size_distance(existing))
+                    / f64::from(new_hash.hash_size().bits()),
+            })
+            .collect::<Vec<_>>();
+
+        hits.sort_by(|h1, h2| {
+            h1.distance
+                .total_cmp(&h2.distance)
+                .then(h1.path.cmp(&h2.path))
+        });
+        hits
+    }
+
+    /// Add a hash to the index, so that later checks can match it.
+    ///
+    /// This does not check the hash against the index. Call [`DuplicateDetector::check`] first if
+    /// that is needed.
+    pub fn add(&mut self, hash: VideoHash) {
+        let idx = self
+            .hashes
+            .partition_point(|existing| search_order(existing, &hash).is_le());
+        self.hashes.insert(idx, hash);
+    }
+
+    // The existing hashes whose durations are comparable with the given duration, whether they are
+    // longer or shorter.
+    fn comparable(&self, duration: u32) -> &[VideoHash] {
+        let gate = self.opts.candidate_gate();
+        let lhs = self
+            .hashes
+            .partition_point(|existing| gate.max_comparable(existing.duration()) < duration);
+        let rhs = self
+            .hashes
+            .partition_point(|existing| existing.duration() <= gate.max_comparable(duration));
+
+        &self.hashes[lhs..rhs.max(lhs)]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+    use crate::{FalseposFilter, Tolerance};
+
+    fn paths(hits: &[DuplicateHit]) -> Vec<&str> {
+        hits.iter()
+            .map(|hit| hit.path().to_str().expect("utf8 path"))
+            .collect()
+    }
+
+    // A random hash at each duration, with one near copy of the one at 60s.
+    fn archive() -> (VideoHash, Vec<VideoHash>) {
+        let mut rng = StdRng::seed_from_u64(7);
+        let original = VideoHash::random_hash(&mut rng).with_duration(60);
+        let mut archive = [30, 60, 61, 1000]
+            .into_iter()
+            .map(|duration| {
+                VideoHash::random_hash(&mut rng)
+                    .with_duration(duration)
+                    .with_src_path(format!("other_{duration}"))
+            })
+            .collect::<Vec<_>>();
+        archive.push(original.with_src_path("original"));
+        archive.push(original.with_flipped_bits(10..16).with_src_path("copy"));
+        (original, archive)
+    }
+
+    #[test]
+    fn test_matches_across_duration_buckets() {
+        let (original, archive) = archive();
+        let detector = DuplicateDetector::new(archive, SearchOptions::default());
+        assert_eq!(detector.len(), 6);
+
+        //the archived copies are 60s long, which is comparable with all of these.
+        for duration in [55, 60, 66] {
+            let upload = original
+                .with_flipped_bits(0..2)
+                .with_duration(duration)
+                .with_src_path("upload");
+            let hits = detector.check(&upload);
+            assert_eq!(paths(&hits), ["original", "copy"], "at {duration}s");
+            assert!(hits[0].distance() < hits[1].distance());
+        }
+
+        //but neither of these is.
+        for duration in [53, 67] {
+            let upload = original.with_duration(duration).with_src_path("upload");
+            assert!(detector.check(&upload).is_empty(), "at {duration}s");
+        }
+
+        //nor is the same file.
+        let hits = detector.check(&original.with_src_path("original"));
+        assert_eq!(paths(&hits), ["copy"]);
+    }
+
+    #[test]
+    fn test_added_hashes_are_checked() {
+        let mut rng = StdRng::seed_from_u64(8);
+        let (original, archive) = archive();
+        let mut detector = DuplicateDetector::new(archive, SearchOptions::default());
+
+        let upload = VideoHash::random_hash(&mut rng)
+            .with_duration(63)
+            .with_src_path("upload");
+        let reupload = upload.with_flipped_bits(10..13).with_src_path("reupload");
+        assert!(detector.check(&upload).is_empty());
+        assert!(detector.check(&reupload).is_empty());
+
+        detector.add(upload);
+        assert_eq!(paths(&detector.check(&reupload)), ["upload"]);
+        assert!(detector
+            .hashes
+            .windows(2)
+            .all(|w| search_order(&w[0], &w[1]).is_le()));
+
+        //existing matches are still found.
+        let copy = original.with_src_path("another_copy");
+        assert_eq!(paths(&detector.check(&copy)), ["original", "copy"]);
+    }
+
+    #[test]
+    fn test_search_options_are_respected() {
+        let (original, archive) = archive();
+        let upload = original.with_flipped_bits(0..2).with_src_path("upload");
+
+        let strict = SearchOptions::new(Tolerance::new(0.0).expect("valid tolerance"));
+        let detector = DuplicateDetector::new(archive.clone(), strict);
+        assert!(detector.check(&upload).is_empty());
+
+        let mut falsepos_filter = FalseposFilter::new();
+        falsepos_filter.insert("upload", "copy");
+        let filtered = SearchOptions {
+            falsepos_filter,
+            ..SearchOptions::default()
+        };
+        let detector = DuplicateDetector::new(archive, filtered);
+        assert_eq!(paths(&detector.check(&upload)), ["original"]);
+    }
+
+    #[test]
+    fn test_detector_is_send_and_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<DuplicateDetector>();
+    }
+}
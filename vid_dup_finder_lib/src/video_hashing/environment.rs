@@ -0,0 +1,201 @@
+//! Recording which decoders created a hash.
+//!
+//! Different versions of a decoder can decode the same video to slightly different frames, so a
+//! hash of a video is not always identical to a hash of the same video created on another machine,
+//! or before the decoders were upgraded. [`environment_info`] describes the decoders in use, and
+//! each hash decoded from a video records the [`EnvironmentDigest`] of the decoders that created it
+//! (see [`VideoHash::environment`][crate::VideoHash::environment]), so that a cache of hashes can
+//! tell when they were created by a different set of decoders.
+use std::{collections::BTreeMap, fmt, num::ParseIntError, str::FromStr};
+
+use ffmpeg_gst_wrapper::FrameReadCfgTrait;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// The decoders that create hashes, and where they run.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EnvironmentInfo {
+    /// The backend that decodes videos, "gstreamer" or "ffmpeg".
+    pub backend: String,
+    /// The version of each component of the backend, by name. For gstreamer these are gstreamer
+    /// itself and each plugin that decodes or converts frames, and for ffmpeg they are ffmpeg and
+    /// ffprobe.
+    pub versions: BTreeMap<String, String>,
+    /// The operating system, as in [`std::env::consts::OS`].
+    pub os: String,
+    /// The version of this crate.
+    pub crate_version: String,
+}
+
+/// A compact digest of the parts of an [`EnvironmentInfo`] that decide how videos are decoded,
+/// from [`EnvironmentInfo::digest`]. Displayed (and parsed) as 16 hexadecimal digits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct EnvironmentDigest(u64);
+
+impl EnvironmentInfo {
+    /// The environment of a backend with the given components, running in this build of this
+    /// crate on this operating system.
+    pub fn new(
+        backend: impl Into<String>,
+        versions: impl IntoIterator<Item = (impl Into<String>, impl Into<String>)>,
+    ) -> Self {
+        Self {
+            backend: backend.into(),
+            versions: versions
+                .into_iter()
+                .map(|(name, version)| (name.into(), version.into()))
+                .collect(),
+            os: std::env::consts::OS.to_string(),
+            crate_version: env!("CARGO_PKG_VERSION").to_string(),
+        }
+    }
+
+    /// The digest of the backend and the versions of its components. The operating system and the
+    /// version of this crate are left out, as neither changes how a video is decoded (changes to
+    /// how hashes are built from the decoded frames are not a matter of the environment), so the
+    /// digest only changes when the decoders do.
+    #[must_use]
+    pub fn digest(&self) -> EnvironmentDigest {
+        let mut hasher = Sha256::new();
+        let mut field = |val: &str| {
+            hasher.update((val.len() as u64).to_le_bytes());
+            hasher.update(val.as_bytes());
+        };
+        field(&self.backend);
+        for (name, version) in &self.versions {
+            field(name);
+            field(version);
+        }
+
+        let digest = hasher.finalize();
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(&digest[..8]);
+        EnvironmentDigest(u64::from_le_bytes(bytes))
+    }
+
+    // The environment of a backend, or None if it does not report its components.
+    pub(crate) fn of_backend<T: FrameReadCfgTrait>() -> Option<Self> {
+        let components = T::components();
+        (!components.backend.is_empty()).then(|| Self::new(components.backend, components.versions))
+    }
+}
+
+impl fmt::Display for EnvironmentInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let versions = self
+            .versions
+            .iter()
+            .map(|(name, version)| format!("{name} {version}"))
+            .collect::<Vec<_>>();
+        write!(
+            f,
+            "{} ({}) on {}, vid_dup_finder_lib {}",
+            self.backend,
+            versions.join(", "),
+            self.os,
+            self.crate_version
+        )
+    }
+}
+
+impl fmt::Display for EnvironmentDigest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:016x}", self.0)
+    }
+}
+
+impl FromStr for EnvironmentDigest {
+    type Err = ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        u64::from_str_radix(s, 16).map(Self)
+    }
+}
+
+/// The environment that hashes are created in by this build: the backend chosen by its features
+/// (ffmpeg, if both are enabled) and the versions of its components.
+///
+/// # Errors
+/// Returns [`crate::Error::NotInitialized`] if [`crate::init`] has not been called, or the error
+/// it returned if it failed.
+#[cfg(any(feature = "ffmpeg_backend", feature = "gstreamer_backend"))]
+pub fn environment_info() -> Result<EnvironmentInfo, crate::Error> {
+    #[cfg(feature = "ffmpeg_backend")]
+    type Backend = ffmpeg_gst_wrapper::ffmpeg_impl::FrameReaderCfgFfmpeg;
+    #[cfg(all(feature = "gstreamer_backend", not(feature = "ffmpeg_backend")))]
+    type Backend = ffmpeg_gst_wrapper::gst_impl::FrameReaderCfgGst;
+
+    crate::video_hashing::init::ensure_initialized()?;
+    EnvironmentInfo::of_backend::<Backend>().ok_or(crate::Error::NotInitialized)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn gstreamer(plugins: &[(&str, &str)]) -> EnvironmentInfo {
+        EnvironmentInfo::new(
+            "gstreamer",
+            [("gstreamer", "1.22.0.0")]
+                .into_iter()
+                .chain(plugins.iter().copied()),
+        )
+    }
+
+    #[test]
+    fn test_digest_is_stable() {
+        let env = gstreamer(&[("libav", "1.22.0"), ("videoconvertscale", "1.22.0")]);
+
+        //the order that the components were found in does not matter.
+        let reordered = gstreamer(&[("videoconvertscale", "1.22.0"), ("libav", "1.22.0")]);
+        assert_eq!(env.digest(), reordered.digest());
+
+        //nor do the operating system and the version of this crate.
+        let elsewhere = EnvironmentInfo {
+            os: "windows".to_string(),
+            crate_version: "99.0.0".to_string(),
+            ..env.clone()
+        };
+        assert_eq!(env.digest(), elsewhere.digest());
+
+        //digests are the same in every build, so they can be compared between machines.
+        assert_eq!(
+            EnvironmentInfo::new("ffmpeg", [("ffmpeg", "6.1.1"), ("ffprobe", "6.1.1")])
+                .digest()
+                .to_string(),
+            "8530bc2c99d535bf"
+        );
+    }
+
+    #[test]
+    fn test_digest_changes_with_the_decoders() {
+        let env = gstreamer(&[("libav", "1.22.0")]);
+        let digest = env.digest();
+
+        assert_ne!(gstreamer(&[("libav", "1.24.2")]).digest(), digest);
+        assert_ne!(gstreamer(&[]).digest(), digest);
+        assert_ne!(
+            gstreamer(&[("libav", "1.22.0"), ("dav1d", "1.22.0")]).digest(),
+            digest
+        );
+        assert_ne!(
+            EnvironmentInfo {
+                backend: "ffmpeg".to_string(),
+                ..env.clone()
+            }
+            .digest(),
+            digest
+        );
+
+        //the fields are kept apart, so moving text between them changes the digest.
+        assert_ne!(gstreamer(&[("libav1", ".22.0")]).digest(), digest);
+    }
+
+    #[test]
+    fn test_digest_round_trips_as_text() {
+        let digest = gstreamer(&[]).digest();
+        assert_eq!(digest.to_string().len(), 16);
+        assert_eq!(digest.to_string().parse(), Ok(digest));
+        assert!("not hex".parse::<EnvironmentDigest>().is_err());
+    }
+}
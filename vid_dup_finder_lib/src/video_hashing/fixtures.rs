@@ -0,0 +1,131 @@
+//! Synthetic videos whose hashes are checked into the repository, so that any change to the hash
+//! values (for example from a change to the resize or DCT code) is caught by the tests rather than
+//! by users finding that their cached hashes no longer match new ones.
+//!
+//! The frames only depend on the code in this module (the noise comes from a fixed generator
+//! rather than from `rand`), and they are hashed with fixed [`CreationOptions`], so the hashes
+//! only change if hashing itself changes. If they are changed on purpose, the golden hashes in
+//! `tests/fixtures/golden_hashes.jsonl` must be regenerated by running the golden hash tests
+//! with `VDF_UPDATE_GOLDEN_HASHES=1` set, so that the change shows up in review.
+
+use std::{path::PathBuf, time::Duration};
+
+use image::{GrayImage, Luma};
+
+use crate::definitions::DCT_SIZE;
+use crate::{CreationOptions, Cropdetect, HashSize, VideoHash};
+
+/// The dimensions of every fixture frame.
+pub const FIXTURE_DIMENSIONS: (u32, u32) = (64, 48);
+
+/// The duration recorded in every fixture hash.
+pub const FIXTURE_DURATION: Duration = Duration::from_secs(60);
+
+/// A deterministic synthetic video.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Fixture {
+    /// Gradients running away from a diagonal line, which brighten from frame to frame.
+    Gradient,
+    /// A checkerboard of 8 pixel squares which scrolls one pixel to the right every frame.
+    Checkerboard,
+    /// A bright box on a dark background, moving two pixels to the right every frame.
+    MovingBox,
+    /// [`Fixture::MovingBox`], but starting two pixels further down and to the right.
+    ShiftedMovingBox,
+    /// Noise from a fixed seed, different in every frame.
+    Noise,
+}
+
+impl Fixture {
+    /// Every fixture.
+    pub const ALL: [Self; 5] = [
+        Self::Gradient,
+        Self::Checkerboard,
+        Self::MovingBox,
+        Self::ShiftedMovingBox,
+        Self::Noise,
+    ];
+
+    #[must_use]
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Gradient => "gradient",
+            Self::Checkerboard => "checkerboard",
+            Self::MovingBox => "moving_box",
+            Self::ShiftedMovingBox => "shifted_moving_box",
+            Self::Noise => "noise",
+        }
+    }
+
+    /// The frames of the video, as many as a hash is built from.
+    #[must_use]
+    pub fn frames(self) -> Vec<GrayImage> {
+        let (width, height) = FIXTURE_DIMENSIONS;
+        let mut noise = SplitMix64(0x5eed);
+        (0..DCT_SIZE)
+            .map(|t| {
+                GrayImage::from_fn(width, height, |x, y| {
+                    Luma([match self {
+                        Self::Gradient => ((x * 3).abs_diff(y * 4) + t * 4).min(255) as u8,
+                        Self::Checkerboard => {
+                            if ((x + t) / 8 + y / 8) % 2 == 0 {
+                                32
+                            } else {
+                                224
+                            }
+                        }
+                        Self::MovingBox => moving_box(x, y, t, 0),
+                        Self::ShiftedMovingBox => moving_box(x, y, t, 2),
+                        Self::Noise => noise.next_u8(),
+                    }])
+                })
+            })
+            .collect()
+    }
+
+    /// The hash of the video at the given size. The path of the hash is the name of the fixture.
+    ///
+    /// # Panics
+    /// If the frames cannot be hashed, which would be a bug.
+    #[must_use]
+    pub fn hash(self, hash_bits: HashSize) -> VideoHash {
+        let opts = CreationOptions {
+            cropdetect: Cropdetect::None,
+            hash_bits,
+            ..CreationOptions::default()
+        };
+        super::video_hash_builder::hash_from_frame_iter(
+            PathBuf::from(self.name()),
+            FIXTURE_DURATION,
+            self.frames(),
+            opts,
+            None,
+        )
+        .expect("fixture frames can be hashed")
+    }
+}
+
+// A 24x16 box of brightness 220 on a background of 20, which starts at (8 + offset, 8 + offset)
+// and moves two pixels to the right every frame.
+fn moving_box(x: u32, y: u32, t: u32, offset: u32) -> u8 {
+    let left = 8 + offset + t * 2;
+    let top = 8 + offset;
+    if (left..left + 24).contains(&x) && (top..top + 16).contains(&y) {
+        220
+    } else {
+        20
+    }
+}
+
+// The SplitMix64 generator. Unlike the generators in rand, its output can never change.
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next_u8(&mut self) -> u8 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        (z ^ (z >> 31)) as u8
+    }
+}
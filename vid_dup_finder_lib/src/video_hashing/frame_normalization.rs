@@ -0,0 +1,213 @@
+//! Adjusting the brightness of frames before they are hashed.
+//!
+//! A hash records the sign of each of its DCT coefficients, so in dark or low contrast footage
+//! (such as night time CCTV) the small changes in brightness made by re-encoding a video can flip
+//! many of its bits. Stretching the brightness of each frame out to the full range first makes
+//! such videos hash more consistently, at the cost of making hashes of flat or faded videos more
+//! sensitive to noise.
+
+use std::sync::Arc;
+
+use image::GrayImage;
+use serde::{Deserialize, Serialize};
+
+use crate::CreationOptionsError;
+
+/// A function applied to each sampled frame before it is hashed. See
+/// [`crate::ffmpeg_builder::VideoHashBuilder::with_frame_preprocessor`].
+pub type FramePreprocessor = Arc<dyn Fn(GrayImage) -> GrayImage + Send + Sync>;
+
+/// How the brightness of each frame is adjusted before it is hashed. Chosen with
+/// [`crate::CreationOptions::normalization`] and recorded in each hash (see
+/// [`crate::VideoHash::normalization`]).
+///
+/// Hashes created with different normalizations never match each other, so all hashes that are
+/// to be searched together must be created with the same one.
+#[derive(Debug, Clone, Copy, Default, PartialEq, PartialOrd, Serialize, Deserialize)]
+pub enum Normalization {
+    /// Frames are hashed as they were decoded.
+    #[default]
+    None,
+
+    /// The brightness levels of each frame are spread so that they are used equally often.
+    HistogramEqualize,
+
+    /// The brightness of each frame is stretched linearly so that the darkest `low_pct` percent
+    /// of its pixels become black and the brightest `100 - high_pct` percent become white.
+    /// Requires `0 <= low_pct < high_pct <= 100`.
+    ContrastStretch { low_pct: f64, high_pct: f64 },
+
+    /// The frames were passed through a [`FramePreprocessor`] instead. Hashes made with different
+    /// preprocessors cannot be told apart, so only search hashes made with the same one together.
+    ///
+    /// Choosing this in [`crate::CreationOptions`] without giving the builder a preprocessor
+    /// hashes the frames unchanged.
+    Custom,
+}
+
+impl Normalization {
+    // The normalization as something totally ordered, for the ordering of VideoHash.
+    pub(crate) fn key(self) -> (u8, u64, u64) {
+        match self {
+            Self::None => (0, 0, 0),
+            Self::HistogramEqualize => (1, 0, 0),
+            Self::ContrastStretch { low_pct, high_pct } => {
+                (2, low_pct.to_bits(), high_pct.to_bits())
+            }
+            Self::Custom => (3, 0, 0),
+        }
+    }
+
+    pub(crate) fn validate(self) -> Result<(), CreationOptionsError> {
+        match self {
+            Self::ContrastStretch { low_pct, high_pct }
+                if !(0.0 <= low_pct && low_pct < high_pct && high_pct <= 100.0) =>
+            {
+                Err(CreationOptionsError::InvalidContrastStretch { low_pct, high_pct })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Apply this normalization to a frame.
+    #[must_use]
+    pub fn apply(self, frame: GrayImage) -> GrayImage {
+        match self {
+            Self::None | Self::Custom => frame,
+            Self::HistogramEqualize => histogram_equalize(frame),
+            Self::ContrastStretch { low_pct, high_pct } => {
+                contrast_stretch(frame, low_pct, high_pct)
+            }
+        }
+    }
+}
+
+fn histogram(frame: &GrayImage) -> [u64; 256] {
+    let mut histogram = [0; 256];
+    for &value in frame.as_raw() {
+        histogram[usize::from(value)] += 1;
+    }
+    histogram
+}
+
+// Each level is mapped to the fraction of the pixels at or below it, leaving out the pixels at the
+// darkest level so that the darkest level in the frame becomes black.
+fn histogram_equalize(mut frame: GrayImage) -> GrayImage {
+    let histogram = histogram(&frame);
+    let total = frame.as_raw().len() as u64;
+    let darkest = histogram.iter().copied().find(|&n| n > 0).unwrap_or(0);
+    if total == darkest {
+        //a flat frame has nothing to spread out.
+        return frame;
+    }
+
+    let mut lut = [0; 256];
+    let mut below = 0;
+    for (level, count) in histogram.iter().enumerate() {
+        below += count;
+        let scaled = below.saturating_sub(darkest) * 255 / (total - darkest);
+        lut[level] = u8::try_from(scaled).unwrap_or(u8::MAX);
+    }
+
+    apply_lut(&mut frame, &lut);
+    frame
+}
+
+fn contrast_stretch(mut frame: GrayImage, low_pct: f64, high_pct: f64) -> GrayImage {
+    let histogram = histogram(&frame);
+    let total = frame.as_raw().len() as u64;
+
+    //the lowest level with at least the given percentage of pixels at or below it.
+    let percentile = |pct: f64| {
+        let wanted = (pct / 100.0 * total as f64).ceil().max(1.0) as u64;
+        let mut below = 0;
+        histogram
+            .iter()
+            .position(|count| {
+                below += count;
+                below >= wanted
+            })
+            .unwrap_or(255) as f64
+    };
+    let (low, high) = (percentile(low_pct), percentile(high_pct));
+    if high <= low {
+        return frame;
+    }
+
+    let mut lut = [0; 256];
+    for (level, entry) in lut.iter_mut().enumerate() {
+        let stretched = (level as f64 - low) * 255.0 / (high - low);
+        *entry = stretched.round().clamp(0.0, 255.0) as u8;
+    }
+
+    apply_lut(&mut frame, &lut);
+    frame
+}
+
+fn apply_lut(frame: &mut GrayImage, lut: &[u8; 256]) {
+    for pixel in frame.pixels_mut() {
+        pixel.0[0] = lut[usize::from(pixel.0[0])];
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use image::Luma;
+
+    use super::*;
+
+    fn gradient(levels: impl Fn(u32) -> u8) -> GrayImage {
+        GrayImage::from_fn(16, 16, |x, y| Luma([levels(y * 16 + x)]))
+    }
+
+    fn range(frame: &GrayImage) -> (u8, u8) {
+        let raw = frame.as_raw();
+        (
+            *raw.iter().min().expect("not empty"),
+            *raw.iter().max().expect("not empty"),
+        )
+    }
+
+    #[test]
+    fn test_histogram_equalize() {
+        //a dark frame using only levels 10 to 41 is spread over the whole range.
+        let dark = gradient(|i| 10 + (i / 8) as u8);
+        let equalized = Normalization::HistogramEqualize.apply(dark.clone());
+        assert_eq!(range(&equalized), (0, 255));
+
+        //the order of the pixels by brightness is kept.
+        for (p1, p2) in dark.as_raw().windows(2).zip(equalized.as_raw().windows(2)) {
+            assert_eq!(p1[0].cmp(&p1[1]), p2[0].cmp(&p2[1]));
+        }
+
+        //and any change in brightness which keeps that order makes no difference.
+        let brighter = gradient(|i| 100 + (i / 8) as u8 * 3);
+        assert_eq!(Normalization::HistogramEqualize.apply(brighter), equalized);
+
+        let flat = gradient(|_| 30);
+        assert_eq!(Normalization::HistogramEqualize.apply(flat.clone()), flat);
+    }
+
+    #[test]
+    fn test_contrast_stretch() {
+        let stretch = |low_pct, high_pct| Normalization::ContrastStretch { low_pct, high_pct };
+
+        let dark = gradient(|i| 10 + (i / 8) as u8);
+        assert_eq!(range(&stretch(0.0, 100.0).apply(dark.clone())), (0, 255));
+
+        //the darkest and brightest 10% are clipped.
+        let clipped = stretch(10.0, 90.0).apply(dark.clone());
+        let black = clipped.as_raw().iter().filter(|&&v| v == 0).count();
+        let white = clipped.as_raw().iter().filter(|&&v| v == 255).count();
+        assert!((25..=40).contains(&black), "{black}");
+        assert!((25..=40).contains(&white), "{white}");
+
+        let flat = gradient(|_| 30);
+        assert_eq!(stretch(1.0, 99.0).apply(flat.clone()), flat);
+
+        assert!(stretch(1.0, 99.0).validate().is_ok());
+        for (low, high) in [(50.0, 50.0), (-1.0, 50.0), (1.0, 101.0), (f64::NAN, 50.0)] {
+            assert!(stretch(low, high).validate().is_err());
+        }
+    }
+}
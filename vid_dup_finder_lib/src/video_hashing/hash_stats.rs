@@ -0,0 +1,28 @@
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+/// How long a hash took to create and what was decoded to create it, returned by
+/// `VideoHashBuilder::hash_with_stats` whether or not the hash could be created.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct HashStats {
+    /// The wall-clock time taken to create the hash (or to fail), including probing the video.
+    pub elapsed: Duration,
+
+    /// The backend that decoded the video: `"ffmpeg"` or `"gstreamer"`.
+    pub backend: String,
+
+    /// The number of frames that the hash was created from, if decoding got that far.
+    pub frames_decoded: Option<u32>,
+
+    /// The resolution of the video before any cropping, if it could be probed.
+    pub resolution: Option<(u32, u32)>,
+
+    /// The video codec, as named by the backend, if it could be probed.
+    pub video_codec: Option<String>,
+
+    /// The decoder that the backend chose for the video, if it reports one. gstreamer gives the
+    /// factory name of the decoder element (such as "avdec_h264" or "vah264dec"), which shows
+    /// whether a video was decoded in software. ffmpeg does not report its decoder.
+    pub decoder: Option<String>,
+}
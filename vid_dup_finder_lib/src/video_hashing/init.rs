@@ -0,0 +1,161 @@
+#[cfg(feature = "ffmpeg_backend")]
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::OnceLock;
+
+#[cfg(feature = "ffmpeg_backend")]
+use ffmpeg_gst_wrapper::ffmpeg_impl::FfmpegVersion;
+
+use crate::Error;
+
+/// The oldest major version of ffmpeg that [`init`] accepts without a warning, unless changed with
+/// [`set_min_ffmpeg_version`].
+#[cfg(feature = "ffmpeg_backend")]
+pub const DEFAULT_MIN_FFMPEG_VERSION: u32 = 4;
+
+// The result of the first call to init, which every later call returns.
+static INIT_RESULT: OnceLock<Result<(), Error>> = OnceLock::new();
+
+#[cfg(feature = "ffmpeg_backend")]
+static MIN_FFMPEG_VERSION: AtomicU32 = AtomicU32::new(DEFAULT_MIN_FFMPEG_VERSION);
+
+#[cfg(feature = "ffmpeg_backend")]
+static FFMPEG_OUTDATED: AtomicBool = AtomicBool::new(false);
+
+/// Initialize the video backends enabled by this crate's features. Must be called before any
+/// hashes are created.
+///
+/// For the gstreamer backend this initializes gstreamer and checks that the plugins needed to
+/// decode videos are installed. For the ffmpeg backend it checks that ffmpeg and ffprobe can be
+/// run.
+///
+/// This may be called any number of times, from any thread. The backends are only checked by the
+/// first call, and every later call returns the same result.
+///
+/// If the version of ffmpeg is older than [`set_min_ffmpeg_version`], a warning is logged and
+/// [`ffmpeg_is_outdated`] returns true, but hashes can still be created.
+///
+/// # Errors
+/// Returns [`Error::FfmpegMissing`] if ffmpeg or ffprobe cannot be run, or
+/// [`Error::MissingComponents`] listing everything that the backends need but could not find if
+/// more than one backend is enabled.
+pub fn init() -> Result<(), Error> {
+    INIT_RESULT.get_or_init(init_backends).clone()
+}
+
+/// Change the oldest major version of ffmpeg that [`init`] accepts without a warning. The default
+/// is [`DEFAULT_MIN_FFMPEG_VERSION`]. Only has an effect if called before the first call to [`init`].
+#[cfg(feature = "ffmpeg_backend")]
+pub fn set_min_ffmpeg_version(major: u32) {
+    MIN_FFMPEG_VERSION.store(major, Ordering::Relaxed);
+}
+
+/// True if [`init`] found that ffmpeg or ffprobe is older than the minimum version (see
+/// [`set_min_ffmpeg_version`]). Hashes created with old versions may fail more often, or may not
+/// match hashes of the same videos created with newer versions.
+#[cfg(feature = "ffmpeg_backend")]
+pub fn ffmpeg_is_outdated() -> bool {
+    FFMPEG_OUTDATED.load(Ordering::Relaxed)
+}
+
+/// Initialize gstreamer (and any other backend).
+///
+/// # Panics
+/// Panics if initialization fails.
+#[deprecated(
+    note = "use vid_dup_finder_lib::init, which returns an error instead of panicking. init_gstreamer will be removed in the next release"
+)]
+#[allow(clippy::panic)]
+pub fn init_gstreamer() {
+    if let Err(e) = init() {
+        panic!("{e}");
+    }
+}
+
+// Called before decoding anything, so that a missing init is reported clearly rather than
+// failing somewhere inside a backend.
+pub(crate) fn ensure_initialized() -> Result<(), Error> {
+    match INIT_RESULT.get() {
+        Some(result) => result.clone(),
+        None => Err(Error::NotInitialized),
+    }
+}
+
+fn init_backends() -> Result<(), Error> {
+    #[allow(unused_mut)]
+    let mut missing: Vec<String> = vec![];
+    #[allow(unused_mut)]
+    let mut ffmpeg_missing: Vec<String> = vec![];
+
+    #[cfg(feature = "gstreamer_backend")]
+    if let Err(gst_missing) = ffmpeg_gst_wrapper::gst_impl::init() {
+        missing.extend(gst_missing);
+    }
+
+    #[cfg(feature = "ffmpeg_backend")]
+    match ffmpeg_gst_wrapper::ffmpeg_impl::init() {
+        Ok(version) => check_ffmpeg_version(&version),
+        Err(names) => ffmpeg_missing = names,
+    }
+
+    match (missing.is_empty(), ffmpeg_missing.is_empty()) {
+        (true, true) => Ok(()),
+        (true, false) => Err(Error::FfmpegMissing(ffmpeg_missing)),
+        (false, _) => {
+            missing.extend(ffmpeg_missing);
+            Err(Error::MissingComponents(missing))
+        }
+    }
+}
+
+#[cfg(feature = "ffmpeg_backend")]
+fn check_ffmpeg_version(version: &FfmpegVersion) {
+    let min_major = MIN_FFMPEG_VERSION.load(Ordering::Relaxed);
+    if version.is_older_than(min_major) {
+        FFMPEG_OUTDATED.store(true, Ordering::Relaxed);
+        log::warn!(
+            "ffmpeg {} (ffprobe {}) is older than version {min_major}. Some videos may fail to hash, \
+             and hashes may not match those created with newer versions",
+            version.ffmpeg,
+            version.ffprobe
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_init_result_is_remembered() {
+        //Nothing else in this test binary calls init.
+        assert!(matches!(ensure_initialized(), Err(Error::NotInitialized)));
+
+        //The backends may or may not be installed here, but either way every caller agrees.
+        let first = format!("{:?}", init());
+        let others = (0..4)
+            .map(|_| std::thread::spawn(init))
+            .collect::<Vec<_>>()
+            .into_iter()
+            .map(|thread| format!("{:?}", thread.join().expect("init does not panic")));
+        for other in others {
+            assert_eq!(other, first);
+        }
+        assert_eq!(format!("{:?}", ensure_initialized()), first);
+    }
+
+    #[cfg(feature = "ffmpeg_backend")]
+    #[test]
+    fn test_old_ffmpeg_is_flagged() {
+        let version = |v: &str| FfmpegVersion {
+            ffmpeg: v.to_string(),
+            ffprobe: v.to_string(),
+        };
+
+        check_ffmpeg_version(&version("N-113404-g0ae5f7b"));
+        check_ffmpeg_version(&version("6.1.1-3ubuntu5"));
+        assert!(!ffmpeg_is_outdated());
+
+        check_ffmpeg_version(&version("3.4.8"));
+        assert!(ffmpeg_is_outdated());
+    }
+}
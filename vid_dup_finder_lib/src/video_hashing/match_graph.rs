@@ -0,0 +1,288 @@
+//! Grouping videos by the graph of which pairs match, for [`GroupingMode::ConnectedComponents`]
+//! and [`GroupingMode::Cliques`].
+
+use crate::GroupingMode;
+
+//The number of calls to bron_kerbosch that each connected component may make before its cliques
+//are found greedily instead.
+const MAX_CLIQUE_STEPS: usize = 10_000;
+
+/// An undirected graph over the videos being searched, by index, with an edge between each pair
+/// that matches.
+#[derive(Debug, Clone)]
+pub(super) struct MatchGraph {
+    //sorted
+    adjacent: Vec<Vec<usize>>,
+}
+
+impl MatchGraph {
+    pub fn new(len: usize) -> Self {
+        Self {
+            adjacent: vec![vec![]; len],
+        }
+    }
+
+    /// Edges must be added in increasing order of `lhs`, then of `rhs`, with `lhs < rhs`.
+    pub fn add_edge(&mut self, lhs: usize, rhs: usize) {
+        debug_assert!(lhs < rhs);
+        self.adjacent[lhs].push(rhs);
+        self.adjacent[rhs].push(lhs);
+    }
+
+    fn matches(&self, v1: usize, v2: usize) -> bool {
+        self.adjacent[v1].binary_search(&v2).is_ok()
+    }
+
+    /// The groups of vertices, each sorted, for any mode other than [`GroupingMode::Centered`].
+    pub fn groups(&self, mode: GroupingMode) -> Vec<Vec<usize>> {
+        let components = self.components();
+        match mode {
+            GroupingMode::Centered | GroupingMode::ConnectedComponents => components,
+            GroupingMode::Cliques { min_mutual } => components
+                .iter()
+                .flat_map(|component| self.cliques(component, min_mutual))
+                .collect(),
+        }
+    }
+
+    // The connected components with more than one vertex.
+    fn components(&self) -> Vec<Vec<usize>> {
+        let mut seen = vec![false; self.adjacent.len()];
+        let mut ret = vec![];
+        for start in 0..self.adjacent.len() {
+            if seen[start] || self.adjacent[start].is_empty() {
+                continue;
+            }
+            seen[start] = true;
+            let mut component = vec![start];
+            let mut next = 0;
+            while let Some(&v) = component.get(next) {
+                next += 1;
+                for &w in &self.adjacent[v] {
+                    if !std::mem::replace(&mut seen[w], true) {
+                        component.push(w);
+                    }
+                }
+            }
+            component.sort_unstable();
+            ret.push(component);
+        }
+        ret
+    }
+
+    fn cliques(&self, component: &[usize], min_mutual: f64) -> Vec<Vec<usize>> {
+        let mut cliques = vec![];
+        let mut steps = 0;
+        let finished = self.bron_kerbosch(
+            &mut vec![],
+            component.to_vec(),
+            vec![],
+            &mut cliques,
+            &mut steps,
+        );
+        if !finished {
+            log::warn!(
+                "Too many overlapping matches between {} videos to find every clique. Grouping them greedily instead",
+                component.len()
+            );
+            cliques = self.greedy_cliques(component);
+        }
+
+        if min_mutual < 1.0 {
+            cliques = self.merge(cliques, min_mutual);
+        }
+        cliques.sort();
+        cliques
+    }
+
+    // Bron-Kerbosch with pivoting. Adds every maximal clique containing all of `clique`, some of
+    // `cands` and none of `excluded` to `found`. Returns false if it ran out of steps.
+    fn bron_kerbosch(
+        &self,
+        clique: &mut Vec<usize>,
+        mut cands: Vec<usize>,
+        mut excluded: Vec<usize>,
+        found: &mut Vec<Vec<usize>>,
+        steps: &mut usize,
+    ) -> bool {
+        *steps += 1;
+        if *steps > MAX_CLIQUE_STEPS {
+            return false;
+        }
+
+        if cands.is_empty() {
+            if excluded.is_empty() {
+                let mut clique = clique.clone();
+                clique.sort_unstable();
+                found.push(clique);
+            }
+            return true;
+        }
+
+        //every maximal clique contains the pivot or a vertex which does not match it.
+        let pivot = cands
+            .iter()
+            .chain(&excluded)
+            .copied()
+            .max_by_key(|&u| cands.iter().filter(|&&v| self.matches(u, v)).count())
+            .expect("cands is not empty");
+
+        let branches = cands
+            .iter()
+            .copied()
+            .filter(|&v| !self.matches(pivot, v))
+            .collect::<Vec<_>>();
+        for v in branches {
+            let neighbours = |vs: &[usize]| {
+                vs.iter()
+                    .copied()
+                    .filter(|&w| self.matches(v, w))
+                    .collect::<Vec<_>>()
+            };
+            clique.push(v);
+            let finished = self.bron_kerbosch(
+                clique,
+                neighbours(&cands),
+                neighbours(&excluded),
+                found,
+                steps,
+            );
+            clique.pop();
+            if !finished {
+                return false;
+            }
+
+            cands.retain(|&w| w != v);
+            excluded.push(v);
+        }
+        true
+    }
+
+    // Split the component into disjoint cliques, each grown from the remaining vertex with the most
+    // remaining neighbours by adding its neighbours in the same order.
+    fn greedy_cliques(&self, component: &[usize]) -> Vec<Vec<usize>> {
+        let mut remaining = component.to_vec();
+        let mut ret = vec![];
+        while remaining.len() > 1 {
+            let degree = |v: usize| remaining.iter().filter(|&&w| self.matches(v, w)).count();
+            let mut by_degree = remaining.clone();
+            //stable, so ties are broken by index.
+            by_degree.sort_by_key(|&v| std::cmp::Reverse(degree(v)));
+
+            let mut clique = vec![by_degree[0]];
+            for &v in &by_degree[1..] {
+                if clique.iter().all(|&member| self.matches(member, v)) {
+                    clique.push(v);
+                }
+            }
+
+            remaining.retain(|v| !clique.contains(v));
+            if clique.len() > 1 {
+                clique.sort_unstable();
+                ret.push(clique);
+            }
+        }
+        ret
+    }
+
+    // Repeatedly merge the first pair of overlapping groups whose union still obeys min_mutual.
+    fn merge(&self, mut groups: Vec<Vec<usize>>, min_mutual: f64) -> Vec<Vec<usize>> {
+        let obeys_min_mutual = |group: &[usize]| {
+            let others = (group.len() - 1) as f64;
+            group.iter().all(|&v| {
+                let matched = group.iter().filter(|&&w| self.matches(v, w)).count();
+                matched as f64 >= min_mutual * others
+            })
+        };
+
+        'merging: loop {
+            for i in 0..groups.len() {
+                for j in i + 1..groups.len() {
+                    if !groups[i].iter().any(|v| groups[j].contains(v)) {
+                        continue;
+                    }
+                    let mut union = [groups[i].as_slice(), &groups[j]].concat();
+                    union.sort_unstable();
+                    union.dedup();
+                    if obeys_min_mutual(&union) {
+                        groups[i] = union;
+                        groups.swap_remove(j);
+                        continue 'merging;
+                    }
+                }
+            }
+            break;
+        }
+
+        //a group may have grown to contain another.
+        let contains = |outer: &[usize], inner: &[usize]| inner.iter().all(|v| outer.contains(v));
+        let mut ret: Vec<Vec<usize>> = vec![];
+        groups.sort_by_key(|group| std::cmp::Reverse(group.len()));
+        for group in groups {
+            if !ret.iter().any(|kept| contains(kept, &group)) {
+                ret.push(group);
+            }
+        }
+        ret
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn graph(len: usize, edges: &[(usize, usize)]) -> MatchGraph {
+        let mut edges = edges.to_vec();
+        edges.sort_unstable();
+        let mut graph = MatchGraph::new(len);
+        for (lhs, rhs) in edges {
+            graph.add_edge(lhs, rhs);
+        }
+        graph
+    }
+
+    fn cliques(graph: &MatchGraph, min_mutual: f64) -> Vec<Vec<usize>> {
+        graph.groups(GroupingMode::Cliques { min_mutual })
+    }
+
+    #[test]
+    fn test_components_and_cliques() {
+        //two triangles sharing vertex 2, a pair, and a vertex with no matches.
+        let g = graph(8, &[(0, 1), (0, 2), (1, 2), (2, 3), (2, 4), (3, 4), (5, 6)]);
+
+        assert_eq!(
+            g.groups(GroupingMode::ConnectedComponents),
+            [vec![0, 1, 2, 3, 4], vec![5, 6]]
+        );
+        assert_eq!(cliques(&g, 1.0), [vec![0, 1, 2], vec![2, 3, 4], vec![5, 6]]);
+
+        //in the union of the triangles, 0, 1, 3 and 4 each match half of the others.
+        assert_eq!(cliques(&g, 0.5), [vec![0, 1, 2, 3, 4], vec![5, 6]]);
+        assert_eq!(cliques(&g, 0.6), [vec![0, 1, 2], vec![2, 3, 4], vec![5, 6]]);
+    }
+
+    #[test]
+    fn test_pathological_graphs_are_grouped_greedily() {
+        //the complement of a perfect matching on 60 vertices has 2^30 maximal cliques.
+        let len = 60;
+        let edges = (0..len)
+            .flat_map(|v| (v + 1..len).map(move |w| (v, w)))
+            .filter(|&(v, w)| !(v % 2 == 0 && w == v + 1))
+            .collect::<Vec<_>>();
+        let g = graph(len, &edges);
+
+        let groups = cliques(&g, 1.0);
+        let mut seen = vec![false; len];
+        for group in &groups {
+            for (i, &v) in group.iter().enumerate() {
+                assert!(
+                    !std::mem::replace(&mut seen[v], true),
+                    "{v} is in two groups"
+                );
+                assert!(group[i + 1..].iter().all(|&w| g.matches(v, w)));
+            }
+        }
+        //one vertex of each pair is taken first, and the other vertices then form a clique too.
+        assert_eq!(groups.len(), 2);
+    }
+}
@@ -0,0 +1,335 @@
+//! Verifying matches by comparing their frames with a classical image metric.
+//!
+//! Hashes only keep the coarse shapes of the frames that they were made from, so before deleting
+//! videos unattended it can be worth checking each match another way. [`verify_match`] decodes a
+//! few frames from the same relative positions in two videos, and compares them by their
+//! structural similarity (SSIM). Searching with
+//! [`SearchOptions::verify_matches`][crate::SearchOptions::verify_matches] set verifies every pair
+//! of videos in every group, and records the reports in the groups (see
+//! [`MatchGroup::match_verification`][crate::MatchGroup::match_verification]).
+use std::{cmp::Ordering, path::Path};
+
+use image::GrayImage;
+
+use crate::{definitions::MAX_COVERAGE_SEGMENTS, Error};
+
+/// Options for [`verify_match`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VerifyOpts {
+    /// The number of frames compared, from positions spaced evenly through each video from its
+    /// start to just before its end. Must be between 1 and [`MAX_COVERAGE_SEGMENTS`].
+    pub frames: u32,
+
+    /// The width and height that the frames of both videos are shrunk to before they are compared,
+    /// so that copies of different resolutions can be compared.
+    pub size: (u32, u32),
+
+    /// The lowest mean SSIM with which a pair of videos passes. Re-encoded copies usually score
+    /// above 0.8, and different videos below 0.4.
+    pub threshold: f64,
+}
+
+impl Default for VerifyOpts {
+    /// 8 frames, shrunk to 128x96 and compared with a threshold of 0.6.
+    fn default() -> Self {
+        Self {
+            frames: MAX_COVERAGE_SEGMENTS,
+            size: (128, 96),
+            threshold: 0.6,
+        }
+    }
+}
+
+/// The outcome of comparing the frames of two videos with [`verify_match`].
+#[derive(Debug, Clone, Copy)]
+pub struct VerifyReport {
+    frames_compared: usize,
+    mean_ssim: f64,
+    min_ssim: f64,
+    passed: bool,
+}
+
+impl VerifyReport {
+    /// The number of pairs of frames that were compared. This is less than
+    /// [`VerifyOpts::frames`] if fewer frames could be decoded from either video.
+    #[must_use]
+    pub const fn frames_compared(&self) -> usize {
+        self.frames_compared
+    }
+
+    /// The mean SSIM of the pairs of frames, where 1.0 means that they are identical.
+    #[must_use]
+    pub const fn mean_ssim(&self) -> f64 {
+        self.mean_ssim
+    }
+
+    /// The SSIM of the least similar pair of frames.
+    #[must_use]
+    pub const fn min_ssim(&self) -> f64 {
+        self.min_ssim
+    }
+
+    /// Whether the mean SSIM is at least [`VerifyOpts::threshold`].
+    #[must_use]
+    pub const fn passed(&self) -> bool {
+        self.passed
+    }
+
+    fn key(&self) -> (usize, u64, u64, bool) {
+        let (mean, min) = (self.mean_ssim.to_bits(), self.min_ssim.to_bits());
+        (self.frames_compared, mean, min, self.passed)
+    }
+}
+
+//Reports are compared by their bits, so that groups (which record them) can be ordered and hashed.
+impl PartialEq for VerifyReport {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for VerifyReport {}
+
+impl PartialOrd for VerifyReport {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for VerifyReport {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.key().cmp(&other.key())
+    }
+}
+
+impl std::hash::Hash for VerifyReport {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.key().hash(state);
+    }
+}
+
+/// Decode [`VerifyOpts::frames`] frames from the same relative positions in each of the two videos
+/// (so that a copy of a different length is still compared at the same moments), shrink them to
+/// [`VerifyOpts::size`] and compare each pair by its SSIM.
+///
+/// The frames are sampled, and letterboxing is cropped, as for a hash with the default
+/// [`CreationOptions`][crate::CreationOptions]. If fewer frames can be decoded from one video than
+/// the other, only as many pairs as both have are compared.
+///
+/// # Errors
+/// [`Error::MissingComponents`] if the library was built without a video backend,
+/// [`Error::InvalidOptions`] or [`Error::VidProc`] if the options are invalid,
+/// [`Error::NotEnoughFrames`] if no frames could be decoded from either video, or any error from
+/// decoding them.
+pub fn verify_match(a: &Path, b: &Path, opts: &VerifyOpts) -> Result<VerifyReport, Error> {
+    compare_aligned_frames(&aligned_frames(a, opts)?, &aligned_frames(b, opts)?, opts)
+}
+
+// Compare frames taken from the same relative positions in two videos, as returned by
+// aligned_frames.
+pub(crate) fn compare_aligned_frames(
+    frames_a: &[GrayImage],
+    frames_b: &[GrayImage],
+    opts: &VerifyOpts,
+) -> Result<VerifyReport, Error> {
+    let (width, height) = opts.size;
+    if width == 0 || height == 0 {
+        return Err(Error::VidProc(format!(
+            "verification frame size must be positive. Got {width}x{height}"
+        )));
+    }
+    let shrink = |frame| {
+        super::video_hash_builder::shrink_frame(frame, width, height).ok_or(Error::NotEnoughFrames)
+    };
+
+    let scores = frames_a
+        .iter()
+        .zip(frames_b)
+        .map(|(a, b)| {
+            let (a, b) = (shrink(a)?, shrink(b)?);
+            vid_dup_finder_common::ssim(&a, &b).ok_or(Error::NotEnoughFrames)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    if scores.is_empty() {
+        return Err(Error::NotEnoughFrames);
+    }
+
+    let mean_ssim = scores.iter().sum::<f64>() / scores.len() as f64;
+    Ok(VerifyReport {
+        frames_compared: scores.len(),
+        mean_ssim,
+        min_ssim: scores.iter().copied().fold(f64::INFINITY, f64::min),
+        passed: mean_ssim >= opts.threshold,
+    })
+}
+
+// The frames of a video at the positions given by the options, after cropping. Each run of frames
+// of a hash with evenly spaced coverage starts at the same relative position in every video, and
+// the first frame of each run is taken.
+#[cfg(any(feature = "ffmpeg_backend", feature = "gstreamer_backend"))]
+pub(crate) fn aligned_frames(path: &Path, opts: &VerifyOpts) -> Result<Vec<GrayImage>, Error> {
+    #[cfg(feature = "ffmpeg_backend")]
+    use crate::ffmpeg_builder::VideoHashBuilder;
+    #[cfg(all(feature = "gstreamer_backend", not(feature = "ffmpeg_backend")))]
+    use crate::gstreamer_builder::VideoHashBuilder;
+
+    let creation_opts = crate::CreationOptions {
+        skip_forward_amount: 0.0,
+        coverage: crate::Coverage::EvenlySpaced {
+            segments: opts.frames,
+        },
+        min_duration: 0.0,
+        ..crate::CreationOptions::default()
+    };
+    let frames = VideoHashBuilder::from_options(creation_opts)
+        .frames(path)?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let step = (frames.len() / opts.frames as usize).max(1);
+    Ok(frames
+        .into_iter()
+        .step_by(step)
+        .take(opts.frames as usize)
+        .collect())
+}
+
+#[cfg(not(any(feature = "ffmpeg_backend", feature = "gstreamer_backend")))]
+pub(crate) fn aligned_frames(_path: &Path, _opts: &VerifyOpts) -> Result<Vec<GrayImage>, Error> {
+    Err(Error::MissingComponents(
+        vec!["a video backend".to_string()],
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use image::Luma;
+
+    use super::*;
+    use crate::{search_with_opts, MatchGroup, SearchOptions, VideoHash};
+
+    // Frames of a scene, each a grid of grey blocks which scrolls to the right over time.
+    fn scene(seed: u32, frames: u32) -> Vec<GrayImage> {
+        (0..frames)
+            .map(|t| {
+                GrayImage::from_fn(320, 240, |x, y| {
+                    let block = ((x + t * 8) / 40 % 8) + y / 30 * 8 + seed * 64;
+                    Luma([(block.wrapping_mul(2_654_435_761) >> 24) as u8])
+                })
+            })
+            .collect()
+    }
+
+    // The same frames at a lower resolution, with a little noise as if re-encoded.
+    fn reencoded(frames: &[GrayImage]) -> Vec<GrayImage> {
+        frames
+            .iter()
+            .map(|frame| {
+                let small =
+                    image::imageops::resize(frame, 160, 120, image::imageops::FilterType::Triangle);
+                GrayImage::from_fn(160, 120, |x, y| {
+                    let noise = ((x * 7 + y * 13) % 5) as u8;
+                    Luma([small.get_pixel(x, y).0[0].saturating_add(noise)])
+                })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_copies_pass_and_different_videos_fail() {
+        let opts = VerifyOpts::default();
+        let original = scene(0, 8);
+
+        let itself = compare_aligned_frames(&original, &original, &opts).expect("comparable");
+        assert_eq!(itself.frames_compared(), 8);
+        assert!((itself.mean_ssim() - 1.0).abs() < 1e-9);
+        assert!(itself.passed());
+
+        let copy =
+            compare_aligned_frames(&original, &reencoded(&original), &opts).expect("comparable");
+        assert!(copy.mean_ssim() > 0.8, "{copy:?}");
+        assert!(copy.min_ssim() <= copy.mean_ssim());
+        assert!(copy.passed());
+
+        let other = compare_aligned_frames(&original, &scene(1, 8), &opts).expect("comparable");
+        assert!(other.mean_ssim() < opts.threshold - 0.2, "{other:?}");
+        assert!(!other.passed());
+    }
+
+    #[test]
+    fn test_frame_count_mismatches() {
+        let opts = VerifyOpts::default();
+        let original = scene(0, 8);
+
+        //a video which could only partly be decoded is compared as far as it goes.
+        let partial = compare_aligned_frames(&original, &original[..3], &opts).expect("comparable");
+        assert_eq!(partial.frames_compared(), 3);
+        assert!(partial.passed());
+
+        assert!(matches!(
+            compare_aligned_frames(&original, &[], &opts),
+            Err(Error::NotEnoughFrames)
+        ));
+        let no_size = VerifyOpts {
+            size: (0, 96),
+            ..opts
+        };
+        assert!(matches!(
+            compare_aligned_frames(&original, &original, &no_size),
+            Err(Error::VidProc(_))
+        ));
+    }
+
+    #[test]
+    fn test_groups_record_verifications() {
+        let opts = VerifyOpts::default();
+        let videos = [
+            ("a.mp4", scene(0, 8)),
+            ("a_copy.mp4", reencoded(&scene(0, 8))),
+            ("b.mp4", scene(1, 8)),
+        ];
+        let group = MatchGroup::new(videos.iter().map(|(name, _)| PathBuf::from(name)))
+            .expect("enough entries")
+            .with_match_verification(&opts, |path| {
+                let (_, frames) = videos.iter().find(|(name, _)| Path::new(name) == path)?;
+                Some(frames.clone())
+            });
+
+        let verified = |a, b| group.match_verification(a, b).map(|report| report.passed());
+        assert_eq!(verified("a.mp4", "a_copy.mp4"), Some(true));
+        assert_eq!(verified("b.mp4", "a.mp4"), Some(false));
+        assert_eq!(verified("a_copy.mp4", "b.mp4"), Some(false));
+        assert_eq!(verified("a.mp4", "c.mp4"), None);
+        assert!(group.failed_verification("b.mp4"));
+
+        //the video which failed is dropped, and the copies are kept with their report.
+        let passing = group.without_failed_verifications().expect("a pair passed");
+        assert_eq!(
+            passing.contained_paths().collect::<Vec<_>>(),
+            [Path::new("a.mp4"), Path::new("a_copy.mp4")]
+        );
+        assert_eq!(
+            passing.match_verification("a.mp4", "a_copy.mp4"),
+            group.match_verification("a.mp4", "a_copy.mp4")
+        );
+        assert!(!passing.failed_verification("a.mp4"));
+    }
+
+    #[test]
+    fn test_searches_only_verify_when_asked() {
+        let hashes = [
+            VideoHash::empty_hash("a.mp4"),
+            VideoHash::empty_hash("b.mp4"),
+        ];
+
+        //neither video exists, so no reports are recorded and nothing is dropped.
+        let opts = SearchOptions {
+            verify_matches: Some(VerifyOpts::default()),
+            ..SearchOptions::default()
+        };
+        let groups = search_with_opts(hashes.clone(), &opts);
+        assert_eq!(groups[0].match_verification("a.mp4", "b.mp4"), None);
+        assert!(groups[0].without_failed_verifications().is_some());
+    }
+}
@@ -0,0 +1,134 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+/// A set of pairs of videos that must never be reported as duplicates of each other,
+/// for use with [`crate::search_filtered`] and [`crate::search_with_references_filtered`].
+///
+/// Pairs are unordered, so suppressing `(a, b)` also suppresses `(b, a)`.
+///
+/// A suppressed pair can still appear in the same [`crate::MatchGroup`] if a third video
+/// in that group matches both of them on its own merit. It will never be grouped together
+/// only because of the suppressed match.
+///
+/// `FalseposFilter` implements `Serialize` and `Deserialize` (as a list of pairs), so it can be
+/// saved to and loaded from a file in any format supported by serde.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(from = "Vec<(PathBuf, PathBuf)>", into = "Vec<(PathBuf, PathBuf)>")]
+pub struct FalseposFilter {
+    //each pair is stored in both directions so that lookups can be done from borrowed paths.
+    pairs: HashMap<PathBuf, HashSet<PathBuf>>,
+}
+
+impl FalseposFilter {
+    /// Create an empty filter, which suppresses nothing.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Suppress matches between `a` and `b`.
+    pub fn insert(&mut self, a: impl AsRef<Path>, b: impl AsRef<Path>) {
+        let (a, b) = (a.as_ref(), b.as_ref());
+        self.pairs
+            .entry(a.to_path_buf())
+            .or_default()
+            .insert(b.to_path_buf());
+        self.pairs
+            .entry(b.to_path_buf())
+            .or_default()
+            .insert(a.to_path_buf());
+    }
+
+    /// Returns true if matches between `a` and `b` are suppressed.
+    #[must_use]
+    pub fn is_suppressed(&self, a: &Path, b: &Path) -> bool {
+        self.pairs
+            .get(a)
+            .is_some_and(|suppressed| suppressed.contains(b))
+    }
+
+    /// Returns true if the filter suppresses nothing.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.pairs.is_empty()
+    }
+
+    /// The suppressed pairs, each reported once.
+    pub fn pairs(&self) -> impl Iterator<Item = (&Path, &Path)> {
+        self.pairs.iter().flat_map(|(a, suppressed)| {
+            suppressed
+                .iter()
+                .filter(move |b| a <= *b)
+                .map(move |b| (a.as_path(), b.as_path()))
+        })
+    }
+}
+
+impl<P, Q> FromIterator<(P, Q)> for FalseposFilter
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    fn from_iter<I: IntoIterator<Item = (P, Q)>>(iter: I) -> Self {
+        let mut ret = Self::new();
+        ret.extend(iter);
+        ret
+    }
+}
+
+impl<P, Q> Extend<(P, Q)> for FalseposFilter
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    fn extend<I: IntoIterator<Item = (P, Q)>>(&mut self, iter: I) {
+        for (a, b) in iter {
+            self.insert(a, b);
+        }
+    }
+}
+
+impl From<Vec<(PathBuf, PathBuf)>> for FalseposFilter {
+    fn from(pairs: Vec<(PathBuf, PathBuf)>) -> Self {
+        pairs.into_iter().collect()
+    }
+}
+
+impl From<FalseposFilter> for Vec<(PathBuf, PathBuf)> {
+    fn from(filter: FalseposFilter) -> Self {
+        let mut ret = filter
+            .pairs()
+            .map(|(a, b)| (a.to_path_buf(), b.to_path_buf()))
+            .collect::<Vec<_>>();
+        ret.sort();
+        ret
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_pairs_are_unordered() {
+        let filter = FalseposFilter::from_iter([("b", "a"), ("a", "b"), ("c", "a")]);
+
+        assert!(filter.is_suppressed(Path::new("a"), Path::new("b")));
+        assert!(filter.is_suppressed(Path::new("b"), Path::new("a")));
+        assert!(!filter.is_suppressed(Path::new("b"), Path::new("c")));
+
+        let pairs: Vec<(PathBuf, PathBuf)> = filter.clone().into();
+        assert_eq!(
+            pairs,
+            [
+                (PathBuf::from("a"), PathBuf::from("b")),
+                (PathBuf::from("a"), PathBuf::from("c"))
+            ]
+        );
+        assert_eq!(FalseposFilter::from(pairs), filter);
+    }
+}
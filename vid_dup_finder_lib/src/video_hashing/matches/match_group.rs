@@ -1,15 +1,61 @@
 use itertools::Itertools;
-use std::path::{Path, PathBuf};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use image::GrayImage;
+
+use crate::{
+    video_hashing::{
+        containment::analyze_containment, match_verification::compare_aligned_frames,
+        zero_distance::compare_frames,
+    },
+    AudioFingerprint, ContainmentOpts, ContainmentReport, FrameSource, VerifyOpts, VerifyReport,
+    VideoHash, ZeroDistanceVerification,
+};
 
 /// A group of duplicate videos detected by [`crate::search`] or [`crate::search_with_references`].
 ///
 /// If the search was performed against a set of references, the reference is included.
 ///
 /// A `MatchGroup` can be queried for the paths of the videos that it contains.
+///
+/// The paths are shared rather than copied: groups from the same search (and the groups made
+/// from them by [`Self::dup_combinations`]) hold the same allocation for each video, however
+/// many groups it is in.
 #[derive(Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
 pub struct MatchGroup {
-    reference: Option<PathBuf>,
-    duplicates: Vec<PathBuf>,
+    reference: Option<Arc<Path>>,
+    duplicates: Vec<Arc<Path>>,
+    //The collection of each video that has one.
+    collections: BTreeMap<Arc<Path>, String>,
+    //Whether the audio agrees, for each pair of videos (smallest path first) that both have an
+    //audio fingerprint. Empty unless the search was asked to compare audio.
+    audio_agreement: BTreeMap<(Arc<Path>, Arc<Path>), bool>,
+    //The outcome of comparing the frames of each pair of videos (smallest path first) whose hashes
+    //are within the epsilon. Empty unless the search was asked to verify such pairs.
+    zero_distance: BTreeMap<(Arc<Path>, Arc<Path>), ZeroDistanceVerification>,
+    //The outcome of comparing the frames of each pair of videos (smallest path first) by their
+    //SSIM. Empty unless the search was asked to verify its matches, and pairs that could not be
+    //decoded are left out.
+    verification: BTreeMap<(Arc<Path>, Arc<Path>), VerifyReport>,
+    //The durations of the shortest and longest videos, if the search was asked to loosen its
+    //duration gate.
+    duration_spread: Option<(u32, u32)>,
+    //For each duplicate, the other references that it matched, most preferred first. Only
+    //recorded by prioritized reference searches.
+    alternate_references: BTreeMap<Arc<Path>, Vec<Arc<Path>>>,
+    //The videos in the search's protected directories.
+    protected: BTreeSet<Arc<Path>>,
+    //The speed of the second video of each pair (smallest path first) relative to the first, for
+    //pairs which match at different speeds. Stored as the bits of the f64, so that groups stay Eq
+    //and Hash. Empty unless the search was asked to match videos at different speeds.
+    speed_factors: BTreeMap<(Arc<Path>, Arc<Path>), u64>,
+    //Which videos of the group were found inside which. Empty unless a report from
+    //analyze_containment was recorded with with_containment.
+    containment: ContainmentReport,
 }
 
 #[derive(Clone, Copy, PartialEq, PartialOrd, Eq, Ord, Debug, Hash)]
@@ -18,13 +64,24 @@ pub struct TooFewEntries();
 impl MatchGroup {
     #[doc(hidden)]
     ///Create a new matchgroup by supplying the paths of the matching items.
-    pub fn new(entries: impl IntoIterator<Item = PathBuf>) -> Result<Self, TooFewEntries> {
-        let duplicates = entries.into_iter().collect::<Vec<_>>();
+    pub fn new(
+        entries: impl IntoIterator<Item = impl Into<Arc<Path>>>,
+    ) -> Result<Self, TooFewEntries> {
+        let duplicates = entries.into_iter().map(Into::into).collect::<Vec<_>>();
 
         (duplicates.len() >= 2)
             .then_some(Self {
                 reference: None,
                 duplicates,
+                collections: BTreeMap::new(),
+                audio_agreement: BTreeMap::new(),
+                zero_distance: BTreeMap::new(),
+                verification: BTreeMap::new(),
+                duration_spread: None,
+                alternate_references: BTreeMap::new(),
+                protected: BTreeSet::new(),
+                speed_factors: BTreeMap::new(),
+                containment: ContainmentReport::default(),
             })
             .ok_or(TooFewEntries())
     }
@@ -33,19 +90,187 @@ impl MatchGroup {
     ///Create a new MatchGroup by supplying the paths of the maching items, and the path of
     ///the reference video.
     pub fn new_with_reference(
-        reference: PathBuf,
-        entries: impl IntoIterator<Item = PathBuf>,
+        reference: impl Into<Arc<Path>>,
+        entries: impl IntoIterator<Item = impl Into<Arc<Path>>>,
     ) -> Result<Self, TooFewEntries> {
-        let duplicates = entries.into_iter().collect::<Vec<_>>();
+        let duplicates = entries.into_iter().map(Into::into).collect::<Vec<_>>();
 
         (!duplicates.is_empty())
             .then_some(Self {
-                reference: Some(reference),
+                reference: Some(reference.into()),
                 duplicates,
+                collections: BTreeMap::new(),
+                audio_agreement: BTreeMap::new(),
+                zero_distance: BTreeMap::new(),
+                verification: BTreeMap::new(),
+                duration_spread: None,
+                alternate_references: BTreeMap::new(),
+                protected: BTreeSet::new(),
+                speed_factors: BTreeMap::new(),
+                containment: ContainmentReport::default(),
             })
             .ok_or(TooFewEntries())
     }
 
+    //Record the collections of the videos in the group, out of the collections of every video searched.
+    pub(crate) fn with_collections(mut self, collections: &HashMap<PathBuf, String>) -> Self {
+        self.collections = self
+            .shared_paths()
+            .filter_map(|path| Some((path.clone(), collections.get(&**path)?.clone())))
+            .collect();
+        self
+    }
+
+    #[doc(hidden)]
+    ///Record which videos in the group are protected from removal.
+    #[must_use]
+    pub fn with_protected(mut self, is_protected: impl Fn(&Path) -> bool) -> Self {
+        self.protected = self
+            .shared_paths()
+            .filter(|path| is_protected(path))
+            .cloned()
+            .collect();
+        self
+    }
+
+    //Record whether the audio of each pair of videos in the group agrees, out of the audio
+    //fingerprints of every video searched.
+    pub(crate) fn with_audio_fingerprints(
+        mut self,
+        fingerprints: &HashMap<PathBuf, AudioFingerprint>,
+    ) -> Self {
+        let fingerprinted = self
+            .shared_paths()
+            .filter_map(|path| Some((path, fingerprints.get(&**path)?)))
+            .collect::<Vec<_>>();
+        self.audio_agreement = fingerprinted
+            .into_iter()
+            .tuple_combinations()
+            .map(|((p1, f1), (p2, f2))| (pair_key(p1, p2), f1.agrees(f2)))
+            .collect();
+        self
+    }
+
+    //Compare the frames of each pair of videos in the group whose hashes are at most epsilon apart,
+    //out of the hashes of every video searched. The frames of each video are only loaded once.
+    pub(crate) fn with_zero_distance_verification(
+        mut self,
+        hashes: &HashMap<PathBuf, VideoHash>,
+        epsilon: f64,
+        mut load_frames: impl FnMut(&VideoHash) -> Option<Vec<GrayImage>>,
+    ) -> Self {
+        let hashed = self
+            .shared_paths()
+            .filter_map(|path| Some((path, hashes.get(&**path)?)))
+            .collect::<Vec<_>>();
+        let close_pairs = hashed
+            .iter()
+            .tuple_combinations()
+            .filter(|((_, h1), (_, h2))| {
+                h1.hamming_distance(h2).is_ok_and(|distance| {
+                    f64::from(distance) <= epsilon * f64::from(h1.hash_size().bits())
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let mut frames = HashMap::new();
+        let mut verified = BTreeMap::new();
+        for ((p1, h1), (p2, h2)) in close_pairs {
+            for (path, hash) in [(p1, h1), (p2, h2)] {
+                frames
+                    .entry(Arc::clone(path))
+                    .or_insert_with(|| load_frames(hash));
+            }
+            let verification = compare_frames(frames[*p1].as_deref(), frames[*p2].as_deref());
+            verified.insert(pair_key(p1, p2), verification);
+        }
+        self.zero_distance = verified;
+        self
+    }
+
+    //Verify each pair of videos in the group by the SSIM of their frames. The frames of each video
+    //are only loaded once.
+    pub(crate) fn with_match_verification(
+        mut self,
+        opts: &VerifyOpts,
+        mut load_frames: impl FnMut(&Path) -> Option<Vec<GrayImage>>,
+    ) -> Self {
+        let frames = self
+            .shared_paths()
+            .filter_map(|path| Some((path, load_frames(path)?)))
+            .collect::<Vec<_>>();
+        self.verification = frames
+            .iter()
+            .tuple_combinations()
+            .filter_map(|((p1, f1), (p2, f2))| {
+                let report = compare_aligned_frames(f1, f2, opts).ok()?;
+                Some((pair_key(p1, p2), report))
+            })
+            .collect();
+        self
+    }
+
+    //Record the speed of each pair of videos in the group which match at different speeds, out of
+    //the hashes of every video searched. speed_factor gives the speed of the second video relative
+    //to the first, if they match at a different speed.
+    pub(crate) fn with_speed_factors(
+        mut self,
+        hashes: &HashMap<PathBuf, VideoHash>,
+        speed_factor: impl Fn(&VideoHash, &VideoHash) -> Option<f64>,
+    ) -> Self {
+        let hashed = self
+            .shared_paths()
+            .filter_map(|path| Some((path, hashes.get(&**path)?)))
+            .sorted_by_key(|&(path, _)| path)
+            .collect::<Vec<_>>();
+        self.speed_factors = hashed
+            .into_iter()
+            .tuple_combinations()
+            .filter_map(|((p1, h1), (p2, h2))| {
+                let factor = speed_factor(h1, h2)?;
+                Some((pair_key(p1, p2), factor.to_bits()))
+            })
+            .collect();
+        self
+    }
+
+    /// Record a report from [`Self::analyze_containment`], so that it is kept with the group and
+    /// returned by [`Self::containment`]. Edges between videos which are not in the group are left
+    /// out.
+    #[must_use]
+    pub fn with_containment(mut self, mut report: ContainmentReport) -> Self {
+        let members = self.shared_paths().cloned().collect::<HashSet<_>>();
+        report.retain(|path| members.contains(path));
+        self.containment = report;
+        self
+    }
+
+    pub(crate) fn with_duration_spread(mut self, duration_spread: Option<(u32, u32)>) -> Self {
+        self.duration_spread = duration_spread;
+        self
+    }
+
+    pub(crate) fn with_alternate_references(
+        mut self,
+        alternate_references: BTreeMap<Arc<Path>, Vec<Arc<Path>>>,
+    ) -> Self {
+        self.alternate_references = alternate_references;
+        self
+    }
+
+    //The number of different collections in the group. Videos without a collection count as one more.
+    pub(crate) fn num_collections(&self) -> usize {
+        self.contained_paths()
+            .map(|path| self.collection_of(path))
+            .collect::<BTreeSet<_>>()
+            .len()
+    }
+
+    //sort the duplicates, so that the group does not depend on the order the search found them in.
+    pub(crate) fn sort_paths(&mut self) {
+        self.duplicates.sort();
+    }
+
     /// The number of duplicate videos in this group.
     #[must_use]
     pub fn len(&self) -> usize {
@@ -60,24 +285,260 @@ impl MatchGroup {
 
     /// An iterator for the paths of the duplicates in this `MatchGroup`
     pub fn duplicates(&self) -> impl Iterator<Item = &Path> {
-        self.duplicates.iter().map(&PathBuf::as_path)
+        self.duplicates.iter().map(AsRef::as_ref)
+    }
+
+    /// The collection of the video at the given path, if its hash was given one with
+    /// [`VideoHash::with_collection`][crate::VideoHash::with_collection].
+    #[must_use]
+    pub fn collection_of(&self, path: impl AsRef<Path>) -> Option<&str> {
+        self.collections.get(path.as_ref()).map(String::as_str)
+    }
+
+    /// Whether the audio of the two videos agrees, if the search compared audio (see
+    /// [`crate::SearchOptions::audio_check`]). Returns None if the search did not, if either video
+    /// has no audio fingerprint, or if either video is not in the group.
+    #[must_use]
+    pub fn audio_agrees(&self, a: impl AsRef<Path>, b: impl AsRef<Path>) -> Option<bool> {
+        let (a, b) = (self.shared_path(a.as_ref())?, self.shared_path(b.as_ref())?);
+        self.audio_agreement.get(&pair_key(a, b)).copied()
+    }
+
+    /// How the frames of the two videos compared, if the search was asked to check pairs whose
+    /// hashes are (almost) identical (see
+    /// [`crate::SearchOptions::zero_distance_check`]). Returns None if it was not, if the hashes
+    /// of the two videos are further apart than the epsilon, or if either video is not in the
+    /// group.
+    #[must_use]
+    pub fn zero_distance_verification(
+        &self,
+        a: impl AsRef<Path>,
+        b: impl AsRef<Path>,
+    ) -> Option<ZeroDistanceVerification> {
+        let (a, b) = (self.shared_path(a.as_ref())?, self.shared_path(b.as_ref())?);
+        self.zero_distance.get(&pair_key(a, b)).copied()
+    }
+
+    /// Whether the frames of the video at the given path were found to differ from those of
+    /// another video in the group, despite their hashes being (almost) identical. Such a video is
+    /// probably not a duplicate, and should not be deleted automatically.
+    #[must_use]
+    pub fn frames_differ(&self, path: impl AsRef<Path>) -> bool {
+        let path = path.as_ref();
+        self.zero_distance.iter().any(|((a, b), verification)| {
+            *verification == ZeroDistanceVerification::FramesDiffer
+                && (&**a == path || &**b == path)
+        })
+    }
+
+    /// The videos in the group which are inside one of the search's protected directories (see
+    /// [`crate::SearchOptions::protected`]), in order of their paths. They should never be removed,
+    /// whichever video of the group is kept.
+    pub fn protected_members(&self) -> impl Iterator<Item = &Path> {
+        self.protected.iter().map(AsRef::as_ref)
+    }
+
+    /// Whether the video at the given path is one of [`Self::protected_members`].
+    #[must_use]
+    pub fn is_protected(&self, path: impl AsRef<Path>) -> bool {
+        self.protected.contains(path.as_ref())
+    }
+
+    /// The outcome of comparing the frames of the two videos by their SSIM, if the search was asked
+    /// to verify its matches (see [`crate::SearchOptions::verify_matches`]). Returns None if it was
+    /// not, if either video could not be decoded, or if either video is not in the group.
+    #[must_use]
+    pub fn match_verification(
+        &self,
+        a: impl AsRef<Path>,
+        b: impl AsRef<Path>,
+    ) -> Option<VerifyReport> {
+        let (a, b) = (self.shared_path(a.as_ref())?, self.shared_path(b.as_ref())?);
+        self.verification.get(&pair_key(a, b)).copied()
+    }
+
+    /// How many times as fast the video at `b` plays as the video at `a`, if the search matched
+    /// them at different speeds (see [`crate::SearchOptions::speed_variants`]), so `2.0` if `b` is
+    /// a copy of `a` at double speed. Returns None if the videos match at the same speed, if they
+    /// do not match each other directly, or if either video is not in the group.
+    #[must_use]
+    pub fn speed_factor(&self, a: impl AsRef<Path>, b: impl AsRef<Path>) -> Option<f64> {
+        let (a, b) = (self.shared_path(a.as_ref())?, self.shared_path(b.as_ref())?);
+        let factor = f64::from_bits(*self.speed_factors.get(&pair_key(a, b))?);
+        Some(if a <= b { factor } else { 1.0 / factor })
+    }
+
+    /// Find which videos of the group were cut from which, with the default
+    /// [`ContainmentOpts`]. See [`Self::analyze_containment_with_opts`].
+    #[must_use]
+    pub fn analyze_containment(&self, frame_source: &impl FrameSource) -> ContainmentReport {
+        self.analyze_containment_with_opts(frame_source, &ContainmentOpts::default())
+    }
+
+    /// Find which videos of the group were cut from which (see the
+    /// [`containment`][crate::containment] module). Every pair of videos whose durations are
+    /// different enough is analyzed, and the windows of each video are hashed by `frame_source`
+    /// at most once. This decodes every video in the group again, so is much slower than the
+    /// search which found the group.
+    ///
+    /// The report is not kept with the group unless it is recorded with [`Self::with_containment`].
+    #[must_use]
+    pub fn analyze_containment_with_opts(
+        &self,
+        frame_source: &impl FrameSource,
+        opts: &ContainmentOpts,
+    ) -> ContainmentReport {
+        analyze_containment(self.shared_paths(), frame_source, opts)
+    }
+
+    /// Which videos of the group were found inside which, if a report was recorded with
+    /// [`Self::with_containment`]. Empty otherwise.
+    #[must_use]
+    pub const fn containment(&self) -> &ContainmentReport {
+        &self.containment
+    }
+
+    /// Whether the video at the given path failed verification against another video in the
+    /// group, so that it is probably not a duplicate of it.
+    #[must_use]
+    pub fn failed_verification(&self, path: impl AsRef<Path>) -> bool {
+        let path = path.as_ref();
+        self.verification
+            .iter()
+            .any(|((a, b), report)| !report.passed() && (&**a == path || &**b == path))
+    }
+
+    /// The group without the pairs of videos which failed verification (see
+    /// [`Self::match_verification`]), or None if too few videos are left.
+    ///
+    /// For a group with a reference, the duplicates which failed against the reference are
+    /// removed. Otherwise each video is removed if it failed against any video kept before it.
+    /// Everything recorded about the videos which are kept is kept too.
+    #[must_use]
+    pub fn without_failed_verifications(&self) -> Option<Self> {
+        let failed = |a: &Arc<Path>, b: &Arc<Path>| {
+            self.verification
+                .get(&pair_key(a, b))
+                .is_some_and(|report| !report.passed())
+        };
+        let duplicates = match &self.reference {
+            Some(reference) => self
+                .duplicates
+                .iter()
+                .filter(|dup| !failed(reference, dup))
+                .cloned()
+                .collect::<Vec<_>>(),
+            None => {
+                let mut kept: Vec<Arc<Path>> = vec![];
+                for dup in &self.duplicates {
+                    if !kept.iter().any(|kept| failed(kept, dup)) {
+                        kept.push(dup.clone());
+                    }
+                }
+                kept
+            }
+        };
+        let min_duplicates = if self.reference.is_some() { 1 } else { 2 };
+        if duplicates.len() < min_duplicates {
+            return None;
+        }
+
+        let mut ret = Self {
+            duplicates,
+            ..self.clone()
+        };
+        let kept = ret.shared_paths().cloned().collect::<HashSet<_>>();
+        let both_kept = |(a, b): &(Arc<Path>, Arc<Path>)| kept.contains(a) && kept.contains(b);
+        ret.collections.retain(|path, _| kept.contains(path));
+        ret.audio_agreement.retain(|pair, _| both_kept(pair));
+        ret.zero_distance.retain(|pair, _| both_kept(pair));
+        ret.verification.retain(|pair, _| both_kept(pair));
+        ret.speed_factors.retain(|pair, _| both_kept(pair));
+        ret.containment.retain(|path| kept.contains(path));
+        ret.alternate_references
+            .retain(|path, _| kept.contains(path));
+        ret.protected.retain(|path| kept.contains(path));
+        Some(ret)
+    }
+
+    /// The durations in seconds of the shortest and longest videos in the group (including the
+    /// reference, if any). Only recorded by searches whose
+    /// [`SearchOptions::duration_gate`][crate::SearchOptions::duration_gate] is not
+    /// [`DurationGate::Strict`][crate::DurationGate::Strict], where a group of videos of very
+    /// different lengths may be worth treating differently.
+    #[must_use]
+    pub const fn duration_spread(&self) -> Option<(u32, u32)> {
+        self.duration_spread
+    }
+
+    /// The references other than [`Self::reference`] that the duplicate at the given path also
+    /// matched, most preferred first. Only recorded by
+    /// [`crate::search_with_references_prioritized`], and empty for any other search.
+    pub fn alternate_references(&self, path: impl AsRef<Path>) -> impl Iterator<Item = &Path> {
+        self.alternate_references
+            .get(path.as_ref())
+            .into_iter()
+            .flatten()
+            .map(AsRef::as_ref)
     }
 
     /// All the paths in this `MatchGroup`, regardless
     /// of whether the path is a reference or not
     pub fn contained_paths(&self) -> impl Iterator<Item = &Path> {
-        // a scratch iterator to return the reference video, if there is one.
-        let mut done = false;
-        let ref_as_iter = std::iter::from_fn(move || {
-            if done {
-                None
+        self.shared_paths().map(AsRef::as_ref)
+    }
+
+    fn shared_paths(&self) -> impl Iterator<Item = &Arc<Path>> {
+        self.duplicates.iter().chain(&self.reference)
+    }
+
+    fn shared_path(&self, path: &Path) -> Option<&Arc<Path>> {
+        self.shared_paths().find(|&shared| &**shared == path)
+    }
+
+    /// Roughly how many bytes of memory the given groups use. A path shared between several of
+    /// the groups is only counted once, as it is only stored once.
+    pub fn approx_memory_usage<'a>(groups: impl IntoIterator<Item = &'a Self>) -> usize {
+        let mut seen = HashSet::new();
+        let mut path_bytes = |path: &Arc<Path>| {
+            let pointer = size_of::<Arc<Path>>();
+            if seen.insert(Arc::as_ptr(path)) {
+                //the reference counts are stored alongside the path.
+                pointer + 2 * size_of::<usize>() + path.as_os_str().len()
             } else {
-                done = true;
-                self.reference.as_deref()
+                pointer
             }
-        });
+        };
 
-        self.duplicates().chain(ref_as_iter)
+        let mut total = 0;
+        for group in groups {
+            total += size_of::<Self>();
+            total += group.shared_paths().map(&mut path_bytes).sum::<usize>();
+            total +=
+                (group.duplicates.capacity() - group.duplicates.len()) * size_of::<Arc<Path>>();
+            for (path, collection) in &group.collections {
+                total += path_bytes(path) + size_of::<String>() + collection.capacity();
+            }
+            for (a, b) in group.audio_agreement.keys() {
+                total += path_bytes(a) + path_bytes(b) + size_of::<bool>();
+            }
+            for (a, b) in group.zero_distance.keys() {
+                total += path_bytes(a) + path_bytes(b) + size_of::<ZeroDistanceVerification>();
+            }
+            for (a, b) in group.verification.keys() {
+                total += path_bytes(a) + path_bytes(b) + size_of::<VerifyReport>();
+            }
+            for (a, b) in group.speed_factors.keys() {
+                total += path_bytes(a) + path_bytes(b) + size_of::<u64>();
+            }
+            total += group.containment.approx_memory_usage(&mut path_bytes);
+            for (path, alternates) in &group.alternate_references {
+                total += path_bytes(path) + size_of::<Vec<Arc<Path>>>();
+                total += alternates.iter().map(&mut path_bytes).sum::<usize>();
+            }
+            total += group.protected.iter().map(&mut path_bytes).sum::<usize>();
+        }
+        total
     }
 
     /// Returns all combinations of duplicate videos in this group.
@@ -104,3 +565,8 @@ impl MatchGroup {
         }
     }
 }
+
+fn pair_key(a: &Arc<Path>, b: &Arc<Path>) -> (Arc<Path>, Arc<Path>) {
+    let (a, b) = if a <= b { (a, b) } else { (b, a) };
+    (a.clone(), b.clone())
+}
@@ -1 +1,2 @@
+pub mod falsepos_filter;
 pub mod match_group;
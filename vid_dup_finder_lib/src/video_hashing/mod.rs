@@ -1,8 +1,39 @@
+#[cfg(feature = "async")]
+mod async_hashing;
+pub mod audio_fingerprint;
+pub mod cancellation;
+pub mod clip_search;
+pub mod compact_hash;
+pub mod containment;
+pub mod distance_matrix;
+pub mod duplicate_detector;
+pub mod environment;
+#[cfg(any(feature = "test-util", test))]
+pub mod fixtures;
+pub mod frame_normalization;
 pub mod hash_creation_error_kind;
+pub mod hash_stats;
+pub mod init;
+mod match_graph;
+pub mod match_verification;
 pub mod matches;
-mod search_algorithm;
+pub mod non_match;
+pub mod packed_hash;
+pub mod pick_best;
+pub mod portable_hash;
+pub mod savings;
+pub(crate) mod search_algorithm;
+pub mod search_options;
+pub mod search_plan;
+pub mod search_snapshot;
+pub mod search_stats;
+pub mod shared_scenes;
+pub mod speed_variants;
+pub mod thumbnails;
+pub mod two_pass;
 pub mod video_dup_finder;
 pub mod video_hash;
+pub mod zero_distance;
 
 mod dct_3d;
 
@@ -25,4 +56,40 @@ pub enum Error {
 
     #[error("Could not extract enough frames")]
     NotEnoughFrames,
+
+    /// [`crate::init`] was not called before creating a hash.
+    #[error("vid_dup_finder_lib::init must be called before creating hashes")]
+    NotInitialized,
+
+    /// The video backend is missing some of the programs or plugins that it needs.
+    #[error("Video backend is missing: {}", .0.join(", "))]
+    MissingComponents(Vec<String>),
+
+    /// The ffmpeg backend could not run the listed commands (ffmpeg and/or ffprobe), so no
+    /// videos can be hashed.
+    #[error("{} could not be run. Make sure ffmpeg is installed and on the PATH", .0.join(" and "))]
+    FfmpegMissing(Vec<String>),
+
+    /// The video is shorter than [`crate::CreationOptions::min_duration`], so it was not decoded.
+    /// Hashing the same file with the same options always fails in this way.
+    #[error("Video is too short to hash ({}s)", .duration.as_secs_f64())]
+    TooShort { duration: std::time::Duration },
+
+    /// The [`crate::CreationOptions`] cannot be used to create hashes.
+    #[error("Invalid creation options: {0}")]
+    InvalidOptions(#[from] video_hash_builder::CreationOptionsError),
+
+    /// A file was chosen from a [`crate::MatchGroup`] which is not part of it (see
+    /// [`crate::KeepPolicy::Custom`]).
+    #[error("{} is not part of the group", .0.display())]
+    NotInGroup(std::path::PathBuf),
+
+    /// The operation was stopped by its [`crate::CancellationToken`] before it finished.
+    #[error("Cancelled")]
+    Cancelled,
+
+    /// The container of the video does not record its duration, and it could not be measured
+    /// within [`crate::CreationOptions::max_measured_duration`].
+    #[error("Video has no recorded duration, and it could not be measured")]
+    UnknownDuration,
 }
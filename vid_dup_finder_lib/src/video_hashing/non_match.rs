@@ -0,0 +1,329 @@
+//! Explaining why a search did not match two videos.
+//!
+//! When a search misses a pair of videos that are known to be duplicates, [`explain_non_match`]
+//! gives the first check of the search that the pair failed, so that the options can be adjusted
+//! (for instance, a pair that fails [`NonMatchReason::DurationGate`] needs a looser
+//! [`SearchOptions::duration_gate`], and one that fails [`NonMatchReason::DistanceAboveTolerance`]
+//! needs a higher tolerance).
+use std::fmt;
+
+use super::search_algorithm::{incompatibility, MatchThreshold};
+use crate::{SearchOptions, VideoHash};
+
+/// A difference between two hashes which means that they can never match, whatever the search
+/// options. The hashes must be created again with the same options to be compared.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Incompatibility {
+    /// The hashes are of different [`HashSize`][crate::HashSize]s.
+    HashSize,
+    /// The frames were normalized differently (see [`crate::Normalization`]).
+    Normalization,
+    /// The frames were taken from different parts of the videos (see [`crate::Coverage`]).
+    Coverage,
+    /// The frames were sampled over different windows (see [`crate::SamplingWindow`]).
+    SamplingWindow,
+    /// The frames of one video were stretched to correct their pixel aspect ratio, and the frames
+    /// of the other were not.
+    PixelAspectRatio,
+}
+
+/// Why a search does or does not match two hashes, from [`explain_non_match`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NonMatchReason {
+    /// The hashes can never be compared.
+    IncompatibleHashes { incompatibility: Incompatibility },
+    /// The durations of the videos are too different for them to be compared. `delta` is the
+    /// difference between them, in seconds.
+    DurationGate { delta: u32 },
+    /// The pair is suppressed by the [`SearchOptions::falsepos_filter`] or a
+    /// [`crate::ConstraintRule::NeverGroupWithin`].
+    Suppressed,
+    /// Both videos are short, and the [`SearchOptions::short_video_policy`] excludes short videos.
+    ShortVideosExcluded,
+    /// The audio of the videos does not agree (see [`SearchOptions::audio_check`]).
+    AudioDisagrees,
+    /// The videos have too different an amount of black in their frames, or too little that is
+    /// not black (see [`SearchOptions::active_area`]). `a` and `b` are the active areas of the
+    /// first and second video, as fractions of their frames.
+    ActiveAreaMismatch { a: f64, b: f64 },
+    /// Too few of the bits of the hashes are reliable to compare them (see
+    /// [`SearchOptions::ignore_unreliable_bits`]). `considered` of the `bits` bits were reliable.
+    TooFewReliableBits { considered: u32, bits: u32 },
+    /// The hashes are further apart than the tolerance. Both are normalized distances, in the
+    /// range 0..=1, and the tolerance is that applied to this pair (which is lower for short
+    /// videos, depending on the [`SearchOptions::short_video_policy`]).
+    DistanceAboveTolerance { distance: f64, tolerance: f64 },
+    /// The hashes match. If a search did not group the videos together, it was for some other
+    /// reason, such as the [`SearchOptions::grouping`] or the [`SearchOptions::constraints`] on
+    /// which groups are reported.
+    Matches,
+}
+
+impl fmt::Display for Incompatibility {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let property = match self {
+            Self::HashSize => "hash sizes",
+            Self::Normalization => "normalizations",
+            Self::Coverage => "coverages",
+            Self::SamplingWindow => "sampling windows",
+            Self::PixelAspectRatio => "pixel aspect ratio corrections",
+        };
+        write!(f, "different {property}")
+    }
+}
+
+impl fmt::Display for NonMatchReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::IncompatibleHashes { incompatibility } => {
+                write!(f, "incompatible hashes ({incompatibility})")
+            }
+            Self::DurationGate { delta } => {
+                write!(f, "durations are {delta}s apart, outside the duration gate")
+            }
+            Self::Suppressed => write!(f, "suppressed as a false positive or by a constraint"),
+            Self::ShortVideosExcluded => write!(f, "both videos are short, and are excluded"),
+            Self::AudioDisagrees => write!(f, "audio does not agree"),
+            Self::ActiveAreaMismatch { a, b } => write!(
+                f,
+                "active areas {:.0}% and {:.0}% are too different or too small",
+                a * 100.0,
+                b * 100.0
+            ),
+            Self::TooFewReliableBits { considered, bits } => {
+                write!(f, "only {considered} of {bits} bits are reliable")
+            }
+            Self::DistanceAboveTolerance {
+                distance,
+                tolerance,
+            } => write!(
+                f,
+                "distance {distance:.3} is above tolerance {tolerance:.3}"
+            ),
+            Self::Matches => write!(f, "the hashes match"),
+        }
+    }
+}
+
+/// Why a search with the given options would not match `a` and `b`, or
+/// [`NonMatchReason::Matches`] if it would. When a pair fails several checks, only the first is
+/// given, in the order of [`NonMatchReason`].
+///
+/// The durations are checked as they are when searching without references. Searches against
+/// references compare a narrower range of durations (see [`crate::DurationGate::Strict`]).
+#[must_use]
+pub fn explain_non_match(a: &VideoHash, b: &VideoHash, opts: &SearchOptions) -> NonMatchReason {
+    if let Some(incompatibility) = incompatibility(a, b) {
+        return NonMatchReason::IncompatibleHashes { incompatibility };
+    }
+
+    let (shorter, longer) = if a.duration() <= b.duration() {
+        (a.duration(), b.duration())
+    } else {
+        (b.duration(), a.duration())
+    };
+    if longer > opts.candidate_gate().max_comparable(shorter) {
+        return NonMatchReason::DurationGate {
+            delta: longer - shorter,
+        };
+    }
+
+    if opts.is_suppressed(a.src_path(), b.src_path()) {
+        return NonMatchReason::Suppressed;
+    }
+
+    MatchThreshold::new(opts).explain(a, b)
+}
+
+#[cfg(test)]
+mod test {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+    use crate::{
+        AudioCheck, AudioFingerprint, Coverage, DurationGate, FalseposFilter, HashSize,
+        ShortVideoPolicy, Tolerance,
+    };
+
+    fn pair() -> (VideoHash, VideoHash) {
+        let mut rng = StdRng::seed_from_u64(3);
+        let a = VideoHash::random_hash(&mut rng)
+            .with_duration(60)
+            .with_src_path("a.mp4");
+        let b = a.with_flipped_bits(0..4).with_src_path("b.mp4");
+        (a, b)
+    }
+
+    #[test]
+    fn test_matching_pairs() {
+        let (a, b) = pair();
+        let opts = SearchOptions::default();
+        assert_eq!(explain_non_match(&a, &b, &opts), NonMatchReason::Matches);
+        assert_eq!(explain_non_match(&b, &a, &opts), NonMatchReason::Matches);
+    }
+
+    #[test]
+    fn test_incompatible_hashes() {
+        let mut rng = StdRng::seed_from_u64(4);
+        let (a, _) = pair();
+        let opts = SearchOptions::default();
+
+        let other_size = VideoHash::random_hash_with_size(&mut rng, HashSize::Ten)
+            .with_duration(60)
+            .with_src_path("b.mp4");
+        assert_eq!(
+            explain_non_match(&a, &other_size, &opts),
+            NonMatchReason::IncompatibleHashes {
+                incompatibility: Incompatibility::HashSize
+            }
+        );
+
+        let other_coverage = a
+            .clone()
+            .with_coverage(Coverage::EvenlySpaced { segments: 5 })
+            .with_src_path("b.mp4");
+        assert_eq!(
+            explain_non_match(&a, &other_coverage, &opts),
+            NonMatchReason::IncompatibleHashes {
+                incompatibility: Incompatibility::Coverage
+            }
+        );
+    }
+
+    #[test]
+    fn test_duration_gate() {
+        let (a, b) = pair();
+        let b = b.with_duration(90);
+        assert_eq!(
+            explain_non_match(&a, &b, &SearchOptions::default()),
+            NonMatchReason::DurationGate { delta: 30 }
+        );
+        assert_eq!(
+            explain_non_match(&b, &a, &SearchOptions::default()),
+            NonMatchReason::DurationGate { delta: 30 }
+        );
+
+        let opts = SearchOptions {
+            duration_gate: DurationGate::Loose { max_ratio: 2.0 },
+            ..SearchOptions::default()
+        };
+        assert_eq!(explain_non_match(&a, &b, &opts), NonMatchReason::Matches);
+    }
+
+    #[test]
+    fn test_suppressed() {
+        let (a, b) = pair();
+        let mut falsepos_filter = FalseposFilter::new();
+        falsepos_filter.insert("b.mp4", "a.mp4");
+        let opts = SearchOptions {
+            falsepos_filter,
+            ..SearchOptions::default()
+        };
+        assert_eq!(explain_non_match(&a, &b, &opts), NonMatchReason::Suppressed);
+    }
+
+    #[test]
+    fn test_short_videos_excluded() {
+        let (a, b) = pair();
+        let (a, b) = (a.with_short_video(true), b.with_short_video(true));
+        let opts = SearchOptions {
+            short_video_policy: ShortVideoPolicy::Exclude,
+            ..SearchOptions::default()
+        };
+        assert_eq!(
+            explain_non_match(&a, &b, &opts),
+            NonMatchReason::ShortVideosExcluded
+        );
+
+        //only when both are short.
+        let a = a.with_short_video(false);
+        assert_eq!(explain_non_match(&a, &b, &opts), NonMatchReason::Matches);
+    }
+
+    #[test]
+    fn test_audio_disagrees() {
+        let (a, b) = pair();
+        let a = a.with_audio_fingerprint(Some(AudioFingerprint::from_slices(vec![0; 16])));
+        let b = b.with_audio_fingerprint(Some(AudioFingerprint::from_slices(vec![u32::MAX; 16])));
+        let opts = SearchOptions {
+            audio_check: AudioCheck::RequireAgreement,
+            ..SearchOptions::default()
+        };
+        assert_eq!(
+            explain_non_match(&a, &b, &opts),
+            NonMatchReason::AudioDisagrees
+        );
+        assert_eq!(
+            explain_non_match(&a, &b, &SearchOptions::default()),
+            NonMatchReason::Matches
+        );
+    }
+
+    #[test]
+    fn test_too_few_reliable_bits() {
+        let (mut a, mut b) = pair();
+        let unreliable = VideoHash::empty_hash("").hash;
+        a.reliable_bits = Some(unreliable.clone());
+        b.reliable_bits = Some(unreliable);
+        let opts = SearchOptions {
+            ignore_unreliable_bits: true,
+            ..SearchOptions::default()
+        };
+        assert_eq!(
+            explain_non_match(&a, &b, &opts),
+            NonMatchReason::TooFewReliableBits {
+                considered: 0,
+                bits: HashSize::default().bits()
+            }
+        );
+    }
+
+    #[test]
+    fn test_distance_above_tolerance() {
+        let (a, _) = pair();
+        let bits = a.hash_size().bits();
+        let b = a.with_flipped_bits(0..bits / 4).with_src_path("b.mp4");
+        let opts = SearchOptions::new(Tolerance::new(0.2).expect("valid tolerance"));
+
+        //the tolerance is that of the whole number of bits that may differ.
+        let tolerance =
+            |tolerance: f64| f64::from((tolerance * f64::from(bits)) as u32) / f64::from(bits);
+        assert_eq!(
+            explain_non_match(&a, &b, &opts),
+            NonMatchReason::DistanceAboveTolerance {
+                distance: f64::from(bits / 4) / f64::from(bits),
+                tolerance: tolerance(0.2),
+            }
+        );
+
+        //short videos are compared with a lower tolerance.
+        let (a, b) = (a.with_short_video(true), b.with_short_video(true));
+        let opts = SearchOptions {
+            short_video_policy: ShortVideoPolicy::Tolerance(0.1),
+            ..opts
+        };
+        assert_eq!(
+            explain_non_match(&a, &b, &opts),
+            NonMatchReason::DistanceAboveTolerance {
+                distance: f64::from(bits / 4) / f64::from(bits),
+                tolerance: tolerance(0.1),
+            }
+        );
+    }
+
+    #[test]
+    fn test_agrees_with_search() {
+        let mut rng = StdRng::seed_from_u64(5);
+        let original = VideoHash::random_hash(&mut rng).with_duration(60);
+        let opts = SearchOptions::default();
+        let threshold = MatchThreshold::new(&opts);
+        for flipped in 0..original.hash_size().bits() / 2 {
+            let copy = original.with_flipped_bits(0..flipped);
+            assert_eq!(
+                explain_non_match(&original, &copy, &opts) == NonMatchReason::Matches,
+                threshold.is_match(&original, &copy),
+                "with {flipped} bits flipped"
+            );
+        }
+    }
+}
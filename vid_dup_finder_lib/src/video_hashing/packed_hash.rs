@@ -0,0 +1,341 @@
+//! A [`serde`] representation of a hash for storing many hashes together, such as in a cache.
+//!
+//! A [`PackedHash`] holds everything that the [`serde`] representation of a [`VideoHash`] holds,
+//! except for its path and its coarse signature:
+//!
+//! * The path is left for the container to store, so that it can share the directories of its
+//!   hashes between them instead of storing every path in full.
+//! * The coarse signature is taken from the bits again when the hash is unpacked.
+//! * The bits (and the reliability mask) are stored as bytes, and only as many bytes as the size
+//!   of the hash needs. A [`VideoHash`] serializes its bits as space for the largest [`HashSize`],
+//!   so a hash of the default size would otherwise take 128 bytes instead of 27.
+//!
+//! Like the [`serde`] representation of [`VideoHash`], and unlike the
+//! [portable][crate::portable_hash] and [compact][crate::compact_hash] formats, this may change
+//! between releases. Containers should version what they write.
+
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::{
+    audio_fingerprint::AudioFingerprint,
+    portable_hash::{from_bytes, to_bytes},
+    speed_variants::SpeedVariant,
+    video_hash::{coarse_signature, HashCrop, InvalidVideoHash},
+};
+use crate::{
+    ColorRange, Coverage, DurationSource, EnvironmentDigest, HashSize, Normalization,
+    SamplingWindow, VideoHash,
+};
+
+/// A [`VideoHash`] without its path, with its bits packed into bytes. See the
+/// [module documentation][crate::packed_hash].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "UncheckedPackedHash")]
+pub struct PackedHash {
+    bits: Vec<u8>,
+    hash_size: HashSize,
+    duration: u32,
+    short_video: bool,
+    video_stream: usize,
+    window_start: Option<Duration>,
+    frame_hashes: Option<Vec<u64>>,
+    leading_black: Duration,
+    reliable_bits: Option<Vec<u8>>,
+    truncated_decode: bool,
+    duration_corrected: bool,
+    motion_crop_fallback: bool,
+    normalization: Normalization,
+    audio_fingerprint: Option<AudioFingerprint>,
+    coverage: Coverage,
+    sampling_window: SamplingWindow,
+    pixel_aspect_ratio: Option<(u32, u32)>,
+    source_color_range: Option<ColorRange>,
+    deinterlaced: bool,
+    environment: Option<EnvironmentDigest>,
+    active_area: Option<u16>,
+    speed_variants: Vec<SpeedVariant>,
+    crop: Option<HashCrop>,
+    duration_source: DurationSource,
+}
+
+//The fields of a PackedHash as they are deserialized, before they are checked to match its size.
+#[derive(Deserialize)]
+struct UncheckedPackedHash {
+    bits: Vec<u8>,
+    hash_size: HashSize,
+    duration: u32,
+    short_video: bool,
+    video_stream: usize,
+    window_start: Option<Duration>,
+    frame_hashes: Option<Vec<u64>>,
+    leading_black: Duration,
+    reliable_bits: Option<Vec<u8>>,
+    truncated_decode: bool,
+    duration_corrected: bool,
+    motion_crop_fallback: bool,
+    normalization: Normalization,
+    audio_fingerprint: Option<AudioFingerprint>,
+    coverage: Coverage,
+    sampling_window: SamplingWindow,
+    pixel_aspect_ratio: Option<(u32, u32)>,
+    source_color_range: Option<ColorRange>,
+    deinterlaced: bool,
+    environment: Option<EnvironmentDigest>,
+    active_area: Option<u16>,
+    speed_variants: Vec<SpeedVariant>,
+    crop: Option<HashCrop>,
+    duration_source: DurationSource,
+}
+
+impl TryFrom<UncheckedPackedHash> for PackedHash {
+    type Error = InvalidVideoHash;
+
+    fn try_from(unchecked: UncheckedPackedHash) -> Result<Self, Self::Error> {
+        let hash_size = unchecked.hash_size;
+        let bytes = hash_size.bits().div_ceil(8) as usize;
+        InvalidVideoHash::check("bits", hash_size, bytes, unchecked.bits.len())?;
+        if let Some(reliable_bits) = &unchecked.reliable_bits {
+            InvalidVideoHash::check("reliable_bits", hash_size, bytes, reliable_bits.len())?;
+        }
+        for variant in &unchecked.speed_variants {
+            InvalidVideoHash::check(
+                "speed_variants",
+                hash_size,
+                hash_size.qwords(),
+                variant.bits.len(),
+            )?;
+        }
+
+        let UncheckedPackedHash {
+            bits,
+            hash_size,
+            duration,
+            short_video,
+            video_stream,
+            window_start,
+            frame_hashes,
+            leading_black,
+            reliable_bits,
+            truncated_decode,
+            duration_corrected,
+            motion_crop_fallback,
+            normalization,
+            audio_fingerprint,
+            coverage,
+            sampling_window,
+            pixel_aspect_ratio,
+            source_color_range,
+            deinterlaced,
+            environment,
+            active_area,
+            speed_variants,
+            crop,
+            duration_source,
+        } = unchecked;
+        Ok(Self {
+            bits,
+            hash_size,
+            duration,
+            short_video,
+            video_stream,
+            window_start,
+            frame_hashes,
+            leading_black,
+            reliable_bits,
+            truncated_decode,
+            duration_corrected,
+            motion_crop_fallback,
+            normalization,
+            audio_fingerprint,
+            coverage,
+            sampling_window,
+            pixel_aspect_ratio,
+            source_color_range,
+            deinterlaced,
+            environment,
+            active_area,
+            speed_variants,
+            crop,
+            duration_source,
+        })
+    }
+}
+
+impl PackedHash {
+    /// The hash, with its path set to `src_path`.
+    #[must_use]
+    pub fn into_hash(self, src_path: impl AsRef<Path>) -> VideoHash {
+        let Self {
+            bits,
+            hash_size,
+            duration,
+            short_video,
+            video_stream,
+            window_start,
+            frame_hashes,
+            leading_black,
+            reliable_bits,
+            truncated_decode,
+            duration_corrected,
+            motion_crop_fallback,
+            normalization,
+            audio_fingerprint,
+            coverage,
+            sampling_window,
+            pixel_aspect_ratio,
+            source_color_range,
+            deinterlaced,
+            environment,
+            active_area,
+            speed_variants,
+            crop,
+            duration_source,
+        } = self;
+
+        let hash = from_bytes(&bits, hash_size);
+        let coarse = Some(coarse_signature(&hash, hash_size));
+        VideoHash {
+            hash,
+            hash_size,
+            src_path: PathBuf::from(src_path.as_ref()),
+            duration,
+            short_video,
+            coarse,
+            video_stream,
+            window_start,
+            frame_hashes,
+            leading_black,
+            collection: None,
+            reliable_bits: reliable_bits.map(|bits| from_bytes(&bits, hash_size)),
+            truncated_decode,
+            duration_corrected,
+            motion_crop_fallback,
+            normalization,
+            audio_fingerprint,
+            coverage,
+            sampling_window,
+            pixel_aspect_ratio,
+            source_color_range,
+            deinterlaced,
+            environment,
+            active_area,
+            speed_variants,
+            crop,
+            duration_source,
+        }
+    }
+}
+
+impl VideoHash {
+    /// This hash in the packed representation described in the
+    /// [`packed_hash`][crate::packed_hash] module. The path is left out, and must be given back
+    /// to [`PackedHash::into_hash`].
+    #[must_use]
+    pub fn to_packed(&self) -> PackedHash {
+        PackedHash {
+            bits: to_bytes(&self.hash, self.hash_size),
+            hash_size: self.hash_size,
+            duration: self.duration,
+            short_video: self.short_video,
+            video_stream: self.video_stream,
+            window_start: self.window_start,
+            frame_hashes: self.frame_hashes.clone(),
+            leading_black: self.leading_black,
+            reliable_bits: self
+                .reliable_bits
+                .as_ref()
+                .map(|bits| to_bytes(bits, self.hash_size)),
+            truncated_decode: self.truncated_decode,
+            duration_corrected: self.duration_corrected,
+            motion_crop_fallback: self.motion_crop_fallback,
+            normalization: self.normalization,
+            audio_fingerprint: self.audio_fingerprint.clone(),
+            coverage: self.coverage,
+            sampling_window: self.sampling_window,
+            pixel_aspect_ratio: self.pixel_aspect_ratio,
+            source_color_range: self.source_color_range,
+            deinterlaced: self.deinterlaced,
+            environment: self.environment,
+            active_area: self.active_area,
+            speed_variants: self.speed_variants.clone(),
+            crop: self.crop,
+            duration_source: self.duration_source,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+    use crate::{Crop, EnvironmentInfo, SpeedFactor};
+
+    #[test]
+    fn test_round_trip() {
+        let mut rng = StdRng::seed_from_u64(1);
+        for hash_size in HashSize::ALL {
+            let hash = VideoHash::random_hash_with_size(&mut rng, hash_size)
+                .with_duration(61)
+                .with_raw_frame_hashes(vec![1, 2, 3])
+                .with_source_color_range(Some(ColorRange::Limited))
+                .with_deinterlaced(true)
+                .with_environment(Some(
+                    EnvironmentInfo::new("ffmpeg", [("ffmpeg", "6.1")]).digest(),
+                ))
+                .with_active_area(Some(0.5))
+                .with_raw_crop(Crop::from_edge_offsets((640, 480), 0, 0, 60, 60));
+            let reliable = VideoHash::random_hash_with_size(&mut rng, hash_size);
+            let hash = hash.with_speed_variant(SpeedFactor::Double, &reliable);
+            let hash = VideoHash {
+                reliable_bits: Some(reliable.hash),
+                ..hash
+            };
+
+            let packed = hash.to_packed();
+            assert_eq!(packed.bits.len(), hash_size.bits().div_ceil(8) as usize);
+
+            let unpacked = packed.into_hash("/videos/cat.mp4");
+            assert_eq!(unpacked, hash.with_src_path("/videos/cat.mp4"));
+            assert_eq!(unpacked.coarse, hash.coarse);
+        }
+    }
+
+    #[test]
+    fn test_packed_hashes_whose_parts_do_not_match_their_size_are_rejected() {
+        let mut rng = StdRng::seed_from_u64(2);
+        let hash = VideoHash::random_hash_with_size(&mut rng, HashSize::Five);
+        let hash = hash.with_speed_variant(SpeedFactor::Double, &hash.clone());
+        let json = serde_json::to_value(hash.to_packed()).expect("hashes serialize");
+        let parse = |json: &serde_json::Value| serde_json::from_value::<PackedHash>(json.clone());
+        assert_eq!(parse(&json).expect("the hash is valid"), hash.to_packed());
+
+        //a larger size than the bits were packed for.
+        let mut wrong_size = json.clone();
+        wrong_size["hash_size"] = "Ten".into();
+        let e = parse(&wrong_size)
+            .expect_err("the hash is invalid")
+            .to_string();
+        assert!(e.starts_with("The bits "), "{e}");
+
+        let mut wrong_variant = json;
+        wrong_variant["speed_variants"][0]["bits"] = serde_json::json!([0]);
+        let e = parse(&wrong_variant)
+            .expect_err("the hash is invalid")
+            .to_string();
+        assert!(e.starts_with("The speed_variants "), "{e}");
+    }
+
+    #[test]
+    fn test_coarse_signature_is_restored() {
+        let hash = VideoHash::full_hash("cat.mp4").without_coarse_signature();
+        let unpacked = hash.to_packed().into_hash("cat.mp4");
+        assert_eq!(unpacked, hash);
+        assert_eq!(unpacked.coarse, VideoHash::full_hash("cat.mp4").coarse);
+    }
+}
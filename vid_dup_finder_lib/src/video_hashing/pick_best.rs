@@ -0,0 +1,365 @@
+use std::{
+    num::NonZeroU32,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use ffmpeg_gst_wrapper::FrameReadCfgTrait;
+use image::RgbImage;
+
+use crate::{CreationOptions, Error, MatchGroup};
+
+/// A property of a video file which can be used to choose the best copy out of a group of
+/// duplicates. For every criterion, larger is better.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Criterion {
+    /// Number of pixels in each frame.
+    Resolution,
+    /// Length of the video.
+    Duration,
+    /// Size of the file on disk.
+    FileSize,
+    /// Size of a few frames after losslessly re-encoding them at a fixed resolution. Frames with
+    /// more real detail compress less well, so this favours the copy that was encoded at the
+    /// highest quality, even when every copy has the same resolution.
+    EncodedFrameSize,
+}
+
+/// The metrics measured for a single file. Only the metrics for the criteria that were asked for
+/// are measured, and the rest are left as `None`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FileMetrics {
+    pub resolution: Option<(u32, u32)>,
+    pub duration: Option<Duration>,
+    pub file_size: Option<u64>,
+    pub encoded_frame_size: Option<u64>,
+}
+
+impl FileMetrics {
+    /// The value compared for the given criterion, if it was measured.
+    pub fn score(&self, criterion: Criterion) -> Option<u128> {
+        match criterion {
+            Criterion::Resolution => self
+                .resolution
+                .map(|(width, height)| u128::from(width) * u128::from(height)),
+            Criterion::Duration => self.duration.map(|duration| duration.as_micros()),
+            Criterion::FileSize => self.file_size.map(u128::from),
+            Criterion::EncodedFrameSize => self.encoded_frame_size.map(u128::from),
+        }
+    }
+}
+
+/// The result of [`crate::pick_best`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BestPick {
+    winner: PathBuf,
+    losers: Vec<PathBuf>,
+    protected: Vec<PathBuf>,
+    metrics: Vec<(PathBuf, FileMetrics)>,
+}
+
+impl BestPick {
+    /// The best file in the group.
+    pub fn winner(&self) -> &Path {
+        &self.winner
+    }
+
+    /// Every other file in the group which is not protected, sorted by path. These are the files
+    /// that can be removed.
+    pub fn losers(&self) -> impl Iterator<Item = &Path> {
+        self.losers.iter().map(PathBuf::as_path)
+    }
+
+    /// The protected files in the group other than the winner (see
+    /// [`MatchGroup::protected_members`]), sorted by path. They are kept alongside the winner.
+    pub fn protected(&self) -> impl Iterator<Item = &Path> {
+        self.protected.iter().map(PathBuf::as_path)
+    }
+
+    /// The metrics measured for every file in the group, sorted by path.
+    pub fn metrics(&self) -> impl Iterator<Item = (&Path, &FileMetrics)> {
+        self.metrics
+            .iter()
+            .map(|(path, metrics)| (path.as_path(), metrics))
+    }
+
+    /// The metrics measured for the given file, if it is part of the group.
+    pub fn metrics_for(&self, path: impl AsRef<Path>) -> Option<&FileMetrics> {
+        let path = path.as_ref();
+        self.metrics
+            .iter()
+            .find(|(p, _)| p == path)
+            .map(|(_, metrics)| metrics)
+    }
+
+    /// True if the given file has the best value (or is tied for the best value) for a single
+    /// criterion, regardless of which file won overall.
+    pub fn is_best_for(&self, path: impl AsRef<Path>, criterion: Criterion) -> bool {
+        let best = self
+            .metrics
+            .iter()
+            .filter_map(|(_, m)| m.score(criterion))
+            .max();
+        let this = self.metrics_for(path).and_then(|m| m.score(criterion));
+        this.is_some() && this == best
+    }
+}
+
+/// Choose the best file out of a group of duplicates, by comparing `criteria` in order.
+///
+/// Files which are tied on the first criterion are compared using the next, and so on. Any tie
+/// remaining after the last criterion is broken in favour of the smallest path. The reference of
+/// the group (if any) is considered alongside the duplicates.
+///
+/// A protected file may win, but never loses: the protected files which do not win are given by
+/// [`BestPick::protected`] instead of [`BestPick::losers`].
+///
+/// Every file is probed or decoded for each criterion, so this is much slower than a search.
+/// [`crate::init`] must have been called first.
+#[cfg(any(feature = "ffmpeg_backend", feature = "gstreamer_backend"))]
+pub fn pick_best(group: &MatchGroup, criteria: &[Criterion]) -> Result<BestPick, Error> {
+    #[cfg(feature = "ffmpeg_backend")]
+    type Backend = ffmpeg_gst_wrapper::ffmpeg_impl::FrameReaderCfgFfmpeg;
+    #[cfg(all(feature = "gstreamer_backend", not(feature = "ffmpeg_backend")))]
+    type Backend = ffmpeg_gst_wrapper::gst_impl::FrameReaderCfgGst;
+
+    crate::video_hashing::init::ensure_initialized()?;
+    pick_best_with::<Backend>(group, criteria)
+}
+
+/// Measure `criteria` for every file in `group`, using the given backend.
+pub(crate) fn pick_best_with<T: FrameReadCfgTrait>(
+    group: &MatchGroup,
+    criteria: &[Criterion],
+) -> Result<BestPick, Error> {
+    let metrics = group
+        .contained_paths()
+        .map(|path| Ok((path.to_path_buf(), measure::<T>(path, criteria)?)))
+        .collect::<Result<Vec<_>, Error>>()?;
+
+    Ok(choose(metrics, criteria, |path| group.is_protected(path)))
+}
+
+fn measure<T: FrameReadCfgTrait>(
+    src_path: &Path,
+    criteria: &[Criterion],
+) -> Result<FileMetrics, Error> {
+    let mut ret = FileMetrics::default();
+
+    if criteria
+        .iter()
+        .any(|c| matches!(c, Criterion::Resolution | Criterion::Duration))
+    {
+        let info = T::from_path(src_path)
+            .get_media_info()
+            .map_err(|_e| Error::NotVideo)?;
+        ret.resolution = Some(info.resolution);
+        ret.duration = Some(info.duration);
+    }
+
+    if criteria.contains(&Criterion::FileSize) {
+        let metadata = std::fs::metadata(src_path).map_err(|e| Error::VidProc(format!("{e}")))?;
+        ret.file_size = Some(metadata.len());
+    }
+
+    if criteria.contains(&Criterion::EncodedFrameSize) {
+        ret.encoded_frame_size = Some(encoded_frame_size::<T>(src_path)?);
+    }
+
+    Ok(ret)
+}
+
+// The first few frames that would be used to build a hash, so that title credits are skipped
+// in the same way.
+fn encoded_frame_size<T: FrameReadCfgTrait>(src_path: &Path) -> Result<u64, Error> {
+    const NUM_FRAMES: usize = 4;
+
+    let builder =
+        super::video_hash_builder::build_frame_reader::<T>(src_path, CreationOptions::default())?;
+
+    let frames = builder
+        .spawn_rgb()
+        .take(NUM_FRAMES)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| Error::VidProc(format!("{e:?}")))?;
+
+    if frames.is_empty() {
+        return Err(Error::NotEnoughFrames);
+    }
+
+    frames.iter().map(png_size).sum()
+}
+
+// Resized first so that the metric does not simply repeat the resolution.
+fn png_size(frame: &RgbImage) -> Result<u64, Error> {
+    let size = NonZeroU32::try_from(500).expect("literal");
+    let resized = vid_dup_finder_common::resize_rgb::resize_img_rgb(frame, size, size);
+
+    let mut buf = std::io::Cursor::new(vec![]);
+    resized
+        .write_to(&mut buf, image::ImageFormat::Png)
+        .map_err(|e| Error::VidProc(format!("{e}")))?;
+
+    Ok(buf.into_inner().len() as u64)
+}
+
+// Keep only the files with the best value for each criterion in turn, until one remains. Any
+// remaining tie is broken by path so that the result is deterministic. Protected files are never
+// losers, whichever file wins.
+fn choose(
+    mut metrics: Vec<(PathBuf, FileMetrics)>,
+    criteria: &[Criterion],
+    is_protected: impl Fn(&Path) -> bool,
+) -> BestPick {
+    metrics.sort_by(|(p1, _), (p2, _)| p1.cmp(p2));
+
+    let mut candidates = metrics.iter().collect::<Vec<_>>();
+    for &criterion in criteria {
+        if candidates.len() <= 1 {
+            break;
+        }
+        let best = candidates.iter().map(|(_, m)| m.score(criterion)).max();
+        candidates.retain(|(_, m)| m.score(criterion) == best.flatten());
+    }
+
+    let winner = candidates[0].0.clone();
+    let (protected, losers) = metrics
+        .iter()
+        .map(|(path, _)| path)
+        .filter(|path| **path != winner)
+        .cloned()
+        .partition(|path| is_protected(path));
+
+    BestPick {
+        winner,
+        losers,
+        protected,
+        metrics,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn file(
+        name: &str,
+        resolution: (u32, u32),
+        duration_secs: u64,
+        file_size: u64,
+    ) -> (PathBuf, FileMetrics) {
+        (
+            PathBuf::from(name),
+            FileMetrics {
+                resolution: Some(resolution),
+                duration: Some(Duration::from_secs(duration_secs)),
+                file_size: Some(file_size),
+                encoded_frame_size: None,
+            },
+        )
+    }
+
+    fn unprotected(_: &Path) -> bool {
+        false
+    }
+
+    fn files() -> Vec<(PathBuf, FileMetrics)> {
+        vec![
+            file("c", (1920, 1080), 60, 100),
+            file("a", (1280, 720), 90, 300),
+            file("b", (1920, 1080), 60, 200),
+        ]
+    }
+
+    #[test]
+    fn test_first_criterion_decides() {
+        let pick = choose(
+            files(),
+            &[Criterion::Duration, Criterion::Resolution],
+            unprotected,
+        );
+        assert_eq!(pick.winner(), Path::new("a"));
+        assert_eq!(pick.losers().collect::<Vec<_>>(), ["b", "c"].map(Path::new));
+    }
+
+    #[test]
+    fn test_ties_fall_through() {
+        //b and c tie on resolution and duration, so file size decides.
+        let criteria = [
+            Criterion::Resolution,
+            Criterion::Duration,
+            Criterion::FileSize,
+        ];
+        let pick = choose(files(), &criteria, unprotected);
+        assert_eq!(pick.winner(), Path::new("b"));
+        assert_eq!(pick.losers().collect::<Vec<_>>(), ["a", "c"].map(Path::new));
+
+        //a later criterion does not override an earlier one.
+        assert!(pick.is_best_for("a", Criterion::FileSize));
+        assert!(!pick.is_best_for("a", Criterion::Resolution));
+        assert!(pick.is_best_for("c", Criterion::Resolution));
+
+        //complete ties are broken by path.
+        let pick = choose(files(), &[Criterion::Resolution], unprotected);
+        assert_eq!(pick.winner(), Path::new("b"));
+        let pick = choose(files(), &[], unprotected);
+        assert_eq!(pick.winner(), Path::new("a"));
+    }
+
+    #[test]
+    fn test_unmeasured_criteria_are_ignored() {
+        //nothing was measured for encoded frame size, so every file ties.
+        let pick = choose(
+            files(),
+            &[Criterion::EncodedFrameSize, Criterion::FileSize],
+            unprotected,
+        );
+        assert_eq!(pick.winner(), Path::new("a"));
+        assert!(!pick.is_best_for("a", Criterion::EncodedFrameSize));
+        assert_eq!(
+            pick.metrics_for("b")
+                .and_then(|m| m.score(Criterion::FileSize)),
+            Some(200)
+        );
+    }
+
+    #[test]
+    fn test_protected_files_never_lose() {
+        let criteria = [Criterion::Duration];
+        let protected = |names: &'static [&'static str]| {
+            move |path: &Path| names.iter().any(|name| path == Path::new(name))
+        };
+        let paths =
+            |paths: Vec<&Path>| paths.into_iter().map(Path::to_path_buf).collect::<Vec<_>>();
+
+        //a protected winner is still the winner.
+        let pick = choose(files(), &criteria, protected(&["a"]));
+        assert_eq!(pick.winner(), Path::new("a"));
+        assert_eq!(
+            paths(pick.losers().collect()),
+            ["b", "c"].map(PathBuf::from)
+        );
+        assert_eq!(pick.protected().count(), 0);
+
+        //a protected file which would have lost is kept alongside the winner.
+        let pick = choose(files(), &criteria, protected(&["b"]));
+        assert_eq!(pick.winner(), Path::new("a"));
+        assert_eq!(paths(pick.losers().collect()), [PathBuf::from("c")]);
+        assert_eq!(paths(pick.protected().collect()), [PathBuf::from("b")]);
+
+        //the winner is chosen regardless of which files are protected.
+        let pick = choose(files(), &criteria, protected(&["b", "c"]));
+        assert_eq!(pick.winner(), Path::new("a"));
+        assert_eq!(pick.losers().count(), 0);
+        assert_eq!(
+            paths(pick.protected().collect()),
+            ["b", "c"].map(PathBuf::from)
+        );
+
+        //when every file is protected, nothing loses.
+        let pick = choose(files(), &criteria, protected(&["a", "b", "c"]));
+        assert_eq!(pick.winner(), Path::new("a"));
+        assert_eq!(pick.losers().count(), 0);
+        assert_eq!(pick.protected().count(), 2);
+    }
+}
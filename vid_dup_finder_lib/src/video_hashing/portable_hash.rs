@@ -0,0 +1,937 @@
+//! A JSON format for exchanging hashes with other programs. Unlike the [`serde`] representation of
+//! [`VideoHash`], which may change between releases, this format is documented and versioned.
+//!
+//! # Schema (version 2)
+//! Each hash is a single JSON object:
+//!
+//! ```json
+//! {
+//!   "version": 2,
+//!   "path": "/videos/cat.mp4",
+//!   "duration_ms": 61000,
+//!   "dimensions": [6, 6, 6],
+//!   "bits": "<base64>",
+//!   "creation": {
+//!     "short_video": false,
+//!     "video_stream": 0,
+//!     "leading_black_ms": 0,
+//!     "pixel_aspect_ratio": [1, 1]
+//!   }
+//! }
+//! ```
+//!
+//! * `path` is the path of the video. Paths which are not valid unicode are converted lossily.
+//! * `duration_ms` is the duration of the video. Hashes only record whole seconds, so any
+//!   milliseconds are dropped when reading.
+//! * `dimensions` is the number of DCT bins along the time, x and y axes of the hash. All three are
+//!   the same, and must be one of the sides of a [`HashSize`].
+//! * `bits` is the hash itself, in standard base64 (with padding). Bin `(t, x, y)` is bit number
+//!   `(t * side + x) * side + y`, and bit `n` is stored in byte `n / 8` at the position of value
+//!   `1 << (n % 8)`. The last byte is padded with zeros.
+//! * `creation` summarises how the hash was created. `window_start_ms` is also present for hashes
+//!   of a window of a longer video, and `normalization` for hashes of frames which were adjusted
+//!   before hashing (see [`Normalization`]). It is an object whose `kind` is one of
+//!   `"histogram_equalize"`, `"contrast_stretch"` (which also has `low_pct` and `high_pct`) or
+//!   `"custom"`. `coverage` is present for hashes whose frames were sampled from more than the
+//!   start of the video (see [`Coverage`]). It is an object whose `kind` is `"start_middle_end"` or
+//!   `"evenly_spaced"` (which also has `segments`). `sampling_window` is present for hashes whose
+//!   frames were not sampled from the default window (see [`SamplingWindow`]). It is an object
+//!   with `skip_forward_ms` and `duration_ms`. `pixel_aspect_ratio` is present for hashes whose
+//!   frames were stretched by their pixel aspect ratio before cropping (see
+//!   [`VideoHash::pixel_aspect_ratio`]), as `[width, height]`. Hashes without it were created
+//!   from unstretched frames, and never match hashes with it. `color_range` is present for hashes
+//!   of videos whose backend reported the range they were stored in (see
+//!   [`VideoHash::source_color_range`]), as `"limited"` or `"full"`. It is only a record, as frames
+//!   are always decoded to full range before hashing. `deinterlaced` is present, as `true`, for
+//!   hashes whose frames were deinterlaced before hashing (see [`VideoHash::deinterlaced`]).
+//!   `environment` is present for hashes which recorded the decoders that created them (see
+//!   [`VideoHash::environment`]), as a 16 digit hexadecimal string. It is only a record, and does
+//!   not affect matching. `active_area_permille` is present for hashes which recorded how much of
+//!   their frames was not black (see [`VideoHash::active_area`]), in thousandths from 0 to 1000.
+//!   `crop` is present for hashes which recorded how their frames were cropped (see
+//!   [`VideoHash::crop`]). It is an object with the `width` and `height` of the decoded frames, and
+//!   the number of pixels cropped from their `left`, `right`, `top` and `bottom`.
+//! * `reliable_bits` (optional) is laid out in the same way as `bits`, with each bit set if the
+//!   same bit of the hash is reliable (see [`CreationOptions::reliability_mask`]).
+//! * `frame_hashes` (optional) is an array of 16 digit hexadecimal strings, one for each sampled
+//!   frame (see [`CreationOptions::frame_hashes`]).
+//! * `audio_fingerprint` (optional) is an array of 8 digit hexadecimal strings, one for each slice
+//!   of the audio (see [`CreationOptions::audio_fingerprint`]).
+//! * `speed_variants` (optional) is an array of objects, one for each speed at which the video was
+//!   also hashed (see [`CreationOptions::speed_variants`]), in order of their speeds. Each has a
+//!   `factor`, which is one of `0.5`, `1.25`, `1.5` or `2`, and `bits`, which are laid out in the
+//!   same way as `bits`.
+//!
+//! Readers ignore any fields that they do not know about, so new optional fields can be added
+//! without changing the version, as long as a reader that ignores them still compares `bits`
+//! correctly. The version changes if the meaning of an existing field does, or if a new field
+//! changes which hashes can be compared.
+//!
+//! # Versions
+//! * Version 2 added `normalization`, `coverage`, `sampling_window` and `pixel_aspect_ratio` to
+//!   `creation`. Hashes which differ in any of them cannot be compared, so a version 1 reader,
+//!   which ignores them, would compare their `bits` as if they had been created in the same way.
+//! * Version 1 hashes are read as version 2 hashes without those fields.
+//!
+//! [`CreationOptions::reliability_mask`]: crate::CreationOptions::reliability_mask
+//! [`CreationOptions::frame_hashes`]: crate::CreationOptions::frame_hashes
+//! [`CreationOptions::audio_fingerprint`]: crate::CreationOptions::audio_fingerprint
+//! [`CreationOptions::speed_variants`]: crate::CreationOptions::speed_variants
+//! [`Normalization`]: crate::Normalization
+//! [`Coverage`]: crate::Coverage
+//! [`SamplingWindow`]: crate::SamplingWindow
+
+use std::{path::PathBuf, time::Duration};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use vid_dup_finder_common::Crop;
+
+use super::speed_variants::SpeedVariant;
+use super::video_hash::{coarse_signature, zeroed_hash_array, HashArray, ACTIVE_AREA_SCALE};
+use crate::{
+    AudioFingerprint, ColorRange, Coverage, DurationSource, EnvironmentDigest, HashSize,
+    Normalization, SamplingWindow, SpeedFactor, VideoHash,
+};
+
+/// The version of the portable format written by [`VideoHash::to_json_portable`]. It is the newest
+/// version that [`VideoHash::from_json_portable`] can read.
+pub const PORTABLE_HASH_VERSION: u32 = 2;
+
+//The oldest version that can still be read. See "Versions" in the module docs.
+const OLDEST_READABLE_VERSION: u32 = 1;
+
+/// The error returned when a hash cannot be read from the portable format.
+#[derive(Error, Debug)]
+pub enum PortableHashError {
+    /// The input is not JSON, or is missing a field or has a field of the wrong type.
+    #[error("Invalid portable hash: {0}")]
+    Json(#[from] serde_json::Error),
+
+    /// The hash was written in a version of the format that cannot be read.
+    #[error(
+        "Unsupported portable hash version {0}. The supported versions are \
+         {OLDEST_READABLE_VERSION} to {PORTABLE_HASH_VERSION}"
+    )]
+    UnsupportedVersion(u32),
+
+    /// The declared dimensions are not those of any [`HashSize`].
+    #[error("Unsupported hash dimensions {0:?}")]
+    UnsupportedDimensions([u32; 3]),
+
+    /// A field is not valid base64.
+    #[error("The {0} field is not valid base64")]
+    InvalidBase64(&'static str),
+
+    /// A field has the wrong number of bytes for the declared dimensions.
+    #[error(
+        "The {field} field has {actual} bytes, but a hash of size {hash_size} needs {expected}"
+    )]
+    WrongPayloadLength {
+        field: &'static str,
+        hash_size: HashSize,
+        expected: usize,
+        actual: usize,
+    },
+
+    /// A frame hash is not a 16 digit hexadecimal number.
+    #[error("Invalid frame hash {0:?}")]
+    InvalidFrameHash(String),
+
+    /// A slice of an audio fingerprint is not an 8 digit hexadecimal number.
+    #[error("Invalid audio fingerprint slice {0:?}")]
+    InvalidAudioFingerprint(String),
+
+    /// The environment digest is not a 16 digit hexadecimal number.
+    #[error("Invalid environment digest {0:?}")]
+    InvalidEnvironmentDigest(String),
+
+    /// The active area is more than 1000 thousandths.
+    #[error("Invalid active area {0}, which must be no more than 1000")]
+    InvalidActiveArea(u32),
+
+    /// The crop leaves nothing of the frames.
+    #[error("Invalid crop, which crops away the whole {0}x{1} frame")]
+    InvalidCrop(u32, u32),
+
+    /// A speed variant has a factor which is not one of the supported [`SpeedFactor`]s.
+    #[error("Unsupported speed factor {0}")]
+    UnsupportedSpeedFactor(f64),
+}
+
+#[derive(Serialize, Deserialize)]
+struct PortableHash {
+    version: u32,
+    path: String,
+    duration_ms: u64,
+    dimensions: [u32; 3],
+    bits: String,
+    creation: PortableCreation,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    reliable_bits: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    frame_hashes: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    audio_fingerprint: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    speed_variants: Option<Vec<PortableSpeedVariant>>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct PortableSpeedVariant {
+    factor: f64,
+    bits: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct PortableCreation {
+    short_video: bool,
+    video_stream: usize,
+    leading_black_ms: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    window_start_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    normalization: Option<PortableNormalization>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    coverage: Option<PortableCoverage>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sampling_window: Option<SamplingWindow>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pixel_aspect_ratio: Option<[u32; 2]>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    color_range: Option<ColorRange>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    deinterlaced: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    environment: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    active_area_permille: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    crop: Option<PortableCrop>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct PortableCrop {
+    width: u32,
+    height: u32,
+    left: u32,
+    right: u32,
+    top: u32,
+    bottom: u32,
+}
+
+impl PortableCrop {
+    fn new(crop: Crop) -> Self {
+        let Crop {
+            orig_res: (width, height),
+            left,
+            right,
+            top,
+            bottom,
+        } = crop;
+        Self {
+            width,
+            height,
+            left,
+            right,
+            top,
+            bottom,
+        }
+    }
+
+    fn crop(self) -> Result<Crop, PortableHashError> {
+        let Self {
+            width,
+            height,
+            left,
+            right,
+            top,
+            bottom,
+        } = self;
+        let fits = |side: u32, a: u32, b: u32| a.checked_add(b).is_some_and(|sum| sum < side);
+        if !(fits(width, left, right) && fits(height, top, bottom)) {
+            return Err(PortableHashError::InvalidCrop(width, height));
+        }
+        Ok(Crop::from_edge_offsets(
+            (width, height),
+            left,
+            right,
+            top,
+            bottom,
+        ))
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum PortableNormalization {
+    HistogramEqualize,
+    ContrastStretch { low_pct: f64, high_pct: f64 },
+    Custom,
+}
+
+impl PortableNormalization {
+    fn new(normalization: Normalization) -> Option<Self> {
+        match normalization {
+            Normalization::None => None,
+            Normalization::HistogramEqualize => Some(Self::HistogramEqualize),
+            Normalization::ContrastStretch { low_pct, high_pct } => {
+                Some(Self::ContrastStretch { low_pct, high_pct })
+            }
+            Normalization::Custom => Some(Self::Custom),
+        }
+    }
+
+    fn normalization(portable: Option<Self>) -> Normalization {
+        match portable {
+            None => Normalization::None,
+            Some(Self::HistogramEqualize) => Normalization::HistogramEqualize,
+            Some(Self::ContrastStretch { low_pct, high_pct }) => {
+                Normalization::ContrastStretch { low_pct, high_pct }
+            }
+            Some(Self::Custom) => Normalization::Custom,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum PortableCoverage {
+    StartMiddleEnd,
+    EvenlySpaced { segments: u32 },
+}
+
+impl PortableCoverage {
+    fn new(coverage: Coverage) -> Option<Self> {
+        match coverage {
+            Coverage::Start => None,
+            Coverage::StartMiddleEnd => Some(Self::StartMiddleEnd),
+            Coverage::EvenlySpaced { segments } => Some(Self::EvenlySpaced { segments }),
+        }
+    }
+
+    fn coverage(portable: Option<Self>) -> Coverage {
+        match portable {
+            None => Coverage::Start,
+            Some(Self::StartMiddleEnd) => Coverage::StartMiddleEnd,
+            Some(Self::EvenlySpaced { segments }) => Coverage::EvenlySpaced { segments },
+        }
+    }
+}
+
+impl VideoHash {
+    /// This hash as JSON in the portable format described in the
+    /// [`portable_hash`][crate::portable_hash] module, for use by other programs. The JSON is on a
+    /// single line.
+    ///
+    /// The collection of the hash (see [`VideoHash::with_collection`]) is not included.
+    #[must_use]
+    pub fn to_json_portable(&self) -> String {
+        let side = self.hash_size.side();
+        let portable = PortableHash {
+            version: PORTABLE_HASH_VERSION,
+            path: self.src_path.to_string_lossy().into_owned(),
+            duration_ms: u64::from(self.duration) * 1000,
+            dimensions: [side; 3],
+            bits: STANDARD.encode(to_bytes(&self.hash, self.hash_size)),
+            creation: PortableCreation {
+                short_video: self.short_video,
+                video_stream: self.video_stream,
+                leading_black_ms: millis(self.leading_black),
+                window_start_ms: self.window_start.map(millis),
+                normalization: PortableNormalization::new(self.normalization),
+                coverage: PortableCoverage::new(self.coverage),
+                sampling_window: Some(self.sampling_window)
+                    .filter(|window| *window != SamplingWindow::default()),
+                pixel_aspect_ratio: self.pixel_aspect_ratio.map(<[u32; 2]>::from),
+                color_range: self.source_color_range,
+                deinterlaced: self.deinterlaced,
+                environment: self.environment.map(|digest| digest.to_string()),
+                active_area_permille: self.active_area.map(u32::from),
+                crop: self.crop().map(PortableCrop::new),
+            },
+            reliable_bits: self
+                .reliable_bits
+                .as_ref()
+                .map(|bits| STANDARD.encode(to_bytes(bits, self.hash_size))),
+            frame_hashes: self
+                .frame_hashes
+                .as_ref()
+                .map(|frames| frames.iter().map(|frame| format!("{frame:016x}")).collect()),
+            audio_fingerprint: self.audio_fingerprint.as_ref().map(|fingerprint| {
+                fingerprint
+                    .slices()
+                    .iter()
+                    .map(|slice| format!("{slice:08x}"))
+                    .collect()
+            }),
+            speed_variants: (!self.speed_variants.is_empty()).then(|| {
+                self.speed_variants
+                    .iter()
+                    .map(|variant| PortableSpeedVariant {
+                        factor: variant.factor.value(),
+                        bits: STANDARD.encode(to_bytes(&variant.hash_array(), self.hash_size)),
+                    })
+                    .collect()
+            }),
+        };
+
+        serde_json::to_string(&portable).expect("all fields can be represented in JSON")
+    }
+
+    /// Read a hash written by [`VideoHash::to_json_portable`] (or by another program following the
+    /// format described in the [`portable_hash`][crate::portable_hash] module).
+    ///
+    /// # Errors
+    /// Returns `Err` if the input is not a hash in a supported version of the format, or if the
+    /// length of its bits does not match its dimensions.
+    pub fn from_json_portable(json: &str) -> Result<Self, PortableHashError> {
+        let portable: PortableHash = serde_json::from_str(json)?;
+        if !(OLDEST_READABLE_VERSION..=PORTABLE_HASH_VERSION).contains(&portable.version) {
+            return Err(PortableHashError::UnsupportedVersion(portable.version));
+        }
+
+        let [t, x, y] = portable.dimensions;
+        let hash_size = HashSize::from_side(t).filter(|_| t == x && x == y).ok_or(
+            PortableHashError::UnsupportedDimensions(portable.dimensions),
+        )?;
+
+        let hash = from_base64(&portable.bits, "bits", hash_size)?;
+        let coarse = Some(coarse_signature(&hash, hash_size));
+        let reliable_bits = portable
+            .reliable_bits
+            .map(|bits| from_base64(&bits, "reliable_bits", hash_size))
+            .transpose()?;
+        let frame_hashes = portable
+            .frame_hashes
+            .map(|frames| frames.iter().map(|frame| parse_frame_hash(frame)).collect())
+            .transpose()?;
+        let audio_fingerprint = portable
+            .audio_fingerprint
+            .map(|slices| {
+                slices
+                    .iter()
+                    .map(|slice| parse_audio_slice(slice))
+                    .collect()
+            })
+            .transpose()?
+            .map(AudioFingerprint::from_slices);
+        let mut speed_variants = portable
+            .speed_variants
+            .unwrap_or_default()
+            .into_iter()
+            .map(|variant| {
+                let factor = SpeedFactor::from_value(variant.factor)
+                    .ok_or(PortableHashError::UnsupportedSpeedFactor(variant.factor))?;
+                let bits = from_base64(&variant.bits, "speed_variants", hash_size)?;
+                Ok(SpeedVariant {
+                    factor,
+                    bits: bits.to_vec(),
+                })
+            })
+            .collect::<Result<Vec<_>, PortableHashError>>()?;
+        speed_variants.sort();
+        speed_variants.dedup_by_key(|variant| variant.factor);
+
+        let PortableCreation {
+            short_video,
+            video_stream,
+            leading_black_ms,
+            window_start_ms,
+            normalization,
+            coverage,
+            sampling_window,
+            pixel_aspect_ratio,
+            color_range,
+            deinterlaced,
+            environment,
+            active_area_permille,
+            crop,
+        } = portable.creation;
+        let environment = environment
+            .map(|digest| parse_environment_digest(&digest))
+            .transpose()?;
+        let active_area = active_area_permille
+            .map(|area| {
+                u16::try_from(area)
+                    .ok()
+                    .filter(|&area| area <= ACTIVE_AREA_SCALE)
+                    .ok_or(PortableHashError::InvalidActiveArea(area))
+            })
+            .transpose()?;
+        let crop = crop.map(PortableCrop::crop).transpose()?;
+
+        Ok(Self {
+            hash,
+            hash_size,
+            src_path: PathBuf::from(portable.path),
+            duration: u32::try_from(portable.duration_ms / 1000).unwrap_or(u32::MAX),
+            short_video,
+            coarse,
+            video_stream,
+            window_start: window_start_ms.map(Duration::from_millis),
+            frame_hashes,
+            leading_black: Duration::from_millis(leading_black_ms),
+            collection: None,
+            reliable_bits,
+            truncated_decode: false,
+            duration_corrected: false,
+            motion_crop_fallback: false,
+            audio_fingerprint,
+            normalization: PortableNormalization::normalization(normalization),
+            coverage: PortableCoverage::coverage(coverage),
+            sampling_window: sampling_window.unwrap_or_default(),
+            pixel_aspect_ratio: pixel_aspect_ratio.map(<(u32, u32)>::from),
+            source_color_range: color_range,
+            deinterlaced,
+            environment,
+            active_area,
+            speed_variants,
+            crop: crop.map(Into::into),
+            duration_source: DurationSource::Container,
+        })
+    }
+}
+
+fn millis(duration: Duration) -> u64 {
+    u64::try_from(duration.as_millis()).unwrap_or(u64::MAX)
+}
+
+//The bits of a hash, as described for the bits field.
+pub(super) fn to_bytes(words: &HashArray, hash_size: HashSize) -> Vec<u8> {
+    let num_bytes = hash_size.bits().div_ceil(8) as usize;
+    words
+        .iter()
+        .flat_map(|word| word.to_le_bytes())
+        .take(num_bytes)
+        .collect()
+}
+
+fn from_base64(
+    encoded: &str,
+    field: &'static str,
+    hash_size: HashSize,
+) -> Result<HashArray, PortableHashError> {
+    let bytes = STANDARD
+        .decode(encoded)
+        .map_err(|_| PortableHashError::InvalidBase64(field))?;
+
+    let expected = hash_size.bits().div_ceil(8) as usize;
+    if bytes.len() != expected {
+        return Err(PortableHashError::WrongPayloadLength {
+            field,
+            hash_size,
+            expected,
+            actual: bytes.len(),
+        });
+    }
+
+    Ok(from_bytes(&bytes, hash_size))
+}
+
+//The inverse of to_bytes. There must be no more bytes than a hash of the given size needs.
+pub(super) fn from_bytes(bytes: &[u8], hash_size: HashSize) -> HashArray {
+    let mut words = zeroed_hash_array(hash_size);
+    for (word, chunk) in words.iter_mut().zip(bytes.chunks(8)) {
+        let mut le_bytes = [0; 8];
+        le_bytes[..chunk.len()].copy_from_slice(chunk);
+        *word = u64::from_le_bytes(le_bytes);
+    }
+
+    //Only the first hash_size.bits() bits are used, and the rest must be zero.
+    let bits = hash_size.bits() as usize;
+    let last = bits / 64;
+    if let Some(word) = words.get_mut(last) {
+        *word &= (1 << (bits % 64)) - 1;
+    }
+
+    words
+}
+
+fn parse_frame_hash(frame: &str) -> Result<u64, PortableHashError> {
+    //from_str_radix allows a leading sign, which is not part of the format.
+    if frame.len() != 16 || !frame.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(PortableHashError::InvalidFrameHash(frame.to_owned()));
+    }
+    u64::from_str_radix(frame, 16)
+        .map_err(|_| PortableHashError::InvalidFrameHash(frame.to_owned()))
+}
+
+fn parse_audio_slice(slice: &str) -> Result<u32, PortableHashError> {
+    if slice.len() != 8 || !slice.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(PortableHashError::InvalidAudioFingerprint(slice.to_owned()));
+    }
+    u32::from_str_radix(slice, 16)
+        .map_err(|_| PortableHashError::InvalidAudioFingerprint(slice.to_owned()))
+}
+
+fn parse_environment_digest(digest: &str) -> Result<EnvironmentDigest, PortableHashError> {
+    if digest.len() != 16 || !digest.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(PortableHashError::InvalidEnvironmentDigest(
+            digest.to_owned(),
+        ));
+    }
+    digest
+        .parse()
+        .map_err(|_| PortableHashError::InvalidEnvironmentDigest(digest.to_owned()))
+}
+
+#[cfg(test)]
+mod test {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::*;
+    use crate::Preset;
+
+    fn portable_json(hash: &VideoHash) -> serde_json::Value {
+        serde_json::from_str(&hash.to_json_portable()).expect("valid JSON")
+    }
+
+    fn from_value(value: &serde_json::Value) -> Result<VideoHash, PortableHashError> {
+        VideoHash::from_json_portable(&value.to_string())
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut rng = StdRng::seed_from_u64(1);
+        for hash_size in HashSize::ALL {
+            let hash = VideoHash::random_hash_with_size(&mut rng, hash_size)
+                .with_src_path(format!("/videos/{hash_size}, \"quoted\".mp4"))
+                .with_duration(61);
+            let read = VideoHash::from_json_portable(&hash.to_json_portable()).expect("round trip");
+            assert_eq!(read, hash);
+        }
+
+        //every optional part of a hash survives too.
+        let mut hash = VideoHash::random_hash(&mut rng)
+            .with_raw_frame_hashes(vec![0, u64::MAX, 0x0123_4567_89ab_cdef])
+            .with_short_video(true)
+            .with_leading_black(Duration::from_millis(2500))
+            .with_window_start(Duration::from_secs(30))
+            .with_video_stream(2)
+            .with_normalization(Normalization::ContrastStretch {
+                low_pct: 1.0,
+                high_pct: 99.5,
+            })
+            .with_coverage(Coverage::EvenlySpaced { segments: 5 });
+        hash.reliable_bits = Some(VideoHash::random_hash(&mut rng).hash);
+        hash.audio_fingerprint = Some(AudioFingerprint::from_slices(vec![
+            0,
+            u32::MAX,
+            0x89ab_cdef,
+        ]));
+        let json = hash.to_json_portable();
+        assert!(!json.contains('\n'));
+        assert_eq!(
+            VideoHash::from_json_portable(&json).expect("round trip"),
+            hash
+        );
+
+        //but collections are not.
+        let labelled = hash.clone().with_collection("nas");
+        let read = VideoHash::from_json_portable(&labelled.to_json_portable()).expect("round trip");
+        assert_eq!(read, hash);
+    }
+
+    #[test]
+    fn test_schema() {
+        let hash = VideoHash::full_hash("a.mp4").with_duration(3);
+        let bits = STANDARD.encode([0xff; 27]);
+        assert_eq!(
+            portable_json(&hash),
+            serde_json::json!({
+                "version": 2,
+                "path": "a.mp4",
+                "duration_ms": 3000,
+                "dimensions": [6, 6, 6],
+                "bits": bits,
+                "creation": {
+                    "short_video": false,
+                    "video_stream": 0,
+                    "leading_black_ms": 0,
+                    "pixel_aspect_ratio": [1, 1],
+                },
+            })
+        );
+
+        //unknown fields are ignored, wherever they are.
+        let mut json = portable_json(&hash);
+        json["from_the_future"] = serde_json::json!({"anything": [1, 2, 3]});
+        json["creation"]["also_new"] = serde_json::json!(true);
+        assert_eq!(from_value(&json).expect("extra fields"), hash);
+
+        //version 1 hashes can still be read.
+        json["version"] = serde_json::json!(1);
+        assert_eq!(from_value(&json).expect("version 1"), hash);
+
+        //milliseconds are dropped.
+        json["duration_ms"] = serde_json::json!(3999);
+        assert_eq!(from_value(&json).expect("valid").duration(), 3);
+
+        let stretched = hash
+            .clone()
+            .with_normalization(Normalization::ContrastStretch {
+                low_pct: 1.0,
+                high_pct: 99.0,
+            });
+        assert_eq!(
+            portable_json(&stretched)["creation"]["normalization"],
+            serde_json::json!({"kind": "contrast_stretch", "low_pct": 1.0, "high_pct": 99.0})
+        );
+        let equalized = hash
+            .clone()
+            .with_normalization(Normalization::HistogramEqualize);
+        assert_eq!(
+            portable_json(&equalized)["creation"]["normalization"],
+            serde_json::json!({"kind": "histogram_equalize"})
+        );
+
+        let covered = hash.clone().with_coverage(Coverage::StartMiddleEnd);
+        assert_eq!(
+            portable_json(&covered)["creation"]["coverage"],
+            serde_json::json!({"kind": "start_middle_end"})
+        );
+
+        let fast = hash
+            .clone()
+            .with_sampling_window(Preset::Fast.options().sampling_window());
+        assert_eq!(
+            portable_json(&fast)["creation"]["sampling_window"],
+            serde_json::json!({"skip_forward_ms": 5000, "duration_ms": 5000})
+        );
+        assert_eq!(from_value(&portable_json(&fast)).expect("valid"), fast);
+
+        let anamorphic = hash.clone().with_pixel_aspect_ratio(Some((16, 15)));
+        assert_eq!(
+            portable_json(&anamorphic)["creation"]["pixel_aspect_ratio"],
+            serde_json::json!([16, 15])
+        );
+        assert_eq!(
+            from_value(&portable_json(&anamorphic)).expect("valid"),
+            anamorphic
+        );
+
+        //hashes from before pixel aspect ratios were applied were never stretched.
+        let mut json = portable_json(&hash);
+        let creation = json["creation"].as_object_mut().expect("an object");
+        creation.remove("pixel_aspect_ratio");
+        assert_eq!(from_value(&json).expect("valid").pixel_aspect_ratio(), None);
+
+        let limited = hash
+            .clone()
+            .with_source_color_range(Some(ColorRange::Limited));
+        assert_eq!(
+            portable_json(&limited)["creation"]["color_range"],
+            serde_json::json!("limited")
+        );
+        assert_eq!(
+            from_value(&portable_json(&limited)).expect("valid"),
+            limited
+        );
+        assert!(portable_json(&hash)["creation"]
+            .get("color_range")
+            .is_none());
+
+        let deinterlaced = hash.clone().with_deinterlaced(true);
+        assert_eq!(
+            portable_json(&deinterlaced)["creation"]["deinterlaced"],
+            serde_json::json!(true)
+        );
+        assert_eq!(
+            from_value(&portable_json(&deinterlaced)).expect("valid"),
+            deinterlaced
+        );
+        assert!(portable_json(&hash)["creation"]
+            .get("deinterlaced")
+            .is_none());
+
+        let digest = crate::EnvironmentInfo::new("ffmpeg", [("ffmpeg", "6.1")]).digest();
+        let with_environment = hash.clone().with_environment(Some(digest));
+        assert_eq!(
+            portable_json(&with_environment)["creation"]["environment"],
+            serde_json::json!(digest.to_string())
+        );
+        assert_eq!(
+            from_value(&portable_json(&with_environment)).expect("valid"),
+            with_environment
+        );
+        assert!(portable_json(&hash)["creation"]
+            .get("environment")
+            .is_none());
+
+        let mut json = portable_json(&with_environment);
+        json["creation"]["environment"] = serde_json::json!("not a digest");
+        assert!(matches!(
+            from_value(&json),
+            Err(PortableHashError::InvalidEnvironmentDigest(_))
+        ));
+
+        let with_active_area = hash.clone().with_active_area(Some(0.75));
+        assert_eq!(
+            portable_json(&with_active_area)["creation"]["active_area_permille"],
+            serde_json::json!(750)
+        );
+        assert_eq!(
+            from_value(&portable_json(&with_active_area)).expect("valid"),
+            with_active_area
+        );
+        assert!(portable_json(&hash)["creation"]
+            .get("active_area_permille")
+            .is_none());
+
+        let mut json = portable_json(&with_active_area);
+        json["creation"]["active_area_permille"] = serde_json::json!(1001);
+        assert!(matches!(
+            from_value(&json),
+            Err(PortableHashError::InvalidActiveArea(1001))
+        ));
+
+        let crop = Crop::from_edge_offsets((640, 480), 0, 0, 60, 60);
+        let with_crop = hash.with_raw_crop(crop);
+        assert_eq!(
+            portable_json(&with_crop)["creation"]["crop"],
+            serde_json::json!({"width": 640, "height": 480, "left": 0, "right": 0, "top": 60, "bottom": 60})
+        );
+        assert_eq!(
+            from_value(&portable_json(&with_crop)).expect("valid"),
+            with_crop
+        );
+        assert!(portable_json(&hash).get("crop").is_none());
+
+        let mut json = portable_json(&with_crop);
+        json["creation"]["crop"]["bottom"] = serde_json::json!(420);
+        assert!(matches!(
+            from_value(&json),
+            Err(PortableHashError::InvalidCrop(640, 480))
+        ));
+
+        let sped_up = VideoHash::full_hash("b.mp4").with_flipped_bits(0..8);
+        let with_variant = hash.with_speed_variant(SpeedFactor::Double, &sped_up);
+        assert_eq!(
+            portable_json(&with_variant)["speed_variants"],
+            serde_json::json!([{"factor": 2.0, "bits": portable_json(&sped_up)["bits"]}])
+        );
+        assert_eq!(
+            from_value(&portable_json(&with_variant)).expect("valid"),
+            with_variant
+        );
+        assert!(portable_json(&hash).get("speed_variants").is_none());
+
+        let mut json = portable_json(&with_variant);
+        json["speed_variants"][0]["factor"] = serde_json::json!(3.0);
+        assert!(matches!(
+            from_value(&json),
+            Err(PortableHashError::UnsupportedSpeedFactor(3.0))
+        ));
+    }
+
+    #[test]
+    fn test_malformed_hashes_are_rejected() {
+        use PortableHashError::*;
+
+        let hash = VideoHash::full_hash("a.mp4");
+        let with = |field: &str, value: serde_json::Value| {
+            let mut json = portable_json(&hash);
+            json[field] = value;
+            from_value(&json)
+        };
+
+        assert!(matches!(
+            with("version", 3.into()),
+            Err(UnsupportedVersion(3))
+        ));
+        assert!(matches!(
+            with("version", 0.into()),
+            Err(UnsupportedVersion(0))
+        ));
+        assert!(matches!(
+            with("dimensions", serde_json::json!([7, 7, 7])),
+            Err(UnsupportedDimensions([7, 7, 7]))
+        ));
+        assert!(matches!(
+            with("dimensions", serde_json::json!([6, 6, 5])),
+            Err(UnsupportedDimensions(_))
+        ));
+        assert!(matches!(
+            with("dimensions", serde_json::json!([6, 6])),
+            Err(Json(_))
+        ));
+        assert!(matches!(
+            with("bits", "not base64!".into()),
+            Err(InvalidBase64("bits"))
+        ));
+        assert!(matches!(
+            with("reliable_bits", "AAAA".into()),
+            Err(WrongPayloadLength {
+                field: "reliable_bits",
+                expected: 27,
+                actual: 3,
+                ..
+            })
+        ));
+        //a 512 bit payload for a hash declared as 1000 bits.
+        let eight =
+            VideoHash::random_hash_with_size(&mut StdRng::seed_from_u64(2), HashSize::Eight);
+        let mut json = portable_json(&eight);
+        json["dimensions"] = serde_json::json!([10, 10, 10]);
+        assert!(matches!(
+            from_value(&json),
+            Err(WrongPayloadLength {
+                hash_size: HashSize::Ten,
+                expected: 125,
+                actual: 64,
+                ..
+            })
+        ));
+        for frame in ["123", "+123456789abcdef", "0123456789abcdeg"] {
+            assert!(matches!(
+                with("frame_hashes", serde_json::json!([frame])),
+                Err(InvalidFrameHash(_))
+            ));
+        }
+        for slice in ["123", "+1234567", "0123456g"] {
+            assert!(matches!(
+                with("audio_fingerprint", serde_json::json!([slice])),
+                Err(InvalidAudioFingerprint(_))
+            ));
+        }
+        assert!(matches!(with("path", 1.into()), Err(Json(_))));
+        assert!(matches!(VideoHash::from_json_portable("[]"), Err(Json(_))));
+
+        //padding bits past the end of the hash are ignored.
+        let mut json = portable_json(&VideoHash::full_hash("b.mp4"));
+        json["dimensions"] = serde_json::json!([5, 5, 5]);
+        json["bits"] = STANDARD.encode([0xff; 16]).into();
+        let five = from_value(&json).expect("valid hash");
+        assert_eq!(five.hash_size(), HashSize::Five);
+        assert_eq!(five.hash[..], [u64::MAX, (1 << 61) - 1]);
+    }
+
+    #[test]
+    fn test_corrupted_input_never_panics() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let mut hash = VideoHash::random_hash(&mut rng).with_raw_frame_hashes(vec![1, 2]);
+        hash.reliable_bits = Some(VideoHash::random_hash(&mut rng).hash);
+        let json = hash.to_json_portable();
+
+        //every truncation, and many random corruptions.
+        for len in 0..json.len() {
+            assert!(VideoHash::from_json_portable(&json[..len]).is_err());
+        }
+        let printable = (b' '..=b'~').collect::<Vec<_>>();
+        for _ in 0..2000 {
+            let mut bytes = json.clone().into_bytes();
+            for _ in 0..rng.gen_range(1..4) {
+                let idx = rng.gen_range(0..bytes.len());
+                bytes[idx] = printable[rng.gen_range(0..printable.len())];
+            }
+            let corrupted = String::from_utf8(bytes).expect("only ascii was written");
+            //anything that is still accepted must be a valid hash.
+            if let Ok(read) = VideoHash::from_json_portable(&corrupted) {
+                let reread = VideoHash::from_json_portable(&read.to_json_portable());
+                assert_eq!(reread.expect("round trip"), read);
+            }
+        }
+    }
+}
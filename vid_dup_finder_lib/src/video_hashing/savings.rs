@@ -0,0 +1,237 @@
+//! Estimating how much disk space would be reclaimed by deleting duplicates.
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use crate::{Error, MatchGroup};
+
+/// How [`MatchGroup::potential_savings`] chooses the file in a group that would be kept. Every
+/// other file in the group (including the reference, if it is not chosen) would be removed.
+#[derive(Debug, Clone, Copy)]
+pub enum KeepPolicy {
+    /// Keep the largest file. Ties are broken in favour of the smallest path.
+    LargestFile,
+    /// Keep the smallest file. Ties are broken in favour of the smallest path.
+    SmallestFile,
+    /// Keep the file with the most pixels in each frame, as chosen by [`crate::pick_best`] with
+    /// [`crate::Criterion::Resolution`]. Every file is probed, and [`crate::init`] must have been
+    /// called first.
+    #[cfg(any(feature = "ffmpeg_backend", feature = "gstreamer_backend"))]
+    HighestResolution,
+    /// Choose the file to keep. It must be one of the files in the group.
+    Custom(fn(&MatchGroup) -> Result<PathBuf, Error>),
+}
+
+/// The result of [`MatchGroup::potential_savings`].
+#[derive(Debug, Clone)]
+pub struct SavingsReport {
+    kept: PathBuf,
+    //sorted by path
+    removable: Vec<(PathBuf, Result<u64, Error>)>,
+}
+
+impl SavingsReport {
+    /// The file that would be kept.
+    pub fn kept(&self) -> &Path {
+        &self.kept
+    }
+
+    /// Every other file in the group, sorted by path, with its size in bytes or the error that
+    /// occurred when reading its size.
+    pub fn removable(&self) -> impl Iterator<Item = (&Path, Result<u64, &Error>)> {
+        self.removable
+            .iter()
+            .map(|(path, size)| (path.as_path(), size.as_ref().copied()))
+    }
+
+    /// The total size of the removable files. Files whose size could not be read are not counted.
+    pub fn reclaimable_bytes(&self) -> u64 {
+        self.removable().filter_map(|(_, size)| size.ok()).sum()
+    }
+}
+
+impl MatchGroup {
+    /// Estimate how much disk space would be reclaimed by keeping one file of this group, chosen
+    /// by `keep`, and removing the rest.
+    ///
+    /// File sizes are read from the filesystem. A file whose size cannot be read is still listed
+    /// in the report, along with its error. Returns an error only if the kept file cannot be
+    /// chosen.
+    pub fn potential_savings(&self, keep: KeepPolicy) -> Result<SavingsReport, Error> {
+        let mut sizes = self
+            .contained_paths()
+            .map(|path| (path.to_path_buf(), file_size(path)))
+            .collect::<Vec<_>>();
+        sizes.sort_by(|(p1, _), (p2, _)| p1.cmp(p2));
+
+        //If no size could be read, every file is as good as any other.
+        let by_size = |larger_is_better: bool| {
+            //min_by returns the first of equal elements, which is the smallest path.
+            sizes
+                .iter()
+                .filter_map(|(path, size)| Some((path, *size.as_ref().ok()?)))
+                .min_by(|(_, s1), (_, s2)| {
+                    if larger_is_better {
+                        s2.cmp(s1)
+                    } else {
+                        s1.cmp(s2)
+                    }
+                })
+                .map_or_else(|| sizes[0].0.clone(), |(path, _)| path.clone())
+        };
+
+        let kept = match keep {
+            KeepPolicy::LargestFile => by_size(true),
+            KeepPolicy::SmallestFile => by_size(false),
+            #[cfg(any(feature = "ffmpeg_backend", feature = "gstreamer_backend"))]
+            KeepPolicy::HighestResolution => {
+                crate::pick_best(self, &[crate::Criterion::Resolution])?
+                    .winner()
+                    .to_path_buf()
+            }
+            KeepPolicy::Custom(choose) => {
+                let kept = choose(self)?;
+                if !sizes.iter().any(|(path, _)| *path == kept) {
+                    return Err(Error::NotInGroup(kept));
+                }
+                kept
+            }
+        };
+
+        let removable = sizes
+            .into_iter()
+            .filter(|(path, _)| *path != kept)
+            .collect();
+        Ok(SavingsReport { kept, removable })
+    }
+}
+
+/// The result of [`total_savings`].
+#[derive(Debug, Clone)]
+pub struct TotalSavings {
+    reports: Vec<SavingsReport>,
+    reclaimable_bytes: u64,
+}
+
+impl TotalSavings {
+    /// The report for each group, in the same order as the groups.
+    pub fn reports(&self) -> &[SavingsReport] {
+        &self.reports
+    }
+
+    /// The total size of the files that would be removed from any group. A file that appears in
+    /// more than one group is counted once, and a file that would be kept by any group is not
+    /// counted at all, because removing it would leave that group with nothing.
+    pub fn reclaimable_bytes(&self) -> u64 {
+        self.reclaimable_bytes
+    }
+}
+
+/// Estimate the disk space reclaimed by applying [`MatchGroup::potential_savings`] to every group.
+///
+/// Returns an error if the kept file of any group cannot be chosen.
+pub fn total_savings(groups: &[MatchGroup], policy: KeepPolicy) -> Result<TotalSavings, Error> {
+    let reports = groups
+        .iter()
+        .map(|group| group.potential_savings(policy))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut removable = BTreeMap::new();
+    for report in &reports {
+        for (path, size) in report.removable() {
+            if let Ok(size) = size {
+                removable.insert(path, size);
+            }
+        }
+    }
+    for report in &reports {
+        removable.remove(report.kept());
+    }
+
+    Ok(TotalSavings {
+        reclaimable_bytes: removable.values().sum(),
+        reports,
+    })
+}
+
+fn file_size(path: &Path) -> Result<u64, Error> {
+    std::fs::metadata(path)
+        .map(|metadata| metadata.len())
+        .map_err(|e| Error::VidProc(format!("{}: {e}", path.display())))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn write_file(dir: &Path, name: &str, len: usize) -> PathBuf {
+        let path = dir.join(name);
+        std::fs::write(&path, vec![0u8; len]).expect("can write test file");
+        path
+    }
+
+    fn group(paths: &[&PathBuf]) -> MatchGroup {
+        MatchGroup::new(paths.iter().map(|&p| p.clone())).expect("at least two paths")
+    }
+
+    #[test]
+    fn test_potential_savings() {
+        let dir = tempfile::tempdir().expect("can create test dir");
+        let (a, b, c) = (
+            write_file(dir.path(), "a", 100),
+            write_file(dir.path(), "b", 300),
+            write_file(dir.path(), "c", 200),
+        );
+        let missing = dir.path().join("missing");
+        let g = group(&[&c, &a, &b, &missing]);
+
+        let largest = g
+            .potential_savings(KeepPolicy::LargestFile)
+            .expect("no error");
+        assert_eq!(largest.kept(), b);
+        let removable = largest.removable().collect::<Vec<_>>();
+        assert_eq!(
+            removable.iter().map(|(p, _)| *p).collect::<Vec<_>>(),
+            [a.as_path(), c.as_path(), missing.as_path()]
+        );
+        assert_eq!(removable[0].1.ok(), Some(100));
+        assert!(removable[2].1.is_err());
+        assert_eq!(largest.reclaimable_bytes(), 300);
+
+        let smallest = g
+            .potential_savings(KeepPolicy::SmallestFile)
+            .expect("no error");
+        assert_eq!(smallest.kept(), a);
+        assert_eq!(smallest.reclaimable_bytes(), 500);
+
+        let custom = g
+            .potential_savings(KeepPolicy::Custom(|_| Ok(PathBuf::from("elsewhere"))))
+            .map(|report| report.kept().to_path_buf());
+        assert!(matches!(custom, Err(Error::NotInGroup(_))));
+    }
+
+    #[test]
+    fn test_shared_paths_are_counted_once() {
+        let dir = tempfile::tempdir().expect("can create test dir");
+        let (a, b, c, d) = (
+            write_file(dir.path(), "a", 1000),
+            write_file(dir.path(), "b", 10),
+            write_file(dir.path(), "c", 20),
+            write_file(dir.path(), "d", 500),
+        );
+
+        //b is removable from both groups. c is removable from the first group, but kept by the
+        //second.
+        let groups = [group(&[&a, &b, &c]), group(&[&b, &c])];
+        let total = total_savings(&groups, KeepPolicy::LargestFile).expect("no error");
+        assert_eq!(total.reports().len(), 2);
+        assert_eq!(total.reports()[1].kept(), c);
+        assert_eq!(total.reclaimable_bytes(), 10);
+
+        let groups = [group(&[&a, &b]), group(&[&d, &b])];
+        let total = total_savings(&groups, KeepPolicy::LargestFile).expect("no error");
+        assert_eq!(total.reclaimable_bytes(), 10);
+    }
+}
@@ -1,30 +1,448 @@
-use std::path::PathBuf;
+use std::{cmp::Ordering, ops::RangeInclusive, path::Path, sync::Arc};
 
-use crate::{definitions::TOLERANCE_SCALING_FACTOR, VideoHash};
-#[derive(Debug, Default)]
+use itertools::Itertools;
+
+use super::match_graph::MatchGraph;
+use crate::{
+    definitions::COARSE_BITS, ActiveAreaGuard, AudioCheck, DurationGate, GroupOrdering,
+    GroupingMode, HashSize, Incompatibility, NonMatchReason, SearchOptions, SearchStats,
+    ShortVideoPolicy, SpeedFactor, VideoHash,
+};
+#[derive(Debug)]
 struct Entry {
     matched: bool,
     value: VideoHash,
+    //The path of the video, shared by every group that it is found in.
+    path: Arc<Path>,
 }
 
 impl From<VideoHash> for Entry {
     fn from(val: VideoHash) -> Self {
         Self {
             matched: false,
+            path: val.src_path().into(),
             value: val,
         }
     }
 }
 
+impl Entry {
+    fn member(&self) -> (&VideoHash, &Arc<Path>) {
+        (&self.value, &self.path)
+    }
+}
+
+// The coarse distance is at most COARSE_BITS, so the coarse signature can only rule out pairs when
+// the threshold is below that. The threshold of the default tolerance is not (0.35 * 216 = 75 bits
+// for hashes of the default size, against 64 coarse bits), so at the default tolerance no pair can
+// ever be ruled out, and the full distance (four popcounts for hashes of the default size) is always
+// needed. Below COARSE_BITS, unrelated videos differ in about half of the bits of their coarse
+// signatures, so checking them only pays for itself when the threshold is far enough below that to
+// reliably rule them out. Otherwise the extra (unpredictable) branch makes searches slower.
+//
+// examples/coarse_search_bench.rs measures the effect on 100k hashes: 1.25-1.3x faster at
+// tolerances of 0.1 and below, and no difference beyond noise (about 7%) from 0.15 up to the
+// default tolerance.
+const MAX_COARSE_THRESHOLD: u32 = COARSE_BITS * 3 / 8;
+
+/// The longest duration that a video of the given duration is compared against when searching.
+pub(super) fn max_comparable_duration(duration: u32) -> u32 {
+    (f64::from(duration) * 1.1) as u32
+}
+
+/// Whether a search would ever compare two videos with these durations.
+pub(super) fn durations_are_comparable(d1: u32, d2: u32) -> bool {
+    d1.max(d2) <= max_comparable_duration(d1.min(d2))
+}
+
+/// The durations of the videos that a reference video of the given duration is compared against.
+pub(super) fn reference_durations(duration: u32) -> RangeInclusive<u32> {
+    (f64::from(duration) * 0.95) as u32..=(f64::from(duration) * 1.05) as u32
+}
+
+/// The order that hashes are searched in: By duration, then by path.
+///
+/// The path is not necessary for correctness, only so that searches are fully deterministic.
+pub(super) fn search_order(h1: &VideoHash, h2: &VideoHash) -> Ordering {
+    fn key(hash: &VideoHash) -> (u32, &Path) {
+        (hash.duration(), hash.src_path())
+    }
+    key(h1).cmp(&key(h2))
+}
+
+/// The maximum hamming distances at which two hashes are considered to match, for hashes of
+/// each [`HashSize`].
+#[derive(Debug, Clone, Copy)]
+pub(super) struct MatchThreshold {
+    by_size: [SizeThreshold; HashSize::ALL.len()],
+    temporal_slack: u32,
+    ignore_unreliable_bits: bool,
+    require_audio_agreement: bool,
+    active_area: Option<ActiveAreaGuard>,
+    //The duration gate of the search, if pairs also match at different speeds. Otherwise the
+    //search only compares pairs within the gate anyway.
+    speed_variants: Option<DurationGate>,
+}
+
+//When aligning frames, pairs whose full hashes are further apart than this multiple of the
+//threshold are not considered. Videos that are out of step still have similar full hashes, because
+//most of the sampled frames are shared.
+const ALIGNMENT_SCREEN_FACTOR: u32 = 2;
+
+//When ignoring unreliable bits, pairs with fewer than 1/MIN_RELIABLE_DIVISOR of their bits left to
+//compare never match. Otherwise two flat videos would match on the handful of bits that remain.
+const MIN_RELIABLE_DIVISOR: u32 = 4;
+
+#[derive(Debug, Clone, Copy)]
+struct SizeThreshold {
+    normal: u32,
+    //None if short videos must never match each other.
+    short: Option<u32>,
+}
+
+impl MatchThreshold {
+    pub(super) fn new(opts: &SearchOptions) -> Self {
+        let SearchOptions {
+            tolerance,
+            short_video_policy,
+            temporal_slack,
+            ignore_unreliable_bits,
+            audio_check,
+            active_area,
+            duration_gate,
+            speed_variants,
+            ..
+        } = *opts;
+
+        let by_size = HashSize::ALL.map(|hash_size| {
+            let scaling_factor = f64::from(hash_size.bits());
+            let normal = (tolerance.value() * scaling_factor) as u32;
+            let short = match short_video_policy {
+                ShortVideoPolicy::SameTolerance => Some(normal),
+                ShortVideoPolicy::Tolerance(short_tolerance) => {
+                    let short = (short_tolerance * scaling_factor) as u32;
+                    Some(short.min(normal))
+                }
+                ShortVideoPolicy::Exclude => None,
+            };
+
+            SizeThreshold { normal, short }
+        });
+
+        Self {
+            by_size,
+            temporal_slack,
+            ignore_unreliable_bits,
+            require_audio_agreement: audio_check == AudioCheck::RequireAgreement,
+            active_area,
+            speed_variants: speed_variants.then_some(duration_gate),
+        }
+    }
+
+    pub(super) fn is_match(&self, h1: &VideoHash, h2: &VideoHash) -> bool {
+        if incompatibility(h1, h2).is_some() {
+            return false;
+        }
+
+        if self.require_audio_agreement && h1.audio_agrees(h2) == Some(false) {
+            return false;
+        }
+
+        if self.rejects_active_areas(h1, h2).is_some() {
+            return false;
+        }
+
+        match self.speed_variants {
+            None => self.is_same_speed_match(h1, h2),
+            Some(gate) if !gate.allows(h1.duration(), h2.duration()) => {
+                self.speed_factor(h1, h2).is_some()
+            }
+            Some(_) => self.is_same_speed_match(h1, h2) || self.speed_factor(h1, h2).is_some(),
+        }
+    }
+
+    //Whether the bits of the hashes match, with the videos played at the same speed.
+    fn is_same_speed_match(&self, h1: &VideoHash, h2: &VideoHash) -> bool {
+        let threshold = self.threshold(h1, h2);
+
+        if self.ignore_unreliable_bits {
+            if let Some((differing, considered)) = h1.reliable_distance(h2) {
+                let bits = h1.hash_size().bits();
+                return threshold.is_some_and(|threshold| {
+                    considered * MIN_RELIABLE_DIVISOR >= bits
+                        && differing * bits <= threshold * considered
+                });
+            }
+        }
+
+        //The coarse distance is a lower bound of the full distance (see coarse_signature), so
+        //if it is already too far then the full distance must be too.
+        threshold.is_some_and(|threshold| {
+            let coarse_too_far = || {
+                threshold <= MAX_COARSE_THRESHOLD
+                    && h1
+                        .coarse_distance(h2)
+                        .is_some_and(|coarse| coarse > threshold)
+            };
+            (!coarse_too_far() && h1.same_size_distance(h2) <= threshold)
+                || self.is_aligned_match(h1, h2, threshold)
+        })
+    }
+
+    /// Why [`MatchThreshold::is_match`] does or does not match two hashes. This is slower than
+    /// `is_match`, because it never takes a shortcut.
+    pub(super) fn explain(&self, h1: &VideoHash, h2: &VideoHash) -> NonMatchReason {
+        if let Some(incompatibility) = incompatibility(h1, h2) {
+            return NonMatchReason::IncompatibleHashes { incompatibility };
+        }
+
+        if self.require_audio_agreement && h1.audio_agrees(h2) == Some(false) {
+            return NonMatchReason::AudioDisagrees;
+        }
+
+        if let Some((a, b)) = self.rejects_active_areas(h1, h2) {
+            return NonMatchReason::ActiveAreaMismatch { a, b };
+        }
+
+        match self.speed_variants {
+            None => self.explain_same_speed(h1, h2),
+            Some(_) if self.speed_factor(h1, h2).is_some() => NonMatchReason::Matches,
+            Some(gate) if !gate.allows(h1.duration(), h2.duration()) => {
+                NonMatchReason::DurationGate {
+                    delta: h1.duration().abs_diff(h2.duration()),
+                }
+            }
+            Some(_) => self.explain_same_speed(h1, h2),
+        }
+    }
+
+    fn explain_same_speed(&self, h1: &VideoHash, h2: &VideoHash) -> NonMatchReason {
+        let Some(threshold) = self.threshold(h1, h2) else {
+            return NonMatchReason::ShortVideosExcluded;
+        };
+
+        let bits = h1.hash_size().bits();
+        let tolerance = f64::from(threshold) / f64::from(bits);
+
+        if self.ignore_unreliable_bits {
+            if let Some((differing, considered)) = h1.reliable_distance(h2) {
+                return if considered * MIN_RELIABLE_DIVISOR < bits {
+                    NonMatchReason::TooFewReliableBits { considered, bits }
+                } else if differing * bits <= threshold * considered {
+                    NonMatchReason::Matches
+                } else {
+                    NonMatchReason::DistanceAboveTolerance {
+                        distance: f64::from(differing) / f64::from(considered),
+                        tolerance,
+                    }
+                };
+            }
+        }
+
+        let distance = h1.same_size_distance(h2);
+        if distance <= threshold || self.is_aligned_match(h1, h2, threshold) {
+            NonMatchReason::Matches
+        } else {
+            NonMatchReason::DistanceAboveTolerance {
+                distance: f64::from(distance) / f64::from(bits),
+                tolerance,
+            }
+        }
+    }
+
+    /// The speed of the video of `h2` relative to that of `h1`, if the search matches videos at
+    /// different speeds and the pair match at one of the speeds that either hash has a variant
+    /// for. The duration of the video with the variant is scaled by its speed before the duration
+    /// gate is applied.
+    pub(super) fn speed_factor(&self, h1: &VideoHash, h2: &VideoHash) -> Option<f64> {
+        let gate = self.speed_variants?;
+        if incompatibility(h1, h2).is_some() {
+            return None;
+        }
+        let threshold = self.threshold(h1, h2)?;
+
+        //The factor at which the video of `from` played at that speed matches the video of `to`.
+        let matching_factor = |from: &VideoHash, to: &VideoHash| {
+            from.speed_variants().iter().find(|&factor| {
+                let scaled = (f64::from(from.duration()) / factor.value()).round() as u32;
+                gate.allows(scaled, to.duration())
+                    && from
+                        .speed_variant_distance(factor, to)
+                        .is_some_and(|distance| distance <= threshold)
+            })
+        };
+
+        matching_factor(h1, h2)
+            .map(SpeedFactor::value)
+            .or_else(|| matching_factor(h2, h1).map(|factor| 1.0 / factor.value()))
+    }
+
+    //The active areas of the pair, if the active area guard stops them from matching.
+    fn rejects_active_areas(&self, h1: &VideoHash, h2: &VideoHash) -> Option<(f64, f64)> {
+        self.active_area.and_then(|guard| guard.rejects(h1, h2))
+    }
+
+    //The threshold for a pair of hashes of the same size, or None if they can never match.
+    fn threshold(&self, h1: &VideoHash, h2: &VideoHash) -> Option<u32> {
+        //ALL is in declaration order, so the discriminant is the index.
+        let SizeThreshold { normal, short } = self.by_size[h1.hash_size() as usize];
+
+        if h1.is_short_video() && h2.is_short_video() {
+            short
+        } else {
+            Some(normal)
+        }
+    }
+
+    //Whether the frames of the two videos match once they are lined up with each other.
+    fn is_aligned_match(&self, h1: &VideoHash, h2: &VideoHash, threshold: u32) -> bool {
+        if self.temporal_slack == 0 || !(h1.has_frame_hashes() && h2.has_frame_hashes()) {
+            return false;
+        }
+
+        if h1.same_size_distance(h2) > threshold.saturating_mul(ALIGNMENT_SCREEN_FACTOR) {
+            return false;
+        }
+
+        let max_distance = f64::from(threshold) / f64::from(h1.hash_size().bits());
+        h1.aligned_frame_distance(h2, self.temporal_slack)
+            .is_some_and(|distance| distance <= max_distance)
+    }
+}
+
+//Hashes of different sizes, of differently normalized frames or of frames sampled from different
+//parts of the videos are never comparable. Nor are stretched and unstretched frames, although
+//frames stretched by different pixel aspect ratios are.
+pub(crate) fn incompatibility(h1: &VideoHash, h2: &VideoHash) -> Option<Incompatibility> {
+    if h1.hash_size() != h2.hash_size() {
+        Some(Incompatibility::HashSize)
+    } else if h1.normalization() != h2.normalization() {
+        Some(Incompatibility::Normalization)
+    } else if h1.coverage() != h2.coverage() {
+        Some(Incompatibility::Coverage)
+    } else if h1.sampling_window() != h2.sampling_window() {
+        Some(Incompatibility::SamplingWindow)
+    } else if h1.pixel_aspect_ratio().is_some() != h2.pixel_aspect_ratio().is_some() {
+        Some(Incompatibility::PixelAspectRatio)
+    } else {
+        None
+    }
+}
+
+/// From the unmatched candidates, select the indices of those which should be grouped with target.
+///
+/// A candidate whose pairing with target is suppressed (by the falsepos filter or a constraint) is
+/// only selected if it also matches some other selected candidate (with that pairing not suppressed
+/// either). This ensures that a suppressed pair only ends up in the same group if a third video
+/// independently matches both.
+fn select_matches(
+    target: &VideoHash,
+    cands: &[Entry],
+    threshold: MatchThreshold,
+    opts: &SearchOptions,
+    stats: &mut SearchStats,
+) -> Vec<usize> {
+    let mut is_match = |h1, h2| {
+        stats.comparisons += 1;
+        threshold.is_match(h1, h2)
+    };
+
+    let mut selected = vec![];
+    let mut suppressed = vec![];
+
+    for (idx, cand) in cands.iter().enumerate() {
+        if !cand.matched && is_match(target, &cand.value) {
+            if opts.is_suppressed(target.src_path(), cand.value.src_path()) {
+                suppressed.push(idx);
+            } else {
+                selected.push(idx);
+            }
+        }
+    }
+
+    let bridged = suppressed
+        .into_iter()
+        .filter(|&suppressed_idx| {
+            let suppressed_hash = &cands[suppressed_idx].value;
+            selected.iter().any(|&selected_idx| {
+                let bridge = &cands[selected_idx].value;
+                is_match(bridge, suppressed_hash)
+                    && !opts.is_suppressed(bridge.src_path(), suppressed_hash.src_path())
+            })
+        })
+        .collect::<Vec<_>>();
+
+    selected.extend(bridged);
+    selected.sort_unstable();
+    selected
+}
+
+/// The paths of a group of matching videos found by a search.
+#[derive(Debug)]
+pub(super) struct FoundGroup {
+    pub paths: Vec<Arc<Path>>,
+    //The largest normalized distance between any two videos in the group (including the reference,
+    //if any). Normalized so that groups with different hash sizes can be compared.
+    //Only calculated when ordering by distance.
+    pub max_distance: Option<f64>,
+    //The durations of the shortest and longest videos in the group (including the reference, if
+    //any). Only calculated when the duration gate is not strict.
+    pub duration_spread: Option<(u32, u32)>,
+}
+
+impl FoundGroup {
+    pub(super) fn new<'a>(
+        members: impl Iterator<Item = (&'a VideoHash, &'a Arc<Path>)> + Clone,
+        reference: Option<&'a VideoHash>,
+        opts: &SearchOptions,
+    ) -> Self {
+        let hashes = members.clone().map(|(hash, _)| hash);
+        let max_distance = (opts.ordering == GroupOrdering::ByDistance).then(|| {
+            hashes
+                .clone()
+                .chain(reference)
+                .tuple_combinations()
+                .map(|(h1, h2)| {
+                    f64::from(h1.same_size_distance(h2)) / f64::from(h1.hash_size().bits())
+                })
+                .fold(0.0, f64::max)
+        });
+
+        let duration_spread = if opts.duration_gate == DurationGate::Strict {
+            None
+        } else {
+            hashes
+                .clone()
+                .chain(reference)
+                .map(VideoHash::duration)
+                .minmax()
+                .into_option()
+        };
+
+        Self {
+            paths: members.map(|(_, path)| path.clone()).collect(),
+            max_distance,
+            duration_spread,
+        }
+    }
+}
+
 /// A data structure for performing duplicate video searches.
 #[derive(Debug, Default)]
 pub(super) struct Search {
     entries: Vec<Entry>,
+    stats: SearchStats,
 }
 
 impl Search {
-    const fn new() -> Self {
-        Self { entries: vec![] }
+    fn new() -> Self {
+        Self::default()
+    }
+
+    /// The work done by all searches so far. The number of groups is left for the caller to fill in,
+    /// because groups may still be filtered out after searching.
+    pub fn stats(&self) -> SearchStats {
+        self.stats.clone()
     }
 
     ///Add video hashes into the Search, for use in searches.
@@ -40,47 +458,108 @@ impl Search {
     pub fn search_with_references<R>(
         &mut self,
         references: &[R],
-        tolerance: f64,
+        opts: &SearchOptions,
         consume: bool,
-    ) -> Vec<Vec<PathBuf>>
+    ) -> Vec<FoundGroup>
     where
         R: AsRef<VideoHash> + Send + Sync,
     {
+        let threshold = MatchThreshold::new(opts);
         references
             .iter()
-            .map(|target| self.search_one(target.as_ref(), tolerance, consume))
+            .map(|target| self.search_one(target.as_ref(), threshold, opts, consume))
             .collect()
     }
 
+    /// Create a Search from hashes which are already in [`search_order`].
+    pub fn presorted(hashes: Vec<VideoHash>) -> Self {
+        debug_assert!(hashes.is_sorted_by(|h1, h2| search_order(h1, h2).is_le()));
+        Self {
+            entries: hashes.into_iter().map(Into::into).collect(),
+            stats: SearchStats::default(),
+        }
+    }
+
     fn sort(&mut self) {
-        //in order to be fully deterministic, it is necessary to use the src_path as a secondary key.
-        //however we have to use .to_owned() which forces an allocation nlogn times during the sort.
-        //Note: src_path is not necessary for correctness, only determinism.
         self.entries
-            .sort_by_key(|entry| (entry.value.duration(), entry.value.src_path().to_owned()));
+            .sort_by(|e1, e2| search_order(&e1.value, &e2.value));
     }
 
-    fn search_one(&mut self, target: &VideoHash, tolerance: f64, consume: bool) -> Vec<PathBuf> {
-        let tolerance_int = (tolerance * TOLERANCE_SCALING_FACTOR) as u32;
-        let mut ret = vec![];
+    fn search_one(
+        &mut self,
+        target: &VideoHash,
+        threshold: MatchThreshold,
+        opts: &SearchOptions,
+        consume: bool,
+    ) -> FoundGroup {
+        let num_entries = self.entries.len() as u64;
+        let (cands, stats) = self.duration_slice(target.duration(), opts);
+        stats.buckets += 1;
+        stats.skipped_by_duration += num_entries - cands.len() as u64;
 
-        for entry in self.duration_slice(target.duration()) {
-            if !entry.matched && target.hamming_distance(&entry.value) <= tolerance_int {
-                ret.push(entry.value.src_path().to_path_buf());
-                if consume {
-                    entry.matched = true;
-                }
+        let selected = select_matches(target, cands, threshold, opts, stats);
+        if consume {
+            for &idx in &selected {
+                cands[idx].matched = true;
             }
         }
 
-        ret
+        let members = selected.iter().map(|&idx| cands[idx].member());
+        FoundGroup::new(members, Some(target), opts)
+    }
+
+    /// Search within all seeded videos for duplicates, within the given tolerance, grouping them
+    /// as [`SearchOptions::grouping`] says.
+    pub fn search_self(&mut self, opts: &SearchOptions) -> Vec<FoundGroup> {
+        let threshold = MatchThreshold::new(opts);
+        match opts.grouping {
+            GroupingMode::Centered => self.search_self_centered(threshold, opts),
+            mode => {
+                let graph = self.match_graph(threshold, opts);
+                graph
+                    .groups(mode)
+                    .into_iter()
+                    .map(|group| {
+                        let members = group.iter().map(|&idx| self.entries[idx].member());
+                        FoundGroup::new(members, None, opts)
+                    })
+                    .collect()
+            }
+        }
     }
 
-    /// Search within all seeded videos for duplicates, within the given tolerance.
-    /// Each video will be matched a maximum of once.
-    pub fn search_self(&mut self, tolerance: f64) -> Vec<Vec<PathBuf>> {
-        let tolerance_int = (tolerance * TOLERANCE_SCALING_FACTOR) as u32;
+    // Compare every pair of videos with comparable durations.
+    fn match_graph(&mut self, threshold: MatchThreshold, opts: &SearchOptions) -> MatchGraph {
+        let mut graph = MatchGraph::new(self.entries.len());
+        for (lhs, target) in self.entries.iter().enumerate() {
+            let max_duration = opts
+                .candidate_gate()
+                .max_comparable(target.value.duration());
+            let rhs = lhs
+                + 1
+                + self.entries[lhs + 1..]
+                    .partition_point(|entry| entry.value.duration() <= max_duration);
+            self.stats.buckets += 1;
+            self.stats.skipped_by_duration += (self.entries.len() - rhs) as u64;
 
+            for (idx, cand) in self.entries.iter().enumerate().take(rhs).skip(lhs + 1) {
+                self.stats.comparisons += 1;
+                if threshold.is_match(&target.value, &cand.value)
+                    && !opts.is_suppressed(target.value.src_path(), cand.value.src_path())
+                {
+                    graph.add_edge(lhs, idx);
+                }
+            }
+        }
+        graph
+    }
+
+    // Each video will be matched a maximum of once.
+    fn search_self_centered(
+        &mut self,
+        threshold: MatchThreshold,
+        opts: &SearchOptions,
+    ) -> Vec<FoundGroup> {
         let mut lhs = 0;
         let mut rhs = 0;
 
@@ -90,13 +569,14 @@ impl Search {
             return vec![];
         }
 
+        let gate = opts.candidate_gate();
         let advance_rhs = |lhs: usize, mut rhs: usize, entries: &Vec<Entry>| -> Option<usize> {
             let lhs_duration = entries
                 .get(lhs)
                 .expect("lhs is always a valid index")
                 .value
                 .duration();
-            let thresh_duration = (f64::from(lhs_duration) * 1.1) as u32;
+            let thresh_duration = gate.max_comparable(lhs_duration);
             loop {
                 #[rustfmt::skip]
                 match entries.get(rhs) {
@@ -106,7 +586,7 @@ impl Search {
                     Some(Entry { matched: true, .. }) =>
                         rhs += 1,
 
-                    Some(Entry { matched: false, value: hash, }) => {
+                    Some(Entry { matched: false, value: hash, .. }) => {
                         if hash.duration() > thresh_duration {
                             return Some(rhs);
                         }
@@ -122,8 +602,8 @@ impl Search {
                 #[rustfmt::skip]
                 match entries.get(lhs) {
                     None => return None,
-                    Some(Entry { matched: true, value: _, }) => (),
-                    Some(Entry { matched: false, value: _, }) => return Some(lhs),
+                    Some(Entry { matched: true, .. }) => (),
+                    Some(Entry { matched: false, .. }) => return Some(lhs),
                 };
             }
         };
@@ -138,26 +618,29 @@ impl Search {
             }
 
             if lhs < rhs {
+                //everything from rhs onwards is too long to be compared with the target.
+                self.stats.buckets += 1;
+                self.stats.skipped_by_duration += (self.entries.len() - rhs) as u64;
+
                 let slice_to_search = &mut self.entries[lhs..rhs];
 
-                let mut it = slice_to_search.iter_mut();
-                let target = it
-                    .next()
-                    .expect("iterator can't be at the end in this branch because lhs < rhs");
+                let (target, cands) = slice_to_search
+                    .split_first_mut()
+                    .expect("slice can't be empty in this branch because lhs < rhs");
                 target.matched = true;
 
-                let mut match_vec = vec![];
-                for cand in it {
-                    if !cand.matched && target.value.hamming_distance(&cand.value) <= tolerance_int
-                    {
-                        match_vec.push(cand.value.src_path().to_path_buf());
-                        cand.matched = true;
+                let selected =
+                    select_matches(&target.value, cands, threshold, opts, &mut self.stats);
+                if !selected.is_empty() {
+                    for &idx in &selected {
+                        cands[idx].matched = true;
                     }
-                }
 
-                if !match_vec.is_empty() {
-                    match_vec.push(target.value.src_path().to_path_buf());
-                    ret.push(match_vec);
+                    let members = selected
+                        .iter()
+                        .map(|&idx| cands[idx].member())
+                        .chain(std::iter::once(target.member()));
+                    ret.push(FoundGroup::new(members, None, opts));
                 }
             }
 
@@ -170,18 +653,20 @@ impl Search {
         }
     }
 
-    fn duration_slice(&mut self, duration_secs: u32) -> &mut [Entry] {
-        let lhs_duration = (f64::from(duration_secs) * 0.95) as u32;
+    fn duration_slice(
+        &mut self,
+        duration_secs: u32,
+        opts: &SearchOptions,
+    ) -> (&mut [Entry], &mut SearchStats) {
+        let durations = opts.candidate_gate().reference_durations(duration_secs);
         let lhs = self
             .entries
-            .partition_point(|entry| entry.value.duration() < lhs_duration);
-
-        let rhs_duration = (f64::from(duration_secs) * 1.05) as u32;
+            .partition_point(|entry| entry.value.duration() < *durations.start());
         let rhs = self
             .entries
-            .partition_point(|entry| entry.value.duration() <= rhs_duration);
+            .partition_point(|entry| entry.value.duration() <= *durations.end());
 
-        &mut self.entries[lhs..rhs]
+        (&mut self.entries[lhs..rhs], &mut self.stats)
     }
 }
 
@@ -197,13 +682,1074 @@ where
         ret
     }
 }
+//Many of these tests search through the functions which take a bare tolerance. They are
+//deprecated, but still need to work.
 #[cfg(test)]
+#[allow(deprecated)]
 mod test {
-    use crate::search;
+    use rand::prelude::*;
+
+    use itertools::Itertools;
+    use std::path::{Path, PathBuf};
+
+    use super::{
+        durations_are_comparable, reference_durations, MatchThreshold, MAX_COARSE_THRESHOLD,
+    };
+    use crate::{
+        definitions::{COARSE_BITS, TOLERANCE_SCALING_FACTOR},
+        plan_search, search, search_filtered, search_with_opts, search_with_plan,
+        search_with_references_filtered, search_with_references_prioritized,
+        search_with_references_with_opts, search_with_references_with_stats,
+        search_with_short_video_policy, search_with_stats, CollectionMode, ConstraintRule,
+        DuplicateInput, DurationGate, FalseposFilter, GroupOrdering, GroupingMode, HashSize,
+        MatchGroup, Normalization, SearchOptions, SearchStats, ShortVideoPolicy, Tolerance,
+        VideoHash,
+    };
+
     #[test]
     fn test_searching_nothing_returns_empty_vec() {
         let no_hashes = vec![];
         let matchgroups = search(no_hashes, 1.0);
         assert!(matchgroups.is_empty());
     }
+
+    // Two hashes that are a quarter of the hash apart: Close enough to match under a tolerance
+    // of 0.3, but not under a stricter tolerance of 0.1
+    fn hash_pair(short_video: bool) -> Vec<VideoHash> {
+        let mut rng = StdRng::seed_from_u64(1);
+        let distance = (0.25 * TOLERANCE_SCALING_FACTOR) as u32;
+
+        let h1 = VideoHash::random_hash(&mut rng)
+            .with_duration(5)
+            .with_short_video(short_video);
+        let h2 = h1
+            .hash_with_spatial_distance(distance, &mut rng)
+            .with_src_path("h2");
+
+        vec![h1.with_src_path("h1"), h2]
+    }
+
+    // Three groups, which are in a different order for each GroupOrdering:
+    // * "a": 2 videos, 2 bits apart
+    // * "b": 2 videos, 40 bits apart
+    // * "c": 3 videos, at most 8 bits apart
+    // Plus an unrelated video which matches nothing.
+    fn ordering_hashes() -> Vec<VideoHash> {
+        let mut rng = StdRng::seed_from_u64(5);
+        let mut base = |duration| VideoHash::random_hash(&mut rng).with_duration(duration);
+
+        let a = base(20);
+        let b = base(30);
+        let c = base(10);
+        vec![
+            a.with_src_path("a/1"),
+            a.with_flipped_bits(0..2).with_src_path("a/2"),
+            b.with_src_path("b/1"),
+            b.with_flipped_bits(0..40).with_src_path("b/2"),
+            c.with_src_path("c/1"),
+            c.with_flipped_bits(0..4).with_src_path("c/2"),
+            c.with_flipped_bits(4..8).with_src_path("c/3"),
+            base(100).with_src_path("d/1"),
+        ]
+    }
+
+    fn group_names(groups: &[MatchGroup]) -> Vec<Vec<&str>> {
+        groups
+            .iter()
+            .map(|group| {
+                group
+                    .duplicates()
+                    .map(|path| path.to_str().expect("test paths are utf8"))
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_search_stats_count_comparisons() {
+        //"a" and "b" are duplicates, "c" is unrelated but the same length, and "d" is far longer.
+        let mut rng = StdRng::seed_from_u64(2);
+        let a = VideoHash::random_hash(&mut rng).with_duration(10);
+        let hashes = vec![
+            a.with_src_path("a"),
+            a.with_flipped_bits(0..2).with_src_path("b"),
+            VideoHash::random_hash(&mut rng)
+                .with_duration(10)
+                .with_src_path("c"),
+            VideoHash::random_hash(&mut rng)
+                .with_duration(100)
+                .with_src_path("d"),
+        ];
+        let opts = SearchOptions::default();
+
+        //a is compared with b and c, and b is then grouped so only c and d are searched for.
+        //Neither a nor c is ever compared with d.
+        let (groups, stats) = search_with_stats(hashes.clone(), &opts);
+        assert_eq!(groups, search_with_opts(hashes.clone(), &opts));
+        assert_eq!(
+            stats,
+            SearchStats {
+                buckets: 3,
+                comparisons: 2,
+                skipped_by_duration: 2,
+                groups: 1,
+                duplicate_inputs: vec![],
+            }
+        );
+
+        let (refs, others) = hashes.split_at(1);
+        let (groups, stats) =
+            search_with_references_with_stats(refs.to_vec(), others.to_vec(), &opts);
+        assert_eq!(groups.len(), 1);
+        assert_eq!(
+            stats,
+            SearchStats {
+                buckets: 1,
+                comparisons: 2,
+                skipped_by_duration: 1,
+                groups: 1,
+                duplicate_inputs: vec![],
+            }
+        );
+
+        assert_eq!(search_with_stats(vec![], &opts).1, SearchStats::default());
+    }
+
+    #[test]
+    fn test_temporal_slack_lines_up_frames() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let frames = (0..16).map(|_| rng.gen()).collect::<Vec<u64>>();
+
+        //The second video starts three frames later than the first.
+        let pair = hash_pair(false);
+        let shifted = frames[3..]
+            .iter()
+            .copied()
+            .chain((0..3).map(|_| rng.gen()))
+            .collect();
+        let with_frames = vec![
+            pair[0].with_raw_frame_hashes(frames),
+            pair[1].with_raw_frame_hashes(shifted),
+        ];
+
+        let num_groups = |hashes: &[VideoHash], temporal_slack| {
+            let opts = SearchOptions::with_tolerance(0.15).temporal_slack(temporal_slack);
+            search_with_opts(hashes.to_vec(), &opts).len()
+        };
+
+        assert_eq!(num_groups(&with_frames, 0), 0);
+        assert_eq!(num_groups(&with_frames, 2), 0);
+        assert_eq!(num_groups(&with_frames, 3), 1);
+        assert_eq!(num_groups(&with_frames, 8), 1);
+
+        //Both videos need frame hashes.
+        let one_without = vec![with_frames[0].clone(), pair[1].clone()];
+        assert_eq!(num_groups(&one_without, 3), 0);
+
+        //Full hashes too far apart are not aligned, even though the frames would line up.
+        let opts = SearchOptions::with_tolerance(0.1).temporal_slack(3);
+        assert!(search_with_opts(with_frames, &opts).is_empty());
+    }
+
+    #[test]
+    fn test_group_ordering() {
+        let order = |ordering| {
+            let opts = SearchOptions {
+                ordering,
+                ..SearchOptions::with_tolerance(0.3)
+            };
+            search_with_opts(ordering_hashes(), &opts)
+        };
+
+        let a = vec!["a/1", "a/2"];
+        let b = vec!["b/1", "b/2"];
+        let c = vec!["c/1", "c/2", "c/3"];
+
+        assert_eq!(
+            group_names(&order(GroupOrdering::ByPath)),
+            [a.clone(), b.clone(), c.clone()]
+        );
+        assert_eq!(
+            group_names(&order(GroupOrdering::BySize)),
+            [c.clone(), a.clone(), b.clone()]
+        );
+        assert_eq!(group_names(&order(GroupOrdering::ByDistance)), [a, c, b]);
+        assert_eq!(order(GroupOrdering::ByPath), search(ordering_hashes(), 0.3));
+
+        assert_eq!(order(GroupOrdering::Unordered).len(), 3);
+    }
+
+    #[test]
+    fn test_cross_collection_only() {
+        //"a" is split between the collections, "b" is all on the nas, and one of "c" has no collection.
+        let label = |hash: VideoHash| {
+            let collection = match hash.src_path().to_str().expect("test paths are utf8") {
+                "a/1" | "b/1" | "b/2" => Some("nas"),
+                "a/2" | "c/1" | "c/2" => Some("laptop"),
+                _ => None,
+            };
+            match collection {
+                Some(collection) => hash.with_collection(collection),
+                None => hash,
+            }
+        };
+        let hashes = ordering_hashes().into_iter().map(label).collect::<Vec<_>>();
+
+        let search_collections = |collections| {
+            let opts = SearchOptions {
+                collections,
+                ..SearchOptions::with_tolerance(0.3)
+            };
+            search_with_opts(hashes.clone(), &opts)
+        };
+
+        let all = search_collections(CollectionMode::All);
+        assert_eq!(all.len(), 3);
+        let cross = search_collections(CollectionMode::CrossCollectionOnly);
+        assert_eq!(
+            group_names(&cross),
+            [vec!["a/1", "a/2"], vec!["c/1", "c/2", "c/3"]]
+        );
+        assert_eq!(cross[0].collection_of("a/1"), Some("nas"));
+        assert_eq!(cross[0].collection_of("a/2"), Some("laptop"));
+        assert_eq!(cross[1].collection_of("c/3"), None);
+
+        //The reference counts too.
+        let opts = SearchOptions {
+            collections: CollectionMode::CrossCollectionOnly,
+            ..SearchOptions::with_tolerance(0.3)
+        };
+        let reference = hashes[0].clone().with_src_path("ref");
+        let num_groups = |collection: &str| {
+            let refs = [reference.clone().with_collection(collection)];
+            search_with_references_with_opts(refs, hashes[..2].to_vec(), &opts).len()
+        };
+        assert_eq!(num_groups("nas"), 1);
+        assert_eq!(num_groups("phone"), 1);
+
+        let only_nas = hashes[..1].to_vec();
+        let refs = [reference.with_collection("nas")];
+        assert!(search_with_references_with_opts(refs, only_nas, &opts).is_empty());
+    }
+
+    #[test]
+    fn test_protected_members() {
+        let opts = SearchOptions::with_tolerance(0.3).protected(["c", "a/2"]);
+        let groups = search_with_opts(ordering_hashes(), &opts);
+        let protected = groups
+            .iter()
+            .map(|group| group.protected_members().collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            protected,
+            [
+                vec![Path::new("a/2")],
+                vec![],
+                ["c/1", "c/2", "c/3"].map(Path::new).to_vec()
+            ]
+        );
+
+        //protection does not change the groups.
+        let opts = SearchOptions::with_tolerance(0.3).protected(["a/1/", "b/"]);
+        let protected_groups = search_with_opts(ordering_hashes(), &opts);
+        assert_eq!(
+            group_names(&protected_groups),
+            group_names(&search(ordering_hashes(), 0.3))
+        );
+        assert!(protected_groups[0].is_protected("a/1"));
+        assert!(!protected_groups[0].is_protected("a/2"));
+
+        //references can be protected too.
+        let reference = ordering_hashes()[0].clone().with_src_path("c/ref");
+        let opts = SearchOptions::with_tolerance(0.3).protected(["c"]);
+        let groups = search_with_references_with_opts([reference], ordering_hashes(), &opts);
+        assert!(groups[0].is_protected("c/ref"));
+        assert!(!groups[0].is_protected("a/1"));
+    }
+
+    #[test]
+    fn test_shuffled_input_gives_identical_output() {
+        let mut rng = StdRng::seed_from_u64(6);
+
+        for ordering in [
+            GroupOrdering::BySize,
+            GroupOrdering::ByDistance,
+            GroupOrdering::ByPath,
+        ] {
+            let opts = SearchOptions {
+                ordering,
+                ..SearchOptions::with_tolerance(0.3)
+            };
+            let refs = [VideoHash::random_hash(&mut rng)
+                .with_duration(10)
+                .with_src_path("ref")];
+
+            let mut hashes = ordering_hashes();
+            let expected = search_with_opts(hashes.clone(), &opts);
+            let expected_refs =
+                search_with_references_with_opts(refs.clone(), hashes.clone(), &opts);
+
+            for _ in 0..20 {
+                hashes.shuffle(&mut rng);
+                assert_eq!(search_with_opts(hashes.clone(), &opts), expected);
+                assert_eq!(
+                    search_with_references_with_opts(refs.clone(), hashes.clone(), &opts),
+                    expected_refs
+                );
+            }
+        }
+    }
+
+    // Pruning on the coarse signature must never change search results.
+    #[test]
+    fn test_coarse_prefilter_preserves_results() {
+        let mut rng = StdRng::seed_from_u64(4);
+
+        let mut hashes = vec![];
+        for cluster in 0..50 {
+            let base = VideoHash::random_hash(&mut rng).with_duration(cluster % 10);
+            for i in 0..10 {
+                let distance = rng.gen_range(0..(0.4 * TOLERANCE_SCALING_FACTOR) as u32);
+                hashes.push(
+                    base.hash_with_spatial_distance(distance, &mut rng)
+                        .with_src_path(format!("{cluster}_{i}")),
+                );
+            }
+        }
+        let fallback_hashes = hashes
+            .iter()
+            .map(VideoHash::without_coarse_signature)
+            .collect::<Vec<_>>();
+
+        for tolerance in [0.0, 0.05, 0.1, 0.15, 0.2, 0.25, 0.3, 0.35] {
+            let coarse = search(hashes.clone(), tolerance);
+            let fallback = search(fallback_hashes.clone(), tolerance);
+            assert_eq!(coarse, fallback, "tolerance: {tolerance}");
+        }
+    }
+
+    // The coarse signature is not checked at the default tolerance because it could never rule
+    // out a pair there, not just because it would be too slow.
+    #[test]
+    fn test_coarse_prefilter_cannot_prune_at_default_tolerance() {
+        let threshold = MatchThreshold::new(&SearchOptions::default());
+        let default_size = threshold.by_size[HashSize::DEFAULT as usize];
+
+        assert!(default_size.normal >= COARSE_BITS);
+        assert!(default_size.normal > MAX_COARSE_THRESHOLD);
+    }
+
+    #[test]
+    fn test_short_videos_use_stricter_tolerance() {
+        let policy = ShortVideoPolicy::Tolerance(0.1);
+
+        let long_groups = search_with_short_video_policy(hash_pair(false), 0.3, policy);
+        assert_eq!(long_groups.len(), 1);
+
+        let short_groups = search_with_short_video_policy(hash_pair(true), 0.3, policy);
+        assert!(short_groups.is_empty());
+
+        let same_groups =
+            search_with_short_video_policy(hash_pair(true), 0.3, ShortVideoPolicy::SameTolerance);
+        assert_eq!(same_groups.len(), 1);
+    }
+
+    #[test]
+    fn test_short_video_tolerance_never_loosens_search() {
+        let policy = ShortVideoPolicy::Tolerance(1.0);
+
+        let groups = search_with_short_video_policy(hash_pair(true), 0.1, policy);
+        assert!(groups.is_empty());
+    }
+
+    #[test]
+    fn test_short_videos_can_be_excluded() {
+        let h1 = VideoHash::empty_hash("h1").with_short_video(true);
+        let h2 = VideoHash::empty_hash("h2").with_short_video(true);
+        let h3 = VideoHash::empty_hash("h3");
+
+        let groups = search_with_short_video_policy(
+            vec![h1.clone(), h2.clone()],
+            0.3,
+            ShortVideoPolicy::Exclude,
+        );
+        assert!(groups.is_empty());
+
+        //but a short video still matches a long one.
+        let groups = search_with_short_video_policy(vec![h1, h3], 0.3, ShortVideoPolicy::Exclude);
+        assert_eq!(groups.len(), 1);
+    }
+
+    // Three hashes in a line: "a" and "c" are each 0.2 away from "b", and 0.4 from each other.
+    // At a tolerance of 0.3, b matches both of the others, but a and c do not match each other.
+    fn hash_chain() -> Vec<VideoHash> {
+        let step = (0.2 * TOLERANCE_SCALING_FACTOR) as u32;
+
+        let b = VideoHash::empty_hash("b").with_duration(5);
+        let a = b.with_flipped_bits(0..step).with_src_path("a");
+        let c = b.with_flipped_bits(step..step * 2).with_src_path("c");
+
+        vec![a, b, c]
+    }
+
+    fn sorted_paths(group: &MatchGroup) -> Vec<&Path> {
+        let mut ret = group.contained_paths().collect::<Vec<_>>();
+        ret.sort();
+        ret
+    }
+
+    #[test]
+    fn test_filter_suppresses_pair() {
+        let hashes = hash_pair(false);
+        let filter = FalseposFilter::from_iter([("h2", "h1")]);
+
+        assert_eq!(search(hashes.clone(), 0.3).len(), 1);
+        assert!(search_filtered(hashes, 0.3, &filter).is_empty());
+    }
+
+    #[test]
+    fn test_filtered_pair_not_glued_by_unrelated_video() {
+        // a-b is suppressed. c matches b but not a, so cannot justify putting a and b together.
+        let hashes = hash_chain();
+        let filter = FalseposFilter::from_iter([("a", "b")]);
+
+        let groups = search_filtered(hashes, 0.3, &filter);
+        assert_eq!(groups.len(), 1);
+        assert_eq!(sorted_paths(&groups[0]), [Path::new("b"), Path::new("c")]);
+    }
+
+    #[test]
+    fn test_filtered_pair_glued_when_bridge_matches_both() {
+        // a, b and x are all within tolerance of each other. a-b is suppressed, but x matches
+        // both of them so all three are still grouped together.
+        let step = (0.1 * TOLERANCE_SCALING_FACTOR) as u32;
+        let a = VideoHash::empty_hash("a").with_duration(5);
+        let b = a.with_flipped_bits(0..step).with_src_path("b");
+        let x = a.with_flipped_bits(step..step * 2).with_src_path("x");
+
+        let filter = FalseposFilter::from_iter([("a", "b")]);
+        let groups = search_filtered(vec![a.clone(), b.clone(), x], 0.3, &filter);
+        assert_eq!(groups.len(), 1);
+        assert_eq!(
+            sorted_paths(&groups[0]),
+            [Path::new("a"), Path::new("b"), Path::new("x")]
+        );
+
+        // ...but without x there is nothing to justify grouping them.
+        assert!(search_filtered(vec![a, b], 0.3, &filter).is_empty());
+    }
+
+    #[test]
+    fn test_filter_with_references() {
+        let [a, b, c]: [VideoHash; 3] = hash_chain().try_into().expect("three hashes");
+
+        // searching with b as a reference would find both a and c...
+        let filter = FalseposFilter::from_iter([("b", "a")]);
+        let groups = search_with_references_filtered(
+            vec![b.clone()],
+            vec![a.clone(), c.clone()],
+            0.3,
+            &FalseposFilter::new(),
+        );
+        assert_eq!(groups[0].len(), 2);
+
+        // ...but a is suppressed, and c does not match a, so only c remains.
+        let groups = search_with_references_filtered(vec![b], vec![a, c], 0.3, &filter);
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].reference(), Some(Path::new("b")));
+        assert_eq!(groups[0].duplicates().collect::<Vec<_>>(), [Path::new("c")]);
+    }
+
+    #[test]
+    fn test_path_constraints() {
+        //"x" is a pair in the archive and a copy of them in incoming, "y" is a pair in the archive
+        //only, and "z" is a pair in incoming only. The members of each match each other.
+        let mut rng = StdRng::seed_from_u64(12);
+        let step = (0.1 * TOLERANCE_SCALING_FACTOR) as u32;
+        let mut group = |paths: &[&str]| {
+            let base = VideoHash::random_hash(&mut rng).with_duration(5);
+            paths
+                .iter()
+                .enumerate()
+                .map(|(i, path)| {
+                    base.with_flipped_bits(0..step * i as u32)
+                        .with_src_path(path)
+                })
+                .collect::<Vec<_>>()
+        };
+        let hashes = [
+            group(&["archive/x1", "archive/x2", "incoming/x3"]),
+            group(&["archive/y1", "archive/y2"]),
+            group(&["incoming/z1", "incoming/z2"]),
+        ]
+        .concat();
+
+        let search_constrained = |hashes: &[VideoHash], constraints: Vec<ConstraintRule>| {
+            let opts = SearchOptions {
+                constraints,
+                ..SearchOptions::new(Tolerance::new(0.3).expect("valid tolerance"))
+            };
+            let groups = search_with_opts(hashes.to_vec(), &opts);
+            groups
+                .iter()
+                .map(|group| {
+                    sorted_paths(group)
+                        .iter()
+                        .map(|p| p.to_path_buf())
+                        .collect()
+                })
+                .collect::<Vec<Vec<_>>>()
+        };
+        let paths = |names: &[&str]| names.iter().map(PathBuf::from).collect::<Vec<_>>();
+        let never_within_archive = ConstraintRule::NeverGroupWithin("archive".into());
+        let only_incoming = ConstraintRule::OnlyReportGroupsContaining("incoming".into());
+
+        //archive files are still grouped when a file in incoming matches them.
+        let expected = [
+            paths(&["archive/x1", "archive/x2", "incoming/x3"]),
+            paths(&["incoming/z1", "incoming/z2"]),
+        ];
+        assert_eq!(
+            search_constrained(&hashes, vec![never_within_archive.clone()]),
+            expected
+        );
+        assert_eq!(
+            search_constrained(&hashes, vec![only_incoming.clone()]),
+            expected
+        );
+        assert_eq!(
+            search_constrained(
+                &hashes,
+                vec![never_within_archive.clone(), only_incoming.clone()]
+            ),
+            expected
+        );
+
+        //...but not by themselves.
+        let archive_x = hashes[..2].to_vec();
+        assert_eq!(search_constrained(&archive_x, vec![]).len(), 1);
+        assert!(search_constrained(&archive_x, vec![never_within_archive.clone()]).is_empty());
+
+        //directories are compared by component.
+        let only_arch = ConstraintRule::OnlyReportGroupsContaining("arch".into());
+        assert!(search_constrained(&hashes, vec![only_arch]).is_empty());
+
+        //a clique cannot contain a suppressed pair.
+        let opts = SearchOptions {
+            constraints: vec![never_within_archive.clone()],
+            grouping: GroupingMode::Cliques { min_mutual: 1.0 },
+            ..SearchOptions::new(Tolerance::new(0.3).expect("valid tolerance"))
+        };
+        let groups = search_with_opts(hashes.clone(), &opts);
+        assert_eq!(
+            groups.iter().map(sorted_paths).collect::<Vec<_>>(),
+            [
+                [Path::new("archive/x1"), Path::new("incoming/x3")],
+                [Path::new("archive/x2"), Path::new("incoming/x3")],
+                [Path::new("incoming/z1"), Path::new("incoming/z2")],
+            ]
+        );
+
+        //with references, the reference counts towards the group.
+        let opts = SearchOptions {
+            constraints: vec![only_incoming],
+            ..SearchOptions::new(Tolerance::new(0.3).expect("valid tolerance"))
+        };
+        let search_refs = |refs: &[VideoHash], new: &[VideoHash]| {
+            search_with_references_with_opts(refs.to_vec(), new.to_vec(), &opts).len()
+        };
+        assert_eq!(search_refs(&hashes[2..3], &hashes[..2]), 1);
+        assert_eq!(search_refs(&hashes[..1], &hashes[1..3]), 1);
+        assert_eq!(search_refs(&hashes[3..4], &hashes[4..5]), 0);
+    }
+
+    #[test]
+    fn test_every_hash_size_is_searchable() {
+        let mut rng = StdRng::seed_from_u64(8);
+        for hash_size in HashSize::ALL {
+            // 20% of the bits apart, so matching at 0.3 but not at 0.1
+            let distance = hash_size.bits() / 5;
+            let h1 = VideoHash::random_hash_with_size(&mut rng, hash_size).with_duration(5);
+            let h2 = h1
+                .hash_with_spatial_distance(distance, &mut rng)
+                .with_src_path("h2");
+            let hashes = vec![h1.with_src_path("h1"), h2];
+
+            assert_eq!(search(hashes.clone(), 0.3).len(), 1, "{hash_size}");
+            assert!(search(hashes, 0.1).is_empty(), "{hash_size}");
+        }
+    }
+
+    #[test]
+    fn test_prioritized_references() {
+        let mut rng = StdRng::seed_from_u64(9);
+        let bits = |fraction: f64| (fraction * TOLERANCE_SCALING_FACTOR) as u32;
+        let cand = VideoHash::random_hash(&mut rng).with_duration(20);
+        let master = cand
+            .hash_with_spatial_distance(bits(0.2), &mut rng)
+            .with_src_path("masters/x");
+        let mirror = cand
+            .hash_with_spatial_distance(bits(0.1), &mut rng)
+            .with_src_path("mirror/x");
+        let other_mirror = cand
+            .hash_with_spatial_distance(bits(0.15), &mut rng)
+            .with_src_path("mirror2/x");
+        let cand = cand.with_src_path("new/x");
+        let opts = SearchOptions::new(Tolerance::new(0.3).expect("valid tolerance"));
+
+        let search = |refs: &[(&VideoHash, u32)]| {
+            let refs = refs
+                .iter()
+                .map(|&(hash, priority)| (hash.clone(), priority));
+            search_with_references_prioritized(refs, [cand.clone()], &opts)
+        };
+        // The reference that the candidate was attached to, and its alternates.
+        fn attachment<'a>(groups: &'a [MatchGroup], cand: &Path) -> (&'a Path, Vec<&'a Path>) {
+            assert_eq!(groups.len(), 1);
+            let group = &groups[0];
+            assert_eq!(group.duplicates().collect::<Vec<_>>(), [cand]);
+            let alternates = group.alternate_references(cand).collect_vec();
+            (group.reference().expect("has a reference"), alternates)
+        }
+
+        //the master is preferred even though the mirror is closer, whatever order they are given in.
+        for refs in [
+            [(&master, 1), (&mirror, 0), (&other_mirror, 0)],
+            [(&other_mirror, 0), (&mirror, 0), (&master, 1)],
+        ] {
+            let groups = search(&refs);
+            let (reference, alternates) = attachment(&groups, cand.src_path());
+            assert_eq!(reference, master.src_path());
+            assert_eq!(alternates, [mirror.src_path(), other_mirror.src_path()]);
+        }
+
+        //between equal priorities the closest wins.
+        let groups = search(&[(&master, 0), (&mirror, 0)]);
+        assert_eq!(
+            attachment(&groups, cand.src_path()),
+            (mirror.src_path(), vec![master.src_path()])
+        );
+
+        //and between equally close references of equal priority, the smallest path.
+        let copy = mirror.with_src_path("another/x");
+        let groups = search(&[(&mirror, 0), (&copy, 0)]);
+        assert_eq!(
+            attachment(&groups, cand.src_path()),
+            (copy.src_path(), vec![mirror.src_path()])
+        );
+
+        //a reference that matches alone has no alternates.
+        let groups = search(&[(&mirror, 0)]);
+        assert_eq!(
+            attachment(&groups, cand.src_path()),
+            (mirror.src_path(), vec![])
+        );
+        assert!(
+            search_with_references_with_opts([mirror.clone()], [cand.clone()], &opts)[0]
+                .alternate_references(cand.src_path())
+                .next()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_groups_share_paths() {
+        let long_path = |name: &str, idx: usize| format!("{}/{name}{idx}", "dir/".repeat(250));
+        let hashes = |name: &str, count: usize| {
+            (0..count)
+                .map(|idx| VideoHash::empty_hash(long_path(name, idx)).with_duration(10))
+                .collect::<Vec<_>>()
+        };
+
+        //every candidate matches every reference, so is in 20 groups.
+        let opts = SearchOptions::new(Tolerance::new(0.1).expect("valid tolerance"));
+        let groups = search_with_references_with_opts(hashes("ref", 20), hashes("new", 50), &opts);
+        assert_eq!(groups.len(), 20);
+        assert!(groups.iter().all(|group| group.len() == 50));
+
+        //the same groups, with a copy of every path in every group.
+        let unshared = groups
+            .iter()
+            .map(|group| {
+                let reference = group.reference().expect("has a reference").to_path_buf();
+                MatchGroup::new_with_reference(reference, group.duplicates().map(Path::to_path_buf))
+                    .expect("has duplicates")
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(groups, unshared);
+
+        let shared_usage = MatchGroup::approx_memory_usage(&groups);
+        let unshared_usage = MatchGroup::approx_memory_usage(&unshared);
+        assert!(
+            shared_usage * 10 < unshared_usage,
+            "{shared_usage} {unshared_usage}"
+        );
+
+        //expanding the groups into pairs does not copy the paths either.
+        let pairs = groups
+            .iter()
+            .flat_map(MatchGroup::dup_combinations)
+            .collect::<Vec<_>>();
+        assert_eq!(pairs.len(), 20 * 50);
+        let pair_usage = MatchGroup::approx_memory_usage(&pairs);
+        assert!(
+            pair_usage < unshared_usage / 2,
+            "{pair_usage} {unshared_usage}"
+        );
+    }
+
+    #[test]
+    fn test_different_hash_sizes_never_match() {
+        //identical (empty) hashes, apart from their size.
+        let hashes = HashSize::ALL
+            .map(|hash_size| {
+                VideoHash::empty_hash_with_size(hash_size.to_string(), hash_size).with_duration(5)
+            })
+            .to_vec();
+
+        assert!(search(hashes.clone(), 1.0).is_empty());
+
+        let (refs, others) = hashes.split_at(1);
+        let groups = search_with_references_with_opts(
+            refs.to_vec(),
+            others.to_vec(),
+            &SearchOptions::with_tolerance(1.0),
+        );
+        assert!(groups.is_empty());
+    }
+
+    #[test]
+    fn test_different_normalizations_never_match() {
+        let normalizations = [
+            Normalization::None,
+            Normalization::HistogramEqualize,
+            Normalization::ContrastStretch {
+                low_pct: 1.0,
+                high_pct: 99.0,
+            },
+            Normalization::ContrastStretch {
+                low_pct: 2.0,
+                high_pct: 99.0,
+            },
+            Normalization::Custom,
+        ];
+        let hashes = normalizations
+            .iter()
+            .enumerate()
+            .map(|(idx, &normalization)| {
+                VideoHash::empty_hash(idx.to_string())
+                    .with_duration(5)
+                    .with_normalization(normalization)
+            })
+            .collect::<Vec<_>>();
+        assert!(search(hashes.clone(), 1.0).is_empty());
+
+        //but hashes normalized in the same way still do.
+        let mut same = hashes.clone();
+        same.push(hashes[2].with_src_path("copy"));
+        assert_eq!(
+            search_with_opts(same, &SearchOptions::with_tolerance(0.0)),
+            [MatchGroup::new([PathBuf::from("2"), PathBuf::from("copy")]).expect("two paths")]
+        );
+    }
+
+    #[test]
+    fn test_duplicated_inputs_are_searched_once() {
+        let mut rng = StdRng::seed_from_u64(11);
+        let a = VideoHash::random_hash(&mut rng)
+            .with_duration(60)
+            .with_src_path("a");
+        let b = a.with_flipped_bits(0..2).with_src_path("b");
+        let c = VideoHash::random_hash(&mut rng)
+            .with_duration(60)
+            .with_src_path("c");
+
+        //the same files found twice by overlapping directories, one of them hashed differently.
+        let hashes = vec![
+            a.clone(),
+            b.clone(),
+            c.clone(),
+            a.clone(),
+            c.with_duration(61),
+            b.clone(),
+        ];
+        let opts = SearchOptions::default();
+        let (groups, stats) = search_with_stats(hashes.clone(), &opts);
+
+        assert_eq!(
+            groups,
+            [MatchGroup::new([PathBuf::from("a"), PathBuf::from("b")]).expect("two paths")]
+        );
+        let duplicate = |path: &str| DuplicateInput {
+            path: PathBuf::from(path),
+            kept_reference: false,
+            discarded_reference: false,
+        };
+        assert_eq!(
+            stats.duplicate_inputs,
+            [duplicate("a"), duplicate("c"), duplicate("b")]
+        );
+
+        //the same groups when the search follows a plan.
+        let plan = plan_search(&hashes, &opts);
+        assert_eq!(search_with_plan(hashes, &plan, &opts), groups);
+    }
+
+    #[test]
+    fn test_duplicated_references_are_searched_once() {
+        let mut rng = StdRng::seed_from_u64(12);
+        let a = VideoHash::random_hash(&mut rng)
+            .with_duration(60)
+            .with_src_path("a");
+        let b = a.with_flipped_bits(0..2).with_src_path("b");
+
+        //a is a reference, so it is not also searched as a candidate.
+        let refs = vec![a.clone(), a.clone()];
+        let cands = vec![a.clone(), b.clone(), b.clone()];
+        let (groups, stats) =
+            search_with_references_with_stats(refs, cands, &SearchOptions::default());
+
+        assert_eq!(
+            groups,
+            [
+                MatchGroup::new_with_reference(PathBuf::from("a"), [PathBuf::from("b")])
+                    .expect("one duplicate")
+            ]
+        );
+        let duplicate = |path: &str, discarded_reference| DuplicateInput {
+            path: PathBuf::from(path),
+            kept_reference: path == "a",
+            discarded_reference,
+        };
+        assert_eq!(
+            stats.duplicate_inputs,
+            [
+                duplicate("a", true),
+                duplicate("a", false),
+                duplicate("b", false)
+            ]
+        );
+    }
+
+    // Small random sets of hashes, many of which are near copies of each other, with unique paths.
+    fn random_hash_set(rng: &mut StdRng) -> Vec<VideoHash> {
+        let mut hashes = vec![];
+        for cluster in 0..rng.gen_range(0..6) {
+            let hash_size = *[HashSize::Five, HashSize::Six]
+                .choose(rng)
+                .expect("not empty");
+            let base = VideoHash::random_hash_with_size(rng, hash_size)
+                .with_duration(rng.gen_range(0..15));
+            for i in 0..rng.gen_range(1..5) {
+                let max_distance = hash_size.bits() * 2 / 5;
+                let distance = rng.gen_range(0..max_distance);
+                let duration = base.duration() + rng.gen_range(0..2);
+                hashes.push(
+                    base.hash_with_spatial_distance(distance, rng)
+                        .with_duration(duration)
+                        .with_src_path(format!("{cluster}_{i}")),
+                );
+            }
+        }
+        hashes
+    }
+
+    #[test]
+    fn test_random_searches_group_consistently() {
+        let mut rng = StdRng::seed_from_u64(13);
+
+        for _ in 0..300 {
+            let hashes = random_hash_set(&mut rng);
+            let opts = SearchOptions::with_tolerance(rng.gen_range(0.0..0.4));
+            let threshold = MatchThreshold::new(&opts);
+            let by_path = |path: &Path| {
+                hashes
+                    .iter()
+                    .find(|hash| hash.src_path() == path)
+                    .expect("groups only contain searched paths")
+            };
+
+            let groups = search_with_opts(hashes.clone(), &opts);
+            let mut grouped = vec![];
+            for group in &groups {
+                let members = group.contained_paths().map(by_path).collect::<Vec<_>>();
+                assert!(members.len() >= 2);
+
+                //every group is built around one video which matches all of the others.
+                assert!(members.iter().any(|center| members
+                    .iter()
+                    .all(|member| member == center || threshold.is_match(center, member))));
+                grouped.extend(members);
+            }
+
+            //no video is in two groups...
+            let num_grouped = grouped.len();
+            grouped.sort();
+            grouped.dedup();
+            assert_eq!(grouped.len(), num_grouped);
+
+            //...and no two videos were left out of every group even though they match.
+            let ungrouped = hashes.iter().filter(|hash| !grouped.contains(hash));
+            for (h1, h2) in ungrouped.tuple_combinations() {
+                assert!(
+                    !(durations_are_comparable(h1.duration(), h2.duration())
+                        && threshold.is_match(h1, h2)),
+                    "{h1:?} and {h2:?} match but were not grouped"
+                );
+            }
+
+            //searching against references finds every candidate which matches each of them.
+            let Some((refs, cands)) = (!hashes.is_empty()).then(|| hashes.split_at(1)) else {
+                continue;
+            };
+            let groups = search_with_references_with_opts(refs.to_vec(), cands.to_vec(), &opts);
+            let expected = cands
+                .iter()
+                .filter(|cand| {
+                    reference_durations(refs[0].duration()).contains(&cand.duration())
+                        && threshold.is_match(&refs[0], cand)
+                })
+                .map(|cand| cand.src_path())
+                .sorted()
+                .collect::<Vec<_>>();
+            assert!(groups.len() <= 1);
+            let mut found = vec![];
+            for group in &groups {
+                assert_eq!(group.reference(), Some(refs[0].src_path()));
+                found.extend(group.duplicates());
+            }
+            found.sort();
+            assert_eq!(found, expected);
+        }
+    }
+
+    #[test]
+    fn test_grouping_modes_on_a_chain() {
+        //a matches b and b matches c, but a and c are too far apart to match.
+        let mut rng = StdRng::seed_from_u64(14);
+        let a = VideoHash::random_hash(&mut rng).with_src_path("a");
+        let b = a.with_flipped_bits(0..50).with_src_path("b");
+        let c = b.with_flipped_bits(50..100).with_src_path("c");
+        let groups = |grouping| {
+            let opts = SearchOptions {
+                grouping,
+                ..SearchOptions::default()
+            };
+            search_with_opts([a.clone(), b.clone(), c.clone()], &opts)
+                .iter()
+                .map(|group| group.contained_paths().map(Path::to_path_buf).collect())
+                .collect::<Vec<Vec<_>>>()
+        };
+        let paths = |names: &[&str]| names.iter().map(PathBuf::from).collect::<Vec<_>>();
+
+        assert_eq!(
+            groups(GroupingMode::ConnectedComponents),
+            [paths(&["a", "b", "c"])]
+        );
+        assert_eq!(
+            groups(GroupingMode::Cliques { min_mutual: 1.0 }),
+            [paths(&["a", "b"]), paths(&["b", "c"])]
+        );
+        //in a group of all three, a and c each match half of the others.
+        assert_eq!(
+            groups(GroupingMode::Cliques { min_mutual: 0.5 }),
+            [paths(&["a", "b", "c"])]
+        );
+        //a is searched first, and c does not match it.
+        assert_eq!(groups(GroupingMode::Centered), [paths(&["a", "b"])]);
+    }
+
+    #[test]
+    fn test_duration_gate() {
+        //the same frames, but one copy has half an hour of black appended.
+        let mut rng = StdRng::seed_from_u64(16);
+        let full = VideoHash::random_hash(&mut rng)
+            .with_duration(600)
+            .with_src_path("full");
+        let padded = full.with_duration(2400).with_src_path("padded");
+        let opts = |duration_gate| SearchOptions {
+            duration_gate,
+            ..SearchOptions::default()
+        };
+        let search = |opts: &SearchOptions| {
+            let groups = search_with_opts([full.clone(), padded.clone()], opts);
+            let refs = search_with_references_with_opts([full.clone()], [padded.clone()], opts);
+            (groups, refs)
+        };
+
+        for gate in [DurationGate::Strict, DurationGate::Loose { max_ratio: 2.0 }] {
+            let (groups, refs) = search(&opts(gate));
+            assert!(groups.is_empty() && refs.is_empty(), "{gate:?}");
+        }
+
+        for gate in [DurationGate::Loose { max_ratio: 4.0 }, DurationGate::Ignore] {
+            let opts = opts(gate);
+            let (groups, refs) = search(&opts);
+            assert_eq!(group_names(&groups), [["full", "padded"]]);
+            assert_eq!(groups[0].duration_spread(), Some((600, 2400)));
+            assert_eq!(refs.len(), 1);
+            assert_eq!(refs[0].duration_spread(), Some((600, 2400)));
+
+            let cliques = SearchOptions {
+                grouping: GroupingMode::Cliques { min_mutual: 1.0 },
+                ..opts.clone()
+            };
+            assert_eq!(search(&cliques).0, groups);
+            assert_eq!(
+                plan_search(&[full.clone(), padded.clone()], &opts).comparisons(),
+                1
+            );
+        }
+
+        //the strict gate still matches close durations, without a spread. A loose gate never
+        //rejects what the strict gate accepts.
+        let close = full.with_duration(610).with_src_path("close");
+        for gate in [DurationGate::Strict, DurationGate::Loose { max_ratio: 1.0 }] {
+            let groups = search_with_opts([full.clone(), close.clone()], &opts(gate));
+            assert_eq!(groups.len(), 1);
+            assert_eq!(
+                groups[0].duration_spread(),
+                (gate != DurationGate::Strict).then_some((600, 610))
+            );
+        }
+    }
+
+    #[test]
+    fn test_random_cliques_cover_every_match() {
+        let mut rng = StdRng::seed_from_u64(15);
+
+        for _ in 0..100 {
+            let hashes = random_hash_set(&mut rng);
+            let opts = SearchOptions {
+                grouping: GroupingMode::Cliques { min_mutual: 1.0 },
+                ..SearchOptions::with_tolerance(rng.gen_range(0.0..0.4))
+            };
+            let threshold = MatchThreshold::new(&opts);
+            let index = |path: &Path| {
+                hashes
+                    .iter()
+                    .position(|hash| hash.src_path() == path)
+                    .expect("groups only contain searched paths")
+            };
+
+            let groups = search_with_opts(hashes.clone(), &opts)
+                .iter()
+                .map(|group| group.contained_paths().map(index).collect::<Vec<_>>())
+                .collect::<Vec<_>>();
+            for group in &groups {
+                for (&i, &j) in group.iter().tuple_combinations() {
+                    assert!(threshold.is_match(&hashes[i], &hashes[j]));
+                }
+            }
+
+            //every comparable pair which matches is grouped together somewhere.
+            for ((i, h1), (j, h2)) in hashes.iter().enumerate().tuple_combinations() {
+                if durations_are_comparable(h1.duration(), h2.duration())
+                    && threshold.is_match(h1, h2)
+                {
+                    assert!(groups.iter().any(|g| g.contains(&i) && g.contains(&j)));
+                }
+            }
+        }
+    }
 }
@@ -0,0 +1,546 @@
+use std::{
+    ops::RangeInclusive,
+    path::{Path, PathBuf},
+};
+
+use thiserror::Error;
+
+use super::search_algorithm::{max_comparable_duration, reference_durations};
+use super::speed_variants::MAX_SPEED_RATIO;
+use crate::{FalseposFilter, ShortVideoPolicy, VerifyOpts, VideoHash, DEFAULT_SEARCH_TOLERANCE};
+
+// The most times as long as each other that DurationGate::Strict compares videos (see
+// max_comparable_duration).
+const STRICT_MAX_RATIO: f64 = 1.1;
+
+/// How different two hashes can be while still matching, between 0.0 and 1.0. A tolerance of 0.0
+/// only matches identical hashes, and 1.0 matches every hash of the same size.
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub struct Tolerance(f64);
+
+/// The error returned when creating a [`Tolerance`] from a value that is not between 0.0 and 1.0.
+#[derive(Error, Debug, Clone, Copy, PartialEq)]
+#[error("Search tolerance must be between 0.0 and 1.0, but got {0}")]
+pub struct ToleranceError(pub f64);
+
+impl Tolerance {
+    /// [`DEFAULT_SEARCH_TOLERANCE`].
+    pub const DEFAULT: Self = Self(DEFAULT_SEARCH_TOLERANCE);
+
+    /// # Errors
+    /// If `tolerance` is NaN, or is not between 0.0 and 1.0 inclusive.
+    pub fn new(tolerance: f64) -> Result<Self, ToleranceError> {
+        if (0.0..=1.0).contains(&tolerance) {
+            Ok(Self(tolerance))
+        } else {
+            Err(ToleranceError(tolerance))
+        }
+    }
+
+    /// The closest valid tolerance to `tolerance`. NaN is treated as 0.0.
+    #[must_use]
+    pub fn clamped(tolerance: f64) -> Self {
+        if tolerance.is_nan() {
+            Self(0.0)
+        } else {
+            Self(tolerance.clamp(0.0, 1.0))
+        }
+    }
+
+    // For functions which still accept a bare f64, so that out of range values are not silently
+    // accepted.
+    pub(crate) fn clamped_with_warning(tolerance: f64) -> Self {
+        let clamped = Self::clamped(tolerance);
+        if clamped.0 != tolerance {
+            log::warn!("Search tolerance {tolerance} is not between 0.0 and 1.0. Using {clamped}");
+        }
+        clamped
+    }
+
+    #[must_use]
+    pub const fn value(self) -> f64 {
+        self.0
+    }
+}
+
+impl Default for Tolerance {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+impl TryFrom<f64> for Tolerance {
+    type Error = ToleranceError;
+
+    fn try_from(tolerance: f64) -> Result<Self, Self::Error> {
+        Self::new(tolerance)
+    }
+}
+
+impl std::fmt::Display for Tolerance {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// The order in which groups are returned from a search.
+///
+/// Except for [`GroupOrdering::Unordered`], the paths within each group are also sorted, so the
+/// output of a search does not depend on the order in which hashes were supplied.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub enum GroupOrdering {
+    /// Largest groups first. Groups of the same size are ordered as [`GroupOrdering::ByPath`].
+    BySize,
+    /// Groups whose videos are most similar first, using the largest distance between any two videos
+    /// in the group. Groups with the same distance are ordered as [`GroupOrdering::ByPath`].
+    ///
+    /// The distances are calculated while searching, which takes a little extra time.
+    ByDistance,
+    /// Ordered by the smallest path contained in each group.
+    #[default]
+    ByPath,
+    /// Whatever order the search produced the groups in. This is the fastest option, but the order is
+    /// an implementation detail which may change.
+    Unordered,
+}
+
+/// Which groups of duplicates are reported, according to the collections their hashes belong to
+/// (see [`VideoHash::with_collection`][crate::VideoHash::with_collection]).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub enum CollectionMode {
+    /// Every group is reported.
+    #[default]
+    All,
+    /// Only groups containing hashes from at least two different collections are reported, to find
+    /// duplicates between (for example) two machines but not within either of them. Hashes without a
+    /// collection count as one more collection.
+    CrossCollectionOnly,
+}
+
+/// How the pairs of matching videos found by a search of hashes against each other (such as
+/// [`crate::search_with_opts`]) are made into groups.
+///
+/// Searches with reference videos always group each reference with every video that matches it.
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Default)]
+pub enum GroupingMode {
+    /// Videos are taken in order of duration, and each one is grouped with every ungrouped video
+    /// that matches it. The members of a group match its first video, but not necessarily each
+    /// other, and each video is in at most one group.
+    #[default]
+    Centered,
+
+    /// Each group is a connected component of the graph of matching pairs: if A matches B and B
+    /// matches C then all three are grouped, even if A and C are far apart. Long chains of loosely
+    /// related videos (such as the episodes of a series which share an opening sequence) can end up
+    /// in one group.
+    ConnectedComponents,
+
+    /// Each group is a maximal clique of the graph of matching pairs, so that every member of a
+    /// group matches every other member. A video may be in more than one group.
+    ///
+    /// If `min_mutual` is below 1.0, overlapping groups are then merged for as long as every member
+    /// of the merged group matches at least that fraction of the other members.
+    ///
+    /// Finding every maximal clique can take exponential time. Each connected component of the
+    /// graph is given a fixed budget of steps, and a component which needs more is instead split
+    /// into disjoint cliques greedily, starting from the videos with the most matches. A warning is
+    /// logged when this happens.
+    Cliques { min_mutual: f64 },
+}
+
+/// A restriction on which videos a search groups together or reports, for
+/// [`SearchOptions::constraints`]. Paths are compared component by component, so a rule for
+/// `/videos/archive` applies to `/videos/archive/a.mp4` but not to `/videos/archived.mp4`.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ConstraintRule {
+    /// Two videos which are both inside this directory never match each other, as if the pair
+    /// were in the [`SearchOptions::falsepos_filter`]. They can still end up in the same group if
+    /// a video outside the directory matches both of them, except with [`GroupingMode::Cliques`],
+    /// where every member of a group must match every other.
+    NeverGroupWithin(PathBuf),
+
+    /// Only groups containing at least one video inside this directory are reported. With several
+    /// of these rules, a group only needs a video inside one of their directories.
+    OnlyReportGroupsContaining(PathBuf),
+}
+
+/// Whether a search uses the audio fingerprints of the hashes (see
+/// [`CreationOptions::audio_fingerprint`][crate::CreationOptions::audio_fingerprint]). Pairs where
+/// either hash has no fingerprint are never affected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub enum AudioCheck {
+    /// Audio fingerprints are ignored.
+    #[default]
+    Off,
+    /// Videos are matched by their frames alone, but each group records whether the audio of each
+    /// pair of its videos agrees (see [`MatchGroup::audio_agrees`][crate::MatchGroup::audio_agrees]).
+    Annotate,
+    /// As [`AudioCheck::Annotate`], but pairs whose audio disagrees never match. A dubbed copy of a
+    /// video (or one with a different soundtrack) is then not reported as a duplicate.
+    RequireAgreement,
+}
+
+/// Whether the frames of pairs of videos with (almost) identical hashes are compared, for
+/// [`SearchOptions::zero_distance_check`].
+///
+/// Different videos which are very short or have very little detail (such as two clips of black
+/// frames) can have exactly the same hash. Comparing their frames tells them apart from real
+/// copies, so that they are not deleted automatically. See [`crate::ZeroDistanceVerification`].
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Default)]
+pub enum ZeroDistanceCheck {
+    /// Groups are made from the hashes alone.
+    #[default]
+    Off,
+    /// For each pair of videos in a group whose normalized distance is at most `epsilon`, a few
+    /// of the frames that their hashes were made from are decoded again and compared pixel by
+    /// pixel, and the outcome is recorded in the group (see
+    /// [`MatchGroup::zero_distance_verification`][crate::MatchGroup::zero_distance_verification]).
+    /// This does not change which videos are grouped.
+    ///
+    /// The videos are decoded with the same backend that hashes them, so searches take longer
+    /// the more such pairs there are. Without a backend, every pair is recorded as
+    /// [`NotChecked`][crate::ZeroDistanceVerification::NotChecked].
+    Verify { epsilon: f64 },
+}
+
+/// How different the durations of two videos can be while they are still compared, for
+/// [`SearchOptions::duration_gate`].
+///
+/// Searches sort the hashes by duration and only compare each video with those of similar length,
+/// which is what keeps large searches fast. Loosening the gate finds videos that show the same
+/// content but are of very different lengths (such as a recording with half an hour of black frames
+/// appended), at the cost of more comparisons.
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Default)]
+pub enum DurationGate {
+    /// Videos are only compared with those up to 10% longer (or, when searching against
+    /// references, those within 5% of the reference's duration).
+    #[default]
+    Strict,
+    /// Videos are compared with those up to `max_ratio` times as long. A ratio below that of
+    /// [`DurationGate::Strict`] (or NaN) is treated as [`DurationGate::Strict`].
+    Loose { max_ratio: f64 },
+    /// Every pair of videos is compared, however different their durations. Searches then compare
+    /// every pair of hashes, so this is slow for large searches.
+    Ignore,
+}
+
+impl DurationGate {
+    // The longest duration compared with a video of the given duration.
+    pub(crate) fn max_comparable(self, duration: u32) -> u32 {
+        let strict = max_comparable_duration(duration);
+        match self {
+            Self::Strict => strict,
+            Self::Loose { max_ratio } => ((f64::from(duration) * max_ratio) as u32).max(strict),
+            Self::Ignore => u32::MAX,
+        }
+    }
+
+    // Whether videos of these durations are compared, whichever is longer.
+    pub(crate) fn allows(self, d1: u32, d2: u32) -> bool {
+        d1.max(d2) <= self.max_comparable(d1.min(d2))
+    }
+
+    // The gate widened so that videos are also compared with those up to `factor` times as long or
+    // as short as the videos that the gate allows.
+    fn widened(self, factor: f64) -> Self {
+        match self {
+            Self::Strict => Self::Loose {
+                max_ratio: STRICT_MAX_RATIO * factor,
+            },
+            Self::Loose { max_ratio } => Self::Loose {
+                max_ratio: max_ratio.max(STRICT_MAX_RATIO) * factor,
+            },
+            Self::Ignore => Self::Ignore,
+        }
+    }
+
+    // The durations compared with a reference video of the given duration.
+    pub(crate) fn reference_durations(self, duration: u32) -> RangeInclusive<u32> {
+        let strict = reference_durations(duration);
+        match self {
+            Self::Strict => strict,
+            Self::Loose { max_ratio } => {
+                let shortest = (f64::from(duration) / max_ratio) as u32;
+                let longest = (f64::from(duration) * max_ratio) as u32;
+                shortest.min(*strict.start())..=longest.max(*strict.end())
+            }
+            Self::Ignore => 0..=u32::MAX,
+        }
+    }
+}
+
+/// A guard against matching videos by their black borders, for [`SearchOptions::active_area`].
+///
+/// The frames of a heavily letterboxed (or pillarboxed) video hashed without
+/// [`CreationOptions::cropdetect`][crate::CreationOptions::cropdetect] are mostly black, so most
+/// of its hash describes the borders rather than the picture, and it can match unrelated videos
+/// with similar borders. Each hash records the fraction of its frames that is not black (see
+/// [`VideoHash::active_area`]), and pairs whose active areas are too different, or which overlap
+/// too little, never match. This is much cheaper than hashing with crop detection, but only
+/// compares the amount of black in the frames, not the picture inside the borders.
+///
+/// Pairs where either hash has no active area (because it was created before they were recorded)
+/// are never affected.
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub struct ActiveAreaGuard {
+    /// Pairs whose active areas differ by more than this fraction of the frame never match.
+    pub max_difference: f64,
+    /// Pairs where the smaller of the two active areas (which is the area that is active in both,
+    /// when the pictures are centred as letterboxing is) is below this fraction of the frame never
+    /// match.
+    pub min_overlap: f64,
+}
+
+impl ActiveAreaGuard {
+    /// The [`ActiveAreaGuard::max_difference`] of [`ActiveAreaGuard::new`]. Copies of a video
+    /// with the same borders have almost exactly the same active area, and fades or dark scenes in
+    /// the sampled frames only move it a few hundredths.
+    pub const DEFAULT_MAX_DIFFERENCE: f64 = 0.1;
+
+    /// A guard which rejects pairs whose active areas overlap by less than `min_overlap` of the
+    /// frame, or differ by more than [`ActiveAreaGuard::DEFAULT_MAX_DIFFERENCE`].
+    #[must_use]
+    pub const fn new(min_overlap: f64) -> Self {
+        Self {
+            max_difference: Self::DEFAULT_MAX_DIFFERENCE,
+            min_overlap,
+        }
+    }
+
+    // The active areas of the pair, if the guard stops them from matching.
+    pub(crate) fn rejects(self, h1: &VideoHash, h2: &VideoHash) -> Option<(f64, f64)> {
+        let (a1, a2) = (h1.active_area()?, h2.active_area()?);
+        ((a1 - a2).abs() > self.max_difference || a1.min(a2) < self.min_overlap).then_some((a1, a2))
+    }
+}
+
+/// Options controlling how a search is performed, for use with [`crate::search_with_opts`]
+/// and [`crate::search_with_references_with_opts`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchOptions {
+    /// How different two hashes can be while still matching.
+    ///
+    /// Searches at tolerances of about 0.1 and below are faster, because most pairs of unrelated
+    /// videos are ruled out by comparing a small part of their hashes. Above that, including at
+    /// the default tolerance, that part of two hashes can never differ by enough to rule them
+    /// out, so every pair is compared in full as it always was.
+    pub tolerance: Tolerance,
+
+    /// How pairs of videos that are both shorter than the hash duration are compared.
+    pub short_video_policy: ShortVideoPolicy,
+
+    /// Pairs of videos that must never be reported as duplicates of each other.
+    pub falsepos_filter: FalseposFilter,
+
+    /// The order in which groups are returned.
+    pub ordering: GroupOrdering,
+
+    /// The number of sampled frames by which two videos are allowed to be out of step, to match
+    /// copies with a second or so cut from the start. See [`SearchOptions::temporal_slack`].
+    pub temporal_slack: u32,
+
+    /// Which groups are reported, according to the collections of their hashes.
+    pub collections: CollectionMode,
+
+    /// Only compare the bits of each pair of hashes which are reliable in at least one of them, so
+    /// that videos which share a plain opening title are not matched on that alone. The distance is
+    /// scaled up by the proportion of bits that were compared, and pairs where fewer than a quarter
+    /// of the bits could be compared never match.
+    ///
+    /// This only applies to pairs whose hashes were both created with
+    /// [`CreationOptions::reliability_mask`][crate::CreationOptions::reliability_mask]. Other pairs
+    /// are compared as usual.
+    pub ignore_unreliable_bits: bool,
+
+    /// How matching pairs are made into groups.
+    ///
+    /// Pairs suppressed by the [`SearchOptions::falsepos_filter`] are not treated as matching, so
+    /// in [`GroupingMode::Cliques`] they are never grouped together.
+    pub grouping: GroupingMode,
+
+    /// Restrictions on which videos are grouped together and which groups are reported, applied
+    /// while the groups are built.
+    pub constraints: Vec<ConstraintRule>,
+
+    /// Whether the audio of matching videos is compared as well.
+    pub audio_check: AudioCheck,
+
+    /// Which pairs of videos are compared, according to their durations. Unless this is
+    /// [`DurationGate::Strict`], each group records the durations of its shortest and longest
+    /// videos (see [`MatchGroup::duration_spread`][crate::MatchGroup::duration_spread]).
+    pub duration_gate: DurationGate,
+
+    /// Whether the frames of pairs of videos with (almost) identical hashes are compared, to catch
+    /// different videos which happen to have the same hash.
+    pub zero_distance_check: ZeroDistanceCheck,
+
+    /// If set, the frames of every pair of videos in each group are compared by their SSIM with
+    /// [`crate::verify_match`], and the reports are recorded in the group (see
+    /// [`MatchGroup::match_verification`][crate::MatchGroup::match_verification]). This does not
+    /// change which videos are grouped, but pairs which fail can be removed with
+    /// [`MatchGroup::without_failed_verifications`][crate::MatchGroup::without_failed_verifications].
+    ///
+    /// Every video in every group is decoded again, so this is much slower than the search itself
+    /// when there are many groups. Pairs that cannot be decoded (and every pair, without a video
+    /// backend) are not recorded.
+    pub verify_matches: Option<VerifyOpts>,
+
+    /// Directories whose videos must never be removed, such as the originals from a camera. This
+    /// does not change which videos are grouped, but each group records which of its videos are
+    /// protected (see [`MatchGroup::protected_members`][crate::MatchGroup::protected_members]),
+    /// and [`crate::pick_best`] never chooses them as losers. Paths are compared component by
+    /// component, as for [`SearchOptions::constraints`].
+    pub protected: Vec<PathBuf>,
+
+    /// If set, pairs of videos whose frames have too different an amount of black, or too little
+    /// that is not black, never match. See [`ActiveAreaGuard`].
+    pub active_area: Option<ActiveAreaGuard>,
+
+    /// Also match videos which are copies of each other played at a different speed, if either
+    /// hash was created with a variant at that speed (see
+    /// [`CreationOptions::speed_variants`][crate::CreationOptions::speed_variants]). The variant
+    /// is compared with the usual bits of the other hash, and the duration gate is applied to the
+    /// duration of the video as if it were played at that speed. Each group records the speed of
+    /// the pairs matched this way (see [`MatchGroup::speed_factor`][crate::MatchGroup::speed_factor]).
+    ///
+    /// Videos are compared with others up to twice as long or as short as the duration gate
+    /// allows, so searches are slower. Pairs that do not match at a different speed are still only
+    /// matched within the duration gate. Off by default.
+    pub speed_variants: bool,
+}
+
+impl Default for SearchOptions {
+    fn default() -> Self {
+        Self {
+            tolerance: Tolerance::DEFAULT,
+            short_video_policy: ShortVideoPolicy::default(),
+            falsepos_filter: FalseposFilter::default(),
+            ordering: GroupOrdering::default(),
+            temporal_slack: 0,
+            collections: CollectionMode::default(),
+            ignore_unreliable_bits: false,
+            grouping: GroupingMode::default(),
+            constraints: vec![],
+            audio_check: AudioCheck::default(),
+            duration_gate: DurationGate::default(),
+            zero_distance_check: ZeroDistanceCheck::default(),
+            verify_matches: None,
+            protected: vec![],
+            active_area: None,
+            speed_variants: false,
+        }
+    }
+}
+
+impl SearchOptions {
+    /// The default options, but with the given tolerance.
+    #[must_use]
+    pub fn new(tolerance: Tolerance) -> Self {
+        Self {
+            tolerance,
+            ..Self::default()
+        }
+    }
+
+    /// As [`SearchOptions::new`], but a tolerance outside of 0.0 to 1.0 is clamped (with a
+    /// warning) instead of being rejected.
+    #[deprecated(note = "use SearchOptions::new with a validated Tolerance")]
+    #[must_use]
+    pub fn with_tolerance(tolerance: f64) -> Self {
+        Self::new(Tolerance::clamped_with_warning(tolerance))
+    }
+
+    /// Also match pairs of videos whose sampled frames are the same once one video is shifted by up to
+    /// `frames` frames. With the default [`CreationOptions`][crate::CreationOptions], frames are sampled
+    /// 6.4 times a second.
+    ///
+    /// This only applies to pairs whose hashes were both created with
+    /// [`CreationOptions::frame_hashes`][crate::CreationOptions::frame_hashes], and which are already
+    /// close to matching. It makes searches slower, and 0 (the default) turns it off.
+    #[must_use]
+    pub fn temporal_slack(self, frames: u32) -> Self {
+        Self {
+            temporal_slack: frames,
+            ..self
+        }
+    }
+
+    /// Protect the videos inside each of the given directories (see
+    /// [`SearchOptions::protected`][field@SearchOptions::protected]), in addition to any that are
+    /// already protected.
+    #[must_use]
+    pub fn protected(mut self, dirs: impl IntoIterator<Item = impl Into<PathBuf>>) -> Self {
+        self.protected.extend(dirs.into_iter().map(Into::into));
+        self
+    }
+
+    // The gate deciding which pairs of videos are compared at all. Pairs matched at a different
+    // speed can be further apart in duration than SearchOptions::duration_gate allows.
+    pub(crate) fn candidate_gate(&self) -> DurationGate {
+        if self.speed_variants {
+            self.duration_gate.widened(MAX_SPEED_RATIO)
+        } else {
+            self.duration_gate
+        }
+    }
+
+    // Whether a pair of matching videos must not be grouped by that match alone.
+    pub(crate) fn is_suppressed(&self, a: &Path, b: &Path) -> bool {
+        self.falsepos_filter.is_suppressed(a, b)
+            || self.constraints.iter().any(|rule| match rule {
+                ConstraintRule::NeverGroupWithin(dir) => a.starts_with(dir) && b.starts_with(dir),
+                ConstraintRule::OnlyReportGroupsContaining(_) => false,
+            })
+    }
+
+    // Whether a video is inside one of the protected directories.
+    pub(crate) fn is_protected(&self, path: &Path) -> bool {
+        self.protected.iter().any(|dir| path.starts_with(dir))
+    }
+
+    // Whether a group containing these paths may be reported.
+    pub(crate) fn allows_group<'a>(&self, mut paths: impl Iterator<Item = &'a Path>) -> bool {
+        let required = self
+            .constraints
+            .iter()
+            .filter_map(|rule| match rule {
+                ConstraintRule::OnlyReportGroupsContaining(dir) => Some(dir),
+                ConstraintRule::NeverGroupWithin(_) => None,
+            })
+            .collect::<Vec<_>>();
+        required.is_empty() || paths.any(|path| required.iter().any(|dir| path.starts_with(dir)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_tolerance_bounds() {
+        assert_eq!(Tolerance::new(0.0).map(Tolerance::value), Ok(0.0));
+        assert_eq!(Tolerance::new(1.0).map(Tolerance::value), Ok(1.0));
+        assert_eq!(
+            Tolerance::new(1.0 + f64::EPSILON),
+            Err(ToleranceError(1.0 + f64::EPSILON))
+        );
+        assert_eq!(Tolerance::new(-0.1), Err(ToleranceError(-0.1)));
+        assert_eq!(Tolerance::new(7.3), Err(ToleranceError(7.3)));
+        assert!(Tolerance::new(f64::NAN).is_err());
+        assert!(Tolerance::new(f64::INFINITY).is_err());
+    }
+
+    #[test]
+    fn test_tolerance_clamping() {
+        assert_eq!(Tolerance::clamped(0.0).value(), 0.0);
+        assert_eq!(Tolerance::clamped(1.0).value(), 1.0);
+        assert_eq!(Tolerance::clamped(0.3).value(), 0.3);
+        assert_eq!(Tolerance::clamped(7.3).value(), 1.0);
+        assert_eq!(Tolerance::clamped(-1.0).value(), 0.0);
+        assert_eq!(Tolerance::clamped(f64::NAN).value(), 0.0);
+
+        #[allow(deprecated)]
+        let opts = SearchOptions::with_tolerance(-1.0);
+        assert_eq!(opts.tolerance.value(), 0.0);
+    }
+}
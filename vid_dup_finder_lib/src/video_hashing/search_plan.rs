@@ -0,0 +1,316 @@
+//! Estimating how long a search will take before starting it.
+//!
+//! A search only compares videos whose durations are within 10% of each other, so its cost is
+//! dominated by the largest groups of videos with similar durations. [`plan_search`] sorts the
+//! hashes into the order that a search would, and reports how many comparisons the search will
+//! make, how long they are likely to take, and which durations account for the most videos.
+//!
+//! ```
+//! use vid_dup_finder_lib::*;
+//! # fn load_hashes() -> Vec<VideoHash> { vec![] }
+//!
+//! let hashes = load_hashes();
+//! let opts = SearchOptions::default();
+//!
+//! let plan = plan_search(&hashes, &opts);
+//! println!("{} comparisons, about {:?}", plan.comparisons(), plan.estimated_time());
+//! for bucket in plan.largest_buckets(5) {
+//!     println!("{} videos of {}s, such as {:?}", bucket.len, bucket.duration, bucket.example_paths);
+//! }
+//!
+//! // The hashes do not need to be sorted again.
+//! let groups = search_with_plan(hashes, &plan, &opts);
+//! ```
+use std::{
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use super::search_algorithm::{search_order, Search};
+use crate::{DurationGate, SearchOptions, VideoHash};
+
+/// The number of example paths kept for each [`DurationBucket`].
+pub const EXAMPLE_PATHS: usize = 3;
+
+//Calibration searches an evenly spaced sample of at most this many hashes, all given the same
+//duration so that every pair of them is compared.
+const CALIBRATION_SAMPLE_SIZE: usize = 256;
+
+//The calibration search is repeated until at least this much time has passed, so that the
+//resolution of the clock does not matter.
+const MIN_CALIBRATION_TIME: Duration = Duration::from_millis(20);
+
+/// All of the videos in a search with the same duration (in whole seconds).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DurationBucket {
+    /// The duration of the videos, in seconds.
+    pub duration: u32,
+
+    /// The number of videos.
+    pub len: usize,
+
+    /// The paths of up to [`EXAMPLE_PATHS`] of the videos.
+    pub example_paths: Vec<PathBuf>,
+}
+
+/// The cost of searching a set of hashes, returned by [`plan_search`].
+///
+/// A plan can also be passed to [`crate::search_with_plan`] so that the hashes are not sorted again.
+#[derive(Debug, Clone)]
+pub struct SearchPlan {
+    //indices of the hashes, in search order.
+    order: Vec<usize>,
+    buckets: Vec<DurationBucket>,
+    comparisons: u64,
+    //None if there were too few hashes to calibrate with.
+    comparisons_per_sec: Option<f64>,
+}
+
+/// Work out the cost of searching `hashes` for duplicates of each other with the given options.
+///
+/// This includes a short calibration search of a sample of the hashes (taking a few tens of
+/// milliseconds) to estimate how quickly they can be compared on this machine.
+#[must_use]
+pub fn plan_search(hashes: &[VideoHash], opts: &SearchOptions) -> SearchPlan {
+    let mut order = (0..hashes.len()).collect::<Vec<_>>();
+    order.sort_by(|&i1, &i2| search_order(&hashes[i1], &hashes[i2]));
+
+    let durations = order
+        .iter()
+        .map(|&idx| hashes[idx].duration())
+        .collect::<Vec<_>>();
+
+    SearchPlan {
+        buckets: buckets(hashes, &order),
+        comparisons: comparisons(&durations, opts.candidate_gate()),
+        comparisons_per_sec: calibrate(hashes, opts),
+        order,
+    }
+}
+
+impl SearchPlan {
+    /// The number of hashes that the plan was made for.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.order.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.order.is_empty()
+    }
+
+    /// Every duration bucket, from shortest to longest duration.
+    #[must_use]
+    pub fn buckets(&self) -> &[DurationBucket] {
+        &self.buckets
+    }
+
+    /// The `n` buckets with the most videos, largest first.
+    #[must_use]
+    pub fn largest_buckets(&self, n: usize) -> Vec<&DurationBucket> {
+        let mut buckets = self.buckets.iter().collect::<Vec<_>>();
+        buckets.sort_by(|b1, b2| b2.len.cmp(&b1.len).then(b1.duration.cmp(&b2.duration)));
+        buckets.truncate(n);
+        buckets
+    }
+
+    /// The number of pairs of hashes that a search will compare, if none of them match.
+    ///
+    /// Once a video has been grouped it is not compared again, so a search of hashes with many
+    /// duplicates makes fewer comparisons than this.
+    #[must_use]
+    pub fn comparisons(&self) -> u64 {
+        self.comparisons
+    }
+
+    /// An estimate of how long comparing [`SearchPlan::comparisons`] pairs of hashes will take, or
+    /// None if there were too few hashes to measure how quickly they can be compared.
+    #[must_use]
+    pub fn estimated_time(&self) -> Option<Duration> {
+        if self.comparisons == 0 {
+            return Some(Duration::ZERO);
+        }
+        self.comparisons_per_sec
+            .map(|rate| Duration::from_secs_f64(self.comparisons as f64 / rate))
+    }
+
+    // Whether these are the hashes the plan was made for, or at least are in search order when
+    // rearranged as the plan would.
+    pub(super) fn fits(&self, hashes: &[VideoHash]) -> bool {
+        if hashes.len() != self.order.len() {
+            return false;
+        }
+
+        let mut seen = vec![false; hashes.len()];
+        let is_permutation = self
+            .order
+            .iter()
+            .all(|&idx| idx < seen.len() && !std::mem::replace(&mut seen[idx], true));
+
+        is_permutation
+            && self
+                .order
+                .windows(2)
+                .all(|w| search_order(&hashes[w[0]], &hashes[w[1]]).is_le())
+    }
+
+    // The hashes in search order, leaving out those which are not to be kept. They must fit the
+    // plan.
+    pub(super) fn sorted(&self, hashes: Vec<VideoHash>, keep: &[bool]) -> Vec<VideoHash> {
+        let mut slots = hashes.into_iter().map(Some).collect::<Vec<_>>();
+        self.order
+            .iter()
+            .filter(|&&idx| keep[idx])
+            .map(|&idx| slots[idx].take().expect("hashes fit the plan"))
+            .collect()
+    }
+}
+
+fn buckets(hashes: &[VideoHash], order: &[usize]) -> Vec<DurationBucket> {
+    let mut buckets: Vec<DurationBucket> = vec![];
+    for hash in order.iter().map(|&idx| &hashes[idx]) {
+        match buckets.last_mut() {
+            Some(bucket) if bucket.duration == hash.duration() => bucket.len += 1,
+            _ => buckets.push(DurationBucket {
+                duration: hash.duration(),
+                len: 1,
+                example_paths: vec![],
+            }),
+        }
+
+        let bucket = buckets.last_mut().expect("a bucket was just pushed");
+        if bucket.example_paths.len() < EXAMPLE_PATHS {
+            bucket.example_paths.push(hash.src_path().to_path_buf());
+        }
+    }
+    buckets
+}
+
+// Each video is compared with every later video in the sorted durations, up to the first one too
+// long to be comparable.
+fn comparisons(sorted_durations: &[u32], gate: DurationGate) -> u64 {
+    let mut rhs = 0;
+    sorted_durations
+        .iter()
+        .enumerate()
+        .map(|(lhs, &duration)| {
+            let max_duration = gate.max_comparable(duration);
+            rhs = rhs.max(lhs + 1);
+            while sorted_durations
+                .get(rhs)
+                .is_some_and(|&d| d <= max_duration)
+            {
+                rhs += 1;
+            }
+            (rhs - lhs - 1) as u64
+        })
+        .sum()
+}
+
+fn calibrate(hashes: &[VideoHash], opts: &SearchOptions) -> Option<f64> {
+    let step = hashes.len().div_ceil(CALIBRATION_SAMPLE_SIZE).max(1);
+    let mut sample = hashes
+        .iter()
+        .step_by(step)
+        .map(|hash| {
+            let mut hash = hash.clone();
+            hash.duration = 0;
+            hash
+        })
+        .collect::<Vec<_>>();
+
+    if sample.len() < 2 {
+        return None;
+    }
+    sample.sort_by(search_order);
+
+    let start = Instant::now();
+    let mut comparisons = 0;
+    while start.elapsed() < MIN_CALIBRATION_TIME || comparisons == 0 {
+        let mut search = Search::presorted(sample.clone());
+        search.search_self(opts);
+        comparisons += search.stats().comparisons;
+    }
+
+    Some(comparisons as f64 / start.elapsed().as_secs_f64())
+}
+
+#[cfg(test)]
+mod test {
+    use rand::prelude::*;
+
+    use super::*;
+    use crate::{search_with_opts, search_with_plan, search_with_stats};
+
+    fn hashes() -> Vec<VideoHash> {
+        let mut rng = StdRng::seed_from_u64(3);
+        //a bucket of 5 at 60s, 2 at 61s (comparable with 60s), 1 at 30s, and 2 at 1000s. Buckets of
+        //the same size are ordered by duration.
+        [61, 60, 1000, 60, 30, 60, 61, 60, 1000, 60]
+            .into_iter()
+            .enumerate()
+            .map(|(idx, duration)| {
+                VideoHash::random_hash(&mut rng)
+                    .with_duration(duration)
+                    .with_src_path(format!("{idx}"))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_plan_reports_buckets_and_comparisons() {
+        let hashes = hashes();
+        let opts = SearchOptions::default();
+        let plan = plan_search(&hashes, &opts);
+
+        let sizes = plan
+            .buckets()
+            .iter()
+            .map(|bucket| (bucket.duration, bucket.len))
+            .collect::<Vec<_>>();
+        assert_eq!(sizes, [(30, 1), (60, 5), (61, 2), (1000, 2)]);
+
+        let largest = plan.largest_buckets(2);
+        assert_eq!(largest.len(), 2);
+        assert_eq!((largest[0].duration, largest[1].duration), (60, 61));
+        assert_eq!(
+            largest[0].example_paths,
+            [PathBuf::from("1"), PathBuf::from("3"), PathBuf::from("5")]
+        );
+
+        //the random hashes never match, so the plan is exact.
+        let (groups, stats) = search_with_stats(hashes, &opts);
+        assert!(groups.is_empty());
+        assert_eq!(plan.comparisons(), stats.comparisons);
+        assert_eq!(plan.comparisons(), 7 * 6 / 2 + 1);
+        assert!(plan.estimated_time().is_some());
+
+        let empty = plan_search(&[], &opts);
+        assert!(empty.is_empty());
+        assert_eq!(empty.estimated_time(), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn test_search_with_plan() {
+        let mut rng = StdRng::seed_from_u64(4);
+        let mut hashes = hashes();
+        let dup = hashes[3].with_flipped_bits(0..2).with_src_path("dup");
+        hashes.push(dup);
+        hashes.shuffle(&mut rng);
+
+        let opts = SearchOptions::default();
+        let plan = plan_search(&hashes, &opts);
+        let expected = search_with_opts(hashes.clone(), &opts);
+        assert_eq!(expected.len(), 1);
+        assert!(plan.fits(&hashes));
+        assert_eq!(search_with_plan(hashes.clone(), &plan, &opts), expected);
+
+        //a plan for other hashes is not used, but the search still works.
+        let mut reordered = hashes.clone();
+        reordered.reverse();
+        assert!(!plan.fits(&reordered));
+        assert!(!plan.fits(&hashes[1..]));
+        assert_eq!(search_with_plan(reordered, &plan, &opts), expected);
+    }
+}
@@ -0,0 +1,533 @@
+//! Skipping candidates which were already known to be unique in a previous reference search.
+//!
+//! A library of reference videos is often checked against a mostly unchanged set of candidates,
+//! over and over. [`search_with_references_incremental`] takes the [`SearchSnapshot`] of the
+//! previous run, and any candidate which was unique last time is reported as unique again without
+//! being compared, so long as neither the candidate's hash nor the references (and the options
+//! they are searched with) have changed since.
+//!
+//! Changes are detected with 128-bit fingerprints, so a stale result could only be reused after a
+//! fingerprint collision.
+//!
+//! ```
+//! use vid_dup_finder_lib::*;
+//! # fn load_hashes() -> (Vec<VideoHash>, Vec<VideoHash>) { (vec![], vec![]) }
+//!
+//! let (references, candidates) = load_hashes();
+//! let opts = SearchOptions::default();
+//!
+//! let results =
+//!     search_with_references_incremental(references.clone(), candidates.clone(), &opts, None);
+//! let snapshot = SearchSnapshot::from_results(&results);
+//! let saved = serde_json::to_string(&snapshot).unwrap();
+//!
+//! // ...a week later
+//! let snapshot: SearchSnapshot = serde_json::from_str(&saved).unwrap();
+//! let results = search_with_references_incremental(references, candidates, &opts, Some(&snapshot));
+//! for group in results.groups() {
+//!     println!("{group:?}")
+//! }
+//! ```
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    search_with_references_with_stats, AudioCheck, CollectionMode, DurationGate, MatchGroup,
+    SearchOptions, SearchStats, ShortVideoPolicy, VideoHash,
+};
+
+/// A collision-resistant 128-bit digest, identifying a hash or a set of references.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+struct Fingerprint(u128);
+
+// Feeds values into a digest with a fixed encoding, so that fingerprints are identical on every
+// platform. Variable length values are length prefixed so that adjacent values can't run together.
+struct Fingerprinter(Sha256);
+
+impl Fingerprinter {
+    fn new() -> Self {
+        Self(Sha256::new())
+    }
+
+    fn u64(&mut self, val: u64) -> &mut Self {
+        self.0.update(val.to_le_bytes());
+        self
+    }
+
+    fn bytes(&mut self, val: &[u8]) -> &mut Self {
+        self.u64(val.len() as u64);
+        self.0.update(val);
+        self
+    }
+
+    fn words(&mut self, vals: Option<&[u64]>) -> &mut Self {
+        match vals {
+            None => self.u64(0),
+            Some(vals) => {
+                self.u64(1).u64(vals.len() as u64);
+                for &val in vals {
+                    self.u64(val);
+                }
+                self
+            }
+        }
+    }
+
+    fn path(&mut self, path: &Path) -> &mut Self {
+        self.bytes(path.as_os_str().as_encoded_bytes())
+    }
+
+    fn finish(&mut self) -> Fingerprint {
+        let digest = self.0.finalize_reset();
+        let mut bytes = [0; 16];
+        bytes.copy_from_slice(&digest[..16]);
+        Fingerprint(u128::from_le_bytes(bytes))
+    }
+}
+
+impl Fingerprint {
+    // Everything a search looks at when comparing the hash with another. The path is not included
+    // because snapshots are keyed by path.
+    fn of_hash(hash: &VideoHash) -> Self {
+        let audio = hash.audio_fingerprint.as_ref().map(|audio| {
+            audio
+                .slices()
+                .iter()
+                .map(|&slice| u64::from(slice))
+                .collect::<Vec<_>>()
+        });
+
+        let mut fp = Fingerprinter::new();
+        fp.u64(hash.speed_variants.len() as u64);
+        for variant in &hash.speed_variants {
+            fp.u64(variant.factor as u64).words(Some(&variant.bits));
+        }
+        fp.u64(u64::from(hash.hash_size.bits()))
+            .words(Some(&hash.hash))
+            .u64(u64::from(hash.duration))
+            .u64(u64::from(hash.short_video))
+            .words(hash.frame_hashes.as_deref())
+            .words(hash.reliable_bits.as_ref().map(|bits| &bits[..]))
+            .u64(hash.active_area.map_or(u64::MAX, u64::from))
+            .bytes(hash.collection.as_deref().unwrap_or_default().as_bytes())
+            .u64(u64::from(hash.collection.is_some()))
+            .words(audio.as_deref())
+            .finish()
+    }
+
+    // The references (in any order), along with the options which decide whether a candidate
+    // matches them.
+    fn of_references(references: &[VideoHash], opts: &SearchOptions) -> Self {
+        let mut references = references
+            .iter()
+            .map(|hash| (hash.src_path(), Self::of_hash(hash)))
+            .collect::<Vec<_>>();
+        references.sort_unstable_by_key(|&(path, Self(fingerprint))| (path, fingerprint));
+
+        let mut fp = Fingerprinter::new();
+        fp.u64(references.len() as u64);
+        for (path, Self(fingerprint)) in references {
+            fp.path(path).bytes(&fingerprint.to_le_bytes());
+        }
+
+        let (policy, short_tolerance) = match opts.short_video_policy {
+            ShortVideoPolicy::SameTolerance => (0, 0.0),
+            ShortVideoPolicy::Tolerance(short_tolerance) => (1, short_tolerance),
+            ShortVideoPolicy::Exclude => (2, 0.0),
+        };
+        let collections = match opts.collections {
+            CollectionMode::All => 0,
+            CollectionMode::CrossCollectionOnly => 1,
+        };
+        let (gate, max_ratio) = match opts.duration_gate {
+            DurationGate::Strict => (0, 0.0),
+            DurationGate::Loose { max_ratio } => (1, max_ratio),
+            DurationGate::Ignore => (2, 0.0),
+        };
+        let audio_check = match opts.audio_check {
+            AudioCheck::Off => 0,
+            AudioCheck::Annotate => 1,
+            AudioCheck::RequireAgreement => 2,
+        };
+        let (max_area_difference, min_area_overlap) =
+            opts.active_area.map_or((f64::NAN, f64::NAN), |guard| {
+                (guard.max_difference, guard.min_overlap)
+            });
+        fp.u64(opts.tolerance.value().to_bits())
+            .u64(policy)
+            .u64(short_tolerance.to_bits())
+            .u64(u64::from(opts.temporal_slack))
+            .u64(u64::from(opts.ignore_unreliable_bits))
+            .u64(collections)
+            .u64(max_area_difference.to_bits())
+            .u64(min_area_overlap.to_bits())
+            .u64(u64::from(opts.speed_variants))
+            .u64(audio_check)
+            .u64(gate)
+            .u64(max_ratio.to_bits())
+            .finish()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct UniqueCandidate {
+    #[serde(with = "vid_dup_finder_common::path_serde")]
+    path: PathBuf,
+    fingerprint: Fingerprint,
+}
+
+/// The candidates which were unique in a reference search, for skipping them in the next search
+/// (see [`search_with_references_incremental`]). Can be saved and loaded with [`serde`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SearchSnapshot {
+    references: Fingerprint,
+    //sorted by path.
+    unique: Vec<UniqueCandidate>,
+}
+
+impl SearchSnapshot {
+    /// The snapshot of a finished search.
+    #[must_use]
+    pub fn from_results(results: &ReferenceSearchResults) -> Self {
+        let mut unique = results.unique_fingerprints.clone();
+        unique.sort_unstable_by(|c1, c2| c1.path.cmp(&c2.path));
+
+        Self {
+            references: results.references,
+            unique,
+        }
+    }
+
+    /// The number of candidates recorded as unique.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.unique.len()
+    }
+
+    /// Whether no candidates are recorded as unique.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.unique.is_empty()
+    }
+
+    fn was_unique(&self, path: &Path, fingerprint: Fingerprint) -> bool {
+        self.unique
+            .binary_search_by(|c| c.path.as_path().cmp(path))
+            .is_ok_and(|idx| self.unique[idx].fingerprint == fingerprint)
+    }
+}
+
+/// The results of [`search_with_references_incremental`].
+#[derive(Debug, Clone)]
+pub struct ReferenceSearchResults {
+    groups: Vec<MatchGroup>,
+    unique: Vec<PathBuf>,
+    skipped: usize,
+    stats: SearchStats,
+    references: Fingerprint,
+    unique_fingerprints: Vec<UniqueCandidate>,
+}
+
+impl ReferenceSearchResults {
+    /// One group for each reference video that was matched, as returned by
+    /// [`search_with_references_with_opts`][crate::search_with_references_with_opts].
+    #[must_use]
+    pub fn groups(&self) -> &[MatchGroup] {
+        &self.groups
+    }
+
+    /// The groups, discarding everything else.
+    #[must_use]
+    pub fn into_groups(self) -> Vec<MatchGroup> {
+        self.groups
+    }
+
+    /// The candidates which did not match any reference, in the order they were given. This
+    /// includes the candidates that were skipped.
+    #[must_use]
+    pub fn unique(&self) -> &[PathBuf] {
+        &self.unique
+    }
+
+    /// How many candidates were reported as unique from the snapshot, without being compared.
+    #[must_use]
+    pub fn skipped(&self) -> usize {
+        self.skipped
+    }
+
+    /// How much work the search did. Skipped candidates are not counted.
+    #[must_use]
+    pub fn stats(&self) -> SearchStats {
+        self.stats.clone()
+    }
+}
+
+/// As [`search_with_references_with_opts`][crate::search_with_references_with_opts], but skipping the candidates which were unique in the
+/// `previous` search and have not changed since. If the references or the search options are
+/// different from the previous search then every candidate is compared.
+///
+/// Skipping relies on a candidate's result depending only on itself and the references. When
+/// `opts.falsepos_filter` is not empty that is not true, because an unrelated new candidate can
+/// bring a suppressed one into a group (see [`search_with_references_filtered`][crate::search_with_references_filtered]).
+/// Nor is it with any [`SearchOptions::constraints`], which can likewise suppress pairs, or hide a
+/// group until a new candidate joins it. So in those cases the snapshot is not used, although the
+/// results can still be snapshotted.
+/// # Panics
+/// Should only panic due to internal implementation error
+pub fn search_with_references_incremental(
+    ref_hashes: impl IntoIterator<Item = VideoHash>,
+    new_hashes: impl IntoIterator<Item = VideoHash>,
+    opts: &SearchOptions,
+    previous: Option<&SearchSnapshot>,
+) -> ReferenceSearchResults {
+    let ref_hashes = ref_hashes.into_iter().collect::<Vec<_>>();
+    let references = Fingerprint::of_references(&ref_hashes, opts);
+    let previous = previous.filter(|previous| {
+        previous.references == references
+            && opts.falsepos_filter.is_empty()
+            && opts.constraints.is_empty()
+    });
+
+    //(path, fingerprint, skipped) for every candidate, in order.
+    let mut candidates = vec![];
+    let mut to_search = vec![];
+    for hash in new_hashes {
+        let fingerprint = Fingerprint::of_hash(&hash);
+        let skipped = previous.is_some_and(|p| p.was_unique(hash.src_path(), fingerprint));
+        candidates.push((hash.src_path().to_path_buf(), fingerprint, skipped));
+        if !skipped {
+            to_search.push(hash);
+        }
+    }
+
+    let (groups, stats) = search_with_references_with_stats(ref_hashes, to_search, opts);
+
+    let matched = groups
+        .iter()
+        .flat_map(MatchGroup::duplicates)
+        .collect::<HashSet<_>>();
+    let (unique, unique_fingerprints) = candidates
+        .iter()
+        .filter(|(path, _, skipped)| *skipped || !matched.contains(path.as_path()))
+        .map(|(path, fingerprint, _)| {
+            let unique = UniqueCandidate {
+                path: path.clone(),
+                fingerprint: *fingerprint,
+            };
+            (path.clone(), unique)
+        })
+        .unzip();
+    let skipped = candidates.iter().filter(|(_, _, skipped)| *skipped).count();
+
+    ReferenceSearchResults {
+        groups,
+        unique,
+        skipped,
+        stats,
+        references,
+        unique_fingerprints,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rand::prelude::*;
+
+    use super::*;
+    use crate::{AudioFingerprint, Tolerance};
+
+    // Two references, a copy of one of them, and a hundred unrelated candidates.
+    fn library() -> (Vec<VideoHash>, Vec<VideoHash>) {
+        let mut rng = StdRng::seed_from_u64(3);
+        let references = (0..2)
+            .map(|i| {
+                VideoHash::random_hash(&mut rng)
+                    .with_duration(60)
+                    .with_src_path(format!("ref/{i}"))
+            })
+            .collect::<Vec<_>>();
+        let candidates = (0..100)
+            .map(|i| {
+                VideoHash::random_hash(&mut rng)
+                    .with_duration(60)
+                    .with_src_path(format!("new/{i}"))
+            })
+            .chain([references[0].with_flipped_bits(0..4).with_src_path("copy")])
+            .collect::<Vec<_>>();
+
+        (references, candidates)
+    }
+
+    fn counted_search(
+        references: &[VideoHash],
+        candidates: &[VideoHash],
+        previous: Option<&SearchSnapshot>,
+    ) -> (ReferenceSearchResults, u64) {
+        let results = search_with_references_incremental(
+            references.to_vec(),
+            candidates.to_vec(),
+            &SearchOptions::default(),
+            previous,
+        );
+        let comparisons = results.stats().comparisons;
+        (results, comparisons)
+    }
+
+    #[test]
+    fn test_unchanged_second_run_does_almost_no_comparisons() {
+        let (references, candidates) = library();
+
+        let (first, first_comparisons) = counted_search(&references, &candidates, None);
+        assert_eq!(first.groups().len(), 1);
+        assert_eq!(first.unique().len(), 100);
+        assert_eq!(first.skipped(), 0);
+        assert!(first_comparisons >= 200, "{first_comparisons}");
+
+        let snapshot = SearchSnapshot::from_results(&first);
+        let (second, second_comparisons) =
+            counted_search(&references, &candidates, Some(&snapshot));
+
+        //only the copy, which wasn't unique, is compared again.
+        assert_eq!(second.skipped(), 100);
+        assert!(second_comparisons <= 2, "{second_comparisons}");
+        assert_eq!(second.groups(), first.groups());
+        assert_eq!(second.unique(), first.unique());
+        assert_eq!(SearchSnapshot::from_results(&second), snapshot);
+    }
+
+    #[test]
+    fn test_changes_are_searched_again() {
+        let (references, mut candidates) = library();
+        let (first, _) = counted_search(&references, &candidates, None);
+        let snapshot = SearchSnapshot::from_results(&first);
+
+        //a candidate that now matches a reference is found, even though it was unique before.
+        candidates[7] = references[1].with_flipped_bits(0..3).with_src_path("new/7");
+        let (results, _) = counted_search(&references, &candidates, Some(&snapshot));
+        assert_eq!(results.skipped(), 99);
+        assert_eq!(results.groups().len(), 2);
+
+        //and a changed reference set invalidates everything.
+        let (results, _) = counted_search(&references[..1], &candidates, Some(&snapshot));
+        assert_eq!(results.skipped(), 0);
+    }
+
+    #[test]
+    fn test_snapshot_is_tied_to_search_options() {
+        let (references, candidates) = library();
+        let (first, _) = counted_search(&references, &candidates, None);
+        let snapshot = SearchSnapshot::from_results(&first);
+
+        let results = search_with_references_incremental(
+            references,
+            candidates,
+            &SearchOptions::new(Tolerance::new(0.5).expect("0.5 is a valid tolerance")),
+            Some(&snapshot),
+        );
+        assert_eq!(results.skipped(), 0);
+    }
+
+    #[test]
+    fn test_snapshot_is_tied_to_duration_gate() {
+        let (references, mut candidates) = library();
+        //a copy of a reference that is too much longer to be compared under the strict gate.
+        candidates[7] = references[1]
+            .with_flipped_bits(0..3)
+            .with_duration(120)
+            .with_src_path("new/7");
+        let (first, _) = counted_search(&references, &candidates, None);
+        assert_eq!(first.groups().len(), 1);
+        let snapshot = SearchSnapshot::from_results(&first);
+
+        for duration_gate in [DurationGate::Loose { max_ratio: 3.0 }, DurationGate::Ignore] {
+            let opts = SearchOptions {
+                duration_gate,
+                ..SearchOptions::default()
+            };
+            let results = search_with_references_incremental(
+                references.clone(),
+                candidates.clone(),
+                &opts,
+                Some(&snapshot),
+            );
+            assert_eq!(results.skipped(), 0, "{duration_gate:?}");
+            assert_eq!(results.groups().len(), 2, "{duration_gate:?}");
+        }
+
+        //loose gates with different ratios are different searches too.
+        let loose = |max_ratio| SearchOptions {
+            duration_gate: DurationGate::Loose { max_ratio },
+            ..SearchOptions::default()
+        };
+        let first = search_with_references_incremental(
+            references.clone(),
+            candidates.clone(),
+            &loose(1.5),
+            None,
+        );
+        let results = search_with_references_incremental(
+            references,
+            candidates,
+            &loose(3.0),
+            Some(&SearchSnapshot::from_results(&first)),
+        );
+        assert_eq!(results.skipped(), 0);
+    }
+
+    #[test]
+    fn test_snapshot_is_tied_to_audio() {
+        let (references, mut candidates) = library();
+        let require_agreement = SearchOptions {
+            audio_check: AudioCheck::RequireAgreement,
+            ..SearchOptions::default()
+        };
+        let first = search_with_references_incremental(
+            references.clone(),
+            candidates.clone(),
+            &require_agreement,
+            None,
+        );
+        let snapshot = SearchSnapshot::from_results(&first);
+
+        //a snapshot taken while requiring the audio to agree is not reused when it is ignored...
+        let results = search_with_references_incremental(
+            references.clone(),
+            candidates.clone(),
+            &SearchOptions::default(),
+            Some(&snapshot),
+        );
+        assert_eq!(results.skipped(), 0);
+
+        //...and a candidate whose audio changed is compared again.
+        candidates[7] = candidates[7]
+            .clone()
+            .with_audio_fingerprint(Some(AudioFingerprint::from_slices(vec![0; 16])));
+        let results = search_with_references_incremental(
+            references,
+            candidates,
+            &require_agreement,
+            Some(&snapshot),
+        );
+        assert_eq!(results.skipped(), 99);
+    }
+
+    #[test]
+    fn test_snapshot_serde_round_trip() {
+        let (references, candidates) = library();
+        let (first, _) = counted_search(&references, &candidates, None);
+        let snapshot = SearchSnapshot::from_results(&first);
+
+        let json = serde_json::to_string(&snapshot).expect("snapshot can be serialized");
+        let loaded: SearchSnapshot = serde_json::from_str(&json).expect("snapshot can be loaded");
+        assert_eq!(loaded, snapshot);
+        assert_eq!(loaded.len(), 100);
+
+        let (results, _) = counted_search(&references, &candidates, Some(&loaded));
+        assert_eq!(results.skipped(), 100);
+    }
+}
@@ -0,0 +1,45 @@
+use std::path::PathBuf;
+
+/// How much work a search did, returned by [`crate::search_with_stats`] and
+/// [`crate::search_with_references_with_stats`].
+///
+/// The same figures are also logged at debug level (with the `log` crate) at the end of every
+/// search.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct SearchStats {
+    /// The number of videos that were searched for duplicates. Each is compared against the bucket of
+    /// videos with a similar duration to it. When searching within a set of hashes, videos that have
+    /// already been grouped are not searched for again.
+    pub buckets: usize,
+
+    /// The number of pairs of hashes that were compared.
+    pub comparisons: u64,
+
+    /// The number of pairs of hashes that were never compared because their durations are too far
+    /// apart for them to be duplicates.
+    pub skipped_by_duration: u64,
+
+    /// The number of groups returned by the search.
+    pub groups: usize,
+
+    /// The hashes that were not searched because an earlier hash had the same path, in the order
+    /// they were given. Each is also logged as a warning.
+    pub duplicate_inputs: Vec<DuplicateInput>,
+}
+
+/// A hash that was left out of a search because it had the same path as a hash given before it.
+/// Only the first hash for each path is searched, so that no file is reported as a duplicate of
+/// itself.
+///
+/// When searching with references, the references count as being given before the other hashes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DuplicateInput {
+    /// The path of both hashes.
+    pub path: PathBuf,
+
+    /// True if the hash that was searched is a reference.
+    pub kept_reference: bool,
+
+    /// True if the hash that was left out is a reference.
+    pub discarded_reference: bool,
+}
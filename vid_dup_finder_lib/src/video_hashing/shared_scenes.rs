@@ -0,0 +1,448 @@
+//! Finding scenes which appear in more than one video.
+//!
+//! Whole-file searches only match videos which are copies of each other. [`search_shared_scenes`]
+//! instead compares the hashes of the windows of each video (as created by `hash_windows`), and
+//! reports the runs of consecutive windows in which two videos show the same thing, such as a
+//! reaction video which embeds a clip, or a compilation of other videos.
+//!
+//! ```
+//! use vid_dup_finder_lib::*;
+//! # fn hash_windows(path: &str) -> Vec<VideoHash> { vec![] }
+//!
+//! let videos = ["reaction.mp4", "clip.mp4", "compilation.mp4"]
+//!     .into_iter()
+//!     .filter_map(|path| WindowedVideoHash::new(hash_windows(path)))
+//!     .collect();
+//! for scene in search_shared_scenes(videos, Tolerance::DEFAULT, 2) {
+//!     println!(
+//!         "{:?} at {:?} is {:?} at {:?}",
+//!         scene.first(),
+//!         scene.first_range(),
+//!         scene.second(),
+//!         scene.second_range()
+//!     );
+//! }
+//! ```
+//!
+//! # Memory and complexity
+//! Comparing every window of every video with every other would take time in proportion to the
+//! square of the total number of windows. Instead an inverted index is built over the coarse
+//! signatures of the windows (the 64 lowest frequency bits of each hash): Each signature is split
+//! into 8 bands of 8 bits, and each window is indexed under each of its bands, so the index holds 8
+//! small entries per window. Only pairs of windows of different videos which have a band in common
+//! are looked at. Each such pair lines up the two videos at some offset, and the windows of the two
+//! videos are compared all along that alignment, once for each alignment however many pairs of
+//! windows suggest it.
+//!
+//! Unrelated windows differ in about half of the bits of their signatures, so they rarely have a
+//! band in common, and the time taken is roughly in proportion to the total number of windows plus
+//! the lengths of the alignments of videos which really do share something. In the worst case, when
+//! many windows look alike (such as windows of black frames), most pairs have a band in common and
+//! the search is as slow as comparing every pair.
+//!
+//! The index is only a filter, so a shared scene is found if at least one of its pairs of windows
+//! has a band in common, which is certain if that pair's signatures differ in fewer than 8 bits.
+//! Scenes which are copies of each other nearly always have such a pair, but scenes which only
+//! loosely match all the way through can be missed.
+use std::{
+    collections::{BTreeSet, HashMap},
+    ops::Range,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use itertools::Itertools;
+
+use super::{search_algorithm::MatchThreshold, video_hash::coarse_signature};
+use crate::{definitions::COARSE_BITS, SearchOptions, Tolerance, VideoHash};
+
+const BANDS: u32 = 8;
+const BAND_BITS: u32 = COARSE_BITS / BANDS;
+
+/// The hashes of the windows of a single video, in order, for [`search_shared_scenes`].
+#[derive(Debug, Clone)]
+pub struct WindowedVideoHash {
+    //never empty, all of the same path and sorted by their start.
+    windows: Vec<VideoHash>,
+}
+
+impl WindowedVideoHash {
+    /// The hashes of the windows of a single video, as returned by `hash_windows`, in any order.
+    ///
+    /// Returns `None` if there are no windows, if any of the hashes is not of a window (see
+    /// [`VideoHash::window_start`]), or if they are not all of the same video.
+    #[must_use]
+    pub fn new(mut windows: Vec<VideoHash>) -> Option<Self> {
+        let path = windows.first()?.src_path().to_path_buf();
+        if windows
+            .iter()
+            .any(|window| window.window_start().is_none() || window.src_path() != path)
+        {
+            return None;
+        }
+
+        windows.sort_by_key(VideoHash::window_start);
+        Some(Self { windows })
+    }
+
+    /// The path of the video.
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        self.windows[0].src_path()
+    }
+
+    /// The hashes of the windows, in order of their start.
+    #[must_use]
+    pub fn windows(&self) -> &[VideoHash] {
+        &self.windows
+    }
+
+    // The part of the video covered by the given run of windows.
+    fn range(&self, windows: Range<usize>) -> Range<Duration> {
+        let start = |window: &VideoHash| window.window_start().unwrap_or_default();
+        let last = &self.windows[windows.end - 1];
+        start(&self.windows[windows.start])..start(last) + last.sampling_window().duration()
+    }
+}
+
+/// A scene which was found in two videos by [`search_shared_scenes`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct SceneMatch {
+    first: PathBuf,
+    first_range: Range<Duration>,
+    second: PathBuf,
+    second_range: Range<Duration>,
+    windows: usize,
+    distance: f64,
+}
+
+impl SceneMatch {
+    /// The path of the first video, which sorts before the second.
+    #[must_use]
+    pub fn first(&self) -> &Path {
+        &self.first
+    }
+
+    /// The part of the first video which shows the scene, from the start of its first matching
+    /// window to the end of its last.
+    #[must_use]
+    pub fn first_range(&self) -> Range<Duration> {
+        self.first_range.clone()
+    }
+
+    /// The path of the second video.
+    #[must_use]
+    pub fn second(&self) -> &Path {
+        &self.second
+    }
+
+    /// The part of the second video which shows the scene.
+    #[must_use]
+    pub fn second_range(&self) -> Range<Duration> {
+        self.second_range.clone()
+    }
+
+    /// The number of consecutive windows of each video which matched.
+    #[must_use]
+    pub const fn windows(&self) -> usize {
+        self.windows
+    }
+
+    /// The average of the normalized distances between the matching windows, in the range 0..=1.
+    #[must_use]
+    pub const fn distance(&self) -> f64 {
+        self.distance
+    }
+}
+
+/// Search for scenes which appear in more than one of the given videos.
+///
+/// A scene is a run of at least `min_consecutive_windows` consecutive windows of one video which
+/// each match the window of another video that is the same number of windows later in it, where
+/// windows match in the same way as the hashes of whole videos do in a search with the given
+/// tolerance except that their durations are not compared. Requiring more than one window
+/// suppresses the odd window that matches by chance. The windows of every video should be created
+/// with the same options and window stride, so that consecutive windows are the same time apart.
+///
+/// Each run is reported once, as far as it extends. When windows overlap, the same scene also
+/// matches at the neighbouring alignments, and runs which overlap a longer (or equally long but
+/// closer) run in both videos are left out. Videos are never matched with themselves, or with
+/// other windows of the same path.
+///
+/// Scenes are returned sorted by their first video, then their second and then the start of the
+/// scene in the first video. See the [module documentation][self] for how long this takes.
+#[must_use]
+pub fn search_shared_scenes(
+    windowed: Vec<WindowedVideoHash>,
+    tolerance: Tolerance,
+    min_consecutive_windows: usize,
+) -> Vec<SceneMatch> {
+    let threshold = MatchThreshold::new(&SearchOptions::new(tolerance));
+    let min_run = min_consecutive_windows.max(1);
+    let mut videos = windowed;
+    videos.sort_by(|v1, v2| v1.path().cmp(v2.path()));
+
+    //(band, bits of the band) -> (video, window), in order of video.
+    let mut index = HashMap::<(u32, u64), Vec<(usize, usize)>>::new();
+    for (v, video) in videos.iter().enumerate() {
+        for (w, window) in video.windows.iter().enumerate() {
+            let signature = coarse_signature(&window.hash, window.hash_size);
+            for band in 0..BANDS {
+                let bits = signature >> (band * BAND_BITS) & ((1 << BAND_BITS) - 1);
+                index.entry((band, bits)).or_default().push((v, w));
+            }
+        }
+    }
+
+    //(first video, second video, how many windows later the second video is)
+    let alignments = index
+        .values()
+        .flat_map(|bucket| bucket.iter().tuple_combinations())
+        .filter(|((v1, _), (v2, _))| videos[*v1].path() != videos[*v2].path())
+        .map(|(&(v1, w1), &(v2, w2))| (v1, v2, w2 as isize - w1 as isize))
+        .collect::<BTreeSet<_>>();
+
+    let runs = alignments
+        .into_iter()
+        .flat_map(|(v1, v2, offset)| {
+            aligned_runs(&videos[v1], &videos[v2], offset, &threshold, min_run)
+        })
+        .collect::<Vec<_>>();
+    without_overlaps(runs)
+}
+
+// The runs of at least min_run matching windows of the two videos, when the second video is offset
+// windows later than the first.
+fn aligned_runs(
+    first: &WindowedVideoHash,
+    second: &WindowedVideoHash,
+    offset: isize,
+    threshold: &MatchThreshold,
+    min_run: usize,
+) -> Vec<SceneMatch> {
+    let pairs = (0..first.windows.len())
+        .filter_map(|i| {
+            let j = usize::try_from(i as isize + offset).ok()?;
+            Some((i, j, second.windows.get(j)?))
+        })
+        .map(|(i, j, w2)| {
+            let w1 = &first.windows[i];
+            let distance = threshold
+                .is_match(w1, w2)
+                .then(|| f64::from(w1.same_size_distance(w2)) / f64::from(w1.hash_size().bits()));
+            (i, j, distance)
+        })
+        .collect::<Vec<_>>();
+
+    pairs
+        .chunk_by(|(_, _, d1), (_, _, d2)| d1.is_some() == d2.is_some())
+        .filter(|run| run.len() >= min_run && run[0].2.is_some())
+        .map(|run| {
+            let (i, j) = (run[0].0, run[0].1);
+            let windows = run.len();
+            SceneMatch {
+                first: first.path().to_path_buf(),
+                first_range: first.range(i..i + windows),
+                second: second.path().to_path_buf(),
+                second_range: second.range(j..j + windows),
+                windows,
+                distance: run.iter().filter_map(|(_, _, d)| *d).sum::<f64>() / windows as f64,
+            }
+        })
+        .collect()
+}
+
+// Drop the runs which overlap a longer or closer run in both videos.
+fn without_overlaps(mut runs: Vec<SceneMatch>) -> Vec<SceneMatch> {
+    fn overlaps(r1: &Range<Duration>, r2: &Range<Duration>) -> bool {
+        r1.start < r2.end && r2.start < r1.end
+    }
+
+    runs.sort_by(|r1, r2| {
+        (&r1.first, &r1.second)
+            .cmp(&(&r2.first, &r2.second))
+            .then(r2.windows.cmp(&r1.windows))
+            .then(r1.distance.total_cmp(&r2.distance))
+            .then(r1.first_range.start.cmp(&r2.first_range.start))
+    });
+
+    let mut kept: Vec<SceneMatch> = vec![];
+    for run in runs {
+        let shadowed = kept.iter().any(|better| {
+            better.first == run.first
+                && better.second == run.second
+                && overlaps(&better.first_range, &run.first_range)
+                && overlaps(&better.second_range, &run.second_range)
+        });
+        if !shadowed {
+            kept.push(run);
+        }
+    }
+
+    kept.sort_by(|r1, r2| {
+        (&r1.first, &r1.second, r1.first_range.start).cmp(&(
+            &r2.first,
+            &r2.second,
+            r2.first_range.start,
+        ))
+    });
+    kept
+}
+
+#[cfg(test)]
+mod test {
+    use image::{GrayImage, Luma};
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+    use crate::{
+        definitions::DCT_SIZE, video_hashing::video_hash_builder::hash_from_frame_iter,
+        CreationOptions, Cropdetect,
+    };
+
+    const STRIDE: f64 = 10.0;
+
+    // Each scene is a different grid of grey blocks, which scrolls to the right over time.
+    fn frame(scene: u32, t: f64) -> GrayImage {
+        let shift = (t * 2.0) as u32;
+        GrayImage::from_fn(64, 48, |x, y| {
+            let block = ((x + shift) / 16 % 4) + y / 12 * 4 + scene * 16;
+            Luma([(block.wrapping_mul(2_654_435_761) >> 24) as u8])
+        })
+    }
+
+    // A synthetic video made of the given parts, each of which is a scene and its length in
+    // seconds, hashed in windows of STRIDE seconds one after the other.
+    fn windowed(name: &str, parts: &[(u32, f64)]) -> WindowedVideoHash {
+        let opts = CreationOptions {
+            skip_forward_amount: 0.0,
+            duration: STRIDE,
+            cropdetect: Cropdetect::None,
+            ..CreationOptions::default()
+        };
+        let duration = parts.iter().map(|(_, length)| length).sum::<f64>();
+        let frame_at = |t: f64| {
+            let mut part_start = 0.0;
+            for &(scene, length) in parts {
+                if t < part_start + length {
+                    return frame(scene, t - part_start);
+                }
+                part_start += length;
+            }
+            frame(u32::MAX, t)
+        };
+
+        let windows = (0..(duration / STRIDE) as u32)
+            .map(|i| {
+                let start = f64::from(i) * STRIDE;
+                let frames = (0..DCT_SIZE)
+                    .map(|f| frame_at(start + STRIDE * f64::from(f) / f64::from(DCT_SIZE)));
+                hash_from_frame_iter(
+                    PathBuf::from(name),
+                    Duration::from_secs_f64(duration),
+                    frames,
+                    opts,
+                    None,
+                )
+                .expect("frames can be hashed")
+                .with_window_start(Duration::from_secs_f64(start))
+            })
+            .collect();
+        WindowedVideoHash::new(windows).expect("windows of one video")
+    }
+
+    fn secs(range: Range<u64>) -> Range<Duration> {
+        Duration::from_secs(range.start)..Duration::from_secs(range.end)
+    }
+
+    #[test]
+    fn test_shared_middle_segment_is_found() {
+        //scene 0 is shown 30 seconds into the reaction video, and 10 seconds into the clip.
+        let reaction = windowed("reaction.mp4", &[(1, 30.0), (0, 40.0), (2, 30.0)]);
+        let clip = windowed("clip.mp4", &[(3, 10.0), (0, 40.0), (4, 10.0)]);
+        let unrelated = windowed("unrelated.mp4", &[(5, 60.0)]);
+
+        let scenes = search_shared_scenes(vec![reaction, unrelated, clip], Tolerance::DEFAULT, 2);
+        assert_eq!(scenes.len(), 1, "{scenes:#?}");
+        let scene = &scenes[0];
+        assert_eq!(scene.first(), Path::new("clip.mp4"));
+        assert_eq!(scene.first_range(), secs(10..50));
+        assert_eq!(scene.second(), Path::new("reaction.mp4"));
+        assert_eq!(scene.second_range(), secs(30..70));
+        assert_eq!(scene.windows(), 4);
+        assert!(scene.distance() < 0.05);
+    }
+
+    // A video of distinct random windows.
+    fn random(name: &str, num_windows: u64, rng: &mut StdRng) -> Vec<VideoHash> {
+        (0..num_windows)
+            .map(|i| {
+                VideoHash::random_hash(rng)
+                    .with_src_path(name)
+                    .with_window_start(Duration::from_secs(i * 10))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_single_windows_are_suppressed() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let a = random("a", 12, &mut rng);
+        let mut b = random("b", 12, &mut rng);
+
+        //b shares a single window with a by chance, and a run of three a little later.
+        b[1] = a[9]
+            .with_src_path("b")
+            .with_window_start(Duration::from_secs(10));
+        for (i, j) in [(4, 6), (5, 7), (6, 8)] {
+            b[j] = a[i]
+                .with_flipped_bits(0..4)
+                .with_src_path("b")
+                .with_window_start(Duration::from_secs(j as u64 * 10));
+        }
+        let videos = || {
+            [a.clone(), b.clone()]
+                .map(|windows| WindowedVideoHash::new(windows).expect("windows of one video"))
+                .to_vec()
+        };
+
+        let scenes = search_shared_scenes(videos(), Tolerance::DEFAULT, 2);
+        assert_eq!(scenes.len(), 1);
+        assert_eq!(scenes[0].first_range(), secs(40..70));
+        assert_eq!(scenes[0].second_range(), secs(60..90));
+        assert_eq!(scenes[0].windows(), 3);
+        assert!(scenes[0].distance() > 0.0);
+
+        let scenes = search_shared_scenes(videos(), Tolerance::DEFAULT, 1);
+        let ranges = scenes.iter().map(SceneMatch::first_range).collect_vec();
+        assert_eq!(ranges, [secs(40..70), secs(90..100)]);
+        assert_eq!(scenes[1].distance(), 0.0);
+
+        //a video never shares scenes with itself.
+        let twice = [a.clone(), a.clone()]
+            .map(|windows| WindowedVideoHash::new(windows).expect("windows of one video"))
+            .to_vec();
+        assert!(search_shared_scenes(twice, Tolerance::DEFAULT, 1).is_empty());
+    }
+
+    #[test]
+    fn test_windows_of_one_video_are_required() {
+        let mut rng = StdRng::seed_from_u64(4);
+        let mut windows = random("a", 3, &mut rng);
+        windows.reverse();
+        let video = WindowedVideoHash::new(windows.clone()).expect("windows of one video");
+        assert_eq!(video.path(), Path::new("a"));
+        assert!(video
+            .windows()
+            .windows(2)
+            .all(|w| w[0].window_start() < w[1].window_start()));
+
+        assert!(WindowedVideoHash::new(vec![]).is_none());
+        let mut mixed = windows.clone();
+        mixed.push(windows[0].with_src_path("b"));
+        assert!(WindowedVideoHash::new(mixed).is_none());
+        let mut whole = windows;
+        whole.push(VideoHash::random_hash(&mut rng).with_src_path("a"));
+        assert!(WindowedVideoHash::new(whole).is_none());
+    }
+}
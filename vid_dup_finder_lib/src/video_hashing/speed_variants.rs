@@ -0,0 +1,204 @@
+//! Hashes of a video as if it were played faster or slower, for matching copies which were sped up
+//! or slowed down.
+//!
+//! A copy of a video played at twice the speed lasts half as long, and from any point in it on,
+//! shows what the original shows twice as far in, twice as fast. So for each factor in
+//! [`CreationOptions::speed_variants`][crate::CreationOptions::speed_variants], the frames of an
+//! extra hash are sampled from the original at the times that the frames of a copy played at that
+//! speed would be sampled from, and the bits of that hash are kept alongside the usual bits as a
+//! variant. Searches with [`SearchOptions::speed_variants`][crate::SearchOptions::speed_variants]
+//! set compare the variants of each video with the usual bits of the others.
+//!
+//! Only the bits of each variant are kept, so each variant makes a hash as many bytes larger as
+//! its bits take.
+
+use std::{fmt, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use super::video_hash::{HashArray, VideoHash};
+
+// The most that any SpeedFactor changes the duration of a video by, either way.
+pub(crate) const MAX_SPEED_RATIO: f64 = 2.0;
+
+/// A speed at which a video can be hashed as well as at its own speed, for
+/// [`CreationOptions::speed_variants`][crate::CreationOptions::speed_variants]. Each is the speed
+/// of a copy relative to the original, so a copy played at [`SpeedFactor::Double`] lasts half as
+/// long.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum SpeedFactor {
+    /// Half speed, as in a slowed down copy.
+    Half,
+    /// 1.25 times as fast.
+    OneAndAQuarter,
+    /// 1.5 times as fast.
+    OneAndAHalf,
+    /// Twice as fast.
+    Double,
+}
+
+/// The error returned when parsing a [`SpeedFactor`] from anything other than one of the supported
+/// factors.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[error("Unsupported speed factor {0:?}. The supported factors are 0.5, 1.25, 1.5 and 2")]
+pub struct UnsupportedSpeedFactor(pub String);
+
+impl SpeedFactor {
+    /// Every factor, from slowest to fastest.
+    pub const ALL: [Self; 4] = [
+        Self::Half,
+        Self::OneAndAQuarter,
+        Self::OneAndAHalf,
+        Self::Double,
+    ];
+
+    /// The speed as a multiple of the original speed.
+    #[must_use]
+    pub const fn value(self) -> f64 {
+        match self {
+            Self::Half => 0.5,
+            Self::OneAndAQuarter => 1.25,
+            Self::OneAndAHalf => 1.5,
+            Self::Double => 2.0,
+        }
+    }
+
+    /// The factor whose [`SpeedFactor::value`] is exactly `value`, if there is one.
+    #[must_use]
+    pub fn from_value(value: f64) -> Option<Self> {
+        Self::ALL.into_iter().find(|factor| factor.value() == value)
+    }
+
+    const fn bit(self) -> u8 {
+        1 << self as u8
+    }
+}
+
+impl fmt::Display for SpeedFactor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.value())
+    }
+}
+
+impl FromStr for SpeedFactor {
+    type Err = UnsupportedSpeedFactor;
+
+    /// Parses the [`SpeedFactor::value`] of a factor, such as `1.25` or `2`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.trim()
+            .parse()
+            .ok()
+            .and_then(Self::from_value)
+            .ok_or_else(|| UnsupportedSpeedFactor(s.to_string()))
+    }
+}
+
+/// A set of [`SpeedFactor`]s. Empty by default.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+pub struct SpeedFactors(u8);
+
+impl SpeedFactors {
+    /// No factors.
+    pub const NONE: Self = Self(0);
+
+    /// Every factor in [`SpeedFactor::ALL`].
+    pub const ALL: Self = Self(
+        SpeedFactor::Half.bit()
+            | SpeedFactor::OneAndAQuarter.bit()
+            | SpeedFactor::OneAndAHalf.bit()
+            | SpeedFactor::Double.bit(),
+    );
+
+    /// The set with `factor` added.
+    #[must_use]
+    pub const fn with(self, factor: SpeedFactor) -> Self {
+        Self(self.0 | factor.bit())
+    }
+
+    #[must_use]
+    pub const fn contains(self, factor: SpeedFactor) -> bool {
+        self.0 & factor.bit() != 0
+    }
+
+    #[must_use]
+    pub const fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    /// The factors in the set, from slowest to fastest.
+    pub fn iter(self) -> impl Iterator<Item = SpeedFactor> {
+        SpeedFactor::ALL
+            .into_iter()
+            .filter(move |&factor| self.contains(factor))
+    }
+}
+
+impl FromIterator<SpeedFactor> for SpeedFactors {
+    fn from_iter<T: IntoIterator<Item = SpeedFactor>>(iter: T) -> Self {
+        iter.into_iter().fold(Self::NONE, Self::with)
+    }
+}
+
+// The bits of a hash of a video as if it were played at the speed of factor.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub(crate) struct SpeedVariant {
+    pub(super) factor: SpeedFactor,
+    //Only the words that the size of the hash uses, so that variants of small hashes stay small.
+    pub(super) bits: Vec<u64>,
+}
+
+impl SpeedVariant {
+    // The variant at the speed of factor, whose bits are those of hash.
+    pub(super) fn new(factor: SpeedFactor, hash: &VideoHash) -> Self {
+        Self {
+            factor,
+            bits: hash.hash.to_vec(),
+        }
+    }
+
+    // The bits laid out as those of a hash. Variants have as many words as the hash they belong to.
+    pub(super) fn hash_array(&self) -> HashArray {
+        HashArray::from_slice(&self.bits)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_factors() {
+        for factor in SpeedFactor::ALL {
+            assert_eq!(factor.to_string().parse(), Ok(factor));
+        }
+        assert_eq!("2".parse(), Ok(SpeedFactor::Double));
+        assert_eq!(" 1.25".parse(), Ok(SpeedFactor::OneAndAQuarter));
+        assert_eq!(
+            "3".parse::<SpeedFactor>(),
+            Err(UnsupportedSpeedFactor(String::from("3")))
+        );
+        assert!("fast".parse::<SpeedFactor>().is_err());
+    }
+
+    #[test]
+    fn test_factor_sets() {
+        assert!(SpeedFactors::default().is_empty());
+        assert_eq!(
+            SpeedFactors::ALL.iter().collect::<Vec<_>>(),
+            SpeedFactor::ALL
+        );
+
+        let factors = [SpeedFactor::Double, SpeedFactor::Half]
+            .into_iter()
+            .collect::<SpeedFactors>();
+        assert!(factors.contains(SpeedFactor::Half));
+        assert!(!factors.contains(SpeedFactor::OneAndAHalf));
+        assert_eq!(
+            factors.iter().collect::<Vec<_>>(),
+            [SpeedFactor::Half, SpeedFactor::Double]
+        );
+    }
+}
@@ -0,0 +1,300 @@
+//! Thumbnails of videos, for showing matches to people.
+//!
+//! Frontends that show thumbnails next to each other (or next to a match they are deciding on)
+//! should all make them with [`thumbnails`] or [`thumbnails_with_options`], so that the same video
+//! always looks the same: the frames are taken from the part of the video that its hash is built
+//! from, and if asked for, cropped exactly as the hash cropped them.
+use std::{num::NonZeroU32, path::Path};
+
+use ffmpeg_gst_wrapper::FrameReadCfgTrait;
+use image::{imageops::FilterType, Rgb, RgbImage};
+use vid_dup_finder_common::Crop;
+
+use crate::video_hashing::video_hash_builder::{
+    display_dimensions, gen_thumbnail_frames, ThumbnailFrames,
+};
+use crate::{CreationOptions, Error};
+
+/// What the thumbnails made by [`thumbnails`] look like.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ThumbSpec {
+    /// The number of thumbnails, spread evenly over the part of the video that its hash is built
+    /// from. Fewer are returned if the video runs out of frames first.
+    pub count: usize,
+
+    /// The length of the longest side of each thumbnail. Frames are shrunk to fit, keeping their
+    /// aspect ratio, but never enlarged. `None` leaves them at the size they were decoded at.
+    pub max_dimension: Option<NonZeroU32>,
+
+    /// Crop the frames as the hash crops them (see [`CreationOptions::cropdetect`]). Unless it is
+    /// given to [`thumbnails_with_crop`], the crop is found by decoding the frames of the hash,
+    /// which takes as long as creating the hash.
+    pub cropdetect: bool,
+
+    /// Draw the time of each frame in its top left corner.
+    pub timestamps: bool,
+}
+
+impl Default for ThumbSpec {
+    fn default() -> Self {
+        Self {
+            count: 4,
+            max_dimension: NonZeroU32::new(320),
+            cropdetect: false,
+            timestamps: false,
+        }
+    }
+}
+
+/// Thumbnails of the video at `src_path`, as described by `spec`, taken from the part of the
+/// video that a hash with the default [`CreationOptions`] is built from.
+///
+/// # Errors
+/// Returns [`crate::Error::NotInitialized`] if [`crate::init`] has not been called, and the error
+/// that creating a hash would return if the video cannot be read. If no frames could be decoded,
+/// returns [`crate::Error::NotEnoughFrames`].
+#[cfg(any(feature = "ffmpeg_backend", feature = "gstreamer_backend"))]
+pub fn thumbnails(src_path: &Path, spec: &ThumbSpec) -> Result<Vec<RgbImage>, Error> {
+    thumbnails_with_options(src_path, spec, CreationOptions::default())
+}
+
+/// As [`thumbnails`], but for a hash created with `opts`. Pass the options that the video was
+/// actually hashed with, so that the thumbnails show the frames (and the crop) that the hash was
+/// built from.
+///
+/// # Errors
+/// As [`thumbnails`], and [`crate::Error::InvalidOptions`] if `opts` are invalid.
+#[cfg(any(feature = "ffmpeg_backend", feature = "gstreamer_backend"))]
+pub fn thumbnails_with_options(
+    src_path: &Path,
+    spec: &ThumbSpec,
+    opts: CreationOptions,
+) -> Result<Vec<RgbImage>, Error> {
+    thumbnails_with_crop(src_path, spec, opts, None)
+}
+
+/// As [`thumbnails_with_options`], but if `spec` asks for the frames to be cropped and `crop` is
+/// given, they are cropped with `crop` instead of finding the crop of the hash again. Pass the crop
+/// recorded in the hash of the video (see [`crate::VideoHash::crop`]), so that the thumbnails are
+/// cropped exactly as its frames were, without decoding them again.
+///
+/// # Errors
+/// As [`thumbnails_with_options`].
+#[cfg(any(feature = "ffmpeg_backend", feature = "gstreamer_backend"))]
+pub fn thumbnails_with_crop(
+    src_path: &Path,
+    spec: &ThumbSpec,
+    opts: CreationOptions,
+    crop: Option<Crop>,
+) -> Result<Vec<RgbImage>, Error> {
+    #[cfg(feature = "ffmpeg_backend")]
+    type Backend = ffmpeg_gst_wrapper::ffmpeg_impl::FrameReaderCfgFfmpeg;
+    #[cfg(all(feature = "gstreamer_backend", not(feature = "ffmpeg_backend")))]
+    type Backend = ffmpeg_gst_wrapper::gst_impl::FrameReaderCfgGst;
+
+    crate::video_hashing::init::ensure_initialized()?;
+    gen_thumbnails::<Backend>(src_path, spec, opts, crop)
+}
+
+pub(crate) fn gen_thumbnails<T: FrameReadCfgTrait + Clone>(
+    src_path: &Path,
+    spec: &ThumbSpec,
+    opts: CreationOptions,
+    known_crop: Option<Crop>,
+) -> Result<Vec<RgbImage>, Error>
+where
+    T::E: std::error::Error,
+{
+    let ThumbnailFrames {
+        frames,
+        crop,
+        pixel_aspect_ratio,
+    } = gen_thumbnail_frames::<T>(src_path, opts, spec.count, spec.cropdetect, known_crop)?;
+
+    let thumbs = frames
+        .into_iter()
+        .map(|(time, frame)| {
+            let (width, height) = display_dimensions(frame.dimensions(), pixel_aspect_ratio);
+            let frame = resize(frame, width, height);
+            let frame = match crop {
+                Some(crop) => apply_crop(&frame, crop),
+                None => frame,
+            };
+            let mut frame = match spec.max_dimension {
+                Some(max_dimension) => fit(frame, max_dimension),
+                None => frame,
+            };
+            if spec.timestamps {
+                draw_timestamp(&mut frame, time);
+            }
+            frame
+        })
+        .collect();
+    Ok(thumbs)
+}
+
+fn resize(frame: RgbImage, width: u32, height: u32) -> RgbImage {
+    if frame.dimensions() == (width, height) {
+        frame
+    } else {
+        image::imageops::resize(&frame, width, height, FilterType::Triangle)
+    }
+}
+
+// Shrink the frame so that its longest side is at most max_dimension.
+fn fit(frame: RgbImage, max_dimension: NonZeroU32) -> RgbImage {
+    let (width, height) = frame.dimensions();
+    let longest = width.max(height);
+    let max_dimension = max_dimension.get();
+    if longest <= max_dimension {
+        return frame;
+    }
+
+    let scale = |side: u32| {
+        let scaled = u64::from(side) * u64::from(max_dimension) / u64::from(longest);
+        (scaled as u32).max(1)
+    };
+    resize(frame, scale(width), scale(height))
+}
+
+// Crop the frame with a crop that was detected in a shrunk copy of it.
+fn apply_crop(frame: &RgbImage, crop: Crop) -> RgbImage {
+    let (width, height) = frame.dimensions();
+    let (crop_width, crop_height) = crop.orig_res;
+    let scale = |offset: u32, side: u32, crop_side: u32| {
+        (u64::from(offset) * u64::from(side) / u64::from(crop_side.max(1))) as u32
+    };
+    let left = scale(crop.left, width, crop_width);
+    let right = scale(crop.right, width, crop_width);
+    let top = scale(crop.top, height, crop_height);
+    let bottom = scale(crop.bottom, height, crop_height);
+
+    let cropped_width = width.saturating_sub(left + right).max(1);
+    let cropped_height = height.saturating_sub(top + bottom).max(1);
+    image::imageops::crop_imm(frame, left, top, cropped_width, cropped_height).to_image()
+}
+
+// "m:ss", or "h:mm:ss" from an hour on.
+fn format_timestamp(seconds: f64) -> String {
+    let total = seconds.max(0.0) as u64;
+    let (hours, minutes, seconds) = (total / 3600, total / 60 % 60, total % 60);
+    if hours > 0 {
+        format!("{hours}:{minutes:02}:{seconds:02}")
+    } else {
+        format!("{minutes}:{seconds:02}")
+    }
+}
+
+// Each glyph is 3 pixels wide and 5 high, one row per element with the leftmost pixel in the
+// highest of the three bits.
+fn glyph(c: char) -> [u8; 5] {
+    match c {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b001, 0b001, 0b001],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        ':' => [0b000, 0b010, 0b000, 0b010, 0b000],
+        _ => [0; 5],
+    }
+}
+
+// Draw the time in white on a black box in the top left corner, scaled with the frame so that it
+// stays legible. Anything that does not fit is left out.
+fn draw_timestamp(frame: &mut RgbImage, seconds: f64) {
+    let text = format_timestamp(seconds);
+    let scale = (frame.height() / 48).max(1);
+    let box_width = (text.chars().count() as u32 * 4 + 1) * scale;
+    let box_height = 7 * scale;
+
+    let mut set = |x: u32, y: u32, pix: Rgb<u8>| {
+        if x < frame.width() && y < frame.height() {
+            frame.put_pixel(x, y, pix);
+        }
+    };
+    for y in 0..box_height {
+        for x in 0..box_width {
+            set(x, y, Rgb([0, 0, 0]));
+        }
+    }
+    for (i, c) in text.chars().enumerate() {
+        let left = (i as u32 * 4 + 1) * scale;
+        for (row, bits) in glyph(c).into_iter().enumerate() {
+            for col in 0..3 {
+                if bits & (0b100 >> col) == 0 {
+                    continue;
+                }
+                for dy in 0..scale {
+                    for dx in 0..scale {
+                        let x = left + col * scale + dx;
+                        let y = (row as u32 + 1) * scale + dy;
+                        set(x, y, Rgb([255, 255, 255]));
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn gradient(width: u32, height: u32) -> RgbImage {
+        RgbImage::from_fn(width, height, |x, y| Rgb([x as u8, y as u8, 128]))
+    }
+
+    #[test]
+    fn test_fit_keeps_the_aspect_ratio() {
+        let size = |n| NonZeroU32::new(n).expect("nonzero");
+        assert_eq!(fit(gradient(640, 480), size(320)).dimensions(), (320, 240));
+        assert_eq!(fit(gradient(480, 640), size(320)).dimensions(), (240, 320));
+
+        //frames are never enlarged.
+        assert_eq!(fit(gradient(160, 90), size(320)).dimensions(), (160, 90));
+    }
+
+    #[test]
+    fn test_crops_are_scaled_to_the_frame() {
+        //letterboxed by 24 rows top and bottom in a 256x192 proxy of a 640x480 frame.
+        let crop = Crop::from_edge_offsets((256, 192), 0, 0, 24, 24);
+        let frame = gradient(640, 480);
+        let cropped = apply_crop(&frame, crop);
+        assert_eq!(cropped.dimensions(), (640, 360));
+        assert_eq!(cropped.get_pixel(0, 0), frame.get_pixel(0, 60));
+
+        //an empty crop changes nothing.
+        let no_crop = Crop::from_edge_offsets((64, 64), 0, 0, 0, 0);
+        assert_eq!(apply_crop(&frame, no_crop), frame);
+    }
+
+    #[test]
+    fn test_timestamps() {
+        assert_eq!(format_timestamp(0.0), "0:00");
+        assert_eq!(format_timestamp(75.9), "1:15");
+        assert_eq!(format_timestamp(3725.0), "1:02:05");
+
+        let frame = gradient(320, 240);
+        let mut stamped = frame.clone();
+        draw_timestamp(&mut stamped, 75.0);
+        let mut later = frame.clone();
+        draw_timestamp(&mut later, 76.0);
+        assert_ne!(stamped, frame);
+        assert_ne!(stamped, later);
+
+        //only the top left corner is drawn over.
+        let changed = frame
+            .enumerate_pixels()
+            .filter(|(x, y, pix)| stamped.get_pixel(*x, *y) != *pix);
+        assert!(changed.clone().all(|(x, y, _)| x < 100 && y < 40));
+
+        //frames too small for the whole time get as much as fits.
+        let mut tiny = gradient(4, 4);
+        draw_timestamp(&mut tiny, 75.0);
+    }
+}
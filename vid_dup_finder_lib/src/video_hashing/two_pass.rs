@@ -0,0 +1,321 @@
+//! Searching with cheap hashes first, and then refining the matches with expensive ones.
+//!
+//! Hashes made from a long window of each video at a large hash size tell videos apart better
+//! than the defaults, but take much longer to create. [`two_pass_search`] only pays for them where
+//! they are needed: Every video is hashed with cheap options and searched at a loose tolerance,
+//! and then only the videos that matched anything are hashed again with the expensive options and
+//! searched again, one group at a time, at a strict tolerance.
+//!
+//! ```
+//! use std::path::PathBuf;
+//! use vid_dup_finder_lib::*;
+//! # fn hash_all(paths: &[PathBuf], opts: CreationOptions) -> Vec<VideoHash> { vec![] }
+//!
+//! let thorough = CreationOptions {
+//!     hash_bits: HashSize::Ten,
+//!     ..Preset::Thorough.options()
+//! };
+//! let paths = vec![PathBuf::from("a.mp4"), PathBuf::from("b.mp4")];
+//! let groups = two_pass_search(
+//!     paths,
+//!     Preset::Fast.options(),
+//!     thorough,
+//!     TwoPassTolerances::default(),
+//!     hash_all,
+//!     None,
+//! )
+//! .expect("valid options");
+//! ```
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use itertools::Itertools;
+
+use crate::{
+    search_with_opts, CancellationToken, CreationOptions, Error, MatchGroup, SearchOptions,
+    Tolerance, VideoHash,
+};
+
+/// The tolerances of the two passes of [`two_pass_search`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TwoPassTolerances {
+    /// The tolerance of the first search, of every video. This should be looser than a normal
+    /// search so that the first pass misses as few duplicates as possible.
+    pub coarse: Tolerance,
+
+    /// The tolerance of the second search, within each group found by the first.
+    pub fine: Tolerance,
+}
+
+impl Default for TwoPassTolerances {
+    /// 0.45 for the first pass and [`Tolerance::DEFAULT`] for the second.
+    fn default() -> Self {
+        Self {
+            coarse: Tolerance::clamped(0.45),
+            fine: Tolerance::DEFAULT,
+        }
+    }
+}
+
+/// Search `paths` for duplicates in two passes, hashing every video cheaply and then only the
+/// videos that might be duplicates expensively:
+///
+/// 1. Every path is hashed with `coarse_opts`, and the hashes are searched at
+///    [`TwoPassTolerances::coarse`].
+/// 2. Only the paths in the groups found are hashed again, with `fine_opts`.
+/// 3. The members of each group are searched again with their new hashes at
+///    [`TwoPassTolerances::fine`], and the groups found are returned.
+///
+/// Hashes are created by `hasher`, which is given a batch of paths and the options to hash them
+/// with, and returns the hashes that it could create. It is called once for each pass, so it can
+/// hash the batch in parallel, or fetch the hashes from a cache (which must keep the hashes of
+/// each set of options apart), and can report progress as it goes. Paths which cannot be hashed
+/// are left out of the search, as they would be from any other.
+///
+/// If `cancellation` is cancelled, the search stops after the current pass and returns
+/// [`Error::Cancelled`]. It is not passed to `hasher`, which should be given the token too if it
+/// takes a long time.
+///
+/// # Errors
+/// [`Error::InvalidOptions`] if either set of options is invalid (before anything is hashed), or
+/// [`Error::Cancelled`].
+pub fn two_pass_search(
+    paths: impl IntoIterator<Item = PathBuf>,
+    coarse_opts: CreationOptions,
+    fine_opts: CreationOptions,
+    tolerances: TwoPassTolerances,
+    mut hasher: impl FnMut(&[PathBuf], CreationOptions) -> Vec<VideoHash>,
+    cancellation: Option<&CancellationToken>,
+) -> Result<Vec<MatchGroup>, Error> {
+    coarse_opts.validate()?;
+    fine_opts.validate()?;
+    let check_cancelled = || match cancellation {
+        Some(token) if token.is_cancelled() => Err(Error::Cancelled),
+        _ => Ok(()),
+    };
+
+    let paths = paths.into_iter().unique().collect::<Vec<_>>();
+    let coarse_hashes = hasher(&paths, coarse_opts);
+    check_cancelled()?;
+    let coarse_groups = search_with_opts(coarse_hashes, &SearchOptions::new(tolerances.coarse));
+
+    let grouped = coarse_groups
+        .iter()
+        .flat_map(MatchGroup::contained_paths)
+        .map(Path::to_path_buf)
+        .unique()
+        .collect::<Vec<_>>();
+    let fine_hashes = hasher(&grouped, fine_opts)
+        .into_iter()
+        .map(|hash| (hash.src_path().to_path_buf(), hash))
+        .collect::<HashMap<_, _>>();
+    check_cancelled()?;
+
+    let fine_search = SearchOptions::new(tolerances.fine);
+    let refined = coarse_groups
+        .iter()
+        .flat_map(|group| {
+            let hashes = group
+                .contained_paths()
+                .filter_map(|path| fine_hashes.get(path).cloned())
+                .collect::<Vec<_>>();
+            search_with_opts(hashes, &fine_search)
+        })
+        //a pair can be in more than one coarse group, depending on the grouping mode.
+        .unique_by(|group| {
+            group
+                .contained_paths()
+                .sorted()
+                .map(Path::to_path_buf)
+                .collect_vec()
+        })
+        .collect();
+    Ok(refined)
+}
+
+#[cfg(test)]
+mod test {
+    use std::{cell::RefCell, time::Duration};
+
+    use image::{GrayImage, Luma};
+
+    use super::*;
+    use crate::{
+        definitions::DCT_SIZE, video_hashing::video_hash_builder::hash_from_frame_iter, Cropdetect,
+        HashSize,
+    };
+
+    // A synthetic video: one scene until the given number of seconds, and another after it.
+    #[derive(Debug, Clone, Copy)]
+    struct Video {
+        name: &'static str,
+        first: u32,
+        second: u32,
+        cut_at: f64,
+    }
+
+    // Each scene is a different grid of grey blocks, which scrolls to the right over time.
+    fn frame(scene: u32, t: f64) -> GrayImage {
+        let shift = (t * 2.0) as u32;
+        GrayImage::from_fn(64, 48, |x, y| {
+            let block = ((x + shift) / 16 % 4) + y / 12 * 4 + scene * 16;
+            Luma([(block.wrapping_mul(2_654_435_761) >> 24) as u8])
+        })
+    }
+
+    // Sample frames evenly from the window given by opts, as the backends do.
+    fn hash(video: Video, opts: CreationOptions) -> VideoHash {
+        let frames = (0..DCT_SIZE).map(|i| {
+            let t = opts.skip_forward_amount + opts.duration * f64::from(i) / f64::from(DCT_SIZE);
+            let scene = if t < video.cut_at {
+                video.first
+            } else {
+                video.second
+            };
+            frame(scene, t)
+        });
+        hash_from_frame_iter(
+            PathBuf::from(video.name),
+            Duration::from_secs(120),
+            frames,
+            opts,
+            None,
+        )
+        .expect("frames can be hashed")
+    }
+
+    fn opts(duration: f64, hash_bits: HashSize) -> CreationOptions {
+        CreationOptions {
+            skip_forward_amount: 0.0,
+            duration,
+            hash_bits,
+            cropdetect: Cropdetect::None,
+            ..CreationOptions::default()
+        }
+    }
+
+    // Videos which start with the same 4 second intro, only two of which are the same after it.
+    const VIDEOS: [Video; 4] = [
+        Video {
+            name: "episode_1.mp4",
+            first: 0,
+            second: 1,
+            cut_at: 4.0,
+        },
+        Video {
+            name: "episode_1_copy.mp4",
+            first: 0,
+            second: 1,
+            cut_at: 4.0,
+        },
+        Video {
+            name: "episode_2.mp4",
+            first: 0,
+            second: 2,
+            cut_at: 4.0,
+        },
+        Video {
+            name: "unrelated.mp4",
+            first: 3,
+            second: 3,
+            cut_at: 0.0,
+        },
+    ];
+
+    fn names(groups: &[MatchGroup]) -> Vec<Vec<&str>> {
+        groups
+            .iter()
+            .map(|group| {
+                group
+                    .contained_paths()
+                    .map(|path| path.to_str().expect("utf8 path"))
+                    .sorted()
+                    .collect()
+            })
+            .sorted()
+            .collect()
+    }
+
+    #[test]
+    fn test_groups_are_split_by_the_fine_pass() {
+        let coarse = opts(3.0, HashSize::Eight);
+        let fine = opts(30.0, HashSize::Ten);
+        let paths = VIDEOS.map(|video| PathBuf::from(video.name));
+        let requests = RefCell::new(vec![]);
+        let hasher = |paths: &[PathBuf], opts: CreationOptions| {
+            requests.borrow_mut().push((paths.len(), opts));
+            VIDEOS
+                .into_iter()
+                .filter(|video| paths.iter().any(|path| path == Path::new(video.name)))
+                .map(|video| hash(video, opts))
+                .collect()
+        };
+
+        //the coarse hashes only see the intro, so every episode matches.
+        let coarse_groups = search_with_opts(
+            VIDEOS.map(|video| hash(video, coarse)),
+            &SearchOptions::new(TwoPassTolerances::default().coarse),
+        );
+        assert_eq!(
+            names(&coarse_groups),
+            [["episode_1.mp4", "episode_1_copy.mp4", "episode_2.mp4"]]
+        );
+
+        let tolerances = TwoPassTolerances {
+            fine: Tolerance::new(0.1).expect("valid tolerance"),
+            ..TwoPassTolerances::default()
+        };
+        let groups =
+            two_pass_search(paths, coarse, fine, tolerances, hasher, None).expect("valid options");
+        assert_eq!(names(&groups), [["episode_1.mp4", "episode_1_copy.mp4"]]);
+
+        //everything was hashed coarsely, and only the episodes finely.
+        assert_eq!(*requests.borrow(), [(4, coarse), (3, fine)]);
+    }
+
+    #[test]
+    fn test_cancelled_and_invalid_searches() {
+        let coarse = opts(3.0, HashSize::Eight);
+        let fine = opts(30.0, HashSize::Ten);
+        let paths = VIDEOS.map(|video| PathBuf::from(video.name));
+
+        //cancelling while the first pass is hashing stops before the second.
+        let token = CancellationToken::new();
+        let mut passes = 0;
+        let res = two_pass_search(
+            paths.clone(),
+            coarse,
+            fine,
+            TwoPassTolerances::default(),
+            |_paths, _opts| {
+                passes += 1;
+                token.cancel();
+                vec![]
+            },
+            Some(&token),
+        );
+        assert!(matches!(res, Err(Error::Cancelled)));
+        assert_eq!(passes, 1);
+
+        let invalid = CreationOptions {
+            duration: -1.0,
+            ..fine
+        };
+        let mut passes = 0;
+        let res = two_pass_search(
+            paths,
+            coarse,
+            invalid,
+            TwoPassTolerances::default(),
+            |_paths, _opts| {
+                passes += 1;
+                vec![]
+            },
+            None,
+        );
+        assert!(matches!(res, Err(Error::InvalidOptions(_))));
+        assert_eq!(passes, 0);
+    }
+}
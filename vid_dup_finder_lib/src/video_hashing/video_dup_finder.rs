@@ -1,32 +1,262 @@
-use crate::{MatchGroup, VideoHash};
+use std::{
+    collections::{hash_map, BTreeMap, HashMap},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Instant,
+};
 
-use super::search_algorithm::Search;
+use crate::{
+    AudioCheck, AudioFingerprint, CollectionMode, FalseposFilter, GroupOrdering, MatchGroup,
+    SearchOptions, SearchStats, ShortVideoPolicy, Tolerance, VideoHash, ZeroDistanceCheck,
+};
+
+use super::{
+    match_verification::aligned_frames,
+    search_algorithm::{FoundGroup, MatchThreshold, Search},
+    search_plan::SearchPlan,
+    search_stats::DuplicateInput,
+    zero_distance::sampled_frames,
+};
 
 /// Search for duplicates within the given hashes, within the given tolerance. Returns groups for all the matching videos.
 /// Each group may have multiple entries if multiple videos are duplicates of each other.
+///
+/// Pairs of short videos are compared using the default [`ShortVideoPolicy`], and groups are
+/// returned in the default [`GroupOrdering`] (sorted by path). A tolerance outside of 0.0 to 1.0
+/// is clamped, with a warning.
+#[deprecated(note = "use search_with_opts with a validated Tolerance")]
 pub fn search(hashes: impl IntoIterator<Item = VideoHash>, tolerance: f64) -> Vec<MatchGroup> {
-    Search::from(hashes)
-        .search_self(tolerance)
+    search_with_opts(hashes, &opts_with_tolerance(tolerance))
+}
+
+/// As [`search`], but with a custom policy for comparing pairs of videos that are both
+/// shorter than the hash duration.
+#[deprecated(note = "use search_with_opts with a validated Tolerance")]
+pub fn search_with_short_video_policy(
+    hashes: impl IntoIterator<Item = VideoHash>,
+    tolerance: f64,
+    short_video_policy: ShortVideoPolicy,
+) -> Vec<MatchGroup> {
+    let opts = SearchOptions {
+        short_video_policy,
+        ..opts_with_tolerance(tolerance)
+    };
+    search_with_opts(hashes, &opts)
+}
+
+/// As [`search`], but never reporting a pair of videos suppressed by `filter` as duplicates.
+///
+/// Suppressed pairs are removed while groups are being built, so they cannot be grouped together
+/// indirectly unless a third video in the group matches both of them.
+#[deprecated(note = "use search_with_opts with a validated Tolerance")]
+pub fn search_filtered(
+    hashes: impl IntoIterator<Item = VideoHash>,
+    tolerance: f64,
+    filter: &FalseposFilter,
+) -> Vec<MatchGroup> {
+    let opts = SearchOptions {
+        falsepos_filter: filter.clone(),
+        ..opts_with_tolerance(tolerance)
+    };
+    search_with_opts(hashes, &opts)
+}
+
+// The default options for the functions which take a bare tolerance.
+fn opts_with_tolerance(tolerance: f64) -> SearchOptions {
+    SearchOptions::new(Tolerance::clamped_with_warning(tolerance))
+}
+
+/// Search for duplicates within the given hashes. Returns groups for all the matching videos.
+/// Each group may have multiple entries if multiple videos are duplicates of each other.
+///
+/// If several hashes have the same path, only the first is searched (see [`DuplicateInput`]), so
+/// a group never contains the same path twice.
+pub fn search_with_opts(
+    hashes: impl IntoIterator<Item = VideoHash>,
+    opts: &SearchOptions,
+) -> Vec<MatchGroup> {
+    search_with_stats(hashes, opts).0
+}
+
+/// As [`search_with_opts`], but also returning how much work the search did.
+pub fn search_with_stats(
+    hashes: impl IntoIterator<Item = VideoHash>,
+    opts: &SearchOptions,
+) -> (Vec<MatchGroup>, SearchStats) {
+    let start = Instant::now();
+    let mut labels = Labels::new(opts);
+    let mut inputs = Inputs::default();
+    let hashes = hashes
         .into_iter()
-        .filter_map(|x| MatchGroup::new(x).ok())
-        .collect()
+        .filter(|hash| inputs.keep(hash, false))
+        .inspect(|hash| labels.record(hash));
+
+    let search_struct = Search::from(hashes);
+    search_self(search_struct, &labels, inputs, opts, start)
+}
+
+/// As [`search_with_opts`], but taking the search order of the hashes from a plan made by
+/// [`crate::plan_search`], instead of sorting them again.
+///
+/// `hashes` must be the hashes that the plan was made for, in the same order. If they are not, a
+/// warning is logged and they are sorted as usual, so the result is the same either way.
+pub fn search_with_plan(
+    hashes: Vec<VideoHash>,
+    plan: &SearchPlan,
+    opts: &SearchOptions,
+) -> Vec<MatchGroup> {
+    let start = Instant::now();
+    let mut labels = Labels::new(opts);
+    let mut inputs = Inputs::default();
+    let keep = hashes
+        .iter()
+        .map(|hash| inputs.keep(hash, false))
+        .collect::<Vec<_>>();
+    for (hash, _) in hashes.iter().zip(&keep).filter(|(_, &keep)| keep) {
+        labels.record(hash);
+    }
+
+    let search_struct = if plan.fits(&hashes) {
+        Search::presorted(plan.sorted(hashes, &keep))
+    } else {
+        log::warn!("search plan was made for different hashes, so they will be sorted again");
+        Search::from(
+            hashes
+                .into_iter()
+                .zip(keep)
+                .filter_map(|(hash, keep)| keep.then_some(hash)),
+        )
+    };
+    search_self(search_struct, &labels, inputs, opts, start).0
+}
+
+fn search_self(
+    mut search_struct: Search,
+    labels: &Labels,
+    inputs: Inputs,
+    opts: &SearchOptions,
+    start: Instant,
+) -> (Vec<MatchGroup>, SearchStats) {
+    let groups = search_struct
+        .search_self(opts)
+        .into_iter()
+        .filter_map(
+            |FoundGroup {
+                 paths,
+                 max_distance,
+                 duration_spread,
+             }| {
+                let group = MatchGroup::new(paths)
+                    .ok()?
+                    .with_duration_spread(duration_spread);
+                if !opts.allows_group(group.contained_paths()) {
+                    return None;
+                }
+                labels.label(group, opts).map(|group| (group, max_distance))
+            },
+        )
+        .collect();
+
+    let groups = order_groups(groups, opts.ordering);
+    let stats = finish_stats(search_struct.stats(), &groups, inputs, start);
+    (groups, stats)
 }
 
 /// Search new_hashes for all videos that are duplicates of videos in ref_hashes. Returns a set of groups,
 /// one group for each reference video that was matched.
+///
+/// Pairs of short videos are compared using the default [`ShortVideoPolicy`]. A tolerance outside
+/// of 0.0 to 1.0 is clamped, with a warning.
 /// # Panics
 /// Should only panic due to internal implementation error
+#[deprecated(note = "use search_with_references_with_opts with a validated Tolerance")]
 pub fn search_with_references(
     ref_hashes: impl IntoIterator<Item = VideoHash>,
     new_hashes: impl IntoIterator<Item = VideoHash>,
     tolerance: f64,
 ) -> Vec<MatchGroup> {
+    search_with_references_with_opts(ref_hashes, new_hashes, &opts_with_tolerance(tolerance))
+}
+
+/// As [`search_with_references`], but with a custom policy for comparing pairs of videos that are both
+/// shorter than the hash duration.
+/// # Panics
+/// Should only panic due to internal implementation error
+#[deprecated(note = "use search_with_references_with_opts with a validated Tolerance")]
+pub fn search_with_references_and_short_video_policy(
+    ref_hashes: impl IntoIterator<Item = VideoHash>,
+    new_hashes: impl IntoIterator<Item = VideoHash>,
+    tolerance: f64,
+    short_video_policy: ShortVideoPolicy,
+) -> Vec<MatchGroup> {
+    let opts = SearchOptions {
+        short_video_policy,
+        ..opts_with_tolerance(tolerance)
+    };
+    search_with_references_with_opts(ref_hashes, new_hashes, &opts)
+}
+
+/// As [`search_with_references`], but never reporting a pair of videos suppressed by `filter` as duplicates.
+///
+/// A video whose pairing with a reference is suppressed is only included in that reference's group
+/// if another video in the group matches both of them.
+/// # Panics
+/// Should only panic due to internal implementation error
+#[deprecated(note = "use search_with_references_with_opts with a validated Tolerance")]
+pub fn search_with_references_filtered(
+    ref_hashes: impl IntoIterator<Item = VideoHash>,
+    new_hashes: impl IntoIterator<Item = VideoHash>,
+    tolerance: f64,
+    filter: &FalseposFilter,
+) -> Vec<MatchGroup> {
+    let opts = SearchOptions {
+        falsepos_filter: filter.clone(),
+        ..opts_with_tolerance(tolerance)
+    };
+    search_with_references_with_opts(ref_hashes, new_hashes, &opts)
+}
+
+/// Search new_hashes for all videos that are duplicates of videos in ref_hashes. Returns a set of
+/// groups, one group for each reference video that was matched.
+///
+/// If several hashes have the same path, only the first is searched (see [`DuplicateInput`]).
+/// A video which is given both as a reference and as a new hash is only searched as a reference.
+/// # Panics
+/// Should only panic due to internal implementation error
+pub fn search_with_references_with_opts(
+    ref_hashes: impl IntoIterator<Item = VideoHash>,
+    new_hashes: impl IntoIterator<Item = VideoHash>,
+    opts: &SearchOptions,
+) -> Vec<MatchGroup> {
+    search_with_references_with_stats(ref_hashes, new_hashes, opts).0
+}
+
+/// As [`search_with_references_with_opts`], but also returning how much work the search did.
+/// # Panics
+/// Should only panic due to internal implementation error
+pub fn search_with_references_with_stats(
+    ref_hashes: impl IntoIterator<Item = VideoHash>,
+    new_hashes: impl IntoIterator<Item = VideoHash>,
+    opts: &SearchOptions,
+) -> (Vec<MatchGroup>, SearchStats) {
+    let start = Instant::now();
+    let mut labels = Labels::new(opts);
+    let mut inputs = Inputs::default();
+    let ref_hashes = ref_hashes
+        .into_iter()
+        .filter(|hash| inputs.keep(hash, true))
+        .collect::<Vec<_>>();
+    let new_hashes = new_hashes
+        .into_iter()
+        .filter(|hash| inputs.keep(hash, false))
+        .inspect(|hash| labels.record(hash));
+
     let mut search_struct = Search::from(new_hashes);
-    ref_hashes
+    let groups = ref_hashes
         .into_iter()
         .filter_map(|ref_hash| {
-            let mut search_result =
-                search_struct.search_with_references(&[&ref_hash], tolerance, false);
+            labels.record(&ref_hash);
+            let mut search_result = search_struct.search_with_references(&[&ref_hash], opts, false);
 
             // Because we search with only a single reference video at a time, the above
             // returns a vec of length exactly 1. If there are any matches then the 0th
@@ -35,12 +265,268 @@ pub fn search_with_references(
                 .pop()
                 .expect("search always returns exactly 1 element");
 
-            if search_result.is_empty() {
-                None
-            } else {
-                MatchGroup::new_with_reference(ref_hash.src_path().to_path_buf(), search_result)
-                    .ok()
+            let group = MatchGroup::new_with_reference(ref_hash.src_path(), search_result.paths)
+                .ok()?
+                .with_duration_spread(search_result.duration_spread);
+            if !opts.allows_group(group.contained_paths()) {
+                return None;
             }
+            labels
+                .label(group, opts)
+                .map(|group| (group, search_result.max_distance))
         })
-        .collect()
+        .collect();
+
+    let groups = order_groups(groups, opts.ordering);
+    let stats = finish_stats(search_struct.stats(), &groups, inputs, start);
+    (groups, stats)
+}
+
+/// As [`search_with_references_with_opts`], but with a priority for each reference. A video which
+/// matches several references is only put in the group of one of them: the one with the highest
+/// priority, or if several have the same priority then the closest, or then the one with the
+/// smallest path. The other references that it matched can be found with
+/// [`MatchGroup::alternate_references`].
+///
+/// This is useful when some references are canonical copies (given a high priority) and others are
+/// mirrors of them, so that a new video is reported as a copy of the canonical video wherever
+/// possible.
+/// # Panics
+/// Should only panic due to internal implementation error
+pub fn search_with_references_prioritized(
+    ref_hashes: impl IntoIterator<Item = (VideoHash, u32)>,
+    new_hashes: impl IntoIterator<Item = VideoHash>,
+    opts: &SearchOptions,
+) -> Vec<MatchGroup> {
+    let mut labels = Labels::new(opts);
+    let mut inputs = Inputs::default();
+    let refs = ref_hashes
+        .into_iter()
+        .filter(|(hash, _)| inputs.keep(hash, true))
+        .inspect(|(hash, _)| labels.record(hash))
+        .collect::<Vec<_>>();
+    let new_hashes = new_hashes
+        .into_iter()
+        .filter(|hash| inputs.keep(hash, false))
+        .inspect(|hash| labels.record(hash))
+        .collect::<Vec<_>>();
+    let new_by_path = new_hashes
+        .iter()
+        .map(|hash| (hash.src_path(), hash))
+        .collect::<HashMap<_, _>>();
+
+    //Every reference that each new video matched, as (reference index, normalized distance).
+    let mut search_struct = Search::from(new_hashes.iter().cloned());
+    let mut matched_refs = HashMap::<Arc<Path>, Vec<(usize, f64)>>::new();
+    for (ref_idx, (ref_hash, _)) in refs.iter().enumerate() {
+        let found = search_struct
+            .search_with_references(&[ref_hash], opts, false)
+            .pop()
+            .expect("search always returns exactly 1 element");
+        for path in found.paths {
+            let distance = f64::from(ref_hash.same_size_distance(new_by_path[&*path]))
+                / f64::from(ref_hash.hash_size().bits());
+            matched_refs
+                .entry(path)
+                .or_default()
+                .push((ref_idx, distance));
+        }
+    }
+
+    let ref_paths = refs
+        .iter()
+        .map(|(hash, _)| Arc::<Path>::from(hash.src_path()))
+        .collect::<Vec<_>>();
+    let mut attached = vec![vec![]; refs.len()];
+    let mut alternates = vec![BTreeMap::new(); refs.len()];
+    for (path, mut matches) in matched_refs {
+        matches.sort_by(|(idx1, d1), (idx2, d2)| {
+            let ((ref1, p1), (ref2, p2)) = (&refs[*idx1], &refs[*idx2]);
+            p2.cmp(p1)
+                .then_with(|| d1.total_cmp(d2))
+                .then_with(|| ref1.src_path().cmp(ref2.src_path()))
+        });
+        let (chosen, _) = matches[0];
+        if matches.len() > 1 {
+            let others = matches[1..]
+                .iter()
+                .map(|&(idx, _)| ref_paths[idx].clone())
+                .collect::<Vec<_>>();
+            alternates[chosen].insert(path.clone(), others);
+        }
+        attached[chosen].push((new_by_path[&*path], path));
+    }
+
+    let groups = refs
+        .iter()
+        .zip(ref_paths)
+        .zip(attached)
+        .zip(alternates)
+        .filter_map(|((((ref_hash, _), ref_path), attached), alternates)| {
+            let members = attached.iter().map(|(hash, path)| (*hash, path));
+            let found = FoundGroup::new(members, Some(ref_hash), opts);
+            let group = MatchGroup::new_with_reference(ref_path, found.paths)
+                .ok()?
+                .with_duration_spread(found.duration_spread)
+                .with_alternate_references(alternates);
+            if !opts.allows_group(group.contained_paths()) {
+                return None;
+            }
+            labels
+                .label(group, opts)
+                .map(|group| (group, found.max_distance))
+        })
+        .collect();
+
+    order_groups(groups, opts.ordering)
+}
+
+fn finish_stats(
+    stats: SearchStats,
+    groups: &[MatchGroup],
+    inputs: Inputs,
+    start: Instant,
+) -> SearchStats {
+    let stats = SearchStats {
+        groups: groups.len(),
+        duplicate_inputs: inputs.duplicates,
+        ..stats
+    };
+    log::debug!(
+        "search finished in {}ms: {} buckets, {} comparisons, {} skipped by duration, {} groups, {} duplicate inputs",
+        start.elapsed().as_millis(),
+        stats.buckets,
+        stats.comparisons,
+        stats.skipped_by_duration,
+        stats.groups,
+        stats.duplicate_inputs.len()
+    );
+    stats
+}
+
+// The paths of the hashes given to a search so far, and whether each is a reference.
+#[derive(Default)]
+struct Inputs {
+    seen: HashMap<PathBuf, bool>,
+    duplicates: Vec<DuplicateInput>,
+}
+
+impl Inputs {
+    // False if a hash with the same path has already been given.
+    fn keep(&mut self, hash: &VideoHash, is_reference: bool) -> bool {
+        match self.seen.entry(hash.src_path().to_path_buf()) {
+            hash_map::Entry::Vacant(entry) => {
+                entry.insert(is_reference);
+                true
+            }
+            hash_map::Entry::Occupied(entry) => {
+                log::warn!(
+                    "{} was given to the search more than once. Only its first hash is searched",
+                    entry.key().display()
+                );
+                self.duplicates.push(DuplicateInput {
+                    path: entry.key().clone(),
+                    kept_reference: *entry.get(),
+                    discarded_reference: is_reference,
+                });
+                false
+            }
+        }
+    }
+}
+
+// The collections and audio fingerprints of the hashes being searched, for labelling the groups
+// that are found. The hashes themselves are only kept if pairs of them are to be verified, or to
+// have their speeds compared.
+#[derive(Default)]
+struct Labels {
+    collections: HashMap<PathBuf, String>,
+    audio: HashMap<PathBuf, AudioFingerprint>,
+    hashes: Option<HashMap<PathBuf, VideoHash>>,
+}
+
+impl Labels {
+    fn new(opts: &SearchOptions) -> Self {
+        Self {
+            hashes: (opts.zero_distance_check != ZeroDistanceCheck::Off || opts.speed_variants)
+                .then(HashMap::new),
+            ..Self::default()
+        }
+    }
+
+    fn record(&mut self, hash: &VideoHash) {
+        let path = hash.src_path();
+        if let Some(collection) = hash.collection() {
+            self.collections
+                .insert(path.to_path_buf(), collection.to_string());
+        }
+        if let Some(fingerprint) = hash.audio_fingerprint() {
+            self.audio.insert(path.to_path_buf(), fingerprint.clone());
+        }
+        if let Some(hashes) = &mut self.hashes {
+            hashes.insert(path.to_path_buf(), hash.clone());
+        }
+    }
+
+    // Returns None if the group should not be reported.
+    fn label(&self, group: MatchGroup, opts: &SearchOptions) -> Option<MatchGroup> {
+        let mut group = group
+            .with_collections(&self.collections)
+            .with_protected(|path| opts.is_protected(path));
+        if opts.audio_check != AudioCheck::Off {
+            group = group.with_audio_fingerprints(&self.audio);
+        }
+        if let (ZeroDistanceCheck::Verify { epsilon }, Some(hashes)) =
+            (opts.zero_distance_check, &self.hashes)
+        {
+            group = group.with_zero_distance_verification(hashes, epsilon, sampled_frames);
+        }
+        if let Some(verify) = &opts.verify_matches {
+            group = group.with_match_verification(verify, |path| aligned_frames(path, verify).ok());
+        }
+        if let (true, Some(hashes)) = (opts.speed_variants, &self.hashes) {
+            let threshold = MatchThreshold::new(opts);
+            group = group.with_speed_factors(hashes, |h1, h2| threshold.speed_factor(h1, h2));
+        }
+        match opts.collections {
+            CollectionMode::All => Some(group),
+            CollectionMode::CrossCollectionOnly => (group.num_collections() >= 2).then_some(group),
+        }
+    }
+}
+
+fn order_groups(
+    mut groups: Vec<(MatchGroup, Option<f64>)>,
+    ordering: GroupOrdering,
+) -> Vec<MatchGroup> {
+    if ordering != GroupOrdering::Unordered {
+        for (group, _) in &mut groups {
+            group.sort_paths();
+        }
+    }
+
+    match ordering {
+        GroupOrdering::BySize => groups.sort_by(|(g1, _), (g2, _)| {
+            let size = |g: &MatchGroup| g.contained_paths().count();
+            size(g2).cmp(&size(g1)).then_with(|| by_path(g1, g2))
+        }),
+        GroupOrdering::ByDistance => groups.sort_by(|(g1, d1), (g2, d2)| {
+            let distance = |d: &Option<f64>| d.unwrap_or_default();
+            distance(d1)
+                .total_cmp(&distance(d2))
+                .then_with(|| by_path(g1, g2))
+        }),
+        GroupOrdering::ByPath => groups.sort_by(|(g1, _), (g2, _)| by_path(g1, g2)),
+        GroupOrdering::Unordered => (),
+    }
+
+    groups.into_iter().map(|(group, _)| group).collect()
+}
+
+// Order by smallest contained path, using the whole group to break ties so that the order is total.
+fn by_path(g1: &MatchGroup, g2: &MatchGroup) -> std::cmp::Ordering {
+    let smallest_path = |g: &MatchGroup| g.contained_paths().min().map(Path::to_path_buf);
+    smallest_path(g1)
+        .cmp(&smallest_path(g2))
+        .then_with(|| g1.cmp(g2))
 }
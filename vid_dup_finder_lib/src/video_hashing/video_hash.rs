@@ -2,54 +2,475 @@ use std::{
     hash::Hash,
     num::NonZeroU32,
     path::{Path, PathBuf},
+    time::Duration,
 };
 
 use bitvec::prelude::*;
 
 use serde::{Deserialize, Serialize};
+use smallvec::{smallvec, SmallVec};
+use thiserror::Error;
 
 use vid_dup_finder_common::crop_resize_flat;
 use vid_dup_finder_common::Crop;
 
 use crate::{
-    definitions::{DCT_SIZE, HASH_BITS, HASH_QWORDS},
+    definitions::{Coverage, HashSize, SamplingWindow, COARSE_SIZE, DCT_SIZE, INLINE_HASH_QWORDS},
     video_hashing::dct_3d::Dct3d,
+    ColorRange, DurationSource, EnvironmentDigest,
     Error::NotEnoughFrames,
+    Normalization,
 };
 
-use image::GrayImage;
+use super::audio_fingerprint::AudioFingerprint;
+use super::speed_variants::{SpeedFactor, SpeedFactors, SpeedVariant};
+
+use image::{GrayImage, Rgb, RgbImage};
+
+//The words of a hash. There are always exactly as many as the size of the hash needs.
+pub(super) type HashArray = SmallVec<[u64; INLINE_HASH_QWORDS]>;
+
+//The words of a hash of the given size, with every bit clear.
+pub(super) fn zeroed_hash_array(hash_size: HashSize) -> HashArray {
+    smallvec![0; hash_size.qwords()]
+}
+
+//Each bin of a difference image is drawn as a square of this many pixels, and the slices are
+//separated by a gap of the same width.
+const DIFF_CELL_PIXELS: u32 = 6;
+const DIFF_AGREE: Rgb<u8> = Rgb([0, 200, 0]);
+const DIFF_DIFFER: Rgb<u8> = Rgb([220, 0, 0]);
+
+//Active areas are stored as integers, so that hashes stay Eq and Hash.
+pub(super) const ACTIVE_AREA_SCALE: u16 = 1000;
+
+//The fields of a Crop, which cannot be serialized itself.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub(super) struct HashCrop {
+    orig_res: (u32, u32),
+    left: u32,
+    right: u32,
+    top: u32,
+    bottom: u32,
+}
+
+impl From<Crop> for HashCrop {
+    fn from(crop: Crop) -> Self {
+        Self {
+            orig_res: crop.orig_res,
+            left: crop.left,
+            right: crop.right,
+            top: crop.top,
+            bottom: crop.bottom,
+        }
+    }
+}
+
+impl From<HashCrop> for Crop {
+    fn from(crop: HashCrop) -> Self {
+        Self {
+            orig_res: crop.orig_res,
+            left: crop.left,
+            right: crop.right,
+            top: crop.top,
+            bottom: crop.bottom,
+        }
+    }
+}
+
+/// The error returned when comparing two hashes which were created with different [`HashSize`]s.
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+#[error("Cannot compare a {0} hash with a {1} hash")]
+pub struct HashSizeMismatch(pub HashSize, pub HashSize);
 
 /// A hash of a video file, used for video duplicate detection. The hash contains information about
-/// the first 30 seconds of a video, and also the duration. Searches will use these data to determine
+/// the first 25 seconds of a video (by default, see [`SamplingWindow`]), and also the duration. Searches will use these data to determine
 /// similarity.
-
-#[derive(Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Serialize, Deserialize)]
+///
+/// Hashes can be saved and loaded with [`serde`], exchanged with other programs in a documented
+/// format with [`VideoHash::to_json_portable`], or stored in a small binary format with
+/// [`VideoHash::encode_compact`].
+///
+/// # Ordering and equality
+/// Hashes are ordered by their bits (first by [`HashSize`], then by the bits themselves), then by
+/// duration, then by path. Hashes which are the same in all of these are ordered by how they were
+/// created (whether they are of a short video, the video stream, window start, frame hashes,
+/// leading black, reliability mask, truncated decode, duration correction and motion crop
+/// fallback), then by collection, then by normalization, then by audio fingerprint and then by
+/// coverage, sampling window, pixel aspect ratio, source color range, deinterlacing, the
+/// environment that created the hash, the active area of its frames, its speed variants, the
+/// crop of its frames and where its duration came from. Two hashes are equal only if every one of these is equal, so
+/// [`Eq`], [`Hash`] and [`Ord`] always agree.
+///
+/// Every part of the key is made of integers, paths and strings (the percentiles of a
+/// [`Normalization::ContrastStretch`] are compared by their bits), so the ordering is total. The coarse
+/// signature that searches use to rule out non-matches is left out, as it is taken from the bits.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(try_from = "UncheckedVideoHash")]
 pub struct VideoHash {
-    //#[serde(with = "BigArray")]
-    hash: [u64; HASH_QWORDS as usize],
+    //Only the first hash_size.bits() bits are used. The rest are always zero.
+    pub(super) hash: HashArray,
+    pub(super) hash_size: HashSize,
+    #[serde(with = "vid_dup_finder_common::path_serde")]
+    pub(super) src_path: PathBuf,
+    pub(super) duration: u32,
+    pub(super) short_video: bool,
+    //A subset of the bits of hash, used to cheaply rule out non-matches during searches.
+    //Hashes serialized before this field existed have no coarse signature.
+    #[serde(default)]
+    pub(super) coarse: Option<u64>,
+    //The index of the video stream that was hashed, among the video streams of the file.
+    #[serde(default)]
+    pub(super) video_stream: usize,
+    //Only set for hashes of a single window of a longer video (see hash_windows).
+    #[serde(default)]
+    pub(super) window_start: Option<Duration>,
+    //A small spatial hash of each sampled frame, only kept if asked for in the CreationOptions.
+    #[serde(default)]
+    pub(super) frame_hashes: Option<Vec<u64>>,
+    //The black frames at the start of the video that were skipped (see CreationOptions::skip_leading_black).
+    #[serde(default)]
+    pub(super) leading_black: Duration,
+    //Where the hash came from, when searching hashes from several caches together. This is a
+    //label for the current search only, so it is never serialized.
+    #[serde(skip)]
+    pub(super) collection: Option<String>,
+    //Which bits of hash are reliable, only kept if asked for in the CreationOptions. Laid out in
+    //the same way as hash.
+    #[serde(default)]
+    pub(super) reliable_bits: Option<HashArray>,
+    //Set when the decoder failed part way through the frames the hash is built from, so that
+    //the last frames which could be decoded were repeated to make up the rest.
+    #[serde(default)]
+    pub(super) truncated_decode: bool,
+    //Set when the container reported a duration much longer than the frames that could be
+    //decoded, so that duration is the observed duration instead.
+    #[serde(default)]
+    pub(super) duration_corrected: bool,
+    //Set when motion cropping was asked for but was not confident enough in the area it found,
+    //so the frames were cropped to their letterbox instead.
+    #[serde(default)]
+    pub(super) motion_crop_fallback: bool,
+    //How the frames were adjusted before hashing (see CreationOptions::normalization).
+    #[serde(default)]
+    pub(super) normalization: Normalization,
+    //A signature of the audio over the same part of the video, only kept if asked for in the
+    //CreationOptions and if the video has audio.
+    #[serde(default)]
+    pub(super) audio_fingerprint: Option<AudioFingerprint>,
+    //Which parts of the video the frames were sampled from (see CreationOptions::coverage).
+    #[serde(default)]
+    pub(super) coverage: Coverage,
+    //The window after skipping forward that the frames were sampled from.
+    #[serde(default)]
+    pub(super) sampling_window: SamplingWindow,
+    //The pixel aspect ratio that the frames were stretched by before cropping, or None if they
+    //were not (which is also what hashes serialized before this field existed read back as).
+    #[serde(default)]
+    pub(super) pixel_aspect_ratio: Option<(u32, u32)>,
+    //The range the video was stored in, if its backend reported one. Frames are always decoded to
+    //full range, so this is only a record and does not affect matching.
+    #[serde(default)]
+    pub(super) source_color_range: Option<ColorRange>,
+    //Whether the frames were deinterlaced before hashing (see CreationOptions::deinterlace).
+    #[serde(default)]
+    pub(super) deinterlaced: bool,
+    //The digest of the decoders that created the hash (see the environment module), or None for
+    //hashes which were not decoded from a video by a backend, or were created before it was recorded.
+    #[serde(default)]
+    pub(super) environment: Option<EnvironmentDigest>,
+    //The average fraction of the area of the hashed frames that is not black, in thousandths (see
+    //VideoHash::active_area), or None for hashes created before it was recorded.
+    #[serde(default)]
+    pub(super) active_area: Option<u16>,
+    //The bits of the video as if played at other speeds, in order of their factors (see
+    //CreationOptions::speed_variants). Empty unless asked for.
+    #[serde(default)]
+    pub(super) speed_variants: Vec<SpeedVariant>,
+    //The crop applied to the decoded frames before hashing (see VideoHash::crop), or None for
+    //hashes which were not decoded from a video, or were created before it was recorded.
+    #[serde(default)]
+    pub(super) crop: Option<HashCrop>,
+    //Whether the duration came from the container or was measured by reading the video (see
+    //VideoHash::duration_source). Hashes created before it was recorded read back as Container.
+    #[serde(default)]
+    pub(super) duration_source: DurationSource,
+}
+
+//The fields of a VideoHash as they are deserialized, before they are checked to be consistent with
+//each other. Corrupt caches can hold any bytes, and a hash whose words do not match its size would
+//panic when it is compared. The serde attributes must match those of VideoHash.
+#[derive(Deserialize)]
+struct UncheckedVideoHash {
+    hash: HashArray,
+    hash_size: HashSize,
+    #[serde(with = "vid_dup_finder_common::path_serde")]
     src_path: PathBuf,
     duration: u32,
+    short_video: bool,
+    #[serde(default)]
+    coarse: Option<u64>,
+    #[serde(default)]
+    video_stream: usize,
+    #[serde(default)]
+    window_start: Option<Duration>,
+    #[serde(default)]
+    frame_hashes: Option<Vec<u64>>,
+    #[serde(default)]
+    leading_black: Duration,
+    #[serde(skip)]
+    collection: Option<String>,
+    #[serde(default)]
+    reliable_bits: Option<HashArray>,
+    #[serde(default)]
+    truncated_decode: bool,
+    #[serde(default)]
+    duration_corrected: bool,
+    #[serde(default)]
+    motion_crop_fallback: bool,
+    #[serde(default)]
+    normalization: Normalization,
+    #[serde(default)]
+    audio_fingerprint: Option<AudioFingerprint>,
+    #[serde(default)]
+    coverage: Coverage,
+    #[serde(default)]
+    sampling_window: SamplingWindow,
+    #[serde(default)]
+    pixel_aspect_ratio: Option<(u32, u32)>,
+    #[serde(default)]
+    source_color_range: Option<ColorRange>,
+    #[serde(default)]
+    deinterlaced: bool,
+    #[serde(default)]
+    environment: Option<EnvironmentDigest>,
+    #[serde(default)]
+    active_area: Option<u16>,
+    #[serde(default)]
+    speed_variants: Vec<SpeedVariant>,
+    #[serde(default)]
+    crop: Option<HashCrop>,
+    #[serde(default)]
+    duration_source: DurationSource,
+}
+
+//The error returned when deserializing a hash whose parts do not match its size.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[error("The {field} of a {hash_size} hash must have length {expected}, but has length {actual}")]
+pub(super) struct InvalidVideoHash {
+    field: &'static str,
+    hash_size: HashSize,
+    expected: usize,
+    actual: usize,
+}
+
+impl InvalidVideoHash {
+    pub(super) fn check(
+        field: &'static str,
+        hash_size: HashSize,
+        expected: usize,
+        actual: usize,
+    ) -> Result<(), Self> {
+        if actual == expected {
+            Ok(())
+        } else {
+            Err(Self {
+                field,
+                hash_size,
+                expected,
+                actual,
+            })
+        }
+    }
+}
+
+impl TryFrom<UncheckedVideoHash> for VideoHash {
+    type Error = InvalidVideoHash;
+
+    fn try_from(unchecked: UncheckedVideoHash) -> Result<Self, Self::Error> {
+        let hash_size = unchecked.hash_size;
+        let check =
+            |field, actual| InvalidVideoHash::check(field, hash_size, hash_size.qwords(), actual);
+        check("bits", unchecked.hash.len())?;
+        if let Some(reliable_bits) = &unchecked.reliable_bits {
+            check("reliable_bits", reliable_bits.len())?;
+        }
+        for variant in &unchecked.speed_variants {
+            check("speed_variants", variant.bits.len())?;
+        }
+
+        let UncheckedVideoHash {
+            hash,
+            hash_size,
+            src_path,
+            duration,
+            short_video,
+            coarse,
+            video_stream,
+            window_start,
+            frame_hashes,
+            leading_black,
+            collection,
+            reliable_bits,
+            truncated_decode,
+            duration_corrected,
+            motion_crop_fallback,
+            normalization,
+            audio_fingerprint,
+            coverage,
+            sampling_window,
+            pixel_aspect_ratio,
+            source_color_range,
+            deinterlaced,
+            environment,
+            active_area,
+            speed_variants,
+            crop,
+            duration_source,
+        } = unchecked;
+        Ok(Self {
+            hash,
+            hash_size,
+            src_path,
+            duration,
+            short_video,
+            coarse,
+            video_stream,
+            window_start,
+            frame_hashes,
+            leading_black,
+            collection,
+            reliable_bits,
+            truncated_decode,
+            duration_corrected,
+            motion_crop_fallback,
+            normalization,
+            audio_fingerprint,
+            coverage,
+            sampling_window,
+            pixel_aspect_ratio,
+            source_color_range,
+            deinterlaced,
+            environment,
+            active_area,
+            speed_variants,
+            crop,
+            duration_source,
+        })
+    }
 }
 
 impl Default for VideoHash {
     fn default() -> Self {
         Self {
-            hash: [0; HASH_QWORDS as usize],
+            hash: zeroed_hash_array(HashSize::default()),
+            hash_size: HashSize::default(),
             src_path: PathBuf::new(),
             duration: Default::default(),
+            short_video: false,
+            coarse: None,
+            video_stream: 0,
+            window_start: None,
+            frame_hashes: None,
+            leading_black: Duration::ZERO,
+            collection: None,
+            reliable_bits: None,
+            truncated_decode: false,
+            duration_corrected: false,
+            motion_crop_fallback: false,
+            normalization: Normalization::None,
+            audio_fingerprint: None,
+            coverage: Coverage::Start,
+            sampling_window: SamplingWindow::default(),
+            pixel_aspect_ratio: Some((1, 1)),
+            source_color_range: None,
+            deinterlaced: false,
+            environment: None,
+            active_area: None,
+            speed_variants: Vec::new(),
+            crop: None,
+            duration_source: DurationSource::Container,
         }
     }
 }
 
 impl VideoHash {
+    //See the ordering section of the docs for VideoHash. Tuples are only Ord up to 12 elements,
+    //hence the nesting.
+    #[allow(clippy::type_complexity)]
+    fn ord_key(
+        &self,
+    ) -> (
+        (HashSize, &HashArray, u32, &Path),
+        (
+            bool,
+            usize,
+            Option<Duration>,
+            Option<&[u64]>,
+            Duration,
+            Option<&HashArray>,
+            (bool, bool, bool),
+            Option<&str>,
+            (u8, u64, u64),
+            Option<&AudioFingerprint>,
+            (
+                Coverage,
+                SamplingWindow,
+                Option<(u32, u32)>,
+                Option<ColorRange>,
+                bool,
+                Option<EnvironmentDigest>,
+                Option<u16>,
+                &[SpeedVariant],
+                Option<HashCrop>,
+                DurationSource,
+            ),
+        ),
+    ) {
+        (
+            (self.hash_size, &self.hash, self.duration, &self.src_path),
+            (
+                self.short_video,
+                self.video_stream,
+                self.window_start,
+                self.frame_hashes.as_deref(),
+                self.leading_black,
+                self.reliable_bits.as_ref(),
+                (
+                    self.truncated_decode,
+                    self.duration_corrected,
+                    self.motion_crop_fallback,
+                ),
+                self.collection.as_deref(),
+                self.normalization.key(),
+                self.audio_fingerprint.as_ref(),
+                (
+                    self.coverage,
+                    self.sampling_window,
+                    self.pixel_aspect_ratio,
+                    self.source_color_range,
+                    self.deinterlaced,
+                    self.environment,
+                    self.active_area,
+                    &self.speed_variants,
+                    self.crop,
+                    self.duration_source,
+                ),
+            ),
+        )
+    }
+
     pub(crate) fn from_frames(
-        frames: impl Clone + IntoIterator<Item = GrayImage>,
+        frames: &[GrayImage],
         src_path: PathBuf,
         duration: u32,
+        short_video: bool,
+        hash_size: HashSize,
+        reliability_mask: bool,
     ) -> Result<Self, crate::Error> {
         let dct_size = NonZeroU32::try_from(DCT_SIZE).expect("will not be nonzero");
 
-        let mut it = frames.into_iter().peekable();
+        let mut it = frames.iter().peekable();
         let first_frame = it.peek().ok_or(NotEnoughFrames)?;
         let width = first_frame.width();
         let height = first_frame.height();
@@ -62,15 +483,17 @@ impl VideoHash {
 
         let dct = Dct3d::from_images(frames_64x64).ok_or(NotEnoughFrames)?;
 
-        // Pack the raw bits of the hash into a bit vector.
-        let mut bitarr: BitArray<[u64; HASH_QWORDS as usize], Lsb0> = BitArray::ZERO;
-        assert!(bitarr.len() >= HASH_BITS as usize);
-        for (mut bitarr_val, hash_bit) in bitarr.iter_mut().zip(dct.hash_bits()) {
-            *bitarr_val = hash_bit;
+        let mut hash = Self::from_components(
+            src_path,
+            pack_bits(dct.hash_bits(hash_size), hash_size),
+            hash_size,
+            duration,
+            short_video,
+        );
+        if reliability_mask {
+            hash.reliable_bits = Some(pack_bits(dct.reliable_bits(hash_size), hash_size));
         }
 
-        let hash = Self::from_components(src_path, bitarr, duration);
-
         Ok(hash)
     }
 
@@ -165,32 +588,545 @@ impl VideoHash {
 
     fn from_components(
         src_path: impl AsRef<Path>,
-        hash_bits: BitArray<[u64; HASH_QWORDS as usize], Lsb0>,
+        mut hash: HashArray,
+        hash_size: HashSize,
         duration: u32,
+        short_video: bool,
     ) -> Self {
+        debug_assert_eq!(hash.len(), hash_size.qwords());
+        BitSlice::<u64, Lsb0>::from_slice_mut(&mut hash)[hash_size.bits() as usize..].fill(false);
+        let coarse = Some(coarse_signature(&hash, hash_size));
         Self {
-            hash: hash_bits.into_inner(),
+            hash,
+            hash_size,
             src_path: src_path.as_ref().to_owned(),
             duration,
+            short_video,
+            coarse,
+            video_stream: 0,
+            window_start: None,
+            frame_hashes: None,
+            leading_black: Duration::ZERO,
+            collection: None,
+            reliable_bits: None,
+            truncated_decode: false,
+            duration_corrected: false,
+            motion_crop_fallback: false,
+            normalization: Normalization::None,
+            audio_fingerprint: None,
+            coverage: Coverage::Start,
+            sampling_window: SamplingWindow::default(),
+            pixel_aspect_ratio: Some((1, 1)),
+            source_color_range: None,
+            deinterlaced: false,
+            environment: None,
+            active_area: None,
+            speed_variants: Vec::new(),
+            crop: None,
+            duration_source: DurationSource::Container,
         }
     }
 
+    pub(crate) fn with_frame_hashes(mut self, frames: &[GrayImage]) -> Self {
+        self.frame_hashes = Some(frames.iter().map(frame_hash).collect());
+        self
+    }
+
+    pub(crate) fn with_video_stream(mut self, index: usize) -> Self {
+        self.video_stream = index;
+        self
+    }
+
+    pub(crate) fn with_leading_black(mut self, leading_black: Duration) -> Self {
+        self.leading_black = leading_black;
+        self
+    }
+
+    pub(crate) fn with_truncated_decode(mut self, truncated_decode: bool) -> Self {
+        self.truncated_decode = truncated_decode;
+        self
+    }
+
+    pub(crate) fn with_duration_corrected(mut self, duration_corrected: bool) -> Self {
+        self.duration_corrected = duration_corrected;
+        self
+    }
+
+    pub(crate) fn with_motion_crop_fallback(mut self, motion_crop_fallback: bool) -> Self {
+        self.motion_crop_fallback = motion_crop_fallback;
+        self
+    }
+
+    pub(crate) fn with_normalization(mut self, normalization: Normalization) -> Self {
+        self.normalization = normalization;
+        self
+    }
+
+    pub(crate) fn with_audio_fingerprint(mut self, fingerprint: Option<AudioFingerprint>) -> Self {
+        self.audio_fingerprint = fingerprint;
+        self
+    }
+
+    pub(crate) fn with_coverage(mut self, coverage: Coverage) -> Self {
+        self.coverage = coverage;
+        self
+    }
+
+    pub(crate) fn with_sampling_window(mut self, window: SamplingWindow) -> Self {
+        self.sampling_window = window;
+        self
+    }
+
+    pub(crate) fn with_pixel_aspect_ratio(
+        mut self,
+        pixel_aspect_ratio: Option<(u32, u32)>,
+    ) -> Self {
+        self.pixel_aspect_ratio = pixel_aspect_ratio;
+        self
+    }
+
+    pub(crate) fn with_source_color_range(mut self, range: Option<ColorRange>) -> Self {
+        self.source_color_range = range;
+        self
+    }
+
+    pub(crate) fn with_deinterlaced(mut self, deinterlaced: bool) -> Self {
+        self.deinterlaced = deinterlaced;
+        self
+    }
+
+    pub(crate) fn with_environment(mut self, environment: Option<EnvironmentDigest>) -> Self {
+        self.environment = environment;
+        self
+    }
+
+    pub(crate) fn with_active_area(mut self, active_area: Option<f64>) -> Self {
+        self.active_area = active_area
+            .map(|area| (area.clamp(0.0, 1.0) * f64::from(ACTIVE_AREA_SCALE)).round() as u16);
+        self
+    }
+
+    pub(crate) fn with_speed_variants(mut self, variants: Vec<SpeedVariant>) -> Self {
+        self.speed_variants = variants;
+        self
+    }
+
+    pub(crate) fn with_crop(mut self, crop: Crop) -> Self {
+        self.crop = Some(crop.into());
+        self
+    }
+
+    pub(crate) fn with_duration_source(mut self, source: DurationSource) -> Self {
+        self.duration_source = source;
+        self
+    }
+
+    pub(crate) fn with_window_start(mut self, start: Duration) -> Self {
+        self.window_start = Some(start);
+        self
+    }
+
     /// The path to the video file from which this hash was created.
     #[must_use]
     pub fn src_path(&self) -> &Path {
         &self.src_path
     }
 
-    /// The duration in seconds of the video.
+    /// A copy of this hash for the same video at another path, for instance after the video was
+    /// moved. Nothing else about the hash changes.
+    #[must_use]
+    pub fn with_src_path(&self, src_path: impl AsRef<Path>) -> Self {
+        let mut ret = self.clone();
+        ret.src_path = src_path.as_ref().to_path_buf();
+        ret
+    }
+
+    /// The duration in whole seconds of the video. See [`crate::fmt::format_duration`] for
+    /// displaying it.
     #[must_use]
     pub const fn duration(&self) -> u32 {
         self.duration
     }
 
-    /// The raw haming distance from this hash to another hash.
+    /// True if the video was shorter than [`CreationOptions::duration`][crate::CreationOptions::duration]
+    /// when the hash was created. The frames of short videos are sampled evenly across the entire video.
+    #[must_use]
+    pub const fn is_short_video(&self) -> bool {
+        self.short_video
+    }
+
+    /// The size of the hash, as chosen by [`CreationOptions::hash_bits`][crate::CreationOptions::hash_bits].
+    #[must_use]
+    pub const fn hash_size(&self) -> HashSize {
+        self.hash_size
+    }
+
+    /// The index of the video stream the hash was created from, counting only the video streams
+    /// of the file. See [`CreationOptions::video_stream`][crate::CreationOptions::video_stream].
+    #[must_use]
+    pub const fn video_stream(&self) -> usize {
+        self.video_stream
+    }
+
+    /// True if the hash holds a hash of each sampled frame, as requested by
+    /// [`CreationOptions::frame_hashes`][crate::CreationOptions::frame_hashes].
+    #[must_use]
+    pub fn has_frame_hashes(&self) -> bool {
+        self.frame_hashes.is_some()
+    }
+
+    /// True if the hash records which of its bits are reliable, as requested by
+    /// [`CreationOptions::reliability_mask`][crate::CreationOptions::reliability_mask].
+    #[must_use]
+    pub fn has_reliability_mask(&self) -> bool {
+        self.reliable_bits.is_some()
+    }
+
+    /// For hashes created by `hash_windows`, the time in the video at which the hashed window
+    /// starts. `None` for hashes of a whole video.
+    #[must_use]
+    pub const fn window_start(&self) -> Option<Duration> {
+        self.window_start
+    }
+
+    /// How the frames were adjusted before the hash was created (see
+    /// [`CreationOptions::normalization`][crate::CreationOptions::normalization]). Hashes with
+    /// different normalizations never match.
+    #[must_use]
+    pub const fn normalization(&self) -> Normalization {
+        self.normalization
+    }
+
+    /// Which parts of the video the frames of the hash were sampled from (see
+    /// [`CreationOptions::coverage`][crate::CreationOptions::coverage]). Hashes with different
+    /// coverages never match.
+    #[must_use]
+    pub const fn coverage(&self) -> Coverage {
+        self.coverage
+    }
+
+    /// The window of the video that the frames of the hash were sampled from (see
+    /// [`SamplingWindow`]). Hashes sampled from different windows never match.
+    #[must_use]
+    pub const fn sampling_window(&self) -> SamplingWindow {
+        self.sampling_window
+    }
+
+    /// The pixel aspect ratio (as width, height in lowest terms) that the frames were stretched
+    /// by before crops were detected in them, such as (16, 15) for a widescreen DVD. Videos with
+    /// square pixels, or whose backend reported no ratio, record (1, 1). `None` if the ratio was
+    /// not applied, either because
+    /// [`CreationOptions::ignore_pixel_aspect_ratio`][crate::CreationOptions::ignore_pixel_aspect_ratio]
+    /// was set or because the hash was created before ratios were applied. Hashes which were
+    /// stretched never match hashes which were not.
+    #[must_use]
+    pub const fn pixel_aspect_ratio(&self) -> Option<(u32, u32)> {
+        self.pixel_aspect_ratio
+    }
+
+    /// The range that the luma of the video was stored in, as reported by the backend that decoded
+    /// it, or `None` if it did not report one (or the hash was created before ranges were
+    /// recorded). Both backends decode frames to full range before hashing them, so hashes of the
+    /// same video stored in either range match, and this does not affect matching.
+    #[must_use]
+    pub const fn source_color_range(&self) -> Option<ColorRange> {
+        self.source_color_range
+    }
+
+    /// Whether the frames were deinterlaced before they were hashed, either because
+    /// [`CreationOptions::deinterlace`][crate::CreationOptions::deinterlace] was
+    /// [`Deinterlace::Force`][crate::Deinterlace::Force], or because it was
+    /// [`Deinterlace::Auto`][crate::Deinterlace::Auto] and the video was interlaced.
+    ///
+    /// Unlike the pixel aspect ratio, this does not stop hashes from matching. Deinterlacing
+    /// removes the combing of moving edges, which is noise on top of the same picture rather than
+    /// a different picture (as a different crop would be), so a deinterlaced hash is only ever
+    /// nearer to a hash of a progressive copy of the video than the hash of the raw interlaced
+    /// frames would be. A progressive video deinterlaced by [`Deinterlace::Force`] is barely
+    /// changed, so it still matches hashes of the same video which were not deinterlaced.
+    #[must_use]
+    pub const fn deinterlaced(&self) -> bool {
+        self.deinterlaced
+    }
+
+    /// The digest of the decoders that created the hash (see
+    /// [`EnvironmentInfo::digest`][crate::EnvironmentInfo::digest]), or `None` if it was not
+    /// decoded from a video by a backend, or was created before digests were recorded.
+    ///
+    /// This does not stop hashes from matching. Hashes of a video created by different versions of
+    /// the decoders may differ by a few bits, so a search still matches them, but a cache of hashes
+    /// can use this to tell that its hashes were made by other decoders than the current ones.
+    #[must_use]
+    pub const fn environment(&self) -> Option<EnvironmentDigest> {
+        self.environment
+    }
+
+    /// The fraction of the area of the frames of the hash that is not black, averaged over the
+    /// frames, in the range 0..=1, or `None` for hashes created before this was recorded. It is
+    /// measured after cropping, so it is near 1 for letterboxed videos hashed with
+    /// [`CreationOptions::cropdetect`][crate::CreationOptions::cropdetect], and lower for
+    /// letterboxed videos hashed without it.
+    ///
+    /// Searches only compare active areas if asked to, with
+    /// [`SearchOptions::active_area`][crate::SearchOptions::active_area].
+    #[must_use]
+    pub fn active_area(&self) -> Option<f64> {
+        self.active_area
+            .map(|area| f64::from(area) / f64::from(ACTIVE_AREA_SCALE))
+    }
+
+    /// The crop that was applied to the decoded frames before they were hashed (see
+    /// [`CreationOptions::cropdetect`][crate::CreationOptions::cropdetect]), or `None` for hashes
+    /// which were not decoded from a video, or were created before this was recorded. Frames can
+    /// be cropped exactly as the hash saw them by passing it to `thumbnails_with_crop`, which
+    /// saves detecting the crop again.
+    #[must_use]
+    pub fn crop(&self) -> Option<Crop> {
+        self.crop.map(Crop::from)
+    }
+
+    /// The speeds at which the hash also has the bits of the video as if it were played at that
+    /// speed (see [`CreationOptions::speed_variants`][crate::CreationOptions::speed_variants]).
+    /// Empty unless they were asked for, and also for short videos and for speeds at which the
+    /// sampled frames would run past the end of the video.
+    #[must_use]
+    pub fn speed_variants(&self) -> SpeedFactors {
+        self.speed_variants
+            .iter()
+            .map(|variant| variant.factor)
+            .collect()
+    }
+
+    /// The [`Preset`][crate::Preset] whose window the frames of the hash were sampled from, if
+    /// any. Hashes made with other options record `None`, unless their window is the same as
+    /// that of a preset.
+    #[must_use]
+    pub fn preset(&self) -> Option<crate::Preset> {
+        crate::Preset::from_sampling_window(self.sampling_window)
+    }
+
+    /// The length of the black lead-in that was skipped before the hash was created, if
+    /// [`CreationOptions::skip_leading_black`][crate::CreationOptions::skip_leading_black] was set.
+    /// Zero otherwise.
+    #[must_use]
+    pub const fn leading_black(&self) -> Duration {
+        self.leading_black
+    }
+
+    /// True if the video could not be decoded all the way through the part of it that is hashed,
+    /// usually because the file is truncated or corrupt. The hash is still built from the frames
+    /// that could be decoded, but it is less likely to match other copies of the same video.
+    #[must_use]
+    pub const fn truncated_decode(&self) -> bool {
+        self.truncated_decode
+    }
+
+    /// True if the container claimed that the video was much longer than the frames that could
+    /// actually be decoded, so [`VideoHash::duration`] is the time of the last decoded frame
+    /// rather than the duration from the container. See `VideoHashBuilder::with_duration_check`.
     #[must_use]
-    pub fn hamming_distance(&self, other: &Self) -> u32 {
-        hamming_distance(&self.hash, &other.hash)
+    pub const fn duration_corrected(&self) -> bool {
+        self.duration_corrected
+    }
+
+    /// Whether [`VideoHash::duration`] is the duration that the container of the video records,
+    /// or was measured by reading the video because the container records none (see
+    /// [`CreationOptions::max_measured_duration`][crate::CreationOptions::max_measured_duration]).
+    #[must_use]
+    pub const fn duration_source(&self) -> DurationSource {
+        self.duration_source
+    }
+
+    /// True if the hash was created with [`Cropdetect::Motion`][crate::Cropdetect::Motion], but
+    /// the moving area that was found was less certain than
+    /// [`CreationOptions::min_motion_crop_confidence`][crate::CreationOptions::min_motion_crop_confidence],
+    /// so the frames were cropped with [`Cropdetect::Letterbox`][crate::Cropdetect::Letterbox]
+    /// instead.
+    #[must_use]
+    pub const fn motion_crop_fallback(&self) -> bool {
+        self.motion_crop_fallback
+    }
+
+    /// The fingerprint of the audio that was decoded along with the frames, if the hash was
+    /// created with [`CreationOptions::audio_fingerprint`][crate::CreationOptions::audio_fingerprint]
+    /// and the video has audio.
+    #[must_use]
+    pub const fn audio_fingerprint(&self) -> Option<&AudioFingerprint> {
+        self.audio_fingerprint.as_ref()
+    }
+
+    /// Whether the audio of the two videos sounds alike, as a check on a visual match. Returns
+    /// None unless both hashes have an [`AudioFingerprint`].
+    #[must_use]
+    pub fn audio_agrees(&self, other: &Self) -> Option<bool> {
+        Some(self.audio_fingerprint()?.agrees(other.audio_fingerprint()?))
+    }
+
+    /// The collection that this hash belongs to, as set by [`VideoHash::with_collection`].
+    #[must_use]
+    pub fn collection(&self) -> Option<&str> {
+        self.collection.as_deref()
+    }
+
+    /// Label the hash as belonging to a collection, such as the name of the machine or cache it came
+    /// from, so that searches can find duplicates between collections (see
+    /// [`CollectionMode::CrossCollectionOnly`][crate::CollectionMode::CrossCollectionOnly]).
+    ///
+    /// The label is not serialized, so it is lost if the hash is saved and loaded again.
+    #[must_use]
+    pub fn with_collection(self, collection: impl Into<String>) -> Self {
+        Self {
+            collection: Some(collection.into()),
+            ..self
+        }
+    }
+
+    /// The raw haming distance from this hash to another hash.
+    ///
+    /// # Errors
+    /// Returns `Err` if the hashes have different sizes.
+    pub fn hamming_distance(&self, other: &Self) -> Result<u32, HashSizeMismatch> {
+        if self.hash_size == other.hash_size {
+            Ok(self.same_size_distance(other))
+        } else {
+            Err(HashSizeMismatch(self.hash_size, other.hash_size))
+        }
+    }
+
+    /// A heat map of where this hash and `other` disagree, to show why two videos did or did not
+    /// match. Each bin of the hash is drawn as a green square if the two hashes agree, or red if
+    /// they differ.
+    ///
+    /// A hash is a cube of DCT bins, and the image shows one square slice of the cube for each
+    /// temporal frequency, lowest first, from left to right. Within a slice the lowest spatial
+    /// frequencies are at the top left.
+    ///
+    /// # Errors
+    /// Returns `Err` if the hashes have different sizes.
+    pub fn difference_image(&self, other: &Self) -> Result<RgbImage, HashSizeMismatch> {
+        if self.hash_size != other.hash_size {
+            return Err(HashSizeMismatch(self.hash_size, other.hash_size));
+        }
+
+        let side = self.hash_size.side();
+        let slice_pixels = side * DIFF_CELL_PIXELS;
+        let width = side * slice_pixels + (side - 1) * DIFF_CELL_PIXELS;
+        let mut img = RgbImage::new(width, slice_pixels);
+
+        //the bins are stored in (time, x, y) order, as they are in the DCT.
+        let num_bits = self.hash_size.bits() as usize;
+        let bits = BitSlice::<u64, Lsb0>::from_slice(&self.hash)[..num_bits]
+            .iter()
+            .zip(&BitSlice::<u64, Lsb0>::from_slice(&other.hash)[..num_bits]);
+        for (idx, (b1, b2)) in (0..).zip(bits) {
+            let (t, x, y) = (idx / (side * side), (idx / side) % side, idx % side);
+            let colour = if *b1 == *b2 { DIFF_AGREE } else { DIFF_DIFFER };
+
+            let left = t * (slice_pixels + DIFF_CELL_PIXELS) + x * DIFF_CELL_PIXELS;
+            let top = y * DIFF_CELL_PIXELS;
+            for px in left..left + DIFF_CELL_PIXELS {
+                for py in top..top + DIFF_CELL_PIXELS {
+                    img.put_pixel(px, py, colour);
+                }
+            }
+        }
+
+        Ok(img)
+    }
+
+    /// As [`VideoHash::hamming_distance`], but without checking the sizes. Must only be called on
+    /// hashes of the same size.
+    pub(crate) fn same_size_distance(&self, other: &Self) -> u32 {
+        debug_assert_eq!(self.hash_size, other.hash_size);
+
+        //Dispatch to a fixed length so that each size gets an unrolled loop.
+        match self.hash_size {
+            HashSize::Five => {
+                hamming_distance::<{ HashSize::Five.qwords() }>(&self.hash, &other.hash)
+            }
+            HashSize::Six => {
+                hamming_distance::<{ HashSize::Six.qwords() }>(&self.hash, &other.hash)
+            }
+            HashSize::Eight => {
+                hamming_distance::<{ HashSize::Eight.qwords() }>(&self.hash, &other.hash)
+            }
+            HashSize::Ten => {
+                hamming_distance::<{ HashSize::Ten.qwords() }>(&self.hash, &other.hash)
+            }
+        }
+    }
+
+    /// The distance between the bits of this hash's variant at the speed of `factor` and the usual
+    /// bits of `other`, or None if it has no such variant. Must only be called on hashes of the
+    /// same size.
+    pub(crate) fn speed_variant_distance(&self, factor: SpeedFactor, other: &Self) -> Option<u32> {
+        debug_assert_eq!(self.hash_size, other.hash_size);
+        let variant = self.speed_variants.iter().find(|v| v.factor == factor)?;
+        let distance = variant
+            .bits
+            .iter()
+            .zip(&other.hash)
+            .map(|(x, y)| (x ^ y).count_ones())
+            .sum();
+        Some(distance)
+    }
+
+    /// The normalized distance between the frames of two hashes when the frames are lined up as
+    /// well as possible, shifting one video by up to `max_shift` frames in either direction.
+    /// Only shifts which leave at least half of the frames overlapping are tried.
+    ///
+    /// None unless both hashes have frame hashes.
+    pub(crate) fn aligned_frame_distance(&self, other: &Self, max_shift: u32) -> Option<f64> {
+        let (f1, f2) = (self.frame_hashes.as_ref()?, other.frame_hashes.as_ref()?);
+        let num_frames = f1.len().min(f2.len());
+        let max_shift = (max_shift as usize).min(num_frames / 2);
+
+        let distance_at_shift = |f1: &[u64], f2: &[u64], shift: usize| {
+            let overlap = num_frames - shift;
+            let raw: u32 = f1[shift..num_frames]
+                .iter()
+                .zip(&f2[..overlap])
+                .map(|(x, y)| (x ^ y).count_ones())
+                .sum();
+            f64::from(raw) / (overlap as f64 * f64::from(u64::BITS))
+        };
+
+        (0..=max_shift)
+            .flat_map(|shift| {
+                [
+                    distance_at_shift(f1, f2, shift),
+                    distance_at_shift(f2, f1, shift),
+                ]
+            })
+            .min_by(f64::total_cmp)
+    }
+
+    /// The number of differing bits that are reliable in at least one of the hashes, and the number
+    /// of bits that are reliable in at least one of the hashes. Bits that are unreliable in both are
+    /// usually the flat parts of a picture, so they say nothing about whether the videos match.
+    ///
+    /// None unless both hashes have reliability masks. Must only be called on hashes of the same size.
+    pub(crate) fn reliable_distance(&self, other: &Self) -> Option<(u32, u32)> {
+        debug_assert_eq!(self.hash_size, other.hash_size);
+        let (m1, m2) = (self.reliable_bits.as_ref()?, other.reliable_bits.as_ref()?);
+
+        let words = (self.hash.iter().zip(&other.hash)).zip(m1.iter().zip(m2));
+        let (mut differing, mut considered) = (0, 0);
+        for ((x, y), (r1, r2)) in words.take(self.hash_size.qwords()) {
+            let reliable = r1 | r2;
+            differing += ((x ^ y) & reliable).count_ones();
+            considered += reliable.count_ones();
+        }
+        Some((differing, considered))
+    }
+
+    /// A lower bound of [`VideoHash::hamming_distance`], which is much cheaper to compute.
+    /// Returns None if either hash has no coarse signature.
+    pub(crate) fn coarse_distance(&self, other: &Self) -> Option<u32> {
+        match (self.coarse, other.coarse) {
+            (Some(x), Some(y)) => Some((x ^ y).count_ones()),
+            _ => None,
+        }
     }
 }
 
@@ -198,11 +1134,13 @@ impl VideoHash {
 #[cfg(feature = "app_only_fns")]
 impl VideoHash {
     /// The distance from this hash to another hash, but normalized into the range 0..=1
-    #[must_use]
-    pub fn normalized_hamming_distance(&self, other: &Self) -> f64 {
-        let raw_distance = f64::from(self.hamming_distance(other));
+    ///
+    /// # Errors
+    /// Returns `Err` if the hashes have different sizes.
+    pub fn normalized_hamming_distance(&self, other: &Self) -> Result<f64, HashSizeMismatch> {
+        let raw_distance = f64::from(self.hamming_distance(other)?);
 
-        raw_distance / crate::definitions::TOLERANCE_SCALING_FACTOR
+        Ok(raw_distance / f64::from(self.hash_size.bits()))
     }
 
     /// An iterator over the raw bits of the hash, The bits are returned in an arbitrary order
@@ -213,20 +1151,46 @@ impl VideoHash {
         // slice with a non-multiple-of-the-raw-storage-size length
 
         let full_raw_slice = BitSlice::<u64, Lsb0>::from_slice(&self.hash);
-        let correct_size_slice = &full_raw_slice[..HASH_BITS as usize];
+        let correct_size_slice = &full_raw_slice[..self.hash_size.bits() as usize];
 
         correct_size_slice.iter().by_vals()
     }
 
     #[must_use]
-    pub const fn hash_frame_dimensions() -> (usize, usize) {
-        use crate::definitions::HASH_SIZE;
-        (HASH_SIZE as usize, HASH_SIZE as usize)
+    pub const fn hash_frame_dimensions(&self) -> (usize, usize) {
+        let side = self.hash_size.side() as usize;
+        (side, side)
     }
 
     #[must_use]
     pub fn hash_bits(&self) -> &BitSlice<u64, Lsb0> {
-        &BitSlice::from_slice(&self.hash)[..HASH_BITS as usize]
+        &BitSlice::from_slice(&self.hash)[..self.hash_size.bits() as usize]
+    }
+}
+
+impl PartialEq for VideoHash {
+    fn eq(&self, other: &Self) -> bool {
+        self.ord_key() == other.ord_key()
+    }
+}
+
+impl Eq for VideoHash {}
+
+impl PartialOrd for VideoHash {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for VideoHash {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.ord_key().cmp(&other.ord_key())
+    }
+}
+
+impl Hash for VideoHash {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.ord_key().hash(state);
     }
 }
 
@@ -243,10 +1207,13 @@ pub mod test_util {
 
     use std::path::Path;
 
-    use super::VideoHash;
-    use crate::video_hashing::video_hash::{HASH_BITS, HASH_QWORDS};
+    use vid_dup_finder_common::Crop;
+
+    use super::{zeroed_hash_array, SpeedFactor, SpeedVariant, VideoHash};
+    use crate::HashSize;
     use bitvec::prelude::*;
     use rand::prelude::*;
+    use smallvec::smallvec;
 
     #[doc(hidden)]
     impl VideoHash {
@@ -257,60 +1224,183 @@ pub mod test_util {
             ret
         }
 
+        //Mimic a hash which was serialized before coarse signatures existed.
+        #[must_use]
+        pub fn without_coarse_signature(&self) -> Self {
+            let mut ret = self.clone();
+            ret.coarse = None;
+            ret
+        }
+
+        #[must_use]
+        pub fn with_raw_frame_hashes(&self, frame_hashes: Vec<u64>) -> Self {
+            let mut ret = self.clone();
+            ret.frame_hashes = Some(frame_hashes);
+            ret
+        }
+
+        #[must_use]
+        pub fn with_short_video(&self, short_video: bool) -> Self {
+            let mut ret = self.clone();
+            ret.short_video = short_video;
+            ret
+        }
+
         #[must_use]
-        pub fn with_src_path(&self, src_path: impl AsRef<Path>) -> Self {
+        pub fn with_raw_crop(&self, crop: Crop) -> Self {
+            self.clone().with_crop(crop)
+        }
+
+        //a hash of the window of a longer video that starts at start.
+        #[must_use]
+        pub fn with_raw_window_start(&self, start: std::time::Duration) -> Self {
+            self.clone().with_window_start(start)
+        }
+
+        //Add a variant at the speed of factor, with the bits of another hash.
+        #[must_use]
+        pub fn with_speed_variant(&self, factor: SpeedFactor, bits_of: &Self) -> Self {
             let mut ret = self.clone();
-            ret.src_path = src_path.as_ref().to_path_buf();
+            ret.speed_variants
+                .retain(|variant| variant.factor != factor);
+            ret.speed_variants.push(SpeedVariant::new(factor, bits_of));
+            ret.speed_variants.sort();
             ret
         }
 
         pub fn full_hash(name: impl AsRef<Path>) -> Self {
-            Self::from_components(name, BitArray::new([u64::MAX; HASH_QWORDS as usize]), 0)
+            Self::from_components(
+                name,
+                smallvec![u64::MAX; HashSize::default().qwords()],
+                HashSize::default(),
+                0,
+                false,
+            )
         }
 
         pub fn empty_hash(name: impl AsRef<Path>) -> Self {
-            Self::from_components(name, BitArray::ZERO, 0)
+            Self::empty_hash_with_size(name, HashSize::default())
+        }
+
+        pub fn empty_hash_with_size(name: impl AsRef<Path>, hash_size: HashSize) -> Self {
+            Self::from_components(name, zeroed_hash_array(hash_size), hash_size, 0, false)
+        }
+
+        //invert the given bits of the hash (counting from 0 up to the number of bits in the hash)
+        #[must_use]
+        pub fn with_flipped_bits(&self, bits: std::ops::Range<u32>) -> Self {
+            assert!(bits.end <= self.hash_size.bits());
+            let mut ret = self.clone();
+            for bit in bits {
+                ret.hash[(bit / 64) as usize] ^= 1u64 << (bit % 64);
+            }
+            ret.update_coarse_signature();
+            ret
         }
 
         //generate a set of temporal hashes, each with a given distance from the empty hash.
         #[must_use]
         pub fn hash_with_spatial_distance(&self, target_distance: u32, rng: &mut StdRng) -> Self {
+            let num_bits = self.hash_size.bits();
             let mut flip_a_bit = |bits: &mut [u64]| {
-                let chosen_qword = rng.gen_range(0..bits.len());
-                let chosen_bit = rng.gen_range(0..64);
-                bits[chosen_qword] ^= 2u64.pow(chosen_bit);
+                let chosen_bit = rng.gen_range(0..num_bits);
+                bits[(chosen_bit / 64) as usize] ^= 1u64 << (chosen_bit % 64);
             };
 
             //flip bits until the required distance is reached
             let mut ret_hash = self.clone();
-            let mut curr_distance = self.hamming_distance(&ret_hash);
+            let mut curr_distance = self.same_size_distance(&ret_hash);
             while curr_distance < target_distance {
                 flip_a_bit(&mut ret_hash.hash);
-                curr_distance = self.hamming_distance(&ret_hash);
+                curr_distance = self.same_size_distance(&ret_hash);
             }
-            assert!(self.hamming_distance(&ret_hash) == target_distance);
+            assert!(self.same_size_distance(&ret_hash) == target_distance);
+            ret_hash.update_coarse_signature();
             ret_hash
         }
 
         pub fn random_hash(rng: &mut StdRng) -> Self {
-            use std::path::PathBuf;
+            Self::random_hash_with_size(rng, HashSize::default())
+        }
 
-            let mut hash: BitArray<[u64; HASH_QWORDS as usize], Lsb0> = BitArray::ZERO;
-            for mut bit in hash.iter_mut().take(HASH_BITS as usize) {
+        pub fn random_hash_with_size(rng: &mut StdRng, hash_size: HashSize) -> Self {
+            let mut hash = zeroed_hash_array(hash_size);
+            let bits = hash.view_bits_mut::<Lsb0>();
+            for mut bit in bits.iter_mut().take(hash_size.bits() as usize) {
                 *bit = rng.gen_bool(0.5);
             }
 
-            Self {
-                hash: hash.into_inner(),
-                src_path: PathBuf::from(""),
-                duration: 0,
+            Self::from_components("", hash, hash_size, 0, false)
+        }
+
+        //keep the coarse signature consistent after the hash bits have been modified.
+        fn update_coarse_signature(&mut self) {
+            if self.coarse.is_some() {
+                self.coarse = Some(super::coarse_signature(&self.hash, self.hash_size));
+            }
+        }
+    }
+}
+
+//Extract the coarse signature from the bits of a full hash.
+//
+//The hash bits are the hash_size cube of lowest frequency DCT bins, laid out in row-major order.
+//The coarse signature copies the bits of the COARSE_SIZE cube of lowest frequency bins out of them, so
+//every bit of the coarse signature is also a bit of the full hash. Therefore for any two hashes the
+//coarse distance counts a subset of the bits counted by the full distance, and can never exceed it.
+pub(super) fn coarse_signature(hash: &HashArray, hash_size: HashSize) -> u64 {
+    let bits = BitSlice::<u64, Lsb0>::from_slice(hash);
+    let (coarse, hash) = (COARSE_SIZE as usize, hash_size.side() as usize);
+
+    let mut ret = 0;
+    let mut coarse_idx = 0;
+    for x in 0..coarse {
+        for y in 0..coarse {
+            for z in 0..coarse {
+                if bits[(x * hash + y) * hash + z] {
+                    ret |= 1 << coarse_idx;
+                }
+                coarse_idx += 1;
             }
         }
     }
+    ret
+}
+
+//A 64 bit average hash of a single frame: Each bit is set if that pixel of the frame shrunk to 8x8 is
+//brighter than the average. Cheap, and good enough to tell whether two frames show the same thing.
+fn frame_hash(frame: &GrayImage) -> u64 {
+    let side = NonZeroU32::try_from(8).expect("literal");
+    let no_crop = Crop::from_edge_offsets(frame.dimensions(), 0, 0, 0, 0);
+    let Some(small) = crop_resize_flat(frame.as_flat_samples(), side, side, no_crop) else {
+        return 0;
+    };
+
+    let total = small.pixels().map(|p| u32::from(p.0[0])).sum::<u32>();
+    small
+        .pixels()
+        .enumerate()
+        .filter(|(_, p)| u32::from(p.0[0]) * 64 > total)
+        .fold(0, |acc, (idx, _)| acc | 1 << idx)
+}
+
+//Pack the bits of a hash (in the order they are to be stored) into the words of a hash of the given size
+fn pack_bits(hash_bits: impl Iterator<Item = bool>, hash_size: HashSize) -> HashArray {
+    let mut hash = zeroed_hash_array(hash_size);
+    for (mut bit, hash_bit) in hash.view_bits_mut::<Lsb0>().iter_mut().zip(hash_bits) {
+        *bit = hash_bit;
+    }
+    hash
 }
 
-//Utility helper: Get the hamming distance between two bitstrings.
-fn hamming_distance<const N: usize>(x: &[u64; N], y: &[u64; N]) -> u32 {
+//Utility helper: Get the hamming distance between the first N qwords of two bitstrings.
+fn hamming_distance<const N: usize>(x: &HashArray, y: &HashArray) -> u32 {
+    let (x, y) = (
+        x.first_chunk::<N>()
+            .expect("hashes of this size have N qwords"),
+        y.first_chunk::<N>()
+            .expect("hashes of this size have N qwords"),
+    );
     x.iter().zip(y.iter()).fold(0, |acc, (x, y)| {
         let difference = x ^ y;
         let set_bits = difference.count_ones();
@@ -322,7 +1412,11 @@ fn hamming_distance<const N: usize>(x: &[u64; N], y: &[u64; N]) -> u32 {
 mod test {
     use rand::prelude::*;
 
-    use super::VideoHash;
+    use bitvec::prelude::*;
+
+    use super::{HashSizeMismatch, VideoHash, DIFF_CELL_PIXELS, DIFF_DIFFER};
+    use crate::{definitions::TOLERANCE_SCALING_FACTOR, HashSize};
+    use image::RgbImage;
 
     #[test]
     fn test_triangle_inequality() {
@@ -332,19 +1426,50 @@ mod test {
             let thash2 = VideoHash::random_hash(&mut rng);
             let thash3 = VideoHash::random_hash(&mut rng);
 
-            let d12 = thash1.hamming_distance(&thash2);
-            let d13 = thash1.hamming_distance(&thash3);
-            let d23 = thash2.hamming_distance(&thash3);
+            let d12 = thash1.hamming_distance(&thash2).expect("same size");
+            let d13 = thash1.hamming_distance(&thash3).expect("same size");
+            let d23 = thash2.hamming_distance(&thash3).expect("same size");
 
             assert!(d12 <= d13 + d23);
         }
     }
+    #[test]
+    fn test_coarse_distance_is_lower_bound() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(3);
+        let base = VideoHash::random_hash(&mut rng);
+        for _i in 0..1_000 {
+            let random = VideoHash::random_hash(&mut rng);
+            let nearby = base.hash_with_spatial_distance(
+                rng.gen_range(0..TOLERANCE_SCALING_FACTOR as u32 / 2),
+                &mut rng,
+            );
+
+            for other in [random, nearby] {
+                let coarse = base
+                    .coarse_distance(&other)
+                    .expect("both have coarse signatures");
+                assert!(coarse <= base.hamming_distance(&other).expect("same size"));
+            }
+        }
+
+        assert_eq!(VideoHash::full_hash("").coarse, Some(u64::MAX));
+        assert_eq!(VideoHash::empty_hash("").coarse, Some(0));
+        assert_eq!(
+            VideoHash::empty_hash("")
+                .without_coarse_signature()
+                .coarse_distance(&base),
+            None
+        );
+    }
+
     #[test]
     fn test_distance_between_two_empty_hashes_is_0() {
         let empty_hash_1 = VideoHash::empty_hash("");
         let empty_hash_2 = VideoHash::empty_hash("");
 
-        let dist = empty_hash_1.hamming_distance(&empty_hash_2);
+        let dist = empty_hash_1
+            .hamming_distance(&empty_hash_2)
+            .expect("same size");
         //println!("{:#?}", dist);
         assert_eq!(0, dist);
     }
@@ -354,7 +1479,9 @@ mod test {
         let empty_hash_1 = VideoHash::full_hash("");
         let empty_hash_2 = VideoHash::full_hash("");
 
-        let dist = empty_hash_1.hamming_distance(&empty_hash_2);
+        let dist = empty_hash_1
+            .hamming_distance(&empty_hash_2)
+            .expect("same size");
         assert_eq!(0, dist);
     }
 
@@ -366,9 +1493,237 @@ mod test {
             let thash2 = VideoHash::random_hash(&mut rng);
 
             assert_eq!(
-                thash1.hamming_distance(&thash2),
-                thash2.hamming_distance(&thash1)
+                thash1.hamming_distance(&thash2).expect("same size"),
+                thash2.hamming_distance(&thash1).expect("same size")
             );
         }
     }
+
+    fn all_bits(hash: &VideoHash) -> &BitSlice<u64, Lsb0> {
+        BitSlice::from_slice(&hash.hash)
+    }
+
+    // Hashes built from the same frames, at every supported size.
+    fn hashes_of_every_size(seed: u64) -> Vec<VideoHash> {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(seed);
+        let frames = (0..crate::definitions::DCT_SIZE)
+            .map(|_| image::GrayImage::from_fn(64, 48, |_, _| image::Luma([rng.gen()])))
+            .collect::<Vec<_>>();
+
+        HashSize::ALL
+            .into_iter()
+            .map(|size| {
+                VideoHash::from_frames(&frames, "".into(), 10, false, size, false)
+                    .expect("enough frames")
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_every_hash_size() {
+        for (hash, other) in hashes_of_every_size(4).iter().zip(hashes_of_every_size(5)) {
+            let size = hash.hash_size();
+            let num_bits = size.bits() as usize;
+            let side = size.side();
+
+            assert_eq!(HashSize::from_side(side), Some(size));
+
+            //hashes only store the words they need, and only larger sizes than the default need the heap.
+            assert_eq!(hash.hash.len(), size.qwords(), "{size}");
+            assert_eq!(hash.hash.spilled(), size > HashSize::default(), "{size}");
+
+            //the bits beyond the end of the hash are never set, so never count towards distances.
+            assert!(all_bits(hash)[num_bits..].not_any(), "{size}");
+            let full = VideoHash::empty_hash_with_size("", size).with_flipped_bits(0..size.bits());
+            assert_eq!(
+                full.hamming_distance(&VideoHash::empty_hash_with_size("", size)),
+                Ok(size.bits())
+            );
+
+            //unrelated frames give a distance of about half the bits.
+            let distance = hash.hamming_distance(&other).expect("same size") as usize;
+            assert!(
+                (num_bits * 3 / 10..num_bits * 7 / 10).contains(&distance),
+                "{size}: {distance}"
+            );
+
+            let coarse = hash
+                .coarse_distance(&other)
+                .expect("both have coarse signatures");
+            assert!(
+                coarse <= hash.hamming_distance(&other).expect("same size"),
+                "{size}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_smaller_hashes_are_a_prefix_of_the_dct() {
+        // The bins of a smaller hash are a subset of the bins of a larger hash of the same frames.
+        let hashes = hashes_of_every_size(6);
+        let bit = |hash: &VideoHash, (x, y, z): (usize, usize, usize)| {
+            let side = hash.hash_size().side() as usize;
+            all_bits(hash)[(x * side + y) * side + z]
+        };
+
+        for (small, large) in hashes.iter().zip(hashes.iter().skip(1)) {
+            let side = small.hash_size().side() as usize;
+            for x in 0..side {
+                for y in 0..side {
+                    for z in 0..side {
+                        assert_eq!(bit(small, (x, y, z)), bit(large, (x, y, z)));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_different_sizes_cannot_be_compared() {
+        let five = VideoHash::empty_hash_with_size("", HashSize::Five);
+        let ten = VideoHash::empty_hash_with_size("", HashSize::Ten);
+
+        assert_eq!(
+            five.hamming_distance(&ten),
+            Err(HashSizeMismatch(HashSize::Five, HashSize::Ten))
+        );
+        assert_eq!(
+            ten.hamming_distance(&five),
+            Err(HashSizeMismatch(HashSize::Ten, HashSize::Five))
+        );
+        assert_eq!(five.hamming_distance(&five), Ok(0));
+    }
+
+    #[test]
+    fn test_hashes_whose_words_do_not_match_their_size_are_rejected() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(6);
+        let hash = VideoHash::random_hash_with_size(&mut rng, HashSize::Five);
+        let json = serde_json::to_value(&hash).expect("hashes serialize");
+        let parse = |json: &serde_json::Value| serde_json::from_value::<VideoHash>(json.clone());
+        assert_eq!(parse(&json).expect("the hash is valid"), hash);
+
+        let mut wrong_size = json.clone();
+        wrong_size["hash_size"] = "Ten".into();
+        let e = parse(&wrong_size)
+            .expect_err("the hash is invalid")
+            .to_string();
+        assert!(e.starts_with("The bits "), "{e}");
+
+        let mut wrong_reliable_bits = json.clone();
+        wrong_reliable_bits["reliable_bits"] = serde_json::json!([0, 0, 0, 0]);
+        let e = parse(&wrong_reliable_bits)
+            .expect_err("the hash is invalid")
+            .to_string();
+        assert!(e.starts_with("The reliable_bits "), "{e}");
+
+        let mut wrong_variant = json;
+        wrong_variant["speed_variants"] = serde_json::json!([{ "factor": "Double", "bits": [0] }]);
+        let e = parse(&wrong_variant)
+            .expect_err("the hash is invalid")
+            .to_string();
+        assert!(e.starts_with("The speed_variants "), "{e}");
+    }
+
+    #[test]
+    fn test_difference_image() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(5);
+        let hash = VideoHash::random_hash(&mut rng);
+        let side = hash.hash_size().side();
+
+        let red_cells = |img: &RgbImage| {
+            (0..img.width())
+                .step_by(DIFF_CELL_PIXELS as usize)
+                .flat_map(|x| {
+                    (0..img.height())
+                        .step_by(DIFF_CELL_PIXELS as usize)
+                        .map(move |y| (x, y))
+                })
+                .filter(|(x, y)| *img.get_pixel(*x, *y) == DIFF_DIFFER)
+                .collect::<Vec<_>>()
+        };
+
+        let same = hash.difference_image(&hash).expect("same size");
+        assert_eq!(same.height(), side * DIFF_CELL_PIXELS);
+        assert_eq!(same.width(), (side * side + side - 1) * DIFF_CELL_PIXELS);
+        assert!(red_cells(&same).is_empty());
+
+        //the first three bits are the first column of the lowest temporal frequency.
+        let flipped = hash.with_flipped_bits(0..3);
+        let diff = hash.difference_image(&flipped).expect("same size");
+        let cell = |y| (0, y * DIFF_CELL_PIXELS);
+        assert_eq!(red_cells(&diff), [cell(0), cell(1), cell(2)]);
+
+        let everything = VideoHash::empty_hash("")
+            .difference_image(&VideoHash::full_hash(""))
+            .expect("same size");
+        assert_eq!(red_cells(&everything).len() as u32, hash.hash_size().bits());
+
+        let five = VideoHash::empty_hash_with_size("", HashSize::Five);
+        assert!(hash.difference_image(&five).is_err());
+    }
+
+    fn std_hash(hash: &VideoHash) -> u64 {
+        use std::hash::{BuildHasher, BuildHasherDefault, DefaultHasher};
+        BuildHasherDefault::<DefaultHasher>::default().hash_one(hash)
+    }
+
+    #[test]
+    fn test_ordering_key() {
+        let hash = VideoHash::empty_hash("b.mp4").with_duration(10);
+
+        //bits come before duration, which comes before path.
+        let more_bits = VideoHash::full_hash("a.mp4").with_duration(1);
+        let longer = hash.with_duration(11).with_src_path("a.mp4");
+        let later_path = hash.with_src_path("c.mp4");
+        assert!(hash < more_bits);
+        assert!(hash < longer && longer < more_bits);
+        assert!(hash < later_path && later_path < longer);
+
+        //then everything else about how the hash was created.
+        let short = hash.with_short_video(true);
+        assert!(hash < short && short < later_path);
+        assert_ne!(std_hash(&hash), std_hash(&short));
+
+        //the coarse signature is only a copy of some of the bits.
+        assert_eq!(hash.without_coarse_signature(), hash);
+        assert_eq!(std_hash(&hash.without_coarse_signature()), std_hash(&hash));
+    }
+
+    #[test]
+    fn test_ordering_laws() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(7);
+
+        //few enough choices of each part that many hashes are equal, or tie on a part of the key.
+        let bits = [
+            VideoHash::empty_hash(""),
+            VideoHash::full_hash(""),
+            VideoHash::random_hash(&mut rng),
+            VideoHash::empty_hash_with_size("", HashSize::Five),
+        ];
+        let hashes = (0..60)
+            .map(|_| {
+                bits[rng.gen_range(0..bits.len())]
+                    .with_duration(rng.gen_range(0..3))
+                    .with_src_path(["a", "b"][rng.gen_range(0..2)])
+                    .with_short_video(rng.gen_bool(0.5))
+            })
+            .collect::<Vec<_>>();
+
+        for a in &hashes {
+            assert_eq!(a.cmp(a), std::cmp::Ordering::Equal);
+            for b in &hashes {
+                assert_eq!(a.cmp(b), b.cmp(a).reverse());
+                assert_eq!(a == b, a.cmp(b).is_eq());
+                assert_eq!(a.partial_cmp(b), Some(a.cmp(b)));
+                if a == b {
+                    assert_eq!(std_hash(a), std_hash(b));
+                }
+                for c in &hashes {
+                    if a <= b && b <= c {
+                        assert!(a <= c);
+                    }
+                }
+            }
+        }
+    }
 }
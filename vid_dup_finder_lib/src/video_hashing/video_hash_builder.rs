@@ -1,20 +1,63 @@
+use std::cell::Cell;
+use std::ffi::OsStr;
+use std::num::NonZeroU32;
 use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
 
-use ffmpeg_gst_wrapper::FrameReadCfgTrait;
-use image::GrayImage;
+use ffmpeg_gst_wrapper::{Deinterlace, DurationSource, FrameReadCfgTrait, MediaInfo, PipelinePool};
+use image::{GrayImage, RgbImage};
+use itertools::{Either, Itertools};
+use serde::{Deserialize, Serialize};
 use vid_dup_finder_common::video_frames_gray::{
-    cropdetect_letterbox, cropdetect_motion, cropdetect_none, VdfFrameExt,
+    cropdetect_letterbox, cropdetect_motion_detailed, cropdetect_none, VdfFrameExt,
 };
-use vid_dup_finder_common::Crop;
+use vid_dup_finder_common::{crop_resize_flat, Crop};
 
-use crate::definitions::{DCT_SIZE, DEFAULT_VID_HASH_DURATION};
-use crate::{Cropdetect, VideoHash, VideoHashResult, DEFAULT_VID_HASH_SKIP_FORWARD};
+use crate::definitions::{
+    HashSize, DCT_SIZE, DEFAULT_MIN_MOTION_CROP_CONFIDENCE, DEFAULT_VID_HASH_DURATION,
+    DEFAULT_VID_HASH_MAX_MEASURED_DURATION, DEFAULT_VID_HASH_MIN_DURATION, MAX_COVERAGE_SEGMENTS,
+};
+use crate::video_hashing::audio_fingerprint::{AudioFingerprint, AUDIO_SAMPLE_RATE};
+use crate::video_hashing::environment::EnvironmentInfo;
+use crate::video_hashing::speed_variants::SpeedVariant;
+use crate::{
+    CancellationToken, ColorRange, Coverage, Cropdetect, EnvironmentDigest, FramePreprocessor,
+    HashStats, Normalization, SamplingWindow, SpeedFactors, VideoHash, VideoHashResult,
+    DEFAULT_DURATION_CHECK_FACTOR, DEFAULT_VID_HASH_SKIP_FORWARD,
+};
 
 use crate::Error;
 
+/// The longest run of black frames that [`CreationOptions::skip_leading_black`] will skip.
+const MAX_LEADING_BLACK: f64 = 10.0;
+
+/// The framerate at which the start of a video is scanned for black frames.
+const LEADING_BLACK_SCAN_FPS: f64 = 4.0;
+
+/// Frames whose average brightness is no more than this are counted as black. This is a little
+/// above the black level of limited range video (16).
+const BLACK_FRAME_MAX_LUMA: f64 = 24.0;
+
+/// Frames used for crop detection are shrunk so that neither side is longer than this many pixels.
+const PROXY_SIZE: u32 = 256;
+
+/// The fewest frames that a hash is built from when the decoder fails part way through the video.
+/// The last decoded frame is repeated to make up the rest, so any fewer and the hash would mostly
+/// be of a single still picture.
+const MIN_TRUNCATED_FRAMES: usize = DCT_SIZE as usize / 2;
+
+/// Frames are not sampled from the last this many seconds of a video unless it is too short to
+/// avoid them, as cumulative rounding errors sometimes lose the frames that are nearest the end.
+const END_MARGIN: f64 = 2.0;
+
+/// Thumbnails of very short videos are at least this many seconds apart, so that the frame rate
+/// asked of the decoder stays reasonable.
+const MIN_THUMBNAIL_SPACING: f64 = 0.05;
+
 /// Options for how videos will be processed when generating hashes. Can be used
 /// to ensure that starting credits are skipped.
-#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Serialize, Deserialize)]
 pub struct CreationOptions {
     /// The amount of time to skip past when before extracting video frames. Used to skip past
     /// title credits and/or overlays at the beginning of videos.
@@ -33,7 +76,8 @@ pub struct CreationOptions {
     /// Lower values speed up the hashing process because less video data needs to be extracted.
     /// Higher values produce slightly more reliable hashes.
     ///
-    /// If any video is shorter than this duration, then hashes will be generated from the entire video.
+    /// If any video is shorter than this duration, then hashes will be generated from frames sampled
+    /// evenly across the entire video, and the hash will be flagged as short (see [`VideoHash::is_short_video`]).
     ///
     /// Unit: Seconds
     ///
@@ -50,6 +94,193 @@ pub struct CreationOptions {
     ///
     /// reccomended range: 2-60.
     pub cropdetect: Cropdetect,
+
+    /// The size of the hash to create. Hashes of different sizes never match each other, so all
+    /// hashes that are to be searched together must be created with the same size.
+    pub hash_bits: HashSize,
+
+    /// The index of the video stream to hash, counting only the video streams of each file. Most
+    /// videos have only one video stream, so this is rarely needed.
+    ///
+    /// If `None`, the stream with the largest resolution multiplied by duration is chosen, and
+    /// ties go to the earliest stream. Files with fewer video streams than the index fail to hash.
+    pub video_stream: Option<usize>,
+
+    /// Also keep a small hash of each sampled frame, which allows searches to line up videos that
+    /// start at slightly different times (see [`crate::SearchOptions::temporal_slack`]). This makes
+    /// each hash about 130 bytes larger, so it is off by default.
+    pub frame_hashes: bool,
+
+    /// Skip any black frames at the start of the video before applying
+    /// [`CreationOptions::skip_forward_amount`], so that copies which only differ by some black
+    /// padding at the start (as is common in broadcast captures) produce similar hashes. At most
+    /// 10 seconds are skipped, and the amount that was skipped is recorded in the hash (see
+    /// [`VideoHash::leading_black`]).
+    ///
+    /// Finding the black frames means decoding the start of each video twice, so this is off by default.
+    pub skip_leading_black: bool,
+
+    /// Also record which bits of each hash are reliable. The bits of a flat part of a video (such
+    /// as an opening title on a plain background) are decided by noise, which makes unrelated
+    /// videos with similar openings look alike. Searches can be told to ignore those bits with
+    /// [`crate::SearchOptions::ignore_unreliable_bits`].
+    ///
+    /// This doubles the size of each hash, so it is off by default.
+    pub reliability_mask: bool,
+
+    /// Videos shorter than this are not hashed, and fail with [`crate::Error::TooShort`] as soon
+    /// as their duration is known. Defaults to [`DEFAULT_VID_HASH_MIN_DURATION`].
+    ///
+    /// Unit: Seconds
+    pub min_duration: f64,
+
+    /// How the brightness of each sampled frame is adjusted (after cropping) before it is hashed,
+    /// which can help with dark or low contrast footage. The choice is recorded in the hash, and
+    /// hashes with different normalizations never match. Defaults to [`Normalization::None`].
+    pub normalization: Normalization,
+
+    /// Also keep a fingerprint of the audio that plays alongside the hashed frames, so that
+    /// searches can check whether visual matches also sound alike (see
+    /// [`crate::SearchOptions::audio_check`]). Videos without audio, or whose audio cannot be
+    /// decoded, have no fingerprint. Decoding the audio takes extra time, so this is off by default.
+    pub audio_fingerprint: bool,
+
+    /// Which parts of each video the frames are sampled from. Defaults to [`Coverage::Start`],
+    /// which samples a single run of frames after [`CreationOptions::skip_forward_amount`]. The
+    /// coverage is recorded in the hash, and hashes with different coverages never match.
+    ///
+    /// The hashes created by `hash_windows` are each of a single run of frames, whatever the
+    /// coverage.
+    pub coverage: Coverage,
+
+    /// The confidence (between 0.0 and 1.0) below which the region found by [`Cropdetect::Motion`]
+    /// is not trusted, and letterboxes are detected instead. Hashes for which that happened are
+    /// flagged (see [`VideoHash::motion_crop_fallback`]). 0.0 always trusts the region. Defaults to
+    /// [`DEFAULT_MIN_MOTION_CROP_CONFIDENCE`].
+    pub min_motion_crop_confidence: f64,
+
+    /// Crop frames at the resolution they are stored at, rather than first stretching them to the
+    /// shape that they are shown at. Off by default.
+    ///
+    /// Some videos have pixels which are not square: A widescreen DVD is stored at 720x576 and
+    /// shown at 1024x576. Normally crops are detected in frames stretched by the pixel aspect
+    /// ratio that the backend reports for the video, so a DVD and a square pixel re-encode of it
+    /// are cropped alike and produce similar hashes. The pixel aspect ratio that was applied is
+    /// recorded in the hash (see [`VideoHash::pixel_aspect_ratio`]). Hashes created with this
+    /// option set record none, as did hashes created before pixel aspect ratios were applied,
+    /// and they never match hashes which were stretched.
+    pub ignore_pixel_aspect_ratio: bool,
+
+    /// Whether to deinterlace frames before hashing them. Defaults to [`Deinterlace::Off`].
+    ///
+    /// Interlaced video (such as from broadcast TV or DVDs) stores each frame as two fields,
+    /// captured a moment apart. Decoded as it is, anything that moves is combed, which makes its
+    /// hash differ from the hash of a progressive copy of the same video. [`Deinterlace::Auto`]
+    /// deinterlaces videos that the backend detects are interlaced, and [`Deinterlace::Force`]
+    /// deinterlaces every video. Whether a hash was deinterlaced is recorded in it (see
+    /// [`VideoHash::deinterlaced`]), but hashes which were and were not deinterlaced still match.
+    #[serde(default)]
+    pub deinterlace: Deinterlace,
+
+    /// The speeds at which each video is also hashed, so that searches can match copies of it
+    /// which were sped up or slowed down (see [`crate::speed_variants`] and
+    /// [`crate::SearchOptions::speed_variants`]). Each speed means decoding another run of frames,
+    /// and makes the hash as many bytes larger as its bits take. Empty by default.
+    ///
+    /// Only hashes with [`Coverage::Start`] of videos which are not short have variants, and
+    /// speeds at which a copy would be too short to sample from the usual window are left out
+    /// (see [`VideoHash::speed_variants`]).
+    #[serde(default)]
+    pub speed_variants: SpeedFactors,
+
+    /// Videos whose containers do not record their duration (as some transport streams and
+    /// fragmented MP4s do not) have it measured by reading them, which is much slower. Reading
+    /// stops this far into each video, and longer videos fail with
+    /// [`crate::Error::UnknownDuration`]. Whether a duration was measured is recorded in the hash
+    /// (see [`VideoHash::duration_source`]). 0.0 never measures. Defaults to
+    /// [`DEFAULT_VID_HASH_MAX_MEASURED_DURATION`].
+    ///
+    /// Unit: Seconds
+    #[serde(default = "default_max_measured_duration")]
+    pub max_measured_duration: f64,
+}
+
+// Options serialized before CreationOptions::max_measured_duration existed read back with the
+// default.
+const fn default_max_measured_duration() -> f64 {
+    DEFAULT_VID_HASH_MAX_MEASURED_DURATION
+}
+
+/// Why a set of [`CreationOptions`] cannot be used to create hashes.
+#[derive(thiserror::Error, Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum CreationOptionsError {
+    /// [`CreationOptions::skip_forward_amount`] is negative or not finite.
+    #[error("The amount to skip forward must be at least 0 seconds. Got {0}")]
+    InvalidSkipForward(f64),
+
+    /// [`CreationOptions::duration`] is not a positive finite number, so there would be no frames
+    /// to hash.
+    #[error("The hash duration must be more than 0 seconds. Got {0}")]
+    InvalidDuration(f64),
+
+    /// [`CreationOptions::min_duration`] is negative or NaN.
+    #[error("The minimum video duration must be at least 0 seconds. Got {0}")]
+    InvalidMinDuration(f64),
+
+    /// The percentiles of a [`Normalization::ContrastStretch`] are not between 0 and 100, or the
+    /// low one is not below the high one.
+    #[error("Contrast stretch percentiles must satisfy 0 <= low < high <= 100. Got {low_pct} and {high_pct}")]
+    InvalidContrastStretch { low_pct: f64, high_pct: f64 },
+
+    /// [`CreationOptions::coverage`] samples from no parts of the video, or from more than
+    /// [`MAX_COVERAGE_SEGMENTS`].
+    #[error("The coverage must sample between 1 and {MAX_COVERAGE_SEGMENTS} parts of each video. Got {0}")]
+    InvalidCoverage(u32),
+
+    /// [`CreationOptions::min_motion_crop_confidence`] is not between 0.0 and 1.0.
+    #[error("The minimum motion crop confidence must be between 0 and 1. Got {0}")]
+    InvalidMotionCropConfidence(f64),
+
+    /// [`CreationOptions::max_measured_duration`] is negative or NaN.
+    #[error("The maximum measured duration must be at least 0 seconds. Got {0}")]
+    InvalidMaxMeasuredDuration(f64),
+}
+
+impl CreationOptions {
+    /// Check that hashes can be created with these options. Hashing with invalid options fails
+    /// with [`crate::Error::InvalidOptions`] before any video is decoded.
+    ///
+    /// # Errors
+    /// If any option is out of range, as described by [`CreationOptionsError`].
+    pub fn validate(&self) -> Result<(), CreationOptionsError> {
+        if !(self.skip_forward_amount.is_finite() && self.skip_forward_amount >= 0.0) {
+            return Err(CreationOptionsError::InvalidSkipForward(
+                self.skip_forward_amount,
+            ));
+        }
+        if !(self.duration.is_finite() && self.duration > 0.0) {
+            return Err(CreationOptionsError::InvalidDuration(self.duration));
+        }
+        //an infinite minimum duration is silly, but well defined.
+        if self.min_duration.is_nan() || self.min_duration < 0.0 {
+            return Err(CreationOptionsError::InvalidMinDuration(self.min_duration));
+        }
+        if self.max_measured_duration.is_nan() || self.max_measured_duration < 0.0 {
+            return Err(CreationOptionsError::InvalidMaxMeasuredDuration(
+                self.max_measured_duration,
+            ));
+        }
+        let segments = self.coverage.segments();
+        if !(1..=MAX_COVERAGE_SEGMENTS).contains(&segments) {
+            return Err(CreationOptionsError::InvalidCoverage(segments));
+        }
+        if !(0.0..=1.0).contains(&self.min_motion_crop_confidence) {
+            return Err(CreationOptionsError::InvalidMotionCropConfidence(
+                self.min_motion_crop_confidence,
+            ));
+        }
+        self.normalization.validate()
+    }
 }
 
 impl std::default::Default for CreationOptions {
@@ -58,8 +289,114 @@ impl std::default::Default for CreationOptions {
             skip_forward_amount: DEFAULT_VID_HASH_SKIP_FORWARD,
             duration: DEFAULT_VID_HASH_DURATION,
             cropdetect: Cropdetect::Letterbox,
+            hash_bits: HashSize::default(),
+            video_stream: None,
+            frame_hashes: false,
+            skip_leading_black: false,
+            reliability_mask: false,
+            min_duration: DEFAULT_VID_HASH_MIN_DURATION,
+            normalization: Normalization::None,
+            audio_fingerprint: false,
+            coverage: Coverage::Start,
+            min_motion_crop_confidence: DEFAULT_MIN_MOTION_CROP_CONFIDENCE,
+            ignore_pixel_aspect_ratio: false,
+            deinterlace: Deinterlace::Off,
+            speed_variants: SpeedFactors::NONE,
+            max_measured_duration: DEFAULT_VID_HASH_MAX_MEASURED_DURATION,
+        }
+    }
+}
+
+impl CreationOptions {
+    /// The part of each video that frames are sampled from, which is recorded in every hash.
+    #[must_use]
+    pub fn sampling_window(&self) -> SamplingWindow {
+        SamplingWindow::new(self.skip_forward_amount, self.duration)
+    }
+}
+
+// The windows of the fast and thorough presets, in seconds.
+const FAST_SKIP_FORWARD: f64 = 5.0;
+const FAST_DURATION: f64 = 5.0;
+const THOROUGH_DURATION: f64 = 30.0;
+
+/// Named sets of [`CreationOptions`], for a choice between hashing quickly and hashing more of
+/// each video. Use with `VideoHashBuilder::preset`, or start from [`Preset::options`] to change
+/// other options as well.
+///
+/// Every preset samples the same number of frames, so a longer window samples them further apart.
+/// The presets sample different windows (see [`SamplingWindow`]), and hashes sampled from
+/// different windows never match, so hashes made with different presets cannot be searched
+/// together.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+pub enum Preset {
+    /// Frames from 5 seconds of each video after skipping the first 5 seconds. Seeking a shorter
+    /// way into each video and decoding less of it makes hashing faster, but more videos will
+    /// match because they share an opening.
+    Fast,
+    /// [`CreationOptions::default`]: frames from [`DEFAULT_VID_HASH_DURATION`] seconds of each
+    /// video after skipping the first [`DEFAULT_VID_HASH_SKIP_FORWARD`] seconds.
+    #[default]
+    Default,
+    /// Frames from 30 seconds of each video after skipping the first
+    /// [`DEFAULT_VID_HASH_SKIP_FORWARD`] seconds, cropped to the part of each frame that moves
+    /// ([`Cropdetect::Motion`]). Slower, but copies which differ by overlays or by borders that
+    /// are not plain black are more likely to match.
+    Thorough,
+}
+
+impl Preset {
+    /// Every preset, from fastest to most thorough.
+    pub const ALL: [Self; 3] = [Self::Fast, Self::Default, Self::Thorough];
+
+    /// The options of the preset.
+    #[must_use]
+    pub fn options(self) -> CreationOptions {
+        let default = CreationOptions::default();
+        match self {
+            Self::Fast => CreationOptions {
+                skip_forward_amount: FAST_SKIP_FORWARD,
+                duration: FAST_DURATION,
+                ..default
+            },
+            Self::Default => default,
+            Self::Thorough => CreationOptions {
+                duration: THOROUGH_DURATION,
+                cropdetect: Cropdetect::Motion,
+                ..default
+            },
         }
     }
+
+    /// The preset which samples frames from the given window, if there is one.
+    #[must_use]
+    pub fn from_sampling_window(window: SamplingWindow) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|preset| preset.options().sampling_window() == window)
+    }
+}
+
+//The docs of builder methods whose behaviour does not depend on the backend, written once for
+//both backends' builders.
+#[cfg(any(feature = "ffmpeg_backend", feature = "gstreamer_backend"))]
+macro_rules! resized_frames_doc {
+    () => {
+        "If true, the frames yielded by [`VideoHashBuilder::frames`] are also resized to the \
+        16x16 pixels that hashes are calculated from. Off by default."
+    };
+}
+
+#[cfg(any(feature = "ffmpeg_backend", feature = "gstreamer_backend"))]
+macro_rules! hash_from_frames_doc {
+    () => {
+        "Create a hash from frames which have already been extracted from a video, such as those \
+        returned by [`VideoHashBuilder::frames`]. The first 16 frames are used, and they must all \
+        be the same size. `duration` is the length of the whole video.\n\n\
+        The hash is created as if the video had one video stream and no leading black frames."
+    };
 }
 
 /// A factory for video hashes, using the ffmpeg backend. (This is the preferred backend as it is more reliable than gstreamer)
@@ -67,28 +404,267 @@ impl std::default::Default for CreationOptions {
 /// Reccomend to always use the the default constructor [`ffmpeg::VideoHashBuilder::default`] unless supplying custom options
 #[cfg(feature = "ffmpeg_backend")]
 pub mod ffmpeg {
-    use std::path::PathBuf;
+    use std::path::{Path, PathBuf};
+    use std::time::Duration;
 
     use ffmpeg_gst_wrapper::ffmpeg_impl::FrameReaderCfgFfmpeg;
+    use image::GrayImage;
+    use itertools::Either;
 
-    use crate::{VideoHash, VideoHashResult};
+    use crate::{
+        FramePreprocessor, HashStats, VideoHash, VideoHashResult, DEFAULT_DURATION_CHECK_FACTOR,
+    };
 
-    use super::CreationOptions;
+    use super::{CreationOptions, Preset};
 
-    #[derive(Default)]
     pub struct VideoHashBuilder {
         options: CreationOptions,
+        resize_frames: bool,
+        preprocessor: Option<FramePreprocessor>,
+        duration_check: Option<f64>,
+        #[cfg(feature = "async")]
+        slots: crate::video_hashing::async_hashing::HashSlots,
+    }
+
+    impl Default for VideoHashBuilder {
+        fn default() -> Self {
+            Self::from_options(CreationOptions::default())
+        }
     }
 
     impl VideoHashBuilder {
         /// Create a video hash builder with the selected [`CreationOptions`]
         pub fn from_options(options: CreationOptions) -> Self {
-            Self { options }
+            Self {
+                options,
+                resize_frames: false,
+                preprocessor: None,
+                duration_check: Some(DEFAULT_DURATION_CHECK_FACTOR),
+                #[cfg(feature = "async")]
+                slots: crate::video_hashing::async_hashing::HashSlots::default(),
+            }
+        }
+
+        /// Create a video hash builder with the options of a [`Preset`].
+        pub fn preset(preset: Preset) -> Self {
+            Self::from_options(preset.options())
+        }
+
+        /// Pass each sampled frame through `preprocessor` after it has been cropped, and before it
+        /// is shrunk to be hashed. This replaces [`CreationOptions::normalization`], and hashes
+        /// made with it record [`Normalization::Custom`][crate::Normalization::Custom].
+        ///
+        /// The frames yielded by [`VideoHashBuilder::frames`] have not been preprocessed, so that
+        /// hashing them with [`VideoHashBuilder::hash_from_frames`] still gives the same hash.
+        #[must_use]
+        pub fn with_frame_preprocessor(mut self, preprocessor: FramePreprocessor) -> Self {
+            self.preprocessor = Some(preprocessor);
+            self
         }
 
-        /// Create a hash of the video on disk at the given path.
+        #[doc = resized_frames_doc!()]
+        #[must_use]
+        pub fn with_resized_frames(mut self, resize_frames: bool) -> Self {
+            self.resize_frames = resize_frames;
+            self
+        }
+
+        /// Some containers report a duration far longer than their video stream, so frames are
+        /// sampled from past its end and too few can be decoded to build a hash. When that
+        /// happens and the reported duration is more than `factor` times the time of the last
+        /// decoded frame, the frames are sampled again as if the video ended at that frame. The
+        /// hash then records that duration, and [`VideoHash::duration_corrected`] is set.
+        ///
+        /// `None` turns the check off. Defaults to [`DEFAULT_DURATION_CHECK_FACTOR`].
+        #[must_use]
+        pub fn with_duration_check(mut self, factor: Option<f64>) -> Self {
+            self.duration_check = factor;
+            self
+        }
+
+        /// The most videos that [`VideoHashBuilder::hash_async`] decodes at once, across all of
+        /// the futures it has returned. Each video being decoded has its own decoder, which can
+        /// use hundreds of megabytes for high resolution videos.
+        ///
+        /// By default, every builder shares one limit of the number of CPUs (but no more than 8).
+        /// Setting a limit gives this builder its own.
+        #[cfg(feature = "async")]
+        #[must_use]
+        pub fn with_max_concurrency(mut self, max_concurrency: std::num::NonZeroUsize) -> Self {
+            self.slots = crate::video_hashing::async_hashing::HashSlots::new(max_concurrency);
+            self
+        }
+
+        /// The frames that [`VideoHashBuilder::hash`] would create a hash of the video from. They
+        /// have already been skipped forward, sampled and cropped according to the
+        /// [`CreationOptions`], so hashing them with [`VideoHashBuilder::hash_from_frames`] gives
+        /// the same hash as [`VideoHashBuilder::hash`] (unless the decoder itself returns different
+        /// frames on different runs, which is rare).
+        ///
+        /// The crop can only be detected from all of the frames at once, so every frame is
+        /// decoded before the first is returned. Returns [`crate::Error::NotInitialized`] if
+        /// [`crate::init`] has not been called.
+        pub fn frames(
+            &self,
+            src_path: &Path,
+        ) -> VideoHashResult<impl Iterator<Item = VideoHashResult<GrayImage>>> {
+            crate::video_hashing::init::ensure_initialized()?;
+            super::gen_frames::<FrameReaderCfgFfmpeg>(
+                src_path,
+                self.options,
+                self.resize_frames,
+                self.duration_check,
+            )
+            .map(|frames| frames.into_iter().map(Ok))
+        }
+
+        #[doc = hash_from_frames_doc!()]
+        pub fn hash_from_frames(
+            &self,
+            src_path: PathBuf,
+            duration: Duration,
+            frames: impl IntoIterator<Item = GrayImage>,
+        ) -> VideoHashResult<VideoHash> {
+            super::hash_from_frame_iter(
+                src_path,
+                duration,
+                frames,
+                self.options,
+                self.preprocessor.as_ref(),
+            )
+        }
+
+        /// Create a hash from a directory of frames which have already been extracted from a
+        /// video (for example with a pinned ffmpeg command), one image per frame. No decoder is
+        /// used, so the hash only depends on the images.
+        ///
+        /// The files in `dir` whose names match `frame_glob` (where `*` matches any number of
+        /// characters and `?` matches exactly one) are sorted by name. The first 16 are converted
+        /// to grayscale and hashed like [`VideoHashBuilder::frames`], including crop detection if
+        /// [`CreationOptions::cropdetect`] asks for it. Skipping forward and sampling are up to
+        /// whatever extracted the frames. `total_duration` is the length of the whole video.
+        ///
+        /// Returns [`crate::Error::NotEnoughFrames`] if fewer than 16 files match, and
+        /// [`crate::Error::VidProc`] if the directory or an image cannot be read.
+        pub fn hash_image_dir(
+            &self,
+            dir: &Path,
+            frame_glob: &str,
+            total_duration: Duration,
+        ) -> VideoHashResult<VideoHash> {
+            super::hash_from_image_dir(
+                dir,
+                frame_glob,
+                total_duration,
+                self.options,
+                self.preprocessor.as_ref(),
+            )
+        }
+
+        /// Create a hash of the video on disk at the given path. Returns [`crate::Error::NotInitialized`]
+        /// if [`crate::init`] has not been called, or [`crate::Error::FfmpegMissing`] (without
+        /// trying to run anything) if it found that ffmpeg could not be run.
         pub fn hash(&self, src_path: PathBuf) -> VideoHashResult<VideoHash> {
-            super::gen_hash::<FrameReaderCfgFfmpeg>(src_path, self.options)
+            crate::video_hashing::init::ensure_initialized()?;
+            super::gen_hash::<FrameReaderCfgFfmpeg>(
+                src_path,
+                self.options,
+                self.preprocessor.as_ref(),
+                self.duration_check,
+            )
+        }
+
+        /// As [`VideoHashBuilder::hash`], for async code running on a tokio runtime. The video is
+        /// decoded on tokio's blocking thread pool once fewer than
+        /// [`VideoHashBuilder::with_max_concurrency`] videos are being decoded, so the future can
+        /// be awaited from any task without blocking it.
+        ///
+        /// Dropping the future before it finishes stops the decode as soon as the next frame
+        /// arrives. The video still counts against the limit until its decoder has stopped.
+        ///
+        /// The returned future does not borrow the builder, so it can be spawned as its own task.
+        /// It panics if it is polled outside of a tokio runtime.
+        #[cfg(feature = "async")]
+        pub fn hash_async(
+            &self,
+            src_path: PathBuf,
+        ) -> impl std::future::Future<Output = VideoHashResult<VideoHash>> + Send + 'static
+        {
+            crate::video_hashing::async_hashing::hash_async::<FrameReaderCfgFfmpeg>(
+                src_path,
+                self.options,
+                self.preprocessor.clone(),
+                self.duration_check,
+                &self.slots,
+            )
+        }
+
+        /// Create a hash of each of the videos at the given paths, as [`VideoHashBuilder::hash`]
+        /// would, paired with its path. The videos are hashed one at a time, as the returned
+        /// iterator is advanced.
+        ///
+        /// ffmpeg is run afresh for every video, so this is no faster than calling
+        /// [`VideoHashBuilder::hash`] for each path (the gstreamer builder's `hash_many` reuses its
+        /// pipelines). If [`crate::init`] has not been called, every path is paired with
+        /// [`crate::Error::NotInitialized`].
+        pub fn hash_many<'a>(
+            &'a self,
+            src_paths: impl IntoIterator<Item = PathBuf> + 'a,
+        ) -> impl Iterator<Item = (PathBuf, VideoHashResult<VideoHash>)> + 'a {
+            match crate::video_hashing::init::ensure_initialized() {
+                Err(e) => Either::Left(
+                    src_paths
+                        .into_iter()
+                        .map(move |path| (path, Err(e.clone()))),
+                ),
+                Ok(()) => Either::Right(super::gen_hash_many::<FrameReaderCfgFfmpeg>(
+                    src_paths,
+                    self.options,
+                    self.preprocessor.as_ref(),
+                    self.duration_check,
+                )),
+            }
+        }
+
+        /// Like [`VideoHashBuilder::hash`], but also returns how long hashing took and what was
+        /// decoded. The stats are returned even if the hash could not be created, in which case
+        /// only the fields that could still be measured are filled in.
+        pub fn hash_with_stats(
+            &self,
+            src_path: PathBuf,
+        ) -> (VideoHashResult<VideoHash>, HashStats) {
+            let start = std::time::Instant::now();
+            if let Err(e) = crate::video_hashing::init::ensure_initialized() {
+                let stats = HashStats {
+                    elapsed: start.elapsed(),
+                    backend: "ffmpeg".to_string(),
+                    ..HashStats::default()
+                };
+                return (Err(e), stats);
+            }
+            super::gen_hash_with_stats::<FrameReaderCfgFfmpeg>(
+                src_path,
+                self.options,
+                self.preprocessor.as_ref(),
+                self.duration_check,
+                "ffmpeg",
+            )
+        }
+
+        /// Create one hash for each window of [`CreationOptions::duration`] seconds in the video,
+        /// starting every `window_stride` seconds. See [`crate::search_for_clips`].
+        pub fn hash_windows(
+            &self,
+            src_path: PathBuf,
+            window_stride: f64,
+        ) -> VideoHashResult<Vec<VideoHash>> {
+            crate::video_hashing::init::ensure_initialized()?;
+            super::gen_window_hashes::<FrameReaderCfgFfmpeg>(
+                src_path,
+                self.options,
+                window_stride,
+                self.preprocessor.as_ref(),
+            )
         }
     }
 }
@@ -98,176 +674,3451 @@ pub mod ffmpeg {
 /// Reccomend to always use the the default constructor [`gstreamer::VideoHashBuilder::default`] unless supplying custom options
 #[cfg(feature = "gstreamer_backend")]
 pub mod gstreamer {
-    use std::path::PathBuf;
+    use std::path::{Path, PathBuf};
+    use std::time::Duration;
 
     use ffmpeg_gst_wrapper::gst_impl::FrameReaderCfgGst;
+    use image::GrayImage;
+    use itertools::Either;
 
-    use crate::{VideoHash, VideoHashResult};
+    use crate::{
+        FramePreprocessor, HashStats, VideoHash, VideoHashResult, DEFAULT_DURATION_CHECK_FACTOR,
+    };
 
-    use super::CreationOptions;
+    use super::{CreationOptions, Preset};
 
-    #[derive(Default)]
     pub struct VideoHashBuilder {
         options: CreationOptions,
+        resize_frames: bool,
+        preprocessor: Option<FramePreprocessor>,
+        duration_check: Option<f64>,
+        #[cfg(feature = "async")]
+        slots: crate::video_hashing::async_hashing::HashSlots,
+    }
+
+    impl Default for VideoHashBuilder {
+        fn default() -> Self {
+            Self::from_options(CreationOptions::default())
+        }
     }
 
     impl VideoHashBuilder {
         /// Create a video hash builder with the selected [`CreationOptions`]
         pub fn from_options(options: CreationOptions) -> Self {
-            Self { options }
+            Self {
+                options,
+                resize_frames: false,
+                preprocessor: None,
+                duration_check: Some(DEFAULT_DURATION_CHECK_FACTOR),
+                #[cfg(feature = "async")]
+                slots: crate::video_hashing::async_hashing::HashSlots::default(),
+            }
+        }
+
+        /// Create a video hash builder with the options of a [`Preset`].
+        pub fn preset(preset: Preset) -> Self {
+            Self::from_options(preset.options())
+        }
+
+        /// Pass each sampled frame through `preprocessor` after it has been cropped, and before it
+        /// is shrunk to be hashed. This replaces [`CreationOptions::normalization`], and hashes
+        /// made with it record [`Normalization::Custom`][crate::Normalization::Custom].
+        ///
+        /// The frames yielded by [`VideoHashBuilder::frames`] have not been preprocessed, so that
+        /// hashing them with [`VideoHashBuilder::hash_from_frames`] still gives the same hash.
+        #[must_use]
+        pub fn with_frame_preprocessor(mut self, preprocessor: FramePreprocessor) -> Self {
+            self.preprocessor = Some(preprocessor);
+            self
+        }
+
+        #[doc = resized_frames_doc!()]
+        #[must_use]
+        pub fn with_resized_frames(mut self, resize_frames: bool) -> Self {
+            self.resize_frames = resize_frames;
+            self
+        }
+
+        /// Some containers report a duration far longer than their video stream, so frames are
+        /// sampled from past its end and too few can be decoded to build a hash. When that
+        /// happens and the reported duration is more than `factor` times the time of the last
+        /// decoded frame, the frames are sampled again as if the video ended at that frame. The
+        /// hash then records that duration, and [`VideoHash::duration_corrected`] is set.
+        ///
+        /// `None` turns the check off. Defaults to [`DEFAULT_DURATION_CHECK_FACTOR`].
+        #[must_use]
+        pub fn with_duration_check(mut self, factor: Option<f64>) -> Self {
+            self.duration_check = factor;
+            self
+        }
+
+        /// The most videos that [`VideoHashBuilder::hash_async`] decodes at once, across all of
+        /// the futures it has returned. Each video being decoded has its own decoder, which can
+        /// use hundreds of megabytes for high resolution videos.
+        ///
+        /// By default, every builder shares one limit of the number of CPUs (but no more than 8).
+        /// Setting a limit gives this builder its own.
+        #[cfg(feature = "async")]
+        #[must_use]
+        pub fn with_max_concurrency(mut self, max_concurrency: std::num::NonZeroUsize) -> Self {
+            self.slots = crate::video_hashing::async_hashing::HashSlots::new(max_concurrency);
+            self
+        }
+
+        /// The frames that [`VideoHashBuilder::hash`] would create a hash of the video from. They
+        /// have already been skipped forward, sampled and cropped according to the
+        /// [`CreationOptions`], so hashing them with [`VideoHashBuilder::hash_from_frames`] gives
+        /// the same hash as [`VideoHashBuilder::hash`] (unless the decoder itself returns different
+        /// frames on different runs, which is rare).
+        ///
+        /// The crop can only be detected from all of the frames at once, so every frame is
+        /// decoded before the first is returned. Returns [`crate::Error::NotInitialized`] if
+        /// [`crate::init`] has not been called.
+        pub fn frames(
+            &self,
+            src_path: &Path,
+        ) -> VideoHashResult<impl Iterator<Item = VideoHashResult<GrayImage>>> {
+            crate::video_hashing::init::ensure_initialized()?;
+            super::gen_frames::<FrameReaderCfgGst>(
+                src_path,
+                self.options,
+                self.resize_frames,
+                self.duration_check,
+            )
+            .map(|frames| frames.into_iter().map(Ok))
+        }
+
+        #[doc = hash_from_frames_doc!()]
+        pub fn hash_from_frames(
+            &self,
+            src_path: PathBuf,
+            duration: Duration,
+            frames: impl IntoIterator<Item = GrayImage>,
+        ) -> VideoHashResult<VideoHash> {
+            super::hash_from_frame_iter(
+                src_path,
+                duration,
+                frames,
+                self.options,
+                self.preprocessor.as_ref(),
+            )
+        }
+
+        /// Create a hash from a directory of frames which have already been extracted from a
+        /// video (for example with a pinned ffmpeg command), one image per frame. No decoder is
+        /// used, so the hash only depends on the images.
+        ///
+        /// The files in `dir` whose names match `frame_glob` (where `*` matches any number of
+        /// characters and `?` matches exactly one) are sorted by name. The first 16 are converted
+        /// to grayscale and hashed like [`VideoHashBuilder::frames`], including crop detection if
+        /// [`CreationOptions::cropdetect`] asks for it. Skipping forward and sampling are up to
+        /// whatever extracted the frames. `total_duration` is the length of the whole video.
+        ///
+        /// Returns [`crate::Error::NotEnoughFrames`] if fewer than 16 files match, and
+        /// [`crate::Error::VidProc`] if the directory or an image cannot be read.
+        pub fn hash_image_dir(
+            &self,
+            dir: &Path,
+            frame_glob: &str,
+            total_duration: Duration,
+        ) -> VideoHashResult<VideoHash> {
+            super::hash_from_image_dir(
+                dir,
+                frame_glob,
+                total_duration,
+                self.options,
+                self.preprocessor.as_ref(),
+            )
         }
 
+        /// Create a hash of the video on disk at the given path. Returns [`crate::Error::NotInitialized`]
+        /// if [`crate::init`] has not been called.
         pub fn hash(&self, src_path: PathBuf) -> VideoHashResult<VideoHash> {
-            super::gen_hash::<FrameReaderCfgGst>(src_path, self.options)
+            crate::video_hashing::init::ensure_initialized()?;
+            super::gen_hash::<FrameReaderCfgGst>(
+                src_path,
+                self.options,
+                self.preprocessor.as_ref(),
+                self.duration_check,
+            )
+        }
+
+        /// As [`VideoHashBuilder::hash`], for async code running on a tokio runtime. The video is
+        /// decoded on tokio's blocking thread pool once fewer than
+        /// [`VideoHashBuilder::with_max_concurrency`] videos are being decoded, so the future can
+        /// be awaited from any task without blocking it.
+        ///
+        /// Dropping the future before it finishes stops the decode as soon as the next frame
+        /// arrives. The video still counts against the limit until its decoder has stopped.
+        ///
+        /// The returned future does not borrow the builder, so it can be spawned as its own task.
+        /// It panics if it is polled outside of a tokio runtime.
+        #[cfg(feature = "async")]
+        pub fn hash_async(
+            &self,
+            src_path: PathBuf,
+        ) -> impl std::future::Future<Output = VideoHashResult<VideoHash>> + Send + 'static
+        {
+            crate::video_hashing::async_hashing::hash_async::<FrameReaderCfgGst>(
+                src_path,
+                self.options,
+                self.preprocessor.clone(),
+                self.duration_check,
+                &self.slots,
+            )
+        }
+
+        /// Create a hash of each of the videos at the given paths, as [`VideoHashBuilder::hash`]
+        /// would, paired with its path. The videos are hashed one at a time, as the returned
+        /// iterator is advanced.
+        ///
+        /// Each video is decoded by a gstreamer pipeline left over from an earlier video where one
+        /// is free, instead of building a new pipeline for every video. Setting up a pipeline costs
+        /// about as much as decoding the frames of a short clip, so this is mostly faster for
+        /// collections of many short videos (run the `hash_many_bench` example to measure it on
+        /// your own videos). The hashes are identical to those from [`VideoHashBuilder::hash`]. A
+        /// pipeline which fails on one video is discarded rather than reused, so errors do not carry
+        /// over to the next video.
+        ///
+        /// If [`crate::init`] has not been called, every path is paired with
+        /// [`crate::Error::NotInitialized`].
+        pub fn hash_many<'a>(
+            &'a self,
+            src_paths: impl IntoIterator<Item = PathBuf> + 'a,
+        ) -> impl Iterator<Item = (PathBuf, VideoHashResult<VideoHash>)> + 'a {
+            match crate::video_hashing::init::ensure_initialized() {
+                Err(e) => Either::Left(
+                    src_paths
+                        .into_iter()
+                        .map(move |path| (path, Err(e.clone()))),
+                ),
+                Ok(()) => Either::Right(super::gen_hash_many::<FrameReaderCfgGst>(
+                    src_paths,
+                    self.options,
+                    self.preprocessor.as_ref(),
+                    self.duration_check,
+                )),
+            }
+        }
+
+        /// Like [`VideoHashBuilder::hash`], but also returns how long hashing took and what was
+        /// decoded. The stats are returned even if the hash could not be created, in which case
+        /// only the fields that could still be measured are filled in.
+        pub fn hash_with_stats(
+            &self,
+            src_path: PathBuf,
+        ) -> (VideoHashResult<VideoHash>, HashStats) {
+            let start = std::time::Instant::now();
+            if let Err(e) = crate::video_hashing::init::ensure_initialized() {
+                let stats = HashStats {
+                    elapsed: start.elapsed(),
+                    backend: "gstreamer".to_string(),
+                    ..HashStats::default()
+                };
+                return (Err(e), stats);
+            }
+            super::gen_hash_with_stats::<FrameReaderCfgGst>(
+                src_path,
+                self.options,
+                self.preprocessor.as_ref(),
+                self.duration_check,
+                "gstreamer",
+            )
+        }
+
+        /// Create one hash for each window of [`CreationOptions::duration`] seconds in the video,
+        /// starting every `window_stride` seconds. See [`crate::search_for_clips`].
+        pub fn hash_windows(
+            &self,
+            src_path: PathBuf,
+            window_stride: f64,
+        ) -> VideoHashResult<Vec<VideoHash>> {
+            crate::video_hashing::init::ensure_initialized()?;
+            super::gen_window_hashes::<FrameReaderCfgGst>(
+                src_path,
+                self.options,
+                window_stride,
+                self.preprocessor.as_ref(),
+            )
         }
     }
 }
 
+#[doc(hidden)]
 pub fn build_frame_reader<T: FrameReadCfgTrait>(
     src_path: impl AsRef<Path>,
     opts: CreationOptions,
 ) -> Result<T, Error>
+where
+    T::E: std::error::Error,
+{
+    build_frame_reader_with_leading_black(src_path, opts, None, None).map(|plan| plan.reader)
+}
+
+// How the frames that a hash is built from are sampled, as planned by
+// build_frame_reader_with_leading_black.
+struct FramePlan<T> {
+    reader: T,
+    // The length of the whole video in seconds.
+    duration: f64,
+    // Whether the duration came from the container, or was measured.
+    duration_source: DurationSource,
+    // The number of seconds of black frames that were skipped (always 0 unless
+    // CreationOptions::skip_leading_black is set).
+    leading_black: f64,
+    // The rate at which frames are sampled.
+    fps: f64,
+    // The runs of frames to sample, in order. The reader is already seeked to the first.
+    segments: Vec<Segment>,
+    // See applied_pixel_aspect_ratio.
+    pixel_aspect_ratio: Option<(u32, u32)>,
+    // The range the video is stored in, if the backend reported it (see
+    // VideoHash::source_color_range).
+    color_range: Option<ColorRange>,
+    // See applies_deinterlacing.
+    deinterlaced: bool,
+}
+
+// A run of frames sampled one after the other, at the rate of the FramePlan.
+#[derive(Debug, Clone, Copy)]
+struct Segment {
+    // The time of the first frame, in seconds from the start of the video.
+    start: f64,
+    // The number of frames in the run.
+    frames: usize,
+}
+
+// The segments to sample for the frames of a hash with the given coverage. The first starts at
+// first_start and the last ends END_MARGIN before the end of the video, or as near to that as
+// possible without starting before the first, and any others are evenly spaced between them. The
+// frames are shared out as evenly as possible, with any left over going to the earliest segments.
+fn plan_segments(coverage: Coverage, first_start: f64, duration: f64, fps: f64) -> Vec<Segment> {
+    let num_segments = coverage.segments() as usize;
+    let frames = |i: usize| {
+        DCT_SIZE as usize / num_segments + usize::from(i < DCT_SIZE as usize % num_segments)
+    };
+
+    let last_len = frames(num_segments - 1) as f64 / fps;
+    let last_start = (duration - last_len - END_MARGIN).max(first_start);
+    let spacing = match num_segments {
+        1 => 0.0,
+        n => (last_start - first_start) / (n - 1) as f64,
+    };
+
+    (0..num_segments)
+        .map(|i| Segment {
+            start: first_start + i as f64 * spacing,
+            frames: frames(i),
+        })
+        .collect()
+}
+
+// As build_frame_reader, but returns the whole plan. If duration is given, the video is treated
+// as if it lasted that many seconds, whatever its container says. If pool is given, the video is
+// decoded with pipelines from it.
+fn build_frame_reader_with_leading_black<T: FrameReadCfgTrait>(
+    src_path: impl AsRef<Path>,
+    opts: CreationOptions,
+    duration: Option<(f64, DurationSource)>,
+    pool: Option<&PipelinePool>,
+) -> Result<FramePlan<T>, Error>
 where
     T::E: std::error::Error,
 {
     let src_path = src_path.as_ref();
     let mut builder = T::from_path(src_path);
+    if let Some(pool) = pool {
+        builder.reuse_pipelines(pool);
+    }
+    if let Some(index) = opts.video_stream {
+        builder.video_stream_index(index);
+    }
+    builder.deinterlace(opts.deinterlace);
 
     // The video duration influcences the exact frames chosen to build the hash
-    let vid_duration = builder
-        .get_duration()
-        .map_err(|_e| Error::NotVideo)?
-        .as_secs_f64();
+    let (full_duration, duration_source) = match duration {
+        Some(duration) => duration,
+        None => video_duration(&builder, src_path, opts)?,
+    };
+    check_min_duration(full_duration, opts)?;
+    let media_info = builder.get_media_info().ok();
+    let pixel_aspect_ratio = applied_pixel_aspect_ratio(media_info.as_ref(), opts);
+    let deinterlaced = applies_deinterlacing(media_info.as_ref(), opts);
+    let color_range = media_info.and_then(|info| info.color_range);
 
-    //println!("duration: {vid_duration}");
+    // Everything below treats the video as if it starts after the black frames.
+    let leading_black = if opts.skip_leading_black {
+        find_leading_black::<T>(src_path, opts, pool)
+    } else {
+        0.0
+    };
+    let vid_duration = full_duration - leading_black;
 
-    let max_seek_amount = opts.skip_forward_amount;
-    let max_hash_duration = opts.duration;
+    //println!("duration: {vid_duration}");
 
     let fps;
     let seek_amount;
 
-    // If the video is really short then set the FPS really high and
-    // try get whatever frames are available. This might not succeed
-    // because a really short video might not have 64 total frames,
+    // If the video is shorter than the desired runtime for building a hash, then sample
+    // frames evenly across the whole video. Budget for one more frame than is needed so that
+    // the last frame lands comfortably before the end of the video (otherwise cumulative
+    // rounding errors sometimes lose the last frame).
     //
-    // But don't sweat over this corner because for degernerately short videos
-    // a duplicate-image utility might work just as well instead.
-    if vid_duration < 2.0 {
-        //println!("sub 2 sec");
-        fps = 64.0;
-        seek_amount = 0f64;
+    // Really short (or zero-length, if CreationOptions::min_duration allows them) videos are
+    // treated as if they last for a fraction of a second, which just asks the decoder for
+    // whatever frames are available.
+    if is_short_video(vid_duration, opts) {
+        const MIN_SAMPLED_DURATION: f64 = 0.1;
 
-    //Otherwise if the video is shorter than the desired runtime for building
-    //a hash, set the FPS to evenly sample frames across the length of the video
-    //
-    //(But to avoid the effect of cumulative rounding errors, try and make
-    //the last frame be 2 seconds before the end. Otherwise sometimes we only
-    //get 63 frames.)
-    } else if vid_duration < max_hash_duration {
-        //println!("sub {max_hash_duration} sec");
-        fps = 64.0 / (vid_duration - 2.0);
+        fps = f64::from(DCT_SIZE + 1) / vid_duration.max(MIN_SAMPLED_DURATION);
         seek_amount = 0f64;
+    } else {
+        fps = 64.0 / opts.duration;
+        seek_amount = sampling_start(vid_duration, opts);
+    }
+
+    //Spawn gstreamer pipeline to begin getting video frames.
+
+    //println!("calculated fps for capturing: {fps:?}, seek_amount: {seek_amount}");
+    builder.fps(fps_fraction(fps));
+    let seek_amount = seek_amount + leading_black;
+    if seek_amount > 0f64 {
+        builder.start_offset(seek_amount);
+    }
+
+    //Short videos are already sampled across their whole length.
+    let start = seek_amount.max(0.0);
+    let segments = if is_short_video(vid_duration, opts) {
+        plan_segments(Coverage::Start, start, full_duration, fps)
+    } else {
+        plan_segments(opts.coverage, start, full_duration, fps)
+    };
+
+    Ok(FramePlan {
+        reader: builder,
+        duration: full_duration,
+        duration_source,
+        leading_black,
+        fps,
+        segments,
+        pixel_aspect_ratio,
+        color_range,
+        deinterlaced,
+    })
+}
+
+// How far into a video that is not short (after any leading black) its frames are sampled from.
+fn sampling_start(vid_duration: f64, opts: CreationOptions) -> f64 {
+    let max_seek_amount = opts.skip_forward_amount;
+    let max_hash_duration = opts.duration;
 
     //If the video is long enough to sample max_hash_duration's worth of content
     //to build the hash, but not long enough that we can apply the full skip,
     //then skip forwards as far as possible.
-    } else if vid_duration < max_seek_amount + max_hash_duration {
-        //println!("sub {} sec", max_seek_amount + max_hash_duration);
-
-        fps = 64.0 / max_hash_duration;
-        seek_amount = vid_duration - max_hash_duration - 2.0;
+    if vid_duration < max_seek_amount + max_hash_duration {
+        vid_duration - max_hash_duration - END_MARGIN
 
     //Otherwise the video is long enough to do what we want.
     } else {
-        //println!("more than {} sec", max_seek_amount + max_hash_duration);
-        fps = 64.0 / max_hash_duration;
-        seek_amount = max_seek_amount;
+        max_seek_amount
     }
+}
 
-    //gstreamer expects framerates to be expressed as integer fractions, so
-    //scale the float framerate by a large number and convert to integer.
-    let fps = ((fps * 16384.0) as u64, 16384);
-
-    //Spawn gstreamer pipeline to begin getting video frames.
+// Whether the backend will deinterlace the frames of the video. With Deinterlace::Auto the
+// backends decide for themselves, so this trusts that they agree with the media info. Videos
+// whose backend does not say whether they are interlaced are assumed to be progressive.
+fn applies_deinterlacing(media_info: Option<&MediaInfo>, opts: CreationOptions) -> bool {
+    match opts.deinterlace {
+        Deinterlace::Off => false,
+        Deinterlace::Auto => media_info.and_then(|info| info.interlaced) == Some(true),
+        Deinterlace::Force => true,
+    }
+}
 
-    //println!("calculated fps for capturing: {fps:?}, seek_amount: {seek_amount}");
-    builder.fps(fps);
-    if seek_amount > 0f64 {
-        builder.start_offset(seek_amount);
+// The pixel aspect ratio that the frames of the video are stretched by before crops are detected,
+// in lowest terms, or None if CreationOptions::ignore_pixel_aspect_ratio is set. Videos whose
+// backend does not report a ratio (or whose media info could not be read) are assumed to have
+// square pixels. The media info is probed separately from the duration, as not every backend
+// reports the duration alongside it as reliably as get_duration does.
+fn applied_pixel_aspect_ratio(
+    media_info: Option<&MediaInfo>,
+    opts: CreationOptions,
+) -> Option<(u32, u32)> {
+    if opts.ignore_pixel_aspect_ratio {
+        return None;
     }
+    let reported = media_info
+        .and_then(|info| info.pixel_aspect_ratio)
+        .filter(|&(num, den)| num > 0 && den > 0);
+    let Some((num, den)) = reported else {
+        return Some((1, 1));
+    };
 
-    Ok(builder)
+    let gcd = {
+        let (mut a, mut b) = (num, den);
+        while b != 0 {
+            (a, b) = (b, a % b);
+        }
+        a
+    };
+    Some((num / gcd, den / gcd))
 }
 
-fn iterate_video_frames<T: FrameReadCfgTrait + Clone>(
-    cfg: &T,
-) -> Result<impl Iterator<Item = GrayImage>, String> {
-    let mut it = cfg.clone().spawn_gray().peekable();
-    match it.peek() {
-        Some(Err(e)) => Err(format!("{e:?}")),
-        None => Err("None".to_string()),
-        Some(Ok(_frame)) => Ok(it.filter_map(Result::ok).take(DCT_SIZE as usize)),
+// The length in seconds of the run of black frames at the start of the video. Videos that are
+// entirely black have nothing to skip.
+fn find_leading_black<T: FrameReadCfgTrait>(
+    src_path: &Path,
+    opts: CreationOptions,
+    pool: Option<&PipelinePool>,
+) -> f64 {
+    let mut scanner = T::from_path(src_path);
+    if let Some(pool) = pool {
+        scanner.reuse_pipelines(pool);
+    }
+    if let Some(index) = opts.video_stream {
+        scanner.video_stream_index(index);
+    }
+    scanner.fps(fps_fraction(LEADING_BLACK_SCAN_FPS));
+
+    let max_frames = (MAX_LEADING_BLACK * LEADING_BLACK_SCAN_FPS) as usize;
+    let mut num_black = 0;
+    for frame in scanner.spawn_gray().take(max_frames) {
+        let Ok(frame) = frame else {
+            break;
+        };
+        if !is_black_frame(&frame) {
+            return num_black as f64 / LEADING_BLACK_SCAN_FPS;
+        }
+        num_black += 1;
+    }
+
+    if num_black == max_frames {
+        MAX_LEADING_BLACK
+    } else {
+        0.0
     }
 }
 
-fn are_all_frames_same_size<'a, T>(frames: T) -> VideoHashResult<()>
-where
-    T: Iterator<Item = &'a GrayImage>,
-{
-    use itertools::Itertools;
-    for (f1, f2) in frames.tuple_windows::<(_, _)>() {
-        if f1.dimensions() != f2.dimensions() {
-            let msg = format!(
-                "frames not all same size: Expected {:?}, Actual {:?}",
-                f1.dimensions(),
-                f2.dimensions()
-            );
-            return Err(crate::Error::VidProc(msg));
+fn is_black_frame(frame: &GrayImage) -> bool {
+    let num_pixels = u64::from(frame.width()) * u64::from(frame.height());
+    let total = frame.pixels().map(|p| u64::from(p.0[0])).sum::<u64>();
+
+    num_pixels == 0 || total as f64 / num_pixels as f64 <= BLACK_FRAME_MAX_LUMA
+}
+
+// The fraction of the pixels of each frame that are brighter than a black frame would be, averaged
+// over the frames (see VideoHash::active_area).
+fn active_area(frames: &[GrayImage]) -> Option<f64> {
+    let fractions = frames
+        .iter()
+        .filter(|frame| frame.width() > 0 && frame.height() > 0)
+        .map(|frame| {
+            let num_pixels = u64::from(frame.width()) * u64::from(frame.height());
+            let active = frame
+                .pixels()
+                .filter(|p| f64::from(p.0[0]) > BLACK_FRAME_MAX_LUMA)
+                .count();
+            active as f64 / num_pixels as f64
+        })
+        .collect::<Vec<_>>();
+
+    (!fractions.is_empty()).then(|| fractions.iter().sum::<f64>() / fractions.len() as f64)
+}
+
+//gstreamer expects framerates to be expressed as integer fractions, so
+//scale the float framerate by a large number and convert to integer.
+fn fps_fraction(fps: f64) -> (u64, u64) {
+    ((fps * 16384.0) as u64, 16384)
+}
+
+// The duration of the video in seconds, and where it came from. If the container does not
+// record the duration, it is measured by reading no more than
+// CreationOptions::max_measured_duration of the video.
+fn video_duration<T: FrameReadCfgTrait>(
+    builder: &T,
+    src_path: &Path,
+    opts: CreationOptions,
+) -> Result<(f64, DurationSource), Error> {
+    let duration = if opts.max_measured_duration > 0.0 {
+        let cap = Duration::try_from_secs_f64(opts.max_measured_duration).unwrap_or(Duration::MAX);
+        builder.get_duration_with_source(cap)
+    } else {
+        builder
+            .get_container_duration()
+            .map(|duration| duration.map(|duration| (duration, DurationSource::Container)))
+    };
+
+    match duration {
+        Ok(Some((duration, source))) => {
+            if source == DurationSource::Measured {
+                log::debug!(
+                    "{}: the container records no duration. Measured {:.2}s",
+                    src_path.display(),
+                    duration.as_secs_f64()
+                );
+            }
+            Ok((duration.as_secs_f64(), source))
         }
+        Ok(None) => Err(Error::UnknownDuration),
+        Err(_e) => Err(Error::NotVideo),
+    }
+}
+
+fn check_min_duration(duration: f64, opts: CreationOptions) -> Result<(), Error> {
+    if duration < opts.min_duration {
+        Err(Error::TooShort {
+            duration: Duration::from_secs_f64(duration),
+        })
+    } else {
+        Ok(())
     }
+}
 
-    Ok(())
+// Videos shorter than the hash duration have their frames sampled across the whole video
+// instead of from a window near the start.
+fn is_short_video(vid_duration: f64, opts: CreationOptions) -> bool {
+    vid_duration < opts.duration
 }
 
-fn crop_video_frames<T>(frames: T, cropdetect_algo: Cropdetect) -> VideoHashResult<Vec<GrayImage>>
-where
-    T: Iterator<Item = GrayImage>,
-{
-    let frames = frames.collect::<Vec<_>>();
+// A frame at the full resolution of the video, as it comes out of the decoder. An 8K frame is
+// 33MB, so while hashing at most one of these is alive at a time on each thread (plus the two
+// either side of a sampling time, when sampling by timestamp).
+struct FullResFrame(GrayImage);
 
-    are_all_frames_same_size(frames.iter())?;
+impl FullResFrame {
+    fn new(frame: GrayImage) -> Self {
+        #[cfg(test)]
+        full_res_count::inc();
 
-    let crop = detect_crop(&frames, cropdetect_algo).ok_or(crate::Error::NotEnoughFrames)?;
+        Self(frame)
+    }
+}
 
-    let cropped_frames = frames
-        .into_iter()
-        .map(|f| f.cropped(crop).to_image())
-        .collect::<Vec<_>>();
+#[cfg(test)]
+impl Drop for FullResFrame {
+    fn drop(&mut self) {
+        full_res_count::dec();
+    }
+}
+
+// Counts the full resolution frames alive on the current thread, so that tests can check that
+// frames are shrunk as soon as they are decoded.
+#[cfg(test)]
+mod full_res_count {
+    use std::cell::Cell;
+
+    thread_local! {
+        static LIVE: Cell<usize> = const { Cell::new(0) };
+        static PEAK: Cell<usize> = const { Cell::new(0) };
+    }
+
+    pub fn inc() {
+        let live = LIVE.get() + 1;
+        LIVE.set(live);
+        PEAK.set(PEAK.get().max(live));
+    }
+
+    pub fn dec() {
+        LIVE.set(LIVE.get() - 1);
+    }
 
-    Ok(cropped_frames)
+    // The most frames that were alive at once since the last call.
+    pub fn take_peak() -> usize {
+        PEAK.replace(LIVE.get())
+    }
 }
 
-fn detect_crop(frames: &[GrayImage], detect_method: Cropdetect) -> Option<Crop> {
-    match detect_method {
-        Cropdetect::None => cropdetect_none(frames),
-        Cropdetect::Letterbox => cropdetect_letterbox(frames),
-        Cropdetect::Motion => cropdetect_motion(frames),
+// Backends which can report frame timestamps have the frame closest to each sampling time picked
+// out of all the decoded frames. Otherwise the backend converts the framerate, which for variable
+// framerate videos can duplicate or drop different frames each time the video is decoded.
+//
+// The frames of a single segment, which cfg must already be seeked to. They stop at the first decode
+// error, after which progress is marked as failed.
+fn iterate_video_frames<T: FrameReadCfgTrait + Clone>(
+    cfg: &T,
+    fps: f64,
+    segment: Segment,
+    progress: &Rc<DecodeProgress>,
+) -> VideoHashResult<impl Iterator<Item = FullResFrame>> {
+    fn check_first<I: Iterator<Item = Result<F, E>>, F, E: std::fmt::Debug>(
+        it: I,
+        progress: &Rc<DecodeProgress>,
+    ) -> VideoHashResult<impl Iterator<Item = F>> {
+        let mut it = it.peekable();
+        match it.peek() {
+            Some(Err(e)) => Err(crate::Error::VidProc(format!("{e:?}"))),
+            None => Err(crate::Error::NotEnoughFrames),
+            Some(Ok(_frame)) => {
+                let progress = progress.clone();
+                Ok(it.map_while(move |frame| {
+                    progress.failed.set(frame.is_err());
+                    frame.ok()
+                }))
+            }
+        }
     }
+
+    let start = segment.start;
+    let frames = match cfg.clone().spawn_gray_timestamped() {
+        Some(it) => {
+            let frames = check_first(it, progress)?.map({
+                let progress = progress.clone();
+                move |(pts, frame)| {
+                    progress.position.set(Some(start + pts.as_secs_f64()));
+                    (pts.as_secs_f64(), frame)
+                }
+            });
+            Either::Left(nearest_frames(frames, fps))
+        }
+        // Without timestamps, the backend's framerate conversion puts the nth frame at n/fps.
+        None => {
+            let frames = check_first(cfg.clone().spawn_gray(), progress)?;
+            let progress = progress.clone();
+            Either::Right(frames.enumerate().map(move |(i, frame)| {
+                progress.position.set(Some(start + i as f64 / fps));
+                frame
+            }))
+        }
+    };
+
+    Ok(frames.take(segment.frames).map(FullResFrame::new))
 }
 
-pub fn gen_hash<T: FrameReadCfgTrait + Clone>(
-    src_path: PathBuf,
-    opts: CreationOptions,
-) -> Result<VideoHash, crate::Error> {
-    use crate::Error::VidProc;
-    let frame_read_cfg = build_frame_reader::<T>(src_path.clone(), opts)?;
-    let frames = iterate_video_frames(&frame_read_cfg).map_err(VidProc)?;
-    let frames = crop_video_frames(frames, opts.cropdetect)?;
+// The frames of each segment in turn. The first is decoded by reader, which must already be seeked
+// to it, and each of the others by a copy of reader seeked to its start. An error before the first
+// frame of the first segment is returned, but for later segments it only stops the frames.
+//
+// No segment is decoded until the frames before it have been used, and the segments stop at the
+// first one that has no frames or fails, as any later ones would be even further into the video.
+fn iterate_segment_frames<'a, T: FrameReadCfgTrait + Clone + 'a>(
+    reader: &T,
+    fps: f64,
+    segments: &'a [Segment],
+) -> VideoHashResult<(impl Iterator<Item = FullResFrame> + 'a, Rc<DecodeProgress>)> {
+    let progress = Rc::new(DecodeProgress::default());
+    let (&first, rest) = segments.split_first().ok_or(Error::NotEnoughFrames)?;
+    let first = iterate_video_frames(reader, fps, first, &progress)?;
 
-    let duration = frame_read_cfg
-        .get_duration()
-        .map_err(|e| VidProc(format!("{e:?}")))?;
+    let rest = rest.iter().copied().map_while({
+        let reader = reader.clone();
+        let progress = progress.clone();
+        move |segment| {
+            if progress.failed.get() {
+                return None;
+            }
+            let mut cfg = reader.clone();
+            cfg.start_offset(segment.start);
+            match iterate_video_frames(&cfg, fps, segment, &progress) {
+                Ok(frames) => Some(frames),
+                Err(Error::NotEnoughFrames) => None,
+                Err(_) => {
+                    progress.failed.set(true);
+                    None
+                }
+            }
+        }
+    });
+
+    Ok((first.chain(rest.flatten()), progress))
+}
+
+// How far decoding got, updated as the frames are read.
+#[derive(Default)]
+struct DecodeProgress {
+    // Set when the decoder returned an error, which stopped the frames.
+    failed: Cell<bool>,
+    // The time of the last decoded frame, in seconds from the start of the video.
+    position: Cell<Option<f64>>,
+}
+
+// For each time at which a frame is sampled (every 1/fps seconds, from 0), the frame whose
+// timestamp in seconds is closest to it, or the earlier frame on a tie. Frames must be in
+// presentation order, and a frame is repeated if it is the closest to more than one sampling time.
+//
+// The last frame is only used for times up to 1/fps after it, so that a video which could not be
+// decoded to the end doesn't get hashed from a single repeated frame.
+fn nearest_frames<F: Clone>(
+    frames: impl Iterator<Item = (f64, F)>,
+    fps: f64,
+) -> impl Iterator<Item = F> {
+    let mut frames = frames.peekable();
+    let mut prev: Option<(f64, F)> = None;
+
+    let times = (0..DCT_SIZE).map(move |i| f64::from(i) / fps);
+    times.map_while(move |time| {
+        while let Some(frame) = frames.next_if(|&(pts, _)| pts <= time) {
+            prev = Some(frame);
+        }
+
+        match (&prev, frames.peek()) {
+            (Some((prev_pts, prev)), Some((next_pts, next))) => {
+                let frame = if next_pts - time < time - prev_pts {
+                    next
+                } else {
+                    prev
+                };
+                Some(frame.clone())
+            }
+            (None, Some((_, next))) => Some(next.clone()),
+            (Some((prev_pts, prev)), None) => (time - prev_pts <= 1.0 / fps).then(|| prev.clone()),
+            (None, None) => None,
+        }
+    })
+}
 
-    VideoHash::from_frames(frames, src_path, duration.as_secs() as u32)
+// Shrink each frame as soon as it is decoded, so that only the small copies are kept. If there is
+// no crop to detect the frames go straight to the size of the DCT, otherwise crop detection is done
+// on proxies no larger than PROXY_SIZE, in the shape that the frames are shown at if given a pixel
+// aspect ratio. (Without a crop the pixel aspect ratio makes no difference, as the frames are
+// squashed into a square either way.)
+//
+// If the decoder failed after at least MIN_TRUNCATED_FRAMES frames, the last frame is repeated to
+// make up the rest and true is returned alongside the frames.
+fn shrink_video_frames<T>(
+    frames: T,
+    cropdetect_algo: Cropdetect,
+    pixel_aspect_ratio: Option<(u32, u32)>,
+    decode_failed: &Cell<bool>,
+) -> VideoHashResult<(Vec<GrayImage>, bool)>
+where
+    T: Iterator<Item = FullResFrame>,
+{
+    let mut dimensions = None;
+    let mut ret = Vec::with_capacity(DCT_SIZE as usize);
+
+    for frame in frames {
+        let frame = &frame.0;
+        let expected = *dimensions.get_or_insert(frame.dimensions());
+        if frame.dimensions() != expected {
+            let msg = format!(
+                "frames not all same size: Expected {expected:?}, Actual {:?}",
+                frame.dimensions()
+            );
+            return Err(crate::Error::VidProc(msg));
+        }
+
+        let (width, height) = match cropdetect_algo {
+            Cropdetect::None => (DCT_SIZE, DCT_SIZE),
+            Cropdetect::Letterbox | Cropdetect::Motion => {
+                proxy_dimensions(display_dimensions(expected, pixel_aspect_ratio))
+            }
+        };
+        ret.push(shrink_frame(frame, width, height).ok_or(crate::Error::NotEnoughFrames)?);
+    }
+
+    // Only now is it known how many frames could actually be decoded.
+    let truncated = ret.len() < DCT_SIZE as usize;
+    if truncated && !(decode_failed.get() && ret.len() >= MIN_TRUNCATED_FRAMES) {
+        return Err(crate::Error::NotEnoughFrames);
+    }
+
+    let last = ret.last().cloned().ok_or(crate::Error::NotEnoughFrames)?;
+    ret.resize(DCT_SIZE as usize, last);
+
+    Ok((ret, truncated))
+}
+
+// Frames are stretched rather than squashed, so that no detail is lost before they are shrunk.
+pub(crate) fn display_dimensions(
+    (width, height): (u32, u32),
+    pixel_aspect_ratio: Option<(u32, u32)>,
+) -> (u32, u32) {
+    let (num, den) = pixel_aspect_ratio.unwrap_or((1, 1));
+    let stretch = |side: u32, by: u32, over: u32| {
+        u32::try_from(u64::from(side) * u64::from(by) / u64::from(over))
+            .unwrap_or(u32::MAX)
+            .max(1)
+    };
+    match num.cmp(&den) {
+        std::cmp::Ordering::Equal => (width, height),
+        std::cmp::Ordering::Greater => (stretch(width, num, den), height),
+        std::cmp::Ordering::Less => (width, stretch(height, den, num)),
+    }
+}
+
+// Frames which are already small enough are left alone.
+fn proxy_dimensions((width, height): (u32, u32)) -> (u32, u32) {
+    let longest = width.max(height);
+    if longest <= PROXY_SIZE {
+        return (width, height);
+    }
+
+    let scale = |side: u32| ((u64::from(side) * u64::from(PROXY_SIZE)) / u64::from(longest)) as u32;
+    (scale(width).max(1), scale(height).max(1))
+}
+
+pub(crate) fn shrink_frame(frame: &GrayImage, width: u32, height: u32) -> Option<GrayImage> {
+    if frame.dimensions() == (width, height) {
+        return Some(frame.clone());
+    }
+
+    let no_crop = Crop::from_edge_offsets(frame.dimensions(), 0, 0, 0, 0);
+    crop_resize_flat(
+        frame.as_flat_samples(),
+        NonZeroU32::new(width)?,
+        NonZeroU32::new(height)?,
+        no_crop,
+    )
+}
+
+// The frames that a hash is built from, after shrinking and cropping.
+struct HashFrames {
+    frames: Vec<GrayImage>,
+    // Relative to the shrunk frames.
+    crop: Crop,
+    // See VideoHash::truncated_decode.
+    truncated: bool,
+    // See VideoHash::motion_crop_fallback.
+    crop_fallback: bool,
+}
+
+fn crop_video_frames<T>(
+    frames: T,
+    opts: CreationOptions,
+    pixel_aspect_ratio: Option<(u32, u32)>,
+    decode_failed: &Cell<bool>,
+) -> VideoHashResult<HashFrames>
+where
+    T: Iterator<Item = FullResFrame>,
+{
+    let (frames, truncated) =
+        shrink_video_frames(frames, opts.cropdetect, pixel_aspect_ratio, decode_failed)?;
+
+    let (crop, crop_fallback) = detect_crop(&frames, opts).ok_or(crate::Error::NotEnoughFrames)?;
+
+    let frames = frames
+        .into_iter()
+        .map(|f| f.cropped(crop).to_image())
+        .collect::<Vec<_>>();
+
+    Ok(HashFrames {
+        frames,
+        crop,
+        truncated,
+        crop_fallback,
+    })
+}
+
+// Also returns whether the motion crop was too unsure of itself to be used, so that the letterbox
+// crop was used instead.
+fn detect_crop(frames: &[GrayImage], opts: CreationOptions) -> Option<(Crop, bool)> {
+    match opts.cropdetect {
+        Cropdetect::None => cropdetect_none(frames).map(|crop| (crop, false)),
+        Cropdetect::Letterbox => cropdetect_letterbox(frames).map(|crop| (crop, false)),
+        Cropdetect::Motion => {
+            let result = cropdetect_motion_detailed(frames)?;
+            if result.confidence >= opts.min_motion_crop_confidence {
+                return Some((result.crop, false));
+            }
+            log::debug!(
+                "motion crop confidence {:.2} from {} frames is too low. Detecting letterboxes instead",
+                result.confidence,
+                result.contributing_frames.len(),
+            );
+            cropdetect_letterbox(frames).map(|crop| (crop, true))
+        }
+    }
+}
+
+// Decode, shrink and crop the frames that a hash is built from.
+fn decode_hash_frames<T: FrameReadCfgTrait + Clone>(
+    frame_read_cfg: &T,
+    fps: f64,
+    segments: &[Segment],
+    opts: CreationOptions,
+    pixel_aspect_ratio: Option<(u32, u32)>,
+) -> VideoHashResult<HashFrames> {
+    decode_hash_frames_with_progress(
+        frame_read_cfg,
+        fps,
+        segments,
+        opts,
+        pixel_aspect_ratio,
+        None,
+    )
+    .0
+}
+
+// As decode_hash_frames, but if the frames ran out cleanly before enough were decoded, also returns
+// the time of the last one, in seconds from the start of the video.
+//
+// If cancellation is cancelled, no more frames are decoded (which stops the decoder) and
+// Error::Cancelled is returned.
+fn decode_hash_frames_with_progress<T: FrameReadCfgTrait + Clone>(
+    frame_read_cfg: &T,
+    fps: f64,
+    segments: &[Segment],
+    opts: CreationOptions,
+    pixel_aspect_ratio: Option<(u32, u32)>,
+    cancellation: Option<&CancellationToken>,
+) -> (VideoHashResult<HashFrames>, Option<f64>) {
+    let is_cancelled = || cancellation.is_some_and(CancellationToken::is_cancelled);
+    let (frames, progress) = match iterate_segment_frames(frame_read_cfg, fps, segments) {
+        Ok(ret) => ret,
+        Err(e) => return (Err(e), None),
+    };
+    let frames = frames.take_while(|_| !is_cancelled());
+    let ret = crop_video_frames(frames, opts, pixel_aspect_ratio, &progress.failed);
+    if is_cancelled() {
+        return (Err(Error::Cancelled), None);
+    }
+    let ended_early = matches!(ret, Err(Error::NotEnoughFrames)) && !progress.failed.get();
+    (ret, progress.position.get().filter(|_| ended_early))
+}
+
+// Plan and decode the frames that a hash is built from. If the frames ran out early, and the
+// container's duration is more than duration_check times the time of the last decoded frame, the
+// container is not believed, and the frames are planned and decoded again as if the video ended
+// at that frame. The returned flag is set if that happened.
+//
+// Returns Error::Cancelled as soon as it notices that cancellation has been cancelled.
+fn decode_planned_frames<T: FrameReadCfgTrait + Clone>(
+    src_path: &Path,
+    opts: CreationOptions,
+    duration_check: Option<f64>,
+    pool: Option<&PipelinePool>,
+    cancellation: Option<&CancellationToken>,
+) -> VideoHashResult<(FramePlan<T>, HashFrames, bool)>
+where
+    T::E: std::error::Error,
+{
+    let check_cancelled = || match cancellation {
+        Some(token) if token.is_cancelled() => Err(Error::Cancelled),
+        _ => Ok(()),
+    };
+
+    check_cancelled()?;
+    let plan = build_frame_reader_with_leading_black::<T>(src_path, opts, None, pool)?;
+    check_cancelled()?;
+    let (frames, observed) = decode_hash_frames_with_progress(
+        &plan.reader,
+        plan.fps,
+        &plan.segments,
+        opts,
+        plan.pixel_aspect_ratio,
+        cancellation,
+    );
+
+    match (observed, duration_check) {
+        (Some(observed), Some(factor)) if plan.duration > observed * factor => {
+            log::debug!(
+                "{}: container claims {:.2}s, but the last frame is at {observed:.2}s. Using the observed duration",
+                src_path.display(),
+                plan.duration,
+            );
+            let duration = Some((observed, plan.duration_source));
+            let plan = build_frame_reader_with_leading_black::<T>(src_path, opts, duration, pool)?;
+            check_cancelled()?;
+            let (frames, _) = decode_hash_frames_with_progress(
+                &plan.reader,
+                plan.fps,
+                &plan.segments,
+                opts,
+                plan.pixel_aspect_ratio,
+                cancellation,
+            );
+            let frames = frames?;
+            Ok((plan, frames, true))
+        }
+        _ => Ok((plan, frames?, false)),
+    }
+}
+
+pub fn gen_frames<T: FrameReadCfgTrait + Clone>(
+    src_path: &Path,
+    opts: CreationOptions,
+    resize: bool,
+    duration_check: Option<f64>,
+) -> VideoHashResult<Vec<GrayImage>> {
+    opts.validate()?;
+    let (_, HashFrames { frames, .. }, _) =
+        decode_planned_frames::<T>(src_path, opts, duration_check, None, None)?;
+
+    if resize {
+        frames
+            .iter()
+            .map(|frame| shrink_frame(frame, DCT_SIZE, DCT_SIZE).ok_or(Error::NotEnoughFrames))
+            .collect()
+    } else {
+        Ok(frames)
+    }
+}
+
+// The frames that thumbnails are made from (see crate::thumbnails), before they are cropped and
+// resized.
+pub(crate) struct ThumbnailFrames {
+    // Each frame, with its time in seconds from the start of the video.
+    pub frames: Vec<(f64, RgbImage)>,
+    // The crop that a hash with the same options applies to its frames, relative to the shrunk
+    // frames it is detected in. None unless it was asked for.
+    pub crop: Option<Crop>,
+    // See applied_pixel_aspect_ratio.
+    pub pixel_aspect_ratio: Option<(u32, u32)>,
+}
+
+// count frames spread evenly over the part of the video that a hash with these options is built
+// from, along with the crop of that hash if cropdetect is set. The crop is known_crop if there is
+// one (the crop recorded in the hash, see VideoHash::crop), and is otherwise found by decoding the
+// frames of the hash itself, so it is exactly the crop that the hash used either way.
+pub(crate) fn gen_thumbnail_frames<T: FrameReadCfgTrait + Clone>(
+    src_path: &Path,
+    opts: CreationOptions,
+    count: usize,
+    cropdetect: bool,
+    known_crop: Option<Crop>,
+) -> VideoHashResult<ThumbnailFrames>
+where
+    T::E: std::error::Error,
+{
+    opts.validate()?;
+    let (plan, crop) = if let (true, Some(crop)) = (cropdetect, known_crop) {
+        let plan = build_frame_reader_with_leading_black::<T>(src_path, opts, None, None)?;
+        (plan, Some(crop))
+    } else if cropdetect {
+        let (plan, hash_frames, _) = decode_planned_frames::<T>(
+            src_path,
+            opts,
+            Some(DEFAULT_DURATION_CHECK_FACTOR),
+            None,
+            None,
+        )?;
+        (plan, Some(hash_frames.crop))
+    } else {
+        let plan = build_frame_reader_with_leading_black::<T>(src_path, opts, None, None)?;
+        (plan, None)
+    };
+
+    let first = plan.segments.first().map_or(0.0, |segment| segment.start);
+    let end = plan.segments.last().map_or(first, |segment| {
+        segment.start + segment.frames as f64 / plan.fps
+    });
+    let spacing = ((end - first) / count.max(1) as f64).max(MIN_THUMBNAIL_SPACING);
+    let first = first + spacing / 2.0;
+
+    let mut reader = plan.reader.clone();
+    reader.start_offset(first);
+    reader.fps(fps_fraction(1.0 / spacing));
+    let frames = reader
+        .spawn_rgb()
+        .map_while(Result::ok)
+        .take(count)
+        .enumerate()
+        .map(|(i, frame)| (first + i as f64 * spacing, frame))
+        .collect::<Vec<_>>();
+
+    if frames.is_empty() && count > 0 {
+        return Err(Error::NotEnoughFrames);
+    }
+
+    Ok(ThumbnailFrames {
+        frames,
+        crop,
+        pixel_aspect_ratio: plan.pixel_aspect_ratio,
+    })
+}
+
+pub fn gen_hash<T: FrameReadCfgTrait + Clone>(
+    src_path: PathBuf,
+    opts: CreationOptions,
+    preprocessor: Option<&FramePreprocessor>,
+    duration_check: Option<f64>,
+) -> Result<VideoHash, crate::Error> {
+    gen_hash_recording::<T>(
+        src_path,
+        opts,
+        preprocessor,
+        duration_check,
+        None,
+        None,
+        &mut HashStats::default(),
+    )
+}
+
+/// As [`gen_hash`], but stops decoding and returns [`crate::Error::Cancelled`] once `cancellation`
+/// is cancelled.
+#[cfg(any(feature = "async", test))]
+pub(crate) fn gen_hash_cancellable<T: FrameReadCfgTrait + Clone>(
+    src_path: PathBuf,
+    opts: CreationOptions,
+    preprocessor: Option<&FramePreprocessor>,
+    duration_check: Option<f64>,
+    cancellation: &CancellationToken,
+) -> Result<VideoHash, crate::Error> {
+    gen_hash_recording::<T>(
+        src_path,
+        opts,
+        preprocessor,
+        duration_check,
+        None,
+        Some(cancellation),
+        &mut HashStats::default(),
+    )
+}
+
+/// As [`gen_hash`] for each of the given videos, decoding them with pipelines shared through one
+/// [`PipelinePool`] (see `VideoHashBuilder::hash_many`). The videos are hashed lazily, one at a time,
+/// as the returned iterator is advanced.
+pub fn gen_hash_many<'a, T: FrameReadCfgTrait + Clone>(
+    src_paths: impl IntoIterator<Item = PathBuf> + 'a,
+    opts: CreationOptions,
+    preprocessor: Option<&'a FramePreprocessor>,
+    duration_check: Option<f64>,
+) -> impl Iterator<Item = (PathBuf, Result<VideoHash, crate::Error>)> + 'a {
+    let pool = PipelinePool::new();
+    src_paths.into_iter().map(move |src_path| {
+        let hash = gen_hash_recording::<T>(
+            src_path.clone(),
+            opts,
+            preprocessor,
+            duration_check,
+            Some(&pool),
+            None,
+            &mut HashStats::default(),
+        );
+        (src_path, hash)
+    })
+}
+
+pub fn gen_hash_with_stats<T: FrameReadCfgTrait + Clone>(
+    src_path: PathBuf,
+    opts: CreationOptions,
+    preprocessor: Option<&FramePreprocessor>,
+    duration_check: Option<f64>,
+    backend: &str,
+) -> (Result<VideoHash, crate::Error>, HashStats) {
+    let start = Instant::now();
+    let mut stats = HashStats {
+        backend: backend.to_string(),
+        ..HashStats::default()
+    };
+    let hash = gen_hash_recording::<T>(
+        src_path.clone(),
+        opts,
+        preprocessor,
+        duration_check,
+        None,
+        None,
+        &mut stats,
+    );
+    //The frames are shrunk as they are decoded, so the source resolution and the decoder are
+    //probed separately. Plain hashing does not pay for this.
+    let reader = T::from_path(&src_path);
+    if let Ok(info) = reader.get_media_info() {
+        stats.resolution = Some(info.resolution);
+        stats.video_codec = info.video_codec;
+    }
+    if let Ok(diagnostics) = reader.diagnose(0) {
+        stats.decoder = diagnostics.decoder;
+    }
+    stats.elapsed = start.elapsed();
+    (hash, stats)
+}
+
+// gen_hash, recording what was decoded into stats as it goes.
+fn gen_hash_recording<T: FrameReadCfgTrait + Clone>(
+    src_path: PathBuf,
+    opts: CreationOptions,
+    preprocessor: Option<&FramePreprocessor>,
+    duration_check: Option<f64>,
+    pool: Option<&PipelinePool>,
+    cancellation: Option<&CancellationToken>,
+    stats: &mut HashStats,
+) -> Result<VideoHash, crate::Error> {
+    use crate::Error::VidProc;
+    opts.validate()?;
+    let decode_start = Instant::now();
+    let (plan, hash_frames, duration_corrected) =
+        decode_planned_frames::<T>(&src_path, opts, duration_check, pool, cancellation)?;
+    let HashFrames {
+        frames,
+        crop,
+        truncated,
+        crop_fallback,
+    } = hash_frames;
+    let decode_time = decode_start.elapsed();
+    stats.frames_decoded = Some(u32::try_from(frames.len()).unwrap_or(u32::MAX));
+
+    let duration = Duration::from_secs_f64(plan.duration);
+    let leading_black = plan.leading_black;
+    let short_video = is_short_video(plan.duration - leading_black, opts);
+    let video_stream = plan
+        .reader
+        .selected_video_stream()
+        .map_err(|e| VidProc(format!("{e:?}")))?;
+
+    let audio_fingerprint = audio_fingerprint(&src_path, &plan.reader, opts);
+    let speed_variants = if short_video {
+        vec![]
+    } else {
+        speed_variants(&plan, crop, opts, preprocessor, cancellation)?
+    };
+
+    let num_frames = frames.len();
+    let dct_start = Instant::now();
+    let hash = hash_from_frames(
+        frames,
+        src_path.clone(),
+        duration.as_secs() as u32,
+        short_video,
+        opts,
+        preprocessor,
+    )?;
+    log::debug!(
+        "hashed {}: decoded in {}ms, dct in {}ms, {} frames{}, crop {}x{} at ({}, {}) of {}x{}",
+        src_path.display(),
+        decode_time.as_millis(),
+        dct_start.elapsed().as_millis(),
+        num_frames,
+        if truncated { " (truncated decode)" } else { "" },
+        crop.width(),
+        crop.height(),
+        crop.left,
+        crop.top,
+        crop.orig_res.0,
+        crop.orig_res.1,
+    );
+
+    Ok(hash
+        .with_video_stream(video_stream)
+        .with_leading_black(Duration::from_secs_f64(leading_black))
+        .with_truncated_decode(truncated)
+        .with_duration_corrected(duration_corrected)
+        .with_duration_source(plan.duration_source)
+        .with_motion_crop_fallback(crop_fallback)
+        .with_audio_fingerprint(audio_fingerprint)
+        .with_pixel_aspect_ratio(plan.pixel_aspect_ratio)
+        .with_source_color_range(plan.color_range)
+        .with_deinterlaced(plan.deinterlaced)
+        .with_environment(environment_digest::<T>())
+        .with_speed_variants(speed_variants)
+        .with_crop(crop))
+}
+
+// The bits of the video as if it were played at each of the speeds of
+// CreationOptions::speed_variants, from the frames that the hash of such a copy would be sampled
+// from, cropped as the frames of the video's own hash were. A copy at speed s lasts 1/s as long,
+// and its time t (after any leading black) is time t * s of the video.
+//
+// Speeds at which the copy would be short, or whose frames would run past the end of the video or
+// could not all be decoded, are left out.
+fn speed_variants<T: FrameReadCfgTrait + Clone>(
+    plan: &FramePlan<T>,
+    crop: Crop,
+    opts: CreationOptions,
+    preprocessor: Option<&FramePreprocessor>,
+    cancellation: Option<&CancellationToken>,
+) -> VideoHashResult<Vec<SpeedVariant>> {
+    if opts.coverage != Coverage::Start {
+        return Ok(vec![]);
+    }
+
+    let vid_duration = plan.duration - plan.leading_black;
+    let mut variants = vec![];
+    for factor in opts.speed_variants.iter() {
+        if cancellation.is_some_and(CancellationToken::is_cancelled) {
+            return Err(Error::Cancelled);
+        }
+
+        let speed = factor.value();
+        let copy_duration = vid_duration / speed;
+        if is_short_video(copy_duration, opts) {
+            continue;
+        }
+        let start = plan.leading_black + sampling_start(copy_duration, opts).max(0.0) * speed;
+        let fps = plan.fps / speed;
+        if start + f64::from(DCT_SIZE) / fps > plan.duration {
+            continue;
+        }
+
+        let mut reader = plan.reader.clone();
+        reader.start_offset(start);
+        reader.fps(fps_fraction(fps));
+        let segments = [Segment {
+            start,
+            frames: DCT_SIZE as usize,
+        }];
+        let Ok((frames, progress)) = iterate_segment_frames(&reader, fps, &segments) else {
+            continue;
+        };
+        let shrunk = shrink_video_frames(
+            frames,
+            opts.cropdetect,
+            plan.pixel_aspect_ratio,
+            &progress.failed,
+        );
+        let frames = match shrunk {
+            Ok((frames, false)) if frames.len() == DCT_SIZE as usize => frames,
+            _ => continue,
+        };
+
+        let frames = frames
+            .into_iter()
+            .map(|f| f.cropped(crop).to_image())
+            .collect();
+        let hash = hash_from_frames(frames, PathBuf::new(), 0, false, opts, preprocessor)?;
+        variants.push(SpeedVariant::new(factor, &hash));
+    }
+    Ok(variants)
+}
+
+// The digest of the decoders of the backend, recorded in each hash that it decodes.
+fn environment_digest<T: FrameReadCfgTrait>() -> Option<EnvironmentDigest> {
+    EnvironmentInfo::of_backend::<T>().map(|info| info.digest())
+}
+
+// The fingerprint of the audio decoded by reader, if CreationOptions::audio_fingerprint is set.
+// Failing to decode the audio only loses the fingerprint, not the whole hash.
+fn audio_fingerprint<T: FrameReadCfgTrait>(
+    src_path: &Path,
+    reader: &T,
+    opts: CreationOptions,
+) -> Option<AudioFingerprint> {
+    if !opts.audio_fingerprint {
+        return None;
+    }
+    match reader.decode_audio(AUDIO_SAMPLE_RATE, opts.duration) {
+        Ok(samples) => AudioFingerprint::from_samples(&samples?),
+        Err(e) => {
+            log::warn!("failed to decode audio of {}: {e:?}", src_path.display());
+            None
+        }
+    }
+}
+
+pub fn hash_from_frame_iter(
+    src_path: PathBuf,
+    duration: Duration,
+    frames: impl IntoIterator<Item = GrayImage>,
+    opts: CreationOptions,
+    preprocessor: Option<&FramePreprocessor>,
+) -> VideoHashResult<VideoHash> {
+    let frames = frames
+        .into_iter()
+        .take(DCT_SIZE as usize)
+        .collect::<Vec<_>>();
+    if frames.len() < DCT_SIZE as usize {
+        return Err(Error::NotEnoughFrames);
+    }
+
+    let short_video = is_short_video(duration.as_secs_f64(), opts);
+    hash_from_frames(
+        frames,
+        src_path,
+        duration.as_secs() as u32,
+        short_video,
+        opts,
+        preprocessor,
+    )
+}
+
+// The files in dir whose names match frame_glob, in lexicographic order.
+fn image_dir_frame_paths(dir: &Path, frame_glob: &str) -> VideoHashResult<Vec<PathBuf>> {
+    let read_err = |e: std::io::Error| Error::VidProc(format!("{}: {e}", dir.display()));
+
+    let mut paths = std::fs::read_dir(dir)
+        .map_err(read_err)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .filter_ok(|path| {
+            path.file_name()
+                .and_then(OsStr::to_str)
+                .is_some_and(|name| glob_matches(frame_glob, name))
+        })
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(read_err)?;
+    paths.sort();
+
+    Ok(paths)
+}
+
+// '*' matches any number of characters and '?' matches exactly one. On a mismatch after a '*',
+// the '*' is retried with one more character.
+fn glob_matches(pattern: &str, name: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let name = name.chars().collect::<Vec<_>>();
+
+    let (mut p, mut n) = (0, 0);
+    let mut last_star = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                last_star = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match last_star {
+                Some((star_p, star_n)) => {
+                    last_star = Some((star_p, star_n + 1));
+                    p = star_p + 1;
+                    n = star_n + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+pub fn hash_from_image_dir(
+    dir: &Path,
+    frame_glob: &str,
+    duration: Duration,
+    opts: CreationOptions,
+    preprocessor: Option<&FramePreprocessor>,
+) -> VideoHashResult<VideoHash> {
+    let paths = image_dir_frame_paths(dir, frame_glob)?;
+    if paths.len() < DCT_SIZE as usize {
+        return Err(Error::NotEnoughFrames);
+    }
+
+    // Loaded one at a time, like decoded frames, so that only the shrunk copies are kept.
+    let frames = paths.iter().take(DCT_SIZE as usize).map(|path| {
+        image::open(path)
+            .map(|image| FullResFrame::new(image.into_luma8()))
+            .map_err(|e| Error::VidProc(format!("{}: {e}", path.display())))
+    });
+    let no_decoder = Cell::new(false);
+    let HashFrames {
+        frames,
+        crop_fallback,
+        ..
+    } = itertools::process_results(frames, |frames| {
+        crop_video_frames(frames, opts, None, &no_decoder)
+    })??;
+
+    let short_video = is_short_video(duration.as_secs_f64(), opts);
+    Ok(hash_from_frames(
+        frames,
+        dir.to_path_buf(),
+        duration.as_secs() as u32,
+        short_video,
+        opts,
+        preprocessor,
+    )?
+    .with_motion_crop_fallback(crop_fallback))
+}
+
+// The frames are normalized (or preprocessed) here, after cropping, so that they are only shrunk
+// to the size of the DCT afterwards.
+fn hash_from_frames(
+    frames: Vec<GrayImage>,
+    src_path: PathBuf,
+    duration: u32,
+    short_video: bool,
+    opts: CreationOptions,
+    preprocessor: Option<&FramePreprocessor>,
+) -> Result<VideoHash, crate::Error> {
+    opts.validate()?;
+    let active_area = active_area(&frames);
+    let (frames, normalization): (Vec<_>, _) = match preprocessor {
+        Some(preprocessor) => (
+            frames.into_iter().map(|f| preprocessor(f)).collect(),
+            Normalization::Custom,
+        ),
+        None => {
+            let normalization = opts.normalization;
+            (
+                frames.into_iter().map(|f| normalization.apply(f)).collect(),
+                normalization,
+            )
+        }
+    };
+
+    let hash = VideoHash::from_frames(
+        &frames,
+        src_path,
+        duration,
+        short_video,
+        opts.hash_bits,
+        opts.reliability_mask,
+    )?
+    .with_normalization(normalization)
+    .with_coverage(opts.coverage)
+    .with_sampling_window(opts.sampling_window())
+    .with_pixel_aspect_ratio((!opts.ignore_pixel_aspect_ratio).then_some((1, 1)))
+    .with_active_area(active_area);
+
+    if opts.frame_hashes {
+        Ok(hash.with_frame_hashes(&frames))
+    } else {
+        Ok(hash)
+    }
+}
+
+pub fn gen_window_hashes<T: FrameReadCfgTrait + Clone>(
+    src_path: PathBuf,
+    opts: CreationOptions,
+    window_stride: f64,
+    preprocessor: Option<&FramePreprocessor>,
+) -> Result<Vec<VideoHash>, crate::Error> {
+    use crate::Error::VidProc;
+
+    opts.validate()?;
+    //Each window is a single run of frames, so every window hash records the same coverage.
+    let opts = CreationOptions {
+        coverage: Coverage::Start,
+        ..opts
+    };
+    if window_stride.is_nan() || window_stride <= 0.0 {
+        return Err(VidProc(format!(
+            "window stride must be positive. Got {window_stride}"
+        )));
+    }
+
+    let mut builder = T::from_path(&src_path);
+    if let Some(index) = opts.video_stream {
+        builder.video_stream_index(index);
+    }
+    builder.deinterlace(opts.deinterlace);
+    let (vid_duration, duration_source) = video_duration(&builder, &src_path, opts)?;
+    check_min_duration(vid_duration, opts)?;
+
+    //A video too short to contain a whole window only has the one window, covering the whole video.
+    //Like the other windows, it is planned from the container's duration.
+    if is_short_video(vid_duration, opts) {
+        let hash = gen_hash::<T>(src_path, opts, preprocessor, None)?;
+        return Ok(vec![hash.with_window_start(Duration::ZERO)]);
+    }
+
+    let video_stream = builder
+        .selected_video_stream()
+        .map_err(|e| VidProc(format!("{e:?}")))?;
+    let media_info = builder.get_media_info().ok();
+    let pixel_aspect_ratio = applied_pixel_aspect_ratio(media_info.as_ref(), opts);
+    let deinterlaced = applies_deinterlacing(media_info.as_ref(), opts);
+    let color_range = media_info.and_then(|info| info.color_range);
+    let environment = environment_digest::<T>();
+    let fps = 64.0 / opts.duration;
+    builder.fps(fps_fraction(fps));
+
+    //Stop the last window a little before the end of the video.
+    let last_start = (vid_duration - opts.duration - END_MARGIN).max(0.0);
+    let num_windows = (last_start / window_stride) as usize + 1;
+
+    //Each window is decoded separately so that only the hashes are kept in memory.
+    (0..num_windows)
+        .map(|i| {
+            let start = i as f64 * window_stride;
+            let mut window_cfg = builder.clone();
+            if start > 0.0 {
+                window_cfg.start_offset(start);
+            }
+
+            let HashFrames {
+                frames,
+                crop,
+                truncated,
+                crop_fallback,
+            } = decode_hash_frames(
+                &window_cfg,
+                fps,
+                &[Segment {
+                    start,
+                    frames: DCT_SIZE as usize,
+                }],
+                opts,
+                pixel_aspect_ratio,
+            )?;
+            let audio_fingerprint = audio_fingerprint(&src_path, &window_cfg, opts);
+            let hash = hash_from_frames(
+                frames,
+                src_path.clone(),
+                vid_duration as u32,
+                false,
+                opts,
+                preprocessor,
+            )?;
+
+            Ok(hash
+                .with_video_stream(video_stream)
+                .with_window_start(Duration::from_secs_f64(start))
+                .with_truncated_decode(truncated)
+                .with_duration_source(duration_source)
+                .with_motion_crop_fallback(crop_fallback)
+                .with_audio_fingerprint(audio_fingerprint)
+                .with_pixel_aspect_ratio(pixel_aspect_ratio)
+                .with_source_color_range(color_range)
+                .with_deinterlaced(deinterlaced)
+                .with_environment(environment)
+                .with_crop(crop))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        cell::Cell,
+        path::Path,
+        path::PathBuf,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use ffmpeg_gst_wrapper::{DecodeDiagnostics, FrameReadCfgTrait, MediaInfo};
+    use image::{GrayImage, Luma, RgbImage};
+    use itertools::Itertools;
+
+    use super::{
+        build_frame_reader, full_res_count, gen_frames, gen_hash, gen_hash_cancellable,
+        gen_hash_many, gen_hash_with_stats, gen_thumbnail_frames, gen_window_hashes, glob_matches,
+        hash_from_frame_iter, hash_from_image_dir, nearest_frames, proxy_dimensions,
+        CreationOptions, Preset,
+    };
+    use super::{
+        crop_video_frames, display_dimensions, hash_from_frames, FullResFrame, HashFrames,
+    };
+    use crate::video_hashing::audio_fingerprint::{test::melody, AUDIO_SAMPLE_RATE};
+    use crate::{
+        definitions::DCT_SIZE, explain_non_match, search_with_opts, ActiveAreaGuard, AudioCheck,
+        CancellationToken, ColorRange, Coverage, CreationOptionsError, Crop, Cropdetect,
+        Deinterlace, DurationSource, Error, FramePreprocessor, GroupingMode, HashSize,
+        NonMatchReason, Normalization, SearchOptions, SpeedFactor, SpeedFactors, Tolerance,
+        VideoHash, DEFAULT_DURATION_CHECK_FACTOR, DEFAULT_MIN_MOTION_CROP_CONFIDENCE,
+    };
+    use crate::{
+        SamplingWindow, DEFAULT_VID_HASH_DURATION, DEFAULT_VID_HASH_MIN_DURATION,
+        DEFAULT_VID_HASH_SKIP_FORWARD,
+    };
+
+    // A stand-in for a real decoder. The video is described by its filename: "5.0" is a 5 second
+    // video, and "5.0-8" is a 5 second video where only 8 frames can be decoded. "5.0-8!" is the
+    // same, except that the decoder reports an error after the 8th frame, as it would for a
+    // truncated file. "8.0+3.0" is the 5 second video with 3 seconds of black frames added to the
+    // start. "5.0~8.0" is a 5 second video whose container claims that it lasts 8 seconds.
+    //
+    // Like gstreamer, frames are produced at whatever framerate is requested. The brightness of
+    // each frame encodes how far through the video it was sampled. Videos with "@30" at the end of
+    // the name can also be decoded with timestamps like ffmpeg, and have a variable framerate of
+    // roughly 30 frames a second. "20.0#1" has a melody made from seed 1 playing over it, and
+    // "20.0#1n" has the same melody after some lossy re-encoding. "60.0=30.0" is the 60 second video,
+    // except that it shows a different picture from 30 seconds on. "20.0^64:45" reports that its
+    // pixels have an aspect ratio of 64:45. "20.0%" is interlaced: Its odd lines are from a moment
+    // later, when the picture has moved to the left, unless it is deinterlaced. The container of
+    // "20.0?" records no duration, so it can only be measured.
+    #[derive(Debug, Clone)]
+    struct SyntheticVideo {
+        duration: f64,
+        reported_duration: Option<f64>,
+        unknown_duration: bool,
+        leading_black: f64,
+        max_frames: usize,
+        decode_error: bool,
+        fps: f64,
+        native_fps: Option<f64>,
+        start_offset: f64,
+        video_stream: usize,
+        // The seed of the melody that plays over the video, and whether it was re-encoded.
+        audio: Option<(u64, bool)>,
+        // The time from which the picture is different.
+        diverges_at: Option<f64>,
+        pixel_aspect_ratio: Option<(u32, u32)>,
+        color_range: Option<ColorRange>,
+        interlaced: bool,
+        deinterlace: Deinterlace,
+    }
+
+    #[derive(Debug, thiserror::Error)]
+    #[error("synthetic video error")]
+    struct SyntheticVideoError;
+
+    impl SyntheticVideo {
+        fn frame_times(&self) -> impl Iterator<Item = f64> {
+            let Self {
+                duration,
+                max_frames,
+                fps,
+                start_offset,
+                ..
+            } = *self;
+
+            (0u32..)
+                .map(move |i| start_offset + f64::from(i) / fps)
+                .take_while(move |t| *t < duration)
+                .take(max_frames)
+        }
+
+        // The times of the frames of a variable framerate video, whose frames are up to 40% of
+        // a frame early or late.
+        fn native_frame_times(&self, native_fps: f64) -> impl Iterator<Item = f64> {
+            let Self {
+                duration,
+                max_frames,
+                start_offset,
+                ..
+            } = *self;
+
+            (0u32..)
+                .map(move |i| {
+                    let jitter = f64::from(i * 7 % 5) * 0.2 - 0.4;
+                    (f64::from(i) + jitter) / native_fps
+                })
+                .skip_while(move |t| *t < start_offset)
+                .take_while(move |t| *t < duration)
+                .take(max_frames)
+        }
+
+        fn frame_at(&self, t: f64) -> GrayImage {
+            let leading_black = self.leading_black;
+            if t < leading_black {
+                return GrayImage::new(32, 24);
+            }
+
+            let brightness = ((t - leading_black) / (self.duration - leading_black) * 255.0) as u8;
+            let diverged = self.diverges_at.is_some_and(|diverges_at| t >= diverges_at);
+            let combed = self.interlaced && self.deinterlace == Deinterlace::Off;
+            GrayImage::from_fn(32, 24, |x, y| {
+                let x = if combed && y % 2 == 1 { x + 6 } else { x };
+                let pattern = if diverged { (x + 24 - y) * 5 } else { x * y };
+                image::Luma([brightness.wrapping_add(pattern as u8)])
+            })
+        }
+    }
+
+    impl FrameReadCfgTrait for SyntheticVideo {
+        type E = SyntheticVideoError;
+
+        fn from_path(src_path: &Path) -> Self {
+            let name = src_path.to_string_lossy();
+            let (name, unknown_duration) = match name.strip_suffix('?') {
+                Some(name) => (name, true),
+                None => (name.as_ref(), false),
+            };
+            let (name, interlaced) = match name.strip_suffix('%') {
+                Some(name) => (name, true),
+                None => (name, false),
+            };
+            let (name, color_range) = match name.split_once('~') {
+                Some((name, "tv")) => (name, Some(ColorRange::Limited)),
+                Some((name, "pc")) => (name, Some(ColorRange::Full)),
+                _ => (name, None),
+            };
+            let (name, pixel_aspect_ratio) = match name.split_once('^') {
+                Some((name, ratio)) => (
+                    name,
+                    ratio
+                        .split_once(':')
+                        .and_then(|(num, den)| Some((num.parse().ok()?, den.parse().ok()?))),
+                ),
+                None => (name, None),
+            };
+            let (name, decode_error) = match name.strip_suffix('!') {
+                Some(name) => (name, true),
+                None => (name, false),
+            };
+            let (name, audio) = match name.split_once('#') {
+                Some((name, audio)) => match audio.strip_suffix('n') {
+                    Some(seed) => (name, seed.parse().ok().map(|seed| (seed, true))),
+                    None => (name, audio.parse().ok().map(|seed| (seed, false))),
+                },
+                None => (name, None),
+            };
+            let (name, native_fps) = match name.split_once('@') {
+                Some((name, native_fps)) => (name, native_fps.parse().ok()),
+                None => (name, None),
+            };
+            let (name, reported_duration) = match name.split_once('~') {
+                Some((name, reported_duration)) => (name, reported_duration.parse().ok()),
+                None => (name, None),
+            };
+            let (name, diverges_at) = match name.split_once('=') {
+                Some((name, diverges_at)) => (name, diverges_at.parse().ok()),
+                None => (name, None),
+            };
+            let (duration, max_frames) = match name.split_once('-') {
+                Some((duration, max_frames)) => (duration, max_frames.parse().ok()),
+                None => (name, None),
+            };
+            let (duration, leading_black) = match duration.split_once('+') {
+                Some((duration, leading_black)) => (duration, leading_black.parse().ok()),
+                None => (duration, None),
+            };
+
+            Self {
+                duration: duration.parse().unwrap_or_default(),
+                reported_duration,
+                unknown_duration,
+                leading_black: leading_black.unwrap_or_default(),
+                max_frames: max_frames.unwrap_or(usize::MAX),
+                decode_error,
+                fps: 1.0,
+                native_fps,
+                start_offset: 0.0,
+                video_stream: 0,
+                audio,
+                diverges_at,
+                pixel_aspect_ratio,
+                color_range,
+                interlaced,
+                deinterlace: Deinterlace::Off,
+            }
+        }
+
+        fn get_duration(&self) -> Result<Duration, Self::E> {
+            let duration = self.reported_duration.unwrap_or(self.duration);
+            Ok(Duration::from_secs_f64(duration))
+        }
+
+        fn get_container_duration(&self) -> Result<Option<Duration>, Self::E> {
+            Ok((!self.unknown_duration).then_some(self.get_duration()?))
+        }
+
+        fn measure_duration(&self, cap: Duration) -> Result<Option<Duration>, Self::E> {
+            let duration = Duration::from_secs_f64(self.duration);
+            Ok((duration <= cap).then_some(duration))
+        }
+
+        fn get_resolution(&self) -> Result<(u32, u32), Self::E> {
+            Ok((32, 24))
+        }
+
+        fn get_media_info(&self) -> Result<MediaInfo, Self::E> {
+            Ok(MediaInfo {
+                resolution: self.get_resolution()?,
+                duration: self.get_duration()?,
+                pixel_aspect_ratio: self.pixel_aspect_ratio,
+                color_range: self.color_range,
+                interlaced: Some(self.interlaced),
+                ..MediaInfo::default()
+            })
+        }
+
+        fn fps(&mut self, (fps_num, fps_den): (u64, u64)) {
+            self.fps = fps_num as f64 / fps_den as f64;
+        }
+
+        fn start_offset(&mut self, offset: f64) {
+            self.start_offset = offset;
+        }
+
+        fn video_stream_index(&mut self, index: usize) {
+            self.video_stream = index;
+        }
+
+        fn selected_video_stream(&self) -> Result<usize, Self::E> {
+            Ok(self.video_stream)
+        }
+
+        // Auto only deinterlaces interlaced videos, as the backends do, and the frames of a
+        // progressive video are unchanged by deinterlacing.
+        fn deinterlace(&mut self, mode: Deinterlace) {
+            self.deinterlace = match mode {
+                Deinterlace::Auto if !self.interlaced => Deinterlace::Off,
+                mode => mode,
+            };
+        }
+
+        fn spawn_gray(self) -> impl Iterator<Item = Result<GrayImage, Self::E>> {
+            let times = self.frame_times();
+            let error = self.decode_error.then_some(Err(SyntheticVideoError));
+            times.map(move |t| Ok(self.frame_at(t))).chain(error)
+        }
+
+        fn spawn_gray_timestamped(
+            self,
+        ) -> Option<impl Iterator<Item = Result<(Duration, GrayImage), Self::E>>> {
+            let times = self.native_frame_times(self.native_fps?);
+            let error = self.decode_error.then_some(Err(SyntheticVideoError));
+            let frames = times.map(move |t| {
+                let pts = Duration::from_secs_f64(t - self.start_offset);
+                Ok((pts, self.frame_at(t)))
+            });
+            Some(frames.chain(error))
+        }
+
+        fn spawn_rgb(self) -> impl Iterator<Item = Result<RgbImage, Self::E>> {
+            self.spawn_gray()
+                .map(|frame| frame.map(|frame| image::DynamicImage::ImageLuma8(frame).to_rgb8()))
+        }
+
+        fn decode_audio(
+            &self,
+            sample_rate: u32,
+            duration: f64,
+        ) -> Result<Option<Vec<i16>>, Self::E> {
+            let Some((seed, reencoded)) = self.audio else {
+                return Ok(None);
+            };
+            //melody is always at AUDIO_SAMPLE_RATE, which is the only rate that is asked for.
+            assert_eq!(sample_rate, AUDIO_SAMPLE_RATE);
+            let end = (self.start_offset + duration).min(self.duration);
+            let mut samples = melody(seed, end, reencoded);
+            let skip = (self.start_offset * f64::from(sample_rate)) as usize;
+            samples.drain(..skip.min(samples.len()));
+            Ok(Some(samples))
+        }
+
+        fn diagnose(&self, _frames: usize) -> Result<DecodeDiagnostics, Self::E> {
+            Ok(DecodeDiagnostics {
+                description: String::from("synthetic frames"),
+                decoder: Some(String::from("synthetic_decoder")),
+                frame_latency: None,
+            })
+        }
+    }
+
+    const DURATION_CHECK: Option<f64> = Some(DEFAULT_DURATION_CHECK_FACTOR);
+
+    fn opts() -> CreationOptions {
+        CreationOptions {
+            cropdetect: Cropdetect::None,
+            ..CreationOptions::default()
+        }
+    }
+
+    #[test]
+    fn test_short_video_frames_span_whole_video() {
+        for name in ["1.0", "5.0"] {
+            let reader =
+                build_frame_reader::<SyntheticVideo>(name, opts()).expect("synthetic video");
+            let brightnesses = reader
+                .spawn_gray()
+                .map(|frame| frame.expect("synthetic frame").get_pixel(0, 0).0[0])
+                .take(DCT_SIZE as usize)
+                .collect::<Vec<_>>();
+
+            assert_eq!(brightnesses.len(), DCT_SIZE as usize, "video: {name}");
+
+            //The first frame is from the start of the video, and the last frame is from near the end.
+            assert!(brightnesses[0] < 25, "video: {name}");
+            assert!(brightnesses[DCT_SIZE as usize - 1] > 200, "video: {name}");
+        }
+    }
+
+    #[test]
+    fn test_short_videos_are_hashed_and_flagged() {
+        for name in ["1.0", "5.0"] {
+            let hash =
+                gen_hash::<SyntheticVideo>(PathBuf::from(name), opts(), None, DURATION_CHECK)
+                    .expect("short video");
+            assert!(hash.is_short_video(), "video: {name}");
+        }
+
+        let hash = gen_hash::<SyntheticVideo>(PathBuf::from("60.0"), opts(), None, DURATION_CHECK)
+            .expect("long video");
+        assert!(!hash.is_short_video());
+    }
+
+    #[test]
+    fn test_hash_stats_are_recorded() {
+        let (hash, stats) = gen_hash_with_stats::<SyntheticVideo>(
+            PathBuf::from("60.0"),
+            opts(),
+            None,
+            DURATION_CHECK,
+            "synthetic",
+        );
+        assert!(hash.is_ok());
+        assert_eq!(stats.backend, "synthetic");
+        assert_eq!(stats.frames_decoded, Some(DCT_SIZE));
+        assert_eq!(stats.resolution, Some((32, 24)));
+        assert_eq!(stats.video_codec, None);
+        assert_eq!(stats.decoder.as_deref(), Some("synthetic_decoder"));
+
+        //Decoding failed before any frames were counted.
+        let (hash, stats) = gen_hash_with_stats::<SyntheticVideo>(
+            PathBuf::from("1.0-0"),
+            opts(),
+            None,
+            DURATION_CHECK,
+            "synthetic",
+        );
+        assert!(matches!(hash, Err(Error::NotEnoughFrames)));
+        assert_eq!(stats.backend, "synthetic");
+        assert_eq!(stats.frames_decoded, None);
+    }
+
+    #[test]
+    fn test_not_enough_frames_only_when_frames_are_missing() {
+        let exactly_enough = format!("1.0-{DCT_SIZE}");
+        assert!(gen_hash::<SyntheticVideo>(
+            PathBuf::from(exactly_enough),
+            opts(),
+            None,
+            DURATION_CHECK
+        )
+        .is_ok());
+
+        let too_few = format!("1.0-{}", DCT_SIZE - 1);
+        assert!(matches!(
+            gen_hash::<SyntheticVideo>(PathBuf::from(too_few), opts(), None, DURATION_CHECK),
+            Err(Error::NotEnoughFrames)
+        ));
+
+        assert!(matches!(
+            gen_hash::<SyntheticVideo>(PathBuf::from("1.0-0"), opts(), None, DURATION_CHECK),
+            Err(Error::NotEnoughFrames)
+        ));
+    }
+
+    #[test]
+    fn test_cancelled_hashes_are_not_decoded() {
+        let hash = |token: &CancellationToken| {
+            gen_hash_cancellable::<SyntheticVideo>(
+                PathBuf::from("60.0"),
+                opts(),
+                None,
+                DURATION_CHECK,
+                token,
+            )
+        };
+
+        let token = CancellationToken::new();
+        let expected =
+            gen_hash::<SyntheticVideo>(PathBuf::from("60.0"), opts(), None, DURATION_CHECK)
+                .expect("synthetic video can be hashed");
+        assert_eq!(hash(&token).expect("not cancelled"), expected);
+
+        token.cancel();
+        assert!(matches!(hash(&token), Err(Error::Cancelled)));
+    }
+
+    #[test]
+    fn test_hash_many_matches_hashing_each_video() {
+        //The failing video in the middle does not affect the videos after it.
+        let names = ["60.0", "20.0^64:45", "1.0-0", "60.0-12!", "20.0%"];
+        let hashes =
+            gen_hash_many::<SyntheticVideo>(names.map(PathBuf::from), opts(), None, DURATION_CHECK)
+                .collect::<Vec<_>>();
+
+        assert_eq!(hashes.len(), names.len());
+        for ((path, hash), name) in hashes.into_iter().zip(names) {
+            assert_eq!(path, PathBuf::from(name));
+            let alone =
+                gen_hash::<SyntheticVideo>(PathBuf::from(name), opts(), None, DURATION_CHECK);
+            match (hash, alone) {
+                (Ok(hash), Ok(alone)) => assert_eq!(hash, alone, "{name}"),
+                (Err(Error::NotEnoughFrames), Err(Error::NotEnoughFrames)) => (),
+                (hash, alone) => unreachable!("{name}: {hash:?} != {alone:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_thumbnails_are_spread_over_the_hashed_frames() {
+        //the default options hash 16 frames at 6.4 frames a second from 15 seconds in.
+        let opts = CreationOptions::default();
+        let thumbs =
+            gen_thumbnail_frames::<SyntheticVideo>(Path::new("60.0"), opts, 4, false, None)
+                .expect("long video");
+        let times = thumbs.frames.iter().map(|(t, _)| *t).collect::<Vec<_>>();
+        assert_eq!(times.len(), 4);
+        assert!(times[0] > 15.0 && times[3] < 17.5, "{times:?}");
+        let spacing = times[1] - times[0];
+        assert!(times
+            .windows(2)
+            .all(|w| (w[1] - w[0] - spacing).abs() < 1e-9));
+        assert!((spacing - 0.625).abs() < 1e-9, "{spacing}");
+        assert!(thumbs.crop.is_none());
+
+        //each frame is the one from its time.
+        let brightness = |frame: &RgbImage| frame.get_pixel(0, 0).0[0];
+        let expected = SyntheticVideo::from_path(Path::new("60.0")).frame_at(times[0]);
+        assert_eq!(
+            brightness(&thumbs.frames[0].1),
+            expected.get_pixel(0, 0).0[0]
+        );
+
+        //the crop comes from decoding the hash itself, so it is the crop the hash recorded.
+        let cropped = CreationOptions {
+            cropdetect: Cropdetect::Letterbox,
+            ..opts
+        };
+        let thumbs =
+            gen_thumbnail_frames::<SyntheticVideo>(Path::new("60.0"), cropped, 4, true, None)
+                .expect("letterboxed video");
+        assert_eq!(thumbs.frames.len(), 4);
+        let hash = gen_hash::<SyntheticVideo>(PathBuf::from("60.0"), cropped, None, DURATION_CHECK)
+            .expect("letterboxed video");
+        let crop = thumbs.crop.expect("asked for");
+        assert_eq!(hash.crop(), Some(crop));
+
+        //unless the crop is already known, in which case it is used as it is.
+        let known = Crop::from_edge_offsets(crop.orig_res, 1, 2, 3, 4);
+        let thumbs = gen_thumbnail_frames::<SyntheticVideo>(
+            Path::new("60.0"),
+            cropped,
+            4,
+            true,
+            Some(known),
+        )
+        .expect("letterboxed video");
+        assert_eq!(thumbs.frames.len(), 4);
+        assert_eq!(thumbs.crop, Some(known));
+
+        //short videos give what they have, and broken ones give an error.
+        let thumbs = gen_thumbnail_frames::<SyntheticVideo>(Path::new("1.0"), opts, 4, false, None)
+            .expect("short video");
+        assert!(!thumbs.frames.is_empty());
+        assert!(
+            gen_thumbnail_frames::<SyntheticVideo>(Path::new("60.0-0"), opts, 4, false, None)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_too_short_videos_are_not_decoded() {
+        //"!" makes decoding fail, so getting TooShort means no decoding was attempted.
+        for name in ["0.0!", "0.5!"] {
+            for result in [
+                gen_hash::<SyntheticVideo>(PathBuf::from(name), opts(), None, DURATION_CHECK)
+                    .map(|_| ()),
+                gen_window_hashes::<SyntheticVideo>(PathBuf::from(name), opts(), 5.0, None)
+                    .map(|_| ()),
+            ] {
+                assert!(
+                    matches!(result, Err(Error::TooShort { .. })),
+                    "video: {name}"
+                );
+            }
+        }
+
+        //a half second video is too short by default.
+        let result = gen_hash::<SyntheticVideo>(PathBuf::from("0.5"), opts(), None, DURATION_CHECK);
+        assert!(
+            matches!(result, Err(Error::TooShort { duration }) if duration == Duration::from_millis(500)),
+            "{result:?}"
+        );
+
+        let opts = CreationOptions {
+            min_duration: 0.25,
+            ..opts()
+        };
+        assert!(
+            gen_hash::<SyntheticVideo>(PathBuf::from("0.5"), opts, None, DURATION_CHECK).is_ok()
+        );
+    }
+
+    #[test]
+    fn test_invalid_options_are_rejected() {
+        use CreationOptionsError::*;
+
+        assert_eq!(CreationOptions::default().validate(), Ok(()));
+        let with = |opts: CreationOptions| opts.validate();
+        assert_eq!(
+            with(CreationOptions {
+                skip_forward_amount: -1.0,
+                ..opts()
+            }),
+            Err(InvalidSkipForward(-1.0))
+        );
+        for duration in [0.0, -5.0, f64::INFINITY] {
+            assert_eq!(
+                with(CreationOptions { duration, ..opts() }),
+                Err(InvalidDuration(duration))
+            );
+        }
+        assert!(matches!(
+            with(CreationOptions {
+                min_duration: f64::NAN,
+                ..opts()
+            }),
+            Err(InvalidMinDuration(_))
+        ));
+        for segments in [0, 9] {
+            assert_eq!(
+                with(CreationOptions {
+                    coverage: Coverage::EvenlySpaced { segments },
+                    ..opts()
+                }),
+                Err(InvalidCoverage(segments))
+            );
+        }
+        for min_motion_crop_confidence in [-0.5, 1.5, f64::NAN] {
+            assert!(matches!(
+                with(CreationOptions {
+                    min_motion_crop_confidence,
+                    ..opts()
+                }),
+                Err(InvalidMotionCropConfidence(_))
+            ));
+        }
+        for max_measured_duration in [-1.0, f64::NAN] {
+            assert!(matches!(
+                with(CreationOptions {
+                    max_measured_duration,
+                    ..opts()
+                }),
+                Err(InvalidMaxMeasuredDuration(_))
+            ));
+        }
+
+        //"!" makes decoding fail, so getting InvalidOptions means no decoding was attempted.
+        let zero_window = CreationOptions {
+            duration: 0.0,
+            ..opts()
+        };
+        for result in [
+            gen_hash::<SyntheticVideo>(PathBuf::from("60.0!"), zero_window, None, DURATION_CHECK)
+                .map(|_| ()),
+            gen_window_hashes::<SyntheticVideo>(PathBuf::from("60.0!"), zero_window, 5.0, None)
+                .map(|_| ()),
+        ] {
+            assert!(
+                matches!(result, Err(Error::InvalidOptions(InvalidDuration(_)))),
+                "{result:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_defaults_are_the_exported_constants() {
+        let default = CreationOptions::default();
+        assert_eq!(default.skip_forward_amount, DEFAULT_VID_HASH_SKIP_FORWARD);
+        assert_eq!(default.duration, DEFAULT_VID_HASH_DURATION);
+        assert_eq!(default.min_duration, DEFAULT_VID_HASH_MIN_DURATION);
+        assert_eq!(
+            default.min_motion_crop_confidence,
+            DEFAULT_MIN_MOTION_CROP_CONFIDENCE
+        );
+        assert_eq!(default.hash_bits, HashSize::DEFAULT);
+        assert_eq!(default.sampling_window(), SamplingWindow::default());
+        assert_eq!(Preset::default().options(), default);
+
+        //wherever the docs say how much of each video is used, they agree with the constants.
+        let skipped = DEFAULT_VID_HASH_SKIP_FORWARD.to_string();
+        let used = (DEFAULT_VID_HASH_SKIP_FORWARD + DEFAULT_VID_HASH_DURATION).to_string();
+        let allowed = [skipped.as_str(), used.as_str()];
+        let docs = [
+            ("lib.rs", include_str!("../lib.rs")),
+            ("README.md", include_str!("../../README.md")),
+            ("video_hash.rs", include_str!("video_hash.rs")),
+        ];
+        for (file, text) in docs {
+            let text = text.replace("//!", "").replace("///", "");
+            let stated = text
+                .split_whitespace()
+                .tuple_windows()
+                .filter(|(first, _, seconds)| *first == "first" && seconds.starts_with("seconds"))
+                .map(|(_, secs, _)| secs)
+                .filter(|secs| secs.parse::<f64>().is_ok())
+                .collect::<Vec<_>>();
+            assert!(stated.contains(&used.as_str()), "{file}");
+            assert!(
+                stated.iter().all(|secs| allowed.contains(secs)),
+                "{file} says {stated:?}, but the defaults skip {skipped} and use the first {used}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_presets() {
+        for preset in Preset::ALL {
+            let options = preset.options();
+            assert_eq!(options.validate(), Ok(()), "{preset:?}");
+            assert_eq!(
+                Preset::from_sampling_window(options.sampling_window()),
+                Some(preset)
+            );
+        }
+        let [fast, default, thorough] = Preset::ALL.map(Preset::options);
+        let end = |opts: CreationOptions| opts.skip_forward_amount + opts.duration;
+        assert!(end(fast) < end(default));
+        assert!(thorough.duration > default.duration);
+        assert_eq!(thorough.cropdetect, Cropdetect::Motion);
+
+        let hash = |name: &str, opts| {
+            gen_hash::<SyntheticVideo>(PathBuf::from(name), opts, None, DURATION_CHECK)
+                .expect("long video")
+        };
+        let hashes = Preset::ALL.map(|preset| {
+            let opts = CreationOptions {
+                cropdetect: Cropdetect::None,
+                ..preset.options()
+            };
+            hash("60.0", opts)
+        });
+        for (hash, preset) in hashes.iter().zip(Preset::ALL) {
+            assert_eq!(hash.preset(), Some(preset));
+        }
+        let custom = CreationOptions {
+            duration: 12.0,
+            ..opts()
+        };
+        assert_eq!(hash("60.0", custom).preset(), None);
+
+        //hashes made with different presets are never compared, even of the same video.
+        let tolerance = SearchOptions::new(Tolerance::new(1.0).expect("1.0 is a valid tolerance"));
+        assert!(search_with_opts(hashes.to_vec(), &tolerance).is_empty());
+        let copy = hash("60.0@30", opts());
+        assert!(!search_with_opts([hashes[1].clone(), copy], &tolerance).is_empty());
+    }
+
+    #[test]
+    fn test_decode_errors_after_enough_frames_are_flagged() {
+        //The error comes before all the frames are decoded, but after enough of them.
+        let hash =
+            gen_hash::<SyntheticVideo>(PathBuf::from("60.0-12!"), opts(), None, DURATION_CHECK)
+                .expect("truncated video");
+        assert!(hash.truncated_decode());
+
+        //The error is never reached.
+        let hash =
+            gen_hash::<SyntheticVideo>(PathBuf::from("60.0-40!"), opts(), None, DURATION_CHECK)
+                .expect("video with a corrupt end");
+        assert!(!hash.truncated_decode());
+
+        let hash = gen_hash::<SyntheticVideo>(PathBuf::from("60.0"), opts(), None, DURATION_CHECK)
+            .expect("long video");
+        assert!(!hash.truncated_decode());
+
+        assert!(matches!(
+            gen_hash::<SyntheticVideo>(PathBuf::from("60.0-3!"), opts(), None, DURATION_CHECK),
+            Err(Error::NotEnoughFrames)
+        ));
+
+        //Without a decode error, a video which simply has too few frames is still an error.
+        assert!(matches!(
+            gen_hash::<SyntheticVideo>(PathBuf::from("60.0-12"), opts(), None, DURATION_CHECK),
+            Err(Error::NotEnoughFrames)
+        ));
+    }
+
+    #[test]
+    fn test_container_durations_are_checked() {
+        //Frames are sampled across 8 seconds, so the video runs out after fewer than 16.
+        let lying = PathBuf::from("5.0~8.0");
+        let hash = gen_hash::<SyntheticVideo>(lying.clone(), opts(), None, DURATION_CHECK)
+            .expect("corrected video");
+        assert!(hash.duration_corrected());
+        assert_eq!(hash.duration(), 4);
+        assert!(hash.is_short_video());
+
+        //The frames are sampled across the frames that exist, as if the container were honest.
+        let frames = gen_frames::<SyntheticVideo>(&lying, opts(), false, DURATION_CHECK)
+            .expect("corrected video");
+        assert!(frames[DCT_SIZE as usize - 1].get_pixel(0, 0).0[0] > 200);
+
+        assert!(matches!(
+            gen_hash::<SyntheticVideo>(lying.clone(), opts(), None, None),
+            Err(Error::NotEnoughFrames)
+        ));
+        assert!(matches!(
+            gen_hash::<SyntheticVideo>(lying, opts(), None, Some(2.0)),
+            Err(Error::NotEnoughFrames)
+        ));
+
+        //A video which is only slightly shorter than its container says still has enough frames.
+        let hash =
+            gen_hash::<SyntheticVideo>(PathBuf::from("5.0~5.2"), opts(), None, DURATION_CHECK)
+                .expect("video");
+        assert!(!hash.duration_corrected());
+        assert_eq!(hash.duration(), 5);
+
+        //Truncated files end at an error rather than at the end of the stream, so are left alone.
+        let hash =
+            gen_hash::<SyntheticVideo>(PathBuf::from("60.0-12!"), opts(), None, DURATION_CHECK)
+                .expect("truncated video");
+        assert!(!hash.duration_corrected());
+        assert_eq!(hash.duration(), 60);
+    }
+
+    #[test]
+    fn test_missing_durations_are_measured() {
+        let hash = gen_hash::<SyntheticVideo>(PathBuf::from("20.0"), opts(), None, DURATION_CHECK)
+            .expect("video");
+        assert_eq!(hash.duration_source(), DurationSource::Container);
+
+        let unknown = PathBuf::from("20.0?");
+        let hash = gen_hash::<SyntheticVideo>(unknown.clone(), opts(), None, DURATION_CHECK)
+            .expect("video without a recorded duration");
+        assert_eq!(hash.duration_source(), DurationSource::Measured);
+        assert_eq!(hash.duration(), 20);
+
+        //Measuring gives up on videos longer than the cap, and can be turned off entirely.
+        for max_measured_duration in [10.0, 0.0] {
+            let opts = CreationOptions {
+                max_measured_duration,
+                ..opts()
+            };
+            assert!(matches!(
+                gen_hash::<SyntheticVideo>(unknown.clone(), opts, None, DURATION_CHECK),
+                Err(Error::UnknownDuration)
+            ));
+        }
+
+        let hashes = gen_window_hashes::<SyntheticVideo>(unknown, opts(), 5.0, None)
+            .expect("windows of a video without a recorded duration");
+        let known = gen_window_hashes::<SyntheticVideo>(PathBuf::from("20.0"), opts(), 5.0, None)
+            .expect("windows of a video");
+        assert_eq!(hashes.len(), known.len());
+        assert!(hashes
+            .iter()
+            .all(|hash| hash.duration_source() == DurationSource::Measured));
+    }
+
+    #[test]
+    fn test_hash_has_requested_size() {
+        for hash_bits in HashSize::ALL {
+            let opts = CreationOptions {
+                hash_bits,
+                ..opts()
+            };
+            let hash =
+                gen_hash::<SyntheticVideo>(PathBuf::from("60.0"), opts, None, DURATION_CHECK)
+                    .expect("long video");
+            assert_eq!(hash.hash_size(), hash_bits);
+        }
+    }
+
+    #[test]
+    fn test_window_hashes_cover_video() {
+        let hashes = gen_window_hashes::<SyntheticVideo>(PathBuf::from("60.0"), opts(), 5.0, None)
+            .expect("long video");
+        let starts = hashes
+            .iter()
+            .map(|hash| hash.window_start().expect("window hashes have a start"))
+            .collect::<Vec<_>>();
+
+        //The last window starts early enough to leave room for decoding a whole window.
+        let expected = (0..10)
+            .map(|i| Duration::from_secs(i * 5))
+            .collect::<Vec<_>>();
+        assert_eq!(starts, expected);
+
+        //Windows show different parts of the video, so they have different hashes.
+        assert_ne!(
+            hashes[0].hamming_distance(&hashes[9]).expect("same size"),
+            0
+        );
+
+        //Short videos have a single window.
+        let hashes = gen_window_hashes::<SyntheticVideo>(PathBuf::from("5.0"), opts(), 5.0, None)
+            .expect("short video");
+        assert_eq!(hashes.len(), 1);
+        assert_eq!(hashes[0].window_start(), Some(Duration::ZERO));
+
+        assert!(
+            gen_window_hashes::<SyntheticVideo>(PathBuf::from("60.0"), opts(), 0.0, None).is_err()
+        );
+    }
+
+    #[test]
+    fn test_coverage_samples_runs_across_video() {
+        let covered = CreationOptions {
+            coverage: Coverage::StartMiddleEnd,
+            ..opts()
+        };
+        let brightnesses = gen_frames::<SyntheticVideo>(Path::new("60.0"), covered, false, None)
+            .expect("long video")
+            .iter()
+            .map(|frame| frame.get_pixel(0, 0).0[0])
+            .collect::<Vec<_>>();
+        assert_eq!(brightnesses.len(), DCT_SIZE as usize);
+
+        //runs of 6, 5 and 5 frames, starting after the skip, in the middle and just before the end.
+        let runs = [
+            &brightnesses[..6],
+            &brightnesses[6..11],
+            &brightnesses[11..],
+        ];
+        for run in runs {
+            assert!(
+                run.windows(2).all(|w| w[0] <= w[1] && w[1] - w[0] < 5),
+                "{run:?}"
+            );
+        }
+        assert!((55..75).contains(&runs[0][0]), "{brightnesses:?}");
+        assert!((140..165).contains(&runs[1][0]), "{brightnesses:?}");
+        assert!(runs[2][4] > 235, "{brightnesses:?}");
+
+        //short videos are sampled across their whole length as usual.
+        let short = |opts| gen_frames::<SyntheticVideo>(Path::new("5.0"), opts, false, None);
+        assert_eq!(short(covered).ok(), short(opts()).ok());
+    }
+
+    #[test]
+    fn test_coverage_rejects_shared_openings() {
+        let hash = |name: &str, coverage| {
+            let opts = CreationOptions { coverage, ..opts() };
+            gen_hash::<SyntheticVideo>(PathBuf::from(name), opts, None, DURATION_CHECK)
+                .expect("synthetic video")
+        };
+        let matches = |a: &VideoHash, b: &VideoHash| {
+            !search_with_opts([a.clone(), b.clone()], &SearchOptions::default()).is_empty()
+        };
+
+        for coverage in [Coverage::Start, Coverage::StartMiddleEnd] {
+            let original = hash("60.0", coverage);
+            assert_eq!(original.coverage(), coverage);
+
+            //A copy decoded at a different framerate is a true duplicate.
+            let copy = hash("60.0@30", coverage);
+            assert!(matches(&original, &copy), "{coverage:?}");
+
+            //This video only shares its first half, which is all that Start looks at.
+            let same_opening = hash("60.0=30.0", coverage);
+            assert_eq!(
+                matches(&original, &same_opening),
+                coverage == Coverage::Start,
+                "{coverage:?}"
+            );
+        }
+
+        //A single evenly spaced run samples the same frames as Start, but the hashes never match.
+        let start = hash("60.0", Coverage::Start);
+        let single = hash("60.0", Coverage::EvenlySpaced { segments: 1 });
+        assert_eq!(start.hamming_distance(&single), Ok(0));
+        assert!(!matches(&start, &single));
+
+        //Window hashes are of a single run whatever the coverage.
+        let opts = CreationOptions {
+            coverage: Coverage::StartMiddleEnd,
+            ..opts()
+        };
+        let windows = gen_window_hashes::<SyntheticVideo>(PathBuf::from("60.0"), opts, 20.0, None)
+            .expect("long video");
+        assert!(windows
+            .iter()
+            .all(|hash| hash.coverage() == Coverage::Start));
+    }
+
+    #[test]
+    fn test_frame_hashes_line_up_shifted_videos() {
+        let hash = |skip_forward_amount| {
+            let opts = CreationOptions {
+                skip_forward_amount,
+                frame_hashes: true,
+                ..opts()
+            };
+            gen_hash::<SyntheticVideo>(PathBuf::from("60.0"), opts, None, DURATION_CHECK)
+                .expect("long video")
+        };
+
+        //Frames are sampled 6.4 times a second, so this starts exactly 5 frames later.
+        let h1 = hash(15.0);
+        let h2 = hash(15.0 + 5.0 / 6.4);
+        assert!(h1.has_frame_hashes());
+
+        let aligned = h1.aligned_frame_distance(&h2, 5).expect("has frame hashes");
+        let unaligned = h1.aligned_frame_distance(&h2, 0).expect("has frame hashes");
+        assert!(aligned < 0.05, "aligned distance: {aligned}");
+        assert!(unaligned > aligned);
+
+        //Off by default.
+        let plain = gen_hash::<SyntheticVideo>(PathBuf::from("60.0"), opts(), None, DURATION_CHECK)
+            .expect("long video");
+        assert!(!plain.has_frame_hashes());
+    }
+
+    #[test]
+    fn test_hash_records_video_stream() {
+        let hash = gen_hash::<SyntheticVideo>(PathBuf::from("60.0"), opts(), None, DURATION_CHECK)
+            .expect("long video");
+        assert_eq!(hash.video_stream(), 0);
+
+        let opts = CreationOptions {
+            video_stream: Some(2),
+            ..opts()
+        };
+        let hash = gen_hash::<SyntheticVideo>(PathBuf::from("60.0"), opts, None, DURATION_CHECK)
+            .expect("long video");
+        assert_eq!(hash.video_stream(), 2);
+    }
+
+    #[test]
+    fn test_unsure_motion_crops_fall_back_to_letterbox() {
+        //The whole of each synthetic frame changes brightness at once, so there is no moving area
+        //to be confident about.
+        let hash_with = |cropdetect, min_motion_crop_confidence| {
+            let opts = CreationOptions {
+                cropdetect,
+                min_motion_crop_confidence,
+                ..opts()
+            };
+            gen_hash::<SyntheticVideo>(PathBuf::from("60.0"), opts, None, DURATION_CHECK)
+                .expect("long video")
+        };
+
+        let fallback = hash_with(Cropdetect::Motion, DEFAULT_MIN_MOTION_CROP_CONFIDENCE);
+        let letterbox = hash_with(Cropdetect::Letterbox, DEFAULT_MIN_MOTION_CROP_CONFIDENCE);
+        assert!(fallback.motion_crop_fallback());
+        assert_eq!(fallback.hamming_distance(&letterbox), Ok(0));
+
+        assert!(!hash_with(Cropdetect::Motion, 0.0).motion_crop_fallback());
+        assert!(!hash_with(Cropdetect::Letterbox, 1.0).motion_crop_fallback());
+    }
+
+    #[test]
+    fn test_full_res_frames_are_shrunk_as_they_are_decoded() {
+        for cropdetect in [Cropdetect::None, Cropdetect::Letterbox, Cropdetect::Motion] {
+            let opts = CreationOptions {
+                cropdetect,
+                ..opts()
+            };
+            let _ = full_res_count::take_peak();
+
+            //Motion detection may not find a crop in the synthetic frames, but either way the
+            //frames must not have been kept.
+            let hash =
+                gen_hash::<SyntheticVideo>(PathBuf::from("60.0"), opts, None, DURATION_CHECK);
+            assert!(hash.is_ok() || cropdetect == Cropdetect::Motion);
+            assert_eq!(full_res_count::take_peak(), 1, "cropdetect: {cropdetect:?}");
+        }
+
+        let _ = gen_window_hashes::<SyntheticVideo>(PathBuf::from("60.0"), opts(), 5.0, None);
+        assert_eq!(full_res_count::take_peak(), 1);
+    }
+
+    #[test]
+    fn test_nearest_frame_is_sampled() {
+        let frames = [(0.0, 'a'), (0.5, 'b'), (0.875, 'c'), (2.0, 'd')];
+
+        //ties go to the earlier frame, and the last frame is held for no more than one sample.
+        let sampled = nearest_frames(frames.into_iter(), 4.0).collect::<String>();
+        assert_eq!(sampled, "aabcccdddd");
+
+        assert_eq!(
+            nearest_frames(std::iter::empty::<(f64, char)>(), 4.0).count(),
+            0
+        );
+    }
+
+    #[test]
+    fn test_variable_framerate_videos_are_sampled_by_timestamp() {
+        let hash = |name: &str| {
+            gen_hash::<SyntheticVideo>(PathBuf::from(name), opts(), None, DURATION_CHECK)
+        };
+
+        //the same frames are picked every time...
+        let vfr = hash("60.0@30").expect("long video");
+        for _ in 0..10 {
+            assert_eq!(hash("60.0@30").expect("long video"), vfr);
+        }
+
+        //...and they are the ones nearest to where a constant framerate would have sampled, so
+        //nothing is duplicated or dropped.
+        let brightnesses = |name: &str| {
+            gen_frames::<SyntheticVideo>(Path::new(name), opts(), true, DURATION_CHECK)
+                .expect("long video")
+                .iter()
+                .map(|frame| i32::from(frame.get_pixel(0, 0).0[0]))
+                .collect::<Vec<_>>()
+        };
+        let vfr_frames = brightnesses("60.0@30");
+        let cfr_frames = brightnesses("60.0");
+        assert_eq!(vfr_frames.len(), cfr_frames.len());
+        assert!(
+            vfr_frames
+                .iter()
+                .zip(&cfr_frames)
+                .all(|(vfr, cfr)| (vfr - cfr).abs() <= 1),
+            "{vfr_frames:?} {cfr_frames:?}"
+        );
+        let cfr = hash("60.0").expect("long video");
+        assert_eq!(
+            search_with_opts(vec![vfr, cfr], &SearchOptions::default()).len(),
+            1
+        );
+
+        //short and truncated videos behave as they do without timestamps.
+        assert!(hash("5.0@30").expect("short video").is_short_video());
+        assert!(matches!(hash("60.0-8@30"), Err(Error::NotEnoughFrames)));
+    }
+
+    #[test]
+    fn test_proxy_dimensions() {
+        assert_eq!(proxy_dimensions((7680, 4320)), (256, 144));
+        assert_eq!(proxy_dimensions((1080, 1920)), (144, 256));
+        assert_eq!(proxy_dimensions((32, 24)), (32, 24));
+        assert_eq!(proxy_dimensions((100_000, 10)), (256, 1));
+    }
+
+    #[test]
+    fn test_display_dimensions() {
+        //a widescreen PAL DVD, and the same DVD in 4:3.
+        assert_eq!(display_dimensions((720, 576), Some((64, 45))), (1024, 576));
+        assert_eq!(display_dimensions((720, 576), Some((16, 15))), (768, 576));
+        //tall pixels stretch the height instead.
+        assert_eq!(display_dimensions((1440, 540), Some((1, 2))), (1440, 1080));
+        assert_eq!(display_dimensions((720, 576), Some((1, 1))), (720, 576));
+        assert_eq!(display_dimensions((720, 576), None), (720, 576));
+    }
+
+    // A widescreen picture with black bars above and below, whose waves scroll right over time,
+    // sampled at the given resolution.
+    fn letterboxed_frame(t: u32, (width, height): (u32, u32)) -> FullResFrame {
+        FullResFrame::new(GrayImage::from_fn(width, height, |x, y| {
+            let u = f64::from(x) / f64::from(width);
+            let v = f64::from(y) / f64::from(height);
+            if !(0.125..0.875).contains(&v) {
+                return Luma([0]);
+            }
+            let t = f64::from(t) / 16.0;
+            let wave = |fu: f64, fv: f64, ft: f64| {
+                (std::f64::consts::TAU * (u * fu + v * fv + t * ft)).sin()
+            };
+            let value = (1..=12)
+                .map(|i| {
+                    let i = f64::from(i);
+                    wave(i % 5.0 - 2.0, i % 3.0 - 1.0, i % 4.0 - 1.5) / i.sqrt()
+                })
+                .sum::<f64>();
+            Luma([(128.0 + 25.0 * value).clamp(40.0, 255.0) as u8])
+        }))
+    }
+
+    #[test]
+    fn test_anamorphic_video_matches_square_pixel_copy() {
+        let opts = CreationOptions {
+            cropdetect: Cropdetect::Letterbox,
+            ..CreationOptions::default()
+        };
+        let hash = |resolution, pixel_aspect_ratio| {
+            let frames = (0..DCT_SIZE).map(|t| letterboxed_frame(t, resolution));
+            let HashFrames { frames, crop, .. } =
+                crop_video_frames(frames, opts, pixel_aspect_ratio, &Cell::new(false))
+                    .expect("frames can be cropped");
+            let hash = hash_from_frames(frames, PathBuf::from("a.mp4"), 60, false, opts, None)
+                .expect("frames can be hashed");
+            (hash, crop)
+        };
+
+        //a widescreen DVD is cropped in the shape of its square pixel re-encode.
+        let (dvd, dvd_crop) = hash((720, 576), Some((64, 45)));
+        let (reencode, reencode_crop) = hash((1024, 576), Some((1, 1)));
+        assert_eq!(dvd_crop.orig_res, (256, 144));
+        assert_eq!(dvd_crop, reencode_crop);
+
+        let distance = dvd.hamming_distance(&reencode).expect("same size");
+        assert!(
+            f64::from(distance) / f64::from(dvd.hash_size().bits()) < 0.05,
+            "distance {distance}"
+        );
+
+        //cropping the DVD in the shape that it is stored in is further off.
+        let (unstretched, unstretched_crop) = hash((720, 576), None);
+        assert_eq!(unstretched_crop.orig_res, (256, 204));
+        let unstretched_distance = unstretched.hamming_distance(&reencode).expect("same size");
+        assert!(
+            distance < unstretched_distance,
+            "{distance} vs {unstretched_distance}"
+        );
+    }
+
+    #[test]
+    fn test_pixel_aspect_ratio_is_recorded() {
+        let hash = |name: &str, opts| {
+            gen_hash::<SyntheticVideo>(PathBuf::from(name), opts, None, DURATION_CHECK)
+                .expect("synthetic video")
+        };
+        let ignored = CreationOptions {
+            ignore_pixel_aspect_ratio: true,
+            ..opts()
+        };
+
+        //ratios are recorded in lowest terms, and unreported ratios are square.
+        let dvd = hash("20.0^32:30", opts());
+        assert_eq!(dvd.pixel_aspect_ratio(), Some((16, 15)));
+        assert_eq!(hash("20.0", opts()).pixel_aspect_ratio(), Some((1, 1)));
+        assert_eq!(hash("20.0^32:30", ignored).pixel_aspect_ratio(), None);
+
+        let windows =
+            gen_window_hashes::<SyntheticVideo>(PathBuf::from("60.0^64:45"), opts(), 5.0, None)
+                .expect("synthetic video");
+        assert!(windows
+            .iter()
+            .all(|hash| hash.pixel_aspect_ratio() == Some((64, 45))));
+
+        //without a crop the ratio makes no difference to the bits, but stretched and unstretched
+        //hashes still never match.
+        let unstretched = hash("20.0^32:30", ignored).with_src_path("unstretched");
+        assert_eq!(dvd.hamming_distance(&unstretched), Ok(0));
+        let square = hash("20.0", opts()).with_src_path("square");
+        let groups = search_with_opts([dvd.clone(), unstretched], &SearchOptions::default());
+        assert!(groups.is_empty());
+        let groups = search_with_opts([dvd, square], &SearchOptions::default());
+        assert_eq!(groups.len(), 1);
+    }
+
+    #[test]
+    fn test_deinterlacing() {
+        let hash = |name: &str, deinterlace: Deinterlace| {
+            let opts = CreationOptions {
+                deinterlace,
+                ..opts()
+            };
+            gen_hash::<SyntheticVideo>(PathBuf::from(name), opts, None, DURATION_CHECK)
+                .expect("synthetic video")
+        };
+
+        //deinterlacing brings an interlaced video nearer to its progressive copy.
+        let progressive = hash("20.0", Deinterlace::Off);
+        let combed = hash("20.0%", Deinterlace::Off);
+        let deinterlaced = hash("20.0%", Deinterlace::Auto);
+        let distance = |hash: &VideoHash| progressive.hamming_distance(hash).expect("same size");
+        assert!(distance(&deinterlaced) < distance(&combed));
+        assert!(!combed.deinterlaced());
+        assert!(deinterlaced.deinterlaced());
+
+        //Auto leaves progressive videos alone, and Force deinterlaces them too.
+        assert!(!hash("20.0", Deinterlace::Auto).deinterlaced());
+        let forced = hash("20.0", Deinterlace::Force).with_src_path("forced");
+        assert!(forced.deinterlaced());
+
+        let windows = gen_window_hashes::<SyntheticVideo>(
+            PathBuf::from("60.0%"),
+            CreationOptions {
+                deinterlace: Deinterlace::Auto,
+                ..opts()
+            },
+            5.0,
+            None,
+        )
+        .expect("synthetic video");
+        assert!(windows.iter().all(VideoHash::deinterlaced));
+
+        //hashes which were and were not deinterlaced are compatible.
+        assert_eq!(progressive.hamming_distance(&forced), Ok(0));
+        let groups = search_with_opts([progressive, forced], &SearchOptions::default());
+        assert_eq!(groups.len(), 1);
+    }
+
+    #[test]
+    fn test_source_color_range_is_recorded() {
+        let hash = |name: &str| {
+            gen_hash::<SyntheticVideo>(PathBuf::from(name), opts(), None, DURATION_CHECK)
+                .expect("synthetic video")
+        };
+
+        let limited = hash("20.0~tv");
+        let full = hash("20.0~pc").with_src_path("full");
+        assert_eq!(limited.source_color_range(), Some(ColorRange::Limited));
+        assert_eq!(full.source_color_range(), Some(ColorRange::Full));
+        assert_eq!(hash("20.0").source_color_range(), None);
+
+        let windows =
+            gen_window_hashes::<SyntheticVideo>(PathBuf::from("60.0~tv"), opts(), 5.0, None)
+                .expect("synthetic video");
+        assert!(windows
+            .iter()
+            .all(|hash| hash.source_color_range() == Some(ColorRange::Limited)));
+
+        //the backend has already decoded both to full range, so the range is only a record.
+        assert_eq!(limited.hamming_distance(&full), Ok(0));
+        let groups = search_with_opts([limited, full], &SearchOptions::default());
+        assert_eq!(groups.len(), 1);
+    }
+
+    #[test]
+    fn test_leading_black_is_skipped() {
+        let hash = |name: &str, skip_leading_black| {
+            let opts = CreationOptions {
+                skip_leading_black,
+                ..opts()
+            };
+            gen_hash::<SyntheticVideo>(PathBuf::from(name), opts, None, DURATION_CHECK)
+                .expect("long video")
+        };
+
+        let original = hash("60.0", true);
+        let padded = hash("63.0+3.0", true);
+        assert_eq!(original.leading_black(), Duration::ZERO);
+        assert_eq!(padded.leading_black(), Duration::from_secs(3));
+        assert_eq!(padded.hamming_distance(&original), Ok(0));
+
+        //Off by default.
+        let padded = hash("63.0+3.0", false);
+        assert_eq!(padded.leading_black(), Duration::ZERO);
+        assert_ne!(padded.hamming_distance(&original), Ok(0));
+
+        //No more than 10 seconds are skipped, and entirely black videos are not skipped at all.
+        assert_eq!(
+            hash("80.0+15.0", true).leading_black(),
+            Duration::from_secs(10)
+        );
+        let black = gen_hash::<SyntheticVideo>(
+            PathBuf::from("5.0+5.0"),
+            CreationOptions {
+                skip_leading_black: true,
+                ..opts()
+            },
+            None,
+            DURATION_CHECK,
+        );
+        assert_eq!(black.map(|h| h.leading_black()).ok(), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn test_hashing_frames_gives_same_hash_as_path() {
+        for (name, cropdetect) in [
+            ("60.0", Cropdetect::None),
+            ("60.0", Cropdetect::Letterbox),
+            ("5.0", Cropdetect::Letterbox),
+        ] {
+            let opts = CreationOptions {
+                cropdetect,
+                frame_hashes: true,
+                ..opts()
+            };
+            let frames = gen_frames::<SyntheticVideo>(Path::new(name), opts, false, DURATION_CHECK)
+                .expect("synthetic video");
+            let duration = Duration::from_secs_f64(name.parse().expect("duration"));
+            let from_frames =
+                hash_from_frame_iter(PathBuf::from(name), duration, frames, opts, None)
+                    .expect("enough frames");
+
+            let from_path =
+                gen_hash::<SyntheticVideo>(PathBuf::from(name), opts, None, DURATION_CHECK)
+                    .expect("video");
+            //frames carry no record of how they were cropped.
+            assert_eq!(from_frames.crop(), None);
+            assert_eq!(
+                from_frames,
+                VideoHash {
+                    crop: None,
+                    ..from_path
+                },
+                "video: {name}, cropdetect: {cropdetect:?}"
+            );
+        }
+
+        let frames = gen_frames::<SyntheticVideo>(Path::new("60.0"), opts(), true, DURATION_CHECK)
+            .expect("synthetic video");
+        assert_eq!(frames.len(), DCT_SIZE as usize);
+        assert!(frames
+            .iter()
+            .all(|frame| frame.dimensions() == (DCT_SIZE, DCT_SIZE)));
+
+        let too_few = frames.into_iter().take(DCT_SIZE as usize - 1);
+        assert!(matches!(
+            hash_from_frame_iter(
+                PathBuf::from("60.0"),
+                Duration::from_secs(60),
+                too_few,
+                opts(),
+                None
+            ),
+            Err(Error::NotEnoughFrames)
+        ));
+    }
+
+    #[test]
+    fn test_glob_matches() {
+        assert!(glob_matches("frame_*.png", "frame_0001.png"));
+        assert!(glob_matches("frame_*.png", "frame_.png"));
+        assert!(glob_matches("*", "anything"));
+        assert!(glob_matches("f??.png", "f01.png"));
+        assert!(glob_matches("*_*.png", "a_b_c.png"));
+        assert!(!glob_matches("frame_*.png", "frame_0001.jpg"));
+        assert!(!glob_matches("frame_*.png", "cover.png"));
+        assert!(!glob_matches("f??.png", "f1.png"));
+    }
+
+    #[test]
+    fn test_hash_image_dir() {
+        use sha2::{Digest, Sha256};
+
+        let temp_dir = tempfile::tempdir().expect("temp dir");
+        let dir = temp_dir.path();
+
+        // A bright square moving across a gradient. The frames are written out of order, and
+        // alongside some files that are not frames.
+        for i in (0..20u32).rev() {
+            let frame = RgbImage::from_fn(64, 48, |x, y| {
+                let in_square = (i * 2..i * 2 + 16).contains(&x) && (16..32).contains(&y);
+                let luma = if in_square { 250 } else { (x + y) as u8 };
+                image::Rgb([luma, luma / 2, 255 - luma])
+            });
+            frame
+                .save(dir.join(format!("frame_{i:03}.png")))
+                .expect("write frame");
+        }
+        std::fs::write(dir.join("notes.txt"), "not a frame").expect("write notes");
+        RgbImage::new(8, 8)
+            .save(dir.join("cover.png"))
+            .expect("write cover");
+
+        let hash = |frame_glob| {
+            hash_from_image_dir(dir, frame_glob, Duration::from_secs(60), opts(), None)
+        };
+
+        //Padded to the 16 words that every hash used to be stored in, so the digest is unchanged.
+        let golden = |hash: &VideoHash| {
+            let words = (hash.hash.iter().chain(std::iter::repeat(&0)).take(16))
+                .flat_map(|word| word.to_le_bytes());
+            format!("{:x}", Sha256::digest(words.collect::<Vec<_>>()))
+        };
+        let first = hash("frame_*.png").expect("enough frames");
+        assert_eq!(first.src_path(), dir);
+        assert_eq!(first.duration(), 60);
+        assert!(first.hash.iter().any(|&word| word != 0));
+
+        // If this changes, hashes of real videos have changed too.
+        assert_eq!(
+            golden(&first),
+            "54e4ad2d27beb01543dfc50976b01debbea6af09492594fe7de4f3f8742d33d9"
+        );
+        assert_eq!(hash("frame_*.png").expect("enough frames"), first);
+
+        // Only frames 0-9 match.
+        assert!(matches!(hash("frame_00?.png"), Err(Error::NotEnoughFrames)));
+
+        // Frames after the 16th are never loaded, but an unreadable frame before it is an error.
+        std::fs::write(dir.join("frame_019.png"), "not a png").expect("write frame");
+        assert_eq!(hash("frame_*.png").expect("enough frames"), first);
+        std::fs::write(dir.join("frame_000.png"), "not a png").expect("write frame");
+        assert!(matches!(hash("frame_*.png"), Err(Error::VidProc(_))));
+        assert!(matches!(hash("frame_*.png"), Err(Error::VidProc(_))));
+    }
+
+    #[test]
+    fn test_unreliable_bits_of_flat_videos_are_ignored() {
+        let opts = CreationOptions {
+            reliability_mask: true,
+            ..opts()
+        };
+        let hash = |name: &str, frames: Vec<GrayImage>| {
+            hash_from_frame_iter(
+                PathBuf::from(name),
+                Duration::from_secs(60),
+                frames,
+                opts,
+                None,
+            )
+            .expect("enough frames")
+        };
+        let flat = |level| vec![GrayImage::from_pixel(32, 24, Luma([level])); DCT_SIZE as usize];
+
+        //a copy of a video, but a little brighter, as if it had been re-encoded.
+        let original = gen_frames::<SyntheticVideo>(Path::new("60.0"), opts, false, DURATION_CHECK)
+            .expect("synthetic video");
+        let mut copy = original.clone();
+        for pix in copy.iter_mut().flat_map(|frame| frame.pixels_mut()) {
+            pix.0[0] = pix.0[0].saturating_add(3);
+        }
+
+        let hashes = vec![
+            hash("flat_a", flat(30)),
+            hash("flat_b", flat(90)),
+            hash("original", original),
+            hash("copy", copy),
+        ];
+        assert!(hashes.iter().all(VideoHash::has_reliability_mask));
+
+        let groups = |ignore_unreliable_bits| {
+            let opts = SearchOptions {
+                ignore_unreliable_bits,
+                ..SearchOptions::default()
+            };
+            search_with_opts(hashes.clone(), &opts)
+                .iter()
+                .map(|group| {
+                    group
+                        .duplicates()
+                        .map(|path| path.to_string_lossy().into_owned())
+                        .collect::<Vec<_>>()
+                })
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(groups(false), [["copy", "original"], ["flat_a", "flat_b"]]);
+        assert_eq!(groups(true), [["copy", "original"]]);
+    }
+
+    // A dark scene with no detail fading in from the given brightness, in a 16:9 frame with black
+    // borders leaving a picture of the given width and height in the middle.
+    fn boxed_fade(start: u8, (width, height): (u32, u32)) -> Vec<GrayImage> {
+        let (frame_width, frame_height) = (128, 72);
+        let (left, top) = ((frame_width - width) / 2, (frame_height - height) / 2);
+        (0..DCT_SIZE)
+            .map(|t| {
+                GrayImage::from_fn(frame_width, frame_height, |x, y| {
+                    if (left..left + width).contains(&x) && (top..top + height).contains(&y) {
+                        Luma([start + 3 * t as u8])
+                    } else {
+                        Luma([0])
+                    }
+                })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_active_area_guard_rejects_differently_boxed_videos() {
+        let hash = |name: &str, frames: Vec<GrayImage>| {
+            let duration = Duration::from_secs(60);
+            hash_from_frame_iter(PathBuf::from(name), duration, frames, opts(), None)
+                .expect("enough frames")
+        };
+
+        //4:3 pictures pillarboxed in 16:9 frames, and a copy of the first.
+        let pillarboxed = hash("pillarboxed", boxed_fade(30, (96, 72)));
+        let copy = hash("copy", boxed_fade(32, (96, 72)));
+        //unrelated scenes, letterboxed to a wide strip, and boxed into a small window.
+        let letterboxed = hash("letterboxed", boxed_fade(60, (128, 36)));
+        let windowed_a = hash("windowed_a", boxed_fade(40, (48, 36)));
+        let windowed_b = hash("windowed_b", boxed_fade(70, (48, 36)));
+
+        let area = |hash: &VideoHash| hash.active_area().expect("recorded when hashing");
+        assert_eq!(area(&pillarboxed), 0.75);
+        assert_eq!(area(&letterboxed), 0.5);
+        assert_eq!(area(&windowed_a), 0.188);
+
+        let hashes = vec![pillarboxed, copy, letterboxed, windowed_a, windowed_b];
+        let groups = |active_area| {
+            let opts = SearchOptions {
+                active_area,
+                grouping: GroupingMode::Cliques { min_mutual: 1.0 },
+                ..SearchOptions::default()
+            };
+            search_with_opts(hashes.clone(), &opts)
+                .iter()
+                .map(|group| {
+                    group
+                        .duplicates()
+                        .map(|path| path.to_string_lossy().into_owned())
+                        .collect::<Vec<_>>()
+                })
+                .collect::<Vec<_>>()
+        };
+
+        //most of each hash describes the borders, so every video matches every other...
+        assert_eq!(
+            groups(None),
+            [[
+                "copy",
+                "letterboxed",
+                "pillarboxed",
+                "windowed_a",
+                "windowed_b"
+            ]]
+        );
+
+        //...until the guard stops differently boxed videos, and those that are mostly border, from
+        //matching. The copy with the same borders still matches.
+        assert_eq!(
+            groups(Some(ActiveAreaGuard::new(0.3))),
+            [["copy", "pillarboxed"]]
+        );
+        assert_eq!(
+            explain_non_match(
+                &hashes[0],
+                &hashes[2],
+                &SearchOptions {
+                    active_area: Some(ActiveAreaGuard::new(0.3)),
+                    ..SearchOptions::default()
+                }
+            ),
+            NonMatchReason::ActiveAreaMismatch { a: 0.75, b: 0.5 }
+        );
+    }
+
+    #[test]
+    fn test_speed_variants_match_sped_up_copies() {
+        let hash = |name: &str, speed_variants| {
+            let opts = CreationOptions {
+                speed_variants,
+                ..opts()
+            };
+            gen_hash::<SyntheticVideo>(PathBuf::from(name), opts, None, DURATION_CHECK)
+                .expect("synthetic video")
+        };
+        let search = |hashes: &[VideoHash], speed_variants| {
+            let opts = SearchOptions {
+                speed_variants,
+                ..SearchOptions::default()
+            };
+            search_with_opts(hashes.to_vec(), &opts)
+        };
+
+        //"30.0" shows everything that "60.0" does, at twice the speed. "30.0=0.0" is as long, but
+        //shows something else.
+        let original = hash("60.0", SpeedFactors::ALL);
+        let sped_up = hash("30.0", SpeedFactors::NONE);
+        let unrelated = hash("30.0=0.0", SpeedFactors::NONE);
+        assert_eq!(original.speed_variants(), SpeedFactors::ALL);
+        assert_eq!(
+            original.clone().with_speed_variants(vec![]),
+            hash("60.0", SpeedFactors::NONE)
+        );
+
+        let hashes = [original.clone(), sped_up.clone(), unrelated];
+        assert!(search(&hashes, false).is_empty());
+        let groups = search(&hashes, true);
+        assert_eq!(groups.len(), 1);
+        assert_eq!(
+            groups[0].contained_paths().sorted().collect::<Vec<_>>(),
+            [Path::new("30.0"), Path::new("60.0")]
+        );
+        assert_eq!(groups[0].speed_factor("60.0", "30.0"), Some(2.0));
+        assert_eq!(groups[0].speed_factor("30.0", "60.0"), Some(0.5));
+
+        let explain = |speed_variants| {
+            let opts = SearchOptions {
+                speed_variants,
+                ..SearchOptions::default()
+            };
+            explain_non_match(&original, &sped_up, &opts)
+        };
+        assert_eq!(explain(false), NonMatchReason::DurationGate { delta: 30 });
+        assert_eq!(explain(true), NonMatchReason::Matches);
+
+        //the variants of either video are compared, so a copy at half speed of the shorter video
+        //matches the longer one.
+        let slowed = hash("30.0", SpeedFactors::NONE.with(SpeedFactor::Half));
+        let groups = search(&[hash("60.0", SpeedFactors::NONE), slowed], true);
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].speed_factor("60.0", "30.0"), Some(2.0));
+
+        //copies at the same speed are matched as usual.
+        let groups = search(&[original, hash("60.0@30", SpeedFactors::NONE)], true);
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].speed_factor("60.0", "60.0@30"), None);
+    }
+
+    #[test]
+    fn test_normalization_makes_dark_copies_match() {
+        //a dark, low contrast scene slowly panning across some texture...
+        let frames = (0..DCT_SIZE)
+            .map(|t| {
+                GrayImage::from_fn(96, 64, |x, y| {
+                    let (x, y, t) = (f64::from(x), f64::from(y), f64::from(t));
+                    let texture = (x * 0.21 + t * 0.13).sin() * (y * 0.17 - t * 0.07).cos()
+                        + (x * 0.05 + y * 0.09 + t * 0.03).sin();
+                    Luma([(14.0 + 6.0 * texture).round() as u8])
+                })
+            })
+            .collect::<Vec<_>>();
+        //...and a re-encode of it with lifted, gamma-adjusted shadows.
+        let lifted = frames
+            .iter()
+            .map(|frame| {
+                let mut frame = frame.clone();
+                for pixel in frame.pixels_mut() {
+                    let v = f64::from(pixel.0[0]) / 255.0;
+                    pixel.0[0] = (255.0 * v.powf(0.6) + 6.0).round() as u8;
+                }
+                frame
+            })
+            .collect::<Vec<_>>();
+
+        //under a strict tolerance the copy only matches once both are normalized.
+        let search_opts =
+            SearchOptions::new(Tolerance::new(0.1).expect("0.1 is a valid tolerance"));
+        let matches = |normalization| {
+            let opts = CreationOptions {
+                normalization,
+                ..opts()
+            };
+            let hash = |name: &str, frames: &[GrayImage]| {
+                let duration = Duration::from_secs(60);
+                hash_from_frame_iter(PathBuf::from(name), duration, frames.to_vec(), opts, None)
+                    .expect("enough frames")
+            };
+            let hashes = vec![hash("original", &frames), hash("copy", &lifted)];
+            assert!(hashes.iter().all(|h| h.normalization() == normalization));
+            !search_with_opts(hashes, &search_opts).is_empty()
+        };
+
+        assert!(!matches(Normalization::None));
+        assert!(matches(Normalization::HistogramEqualize));
+
+        //invalid percentiles are rejected before any frames are hashed.
+        let opts = CreationOptions {
+            normalization: Normalization::ContrastStretch {
+                low_pct: 60.0,
+                high_pct: 40.0,
+            },
+            ..opts()
+        };
+        let result = hash_from_frame_iter(
+            PathBuf::from("invalid"),
+            Duration::from_secs(60),
+            frames.clone(),
+            opts,
+            None,
+        );
+        assert!(
+            matches!(
+                result,
+                Err(Error::InvalidOptions(
+                    CreationOptionsError::InvalidContrastStretch { .. }
+                ))
+            ),
+            "{result:?}"
+        );
+    }
+
+    #[test]
+    fn test_frame_preprocessor_is_recorded_as_custom() {
+        let frames = (0..DCT_SIZE)
+            .map(|t| GrayImage::from_fn(32, 32, |x, y| Luma([((x * y + t) % 256) as u8])))
+            .collect::<Vec<_>>();
+        let seen = Arc::new(AtomicUsize::new(0));
+        let preprocessor: FramePreprocessor = {
+            let seen = Arc::clone(&seen);
+            Arc::new(move |frame| {
+                seen.fetch_add(1, Ordering::Relaxed);
+                frame
+            })
+        };
+
+        //the preprocessor replaces whatever normalization was chosen.
+        let opts = CreationOptions {
+            normalization: Normalization::HistogramEqualize,
+            ..opts()
+        };
+        let hash = hash_from_frame_iter(
+            PathBuf::from("custom"),
+            Duration::from_secs(60),
+            frames,
+            opts,
+            Some(&preprocessor),
+        )
+        .expect("enough frames");
+        assert_eq!(hash.normalization(), Normalization::Custom);
+        assert_eq!(seen.load(Ordering::Relaxed), DCT_SIZE as usize);
+    }
+
+    #[test]
+    fn test_audio_fingerprints_confirm_matches() {
+        let hash = |name: &str, audio_fingerprint| {
+            let opts = CreationOptions {
+                audio_fingerprint,
+                ..opts()
+            };
+            gen_hash::<SyntheticVideo>(PathBuf::from(name), opts, None, DURATION_CHECK)
+                .expect("synthetic video")
+        };
+        assert!(hash("20.0#1", false).audio_fingerprint().is_none());
+
+        //the videos all look the same, but only the first two sound the same.
+        let hashes = ["20.0#1", "20.0#1n", "20.0#2", "20.0"].map(|name| hash(name, true));
+        assert!(hashes[3].audio_fingerprint().is_none());
+        assert_eq!(hashes[0].audio_agrees(&hashes[1]), Some(true));
+        assert_eq!(hashes[0].audio_agrees(&hashes[2]), Some(false));
+        assert_eq!(hashes[0].audio_agrees(&hashes[3]), None);
+
+        let search = |hashes: &[VideoHash], audio_check| {
+            let opts = SearchOptions {
+                audio_check,
+                ..SearchOptions::default()
+            };
+            search_with_opts(hashes.to_vec(), &opts)
+        };
+
+        //annotating does not change which videos match.
+        let groups = search(&hashes, AudioCheck::Annotate);
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].contained_paths().count(), 4);
+        assert_eq!(groups[0].audio_agrees("20.0#1n", "20.0#1"), Some(true));
+        assert_eq!(groups[0].audio_agrees("20.0#1", "20.0#2"), Some(false));
+        assert_eq!(groups[0].audio_agrees("20.0#1", "20.0"), None);
+        let groups = search(&hashes, AudioCheck::Off);
+        assert_eq!(groups[0].audio_agrees("20.0#1", "20.0#1n"), None);
+
+        let groups = search(&hashes[..3], AudioCheck::RequireAgreement);
+        assert_eq!(groups.len(), 1);
+        assert_eq!(
+            groups[0].contained_paths().collect::<Vec<_>>(),
+            [Path::new("20.0#1"), Path::new("20.0#1n")]
+        );
+    }
 }
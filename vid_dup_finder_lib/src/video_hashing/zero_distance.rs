@@ -0,0 +1,235 @@
+//! Checking whether videos with identical hashes really show the same frames.
+//!
+//! Very short or low-detail videos (such as two different clips of black frames) can have exactly
+//! the same hash, so a reported distance of 0.0 does not always mean that they are copies of each
+//! other. Searching with [`SearchOptions::zero_distance_check`][crate::SearchOptions::zero_distance_check]
+//! set decodes a few frames of each such pair again and compares their pixels, and records the
+//! outcome in the group (see [`MatchGroup::zero_distance_verification`][crate::MatchGroup::zero_distance_verification]).
+use image::GrayImage;
+
+use crate::VideoHash;
+
+/// The number of frames of each video compared by a verification.
+pub const VERIFIED_FRAMES: usize = 4;
+
+//Frames whose pixels differ by at most this much on average (out of 255) are treated as the same,
+//so that copies which were encoded differently are still confirmed.
+const MAX_MEAN_PIXEL_DIFFERENCE: f64 = 4.0;
+
+/// The outcome of checking the frames of a pair of videos whose hashes are (almost) identical.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ZeroDistanceVerification {
+    /// The compared frames of the two videos are the same, allowing for encoding noise.
+    ConfirmedIdenticalFrames,
+    /// The compared frames differ, so the videos are probably different content that happens to
+    /// have the same hash. They should not be deleted without being looked at.
+    FramesDiffer,
+    /// The frames of one of the videos could not be decoded, so the pair was not checked.
+    NotChecked,
+}
+
+// Compare the frames of two videos, as returned by sampled_frames.
+pub(crate) fn compare_frames(
+    frames1: Option<&[GrayImage]>,
+    frames2: Option<&[GrayImage]>,
+) -> ZeroDistanceVerification {
+    let (Some(frames1), Some(frames2)) = (frames1, frames2) else {
+        return ZeroDistanceVerification::NotChecked;
+    };
+    let comparable = !frames1.is_empty()
+        && frames1.len() == frames2.len()
+        && frames1
+            .iter()
+            .zip(frames2)
+            .all(|(f1, f2)| f1.dimensions() == f2.dimensions());
+    if !comparable {
+        return ZeroDistanceVerification::NotChecked;
+    }
+
+    let (total, pixels) = frames1
+        .iter()
+        .zip(frames2)
+        .flat_map(|(f1, f2)| f1.as_raw().iter().zip(f2.as_raw()))
+        .fold((0u64, 0u64), |(total, pixels), (p1, p2)| {
+            (total + u64::from(p1.abs_diff(*p2)), pixels + 1)
+        });
+    if total as f64 <= MAX_MEAN_PIXEL_DIFFERENCE * pixels as f64 {
+        ZeroDistanceVerification::ConfirmedIdenticalFrames
+    } else {
+        ZeroDistanceVerification::FramesDiffer
+    }
+}
+
+// VERIFIED_FRAMES evenly spaced frames out of those that the hash was made from, shrunk to the size
+// that they are hashed at. None if the video cannot be decoded.
+#[cfg(any(feature = "ffmpeg_backend", feature = "gstreamer_backend"))]
+pub(crate) fn sampled_frames(hash: &VideoHash) -> Option<Vec<GrayImage>> {
+    #[cfg(feature = "ffmpeg_backend")]
+    use crate::ffmpeg_builder::VideoHashBuilder;
+    #[cfg(all(feature = "gstreamer_backend", not(feature = "ffmpeg_backend")))]
+    use crate::gstreamer_builder::VideoHashBuilder;
+
+    let window = hash.sampling_window();
+    let opts = crate::CreationOptions {
+        skip_forward_amount: window.skip_forward().as_secs_f64(),
+        duration: window.duration().as_secs_f64(),
+        coverage: hash.coverage(),
+        ignore_pixel_aspect_ratio: hash.pixel_aspect_ratio().is_none(),
+        deinterlace: if hash.deinterlaced() {
+            crate::Deinterlace::Force
+        } else {
+            crate::Deinterlace::Off
+        },
+        min_duration: 0.0,
+        ..crate::CreationOptions::default()
+    };
+    let frames = VideoHashBuilder::from_options(opts)
+        .with_resized_frames(true)
+        .frames(hash.src_path())
+        .ok()?
+        .collect::<Result<Vec<_>, _>>()
+        .ok()?;
+
+    let step = (frames.len() / VERIFIED_FRAMES).max(1);
+    Some(
+        frames
+            .into_iter()
+            .step_by(step)
+            .take(VERIFIED_FRAMES)
+            .collect(),
+    )
+}
+
+#[cfg(not(any(feature = "ffmpeg_backend", feature = "gstreamer_backend")))]
+pub(crate) fn sampled_frames(_hash: &VideoHash) -> Option<Vec<GrayImage>> {
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::HashMap, path::PathBuf, time::Duration};
+
+    use image::Luma;
+
+    use super::*;
+    use crate::{
+        search_with_opts, video_hashing::video_hash_builder::hash_from_frame_iter, CreationOptions,
+        Cropdetect, MatchGroup, SearchOptions, ZeroDistanceCheck,
+    };
+
+    // A clip of frames of a single brightness.
+    fn flat_frames(brightness: u8) -> Vec<GrayImage> {
+        vec![GrayImage::from_pixel(64, 48, Luma([brightness])); 64]
+    }
+
+    fn hash(name: &str, frames: Vec<GrayImage>) -> VideoHash {
+        let opts = CreationOptions {
+            cropdetect: Cropdetect::None,
+            ..CreationOptions::default()
+        };
+        hash_from_frame_iter(
+            PathBuf::from(name),
+            Duration::from_secs(10),
+            frames,
+            opts,
+            None,
+        )
+        .expect("frames can be hashed")
+    }
+
+    #[test]
+    fn test_distinct_black_videos_are_told_apart() {
+        let black = flat_frames(0);
+        let dark_grey = flat_frames(40);
+        let videos = HashMap::from([
+            ("black.mp4", black.clone()),
+            ("black_copy.mp4", black),
+            ("dark_grey.mp4", dark_grey),
+        ]);
+        let hashes = videos
+            .iter()
+            .map(|(name, frames)| (PathBuf::from(name), hash(name, frames.clone())))
+            .collect::<HashMap<_, _>>();
+
+        //the hashes cannot tell the videos apart.
+        let black = &hashes[&PathBuf::from("black.mp4")];
+        let dark_grey = &hashes[&PathBuf::from("dark_grey.mp4")];
+        assert_eq!(black.hamming_distance(dark_grey), Ok(0));
+
+        let group =
+            MatchGroup::new(["black.mp4", "black_copy.mp4", "dark_grey.mp4"].map(PathBuf::from))
+                .expect("enough entries")
+                .with_zero_distance_verification(&hashes, 0.0, |hash| {
+                    let name = hash.src_path().to_str().expect("utf8 path");
+                    Some(videos[name][..VERIFIED_FRAMES].to_vec())
+                });
+
+        use ZeroDistanceVerification::*;
+        let verified = |a, b| group.zero_distance_verification(a, b);
+        assert_eq!(
+            verified("black.mp4", "black_copy.mp4"),
+            Some(ConfirmedIdenticalFrames)
+        );
+        assert_eq!(verified("dark_grey.mp4", "black.mp4"), Some(FramesDiffer));
+        assert_eq!(
+            verified("black_copy.mp4", "dark_grey.mp4"),
+            Some(FramesDiffer)
+        );
+        assert!(group.frames_differ("dark_grey.mp4"));
+        assert!(group.frames_differ("black.mp4"));
+
+        //only the copies of black frames can be deleted automatically.
+        let confirmed = MatchGroup::new(["black.mp4", "black_copy.mp4"].map(PathBuf::from))
+            .expect("enough entries")
+            .with_zero_distance_verification(&hashes, 0.0, |hash| {
+                let name = hash.src_path().to_str().expect("utf8 path");
+                Some(videos[name].clone())
+            });
+        assert!(!confirmed.frames_differ("black.mp4"));
+    }
+
+    #[test]
+    fn test_pairs_are_only_verified_when_asked() {
+        let hashes = [
+            hash("a.mp4", flat_frames(0)),
+            hash("b.mp4", flat_frames(90)),
+        ];
+
+        //neither video exists, so their frames cannot be checked.
+        let opts = SearchOptions {
+            zero_distance_check: ZeroDistanceCheck::Verify { epsilon: 0.0 },
+            ..SearchOptions::default()
+        };
+        let groups = search_with_opts(hashes.clone(), &opts);
+        assert_eq!(
+            groups[0].zero_distance_verification("a.mp4", "b.mp4"),
+            Some(ZeroDistanceVerification::NotChecked)
+        );
+        assert!(!groups[0].frames_differ("a.mp4"));
+
+        let groups = search_with_opts(hashes, &SearchOptions::default());
+        assert_eq!(groups[0].zero_distance_verification("a.mp4", "b.mp4"), None);
+    }
+
+    #[test]
+    fn test_frames_which_cannot_be_compared() {
+        let frames = flat_frames(0);
+        let smaller = vec![GrayImage::new(32, 32); 64];
+
+        use ZeroDistanceVerification::*;
+        assert_eq!(compare_frames(Some(&frames), None), NotChecked);
+        assert_eq!(compare_frames(Some(&frames), Some(&smaller)), NotChecked);
+        assert_eq!(compare_frames(Some(&[]), Some(&[])), NotChecked);
+        assert_eq!(
+            compare_frames(Some(&frames), Some(&frames)),
+            ConfirmedIdenticalFrames
+        );
+
+        //a little noise is allowed.
+        let noisy = flat_frames(3);
+        assert_eq!(
+            compare_frames(Some(&frames), Some(&noisy)),
+            ConfirmedIdenticalFrames
+        );
+    }
+}
@@ -0,0 +1,7 @@
+use std::path::PathBuf;
+
+pub fn example_vid(filename: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("examples/vids")
+        .join(filename)
+}
@@ -0,0 +1,71 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use vid_dup_finder_lib::ffmpeg_builder::VideoHashBuilder;
+use vid_dup_finder_lib::*;
+
+mod common;
+use common::example_vid;
+
+// Tunes which change note every quarter or third of a second. The example videos have no audio, so
+// one of these is added to each copy.
+const TUNE: &str = "0.5*sin(2*PI*(220+110*floor(4*t-8*floor(t/2)))*t)";
+const OTHER_TUNE: &str = "0.5*sin(2*PI*(330+70*floor(3*t-7*floor(3*t/7)))*t)";
+
+// A copy of the video with the tune as its soundtrack, encoded at the given audio bitrate.
+fn with_audio(src: &Path, dir: &Path, name: &str, tune: &str, bitrate: &str) -> PathBuf {
+    std::fs::create_dir_all(dir).expect("can create test dir");
+    let dst = dir.join(name);
+
+    let status = Command::new("ffmpeg")
+        .args(["-y", "-loglevel", "error", "-i"])
+        .arg(src)
+        .args(["-f", "lavfi", "-i", &format!("aevalsrc={tune}:s=44100")])
+        .args(["-map", "0:v", "-map", "1:a", "-c:v", "copy"])
+        .args(["-c:a", "aac", "-b:a", bitrate, "-shortest"])
+        .arg(&dst)
+        .status()
+        .expect("ffmpeg is installed");
+    assert!(
+        status.success(),
+        "ffmpeg failed to add audio to {}",
+        src.display()
+    );
+
+    dst
+}
+
+fn hash(path: PathBuf) -> VideoHash {
+    let opts = CreationOptions {
+        audio_fingerprint: true,
+        ..CreationOptions::default()
+    };
+    VideoHashBuilder::from_options(opts)
+        .hash(path)
+        .expect("video can be hashed")
+}
+
+#[test]
+fn test_audio_fingerprints_of_reencoded_audio_agree() {
+    vid_dup_finder_lib::init().expect("ffmpeg and ffprobe are installed");
+
+    let temp_dir = tempfile::tempdir().expect("can create temp dir");
+    let dir = temp_dir.path();
+    let cat = example_vid("cat.1.mp4");
+    let low = hash(with_audio(&cat, dir, "low.mp4", TUNE, "32k"));
+    let high = hash(with_audio(&cat, dir, "high.mp4", TUNE, "128k"));
+    let other = hash(with_audio(
+        &example_vid("dog.1.mp4"),
+        dir,
+        "other.mp4",
+        OTHER_TUNE,
+        "128k",
+    ));
+    let silent = hash(cat);
+
+    assert!(low.audio_fingerprint().is_some());
+    assert!(silent.audio_fingerprint().is_none());
+    assert_eq!(low.audio_agrees(&high), Some(true));
+    assert_eq!(low.audio_agrees(&other), Some(false));
+    assert_eq!(high.audio_agrees(&silent), None);
+}
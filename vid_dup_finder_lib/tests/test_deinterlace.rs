@@ -0,0 +1,75 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use vid_dup_finder_lib::ffmpeg_builder::VideoHashBuilder;
+use vid_dup_finder_lib::*;
+
+mod common;
+use common::example_vid;
+
+// An interlaced copy of the video, like a broadcast capture. Each frame is woven from two fields:
+// the top field from the picture, and the bottom field from the same picture shifted 16 pixels to
+// the left, as if it had been captured a moment later while the camera panned. The fields are
+// half height, so the woven frames are the size of the original.
+fn interlaced_copy(src: &Path, dir: &Path) -> PathBuf {
+    std::fs::create_dir_all(dir).expect("can create test dir");
+    let dst = dir.join("interlaced.mp4");
+
+    let filter = "[0:v]fps=25,settb=1/100,scale=iw:trunc(ih/4)*2,split[top][bottom];\
+                  [bottom]crop=iw-16:ih:16:0,pad=iw+16:ih[shifted];\
+                  [top]setpts=4*N[top_field];\
+                  [shifted]setpts=4*N+2[bottom_field];\
+                  [top_field][bottom_field]interleave,weave=first_field=top,setfield=tff";
+
+    let status = Command::new("ffmpeg")
+        .args(["-y", "-loglevel", "error", "-i"])
+        .arg(src)
+        .args(["-filter_complex", filter])
+        .args(["-flags", "+ilme+ildct", "-field_order", "tt", "-an"])
+        .arg(&dst)
+        .status()
+        .expect("ffmpeg is installed");
+    assert!(
+        status.success(),
+        "ffmpeg failed to interlace {}",
+        src.display()
+    );
+
+    dst
+}
+
+fn hash(path: PathBuf, deinterlace: Deinterlace) -> VideoHash {
+    let opts = CreationOptions {
+        deinterlace,
+        ..CreationOptions::default()
+    };
+    VideoHashBuilder::from_options(opts)
+        .hash(path)
+        .expect("video can be hashed")
+}
+
+#[test]
+fn test_deinterlaced_copy_is_nearer_to_original() {
+    vid_dup_finder_lib::init().expect("ffmpeg and ffprobe are installed");
+
+    let temp_dir = tempfile::tempdir().expect("can create temp dir");
+    let dir = temp_dir.path();
+    let progressive = hash(example_vid("cat.1.mp4"), Deinterlace::Off);
+    let interlaced = interlaced_copy(&example_vid("cat.1.mp4"), dir);
+    let combed = hash(interlaced.clone(), Deinterlace::Off);
+    let deinterlaced = hash(interlaced, Deinterlace::Auto);
+
+    assert!(!combed.deinterlaced());
+    assert!(deinterlaced.deinterlaced());
+
+    let distance = |hash: &VideoHash| progressive.hamming_distance(hash).expect("same size");
+    assert!(
+        distance(&deinterlaced) < distance(&combed),
+        "deinterlaced: {}, combed: {}",
+        distance(&deinterlaced),
+        distance(&combed)
+    );
+
+    //Auto leaves progressive videos alone.
+    assert!(!hash(example_vid("cat.1.mp4"), Deinterlace::Auto).deinterlaced());
+}
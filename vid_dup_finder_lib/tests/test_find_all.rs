@@ -41,7 +41,9 @@ impl HashesWithDistance {
         for pair in members.iter().permutations(2) {
             let hash_1 = pair[0];
             let hash_2 = pair[1];
-            let distance = hash_1.hamming_distance(hash_2);
+            let distance = hash_1
+                .hamming_distance(hash_2)
+                .expect("hashes are the same size");
             assert!(distance <= distance_from_start * 2);
         }
 
@@ -59,6 +61,11 @@ impl HashesWithDistance {
     }
 }
 
+#[cfg(feature = "test-util")]
+fn opts(tolerance: f64) -> SearchOptions {
+    SearchOptions::new(Tolerance::new(tolerance).expect("test tolerances are in range"))
+}
+
 /// a collection of HashesWithDistance, where each member of the collection is guaranteed (hopefully) to be
 #[cfg(feature = "test-util")]
 struct HashesWithDistanceSet {
@@ -151,9 +158,9 @@ fn test_find_dups_finds_a_known_group() {
     );
     let members = groups.all_members(&mut rng);
 
-    let dups = search(
+    let dups = search_with_opts(
         members,
-        (intragroup_distance * 2) as f64 / TOLERANCE_SCALING_FACTOR,
+        &opts((intragroup_distance * 2) as f64 / TOLERANCE_SCALING_FACTOR),
     );
 
     assert!(
@@ -204,9 +211,9 @@ fn test_find_dups_discriminates_by_duration() {
     all_hashes.extend(long_group);
 
     all_hashes.shuffle(&mut rng);
-    let mut dups = search(
+    let mut dups = search_with_opts(
         all_hashes,
-        (intragroup_distance * 2) as f64 / TOLERANCE_SCALING_FACTOR,
+        &opts((intragroup_distance * 2) as f64 / TOLERANCE_SCALING_FACTOR),
     );
 
     //sort the dups by len -- so that if everything worked the first group is the one with the shorter videos.
@@ -257,9 +264,9 @@ fn test_find_dups_discriminates_by_distance() {
     );
 
     let all_hashes = hash_groups.all_members(&mut rng);
-    let mut dups = search(
+    let mut dups = search_with_opts(
         all_hashes,
-        (intragroup_distance * 2) as f64 / TOLERANCE_SCALING_FACTOR,
+        &opts((intragroup_distance * 2) as f64 / TOLERANCE_SCALING_FACTOR),
     );
     dups.sort_by_key(MatchGroup::len);
 
@@ -290,10 +297,10 @@ fn test_find_with_refs() {
 
     let cand_hashes = hash_groups.all_members(&mut rng);
     assert_eq!(cand_hashes.len(), 100 + 110 + 120 + 130 + 140);
-    let dups = search_with_references(
+    let dups = search_with_references_with_opts(
         [start_hash.clone()],
         cand_hashes.clone(),
-        intragroup_distance as f64 / TOLERANCE_SCALING_FACTOR,
+        &opts(intragroup_distance as f64 / TOLERANCE_SCALING_FACTOR),
     );
 
     assert_eq!(dups.len(), 1);
@@ -304,10 +311,10 @@ fn test_find_with_refs() {
         hash_groups.groups()[0].start_hash.clone(),
         hash_groups.groups()[4].start_hash.clone(),
     ];
-    let dups2 = search_with_references(
+    let dups2 = search_with_references_with_opts(
         start_hashes,
         cand_hashes,
-        intragroup_distance as f64 / TOLERANCE_SCALING_FACTOR,
+        &opts(intragroup_distance as f64 / TOLERANCE_SCALING_FACTOR),
     );
     assert_eq!(dups2.len(), 2);
     assert_eq!(dups2[0].len(), 100);
@@ -0,0 +1,63 @@
+use std::path::PathBuf;
+
+use vid_dup_finder_lib::fixtures::Fixture;
+use vid_dup_finder_lib::*;
+
+// Set to write the current hashes to the golden file instead of checking them.
+const UPDATE_VAR: &str = "VDF_UPDATE_GOLDEN_HASHES";
+
+fn golden_path() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/golden_hashes.jsonl")
+}
+
+// One hash per line in the portable format, for every fixture at every size.
+fn current_hashes() -> Vec<String> {
+    Fixture::ALL
+        .into_iter()
+        .flat_map(|fixture| HashSize::ALL.map(|size| fixture.hash(size).to_json_portable()))
+        .collect()
+}
+
+fn distance(a: Fixture, b: Fixture) -> f64 {
+    let (a, b) = (a.hash(HashSize::DEFAULT), b.hash(HashSize::DEFAULT));
+    let bits = a.hamming_distance(&b).expect("same size");
+    f64::from(bits) / f64::from(a.hash_size().bits())
+}
+
+#[test]
+fn test_hashes_match_golden_hashes() {
+    let current = current_hashes();
+    if std::env::var_os(UPDATE_VAR).is_some() {
+        std::fs::write(golden_path(), current.join("\n") + "\n").expect("can write golden hashes");
+        return;
+    }
+
+    let golden = std::fs::read_to_string(golden_path()).expect("golden hashes are checked in");
+    let golden = golden.lines().collect::<Vec<_>>();
+    assert_eq!(golden.len(), current.len(), "rerun with {UPDATE_VAR}=1");
+    for (golden, current) in golden.iter().zip(&current) {
+        assert_eq!(
+            VideoHash::from_json_portable(golden).expect("golden hashes are valid"),
+            VideoHash::from_json_portable(current).expect("round trips"),
+            "hash values have changed. If this is intended, rerun with {UPDATE_VAR}=1 and \
+             check in the new golden hashes"
+        );
+        assert_eq!(golden, current);
+    }
+}
+
+#[test]
+fn test_fixture_distances() {
+    use Fixture::*;
+
+    assert!(distance(MovingBox, ShiftedMovingBox) < 0.1);
+    assert!(distance(Noise, Gradient) > 0.4);
+    assert!(distance(Noise, Checkerboard) > 0.3);
+    assert!(distance(MovingBox, Checkerboard) > 0.3);
+
+    //fixtures are the same every time they are generated.
+    for fixture in Fixture::ALL {
+        assert_eq!(fixture.frames(), fixture.frames());
+        assert_eq!(distance(fixture, fixture), 0.0);
+    }
+}
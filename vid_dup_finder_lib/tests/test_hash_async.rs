@@ -0,0 +1,59 @@
+use std::num::NonZeroUsize;
+use std::time::Duration;
+
+use vid_dup_finder_lib::ffmpeg_builder::VideoHashBuilder;
+
+mod common;
+use common::example_vid;
+
+const EXAMPLE_VIDS: [&str; 4] = ["cat.1.mp4", "cat.2.mp4", "dog.1.mp4", "dog.2.mp4"];
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_concurrent_hashes_match_blocking_hashes() {
+    vid_dup_finder_lib::init().expect("ffmpeg and ffprobe are installed");
+
+    let builder = VideoHashBuilder::default()
+        .with_max_concurrency(NonZeroUsize::new(2).expect("2 is nonzero"));
+    let tasks = EXAMPLE_VIDS.map(|name| tokio::spawn(builder.hash_async(example_vid(name))));
+
+    for (name, task) in EXAMPLE_VIDS.into_iter().zip(tasks) {
+        let hash = task
+            .await
+            .expect("hashing task did not panic")
+            .expect("example video can be hashed");
+        let expected = builder
+            .hash(example_vid(name))
+            .expect("example video can be hashed");
+        assert_eq!(hash, expected, "{name}");
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_dropped_hashes_stop_decoding() {
+    vid_dup_finder_lib::init().expect("ffmpeg and ffprobe are installed");
+
+    //one video at a time, so the next hash can only start once the first decoder has stopped.
+    let builder = VideoHashBuilder::default()
+        .with_max_concurrency(NonZeroUsize::new(1).expect("1 is nonzero"));
+
+    let dropped = tokio::time::timeout(
+        Duration::from_millis(50),
+        builder.hash_async(example_vid("cat.1.mp4")),
+    )
+    .await;
+    assert!(dropped.is_err(), "hashing finished before it was dropped");
+
+    let hash = tokio::time::timeout(
+        Duration::from_secs(60),
+        builder.hash_async(example_vid("dog.1.mp4")),
+    )
+    .await
+    .expect("the cancelled decode released its slot")
+    .expect("example video can be hashed");
+    assert_eq!(
+        hash,
+        builder
+            .hash(example_vid("dog.1.mp4"))
+            .expect("example video can be hashed")
+    );
+}
@@ -0,0 +1,65 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Duration;
+
+use vid_dup_finder_lib::ffmpeg_builder::VideoHashBuilder;
+use vid_dup_finder_lib::*;
+
+mod common;
+use common::example_vid;
+
+// A copy of the video with some black frames added to the start, like a broadcast capture.
+fn with_black_padding(src: &Path, dir: &Path, secs: f64) -> PathBuf {
+    std::fs::create_dir_all(dir).expect("can create test dir");
+    let dst = dir.join("padded.mp4");
+
+    let status = Command::new("ffmpeg")
+        .args(["-y", "-loglevel", "error", "-i"])
+        .arg(src)
+        .args([
+            "-vf",
+            &format!("tpad=start_duration={secs}:color=black"),
+            "-an",
+        ])
+        .arg(&dst)
+        .status()
+        .expect("ffmpeg is installed");
+    assert!(status.success(), "ffmpeg failed to pad {}", src.display());
+
+    dst
+}
+
+fn hash(path: PathBuf) -> VideoHash {
+    let opts = CreationOptions {
+        skip_leading_black: true,
+        ..CreationOptions::default()
+    };
+    VideoHashBuilder::from_options(opts)
+        .hash(path)
+        .expect("video can be hashed")
+}
+
+#[test]
+fn test_padded_copy_matches_original() {
+    vid_dup_finder_lib::init().expect("ffmpeg and ffprobe are installed");
+
+    let temp_dir = tempfile::tempdir().expect("can create temp dir");
+    let dir = temp_dir.path();
+    let original = hash(example_vid("cat.1.mp4"));
+    let padded = hash(with_black_padding(&example_vid("cat.1.mp4"), dir, 3.0));
+    let unrelated = hash(example_vid("dog.1.mp4"));
+
+    //the black frames are found to within the scanning framerate.
+    let lead_in = padded.leading_black().as_secs_f64();
+    assert!((2.5..=3.5).contains(&lead_in), "leading black: {lead_in}");
+    assert_eq!(original.leading_black(), Duration::ZERO);
+
+    let groups = search_with_opts(vec![original, padded, unrelated], &SearchOptions::default());
+    assert_eq!(groups.len(), 1);
+    let mut paths = groups[0]
+        .duplicates()
+        .map(|path| path.file_name().expect("is a file").to_string_lossy())
+        .collect::<Vec<_>>();
+    paths.sort();
+    assert_eq!(paths, ["cat.1.mp4", "padded.mp4"]);
+}
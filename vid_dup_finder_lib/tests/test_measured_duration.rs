@@ -0,0 +1,67 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use vid_dup_finder_lib::ffmpeg_builder::VideoHashBuilder;
+use vid_dup_finder_lib::*;
+
+mod common;
+use common::example_vid;
+
+// A fragmented copy of the video, written as a live stream would be: the moov box is written
+// before any frames, so it has no duration in it.
+fn fragmented(src: &Path, dir: &Path) -> PathBuf {
+    std::fs::create_dir_all(dir).expect("can create test dir");
+    let dst = dir.join("fragmented.mp4");
+
+    let status = Command::new("ffmpeg")
+        .args(["-y", "-loglevel", "error", "-i"])
+        .arg(src)
+        .args([
+            "-an",
+            "-movflags",
+            "frag_keyframe+empty_moov+default_base_moof",
+        ])
+        .arg(&dst)
+        .status()
+        .expect("ffmpeg is installed");
+    assert!(
+        status.success(),
+        "ffmpeg failed to fragment {}",
+        src.display()
+    );
+
+    dst
+}
+
+#[test]
+fn test_fragmented_copy_can_be_hashed() {
+    vid_dup_finder_lib::init().expect("ffmpeg and ffprobe are installed");
+
+    let temp_dir = tempfile::tempdir().expect("can create temp dir");
+    let dir = temp_dir.path();
+    let builder = VideoHashBuilder::default();
+    let original = builder
+        .hash(example_vid("cat.1.mp4"))
+        .expect("video can be hashed");
+    let fragmented = builder
+        .hash(fragmented(&example_vid("cat.1.mp4"), dir))
+        .expect("fragmented video can be hashed");
+
+    //whether or not the duration had to be measured, it is the same as the original's.
+    let duration_diff = original.duration().abs_diff(fragmented.duration());
+    assert!(duration_diff <= 1, "duration differs by {duration_diff}s");
+    assert_eq!(original.duration_source(), DurationSource::Container);
+
+    //...and when measuring is turned off, a missing duration is an error.
+    let never_measure = VideoHashBuilder::from_options(CreationOptions {
+        max_measured_duration: 0.0,
+        ..CreationOptions::default()
+    });
+    match never_measure.hash(dir.join("fragmented.mp4")) {
+        Ok(hash) => assert_eq!(hash.duration_source(), DurationSource::Container),
+        Err(e) => assert!(matches!(e, Error::UnknownDuration), "{e}"),
+    }
+
+    let groups = search_with_opts(vec![original, fragmented], &SearchOptions::default());
+    assert_eq!(groups.len(), 1);
+}
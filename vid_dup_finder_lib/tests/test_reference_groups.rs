@@ -0,0 +1,128 @@
+use std::path::Path;
+
+use vid_dup_finder_lib::ffmpeg_builder::VideoHashBuilder;
+use vid_dup_finder_lib::*;
+
+mod common;
+use common::example_vid;
+
+fn hashes(filenames: &[&str]) -> Vec<VideoHash> {
+    let builder = VideoHashBuilder::default();
+    filenames
+        .iter()
+        .map(|filename| {
+            builder
+                .hash(example_vid(filename))
+                .expect("video can be hashed")
+        })
+        .collect()
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name().unwrap().to_string_lossy().into_owned()
+}
+
+// Each group as its reference (if any) and its sorted duplicates, in a stable order.
+fn summarize(groups: &[MatchGroup]) -> Vec<(Option<String>, Vec<String>)> {
+    let mut ret = groups
+        .iter()
+        .map(|group| {
+            let mut dups = group.duplicates().map(file_name).collect::<Vec<_>>();
+            dups.sort();
+            (group.reference().map(file_name), dups)
+        })
+        .collect::<Vec<_>>();
+    ret.sort();
+    ret
+}
+
+fn reference_search() -> Vec<MatchGroup> {
+    search_with_references_with_opts(
+        hashes(&["cat.1.mp4", "dog.1.mp4"]),
+        hashes(&["cat.2.mp4", "cat.3.webm", "dog.2.mp4", "dog.3.webm"]),
+        &SearchOptions::default(),
+    )
+}
+
+#[test]
+fn test_reference_search_groups_examples_by_reference() {
+    vid_dup_finder_lib::init().expect("ffmpeg and ffprobe are installed");
+
+    assert_eq!(
+        summarize(&reference_search()),
+        [
+            (
+                Some("cat.1.mp4".into()),
+                vec!["cat.2.mp4".into(), "cat.3.webm".into()]
+            ),
+            (
+                Some("dog.1.mp4".into()),
+                vec!["dog.2.mp4".into(), "dog.3.webm".into()]
+            ),
+        ]
+    );
+}
+
+#[test]
+fn test_cartesian_product_keeps_references() {
+    vid_dup_finder_lib::init().expect("ffmpeg and ffprobe are installed");
+
+    //each duplicate is paired with its own reference, never with another duplicate.
+    let pairs = reference_search()
+        .iter()
+        .flat_map(MatchGroup::dup_combinations)
+        .collect::<Vec<_>>();
+    assert_eq!(
+        summarize(&pairs),
+        [
+            (Some("cat.1.mp4".into()), vec!["cat.2.mp4".into()]),
+            (Some("cat.1.mp4".into()), vec!["cat.3.webm".into()]),
+            (Some("dog.1.mp4".into()), vec!["dog.2.mp4".into()]),
+            (Some("dog.1.mp4".into()), vec!["dog.3.webm".into()]),
+        ]
+    );
+
+    //without references, every video is paired with every other.
+    let all = hashes(&["cat.1.mp4", "cat.2.mp4", "cat.3.webm"]);
+    let pairs = search_with_opts(all, &SearchOptions::default())
+        .iter()
+        .flat_map(MatchGroup::dup_combinations)
+        .collect::<Vec<_>>();
+    assert_eq!(
+        summarize(&pairs),
+        [
+            (None, vec!["cat.1.mp4".into(), "cat.2.mp4".into()]),
+            (None, vec!["cat.1.mp4".into(), "cat.3.webm".into()]),
+            (None, vec!["cat.2.mp4".into(), "cat.3.webm".into()]),
+        ]
+    );
+}
+
+#[test]
+fn test_filtered_reference_groups_are_never_singletons() {
+    vid_dup_finder_lib::init().expect("ffmpeg and ffprobe are installed");
+
+    //rebuild each pair without the duplicates that are already known to match, as the app does
+    //when known matches are removed.
+    let known = example_vid("cat.2.mp4");
+    let filtered = reference_search()
+        .iter()
+        .flat_map(MatchGroup::dup_combinations)
+        .filter_map(|pair| {
+            let reference = pair.reference().expect("pairs keep their reference");
+            let dups = pair.duplicates().filter(|dup| *dup != known);
+            MatchGroup::new_with_reference(reference.to_path_buf(), dups.map(Path::to_path_buf))
+                .ok()
+        })
+        .collect::<Vec<_>>();
+
+    //the pair which only held the known match is gone, rather than left with just its reference.
+    assert_eq!(
+        summarize(&filtered),
+        [
+            (Some("cat.1.mp4".into()), vec!["cat.3.webm".into()]),
+            (Some("dog.1.mp4".into()), vec!["dog.2.mp4".into()]),
+            (Some("dog.1.mp4".into()), vec!["dog.3.webm".into()]),
+        ]
+    );
+}
@@ -0,0 +1,62 @@
+use vid_dup_finder_lib::ffmpeg_builder::VideoHashBuilder;
+use vid_dup_finder_lib::*;
+
+mod common;
+use common::example_vid;
+
+fn names(groups: &[MatchGroup]) -> Vec<Vec<String>> {
+    groups
+        .iter()
+        .map(|group| {
+            group
+                .duplicates()
+                .map(|path| {
+                    let name = path.file_name().expect("is a file");
+                    name.to_string_lossy().into_owned()
+                })
+                .collect()
+        })
+        .collect()
+}
+
+#[test]
+fn test_duplicates_still_match_when_ignoring_unreliable_bits() {
+    vid_dup_finder_lib::init().expect("ffmpeg and ffprobe are installed");
+
+    let opts = CreationOptions {
+        reliability_mask: true,
+        ..CreationOptions::default()
+    };
+    let builder = VideoHashBuilder::from_options(opts);
+    let hashes = [
+        "cat.1.mp4",
+        "cat.2.mp4",
+        "cat.3.webm",
+        "dog.1.mp4",
+        "dog.2.mp4",
+        "dog.3.webm",
+    ]
+    .map(|name| {
+        builder
+            .hash(example_vid(name))
+            .expect("example video can be hashed")
+    });
+    assert!(hashes.iter().all(VideoHash::has_reliability_mask));
+
+    let opts = SearchOptions {
+        ignore_unreliable_bits: true,
+        ..SearchOptions::default()
+    };
+    let groups = search_with_opts(hashes.clone(), &opts);
+    assert_eq!(
+        names(&groups),
+        [
+            ["cat.1.mp4", "cat.2.mp4", "cat.3.webm"],
+            ["dog.1.mp4", "dog.2.mp4", "dog.3.webm"]
+        ]
+    );
+    assert_eq!(
+        names(&groups),
+        names(&search_with_opts(hashes, &SearchOptions::default()))
+    );
+}
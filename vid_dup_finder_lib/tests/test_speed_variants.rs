@@ -0,0 +1,74 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use vid_dup_finder_lib::ffmpeg_builder::VideoHashBuilder;
+use vid_dup_finder_lib::*;
+
+mod common;
+use common::example_vid;
+
+// A copy of the video played at twice the speed, keeping the original frame rate.
+fn twice_as_fast(src: &Path, dir: &Path) -> PathBuf {
+    std::fs::create_dir_all(dir).expect("can create test dir");
+    let dst = dir.join("double.mp4");
+
+    let status = Command::new("ffmpeg")
+        .args(["-y", "-loglevel", "error", "-i"])
+        .arg(src)
+        .args(["-an", "-vf", "setpts=0.5*PTS", "-c:v", "libx264"])
+        .arg(&dst)
+        .status()
+        .expect("ffmpeg is installed");
+    assert!(
+        status.success(),
+        "ffmpeg failed to speed up {}",
+        src.display()
+    );
+
+    dst
+}
+
+fn hash(path: PathBuf, speed_variants: SpeedFactors) -> VideoHash {
+    let opts = CreationOptions {
+        speed_variants,
+        ..CreationOptions::default()
+    };
+    VideoHashBuilder::from_options(opts)
+        .hash(path)
+        .expect("video can be hashed")
+}
+
+fn groups(hashes: &[VideoHash], speed_variants: bool) -> Vec<MatchGroup> {
+    let opts = SearchOptions {
+        speed_variants,
+        ..SearchOptions::default()
+    };
+    search_with_opts(hashes.to_vec(), &opts)
+}
+
+#[test]
+fn test_sped_up_copies_match_with_speed_variants() {
+    vid_dup_finder_lib::init().expect("ffmpeg and ffprobe are installed");
+
+    let temp_dir = tempfile::tempdir().expect("can create temp dir");
+    let dir = temp_dir.path();
+    let cat = example_vid("cat.1.mp4");
+    let double = twice_as_fast(&cat, dir);
+
+    let plain = [
+        hash(cat.clone(), SpeedFactors::NONE),
+        hash(double.clone(), SpeedFactors::NONE),
+    ];
+    assert!(groups(&plain, true).is_empty());
+
+    let with_variants = [
+        hash(cat.clone(), SpeedFactors::ALL),
+        hash(double.clone(), SpeedFactors::NONE),
+    ];
+    assert!(groups(&with_variants, false).is_empty());
+
+    let matches = groups(&with_variants, true);
+    assert_eq!(matches.len(), 1);
+    assert_eq!(matches[0].speed_factor(&cat, &double), Some(2.0));
+    assert_eq!(matches[0].speed_factor(&double, &cat), Some(0.5));
+}
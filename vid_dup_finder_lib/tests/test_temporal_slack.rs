@@ -0,0 +1,40 @@
+use vid_dup_finder_lib::ffmpeg_builder::VideoHashBuilder;
+use vid_dup_finder_lib::*;
+
+mod common;
+use common::example_vid;
+
+// Reading frames from a second further into the video gives exactly the frames that a copy with
+// its first second cut off would give.
+fn hash(filename: &str, trimmed_secs: f64) -> VideoHash {
+    let opts = CreationOptions {
+        skip_forward_amount: DEFAULT_VID_HASH_SKIP_FORWARD + trimmed_secs,
+        frame_hashes: true,
+        ..CreationOptions::default()
+    };
+    VideoHashBuilder::from_options(opts)
+        .hash(example_vid(filename))
+        .expect("example video can be hashed")
+}
+
+#[test]
+fn test_trimmed_copy_matches_with_slack() {
+    vid_dup_finder_lib::init().expect("ffmpeg and ffprobe are installed");
+
+    let original = hash("cat.1.mp4", 0.0);
+    let trimmed = hash("cat.1.mp4", 1.0).with_src_path("cat.trimmed.mp4");
+    let unrelated = hash("dog.1.mp4", 0.0);
+    let hashes = vec![original, trimmed, unrelated];
+
+    //a second is 6.4 sampled frames.
+    let opts = SearchOptions::new(Tolerance::new(0.15).expect("0.15 is a valid tolerance"))
+        .temporal_slack(8);
+    let groups = search_with_opts(hashes, &opts);
+    assert_eq!(groups.len(), 1);
+    let mut paths = groups[0]
+        .duplicates()
+        .map(|path| path.file_name().expect("is a file").to_string_lossy())
+        .collect::<Vec<_>>();
+    paths.sort();
+    assert_eq!(paths, ["cat.1.mp4", "cat.trimmed.mp4"]);
+}
@@ -0,0 +1,40 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use vid_dup_finder_lib::ffmpeg_builder::VideoHashBuilder;
+use vid_dup_finder_lib::*;
+
+// An mp4 containing a single frame, like the "motion photos" taken by some phones.
+fn single_frame_video(dir: &Path) -> PathBuf {
+    std::fs::create_dir_all(dir).expect("can create test dir");
+    let dst = dir.join("motion_photo.mp4");
+
+    let status = Command::new("ffmpeg")
+        .args(["-y", "-loglevel", "error", "-f", "lavfi"])
+        .args(["-i", "testsrc=size=320x240:rate=25", "-frames:v", "1"])
+        .arg(&dst)
+        .status()
+        .expect("ffmpeg is installed");
+    assert!(
+        status.success(),
+        "ffmpeg failed to create {}",
+        dst.display()
+    );
+
+    dst
+}
+
+#[test]
+fn test_single_frame_video_is_too_short() {
+    vid_dup_finder_lib::init().expect("ffmpeg and ffprobe are installed");
+
+    let temp_dir = tempfile::tempdir().expect("can create temp dir");
+    let dir = temp_dir.path();
+    let src = single_frame_video(dir);
+
+    let result = VideoHashBuilder::default().hash(src);
+    assert!(
+        matches!(result, Err(Error::TooShort { duration }) if duration.as_secs_f64() < 1.0),
+        "{result:?}"
+    );
+}
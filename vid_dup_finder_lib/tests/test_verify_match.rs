@@ -0,0 +1,30 @@
+use vid_dup_finder_lib::*;
+
+mod common;
+use common::example_vid;
+
+#[test]
+fn test_video_verifies_against_itself() {
+    vid_dup_finder_lib::init().expect("ffmpeg and ffprobe are installed");
+
+    let opts = VerifyOpts::default();
+    let cat = example_vid("cat.1.mp4");
+    let report = verify_match(&cat, &cat, &opts).expect("video can be decoded");
+
+    assert_eq!(report.frames_compared(), opts.frames as usize);
+    assert!((report.mean_ssim() - 1.0).abs() < 1e-6, "{report:?}");
+    assert!(report.passed());
+}
+
+#[test]
+fn test_different_videos_fail_verification() {
+    vid_dup_finder_lib::init().expect("ffmpeg and ffprobe are installed");
+
+    let opts = VerifyOpts::default();
+    let report = verify_match(&example_vid("cat.1.mp4"), &example_vid("dog.1.mp4"), &opts)
+        .expect("videos can be decoded");
+
+    assert!(report.mean_ssim() < opts.threshold - 0.2, "{report:?}");
+    assert!(report.min_ssim() <= report.mean_ssim());
+    assert!(!report.passed());
+}
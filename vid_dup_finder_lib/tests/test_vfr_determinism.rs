@@ -0,0 +1,60 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use vid_dup_finder_lib::ffmpeg_builder::VideoHashBuilder;
+use vid_dup_finder_lib::*;
+
+mod common;
+use common::example_vid;
+
+// A copy of the video whose frames are irregularly spaced, like a screen recording.
+fn with_variable_framerate(src: &Path, dir: &Path) -> PathBuf {
+    std::fs::create_dir_all(dir).expect("can create test dir");
+    let dst = dir.join("vfr.mkv");
+
+    let status = Command::new("ffmpeg")
+        .args(["-y", "-loglevel", "error", "-i"])
+        .arg(src)
+        .args([
+            "-vf",
+            "setpts=(N+0.4*sin(N*1.7))/30/TB",
+            "-vsync",
+            "vfr",
+            "-an",
+        ])
+        .arg(&dst)
+        .status()
+        .expect("ffmpeg is installed");
+    assert!(
+        status.success(),
+        "ffmpeg failed to convert {}",
+        src.display()
+    );
+
+    dst
+}
+
+#[test]
+fn test_variable_framerate_hash_is_deterministic() {
+    vid_dup_finder_lib::init().expect("ffmpeg and ffprobe are installed");
+
+    let temp_dir = tempfile::tempdir().expect("can create temp dir");
+    let dir = temp_dir.path();
+    let vfr = with_variable_framerate(&example_vid("cat.1.mp4"), dir);
+    let builder = VideoHashBuilder::default();
+
+    let first = builder.hash(vfr.clone()).expect("video can be hashed");
+    for _ in 0..9 {
+        let hash = builder.hash(vfr.clone()).expect("video can be hashed");
+        assert_eq!(hash, first);
+    }
+
+    //and it is still a duplicate of the original.
+    let original = builder
+        .hash(example_vid("cat.1.mp4"))
+        .expect("video can be hashed");
+    assert_eq!(
+        search_with_opts(vec![original, first], &SearchOptions::default()).len(),
+        1
+    );
+}
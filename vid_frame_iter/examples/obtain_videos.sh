@@ -35,10 +35,17 @@ truncate_src_vids() {
   done
 }
 
+# A copy of one video whose container says it must be rotated by 90 degrees
+# (as phone cameras do). The frames themselves are unchanged.
+make_rotated_vid() {
+  ffmpeg -y -i $PROC_DIR/dog.1.mp4 -c copy -metadata:s:v:0 rotate=90 $PROC_DIR/dog.rotated.mp4
+}
+
 get_src_vid $DOG_SRC dog
 get_src_vid $CAT_SRC cat
 
 truncate_src_vids
+make_rotated_vid
 
 #remove source videos
 #rm -rf $SRC_DIR
\ No newline at end of file
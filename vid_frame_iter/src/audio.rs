@@ -0,0 +1,133 @@
+use gstreamer::{prelude::*, ClockTime, CoreError, SeekFlags};
+
+use crate::frame_iter::{change_state_blocking, get_bus_errors};
+use crate::FrameIterError;
+
+/// Decode up to `duration` seconds of the first audio stream at `uri`, starting `start_offset`
+/// seconds in. The audio is mixed down to a single channel of signed 16 bit samples at
+/// `sample_rate` samples per second.
+///
+/// Returns None if there are no audio streams. Returns fewer samples than requested if the audio
+/// ends first.
+pub fn decode_audio(
+    uri: impl AsRef<str>,
+    sample_rate: u32,
+    start_offset: Option<f64>,
+    duration: f64,
+) -> Result<Option<Vec<i16>>, glib::Error> {
+    let uri = uri.as_ref();
+    if !crate::mediainfo_utils::has_audio(uri)? {
+        return Ok(None);
+    }
+
+    let pipeline_desc = format!(
+        "uridecodebin name=decode uri=\"{uri}\" audioconvert name=audio_in ! audioresample ! appsink name=sink"
+    );
+    let pipeline = gstreamer::parse::launch(&pipeline_desc)?
+        .downcast::<gstreamer::Pipeline>()
+        .expect("Expected a gstreamer::Pipeline");
+
+    // Link the first audio stream, leaving any video streams unlinked.
+    let audio_in = pipeline
+        .by_name("audio_in")
+        .expect("audio_in element not found")
+        .static_pad("sink")
+        .expect("audioconvert always has a sink pad");
+    pipeline
+        .by_name("decode")
+        .expect("decode element not found")
+        .connect_pad_added(move |_decode, pad| {
+            let is_audio = pad.current_caps().is_some_and(|caps| {
+                caps.structure(0)
+                    .is_some_and(|s| s.name().starts_with("audio/"))
+            });
+            if is_audio && !audio_in.is_linked() {
+                // If this fails, the pipeline reports a not-linked error on the bus.
+                let _ = pad.link(&audio_in);
+            }
+        });
+
+    let appsink = pipeline
+        .by_name("sink")
+        .expect("Sink element not found")
+        .downcast::<gstreamer_app::AppSink>()
+        .expect("Sink element is expected to be an appsink!");
+    appsink.set_property("sync", false);
+    appsink.set_max_buffers(1);
+    appsink.set_drop(false);
+    appsink.set_caps(Some(
+        &gstreamer::Caps::builder("audio/x-raw")
+            .field("format", "S16LE")
+            .field("layout", "interleaved")
+            .field("channels", 1i32)
+            .field("rate", sample_rate as i32)
+            .build(),
+    ));
+
+    let wanted = (duration.max(0.0) * f64::from(sample_rate)) as usize;
+    let ret = pull_samples(&pipeline, &appsink, start_offset, wanted);
+
+    //the pipeline must be stopped to free it, whether or not decoding succeeded.
+    match change_state_blocking(&pipeline, gstreamer::State::Null) {
+        Ok(()) => ret.map(Some),
+        Err(e) => panic!("{e:?}"),
+    }
+}
+
+fn pull_samples(
+    pipeline: &gstreamer::Pipeline,
+    appsink: &gstreamer_app::AppSink,
+    start_offset: Option<f64>,
+    wanted: usize,
+) -> Result<Vec<i16>, glib::Error> {
+    let try_pull_sample_timeout = 30 * ClockTime::SECOND;
+    let bus = pipeline
+        .bus()
+        .expect("Failed to get pipeline from bus. Shouldn't happen!");
+
+    change_state_blocking(pipeline, gstreamer::State::Paused)?;
+    if let Some(offset) = start_offset {
+        let offset_ns = (offset * ClockTime::SECOND.nseconds() as f64) as u64;
+        pipeline
+            .seek_simple(
+                SeekFlags::ACCURATE.union(SeekFlags::FLUSH),
+                ClockTime::from_nseconds(offset_ns),
+            )
+            .map_err(|e| glib::Error::new(CoreError::TooLazy, &e.message))?;
+    }
+    change_state_blocking(pipeline, gstreamer::State::Playing)?;
+
+    let mut samples = Vec::with_capacity(wanted);
+    while samples.len() < wanted {
+        if let Some(e) = get_bus_errors(&bus).next() {
+            return Err(e);
+        }
+
+        let Some(sample) = appsink.try_pull_sample(try_pull_sample_timeout) else {
+            if let Some(e) = get_bus_errors(&bus).next() {
+                return Err(e);
+            }
+            if !appsink.is_eos() {
+                return Err(glib::Error::new(
+                    FrameIterError::Timeout,
+                    "Gstreamer timed out",
+                ));
+            }
+            break;
+        };
+
+        let Some(buffer) = sample.buffer() else {
+            continue;
+        };
+        let map = buffer
+            .map_readable()
+            .map_err(|e| glib::Error::new(CoreError::Failed, &e.message))?;
+        samples.extend(
+            map.chunks_exact(2)
+                .map(|sample| i16::from_le_bytes([sample[0], sample[1]])),
+        );
+    }
+
+    samples.truncate(wanted);
+    Ok(samples)
+}
@@ -2,18 +2,97 @@
 //further processing
 //
 
-use std::iter::FusedIterator;
+use std::{
+    iter::FusedIterator,
+    time::{Duration, Instant},
+};
+
+use gstreamer::{
+    prelude::*, ClockTime, CoreError, ElementFactoryType, MessageView, StateChangeSuccess,
+};
+
+use gstreamer_video::{
+    VideoColorMatrix, VideoColorPrimaries, VideoColorRange, VideoColorimetry, VideoFrameExt,
+    VideoTransferFunction,
+};
+use image::GenericImageView;
 
-use gstreamer::{prelude::*, ClockTime, CoreError, MessageView, StateChangeSuccess};
+use crate::PipelinePool;
 
-use gstreamer_video::VideoFrameExt;
-use image::GenericImageView;
+/// Errors raised by this crate itself rather than by gstreamer, in their own [`glib::Error`] domain.
+///
+/// ```
+/// # use vid_frame_iter::FrameIterError;
+/// # let e = glib::Error::new(FrameIterError::Timeout, "Gstreamer timed out");
+/// if e.matches(FrameIterError::Timeout) {
+///     println!("gstreamer stopped producing frames before the end of the video");
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, glib::ErrorDomain)]
+#[error_domain(name = "vid-frame-iter")]
+pub enum FrameIterError {
+    /// Gstreamer did not produce a frame or change state in time, but did not report an error.
+    /// This is distinct from reaching the end of the video, which simply ends iteration.
+    Timeout,
+}
+
+/// The range of values that the luma (and chroma) of a video is stored in. Decoders do not agree
+/// on which range they output the same video in, so frames are always converted to full range
+/// before they are returned (see [`VideoFrameIter::source_color_range`]).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ColorRange {
+    /// Luma is stored from 16 to 235, as most video is ("tv" range).
+    Limited,
+    /// Luma is stored from 0 to 255, as JPEGs and some screen recordings are ("pc" range).
+    Full,
+}
+
+impl ColorRange {
+    fn from_gst(range: VideoColorRange) -> Option<Self> {
+        match range {
+            VideoColorRange::Range16_235 => Some(Self::Limited),
+            VideoColorRange::Range0_255 => Some(Self::Full),
+            _ => None,
+        }
+    }
+
+    // The range of video with the given caps. Raw video caps without a colorimetry field take
+    // gstreamer's default for their format. Encoded video caps only have one if the parser found
+    // the range in the stream.
+    pub(crate) fn from_caps(caps: &gstreamer::CapsRef) -> Option<Self> {
+        if let Ok(info) = gstreamer_video::VideoInfo::from_caps(caps) {
+            return Self::from_gst(info.colorimetry().range());
+        }
+        let colorimetry = caps.structure(0)?.get::<&str>("colorimetry").ok()?;
+        Self::from_gst(colorimetry.parse::<VideoColorimetry>().ok()?.range())
+    }
+}
+
+/// Whether frames are deinterlaced (with gstreamer's `deinterlace` element) before they are
+/// returned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Deinterlace {
+    /// Frames are returned as they are decoded.
+    #[default]
+    Off,
+    /// Frames are deinterlaced if the caps of the decoded video say that it is interlaced.
+    Auto,
+    /// Every frame is deinterlaced, whether or not the video is interlaced.
+    Force,
+}
 
 #[derive(Debug, Clone)]
 pub struct VideoFrameIterBuilder {
     uri: String,
     fps: Option<(u64, u64)>,
     start_offset: Option<f64>,
+    apply_rotation: bool,
+    video_stream: Option<usize>,
+    deinterlace: Deinterlace,
+    // See force_source_color_range.
+    source_color_range: Option<ColorRange>,
+    // See reuse_pipelines.
+    pool: Option<PipelinePool>,
 }
 
 impl VideoFrameIterBuilder {
@@ -24,6 +103,11 @@ impl VideoFrameIterBuilder {
             uri: uri.as_ref().to_string(),
             fps: None,
             start_offset: None,
+            apply_rotation: true,
+            video_stream: None,
+            deinterlace: Deinterlace::Off,
+            source_color_range: None,
+            pool: None,
         }
     }
 
@@ -43,6 +127,78 @@ impl VideoFrameIterBuilder {
         self.start_offset = Some(seconds);
     }
 
+    /// Decode up to `duration` seconds of the first audio stream from [`Self::start_offset`]. See
+    /// [`crate::decode_audio`].
+    pub fn decode_audio(
+        &self,
+        sample_rate: u32,
+        duration: f64,
+    ) -> Result<Option<Vec<i16>>, glib::Error> {
+        crate::decode_audio(&self.uri, sample_rate, self.start_offset, duration)
+    }
+
+    /// Whether to rotate frames according to the orientation tag of the video (see [`Self::rotation`]).
+    /// Defaults to true. When disabled, frames are returned as they are stored in the file.
+    pub fn apply_rotation(&mut self, apply_rotation: bool) {
+        self.apply_rotation = apply_rotation;
+    }
+
+    /// Decode the video stream with the given index (counting video streams only, in the order
+    /// returned by [`crate::video_streams`]) instead of the default, which is the stream with the
+    /// largest resolution × duration (see [`crate::default_video_stream`]).
+    pub fn video_stream_index(&mut self, index: usize) {
+        self.video_stream = Some(index);
+    }
+
+    /// Deinterlace frames before they are returned (see [`Deinterlace`]). Defaults to
+    /// [`Deinterlace::Off`].
+    pub fn deinterlace(&mut self, mode: Deinterlace) {
+        self.deinterlace = mode;
+    }
+
+    /// Take pipelines from `pool` and return them to it when iterators are done with them, instead
+    /// of building a new pipeline for every iterator (see [`PipelinePool`]). The frames are the
+    /// same either way.
+    pub fn reuse_pipelines(&mut self, pool: &PipelinePool) {
+        self.pool = Some(pool.clone());
+    }
+
+    /// Convert decoded frames to the given range before they are converted to gray or rgb, as
+    /// if the decoder had output that range. Used to check that frames come out the same
+    /// whichever range a decoder chooses.
+    #[cfg(feature = "test-util")]
+    #[doc(hidden)]
+    pub fn force_source_color_range(&mut self, range: ColorRange) {
+        self.source_color_range = Some(range);
+    }
+
+    /// The index of the video stream that will be decoded. Returns None if the file contains
+    /// no video streams, and an error if a stream was requested that does not exist.
+    pub fn selected_video_stream(&self) -> Result<Option<usize>, glib::Error> {
+        let streams = crate::mediainfo_utils::video_streams(&self.uri)?;
+        self.select_video_stream(&streams)
+    }
+
+    fn select_video_stream(
+        &self,
+        streams: &[crate::VideoStreamInfo],
+    ) -> Result<Option<usize>, glib::Error> {
+        match self.video_stream {
+            None => Ok(crate::mediainfo_utils::default_video_stream(streams)),
+            Some(index) if index < streams.len() => Ok(Some(index)),
+            Some(index) => Err(glib::Error::new(
+                CoreError::Failed,
+                &format!("The video has no video stream with index {index}"),
+            )),
+        }
+    }
+
+    /// The rotation recorded in the container of the video, regardless of whether it will be applied.
+    /// Returns None if the file contains no video streams.
+    pub fn rotation(&self) -> Result<Option<crate::Rotation>, glib::Error> {
+        crate::mediainfo_utils::rotation(&self.uri)
+    }
+
     /// Consumes the builder and creates an iterator returning video frames.
     /// Frames are grayscale, with 8 bits per pixel.
     pub fn spawn_gray(&self) -> Result<VideoFrameIter<GrayFrame>, glib::Error> {
@@ -56,6 +212,39 @@ impl VideoFrameIterBuilder {
     }
 
     fn create_pipeline<RF: VideoFrame>(&self) -> Result<VideoFrameIter<RF>, glib::Error> {
+        let description = self.launch_description();
+        let caps = Self::sink_caps::<RF>();
+        let Some(pool) = &self.pool else {
+            let pipeline = Self::build_pipeline(&description, &caps)?;
+            return self.start(pipeline, None);
+        };
+
+        // Pipelines are only reused for videos which need the same elements and frame format.
+        let kind = format!("{description} ! {caps}");
+        let slot = || PoolSlot {
+            pool: pool.clone(),
+            kind: kind.clone(),
+        };
+        if let Some(pipeline) = pool.take(&kind) {
+            match self.start(pipeline, Some(slot())) {
+                Ok(it) => {
+                    pool.record(|stats| stats.reused += 1);
+                    return Ok(it);
+                }
+                // The pipeline may have been left unusable by the video before, so give this
+                // video a new one before giving up on it.
+                Err(_e) => pool.record(|stats| stats.fallbacks += 1),
+            }
+        }
+
+        let pipeline = Self::build_pipeline(&description, &caps)?;
+        pool.record(|stats| stats.built += 1);
+        self.start(pipeline, Some(slot()))
+    }
+
+    // The description of the pipeline. The URI is left out (it is set as a property of the
+    // decoder in start) so that a pipeline can be reused for other videos.
+    fn launch_description(&self) -> String {
         let fps_arg = match self.fps {
             None => String::from(""),
             Some((numer, denom)) => {
@@ -65,13 +254,64 @@ impl VideoFrameIterBuilder {
             }
         };
 
-        // Create our pipeline from a pipeline description string.
-        let src_path = &self.uri;
-        let pipeline_desc = format!(
-            "uridecodebin uri=\"{src_path}\" buffer-size=1 ! {fps_arg} videoconvert ! appsink name=sink"
-        );
+        // Whether rotation happens automatically varies between plugin sets, so always be explicit.
+        // videoflip reads the orientation tag from the stream when its method is "automatic".
+        let rotate_arg = if self.apply_rotation {
+            "videoflip method=automatic ! "
+        } else {
+            ""
+        };
 
-        let pipeline = gstreamer::parse::launch(&pipeline_desc)?
+        let force_range_arg = match self.source_color_range {
+            None => String::new(),
+            Some(range) => {
+                let range = match range {
+                    ColorRange::Limited => VideoColorRange::Range16_235,
+                    ColorRange::Full => VideoColorRange::Range0_255,
+                };
+                let colorimetry = VideoColorimetry::new(
+                    range,
+                    VideoColorMatrix::Bt601,
+                    VideoTransferFunction::Bt709,
+                    VideoColorPrimaries::Smpte170m,
+                );
+                format!("videoconvert ! video/x-raw,format=I420,colorimetry={colorimetry} ! ")
+            }
+        };
+
+        // Only the top field of each frame is interpolated, so that there is one frame out for
+        // each frame in (rather than one for each field) and the frame rate is unchanged. This
+        // comes before videorate, as deinterlacing needs the frames on either side of each frame.
+        let deinterlace_arg = match self.deinterlace {
+            Deinterlace::Off => "",
+            Deinterlace::Auto => "deinterlace mode=auto fields=top ! ",
+            Deinterlace::Force => "deinterlace mode=interlaced fields=top ! ",
+        };
+
+        // The decoder is linked to the rest of the pipeline once its streams are known (see
+        // start).
+        format!(
+            "uridecodebin name=decode buffer-size=1 identity name=video_in ! {force_range_arg}{deinterlace_arg}{fps_arg} videoconvert name=normalize ! {rotate_arg}appsink name=sink"
+        )
+    }
+
+    // The format that the appsink asks for.
+    //
+    // The colorimetry is given too, as otherwise videoconvert may keep the range of the decoded
+    // video, which differs between decoders of the same file.
+    fn sink_caps<RF: VideoFrame>() -> gstreamer::Caps {
+        gstreamer::Caps::builder("video/x-raw")
+            .field("format", RF::gst_video_format().to_str())
+            .field("colorimetry", RF::gst_colorimetry().to_string())
+            .build()
+    }
+
+    // Create a pipeline from a pipeline description string. It is not yet given a video.
+    fn build_pipeline(
+        description: &str,
+        caps: &gstreamer::Caps,
+    ) -> Result<gstreamer::Pipeline, glib::Error> {
+        let pipeline = gstreamer::parse::launch(description)?
             .downcast::<gstreamer::Pipeline>()
             .expect("Expected a gstreamer::Pipeline");
 
@@ -92,17 +332,74 @@ impl VideoFrameIterBuilder {
         // Tell the appsink what format we want.
         // This can be set after linking the two objects, because format negotiation between
         // both elements will happen during pre-rolling of the pipeline.
-        appsink.set_caps(Some(
-            &gstreamer::Caps::builder("video/x-raw")
-                .field("format", RF::gst_video_format().to_str())
-                .build(),
-        ));
+        appsink.set_caps(Some(caps));
+
+        Ok(pipeline)
+    }
 
-        let pipeline = VideoFrameIter::<RF> {
+    // Point a pipeline (either new, or readied by a previous iterator) at the video and play it.
+    fn start<RF: VideoFrame>(
+        &self,
+        pipeline: gstreamer::Pipeline,
+        pool: Option<PoolSlot>,
+    ) -> Result<VideoFrameIter<RF>, glib::Error> {
+        // From here on, the iterator's drop shuts the pipeline down if anything goes wrong.
+        let mut pipeline = VideoFrameIter::<RF> {
             pipeline,
             fused: false,
+            warnings: vec![],
+            last_pts: None,
+            frames_yielded: 0,
+            pull_latency: None,
+            description: String::new(),
+            decoder: None,
+            source_color_range: None,
+            pool,
+            pad_added: None,
+            reusable: false,
             _phantom: std::marker::PhantomData,
         };
+
+        let decode = pipeline
+            .pipeline
+            .by_name("decode")
+            .expect("decode element not found");
+        decode.set_property("uri", self.uri.as_str());
+
+        // Left to itself, uridecodebin links whichever video stream it exposes first, which has
+        // been seen to change between gstreamer versions. So choose the stream up front and link
+        // only the pad with that stream's id. Unselected streams are left unlinked, as audio
+        // streams always have been.
+        let streams = crate::mediainfo_utils::video_streams(&self.uri)?;
+        let wanted_stream_id = self
+            .select_video_stream(&streams)?
+            .and_then(|index| streams[index].stream_id.clone());
+
+        let video_in = pipeline
+            .pipeline
+            .by_name("video_in")
+            .expect("video_in element not found")
+            .static_pad("sink")
+            .expect("identity always has a sink pad");
+
+        pipeline.pad_added = Some(decode.connect_pad_added(move |_decode, pad| {
+            let is_video = pad.current_caps().is_some_and(|caps| {
+                caps.structure(0)
+                    .is_some_and(|s| s.name().starts_with("video/"))
+            });
+            let is_wanted = match &wanted_stream_id {
+                Some(wanted_id) => pad
+                    .stream_id()
+                    .is_some_and(|pad_id| pad_id.as_str() == wanted_id.as_str()),
+                None => true,
+            };
+
+            if is_video && is_wanted && !video_in.is_linked() {
+                // If this fails, the pipeline reports a not-linked error on the bus.
+                let _ = pad.link(&video_in);
+            }
+        }));
+
         pipeline.pause()?;
 
         if let Some(skip_amount) = self.start_offset {
@@ -110,11 +407,75 @@ impl VideoFrameIterBuilder {
         }
 
         pipeline.play()?;
+        pipeline.reusable = pipeline.pool.is_some();
+
+        // uridecodebin has plugged its decoders by now, so record which ones it chose.
+        let elements = pipeline.pipeline.children().into_iter().rev();
+        pipeline.description = elements
+            .map(|e| describe_element(&e))
+            .collect::<Vec<_>>()
+            .join(", ");
+        pipeline.decoder = pipeline
+            .pipeline
+            .by_name("decode")
+            .and_then(|decode| find_video_decoder(&decode));
+        pipeline.source_color_range = pipeline
+            .pipeline
+            .by_name("normalize")
+            .and_then(|normalize| normalize.static_pad("sink"))
+            .and_then(|pad| pad.current_caps())
+            .and_then(|caps| ColorRange::from_caps(&caps));
         Ok(pipeline)
     }
 }
 
-fn change_state_blocking(
+// Where an iterator from a pooled builder returns its pipeline.
+#[derive(Debug)]
+struct PoolSlot {
+    pool: PipelinePool,
+    kind: String,
+}
+
+// "name (factory)", followed by the same for each child in brackets if the element is a bin.
+fn describe_element(element: &gstreamer::Element) -> String {
+    let factory = element
+        .factory()
+        .map_or_else(|| String::from("unknown"), |f| f.name().to_string());
+    let mut desc = format!("{} ({factory})", element.name());
+
+    if let Some(bin) = element.downcast_ref::<gstreamer::Bin>() {
+        // Bins keep their newest child first.
+        let children = bin
+            .children()
+            .into_iter()
+            .rev()
+            .map(|child| describe_element(&child))
+            .collect::<Vec<_>>();
+        if !children.is_empty() {
+            desc += &format!(" [{}]", children.join(", "));
+        }
+    }
+    desc
+}
+
+// The factory name of the first video decoder anywhere inside element.
+fn find_video_decoder(element: &gstreamer::Element) -> Option<String> {
+    let factory = element.factory();
+    if factory
+        .as_ref()
+        .is_some_and(|f| f.has_type(ElementFactoryType::DECODER | ElementFactoryType::MEDIA_VIDEO))
+    {
+        return factory.map(|f| f.name().to_string());
+    }
+
+    let bin = element.downcast_ref::<gstreamer::Bin>()?;
+    bin.children()
+        .into_iter()
+        .rev()
+        .find_map(|child| find_video_decoder(&child))
+}
+
+pub(crate) fn change_state_blocking(
     pipeline: &gstreamer::Pipeline,
     new_state: gstreamer::State,
 ) -> Result<(), glib::Error> {
@@ -150,7 +511,10 @@ fn change_state_blocking(
                         "Gstreamer State Change Error",
                     )
                 } else {
-                    glib::Error::new(gstreamer::CoreError::TooLazy, "Internal Gstreamer error")
+                    glib::Error::new(
+                        FrameIterError::Timeout,
+                        "Gstreamer timed out changing state",
+                    )
                 }
             }
         };
@@ -163,31 +527,25 @@ fn change_state_blocking(
     }
 }
 
-fn into_glib_error(msg: gstreamer::Message) -> glib::Error {
-    match msg.view() {
-        MessageView::Error(e) => e.error(),
-        MessageView::Warning(w) => w.error(),
-        _ => {
-            panic!("Only Warning and Error messages can be converted into GstreamerError")
-        }
-    }
-}
-
-// Drain all messages from the bus, keeping track of eos and error.
+// Drain all error and warning messages from the bus, returning only the errors.
 //(This prevents messages piling up and causing memory leaks)
-fn get_bus_errors(bus: &gstreamer::Bus) -> impl Iterator<Item = glib::Error> + '_ {
+pub(crate) fn get_bus_errors(bus: &gstreamer::Bus) -> impl Iterator<Item = glib::Error> + '_ {
     let errs_warns = [
         gstreamer::MessageType::Error,
         gstreamer::MessageType::Warning,
     ];
 
-    std::iter::from_fn(move || bus.pop_filtered(&errs_warns).map(into_glib_error))
+    std::iter::from_fn(move || bus.pop_filtered(&errs_warns)).filter_map(|msg| match msg.view() {
+        MessageView::Error(e) => Some(e.error()),
+        _ => None,
+    })
 }
 
 pub(crate) mod private {
     pub trait VideoFrameInternal {
         fn new(sample: gstreamer::Sample) -> Self;
         fn gst_video_format() -> gstreamer_video::VideoFormat;
+        fn gst_colorimetry() -> gstreamer_video::VideoColorimetry;
     }
 }
 use private::VideoFrameInternal;
@@ -195,6 +553,12 @@ use private::VideoFrameInternal;
 pub trait VideoFrame: VideoFrameInternal {
     /// Get a reference to the raw framebuffer data from gstreamer
     fn raw_frame(&self) -> &gstreamer_video::VideoFrame<gstreamer_video::video_frame::Readable>;
+
+    /// The range that the pixels of the frame are stored in. Frames are converted to full range
+    /// as they are decoded, so this is always [`ColorRange::Full`] unless gstreamer did not say.
+    fn color_range(&self) -> Option<ColorRange> {
+        ColorRange::from_gst(self.raw_frame().info().colorimetry().range())
+    }
 }
 
 /// Conversion functions to types provided by the popular[`image`] crate.
@@ -206,6 +570,60 @@ pub trait ImageFns {
 
     /// Copy the underlying frame data into an owned [`image::ImageBuffer`].
     fn to_imagebuffer(&self) -> Self::IB;
+
+    /// As [`ImageFns::as_flat`], but for only the region of the frame given as `(left, top, width, height)`.
+    /// No pixels are copied: the samples start at the top left corner of the region, and the strides are
+    /// those of the whole frame. Returns None if the region is empty or does not fit inside the frame.
+    fn as_flat_cropped(&self, crop: (u32, u32, u32, u32)) -> Option<image::FlatSamples<&[u8]>> {
+        crop_flat(self.as_flat(), crop)
+    }
+
+    /// Copy only the given region of the frame (see [`ImageFns::as_flat_cropped`]) into an owned
+    /// [`image::ImageBuffer`], a row at a time. Much cheaper than cropping the result of
+    /// [`ImageFns::to_imagebuffer`] when most of a large frame is cropped away.
+    fn to_imagebuffer_cropped(&self, crop: (u32, u32, u32, u32)) -> Option<Self::IB>;
+}
+
+// See ImageFns::as_flat_cropped.
+fn crop_flat(
+    flat: image::FlatSamples<&[u8]>,
+    (left, top, width, height): (u32, u32, u32, u32),
+) -> Option<image::FlatSamples<&[u8]>> {
+    let layout = flat.layout;
+    if width == 0
+        || height == 0
+        || left.checked_add(width)? > layout.width
+        || top.checked_add(height)? > layout.height
+    {
+        return None;
+    }
+
+    let offset = top as usize * layout.height_stride + left as usize * layout.width_stride;
+    Some(image::FlatSamples {
+        samples: flat.samples.get(offset..)?,
+        layout: image::flat::SampleLayout {
+            width,
+            height,
+            ..layout
+        },
+        color_hint: flat.color_hint,
+    })
+}
+
+// Copy the rows of the samples into a buffer of their own, leaving out any padding at the end of
+// each row. Pixels must be packed next to each other within their rows.
+fn copy_rows(flat: &image::FlatSamples<&[u8]>) -> Vec<u8> {
+    let layout = flat.layout;
+    let row_len = layout.width as usize * layout.width_stride;
+    let mut ret = Vec::with_capacity(row_len * layout.height as usize);
+    for row in flat
+        .samples
+        .chunks(layout.height_stride)
+        .take(layout.height as usize)
+    {
+        ret.extend_from_slice(&row[..row_len]);
+    }
+    ret
 }
 
 // Iterates over all the frames in a video.
@@ -215,6 +633,8 @@ pub trait ImageFns {
 // the iterator will produce Err(error).
 // Once all frames and the first error has been produced the iterator
 // will produce None.
+//
+// Warnings do not stop iteration. They are kept, and can be read with warnings().
 #[derive(Debug)]
 pub struct VideoFrameIter<RF: VideoFrame> {
     //Source of video frames
@@ -223,9 +643,76 @@ pub struct VideoFrameIter<RF: VideoFrame> {
     //Whether the last frame has been returned
     fused: bool,
 
+    //Warnings posted by the pipeline so far, oldest first.
+    warnings: Vec<glib::Error>,
+
+    //Presentation timestamp of the most recent frame, if it had one.
+    last_pts: Option<ClockTime>,
+
+    //The number of frames returned so far.
+    frames_yielded: u64,
+
+    //How long each frame returned so far took to arrive, if there have been any.
+    pull_latency: Option<PullLatency>,
+
+    //The elements of the pipeline once it started playing, see pipeline_description().
+    description: String,
+
+    //The factory name of the video decoder plugged by uridecodebin, if one was found.
+    decoder: Option<String>,
+
+    //The range of the frames before they were converted, see source_color_range().
+    source_color_range: Option<ColorRange>,
+
+    //The pool to return the pipeline to, if it came from a pooled builder.
+    pool: Option<PoolSlot>,
+
+    //The handler linking the decoder to the rest of the pipeline, which is only for this video.
+    pad_added: Option<glib::SignalHandlerId>,
+
+    //Whether the pipeline can be returned to the pool. Only true once it has started playing, and
+    //until it reports an error.
+    reusable: bool,
+
     _phantom: std::marker::PhantomData<RF>,
 }
 
+/// How long a [`VideoFrameIter`] has waited for gstreamer to produce each frame, as returned by
+/// [`VideoFrameIter::decode_stats`]. This is mostly the time spent decoding, so it shows which
+/// videos are slow to decode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodeStats {
+    /// The number of frames that have been returned.
+    pub frames: u64,
+    pub min: Duration,
+    pub mean: Duration,
+    pub max: Duration,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct PullLatency {
+    total: Duration,
+    min: Duration,
+    max: Duration,
+}
+
+impl PullLatency {
+    fn record(latency: Option<Self>, elapsed: Duration) -> Self {
+        match latency {
+            None => Self {
+                total: elapsed,
+                min: elapsed,
+                max: elapsed,
+            },
+            Some(l) => Self {
+                total: l.total + elapsed,
+                min: l.min.min(elapsed),
+                max: l.max.max(elapsed),
+            },
+        }
+    }
+}
+
 impl<RF: VideoFrame> FusedIterator for VideoFrameIter<RF> {}
 impl<RF: VideoFrame> Iterator for VideoFrameIter<RF> {
     type Item = Result<RF, glib::Error>;
@@ -259,15 +746,25 @@ impl<RF: VideoFrame> VideoFrameIter<RF> {
             .downcast::<gstreamer_app::AppSink>()
             .expect("Sink element is expected to be an appsink!");
 
-        //If any error/warning occurred, then return it now.
-        if let Some(error) = Self::try_find_error(&bus) {
+        //If any error occurred, then return it now.
+        if let Some(error) = self.try_find_error(&bus) {
+            self.reusable = false;
             return Some(Err(error));
         }
 
+        let pull_start = Instant::now();
         let sample = appsink.try_pull_sample(try_pull_sample_timeout);
         match sample {
             //If a frame was extracted then return it.
-            Some(sample) => Some(Ok(sample)),
+            Some(sample) => {
+                self.frames_yielded += 1;
+                self.pull_latency =
+                    Some(PullLatency::record(self.pull_latency, pull_start.elapsed()));
+                if let Some(pts) = sample.buffer().and_then(|buf| buf.pts()) {
+                    self.last_pts = Some(pts);
+                }
+                Some(Ok(sample))
+            }
 
             None => {
                 // Make sure no more frames can be drawn if next is called again
@@ -276,12 +773,12 @@ impl<RF: VideoFrame> VideoFrameIter<RF> {
                 //if no sample was returned then we might have hit the timeout.
                 //If so check for any possible error being written into the log
                 //at that time
-                let ret = match Self::try_find_error(&bus) {
+                let ret = match self.try_find_error(&bus) {
                     Some(error) => Some(Err(error)),
                     _ => {
                         if !appsink.is_eos() {
                             Some(Err(glib::Error::new(
-                                CoreError::TooLazy,
+                                FrameIterError::Timeout,
                                 "Gstreamer timed out",
                             )))
 
@@ -292,6 +789,14 @@ impl<RF: VideoFrame> VideoFrameIter<RF> {
                     }
                 };
 
+                //A pipeline from a pool is kept for the next video, unless this one failed.
+                self.reusable &= ret.is_none();
+                if self.reusable {
+                    //change_state_blocking shuts the pipeline down if it cannot be readied.
+                    self.reusable =
+                        change_state_blocking(&self.pipeline, gstreamer::State::Ready).is_ok();
+                    return ret;
+                }
                 match change_state_blocking(&self.pipeline, gstreamer::State::Null) {
                     Ok(()) => ret,
                     Err(e) => panic!("{e:?}"),
@@ -322,13 +827,116 @@ impl<RF: VideoFrame> VideoFrameIter<RF> {
             .map_err(|e| glib::Error::new(CoreError::TooLazy, &e.message))
     }
 
-    fn try_find_error(bus: &gstreamer::Bus) -> Option<glib::Error> {
-        bus.pop_filtered(&[
+    /// The presentation timestamp, in seconds, of the most recent frame that had one. Unlike the
+    /// duration reported by the container, this is where decoding actually reached, so after the
+    /// last frame it is a lower bound of the true duration of the video.
+    pub fn position(&self) -> Option<f64> {
+        self.last_pts
+            .map(|pts| pts.nseconds() as f64 / ClockTime::SECOND.nseconds() as f64)
+    }
+
+    /// The number of frames returned so far.
+    pub fn frames_yielded(&self) -> u64 {
+        self.frames_yielded
+    }
+
+    /// The elements of the pipeline, as they were once it started playing. Each element is
+    /// written as "name (factory)", and the elements inside a bin follow it in brackets, so this
+    /// shows which demuxers and decoders uridecodebin chose for the video. For example:
+    ///
+    /// ```text
+    /// decode (uridecodebin) [source (filesrc), decodebin0 (decodebin) [typefind (typefind),
+    /// qtdemux0 (qtdemux), multiqueue0 (multiqueue), h264parse0 (h264parse),
+    /// capsfilter0 (capsfilter), avdec_h264-0 (avdec_h264)]], video_in (identity),
+    /// videoconvert0 (videoconvert), sink (appsink)
+    /// ```
+    pub fn pipeline_description(&self) -> String {
+        self.description.clone()
+    }
+
+    /// The factory name of the element decoding the video, such as "avdec_h264" or
+    /// "vah264dec", if uridecodebin plugged one that gstreamer classifies as a video decoder.
+    pub fn decoder(&self) -> Option<&str> {
+        self.decoder.as_deref()
+    }
+
+    /// The range that the decoder output frames in, before they were converted to full range, or
+    /// None if gstreamer did not say. Every frame returned is full range whatever this is (see
+    /// [`VideoFrame::color_range`]), so that the same video gives the same frames whichever
+    /// decoder reads it.
+    pub fn source_color_range(&self) -> Option<ColorRange> {
+        self.source_color_range
+    }
+
+    /// How long each frame returned so far took to arrive, or None if no frames have been
+    /// returned yet.
+    pub fn decode_stats(&self) -> Option<DecodeStats> {
+        self.pull_latency.map(|l| DecodeStats {
+            frames: self.frames_yielded,
+            min: l.min,
+            mean: l.total.div_f64(self.frames_yielded as f64),
+            max: l.max,
+        })
+    }
+
+    /// The warnings posted by gstreamer while reading frames so far, such as frames being dropped
+    /// by the decoder. Unlike errors, warnings do not stop iteration.
+    pub fn warnings(&self) -> &[glib::Error] {
+        &self.warnings
+    }
+
+    /// Post a warning on the bus of the pipeline, as if an element had posted it.
+    #[cfg(feature = "test-util")]
+    #[doc(hidden)]
+    pub fn post_warning(&self, message: &str) -> Result<(), glib::BoolError> {
+        let msg = gstreamer::message::Warning::builder(CoreError::Failed, message)
+            .src(&self.pipeline)
+            .build();
+        self.pipeline.post_message(msg)
+    }
+
+    // Forget everything about this video that would otherwise reach the next one, and give the
+    // pipeline (which must be Ready) back to the pool.
+    fn return_to_pool(&mut self, slot: PoolSlot) {
+        if let (Some(decode), Some(handler)) =
+            (self.pipeline.by_name("decode"), self.pad_added.take())
+        {
+            decode.disconnect(handler);
+        }
+
+        // uridecodebin removes its pads when it is readied, which unlinks them, but make sure.
+        let video_in = self
+            .pipeline
+            .by_name("video_in")
+            .and_then(|video_in| video_in.static_pad("sink"));
+        if let Some(video_in) = video_in {
+            if let Some(peer) = video_in.peer() {
+                let _ = peer.unlink(&video_in);
+            }
+        }
+
+        if let Some(bus) = self.pipeline.bus() {
+            while bus.pop().is_some() {}
+        }
+
+        slot.pool.put(&slot.kind, self.pipeline.clone());
+    }
+
+    // Pop errors and warnings from the bus until an error is found, keeping the warnings.
+    fn try_find_error(&mut self, bus: &gstreamer::Bus) -> Option<glib::Error> {
+        let errs_warns = [
             gstreamer::MessageType::Error,
             gstreamer::MessageType::Warning,
-        ])
-        .filter(|msg| matches!(msg.view(), MessageView::Error(_) | MessageView::Warning(_)))
-        .map(into_glib_error)
+        ];
+
+        while let Some(msg) = bus.pop_filtered(&errs_warns) {
+            match msg.view() {
+                MessageView::Error(e) => return Some(e.error()),
+                MessageView::Warning(w) => self.warnings.push(w.error()),
+                _ => (),
+            }
+        }
+        None
     }
 }
 
@@ -337,8 +945,18 @@ impl<RF: VideoFrame> VideoFrameIter<RF> {
 //
 //Unfortunately there's no way to capture if something goes wrong, which
 //could cause silent memory leaks, so prefer to panic instead here.
+//
+//Pipelines from a pool are readied and returned to it instead, unless they reported an error.
 impl<T: VideoFrame> Drop for VideoFrameIter<T> {
     fn drop(&mut self) {
+        if let Some(slot) = self.pool.take().filter(|_| self.reusable) {
+            //change_state_blocking shuts the pipeline down if it cannot be readied.
+            if change_state_blocking(&self.pipeline, gstreamer::State::Ready).is_ok() {
+                self.return_to_pool(slot);
+            }
+            return;
+        }
+
         match change_state_blocking(&self.pipeline, gstreamer::State::Null) {
             Ok(()) => (),
             Err(e) => panic!("{e:?}"),
@@ -359,7 +977,8 @@ impl<T: VideoFrame> Drop for VideoFrameIter<T> {
 /// If you want to pass frames around in your code, it is better to clone them instead of handing outreferences. In other
 /// words, you can treat frames as if they were wrapped by an [`std::rc::Rc`]
 ///
-/// Most functions have been written to avoid copying raw frames. Currently the only function that does copy is [`ImageFns::to_imagebuffer`].
+/// Most functions have been written to avoid copying raw frames. Currently the only functions that do copy are
+/// [`ImageFns::to_imagebuffer`] and [`ImageFns::to_imagebuffer_cropped`].
 ///
 /// # Examples
 /// Print the integer value of the top left pixel.
@@ -473,6 +1092,29 @@ impl VideoFrameInternal for GrayFrame {
     fn gst_video_format() -> gstreamer_video::VideoFormat {
         gstreamer_video::VideoFormat::Gray8
     }
+
+    // gstreamer only accepts gray colorimetries with no matrix, transfer function or primaries.
+    fn gst_colorimetry() -> gstreamer_video::VideoColorimetry {
+        VideoColorimetry::new(
+            VideoColorRange::Range0_255,
+            VideoColorMatrix::Unknown,
+            VideoTransferFunction::Unknown,
+            VideoColorPrimaries::Unknown,
+        )
+    }
+}
+
+impl GrayFrame {
+    /// A view of the region of the frame given as `(left, top, width, height)`, which reads the
+    /// frame's own pixels rather than a copy of them. Returns None if the region is empty or does
+    /// not fit inside the frame.
+    #[must_use]
+    pub fn crop_view(
+        &self,
+        crop: (u32, u32, u32, u32),
+    ) -> Option<image::flat::View<&[u8], image::Luma<u8>>> {
+        self.as_flat_cropped(crop)?.into_view().ok()
+    }
 }
 
 impl VideoFrame for GrayFrame {
@@ -521,6 +1163,11 @@ impl ImageFns for GrayFrame {
 
         image::ImageBuffer::from_fn(width, height, |x, y| view.get_pixel(x, y))
     }
+
+    fn to_imagebuffer_cropped(&self, crop: (u32, u32, u32, u32)) -> Option<Self::IB> {
+        let flat = self.as_flat_cropped(crop)?;
+        image::ImageBuffer::from_raw(flat.layout.width, flat.layout.height, copy_rows(&flat))
+    }
 }
 
 /// A single video frame, with 24 bits per pixel, Rgb encoding.
@@ -536,7 +1183,8 @@ impl ImageFns for GrayFrame {
 /// If you want to pass frames around in your code, it is better to clone them instead of handing outreferences. In other
 /// words, you can treat frames as if they were wrapped by an [`std::rc::Rc`]
 ///
-/// Most functions have been written to avoid copying raw frames. Currently the only function that does copy is [`ImageFns::to_imagebuffer`].
+/// Most functions have been written to avoid copying raw frames. Currently the only functions that do copy are
+/// [`ImageFns::to_imagebuffer`] and [`ImageFns::to_imagebuffer_cropped`].
 ///
 /// # Examples
 /// Sum the raw pixel values of an entire frame.
@@ -648,6 +1296,29 @@ impl VideoFrameInternal for RgbFrame {
     fn gst_video_format() -> gstreamer_video::VideoFormat {
         gstreamer_video::VideoFormat::Rgb
     }
+
+    // sRGB, which is full range.
+    fn gst_colorimetry() -> gstreamer_video::VideoColorimetry {
+        VideoColorimetry::new(
+            VideoColorRange::Range0_255,
+            VideoColorMatrix::Rgb,
+            VideoTransferFunction::Srgb,
+            VideoColorPrimaries::Bt709,
+        )
+    }
+}
+
+impl RgbFrame {
+    /// A view of the region of the frame given as `(left, top, width, height)`, which reads the
+    /// frame's own pixels rather than a copy of them. Returns None if the region is empty or does
+    /// not fit inside the frame.
+    #[must_use]
+    pub fn crop_view(
+        &self,
+        crop: (u32, u32, u32, u32),
+    ) -> Option<image::flat::View<&[u8], image::Rgb<u8>>> {
+        self.as_flat_cropped(crop)?.into_view().ok()
+    }
 }
 
 impl VideoFrame for RgbFrame {
@@ -689,4 +1360,9 @@ impl ImageFns for RgbFrame {
 
         image::ImageBuffer::from_fn(width, height, |x, y| view.get_pixel(x, y))
     }
+
+    fn to_imagebuffer_cropped(&self, crop: (u32, u32, u32, u32)) -> Option<Self::IB> {
+        let flat = self.as_flat_cropped(crop)?;
+        image::ImageBuffer::from_raw(flat.layout.width, flat.layout.height, copy_rows(&flat))
+    }
 }
@@ -119,6 +119,20 @@
 //! }
 //! ```
 //!
+//! Timeouts, where gstreamer stops producing frames without reporting why, are reported in this
+//! crate's own domain as [`FrameIterError::Timeout`]. Warnings from gstreamer do not stop
+//! iteration, and can be read afterwards with [`VideoFrameIter::warnings`].
+//!
+//! # Diagnosing slow videos
+//! [`VideoFrameIter::pipeline_description`] lists the elements that gstreamer chose to read a
+//! video (such as a software decoder where a hardware one was expected), and
+//! [`VideoFrameIter::decode_stats`] reports how long each frame took to arrive.
+//!
+//! # Color range
+//! Frames are always returned in full range (luma from 0 to 255), whichever range the decoder
+//! output them in. [`VideoFrameIter::source_color_range`] reports the range that was converted
+//! from.
+//!
 //! # Supported operating systems
 //! Currently only tested on Ubuntu Linux 22.04. This crate should work in MacOS and windows but this has not been tested.
 //!
@@ -134,16 +148,99 @@ pub mod mediainfo_utils;
 pub mod extras;
 pub mod frame_iter;
 
+/// Decoding audio as raw samples.
+pub mod audio;
+
+/// Reusing pipelines between videos.
+pub mod pipeline_pool;
+
+pub use frame_iter::ColorRange;
+pub use frame_iter::DecodeStats;
+pub use frame_iter::Deinterlace;
+pub use frame_iter::FrameIterError;
 pub use frame_iter::GrayFrame;
 pub use frame_iter::ImageFns;
 pub use frame_iter::RgbFrame;
 pub use frame_iter::VideoFrameIter;
 pub use frame_iter::VideoFrameIterBuilder;
 
+pub use audio::decode_audio;
+
+pub use pipeline_pool::PipelinePool;
+pub use pipeline_pool::PoolStats;
+
 pub use extras::*;
 pub use mediainfo_utils::*;
 
 /// Initialize gstreamer. You must call this function before calling any other function in this crate.
+///
+/// # Panics
+/// Panics if gstreamer cannot be initialized. Use [`try_init_gstreamer`] to handle the error instead.
 pub fn init_gstreamer() {
-    gstreamer::init().expect("Failed to initialize gstreamer")
+    try_init_gstreamer().expect("Failed to initialize gstreamer")
+}
+
+/// As [`init_gstreamer`], but returns an error instead of panicking. Safe to call more than once.
+pub fn try_init_gstreamer() -> Result<(), glib::Error> {
+    gstreamer::init()
+}
+
+// Every element that the pipelines in this crate may be built from.
+const REQUIRED_ELEMENTS: [&str; 8] = [
+    "uridecodebin",
+    "identity",
+    "deinterlace",
+    "videorate",
+    "capsfilter",
+    "videoconvert",
+    "videoflip",
+    "appsink",
+];
+
+// Plugins which are not needed to build the pipelines, but which provide the decoders that
+// uridecodebin usually picks, so their versions decide how frames are decoded.
+const DECODER_PLUGINS: [&str; 4] = ["libav", "dav1d", "vpx", "openh264"];
+
+/// The version of gstreamer, followed by the version of each installed plugin that decodes or
+/// converts frames for this crate (in order of their names), as `(name, version)` pairs.
+///
+/// gstreamer must be initialized first.
+pub fn component_versions() -> Vec<(String, String)> {
+    use gstreamer::prelude::*;
+
+    let registry = gstreamer::Registry::get();
+    let plugins = REQUIRED_ELEMENTS
+        .into_iter()
+        .filter_map(|name| gstreamer::ElementFactory::find(name)?.plugin())
+        .chain(
+            DECODER_PLUGINS
+                .into_iter()
+                .filter_map(|name| registry.find_plugin(name)),
+        )
+        .map(|plugin| {
+            (
+                plugin.plugin_name().to_string(),
+                plugin.version().to_string(),
+            )
+        })
+        .collect::<std::collections::BTreeMap<_, _>>();
+
+    let (major, minor, micro, nano) = gstreamer::version();
+    std::iter::once((
+        "gstreamer".to_string(),
+        format!("{major}.{minor}.{micro}.{nano}"),
+    ))
+    .chain(plugins)
+    .collect()
+}
+
+/// The names of any gstreamer elements needed by this crate which are not installed. Frames
+/// cannot be read until the plugins providing them are installed.
+///
+/// gstreamer must be initialized first.
+pub fn missing_elements() -> Vec<&'static str> {
+    REQUIRED_ELEMENTS
+        .into_iter()
+        .filter(|name| gstreamer::ElementFactory::find(name).is_none())
+        .collect()
 }
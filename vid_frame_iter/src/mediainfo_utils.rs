@@ -1,11 +1,123 @@
+use std::cell::RefCell;
+
 use gstreamer::ClockTime;
-use gstreamer_pbutils::{Discoverer, DiscovererInfo};
+use gstreamer_pbutils::{prelude::*, Discoverer, DiscovererInfo};
+
+thread_local! {
+    // Creating a discoverer is comparatively expensive, so each thread keeps one around
+    // and reuses it for every file it is asked about.
+    static DISCOVERER: RefCell<Option<Discoverer>> = const { RefCell::new(None) };
+}
 
 fn media_info(uri: impl AsRef<str>) -> Result<DiscovererInfo, glib::Error> {
-    let timeout = ClockTime::from_seconds(15);
+    let discoverer = DISCOVERER.with(|cached| -> Result<Discoverer, glib::Error> {
+        let mut cached = cached.borrow_mut();
+        match cached.as_ref() {
+            Some(discoverer) => Ok(discoverer.clone()),
+            None => {
+                let timeout = ClockTime::from_seconds(15);
+                let discoverer = Discoverer::new(timeout)?;
+                *cached = Some(discoverer.clone());
+                Ok(discoverer)
+            }
+        }
+    })?;
 
-    let ret = Discoverer::new(timeout)?.discover_uri(uri.as_ref())?;
-    Ok(ret)
+    discoverer.discover_uri(uri.as_ref())
+}
+
+/// Metadata of a video file, gathered from a single discoverer pass.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MediaSummary {
+    /// Duration of the file, if known.
+    pub duration: Option<std::time::Duration>,
+    /// Dimensions of the largest video stream.
+    pub dimensions: (u32, u32),
+    /// Frame rate of the first video stream as (numerator, denominator).
+    pub frame_rate: Option<(u32, u32)>,
+    /// Human readable description of the codec of the first video stream, e.g. "H.264 (High Profile)"
+    pub video_codec: Option<String>,
+    /// Bitrate of the first video stream in bits per second.
+    pub bit_rate: Option<u64>,
+    /// Human readable description of the container format, e.g. "Quicktime"
+    pub container: Option<String>,
+    /// Pixel aspect ratio of the first video stream as (width, height), e.g. (64, 45) for a
+    /// widescreen PAL DVD.
+    pub pixel_aspect_ratio: Option<(u32, u32)>,
+    /// The range that the first video stream is stored in, if its caps say.
+    pub color_range: Option<crate::ColorRange>,
+    /// Whether the first video stream is interlaced, if its caps say.
+    pub interlaced: Option<bool>,
+}
+
+/// Get all the metadata in [`MediaSummary`] at once, or None if the file contains
+/// no video streams. This is cheaper than calling [`duration`], [`dimensions`]
+/// and [`frame_rate`] individually, as the file is only discovered once.
+pub fn summary(uri: impl AsRef<str>) -> Result<Option<MediaSummary>, glib::Error> {
+    let info = media_info(uri)?;
+    let video_streams = info.video_streams();
+
+    let Some(first_stream) = video_streams.first() else {
+        return Ok(None);
+    };
+
+    let duration = info
+        .duration()
+        .map(|duration| std::time::Duration::from_nanos(duration.nseconds()));
+
+    let dimensions = video_streams
+        .iter()
+        .map(|vstream| (vstream.width(), vstream.height()))
+        .reduce(|best_res, curr_res| {
+            if curr_res.0 * curr_res.1 > best_res.0 * best_res.1 {
+                curr_res
+            } else {
+                best_res
+            }
+        })
+        .unwrap_or_default();
+
+    let positive = |frac: gstreamer::Fraction| match (
+        u32::try_from(frac.numer()),
+        u32::try_from(frac.denom()),
+    ) {
+        (Ok(numer), Ok(denom)) if numer > 0 && denom > 0 => Some((numer, denom)),
+        _ => None,
+    };
+    let frame_rate = positive(first_stream.framerate());
+    let pixel_aspect_ratio = positive(first_stream.par());
+
+    let video_codec = first_stream
+        .caps()
+        .map(|caps| gstreamer_pbutils::pb_utils_get_codec_description(&caps).to_string());
+    let color_range = first_stream
+        .caps()
+        .and_then(|caps| crate::ColorRange::from_caps(&caps));
+    let interlaced = first_stream.caps().and_then(|caps| {
+        let mode = caps.structure(0)?.get::<&str>("interlace-mode").ok()?;
+        Some(mode != "progressive")
+    });
+
+    //gstreamer reports 0 when the bitrate is unknown.
+    let bit_rate = Some(u64::from(first_stream.bitrate())).filter(|&bit_rate| bit_rate > 0);
+
+    let container = info
+        .container_streams()
+        .first()
+        .and_then(|container| container.caps())
+        .map(|caps| gstreamer_pbutils::pb_utils_get_codec_description(&caps).to_string());
+
+    Ok(Some(MediaSummary {
+        duration,
+        dimensions,
+        frame_rate,
+        video_codec,
+        bit_rate,
+        container,
+        pixel_aspect_ratio,
+        color_range,
+        interlaced,
+    }))
 }
 
 /// Get the duration of the given video file in seconds, or None
@@ -52,6 +164,144 @@ pub fn dimensions(uri: impl AsRef<str>) -> Result<Option<(u32, u32)>, glib::Erro
     Ok(ret)
 }
 
+/// A single video stream within a file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VideoStreamInfo {
+    /// The identifier gstreamer uses for this stream, which is stable between discovery and decoding.
+    pub stream_id: Option<String>,
+    /// Dimensions of the stream.
+    pub dimensions: (u32, u32),
+    /// Duration of the stream if it is tagged, otherwise the duration of the whole file.
+    pub duration: Option<std::time::Duration>,
+}
+
+/// Get every video stream in the given file, in the order they are stored. Indexes into the
+/// returned list are the indexes accepted by
+/// [`VideoFrameIterBuilder::video_stream_index`][crate::VideoFrameIterBuilder::video_stream_index].
+pub fn video_streams(uri: impl AsRef<str>) -> Result<Vec<VideoStreamInfo>, glib::Error> {
+    let info = media_info(uri)?;
+    let file_duration = info.duration();
+
+    let ret = info
+        .video_streams()
+        .iter()
+        .map(|vstream| {
+            let tagged_duration = vstream
+                .tags()
+                .and_then(|tags| tags.get::<gstreamer::tags::Duration>())
+                .map(|duration| duration.get());
+
+            VideoStreamInfo {
+                stream_id: vstream.stream_id().map(|id| id.to_string()),
+                dimensions: (vstream.width(), vstream.height()),
+                duration: tagged_duration
+                    .or(file_duration)
+                    .map(|duration| std::time::Duration::from_nanos(duration.nseconds())),
+            }
+        })
+        .collect();
+
+    Ok(ret)
+}
+
+/// The index of the stream that is decoded by default: the stream with the largest
+/// resolution × duration, or the earliest such stream if there is a tie.
+///
+/// Returns None if there are no streams.
+#[must_use]
+pub fn default_video_stream(streams: &[VideoStreamInfo]) -> Option<usize> {
+    let score = |stream: &VideoStreamInfo| {
+        let (width, height) = stream.dimensions;
+        let area = u64::from(width) * u64::from(height);
+        let duration = stream.duration.unwrap_or_default().as_secs_f64();
+        (area as f64 * duration, area)
+    };
+
+    streams
+        .iter()
+        .enumerate()
+        .rev()
+        .max_by(|(_, s1), (_, s2)| {
+            let (score_1, area_1) = score(s1);
+            let (score_2, area_2) = score(s2);
+            score_1.total_cmp(&score_2).then(area_1.cmp(&area_2))
+        })
+        .map(|(idx, _stream)| idx)
+}
+
+/// The orientation of a video as recorded in its container (e.g. by phone cameras), describing
+/// how its frames must be transformed to be displayed upright.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Rotation {
+    degrees: u32,
+    flipped: bool,
+}
+
+impl Rotation {
+    /// Parse the value of a gstreamer `image-orientation` tag, e.g. "rotate-90" or "flip-rotate-180".
+    #[must_use]
+    pub fn from_tag(tag: &str) -> Option<Self> {
+        let (flipped, degrees) = match tag.strip_prefix("flip-") {
+            Some(rest) => (true, rest),
+            None => (false, tag),
+        };
+
+        let degrees = match degrees.strip_prefix("rotate-")? {
+            "0" => 0,
+            "90" => 90,
+            "180" => 180,
+            "270" => 270,
+            _ => return None,
+        };
+
+        Some(Self { degrees, flipped })
+    }
+
+    /// Clockwise rotation in degrees that must be applied to display the video upright.
+    /// Always one of 0, 90, 180 or 270.
+    #[must_use]
+    pub const fn degrees(&self) -> u32 {
+        self.degrees
+    }
+
+    /// True if the video must also be flipped horizontally (before rotating).
+    #[must_use]
+    pub const fn is_flipped(&self) -> bool {
+        self.flipped
+    }
+
+    /// True if the width and height of frames are swapped when the rotation is applied.
+    #[must_use]
+    pub const fn transposes_dimensions(&self) -> bool {
+        self.degrees % 180 == 90
+    }
+}
+
+/// Get the rotation of the first video stream of the given file, or None if the file contains no
+/// video streams. Videos without an orientation tag have the default (identity) rotation.
+pub fn rotation(uri: impl AsRef<str>) -> Result<Option<Rotation>, glib::Error> {
+    let info = media_info(uri)?;
+    let Some(first_stream) = info.video_streams().into_iter().next() else {
+        return Ok(None);
+    };
+
+    //demuxers usually attach the tag to the stream, but check the global tags too.
+    let tagged = [first_stream.tags(), info.tags()]
+        .into_iter()
+        .flatten()
+        .find_map(|tags| {
+            let tag = tags.get::<gstreamer::tags::ImageOrientation>()?;
+            Rotation::from_tag(tag.get())
+        });
+
+    Ok(Some(tagged.unwrap_or_default()))
+}
+
+/// Whether the file has any audio streams.
+pub fn has_audio(uri: impl AsRef<str>) -> Result<bool, glib::Error> {
+    Ok(!media_info(uri)?.audio_streams().is_empty())
+}
+
 //Get the frame rate of a video.
 pub fn frame_rate(uri: impl AsRef<str>) -> Result<Option<f64>, glib::Error> {
     let info = media_info(uri)?;
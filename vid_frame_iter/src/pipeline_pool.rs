@@ -0,0 +1,98 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, PoisonError},
+};
+
+use gstreamer::prelude::*;
+
+// Pipelines beyond this many of each kind are shut down instead of being kept.
+const MAX_IDLE_PER_KIND: usize = 4;
+
+/// Pipelines kept between videos by the iterators of builders given the pool with
+/// [`VideoFrameIterBuilder::reuse_pipelines`][crate::VideoFrameIterBuilder::reuse_pipelines].
+///
+/// Building a pipeline (parsing its description, creating its elements and negotiating its caps)
+/// can take longer than decoding the frames of a short clip. An iterator from a pooled builder
+/// returns its pipeline to the pool when it is dropped (or reaches the end of the video), reset to
+/// the `Ready` state, and the next iterator with the same frame rate, rotation, deinterlacing and
+/// frame format only swaps the URI of its `uridecodebin` before playing it again. A pipeline
+/// which reported an error is shut down rather than returned, so that one broken video cannot
+/// affect the next, and a reused pipeline which fails to start is replaced by a new one before
+/// the video is given up on.
+///
+/// Clones share the same pipelines. Idle pipelines are shut down when the last clone is dropped.
+#[derive(Debug, Clone, Default)]
+pub struct PipelinePool(Arc<Mutex<PoolState>>);
+
+/// How often a [`PipelinePool`] has built and reused pipelines, from [`PipelinePool::stats`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PoolStats {
+    /// The number of pipelines built from scratch.
+    pub built: u64,
+    /// The number of videos read by a pipeline from the pool.
+    pub reused: u64,
+    /// The number of pipelines from the pool which failed to start, and were replaced by a new
+    /// pipeline. These are counted in `built` as well.
+    pub fallbacks: u64,
+}
+
+#[derive(Debug, Default)]
+struct PoolState {
+    // Idle pipelines, by the description they were built from.
+    idle: HashMap<String, Vec<gstreamer::Pipeline>>,
+    stats: PoolStats,
+}
+
+impl PipelinePool {
+    /// An empty pool.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// How the pool has been used so far.
+    #[must_use]
+    pub fn stats(&self) -> PoolStats {
+        self.lock().stats
+    }
+
+    /// The number of pipelines waiting to be reused.
+    #[must_use]
+    pub fn idle(&self) -> usize {
+        self.lock().idle.values().map(Vec::len).sum()
+    }
+
+    pub(crate) fn take(&self, kind: &str) -> Option<gstreamer::Pipeline> {
+        self.lock().idle.get_mut(kind)?.pop()
+    }
+
+    // Keep a pipeline, which must be in the Ready state, for the next video of the same kind.
+    pub(crate) fn put(&self, kind: &str, pipeline: gstreamer::Pipeline) {
+        let mut state = self.lock();
+        let idle = state.idle.entry(kind.to_string()).or_default();
+        if idle.len() < MAX_IDLE_PER_KIND {
+            idle.push(pipeline);
+        } else {
+            drop(state);
+            let _ = pipeline.set_state(gstreamer::State::Null);
+        }
+    }
+
+    pub(crate) fn record(&self, f: impl FnOnce(&mut PoolStats)) {
+        f(&mut self.lock().stats);
+    }
+
+    // A panic while the lock was held cannot leave the state inconsistent, so poisoning is ignored.
+    fn lock(&self) -> std::sync::MutexGuard<'_, PoolState> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+//As with VideoFrameIter, pipelines must be set to Null before they are freed.
+impl Drop for PoolState {
+    fn drop(&mut self) {
+        for pipeline in self.idle.values().flatten() {
+            let _ = pipeline.set_state(gstreamer::State::Null);
+        }
+    }
+}
@@ -0,0 +1,72 @@
+use image::GrayImage;
+use vid_frame_iter::{frame_iter::VideoFrame, ColorRange, ImageFns, VideoFrameIterBuilder};
+
+fn uri(filename: &str) -> String {
+    let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("examples")
+        .join("vids")
+        .join(filename);
+    url::Url::from_file_path(path)
+        .expect("absolute path")
+        .to_string()
+}
+
+// The first few frames of the video at one frame per second, decoded as if the decoder had output
+// the given range.
+fn frames_via(range: ColorRange) -> Vec<GrayImage> {
+    let mut builder = VideoFrameIterBuilder::from_uri(uri("dog.1.mp4"));
+    builder.frame_rate((1, 1));
+    builder.force_source_color_range(range);
+
+    let mut frames = builder.spawn_gray().expect("example video is readable");
+    assert_eq!(frames.source_color_range(), Some(range));
+
+    frames
+        .by_ref()
+        .take(8)
+        .map(|frame| {
+            let frame = frame.expect("frame is decodable");
+            assert_eq!(frame.color_range(), Some(ColorRange::Full));
+            frame.to_imagebuffer()
+        })
+        .collect()
+}
+
+// A 64 bit hash of the frame, with each bit set if that cell of an 8x8 grid is brighter than the
+// mean. Like the hashes of vid_dup_finder, small shifts in brightness flip the bits of cells near
+// the mean.
+fn average_hash(frame: &GrayImage) -> u64 {
+    let small = image::imageops::resize(frame, 8, 8, image::imageops::FilterType::Triangle);
+    let mean = small.pixels().map(|p| u32::from(p.0[0])).sum::<u32>() / 64;
+    small
+        .pixels()
+        .enumerate()
+        .filter(|(_, p)| u32::from(p.0[0]) > mean)
+        .fold(0, |hash, (idx, _)| hash | 1 << idx)
+}
+
+#[test]
+fn test_frames_do_not_depend_on_decoded_range() {
+    vid_frame_iter::init_gstreamer();
+
+    let limited = frames_via(ColorRange::Limited);
+    let full = frames_via(ColorRange::Full);
+    assert_eq!(limited.len(), full.len());
+    assert!(!limited.is_empty());
+
+    for (limited, full) in limited.iter().zip(&full) {
+        assert_eq!(limited.dimensions(), full.dimensions());
+
+        //limited range has fewer levels, so allow for rounding.
+        let total_diff = limited
+            .pixels()
+            .zip(full.pixels())
+            .map(|(a, b)| u64::from(a.0[0].abs_diff(b.0[0])))
+            .sum::<u64>();
+        let mean_diff = total_diff as f64 / f64::from(limited.width() * limited.height());
+        assert!(mean_diff < 1.5, "mean difference {mean_diff}");
+
+        let distance = (average_hash(limited) ^ average_hash(full)).count_ones();
+        assert!(distance <= 2, "hash distance {distance}");
+    }
+}
@@ -0,0 +1,66 @@
+use image::GenericImageView;
+use vid_frame_iter::{ImageFns, VideoFrameIterBuilder};
+
+fn uri(filename: &str) -> String {
+    let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("examples")
+        .join("vids")
+        .join(filename);
+    url::Url::from_file_path(path)
+        .expect("absolute path")
+        .to_string()
+}
+
+fn builder() -> VideoFrameIterBuilder {
+    vid_frame_iter::init_gstreamer();
+    VideoFrameIterBuilder::from_uri(uri("dog.1.mp4"))
+}
+
+#[test]
+fn test_gray_crop_views_match_cropped_copies() {
+    let frame = builder()
+        .spawn_gray()
+        .expect("example video is readable")
+        .next()
+        .expect("video has frames")
+        .expect("frame is decodable");
+    let (width, height) = frame.dimensions();
+    let crop = (width / 8, height / 5, width / 2, height / 3);
+
+    let expected = frame
+        .to_imagebuffer()
+        .view(crop.0, crop.1, crop.2, crop.3)
+        .to_image();
+    let view = frame.crop_view(crop).expect("crop fits");
+    assert_eq!(view.dimensions(), (crop.2, crop.3));
+    assert!(view
+        .pixels()
+        .all(|(x, y, px)| expected.get_pixel(x, y) == &px));
+    assert_eq!(frame.to_imagebuffer_cropped(crop), Some(expected));
+
+    //regions which do not fit are refused.
+    assert!(frame.crop_view((width / 2, 0, width, height)).is_none());
+    assert!(frame.to_imagebuffer_cropped((0, 0, 0, height)).is_none());
+}
+
+#[test]
+fn test_rgb_crop_views_match_cropped_copies() {
+    let frame = builder()
+        .spawn_rgb()
+        .expect("example video is readable")
+        .next()
+        .expect("video has frames")
+        .expect("frame is decodable");
+    let (width, height) = frame.dimensions();
+    let crop = (1, 3, width - 7, height - 10);
+
+    let expected = frame
+        .to_imagebuffer()
+        .view(crop.0, crop.1, crop.2, crop.3)
+        .to_image();
+    let view = frame.crop_view(crop).expect("crop fits");
+    assert!(view
+        .pixels()
+        .all(|(x, y, px)| expected.get_pixel(x, y) == &px));
+    assert_eq!(frame.to_imagebuffer_cropped(crop), Some(expected));
+}
@@ -0,0 +1,46 @@
+use vid_frame_iter::VideoFrameIterBuilder;
+
+fn uri(filename: &str) -> String {
+    let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("examples")
+        .join("vids")
+        .join(filename);
+    url::Url::from_file_path(path)
+        .expect("absolute path")
+        .to_string()
+}
+
+#[test]
+fn test_pipeline_is_described() {
+    vid_frame_iter::init_gstreamer();
+    let it = VideoFrameIterBuilder::from_uri(uri("dog.1.mp4"))
+        .spawn_gray()
+        .expect("example video is readable");
+
+    let desc = it.pipeline_description();
+    assert!(desc.contains("sink (appsink)"), "{desc}");
+    assert!(desc.contains("decode (uridecodebin) ["), "{desc}");
+
+    //the decoder is one of the elements inside uridecodebin.
+    let decoder = it.decoder().expect("example video has a video decoder");
+    assert!(desc.contains(&format!("({decoder})")), "{desc}");
+}
+
+#[test]
+fn test_decode_stats_cover_returned_frames() {
+    vid_frame_iter::init_gstreamer();
+    let mut it = VideoFrameIterBuilder::from_uri(uri("dog.1.mp4"))
+        .spawn_gray()
+        .expect("example video is readable");
+    assert_eq!(it.decode_stats(), None);
+
+    for frame in it.by_ref().take(5) {
+        frame.expect("frame is decodable");
+    }
+    let stats = it.decode_stats().expect("frames were returned");
+    assert_eq!(stats.frames, 5);
+    assert!(
+        stats.min <= stats.mean && stats.mean <= stats.max,
+        "{stats:?}"
+    );
+}
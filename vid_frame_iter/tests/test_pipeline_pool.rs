@@ -0,0 +1,97 @@
+use vid_frame_iter::{ImageFns, PipelinePool, PoolStats, VideoFrameIterBuilder};
+
+fn uri(filename: &str) -> String {
+    let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("examples")
+        .join("vids")
+        .join(filename);
+    url::Url::from_file_path(path)
+        .expect("absolute path")
+        .to_string()
+}
+
+// Every frame of the video at one frame per second, from a pooled builder if a pool is given.
+fn frames(filename: &str, pool: Option<&PipelinePool>) -> Vec<Vec<u8>> {
+    let mut builder = VideoFrameIterBuilder::from_uri(uri(filename));
+    builder.frame_rate((1, 1));
+    if let Some(pool) = pool {
+        builder.reuse_pipelines(pool);
+    }
+
+    builder
+        .spawn_gray()
+        .expect("example video is readable")
+        .map(|frame| {
+            frame
+                .expect("frame is decodable")
+                .to_imagebuffer()
+                .into_raw()
+        })
+        .collect()
+}
+
+#[test]
+fn test_pooled_frames_are_identical() {
+    vid_frame_iter::init_gstreamer();
+
+    let vids = ["cat.1.mp4", "dog.1.mp4", "cat.2.mp4", "dog.2.mp4"];
+    let pool = PipelinePool::new();
+    for vid in vids {
+        assert_eq!(frames(vid, Some(&pool)), frames(vid, None), "{vid}");
+    }
+
+    //each iterator was done with its pipeline before the next was spawned, so one was enough.
+    assert_eq!(
+        pool.stats(),
+        PoolStats {
+            built: 1,
+            reused: 3,
+            fallbacks: 0
+        }
+    );
+    assert_eq!(pool.idle(), 1);
+}
+
+#[test]
+fn test_errors_do_not_reach_the_next_video() {
+    vid_frame_iter::init_gstreamer();
+
+    let pool = PipelinePool::new();
+    let expected = frames("dog.1.mp4", None);
+    assert_eq!(frames("dog.1.mp4", Some(&pool)), expected);
+
+    //a file that is not a video, read by the pooled pipeline.
+    let not_a_video = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("Cargo.toml");
+    let not_a_video = url::Url::from_file_path(not_a_video).expect("absolute path");
+    let mut builder = VideoFrameIterBuilder::from_uri(not_a_video);
+    builder.frame_rate((1, 1));
+    builder.reuse_pipelines(&pool);
+    let failed = builder
+        .spawn_gray()
+        .map(|frames| frames.collect::<Result<Vec<_>, _>>());
+    assert!(!matches!(failed, Ok(Ok(_))));
+
+    //the failed pipeline was shut down instead of being returned, and the next video is unaffected.
+    assert_eq!(frames("dog.1.mp4", Some(&pool)), expected);
+    assert_eq!(pool.idle(), 1);
+}
+
+#[test]
+fn test_iterators_alive_at_once_do_not_share_pipelines() {
+    vid_frame_iter::init_gstreamer();
+
+    let pool = PipelinePool::new();
+    let mut builder = VideoFrameIterBuilder::from_uri(uri("cat.1.mp4"));
+    builder.reuse_pipelines(&pool);
+    let first = builder.spawn_gray().expect("example video is readable");
+    let second = builder.spawn_gray().expect("example video is readable");
+    assert_eq!(pool.stats().built, 2);
+
+    drop((first, second));
+    assert_eq!(pool.idle(), 2);
+
+    //rgb frames need a different pipeline to gray frames.
+    let _rgb = builder.spawn_rgb().expect("example video is readable");
+    assert_eq!(pool.stats().built, 3);
+    assert_eq!(pool.idle(), 2);
+}
@@ -0,0 +1,65 @@
+use image::GenericImageView;
+use vid_frame_iter::{Rotation, VideoFrameIterBuilder};
+
+// dog.rotated.mp4 is dog.1.mp4 with its container tagged as rotated by 90 degrees
+// (see examples/obtain_videos.sh).
+fn uri(filename: &str) -> String {
+    let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("examples")
+        .join("vids")
+        .join(filename);
+    url::Url::from_file_path(path)
+        .expect("absolute path")
+        .to_string()
+}
+
+fn first_frame_dimensions(builder: &VideoFrameIterBuilder) -> (u32, u32) {
+    let mut frames = builder.spawn_gray().expect("example video is readable");
+    let frame = frames
+        .next()
+        .expect("video has frames")
+        .expect("frame is decodable");
+    frame.dimensions()
+}
+
+#[test]
+fn test_rotation_is_detected() {
+    vid_frame_iter::init_gstreamer();
+
+    let rotated = VideoFrameIterBuilder::from_uri(uri("dog.rotated.mp4"));
+    let rotation = rotated.rotation().expect("readable").expect("is a video");
+    assert_eq!(rotation.degrees() % 180, 90);
+    assert!(rotation.transposes_dimensions());
+
+    let unrotated = VideoFrameIterBuilder::from_uri(uri("dog.1.mp4"));
+    let rotation = unrotated.rotation().expect("readable").expect("is a video");
+    assert_eq!(rotation, Rotation::default());
+}
+
+#[test]
+fn test_rotation_transposes_frames() {
+    vid_frame_iter::init_gstreamer();
+
+    let (width, height) =
+        first_frame_dimensions(&VideoFrameIterBuilder::from_uri(uri("dog.1.mp4")));
+    assert!(width != height);
+
+    let mut builder = VideoFrameIterBuilder::from_uri(uri("dog.rotated.mp4"));
+    assert_eq!(first_frame_dimensions(&builder), (height, width));
+
+    builder.apply_rotation(false);
+    assert_eq!(first_frame_dimensions(&builder), (width, height));
+}
+
+#[test]
+fn test_rotation_from_tag() {
+    let rotation = Rotation::from_tag("rotate-270").expect("valid tag");
+    assert_eq!((rotation.degrees(), rotation.is_flipped()), (270, false));
+
+    let rotation = Rotation::from_tag("flip-rotate-180").expect("valid tag");
+    assert_eq!((rotation.degrees(), rotation.is_flipped()), (180, true));
+    assert!(!rotation.transposes_dimensions());
+
+    assert_eq!(Rotation::from_tag("rotate-45"), None);
+    assert_eq!(Rotation::from_tag("upside-down"), None);
+}
@@ -0,0 +1,83 @@
+use std::path::{Path, PathBuf};
+
+use gstreamer::prelude::*;
+use image::GenericImageView;
+use vid_frame_iter::VideoFrameIterBuilder;
+
+// Write a matroska file with two video streams of the same length: a small one first, so that
+// picking the first stream is not the same as picking the default, and a larger one second.
+fn two_stream_video(dir: &Path) -> PathBuf {
+    std::fs::create_dir_all(dir).expect("can create test dir");
+    let path = dir.join("two_streams.mkv");
+
+    let caps = |width: u32, height: u32| {
+        format!("video/x-raw,width={width},height={height},framerate=25/1")
+    };
+    let pipeline_desc = format!(
+        "matroskamux name=mux ! filesink location=\"{}\" \
+         videotestsrc num-buffers=50 ! {} ! jpegenc ! mux. \
+         videotestsrc num-buffers=50 pattern=ball ! {} ! jpegenc ! mux.",
+        path.display(),
+        caps(160, 120),
+        caps(320, 240),
+    );
+
+    let pipeline = gstreamer::parse::launch(&pipeline_desc)
+        .expect("valid pipeline")
+        .downcast::<gstreamer::Pipeline>()
+        .expect("is a pipeline");
+    pipeline
+        .set_state(gstreamer::State::Playing)
+        .expect("can start pipeline");
+
+    let bus = pipeline.bus().expect("pipelines have a bus");
+    for msg in bus.iter_timed(gstreamer::ClockTime::NONE) {
+        match msg.view() {
+            gstreamer::MessageView::Eos(_) => break,
+            gstreamer::MessageView::Error(e) => panic!("failed to write test video: {}", e.error()),
+            _ => (),
+        }
+    }
+    pipeline
+        .set_state(gstreamer::State::Null)
+        .expect("can stop pipeline");
+
+    path
+}
+
+fn first_frame_dimensions(builder: &VideoFrameIterBuilder) -> (u32, u32) {
+    let mut frames = builder.spawn_gray().expect("test video is readable");
+    let frame = frames
+        .next()
+        .expect("video has frames")
+        .expect("frame is decodable");
+    frame.dimensions()
+}
+
+#[test]
+fn test_default_video_stream_is_largest() {
+    vid_frame_iter::init_gstreamer();
+
+    let temp_dir = tempfile::tempdir().expect("can create temp dir");
+    let dir = temp_dir.path();
+    let uri = url::Url::from_file_path(two_stream_video(dir))
+        .expect("absolute path")
+        .to_string();
+
+    let streams = vid_frame_iter::video_streams(&uri).expect("readable");
+    let dimensions = streams.iter().map(|s| s.dimensions).collect::<Vec<_>>();
+    assert_eq!(dimensions, [(160, 120), (320, 240)]);
+    assert_eq!(vid_frame_iter::default_video_stream(&streams), Some(1));
+
+    let mut builder = VideoFrameIterBuilder::from_uri(&uri);
+    assert_eq!(builder.selected_video_stream().expect("readable"), Some(1));
+    assert_eq!(first_frame_dimensions(&builder), (320, 240));
+
+    builder.video_stream_index(0);
+    assert_eq!(builder.selected_video_stream().expect("readable"), Some(0));
+    assert_eq!(first_frame_dimensions(&builder), (160, 120));
+
+    builder.video_stream_index(2);
+    assert!(builder.selected_video_stream().is_err());
+    assert!(builder.spawn_gray().is_err());
+}
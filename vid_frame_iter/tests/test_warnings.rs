@@ -0,0 +1,48 @@
+use vid_frame_iter::{FrameIterError, VideoFrameIterBuilder};
+
+fn uri(filename: &str) -> String {
+    let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("examples")
+        .join("vids")
+        .join(filename);
+    url::Url::from_file_path(path)
+        .expect("absolute path")
+        .to_string()
+}
+
+fn count_frames(builder: &VideoFrameIterBuilder) -> usize {
+    builder
+        .spawn_gray()
+        .expect("example video is readable")
+        .map(|frame| frame.expect("frame is decodable"))
+        .count()
+}
+
+#[test]
+fn test_warnings_do_not_stop_iteration() {
+    vid_frame_iter::init_gstreamer();
+
+    let mut builder = VideoFrameIterBuilder::from_uri(uri("dog.1.mp4"));
+    builder.frame_rate((1, 1));
+    let expected_frames = count_frames(&builder);
+
+    let mut frames = builder.spawn_gray().expect("example video is readable");
+    frames
+        .next()
+        .expect("video has frames")
+        .expect("frame is decodable");
+    frames
+        .post_warning("Dropping frame due to QoS")
+        .expect("can post to the bus");
+
+    let remaining = frames
+        .by_ref()
+        .map(|frame| frame.expect("warnings are not returned as errors"))
+        .count();
+    assert_eq!(remaining + 1, expected_frames);
+
+    let warnings = frames.warnings();
+    assert_eq!(warnings.len(), 1);
+    assert_eq!(warnings[0].message(), "Dropping frame due to QoS");
+    assert!(!warnings[0].matches(FrameIterError::Timeout));
+}